@@ -0,0 +1,29 @@
+// Run `cargo xtask gen-kotlin` to build the native libraries and generate the
+// bindings, then `gradle assembleRelease` to pack them into an AAR.
+plugins {
+    id("com.android.library") version "8.5.2"
+    kotlin("android") version "2.0.20"
+}
+
+android {
+    namespace = "camrete.core"
+    compileSdk = 34
+
+    defaultConfig {
+        minSdk = 24
+    }
+
+    compileOptions {
+        sourceCompatibility = JavaVersion.VERSION_17
+        targetCompatibility = JavaVersion.VERSION_17
+    }
+}
+
+kotlin {
+    jvmToolchain(17)
+}
+
+dependencies {
+    implementation("net.java.dev.jna:jna:5.14.0@aar")
+    implementation("org.jetbrains.kotlinx:kotlinx-coroutines-core:1.8.1")
+}
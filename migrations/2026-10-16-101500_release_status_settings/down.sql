@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS module_settings;
+DROP TABLE IF EXISTS settings;
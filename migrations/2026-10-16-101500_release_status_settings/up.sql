@@ -0,0 +1,12 @@
+-- User preferences which apply to the whole database.
+CREATE TABLE settings (
+    name TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
+
+-- Per-module user preferences. These are keyed by slug instead of module id
+-- so that they survive repository updates, which recreate every module.
+CREATE TABLE module_settings (
+    module_slug TEXT PRIMARY KEY NOT NULL,
+    allow_prerelease INTEGER -- NULL to follow the global setting
+);
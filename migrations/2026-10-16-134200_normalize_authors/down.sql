@@ -0,0 +1,19 @@
+CREATE TABLE module_authors_old (
+    id INTEGER PRIMARY KEY NOT NULL,
+    release_id INTEGER NOT NULL REFERENCES module_releases(release_id) ON DELETE CASCADE,
+    ordinal INTEGER NOT NULL,
+    author TEXT NOT NULL,
+
+    UNIQUE (release_id, ordinal)
+);
+
+INSERT INTO module_authors_old (id, release_id, ordinal, author)
+SELECT module_authors.id, module_authors.release_id, module_authors.ordinal, authors.name
+FROM module_authors
+INNER JOIN authors ON authors.author_id = module_authors.author_id;
+
+DROP TABLE module_authors;
+ALTER TABLE module_authors_old RENAME TO module_authors;
+DROP TABLE authors;
+
+CREATE INDEX idx_module_authors_release_id ON module_authors(release_id);
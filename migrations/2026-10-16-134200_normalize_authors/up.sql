@@ -0,0 +1,30 @@
+-- Each author is stored once, and releases refer to them by id. Names are
+-- compared case-insensitively so that differently-capitalized spellings of the
+-- same name are treated as one author.
+CREATE TABLE authors (
+    author_id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT UNIQUE NOT NULL COLLATE NOCASE
+);
+
+CREATE TABLE module_authors_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    release_id INTEGER NOT NULL REFERENCES module_releases(release_id) ON DELETE CASCADE,
+    ordinal INTEGER NOT NULL,
+    author_id INTEGER NOT NULL REFERENCES authors(author_id),
+
+    UNIQUE (release_id, ordinal)
+);
+
+INSERT OR IGNORE INTO authors (name)
+SELECT DISTINCT author FROM module_authors;
+
+INSERT INTO module_authors_new (id, release_id, ordinal, author_id)
+SELECT module_authors.id, module_authors.release_id, module_authors.ordinal, authors.author_id
+FROM module_authors
+INNER JOIN authors ON authors.name = module_authors.author;
+
+DROP TABLE module_authors;
+ALTER TABLE module_authors_new RENAME TO module_authors;
+
+CREATE INDEX idx_module_authors_release_id ON module_authors(release_id);
+CREATE INDEX idx_module_authors_author_id ON module_authors(author_id);
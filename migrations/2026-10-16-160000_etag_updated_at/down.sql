@@ -0,0 +1 @@
+ALTER TABLE etags DROP COLUMN updated_at;
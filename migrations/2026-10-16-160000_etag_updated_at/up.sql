@@ -0,0 +1,3 @@
+-- When each repository URL was last downloaded successfully, so that stale
+-- repositories can be spotted.
+ALTER TABLE etags ADD COLUMN updated_at TIMESTAMP;
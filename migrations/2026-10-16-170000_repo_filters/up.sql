@@ -0,0 +1,3 @@
+-- Which releases to keep when a repository is unpacked, as a JSON object. NULL
+-- keeps everything.
+ALTER TABLE repositories ADD COLUMN filter BLOB;
@@ -0,0 +1,5 @@
+-- Older versions can't read compressed metadata, so remove those releases and
+-- forget the ETags of every repository to make the next update replace them.
+DELETE FROM module_releases WHERE substr(metadata, 1, 4) = X'28B52FFD';
+DELETE FROM etags;
+DELETE FROM settings WHERE name = 'compress_metadata';
@@ -0,0 +1,5 @@
+-- Release metadata can be stored as zstd-compressed JSON text instead of JSONB
+-- to save space. Compressed blobs start with the zstd magic number, so both
+-- formats can share the `metadata` column. Releases are only compressed while
+-- this setting is on, starting from the next repository update.
+INSERT OR IGNORE INTO settings (name, value) VALUES ('compress_metadata', 'off');
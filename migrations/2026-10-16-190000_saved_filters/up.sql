@@ -0,0 +1,7 @@
+-- Searches of the module list which the user has saved by name. Filters are
+-- stored as JSONB-encoded `ModuleFilter`s and translated into SQL when they're
+-- used, so no SQL from users is ever run.
+CREATE TABLE saved_filters (
+    name TEXT PRIMARY KEY NOT NULL,
+    filter BLOB NOT NULL
+);
@@ -0,0 +1,19 @@
+-- Files waiting to be downloaded for installs or for the download cache. The
+-- queue is kept in the database so that downloads which were interrupted when
+-- the program exited can be picked up again the next time it runs.
+--
+-- `state` holds a `DownloadState`, and `url` holds a JSONB-encoded URL.
+CREATE TABLE download_queue (
+    download_id INTEGER PRIMARY KEY NOT NULL,
+    module_slug TEXT NOT NULL,
+    version TEXT NOT NULL,
+    url BLOB NOT NULL,
+    destination TEXT NOT NULL UNIQUE,
+    expected_size BIGINT,
+    state INTEGER NOT NULL DEFAULT 0,
+    bytes_downloaded BIGINT NOT NULL DEFAULT 0,
+    error TEXT,
+    queued_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_download_queue_state ON download_queue(state, download_id);
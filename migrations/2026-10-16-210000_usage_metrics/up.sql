@@ -0,0 +1,12 @@
+-- Counts of how often things have happened, and how long they took, for
+-- diagnosing performance problems. They're only recorded once the user turns
+-- on the `telemetry` setting, and they never leave the database.
+--
+-- `timed_count` is the number of events whose duration was recorded, which is
+-- what `total_millis` is divided by to find the average.
+CREATE TABLE usage_metrics (
+    name TEXT PRIMARY KEY NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    timed_count BIGINT NOT NULL DEFAULT 0,
+    total_millis BIGINT NOT NULL DEFAULT 0
+);
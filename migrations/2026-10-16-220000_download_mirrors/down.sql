@@ -0,0 +1,2 @@
+ALTER TABLE download_queue DROP COLUMN mirrors;
+DROP TABLE download_mirrors;
@@ -0,0 +1,15 @@
+-- What happened the last time each download URL was used, so that a release
+-- with several mirrors can be downloaded from whichever has worked best.
+--
+-- `url` holds a JSONB-encoded URL, as in `etags`.
+CREATE TABLE download_mirrors (
+    url BLOB PRIMARY KEY NOT NULL,
+    content_type TEXT,
+    observed_size BIGINT,
+    last_success TIMESTAMP,
+    last_failure TIMESTAMP,
+    failure_reason TEXT
+);
+
+-- The release's other download URLs, as a JSONB array, for when `url` fails.
+ALTER TABLE download_queue ADD COLUMN mirrors BLOB;
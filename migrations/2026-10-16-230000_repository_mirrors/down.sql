@@ -0,0 +1,4 @@
+ALTER TABLE repository_refs DROP COLUMN x_comment;
+ALTER TABLE repository_refs DROP COLUMN x_mirror;
+ALTER TABLE repositories DROP COLUMN x_comment;
+ALTER TABLE repositories DROP COLUMN x_mirror;
@@ -0,0 +1,6 @@
+-- `repositories.json` can mark a repository as a mirror of another, and give
+-- it a comment, such as which region the mirror is in.
+ALTER TABLE repositories ADD COLUMN x_mirror BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE repositories ADD COLUMN x_comment TEXT;
+ALTER TABLE repository_refs ADD COLUMN x_mirror BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE repository_refs ADD COLUMN x_comment TEXT;
@@ -0,0 +1,4 @@
+-- A hash of the releases in each repository, so that databases can cheaply
+-- check whether they have the same content. It's empty until the repository is
+-- next updated.
+ALTER TABLE repositories ADD COLUMN content_hash TEXT;
@@ -0,0 +1,15 @@
+-- Release notes fetched from GitHub. They're keyed by the GitHub repository
+-- and the module version rather than by release, since releases are replaced
+-- every time their repository is updated.
+--
+-- A row without a tag means GitHub had no release for the version when it was
+-- last asked. `url` holds a JSONB-encoded URL, as in `etags`.
+CREATE TABLE release_notes (
+    github_repo TEXT NOT NULL,
+    version TEXT NOT NULL,
+    tag TEXT,
+    body TEXT,
+    url BLOB,
+    fetched_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (github_repo, version)
+);
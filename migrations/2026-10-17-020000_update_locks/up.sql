@@ -0,0 +1,11 @@
+-- Advisory locks which stop two programs from updating the same repository at
+-- once. A lock is held until its update finishes or `expires_at` passes, so
+-- that a program which crashes mid-update doesn't block updates forever.
+--
+-- `token` identifies the holder, so only it can release the lock.
+CREATE TABLE update_locks (
+    repo_id INTEGER PRIMARY KEY NOT NULL REFERENCES repositories (repo_id) ON DELETE CASCADE,
+    token TEXT NOT NULL,
+    process_id INTEGER NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1 @@
+DROP TABLE yanked_releases;
@@ -0,0 +1,11 @@
+-- Releases the user has yanked, which are never chosen as a module's latest
+-- release or installed to satisfy a relationship. Like `module_settings`,
+-- they're keyed by module identifier and version, since releases are replaced
+-- every time their repository is updated.
+CREATE TABLE yanked_releases (
+    module_slug TEXT NOT NULL,
+    version TEXT NOT NULL,
+    reason TEXT,
+    yanked_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (module_slug, version)
+);
@@ -0,0 +1 @@
+ALTER TABLE module_releases DROP COLUMN game_version_max;
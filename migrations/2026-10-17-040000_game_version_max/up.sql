@@ -0,0 +1,4 @@
+-- The newest game version each release supports, from its `ksp_version_max`.
+-- It's JSONB-encoded like `game_version_min`, and empty for releases without
+-- an upper bound, including those stored before this column was added.
+ALTER TABLE module_releases ADD COLUMN game_version_max BLOB;
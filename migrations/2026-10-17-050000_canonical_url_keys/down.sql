@@ -0,0 +1,2 @@
+-- Canonical URLs are still valid keys, so there's nothing to undo.
+SELECT 1;
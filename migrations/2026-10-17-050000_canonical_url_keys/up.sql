@@ -0,0 +1,33 @@
+-- URL-keyed tables are now keyed on each URL's canonical form, so that
+-- different spellings of a URL (a trailing slash, a fragment) share one row.
+-- `URL_KEY` is registered by the application on each connection.
+--
+-- Where several rows have the same canonical URL, the most recently updated
+-- one is kept.
+DELETE FROM etags
+WHERE EXISTS (
+    SELECT 1 FROM etags newer
+    WHERE URL_KEY(newer.url) = URL_KEY(etags.url)
+        AND (COALESCE(newer.updated_at, ''), newer.rowid)
+            > (COALESCE(etags.updated_at, ''), etags.rowid)
+);
+
+UPDATE etags SET url = URL_KEY(url);
+
+DELETE FROM download_mirrors
+WHERE EXISTS (
+    SELECT 1 FROM download_mirrors newer
+    WHERE URL_KEY(newer.url) = URL_KEY(download_mirrors.url)
+        AND (
+            MAX(COALESCE(newer.last_success, ''), COALESCE(newer.last_failure, '')),
+            newer.rowid
+        ) > (
+            MAX(
+                COALESCE(download_mirrors.last_success, ''),
+                COALESCE(download_mirrors.last_failure, '')
+            ),
+            download_mirrors.rowid
+        )
+);
+
+UPDATE download_mirrors SET url = URL_KEY(url);
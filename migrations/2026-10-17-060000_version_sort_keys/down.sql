@@ -0,0 +1,2 @@
+DROP INDEX idx_module_releases_by_sort_key;
+ALTER TABLE module_releases DROP COLUMN sort_key;
@@ -0,0 +1,9 @@
+-- A byte string for each release which sorts like its version, so ordering
+-- releases doesn't need to call the `MODULE_VERSION` collation for every
+-- comparison. `VERSION_SORT_KEY` is registered by the application on each
+-- connection.
+ALTER TABLE module_releases ADD COLUMN sort_key BLOB;
+
+UPDATE module_releases SET sort_key = VERSION_SORT_KEY(version);
+
+CREATE INDEX idx_module_releases_by_sort_key ON module_releases(module_id, sort_key);
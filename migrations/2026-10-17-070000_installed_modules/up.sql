@@ -0,0 +1,13 @@
+-- The modules installed in the game. Like `yanked_releases`, they're keyed by
+-- module identifier and version, since releases are replaced every time their
+-- repository is updated.
+--
+-- `files` holds a JSONB array of the module's files, relative to the game
+-- directory.
+CREATE TABLE installed_modules (
+    module_slug TEXT PRIMARY KEY NOT NULL,
+    version TEXT NOT NULL,
+    auto_installed BOOLEAN NOT NULL DEFAULT FALSE,
+    installed_at TIMESTAMP,
+    files BLOB NOT NULL
+);
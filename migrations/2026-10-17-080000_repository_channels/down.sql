@@ -0,0 +1,2 @@
+ALTER TABLE repositories DROP COLUMN url_template;
+ALTER TABLE repositories DROP COLUMN channel;
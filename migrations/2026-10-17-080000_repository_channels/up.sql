@@ -0,0 +1,5 @@
+-- The branch or tag of its source which each repository is downloaded from,
+-- and the URL with `{channel}` in place of it. Both are empty until a channel
+-- is chosen with `camrete repo set-channel`.
+ALTER TABLE repositories ADD COLUMN channel TEXT;
+ALTER TABLE repositories ADD COLUMN url_template TEXT;
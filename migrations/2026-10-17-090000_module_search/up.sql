@@ -0,0 +1,31 @@
+-- A full-text index of each module's identifier, and the name, tags and
+-- summary of its newest release, for searching as the user types. The trigram
+-- tokenizer matches any part of a word, so `allax` finds Parallax. Rows are
+-- keyed by module ID, and the index is rebuilt whenever a repository is
+-- updated.
+CREATE VIRTUAL TABLE module_search USING fts5(
+    slug,
+    name,
+    tags,
+    summary,
+    tokenize = 'trigram'
+);
+
+INSERT INTO module_search (rowid, slug, name, tags, summary)
+SELECT
+    m.module_id,
+    m.module_slug,
+    r.display_name,
+    (
+        SELECT COALESCE(group_concat(tag, ' '), '')
+        FROM (SELECT tag FROM module_tags WHERE release_id = r.release_id ORDER BY ordinal)
+    ),
+    r.summary
+FROM modules m
+INNER JOIN module_releases r ON r.release_id = (
+    SELECT latest.release_id
+    FROM module_releases latest
+    WHERE latest.module_id = m.module_id
+    ORDER BY latest.sort_key DESC, latest.version
+    LIMIT 1
+);
@@ -0,0 +1,2 @@
+ALTER TABLE repositories DROP COLUMN moved_url;
+ALTER TABLE repositories DROP COLUMN final_url;
@@ -0,0 +1,5 @@
+-- Where each repository was last downloaded from after following redirects,
+-- and where it has moved if the redirects were permanent. Both are JSON URLs
+-- like `url`, and are empty if the last update wasn't redirected.
+ALTER TABLE repositories ADD COLUMN final_url BLOB;
+ALTER TABLE repositories ADD COLUMN moved_url BLOB;
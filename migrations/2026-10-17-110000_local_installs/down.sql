@@ -0,0 +1 @@
+ALTER TABLE installed_modules DROP COLUMN local_archive;
@@ -0,0 +1,4 @@
+-- The archive each module was installed from, for modules installed from a
+-- file on disk instead of a repository's download. Empty for every other
+-- module.
+ALTER TABLE installed_modules ADD COLUMN local_archive TEXT;
@@ -0,0 +1,3 @@
+DROP TRIGGER release_validations_on_insert;
+DROP TRIGGER release_validations_on_update;
+DROP TABLE release_validations;
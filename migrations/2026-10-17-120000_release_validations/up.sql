@@ -0,0 +1,21 @@
+-- What was found wrong with each release's metadata when it was last checked,
+-- as a JSONB array of findings. Releases which haven't been checked have no
+-- row, and an empty array means nothing was found.
+--
+-- A release's row is deleted whenever the release changes, so its findings are
+-- never out of date. The insert trigger covers IDs which SQLite reuses after a
+-- release is replaced.
+CREATE TABLE release_validations (
+    release_id INTEGER PRIMARY KEY NOT NULL REFERENCES module_releases(release_id) ON DELETE CASCADE,
+    findings BLOB NOT NULL
+);
+
+CREATE TRIGGER release_validations_on_update AFTER UPDATE ON module_releases
+BEGIN
+    DELETE FROM release_validations WHERE release_id = OLD.release_id;
+END;
+
+CREATE TRIGGER release_validations_on_insert AFTER INSERT ON module_releases
+BEGIN
+    DELETE FROM release_validations WHERE release_id = NEW.release_id;
+END;
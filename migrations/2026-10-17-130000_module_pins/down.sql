@@ -0,0 +1 @@
+ALTER TABLE module_settings DROP COLUMN pinned_version;
@@ -0,0 +1,3 @@
+-- The version a module is held at, if the user pinned it. Pinned modules
+-- aren't offered upgrades.
+ALTER TABLE module_settings ADD COLUMN pinned_version TEXT;
@@ -0,0 +1 @@
+DROP INDEX idx_module_relationships_target_name;
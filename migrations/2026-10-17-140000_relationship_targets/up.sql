@@ -0,0 +1,3 @@
+-- Finding the modules which depend on a given module, for filters like
+-- `depends:ModuleManager`.
+CREATE INDEX idx_module_relationships_target_name ON module_relationships(target_name);
@@ -0,0 +1,8 @@
+-- Licenses and terms of use found at the top of a repository's archive, like
+-- `LICENSE.md`. They're replaced whenever the repository is updated.
+CREATE TABLE repo_metadata (
+    repo_id INTEGER NOT NULL REFERENCES repositories(repo_id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    contents TEXT NOT NULL,
+    PRIMARY KEY (repo_id, file_name)
+);
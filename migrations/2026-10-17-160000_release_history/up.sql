@@ -0,0 +1,26 @@
+-- Every version of a module a repository has provided, with when it was first
+-- and last seen in an update and when it disappeared. Releases are replaced at
+-- every update, so this is the only record of versions which have been removed.
+-- Rows are keyed by slug rather than by module, since modules are replaced too.
+CREATE TABLE release_history (
+    repo_id INTEGER NOT NULL REFERENCES repositories(repo_id) ON DELETE CASCADE,
+    module_slug TEXT NOT NULL,
+    version TEXT NOT NULL,
+    first_seen TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    removed_at TIMESTAMP,
+    PRIMARY KEY (repo_id, module_slug, version)
+);
+
+CREATE INDEX release_history_slug ON release_history (module_slug);
+
+-- The releases already saved were seen at their repository's last update,
+-- which is when its ETag was last saved. `URL_KEY` is registered by the
+-- application on each connection.
+INSERT OR IGNORE INTO release_history (repo_id, module_slug, version, first_seen, last_seen)
+SELECT m.repo_id, m.module_slug, r.version, e.updated_at, e.updated_at
+FROM module_releases r
+INNER JOIN modules m ON m.module_id = r.module_id
+INNER JOIN repositories repo ON repo.repo_id = m.repo_id
+INNER JOIN etags e ON e.url = URL_KEY(repo.url)
+WHERE e.updated_at IS NOT NULL;
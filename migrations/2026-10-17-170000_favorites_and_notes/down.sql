@@ -0,0 +1,2 @@
+DROP TABLE module_notes;
+DROP TABLE favorites;
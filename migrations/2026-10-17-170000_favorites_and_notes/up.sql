@@ -0,0 +1,14 @@
+-- Modules the user has marked as favorites. Like `yanked_releases`, they're
+-- keyed by module identifier, so they're kept across repository updates.
+CREATE TABLE favorites (
+    module_slug TEXT PRIMARY KEY NOT NULL,
+    added_at TIMESTAMP NOT NULL
+);
+
+-- Notes the user has written about modules, one per module. They're never
+-- sent anywhere.
+CREATE TABLE module_notes (
+    module_slug TEXT PRIMARY KEY NOT NULL,
+    note TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
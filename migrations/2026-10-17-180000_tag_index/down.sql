@@ -0,0 +1 @@
+DROP INDEX idx_module_tags_tag;
@@ -0,0 +1,3 @@
+-- Finding the releases with a given tag, for filters like `tag:parts`.
+-- Including the release means the lookup doesn't need the table itself.
+CREATE INDEX idx_module_tags_tag ON module_tags(tag, release_id);
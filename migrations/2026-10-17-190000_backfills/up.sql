@@ -0,0 +1,8 @@
+-- Backfills which have finished filling in values for releases saved before
+-- the columns holding them were added. Backfills are run by the application
+-- after migrations, since they need to parse release metadata, and each is
+-- only run again if its row is missing.
+CREATE TABLE backfills (
+    name TEXT PRIMARY KEY NOT NULL,
+    completed_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,2 @@
+DROP TABLE unpack_checkpoints;
+DROP TABLE resumable_updates;
@@ -0,0 +1,19 @@
+-- Resumable repository updates which haven't finished, either because they're
+-- still running or because they were interrupted. The repository's archive
+-- was saved to `archive_path` before it was unpacked, so an interrupted update
+-- can carry on from it without downloading it again.
+CREATE TABLE resumable_updates (
+    repo_id INTEGER PRIMARY KEY NOT NULL REFERENCES repositories(repo_id) ON DELETE CASCADE,
+    archive_path TEXT NOT NULL,
+    etag TEXT,
+    content_type TEXT,
+    started_at TIMESTAMP NOT NULL
+);
+
+-- The releases a resumable update has saved, by a SHA-256 hash of each one's
+-- path in the archive. They're saved in the same transaction as the releases.
+CREATE TABLE unpack_checkpoints (
+    repo_id INTEGER NOT NULL REFERENCES resumable_updates(repo_id) ON DELETE CASCADE,
+    asset_hash BLOB NOT NULL,
+    PRIMARY KEY (repo_id, asset_hash)
+);
@@ -0,0 +1 @@
+ALTER TABLE module_authors DROP COLUMN role;
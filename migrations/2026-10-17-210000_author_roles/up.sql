@@ -0,0 +1,4 @@
+-- What each author did on a release, like `maintainer`, taken from the end of
+-- author strings such as `Linx (maintainer)`. The name without the role is
+-- the author's. Releases saved before this are split by a backfill.
+ALTER TABLE module_authors ADD COLUMN role TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE bugtracker_checks;
+DROP TABLE module_staleness;
@@ -0,0 +1,17 @@
+-- How far each module has fallen behind the game, recomputed after every
+-- repository update. Whether that makes a module stale also depends on the
+-- current date, so it's worked out when the rows are loaded.
+CREATE TABLE module_staleness (
+    module_id INTEGER PRIMARY KEY NOT NULL REFERENCES modules(module_id) ON DELETE CASCADE,
+    last_release_date TIMESTAMP,
+    game_versions_behind INTEGER NOT NULL
+);
+
+-- Whether each module's bug tracker could be reached when it was last
+-- checked. Like `favorites`, they're keyed by module identifier, so they're
+-- kept across repository updates.
+CREATE TABLE bugtracker_checks (
+    module_slug TEXT PRIMARY KEY NOT NULL,
+    alive BOOLEAN NOT NULL,
+    checked_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,11 @@
+-- The commit of a Git repository that each repository was last updated from,
+-- so that the next update only applies the files which changed since. The
+-- commit only counts while the repository is still fetched from the same
+-- remote and branch.
+CREATE TABLE repo_commits (
+    repo_id INTEGER PRIMARY KEY NOT NULL REFERENCES repositories(repo_id) ON DELETE CASCADE,
+    remote TEXT NOT NULL,
+    branch TEXT NOT NULL,
+    commit_id TEXT NOT NULL,
+    fetched_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE download_queue DROP COLUMN sha256;
+ALTER TABLE download_queue DROP COLUMN sha1;
@@ -0,0 +1,4 @@
+-- The checksums from the release's `download_hash`, as hex, so that a
+-- finished download can be checked before it's moved into place.
+ALTER TABLE download_queue ADD COLUMN sha1 TEXT;
+ALTER TABLE download_queue ADD COLUMN sha256 TEXT;
@@ -0,0 +1 @@
+ALTER TABLE download_queue DROP COLUMN partial_url;
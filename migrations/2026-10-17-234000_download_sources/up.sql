@@ -0,0 +1,5 @@
+-- The URL that the partial file of a download came from, as JSONB, so that
+-- it's only resumed from the same URL. Mirrors don't always hold the same
+-- bytes, so joining the start of one's file to the end of another's would
+-- corrupt it.
+ALTER TABLE download_queue ADD COLUMN partial_url BLOB;
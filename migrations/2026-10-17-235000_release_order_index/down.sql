@@ -0,0 +1,2 @@
+DROP INDEX idx_module_releases_by_sort_key;
+CREATE INDEX idx_module_releases_by_sort_key ON module_releases(module_id, sort_key);
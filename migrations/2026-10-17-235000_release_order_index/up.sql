@@ -0,0 +1,5 @@
+-- Releases are listed by sort key and then by version, for the releases
+-- saved before sort keys were added. Covering both lets SQLite read them in
+-- order from the index instead of sorting them.
+DROP INDEX idx_module_releases_by_sort_key;
+CREATE INDEX idx_module_releases_by_sort_key ON module_releases(module_id, sort_key DESC, version);
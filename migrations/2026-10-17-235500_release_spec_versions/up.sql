@@ -0,0 +1,4 @@
+-- The version of the metadata spec each release was written for, like
+-- `v1.4`. It's empty for releases stored before this column was added until
+-- their repository is next downloaded.
+ALTER TABLE module_releases ADD COLUMN spec_version TEXT;
@@ -0,0 +1,104 @@
+//! Looks up messages in the catalog for the user's language.
+//!
+//! The catalogs come from [`camrete_core::l10n`], so messages have the same IDs
+//! and wording as in the GUI. Use the [`t!`] macro to format a message.
+
+use std::sync::OnceLock;
+
+use camrete_core::l10n::{self, DEFAULT_LOCALE};
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue, concurrent::FluentBundle};
+use unic_langid::LanguageIdentifier;
+
+type Bundle = FluentBundle<FluentResource>;
+
+/// The bundles to look messages up in, the user's language first.
+static BUNDLES: OnceLock<Vec<Bundle>> = OnceLock::new();
+
+/// The environment variables which hold the user's language, in order of
+/// precedence, as read by gettext.
+const LOCALE_VARS: [&str; 3] = ["LC_ALL", "LC_MESSAGES", "LANG"];
+
+/// Chooses the language to show messages in. If no language is given, it's
+/// read from the environment. Messages are shown in the default language
+/// unless this is called before the first one is formatted.
+pub fn init(requested: Option<&str>) {
+    let _ = BUNDLES.set(load(requested));
+}
+
+fn load(requested: Option<&str>) -> Vec<Bundle> {
+    let requested = requested.map(String::from).or_else(|| {
+        LOCALE_VARS
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+    });
+    let locale = l10n::negotiate(requested.as_deref().unwrap_or(DEFAULT_LOCALE));
+
+    let mut bundles = vec![bundle(locale)];
+    if locale != DEFAULT_LOCALE {
+        bundles.push(bundle(DEFAULT_LOCALE));
+    }
+    bundles
+}
+
+fn bundle(locale: &str) -> Bundle {
+    let language = locale
+        .parse::<LanguageIdentifier>()
+        .expect("catalogs are named with valid locales");
+    let catalog = l10n::catalog(locale).expect("negotiated locales have a catalog");
+    let resource = FluentResource::try_new(catalog.to_owned())
+        .unwrap_or_else(|(_, errors)| panic!("the {locale} catalog is invalid: {errors:?}"));
+
+    let mut bundle = FluentBundle::new_concurrent(vec![language]);
+    // Terminals don't need the Unicode isolation marks around arguments, and
+    // some show them as boxes.
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(resource)
+        .expect("catalogs don't define a message twice");
+    bundle
+}
+
+/// Formats a message, or one of its attributes if `attribute` is given. If no
+/// catalog has the message, its ID is returned instead.
+pub fn message(id: &str, attribute: Option<&str>, args: &[(&str, FluentValue)]) -> String {
+    let bundles = BUNDLES.get_or_init(|| load(None));
+    let args = args.iter().cloned().collect::<FluentArgs>();
+
+    for bundle in bundles {
+        let Some(message) = bundle.get_message(id) else {
+            continue;
+        };
+        let pattern = match attribute {
+            Some(attribute) => message.get_attribute(attribute).map(|attr| attr.value()),
+            None => message.value(),
+        };
+
+        if let Some(pattern) = pattern {
+            let mut errors = vec![];
+            return bundle
+                .format_pattern(pattern, Some(&args), &mut errors)
+                .into_owned();
+        }
+    }
+
+    id.to_owned()
+}
+
+/// Formats a message from the catalog, like `t!("yank-done", version = "1.0")`.
+/// Use `t!("error-filter-not-found.help")` to format an attribute.
+macro_rules! t {
+    ($id:literal $(, $name:ident = $value:expr)* $(,)?) => {{
+        let (id, attribute) = match $id.split_once('.') {
+            Some((id, attribute)) => (id, Some(attribute)),
+            None => ($id, None),
+        };
+        $crate::l10n::message(
+            id,
+            attribute,
+            &[$((stringify!($name), ::fluent_bundle::FluentValue::from($value))),*],
+        )
+    }};
+}
+
+pub(crate) use t;
@@ -1,19 +1,50 @@
-use std::{sync::LazyLock, time::Duration};
+use std::{
+    borrow::Cow,
+    io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::LazyLock,
+    time::Duration,
+};
 
 use camrete_core::{
-    database::models::{Module, ModuleRelease, module::{ModuleRelationship, ModuleRelationshipGroup}},
-    diesel::{self, OptionalExtension, QueryDsl, RunQueryDsl},
-    json::ReleaseStatus,
-    repo::client::RepoManager,
+    DbConnection,
+    database::{
+        ModuleStaleness, PlanWarning, ReleaseFinding, RepoDB, Staleness, models::RepoFilter,
+    },
+    install::{
+        CkanConfig, CkanRegistry, ExportRelationship, MetapackageOptions, ReleaseFiles, RepoImport,
+        VersionPin, detect_dlc, read_metadata,
+    },
+    json::{Identifier, ModuleKind, spec_version::SpecVersion},
+    markdown,
+    policy::{Policy, PolicyError},
+    prelude::{
+        Column, FilterCondition, FilterTerm, GameVersion, ModuleFilter, ModuleRelease, Profile,
+        ProfileRegistry, RelationshipType, ReleaseDetail, ReleaseStatus, RepoManager, TableFormat,
+        UpdatePreview,
+    },
+    profile::ProfileError,
+    repo::RefreshSource,
+    resolver::{Choice, Graph, Reason},
 };
 use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use miette::Diagnostic;
+use indicatif::{DecimalBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use miette::{Diagnostic, Severity};
 use owo_colors::OwoColorize;
 use termimad::MadSkin;
 use thiserror::Error;
-use time::{format_description::BorrowedFormatItem, macros::format_description};
+use time::{
+    OffsetDateTime,
+    format_description::{BorrowedFormatItem, well_known::Rfc3339},
+    macros::format_description,
+};
 use tracing_subscriber::{EnvFilter, util::SubscriberInitExt};
+use url::Url;
+
+use crate::l10n::t;
+
+mod l10n;
 
 #[derive(Debug, Error, Diagnostic)]
 enum CliError {
@@ -21,63 +52,987 @@ enum CliError {
     #[diagnostic(transparent)]
     Core(#[from] camrete_core::Error),
 
-    #[error("No such module: {0}")]
-    #[diagnostic(code(camrete::module_not_found))]
+    #[error("{}", t!("error-module-not-found", identifier = .0))]
+    #[diagnostic(code(camrete::module_not_found), severity(Warning))]
     ModuleNotFound(String),
+
+    #[error("{}", t!("error-repo-not-found", name = .0))]
+    #[diagnostic(code(camrete::repo_not_found), severity(Warning))]
+    RepoNotFound(String),
+
+    #[error("{}", t!("error-release-not-found", identifier = .slug, version = .version))]
+    #[diagnostic(code(camrete::release_not_found), severity(Warning))]
+    ReleaseNotFound { slug: String, version: String },
+
+    #[error("{}", t!("error-filter-not-found", name = .0))]
+    #[diagnostic(
+        code(camrete::filter_not_found),
+        severity(Warning),
+        help("{}", t!("error-filter-not-found.help"))
+    )]
+    FilterNotFound(String),
+
+    #[error("{}", t!("error-invalid-setting-value", value = .0))]
+    #[diagnostic(
+        code(camrete::invalid_setting_value),
+        severity(Warning),
+        help("{}", t!("error-invalid-setting-value.help"))
+    )]
+    InvalidSettingValue(String),
+
+    #[error("{}", t!("error-suggestion-not-found", name = .0))]
+    #[diagnostic(
+        code(camrete::suggestion_not_found),
+        severity(Warning),
+        help("{}", t!("error-suggestion-not-found.help"))
+    )]
+    SuggestionNotFound(String),
+
+    #[error("{}", t!("error-global-setting"))]
+    #[diagnostic(code(camrete::global_setting), severity(Warning))]
+    ModuleSetting,
+
+    #[error("{}", t!("error-not-needed", identifier = .identifier, requested = .requested))]
+    #[diagnostic(code(camrete::not_needed), severity(Warning))]
+    NotNeeded { identifier: String, requested: String },
+}
+
+/// Exit codes which scripts can rely on. Any other failure exits with 1.
+const EXIT_NOT_FOUND: u8 = 2;
+const EXIT_CONFLICT: u8 = 3;
+const EXIT_NETWORK: u8 = 4;
+const EXIT_DATABASE: u8 = 5;
+const EXIT_UPDATE_IN_PROGRESS: u8 = 6;
+const EXIT_INTERNAL: u8 = 7;
+
+impl CliError {
+    /// Whether the user can fix this error, rather than it being a bug.
+    /// Errors like this are reported as warnings.
+    fn is_user_error(&self) -> bool {
+        self.severity() == Some(Severity::Warning)
+    }
+
+    fn exit_code(&self) -> ExitCode {
+        use camrete_core::Error;
+
+        let code = match self {
+            Self::ModuleNotFound(_)
+            | Self::RepoNotFound(_)
+            | Self::SuggestionNotFound(_)
+            | Self::ReleaseNotFound { .. }
+            | Self::FilterNotFound(_) => EXIT_NOT_FOUND,
+            Self::InvalidSettingValue(_) | Self::ModuleSetting | Self::NotNeeded { .. } => {
+                return ExitCode::FAILURE;
+            }
+            Self::Core(error) => match error {
+                Error::Profile(ProfileError::NotFound(_)) => EXIT_NOT_FOUND,
+                Error::InstallPlan(_) => EXIT_CONFLICT,
+                Error::Http(_) | Error::HttpStatus { .. } => EXIT_NETWORK,
+                Error::Network(_) => EXIT_NETWORK,
+                Error::UpdateAlreadyInProgress { .. } => EXIT_UPDATE_IN_PROGRESS,
+                Error::DbConnection(_)
+                | Error::DbPool(_)
+                | Error::DbMigrations(_)
+                | Error::BackgroundUpgrade(_)
+                | Error::DatabaseCorrupt(_)
+                | Error::Db(_)
+                | Error::Query(_)
+                | Error::DatabaseBusy { .. } => EXIT_DATABASE,
+                _ if !error.is_user_error() => EXIT_INTERNAL,
+                _ => return ExitCode::FAILURE,
+            },
+        };
+
+        ExitCode::from(code)
+    }
+}
+
+impl From<camrete_core::database::QueryError> for CliError {
+    fn from(value: camrete_core::database::QueryError) -> Self {
+        camrete_core::Error::from(value).into()
+    }
+}
+
+impl From<ProfileError> for CliError {
+    fn from(value: ProfileError) -> Self {
+        camrete_core::Error::from(value).into()
+    }
 }
 
-impl From<diesel::result::Error> for CliError {
-    fn from(value: diesel::result::Error) -> Self {
+impl From<PolicyError> for CliError {
+    fn from(value: PolicyError) -> Self {
         camrete_core::Error::from(value).into()
     }
 }
 
 #[derive(Debug, clap::Parser)]
 struct Args {
+    /// The database file to use, instead of the one in the user's data directory.
+    #[clap(long, global = true, env = "CAMRETE_DB")]
+    db: Option<PathBuf>,
+    /// The profile whose database to use, instead of the default one.
+    #[clap(long, global = true, env = "CAMRETE_PROFILE", conflicts_with = "db")]
+    profile: Option<String>,
+    /// The policy file which decides which modules can be used, instead of the
+    /// one in the user's config directory.
+    #[clap(long, global = true, env = "CAMRETE_POLICY")]
+    policy: Option<PathBuf>,
+    /// Only print the information that was asked for, without progress bars or
+    /// status messages.
+    #[clap(long, short, global = true)]
+    quiet: bool,
+    /// Print tab-separated fields in a format which won't change between
+    /// versions, for use in scripts. Implies `--quiet`.
+    #[clap(long, global = true)]
+    porcelain: bool,
+    /// The language to show messages in, like `en-GB`. Defaults to the
+    /// language in the `LC_ALL`, `LC_MESSAGES` or `LANG` environment variable.
+    #[clap(long, global = true, env = "CAMRETE_LANG")]
+    lang: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
 
+impl Args {
+    fn output(&self) -> Output {
+        if self.porcelain {
+            Output::Porcelain
+        } else if self.quiet {
+            Output::Quiet
+        } else {
+            Output::Normal
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Normal,
+    Quiet,
+    Porcelain,
+}
+
+impl Output {
+    /// Whether progress bars and status messages should be shown.
+    fn is_verbose(self) -> bool {
+        self == Self::Normal
+    }
+
+    fn progress_target(self) -> ProgressDrawTarget {
+        if self.is_verbose() {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        }
+    }
+}
+
+/// Makes text safe to print as one field of porcelain output.
+fn field(text: &str) -> Cow<'_, str> {
+    const SEPARATORS: [char; 3] = ['\t', '\n', '\r'];
+
+    if text.contains(SEPARATORS) {
+        Cow::Owned(text.replace(SEPARATORS, " "))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
 #[derive(Debug, clap::Subcommand)]
 enum Command {
-    Update {},
+    /// Download the latest version of each repository.
+    Update {
+        /// Show what would change without saving anything to the database.
+        #[clap(long)]
+        dry_run: bool,
+        /// If another program is updating a repository, wait for it to finish
+        /// instead of failing.
+        #[clap(long, conflicts_with = "dry_run")]
+        wait: bool,
+        /// Unpack every repository again, even if it hasn't changed since it
+        /// was last unpacked. Archives which haven't changed are unpacked from
+        /// the cache instead of being downloaded again.
+        #[clap(long, conflicts_with = "dry_run")]
+        force_reunpack: bool,
+        /// Fetch repositories on GitHub or GitLab with Git, applying only the
+        /// files which changed since the last update. Repositories which
+        /// can't be fetched with Git are downloaded as archives.
+        #[clap(long, conflicts_with = "dry_run")]
+        git: bool,
+    },
+    /// Fetch one release's metadata and save it, without downloading the
+    /// whole repository. Useful for checking a change to a `.ckan` file
+    /// before the repository's archive is rebuilt. The release is replaced
+    /// again at the next `update`.
+    RefreshModule {
+        /// The URL of a `.ckan` file, or the identifier of a mod to refresh
+        /// its newest release from a repository on GitHub or GitLab.
+        source: RefreshSource,
+        /// Save the release to this repository. By default it's saved to the
+        /// repository the mod is from.
+        #[clap(long)]
+        repo: Option<String>,
+    },
     /// Show the details for a mod.
     Show {
-        identifier: String,
+        identifier: Identifier,
+        /// Also show the newest release for each version of the game.
+        #[clap(long)]
+        compat: bool,
+        /// Also show the release notes from GitHub, if the mod is hosted
+        /// there. Notes are only fetched while the `release-notes` setting is
+        /// on.
+        #[clap(long)]
+        notes: bool,
+        /// Also show where the release is downloaded from, and how it's
+        /// installed.
+        #[clap(long)]
+        files: bool,
+        /// Check whether the mod's bug tracker can still be reached, which
+        /// counts towards whether it looks maintained.
+        #[clap(long)]
+        check_bugtracker: bool,
+    },
+    /// List the tags used by mods, with how many mods use each one.
+    Tags {
+        /// Group tags by the part before the first `/`, like `planet-pack`.
+        #[clap(long)]
+        grouped: bool,
+    },
+    /// List the mods in the database.
+    List {
+        /// Only list mods with a release by this author.
+        #[clap(long)]
+        author: Option<String>,
+        /// Only list mods with a release which has this tag. Can be given more
+        /// than once. Use `camrete tags` to see every tag.
+        #[clap(long)]
+        tag: Vec<String>,
+        /// Only list mods whose newest release depends on this mod. Can be
+        /// given more than once.
+        #[clap(long)]
+        depends_on: Vec<String>,
+        /// Only list mods matching a search, like `tag:parts -tag:graphics`.
+        /// Searches can use `tag:`, `author:`, `kind:`, `repo:` and
+        /// relationships like `depends:` or `conflicts:`, and anything else is
+        /// searched for in the mods' names and summaries.
+        #[clap(long)]
+        filter: Option<String>,
+        /// Only list mods matching a filter saved by `filter save`.
+        #[clap(long, conflicts_with = "filter")]
+        filter_name: Option<String>,
+        /// Print a table for spreadsheets instead of a list. Tables have a
+        /// header row, and aren't affected by `--porcelain`.
+        #[clap(long = "output", value_enum, default_value = "text")]
+        format: ListFormat,
+        /// The table's columns, separated by commas. Can be `identifier`,
+        /// `name`, `version`, `status`, `summary`, `authors`, `licenses`,
+        /// `tags`, `downloads`, `release_date`, `download_size`, `homepage` or
+        /// `repository`.
+        #[clap(long, value_delimiter = ',')]
+        columns: Vec<Column>,
+    },
+    /// Yank a release of a mod, so that it's never chosen as the mod's latest
+    /// release. It's still shown by `show`.
+    Yank {
+        identifier: Identifier,
+        version: String,
+        /// Why the release was yanked.
+        #[clap(long, conflicts_with = "undo")]
+        reason: Option<String>,
+        /// Reverse an earlier yank.
+        #[clap(long)]
+        undo: bool,
+    },
+    /// Show your note about a mod, or replace it. Notes are only stored in
+    /// the database on this device, and are shown by `show`.
+    Note {
+        identifier: Identifier,
+        /// The new note. Without it, the current note is shown.
+        text: Option<String>,
+        /// Remove the note.
+        #[clap(long, conflicts_with = "text")]
+        clear: bool,
+    },
+    /// Show every version of a mod which a repository has provided, with when
+    /// it first appeared and when it was removed.
+    History {
+        identifier: Identifier,
+        /// Only show this version.
+        #[clap(long)]
+        version: Option<String>,
+    },
+    /// Explain why a mod would be installed alongside others, following the
+    /// dependencies from the mods being installed to it.
+    Why {
+        identifier: Identifier,
+        /// A mod being installed. Can be given more than once.
+        #[clap(long = "for", value_name = "MOD", required = true)]
+        requested: Vec<Identifier>,
+        /// Follow recommendations as well as dependencies.
+        #[clap(long)]
+        recommends: bool,
+    },
+    /// Show what installing and removing mods would change, without changing
+    /// anything: the mods which would be installed, upgraded or removed, and
+    /// how much would be downloaded.
+    Plan {
+        /// A mod to install. Can be given more than once.
+        identifiers: Vec<Identifier>,
+        /// An installed mod to remove. Can be given more than once.
+        #[clap(long)]
+        remove: Vec<Identifier>,
+        /// Install recommended mods as well as dependencies.
+        #[clap(long)]
+        recommends: bool,
+    },
+    /// Install a mod from a zip file, like an unpublished build, and record it
+    /// as installed from that file. The mod's metadata is read from the
+    /// `.ckan` file inside the zip, unless `--metadata` is given.
+    Install {
+        /// The zip file to install.
+        #[clap(long)]
+        file: PathBuf,
+        /// The identifier to record the mod as installed under, instead of
+        /// the one in its metadata.
+        #[clap(long = "as", value_name = "IDENTIFIER")]
+        identifier: Option<Identifier>,
+        /// A `.ckan` file describing the mod, for zips without one or whose
+        /// own is out of date.
+        #[clap(long)]
+        metadata: Option<PathBuf>,
+        /// The game directory to install the mod into.
+        #[clap(long, default_value = ".")]
+        game_dir: PathBuf,
+    },
+    /// Show diagnostic details about the database.
+    #[clap(subcommand)]
+    Info(InfoCommand),
+    /// Manage the on-device database.
+    #[clap(subcommand)]
+    Db(DbCommand),
+    /// View or change settings.
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Manage profiles, which each have their own database.
+    #[clap(subcommand)]
+    Profile(ProfileCommand),
+    /// Find and add repositories.
+    #[clap(subcommand)]
+    Repo(RepoCommand),
+    /// Manage saved searches of the mod list.
+    #[clap(subcommand)]
+    Filter(FilterCommand),
+    /// Mark mods as favorites, which `list` marks with `★`.
+    #[clap(subcommand)]
+    Fav(FavCommand),
+    /// View or import the list of installed mods.
+    #[clap(subcommand)]
+    Installed(InstalledCommand),
+    /// View the usage metrics recorded while the `telemetry` setting is on.
+    /// They're never sent anywhere.
+    #[clap(subcommand)]
+    Metrics(MetricsCommand),
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum InfoCommand {
+    /// Show how many mods a repository has and when it was last updated.
+    Repo { name: String },
+    /// Show which DLC is installed in a game directory.
+    Dlc { game_dir: PathBuf },
+    /// List the releases which depend on each other in a loop. They can still
+    /// be installed, but the loop is usually a mistake in their metadata.
+    Cycles,
+    /// List the mods which look unmaintained: those which haven't had a
+    /// release for a year, and don't support the newest version of the game.
+    Stale {
+        /// Only list the mods which look abandoned.
+        #[clap(long)]
+        abandoned: bool,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum DbCommand {
+    /// Print the location of the database.
+    Path,
+    /// Save a snapshot of the database to a new file.
+    Backup { file: PathBuf },
+    /// Replace the contents of the database with a snapshot made by `db backup`.
+    Restore { file: PathBuf },
+    /// Check the values the database works out from others, like sort keys
+    /// and the search index, and look for rows left behind by deleted ones.
+    Verify {
+        /// Fix the problems found.
+        #[arg(long)]
+        repair: bool,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum RepoCommand {
+    /// List the repositories in use, with a hash of each one's releases for
+    /// checking whether two databases have the same content.
+    List,
+    /// List the repositories suggested by the ones already in use. Those which
+    /// are already in use are marked with `*`.
+    Suggestions,
+    /// Add a suggested repository.
+    Add { name: String },
+    /// Change the URL a repository is downloaded from. Its mods are kept until
+    /// the next `camrete update`.
+    SetUrl { name: String, url: Url },
+    /// Switch a repository to another branch or tag of its source, such as
+    /// CKAN-meta's `master`, `stable` or `testing`. The URL is worked out from
+    /// its GitHub or GitLab URL unless a template is given.
+    SetChannel {
+        name: String,
+        channel: String,
+        /// The repository's URL, with `{channel}` in place of the branch or
+        /// tag. It's remembered for later changes of channel.
+        #[arg(long)]
+        template: Option<String>,
+    },
+    /// Move a repository to where its URL permanently redirected at its last
+    /// update. `camrete update` warns when that happens.
+    FollowRedirect { name: String },
+    /// Add the repositories CKAN uses, keeping their priorities. Repositories
+    /// which are already in use aren't replaced.
+    Import {
+        /// A game directory, or the `CKAN/registry.json` file in one. Without
+        /// it, every game directory in CKAN's settings is read.
+        registry: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum FilterCommand {
+    /// List the saved filters.
+    List,
+    /// Save a search for use with `list --filter-name`, replacing any filter
+    /// with the same name.
+    Save { name: String, filter: String },
+    /// Delete a saved filter.
+    Remove { name: String },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum FavCommand {
+    /// List the favorite mods.
+    List,
+    /// Mark a mod as a favorite.
+    Add { identifier: Identifier },
+    /// Stop a mod being a favorite.
+    Remove { identifier: Identifier },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum InstalledCommand {
+    /// List the installed mods. Mods installed only to satisfy another mod's
+    /// dependencies are marked with `(auto)`, and mods installed from a zip
+    /// file with `(local)`.
+    List,
+    /// Import the mods installed by CKAN, replacing the records of any which
+    /// were already imported.
+    Import {
+        /// A game directory, or the `CKAN/registry.json` file in one.
+        registry: PathBuf,
+    },
+    /// Save the installed mods as a metapackage, which CKAN can install to
+    /// get the same mods. It's written to `installed-<NAME>.ckan` unless
+    /// `--output` is given.
+    Export {
+        /// The name of the game instance, used in the metapackage's
+        /// identifier.
+        #[clap(long, default_value = "default")]
+        name: String,
+        /// Where to write the metapackage. Use `-` to print it.
+        #[clap(long)]
+        output: Option<PathBuf>,
+        /// Recommend the mods instead of depending on them, so they can be
+        /// chosen individually when the metapackage is installed.
+        #[clap(long)]
+        recommends: bool,
+        /// How closely the installed versions are required.
+        #[clap(long, value_enum, default_value = "none")]
+        pin: Pin,
+        /// Also list the mods which were only installed as dependencies.
+        #[clap(long)]
+        include_auto: bool,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum MetricsCommand {
+    /// Show how often each thing has happened, and how long it took on
+    /// average.
+    Show,
+    /// Delete the recorded metrics.
+    Reset,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ProfileCommand {
+    /// List the profiles. The default profile is marked with `*`.
+    List,
+    /// Add a profile.
+    Create {
+        name: String,
+        /// The database file to use, instead of a new one in the user's data
+        /// directory.
+        database: Option<PathBuf>,
+    },
+    /// Remove a profile from the list. Its database file isn't deleted.
+    Remove { name: String },
+    /// Choose which profile is used when `--profile` isn't given. Run it
+    /// without a name to go back to the database in the user's data directory.
+    Default { name: Option<String> },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ConfigCommand {
+    /// Show the value of a setting, either globally or for one module.
+    Get {
+        setting: Setting,
+        module: Option<String>,
+    },
+    /// Change a setting, either globally or for one module.
+    ///
+    /// Set a module's value to `default` to make it follow the global setting
+    /// again.
+    Set {
+        setting: Setting,
+        #[clap(value_name = "[MODULE] VALUE", num_args = 1..=2, required = true)]
+        args: Vec<String>,
     },
+    /// Limit which releases are saved from a repository, starting from its
+    /// next update.
+    ///
+    /// Run it without any limits to save every release again.
+    RepoFilter {
+        repo: String,
+        /// Skip releases with this tag. Can be given more than once.
+        #[clap(long = "exclude-tag", value_name = "TAG")]
+        exclude_tags: Vec<String>,
+        /// Only save releases of this kind. Can be given more than once.
+        #[clap(long = "only-kind", value_name = "KIND")]
+        only_kinds: Vec<Kind>,
+        /// Skip releases which need a newer version of the metadata spec, like
+        /// `v1.34`.
+        #[clap(long)]
+        max_spec_version: Option<SpecVersion>,
+        /// Skip releases which are less stable than this.
+        #[clap(long)]
+        max_release_status: Option<Status>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Kind {
+    Package,
+    Metapackage,
+    Dlc,
+}
+
+impl From<Kind> for ModuleKind {
+    fn from(value: Kind) -> Self {
+        match value {
+            Kind::Package => Self::Package,
+            Kind::Metapackage => Self::Metapackage,
+            Kind::Dlc => Self::Dlc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Status {
+    Stable,
+    Testing,
+    Development,
+}
+
+impl From<Status> for ReleaseStatus {
+    fn from(value: Status) -> Self {
+        match value {
+            Status::Stable => Self::Stable,
+            Status::Testing => Self::Testing,
+            Status::Development => Self::Development,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Pin {
+    /// Any version.
+    None,
+    /// The installed version or a newer one.
+    Minimum,
+    /// Exactly the installed version.
+    Exact,
+}
+
+impl From<Pin> for VersionPin {
+    fn from(value: Pin) -> Self {
+        match value {
+            Pin::None => Self::None,
+            Pin::Minimum => Self::Minimum,
+            Pin::Exact => Self::Exact,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListFormat {
+    Text,
+    Csv,
+    Tsv,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Setting {
+    /// Whether testing and development releases are considered.
+    Prerelease,
+    /// Whether release metadata is compressed to save disk space, starting
+    /// from the next update. This setting can't be changed per module.
+    CompressMetadata,
+    /// Whether usage metrics are recorded in the database, for diagnosing
+    /// performance problems. They're never sent anywhere. This setting can't
+    /// be changed per module.
+    Telemetry,
+    /// Whether `show --notes` fetches release notes from GitHub. This setting
+    /// can't be changed per module.
+    ReleaseNotes,
 }
 
 #[tokio::main]
-async fn main() -> miette::Result<()> {
+async fn main() -> ExitCode {
     tracing_subscriber::fmt::fmt()
         .pretty()
         .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(io::stderr)
         .finish()
         .init();
 
-    let args = Args::parse();
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(error) => {
+            let _ = error.print();
+            return if error.use_stderr() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+
+    l10n::init(args.lang.as_deref());
 
-    let mut repo_mgr = RepoManager::new("development.db")?;
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            let code = error.exit_code();
+            let is_user_error = error.is_user_error();
+            eprintln!("{:?}", miette::Report::new(error));
+            if !is_user_error {
+                eprintln!("{}", t!("error-internal"));
+            }
+            code
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), CliError> {
+    let output = args.output();
+
+    match args.command {
+        Command::Db(DbCommand::Path) => {
+            let path = match (&args.db, selected_profile(&args)?) {
+                (Some(path), _) => path.clone(),
+                (None, Some(profile)) => profile.database,
+                (None, None) => RepoManager::data_dir_path(),
+            };
+            println!("{}", path.display());
+            return Ok(());
+        }
+        Command::Profile(command) => return manage_profiles(command, output),
+        _ => {}
+    }
+
+    let mut repo_mgr = match (&args.db, selected_profile(&args)?) {
+        (Some(path), _) => RepoManager::new(&path.to_string_lossy())?,
+        (None, Some(profile)) => profile.open()?,
+        (None, None) => RepoManager::from_data_dir().await?,
+    };
+    let policy = match &args.policy {
+        Some(path) => Policy::load_from(path.clone()),
+        None => Policy::load(),
+    };
+    repo_mgr.set_policy(policy?);
 
     match args.command {
-        Command::Update {} => {
-            update(&mut repo_mgr).await?;
+        Command::Update {
+            dry_run: false,
+            wait,
+            force_reunpack,
+            git,
+        } => {
+            repo_mgr.set_wait_for_updates(wait);
+            repo_mgr.set_force_reunpack(force_reunpack);
+            repo_mgr.set_archive_cache(Some(RepoManager::archive_cache_path()));
+            if git {
+                repo_mgr.set_git_checkouts(Some(RepoManager::git_checkouts_path()));
+            }
+            update(&mut repo_mgr, output).await?;
+        }
+        Command::Update { dry_run: true, .. } => {
+            repo_mgr.set_archive_cache(Some(RepoManager::archive_cache_path()));
+            preview_update(&repo_mgr, output).await?;
+        }
+        Command::RefreshModule { source, repo } => {
+            refresh_module(&repo_mgr, source, repo, output).await?;
+        }
+        Command::Show {
+            identifier,
+            compat,
+            notes,
+            files,
+            check_bugtracker,
+        } => {
+            show(
+                &mut repo_mgr,
+                identifier,
+                compat,
+                notes,
+                files,
+                check_bugtracker,
+                output,
+            )
+            .await?;
+        }
+        Command::List {
+            author,
+            tag,
+            depends_on,
+            filter,
+            filter_name,
+            format,
+            columns,
+        } => {
+            let mut filter = match (filter, filter_name) {
+                (Some(text), _) => text.parse().map_err(camrete_core::Error::from)?,
+                (None, Some(name)) => repo_mgr
+                    .db()?
+                    .saved_filter(&name)?
+                    .ok_or(CliError::FilterNotFound(name))?,
+                (None, None) => ModuleFilter::default(),
+            };
+            if let Some(name) = author {
+                filter.terms.push(FilterTerm {
+                    negated: false,
+                    condition: FilterCondition::Author { name },
+                });
+            }
+            filter.terms.extend(tag.into_iter().map(|tag| FilterTerm {
+                negated: false,
+                condition: FilterCondition::Tag { tag },
+            }));
+            filter.terms.extend(depends_on.into_iter().map(|target| FilterTerm {
+                negated: false,
+                condition: FilterCondition::Relationship {
+                    rel_type: RelationshipType::Depends,
+                    target,
+                },
+            }));
+
+            match format {
+                ListFormat::Text => list(&repo_mgr, &filter, output)?,
+                ListFormat::Csv => export(&repo_mgr, &filter, &columns, TableFormat::Csv)?,
+                ListFormat::Tsv => export(&repo_mgr, &filter, &columns, TableFormat::Tsv)?,
+            }
+        }
+        Command::Yank {
+            identifier,
+            version,
+            reason,
+            undo,
+        } => {
+            yank(&repo_mgr, identifier, version, reason, undo, output)?;
+        }
+        Command::Note {
+            identifier,
+            text,
+            clear,
+        } => {
+            note(&repo_mgr, identifier, text, clear, output)?;
+        }
+        Command::History {
+            identifier,
+            version,
+        } => {
+            history(&repo_mgr, identifier, version, output)?;
+        }
+        Command::Why {
+            identifier,
+            requested,
+            recommends,
+        } => {
+            why(&repo_mgr, identifier, requested, recommends, output)?;
+        }
+        Command::Plan {
+            identifiers,
+            remove,
+            recommends,
+        } => {
+            plan(&repo_mgr, identifiers, remove, recommends, output)?;
+        }
+        Command::Install {
+            file,
+            identifier,
+            metadata,
+            game_dir,
+        } => {
+            install_local(&repo_mgr, &file, identifier, metadata, &game_dir, output)?;
+        }
+        Command::Info(InfoCommand::Repo { name }) => {
+            repo_info(&repo_mgr, name, output)?;
+        }
+        Command::Info(InfoCommand::Dlc { game_dir }) => {
+            dlc_info(&game_dir, output)?;
+        }
+        Command::Info(InfoCommand::Cycles) => {
+            cycles_info(&repo_mgr, output)?;
+        }
+        Command::Info(InfoCommand::Stale { abandoned }) => {
+            stale_info(&repo_mgr, abandoned, output)?;
+        }
+        Command::Db(DbCommand::Backup { file }) => {
+            repo_mgr.db()?.backup_to(&file)?;
+            if output.is_verbose() {
+                println!("{}", t!("backup-saved", path = file.display().to_string()));
+            }
+        }
+        Command::Db(DbCommand::Restore { file }) => {
+            repo_mgr.db()?.restore_from(&file)?;
+            if output.is_verbose() {
+                println!("{}", t!("backup-restored", path = file.display().to_string()));
+            }
+        }
+        Command::Db(DbCommand::Verify { repair }) => {
+            verify_db(&repo_mgr, repair, output)?;
+        }
+        Command::Db(DbCommand::Path) | Command::Profile(_) => unreachable!(),
+        Command::Config(command) => {
+            config(&repo_mgr, command)?;
+        }
+        Command::Repo(command) => {
+            manage_repos(&repo_mgr, command, output)?;
+        }
+        Command::Filter(command) => {
+            manage_filters(&repo_mgr, command, output)?;
+        }
+        Command::Fav(command) => {
+            favorites(&repo_mgr, command, output)?;
         }
-        Command::Show { identifier } => {
-            show(&mut repo_mgr, identifier).await?;
+        Command::Installed(command) => {
+            installed(&repo_mgr, command, output)?;
+        }
+        Command::Metrics(command) => {
+            metrics(&repo_mgr, command, output)?;
+        }
+        Command::Tags { grouped } => {
+            tags(&repo_mgr, grouped, output)?;
         }
     }
 
     Ok(())
 }
 
-async fn update(repo_mgr: &mut RepoManager) -> camrete_core::Result<()> {
+/// The profile chosen with `--profile`, or the default profile if there is one.
+fn selected_profile(args: &Args) -> Result<Option<Profile>, CliError> {
+    if args.db.is_some() {
+        return Ok(None);
+    }
+
+    let registry = ProfileRegistry::load()?;
+    Ok(match &args.profile {
+        Some(name) => Some(registry.get(name)?),
+        None => registry.default_profile(),
+    })
+}
+
+fn manage_profiles(command: ProfileCommand, output: Output) -> Result<(), CliError> {
+    let mut registry = ProfileRegistry::load()?;
+
+    match command {
+        ProfileCommand::List => {
+            let default = registry.default_profile().map(|p| p.name);
+
+            for profile in registry.profiles() {
+                let is_default = default.as_ref() == Some(&profile.name);
+
+                if output == Output::Porcelain {
+                    println!(
+                        "{}\t{}\t{}",
+                        field(&profile.name),
+                        field(&profile.database.to_string_lossy()),
+                        if is_default { "default" } else { "" }
+                    );
+                } else {
+                    let marker = if is_default { "*" } else { " " };
+                    println!(
+                        "{marker} {} ({})",
+                        profile.name.bright_green(),
+                        profile.database.display()
+                    );
+                }
+            }
+            return Ok(());
+        }
+        ProfileCommand::Create { name, database } => {
+            let profile = registry.create(&name, database.as_deref())?;
+            if output.is_verbose() {
+                println!(
+                    "{}",
+                    t!(
+                        "profile-created",
+                        name = &*profile.name,
+                        path = profile.database.display().to_string()
+                    )
+                );
+            }
+        }
+        ProfileCommand::Remove { name } => {
+            let profile = registry.remove(&name)?;
+            if output.is_verbose() {
+                println!(
+                    "{}",
+                    t!(
+                        "profile-removed",
+                        name = &*profile.name,
+                        path = profile.database.display().to_string()
+                    )
+                );
+            }
+        }
+        ProfileCommand::Default { name } => registry.set_default(name.as_deref())?,
+    }
+
+    Ok(registry.save()?)
+}
+
+async fn update(repo_mgr: &mut RepoManager, output: Output) -> camrete_core::Result<()> {
     let all_repos = repo_mgr.db()?.all_repos(true)?;
 
     for repo in all_repos {
-        println!("Updating {} ({})", repo.name, repo.url);
+        if output.is_verbose() {
+            println!(
+                "{}",
+                t!("update-started", name = &*repo.name, url = repo.url.as_str())
+            );
+        }
 
-        let bars = MultiProgress::new();
+        let bars = MultiProgress::with_draw_target(output.progress_target());
 
         let download_bar = ProgressBar::no_length().with_style(PROGRESS_STYLE_DOWNLOAD.clone());
         bars.add(download_bar.clone());
@@ -87,16 +1042,21 @@ async fn update(repo_mgr: &mut RepoManager) -> camrete_core::Result<()> {
         bars.add(unpack_bar.clone());
         unpack_bar.enable_steady_tick(Duration::from_millis(100));
 
-        repo_mgr
+        let report = repo_mgr
             .download(&repo, {
                 let download_bar = download_bar.clone();
                 let unpack_bar = unpack_bar.clone();
 
                 Box::new(move |p| {
+                    if p.waiting_for_update {
+                        unpack_bar.set_message(t!("update-waiting"));
+                        return;
+                    }
+
                     if p.items_unpacked == 0 {
-                        unpack_bar.set_message("Purging outdated modules...");
+                        unpack_bar.set_message(t!("update-purging"));
                     } else {
-                        unpack_bar.set_message(format!("{} items unpacked", p.items_unpacked));
+                        unpack_bar.set_message(t!("update-unpacked", count = p.items_unpacked));
                     }
 
                     if download_bar.is_finished() {
@@ -116,133 +1076,1936 @@ async fn update(repo_mgr: &mut RepoManager) -> camrete_core::Result<()> {
             .await?;
 
         download_bar.finish();
-        unpack_bar.finish_with_message("Update complete");
+        if report.unchanged {
+            unpack_bar.finish_with_message(t!("update-up-to-date"));
+        } else {
+            unpack_bar.finish_with_message(t!("update-complete"));
+        }
+
+        for quarantined in &report.quarantined {
+            eprintln!(
+                "{} skipped {}: {}",
+                "warning:".yellow(),
+                quarantined.path.display(),
+                quarantined.error
+            );
+        }
+        if report.releases_blocked > 0 {
+            eprintln!(
+                "{} {}",
+                "warning:".yellow(),
+                t!(
+                    "update-blocked",
+                    name = &*repo.name,
+                    count = report.releases_blocked
+                )
+            );
+        }
+        if report.releases_invalid > 0 {
+            eprintln!(
+                "{} {}",
+                "warning:".yellow(),
+                t!(
+                    "update-invalid",
+                    name = &*repo.name,
+                    count = report.releases_invalid
+                )
+            );
+        }
+        if let Some(moved_to) = &report.moved_to {
+            eprintln!(
+                "{} {}",
+                "warning:".yellow(),
+                t!("update-moved", name = &*repo.name, url = moved_to.as_str())
+            );
+        }
+
+        if output == Output::Porcelain {
+            println!("{}\t{}", field(&repo.name), report.items_unpacked);
+        } else if !report.new_terms.is_empty() {
+            println!("{}", t!("update-new-terms", name = &*repo.name));
+            for terms in &report.new_terms {
+                println!("\n{}\n{}", terms.file_name.bold(), terms.contents.trim_end());
+            }
+        }
+
+        #[cfg(feature = "parse-timing")]
+        if output.is_verbose() {
+            println!("{}", t!("update-slowest-assets"));
+            for timing in &report.slowest_assets {
+                println!(
+                    "  {:>8.2?} {:>8} bytes  {}",
+                    timing.duration,
+                    timing.size,
+                    timing.path.display()
+                );
+            }
+        }
     }
 
     Ok(())
 }
 
-async fn show(repo_mgr: &mut RepoManager, slug: String) -> Result<(), CliError> {
-    let md_skin = MadSkin::default();
+async fn preview_update(repo_mgr: &RepoManager, output: Output) -> camrete_core::Result<()> {
+    let all_repos = repo_mgr.db()?.all_repos(true)?;
 
-    let mut db = repo_mgr.db()?;
+    for repo in all_repos {
+        if output.is_verbose() {
+            println!(
+                "{}",
+                t!("update-checking", name = &*repo.name, url = repo.url.as_str())
+            );
+        }
 
-    let Some(module) = Module::all()
-        .filter(Module::with_slug(&slug))
-        .get_result(db.as_mut())
-        .optional()?
-    else {
-        return Err(CliError::ModuleNotFound(slug));
-    };
+        let download_bar = ProgressBar::with_draw_target(None, output.progress_target())
+            .with_style(PROGRESS_STYLE_DOWNLOAD.clone());
+        download_bar.enable_steady_tick(Duration::from_millis(100));
 
-    let releases: Vec<ModuleRelease> = ModuleRelease::all()
-        .filter(ModuleRelease::with_parent(module.id))
-        .order_by(ModuleRelease::by_version())
-        .load(db.as_mut())?;
+        let preview = repo_mgr
+            .preview_download(&repo, {
+                let download_bar = download_bar.clone();
 
-    let mut releases = releases.into_iter();
-    let Some(first) = releases.next() else {
-        return Err(CliError::ModuleNotFound(slug));
-    };
+                Box::new(move |p| {
+                    download_bar.set_position(p.bytes_downloaded);
+                    if let Some(bytes_expected) = p.bytes_expected {
+                        download_bar.set_length(bytes_expected);
+                    }
+                })
+            })
+            .await?;
 
-    let tags = ModuleRelease::tags_for(first.id).load::<String>(db.as_mut())?;
-    let authors = ModuleRelease::authors_for(first.id).load::<String>(db.as_mut())?;
-    let licenses = ModuleRelease::licenses_for(first.id).load::<String>(db.as_mut())?;
+        download_bar.finish_and_clear();
 
-    print!("{} {}", first.display_name.bright_green(), first.version);
-    for tag in tags {
-        print!(" {}", format!("#{tag}").blue());
-    }
-    if first.release_status != ReleaseStatus::Stable {
-        print!(" ({})", format!("{:?}", first.release_status).red());
-    }
-    println!();
+        if output == Output::Porcelain {
+            print_preview_porcelain(&repo.name, &preview);
+            continue;
+        }
 
-    println!("\n{}", md_skin.term_text(&first.summary));
+        if preview.is_empty() {
+            println!("{}", t!("update-no-changes"));
+            continue;
+        }
 
-    if let Some(description) = &first.description {
-        println!("{}", md_skin.term_text(description));
-        println!();
-    }
+        for change in &preview.added {
+            println!(
+                "  {} {} ({})",
+                "+".bright_green(),
+                change.slug,
+                change.added_versions.join(", ")
+            );
+        }
+        for change in &preview.removed {
+            println!("  {} {}", "-".red(), change.slug);
+        }
+        for change in &preview.updated {
+            let versions = change
+                .added_versions
+                .iter()
+                .map(|v| format!("+{v}"))
+                .chain(change.removed_versions.iter().map(|v| format!("-{v}")))
+                .collect::<Vec<_>>();
 
-    let resources = &first.metadata.resources;
-    if let Some(homepage) = &resources.homepage {
-        println!("{}", homepage.bold());
+            println!("  {} {} ({})", "~".yellow(), change.slug, versions.join(", "));
+        }
+
+        println!(
+            "{}",
+            t!(
+                "update-summary",
+                added = preview.added.len(),
+                removed = preview.removed.len(),
+                updated = preview.updated.len(),
+            )
+        );
     }
 
-    println!("Authors: {}", authors.join(", "));
-    println!("License: {}", licenses.join(" or "));
+    Ok(())
+}
 
+/// Prints one line per changed module: the repository, `+`, `-` or `~`, the
+/// module, and its changed versions.
+fn print_preview_porcelain(repo: &str, preview: &UpdatePreview) {
+    let repo = field(repo);
 
-    if let Some(link) = &resources.bugtracker {
-        println!("Bug tracker: {}", link.bold());
+    for change in &preview.added {
+        let versions = change.added_versions.join(",");
+        println!("{repo}\t+\t{}\t{}", field(&change.slug), field(&versions));
     }
-    if let Some(link) = &resources.repository {
-        println!("Repository: {}", link.bold());
+    for change in &preview.removed {
+        println!("{repo}\t-\t{}\t", field(&change.slug));
     }
-    if let Some(link) = &resources.spacedock {
-        println!("Spacedock: {}", link.bold());
+    for change in &preview.updated {
+        let versions = change
+            .added_versions
+            .iter()
+            .map(|v| format!("+{v}"))
+            .chain(change.removed_versions.iter().map(|v| format!("-{v}")))
+            .collect::<Vec<_>>()
+            .join(",");
+        println!("{repo}\t~\t{}\t{}", field(&change.slug), field(&versions));
     }
+}
 
-    if let Some(release_date) = first.release_date
-        && let Ok(date_str) = release_date.format(DATE_TIME_FMT)
-    {
-        println!("Release date: {}", date_str);
-    }
+fn config(repo_mgr: &RepoManager, command: ConfigCommand) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
 
-    if releases.len() != 0 {
-        print!(
-            "Other versions: {}",
-            releases
-                .by_ref()
-                .map(|r| r.version)
-                .take(3)
-                .collect::<Vec<_>>()
-                .join(", ")
+    match command {
+        ConfigCommand::Get {
+            setting: Setting::Prerelease,
+            module,
+        } => {
+            let allowed = match &module {
+                Some(slug) => db.allows_prerelease(slug)?,
+                None => db.prerelease_default()?,
+            };
+
+            println!("{}", if allowed { "on" } else { "off" });
+        }
+        ConfigCommand::Set {
+            setting: Setting::Prerelease,
+            mut args,
+        } => {
+            let value = args.pop().expect("at least one argument");
+            let module = args.pop();
+
+            match (module, value.as_str()) {
+                (None, "on") => db.set_prerelease_default(true)?,
+                (None, "off") => db.set_prerelease_default(false)?,
+                (Some(slug), "on") => db.set_module_prerelease(&slug, Some(true))?,
+                (Some(slug), "off") => db.set_module_prerelease(&slug, Some(false))?,
+                (Some(slug), "default") => db.set_module_prerelease(&slug, None)?,
+                _ => return Err(CliError::InvalidSettingValue(value)),
+            }
+        }
+        ConfigCommand::Get {
+            setting: Setting::CompressMetadata | Setting::Telemetry | Setting::ReleaseNotes,
+            module: Some(_),
+        } => return Err(CliError::ModuleSetting),
+        ConfigCommand::Get {
+            setting: Setting::CompressMetadata,
+            module: None,
+        } => {
+            let compressed = db.compresses_metadata()?;
+            println!("{}", if compressed { "on" } else { "off" });
+        }
+        ConfigCommand::Set {
+            setting: Setting::CompressMetadata,
+            args,
+        } => db.set_metadata_compression(global_switch(args)?)?,
+        ConfigCommand::Get {
+            setting: Setting::Telemetry,
+            module: None,
+        } => {
+            let enabled = db.telemetry_enabled()?;
+            println!("{}", if enabled { "on" } else { "off" });
+        }
+        ConfigCommand::Set {
+            setting: Setting::Telemetry,
+            args,
+        } => db.set_telemetry(global_switch(args)?)?,
+        ConfigCommand::Get {
+            setting: Setting::ReleaseNotes,
+            module: None,
+        } => {
+            let enabled = db.fetches_release_notes()?;
+            println!("{}", if enabled { "on" } else { "off" });
+        }
+        ConfigCommand::Set {
+            setting: Setting::ReleaseNotes,
+            args,
+        } => db.set_release_notes(global_switch(args)?)?,
+        ConfigCommand::RepoFilter {
+            repo,
+            exclude_tags,
+            only_kinds,
+            max_spec_version,
+            max_release_status,
+        } => {
+            let Some(repo) = db.all_repos(true)?.into_iter().find(|r| r.name == repo) else {
+                return Err(CliError::RepoNotFound(repo));
+            };
+
+            let filter = RepoFilter {
+                exclude_tags,
+                only_kinds: only_kinds.into_iter().map(Into::into).collect(),
+                max_spec_version,
+                max_release_status: max_release_status.map(Into::into),
+            };
+            db.set_repo_filter(repo.id, &filter)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn manage_repos(
+    repo_mgr: &RepoManager,
+    command: RepoCommand,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    match command {
+        RepoCommand::List => {
+            for repo in db.all_repos(true)? {
+                if output == Output::Porcelain {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}",
+                        field(&repo.name),
+                        repo.url,
+                        repo.priority,
+                        if repo.x_mirror { "mirror" } else { "" },
+                        field(repo.x_comment.as_deref().unwrap_or_default()),
+                        repo.content_hash.as_deref().unwrap_or_default()
+                    );
+                } else {
+                    println!(
+                        "{} ({}){}",
+                        repo.name.bright_green(),
+                        repo.url,
+                        mirror_note(repo.x_mirror, repo.x_comment.as_deref())
+                    );
+                    if let Some(channel) = repo.current_channel() {
+                        let channel = channel.bright_cyan().to_string();
+                        println!("  {}", t!("repo-list-channel", channel = channel));
+                    }
+                    if let Some(moved_url) = &repo.moved_url {
+                        let url = moved_url.as_str().yellow().to_string();
+                        println!("  {}", t!("repo-list-moved", url = url));
+                    } else if let Some(final_url) = &repo.final_url {
+                        println!("  {}", t!("repo-list-redirects", url = final_url.as_str()));
+                    }
+                    if let Some(hash) = &repo.content_hash {
+                        let hash = hash.dimmed().to_string();
+                        println!("  {}", t!("repo-list-content-hash", hash = hash));
+                    }
+                }
+            }
+        }
+        RepoCommand::Suggestions => {
+            for suggestion in db.repo_suggestions()? {
+                if output == Output::Porcelain {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}",
+                        field(&suggestion.name),
+                        suggestion.url,
+                        if suggestion.configured { "configured" } else { "" },
+                        field(&suggestion.suggested_by.join(",")),
+                        if suggestion.x_mirror { "mirror" } else { "" },
+                        field(suggestion.x_comment.as_deref().unwrap_or_default())
+                    );
+                } else {
+                    let marker = if suggestion.configured { "*" } else { " " };
+                    println!(
+                        "{marker} {} ({}), suggested by {}{}",
+                        suggestion.name.bright_green(),
+                        suggestion.url,
+                        suggestion.suggested_by.join(", "),
+                        mirror_note(suggestion.x_mirror, suggestion.x_comment.as_deref())
+                    );
+                }
+            }
+        }
+        RepoCommand::Add { name } => {
+            let Some(repo) = db.add_suggested_repo(&name)? else {
+                return Err(CliError::SuggestionNotFound(name));
+            };
+            if output.is_verbose() {
+                println!(
+                    "{}",
+                    t!("repo-added", name = &*repo.name, url = repo.url.as_str())
+                );
+            }
+        }
+        RepoCommand::SetUrl { name, url } => {
+            let Some(repo) = db.all_repos(true)?.into_iter().find(|r| r.name == name) else {
+                return Err(CliError::RepoNotFound(name));
+            };
+
+            db.update_repo_url(repo.id, &url)?;
+            if output.is_verbose() {
+                println!("{}", t!("repo-url-changed", name = name, url = url.as_str()));
+            }
+        }
+        RepoCommand::SetChannel {
+            name,
+            channel,
+            template,
+        } => {
+            let Some(repo) = db.all_repos(true)?.into_iter().find(|r| r.name == name) else {
+                return Err(CliError::RepoNotFound(name));
+            };
+
+            let repo = db.set_repo_channel(repo.id, &channel, template.as_deref())?;
+            if output.is_verbose() {
+                println!(
+                    "{}",
+                    t!(
+                        "repo-channel-changed",
+                        name = name,
+                        channel = channel,
+                        url = repo.url.as_str()
+                    )
+                );
+            }
+        }
+        RepoCommand::FollowRedirect { name } => {
+            let Some(repo) = db.all_repos(true)?.into_iter().find(|r| r.name == name) else {
+                return Err(CliError::RepoNotFound(name));
+            };
+
+            match db.follow_repo_redirect(repo.id)? {
+                Some(repo) if output.is_verbose() => {
+                    println!(
+                        "{}",
+                        t!("repo-url-changed", name = name, url = repo.url.as_str())
+                    );
+                }
+                Some(_) => {}
+                None => println!("{}", t!("repo-not-moved", name = name)),
+            }
+        }
+        RepoCommand::Import { registry } => {
+            let repos = match registry {
+                Some(path) => CkanRegistry::load(&path).map(|r| r.repositories()),
+                None => CkanConfig::load_default().map(|config| config.repositories()),
+            }
+            .map_err(camrete_core::Error::from)?;
+            let imported = db.import_ckan_repos(repos)?;
+
+            for repo in &imported {
+                if output == Output::Porcelain {
+                    let outcome = match repo.outcome {
+                        RepoImport::Added => "added",
+                        RepoImport::AlreadyAdded => "existing",
+                        RepoImport::NameTaken => "name-taken",
+                    };
+                    println!("{}\t{}\t{}", field(&repo.name), repo.url, outcome);
+                } else if repo.outcome == RepoImport::NameTaken {
+                    eprintln!(
+                        "{} {}",
+                        "warning:".yellow(),
+                        t!("repo-import-name-taken", name = &*repo.name, url = repo.url.as_str())
+                    );
+                }
+            }
+
+            if output.is_verbose() {
+                let added = imported
+                    .iter()
+                    .filter(|r| r.outcome == RepoImport::Added)
+                    .count();
+                println!("{}", t!("repo-imported", count = added));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes a repository's mirror designation and comment, if it has either,
+/// like ` [mirror: Hosted in Europe]`.
+fn mirror_note(mirror: bool, comment: Option<&str>) -> String {
+    match (mirror, comment) {
+        (true, Some(comment)) => {
+            format!(" [{}]", t!("repo-list-mirror-comment", comment = comment))
+        }
+        (true, None) => format!(" [{}]", t!("repo-list-mirror")),
+        (false, Some(comment)) => format!(" [{comment}]"),
+        (false, None) => String::new(),
+    }
+}
+
+/// Parses the value of a setting which can't be changed per module.
+fn global_switch(args: Vec<String>) -> Result<bool, CliError> {
+    match args.as_slice() {
+        [value] if value == "on" => Ok(true),
+        [value] if value == "off" => Ok(false),
+        [_, _] => Err(CliError::ModuleSetting),
+        _ => Err(CliError::InvalidSettingValue(args.concat())),
+    }
+}
+
+fn metrics(
+    repo_mgr: &RepoManager,
+    command: MetricsCommand,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    match command {
+        MetricsCommand::Show => {
+            if output.is_verbose() && !db.telemetry_enabled()? {
+                eprintln!("{}", t!("metrics-telemetry-off"));
+            }
+
+            for summary in db.metrics()? {
+                let name = summary.metric.name();
+                let average = summary.average_duration.map(|d| d.as_millis());
+
+                if output == Output::Porcelain {
+                    let average = average.map(|ms| ms.to_string()).unwrap_or_default();
+                    println!("{name}\t{}\t{average}", summary.count);
+                    continue;
+                }
+
+                let name = name.bright_green().to_string();
+                match average {
+                    Some(ms) => println!(
+                        "{}",
+                        t!(
+                            "metrics-count-average",
+                            name = name,
+                            count = summary.count,
+                            average = ms.to_string()
+                        )
+                    ),
+                    None => println!("{}", t!("metrics-count", name = name, count = summary.count)),
+                }
+            }
+        }
+        MetricsCommand::Reset => db.reset_metrics()?,
+    }
+
+    Ok(())
+}
+
+fn manage_filters(
+    repo_mgr: &RepoManager,
+    command: FilterCommand,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    match command {
+        FilterCommand::List => {
+            for saved in db.saved_filters()? {
+                if output == Output::Porcelain {
+                    println!("{}\t{}", field(&saved.name), field(&saved.filter.to_string()));
+                } else {
+                    println!("{}: {}", saved.name.bright_green(), saved.filter);
+                }
+            }
+        }
+        FilterCommand::Save { name, filter } => {
+            let filter = filter.parse::<ModuleFilter>().map_err(camrete_core::Error::from)?;
+            db.save_filter(&name, &filter)?;
+        }
+        FilterCommand::Remove { name } => {
+            if !db.delete_saved_filter(&name)? {
+                return Err(CliError::FilterNotFound(name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn installed(
+    repo_mgr: &RepoManager,
+    command: InstalledCommand,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    match command {
+        InstalledCommand::List => {
+            for module in db.installed_modules()? {
+                if output == Output::Porcelain {
+                    println!(
+                        "{}\t{}\t{}",
+                        field(&module.slug),
+                        field(&module.version),
+                        module.auto_installed
+                    );
+                } else if module.local_archive.is_some() {
+                    println!(
+                        "{} {} {}",
+                        module.slug.bright_green(),
+                        module.version,
+                        t!("installed-list-local")
+                    );
+                } else if module.auto_installed {
+                    println!(
+                        "{} {} {}",
+                        module.slug.bright_green(),
+                        module.version,
+                        t!("installed-list-auto")
+                    );
+                } else {
+                    println!("{} {}", module.slug.bright_green(), module.version);
+                }
+            }
+        }
+        InstalledCommand::Import { registry } => {
+            let registry = CkanRegistry::load(&registry).map_err(camrete_core::Error::from)?;
+            let imported = db.import_ckan_registry(registry)?;
+
+            if output.is_verbose() {
+                let unknown = imported.iter().filter(|m| m.release.is_none()).count();
+                println!(
+                    "{}",
+                    t!("installed-imported", count = imported.len(), unknown = unknown)
+                );
+            }
+        }
+        InstalledCommand::Export {
+            name,
+            output: path,
+            recommends,
+            pin,
+            include_auto,
+        } => {
+            let options = MetapackageOptions {
+                name,
+                relationship: if recommends {
+                    ExportRelationship::Recommends
+                } else {
+                    ExportRelationship::Depends
+                },
+                pin: pin.into(),
+                include_auto_installed: include_auto,
+            };
+
+            let mut metapackage = Vec::new();
+            db.export_installed(&options, &mut metapackage)?;
+
+            let path = path.unwrap_or_else(|| options.file_name().into());
+            if path == Path::new("-") {
+                print!("{}", String::from_utf8_lossy(&metapackage));
+                return Ok(());
+            }
+
+            std::fs::write(&path, metapackage).map_err(camrete_core::Error::from)?;
+            if output.is_verbose() {
+                println!("{}", t!("installed-exported", path = path.display().to_string()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn install_local(
+    repo_mgr: &RepoManager,
+    file: &Path,
+    identifier: Option<Identifier>,
+    metadata: Option<PathBuf>,
+    game_dir: &Path,
+    output: Output,
+) -> Result<(), CliError> {
+    let metadata = metadata.map(|path| read_metadata(&path)).transpose()?;
+    let installed =
+        repo_mgr
+            .db()?
+            .install_local_archive(file, game_dir, metadata, identifier.as_ref())?;
+
+    if output == Output::Porcelain {
+        println!(
+            "{}\t{}\t{}",
+            field(&installed.slug),
+            field(&installed.version),
+            installed.files.len()
+        );
+    } else if output.is_verbose() {
+        println!(
+            "{}",
+            t!(
+                "installed-local",
+                identifier = installed.slug,
+                version = installed.version,
+                path = file.display().to_string(),
+                count = installed.files.len()
+            )
         );
+    }
+
+    Ok(())
+}
+
+/// Prints each tag with its module count. Porcelain output is the same whether
+/// or not the tags are grouped, so it can be used for shell completion.
+fn tags(repo_mgr: &RepoManager, grouped: bool, output: Output) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    if output == Output::Porcelain || !grouped {
+        for tag in db.tags()? {
+            if output == Output::Porcelain {
+                println!("{}\t{}", field(&tag.tag), tag.modules);
+            } else {
+                println!("{} ({})", tag.tag.blue(), tag.modules);
+            }
+        }
+        return Ok(());
+    }
+
+    for group in db.tag_groups()? {
+        println!("{}", group.prefix.bold());
+        for tag in group.tags {
+            println!("  {} ({})", tag.tag.blue(), tag.modules);
+        }
+    }
+
+    Ok(())
+}
+
+fn list(repo_mgr: &RepoManager, filter: &ModuleFilter, output: Output) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+    let favorites = db.favorites()?;
+
+    for module in db.filtered_modules(filter)? {
+        let release = db.latest_release(module.id)?;
+        let slug = if favorites.iter().any(|f| f.slug == module.slug) {
+            format!("{} {}", module.slug.bright_green(), "★".yellow())
+        } else {
+            module.slug.bright_green().to_string()
+        };
+
+        if output == Output::Porcelain {
+            let (version, summary) = release
+                .as_ref()
+                .map_or(("", ""), |r| (r.version.as_str(), r.summary.as_str()));
+            println!("{}\t{}\t{}", field(&module.slug), field(version), field(summary));
+            continue;
+        }
+
+        match release {
+            Some(release) => println!("{slug} {} - {}", release.version, release.summary),
+            None => println!("{slug}"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn refresh_module(
+    repo_mgr: &RepoManager,
+    source: RefreshSource,
+    repo: Option<String>,
+    output: Output,
+) -> Result<(), CliError> {
+    let repo = match repo {
+        Some(name) => {
+            let repos = repo_mgr.db()?.all_repos(true)?;
+            let Some(repo) = repos.into_iter().find(|r| r.name == name) else {
+                return Err(CliError::RepoNotFound(name));
+            };
+            Some(repo)
+        }
+        None => None,
+    };
+
+    let refreshed = repo_mgr.refresh_module(&source, repo.as_ref()).await?;
+
+    if output == Output::Porcelain {
+        println!(
+            "{}\t{}\t{}\t{}",
+            field(&refreshed.identifier),
+            field(&refreshed.version),
+            field(&refreshed.repo),
+            refreshed.url
+        );
+    } else if output.is_verbose() {
+        let identifier = &*refreshed.identifier;
+        let (version, name) = (&*refreshed.version, &*refreshed.repo);
+        let message = if refreshed.replaced {
+            t!("refresh-replaced", identifier = identifier, version = version, name = name)
+        } else {
+            t!("refresh-added", identifier = identifier, version = version, name = name)
+        };
+        println!("{message}");
+    }
+
+    Ok(())
+}
+
+fn yank(
+    repo_mgr: &RepoManager,
+    slug: Identifier,
+    version: String,
+    reason: Option<String>,
+    undo: bool,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    if undo {
+        let was_yanked = db.unyank_release(&slug, &version)?;
+        if output.is_verbose() {
+            let message = if was_yanked {
+                t!("yank-undone", version = &*version, identifier = &*slug)
+            } else {
+                t!("yank-not-yanked", version = &*version, identifier = &*slug)
+            };
+            println!("{message}");
+        }
+        return Ok(());
+    }
+
+    let Some(module) = db.find_module(&slug)? else {
+        return Err(CliError::ModuleNotFound(slug.into()));
+    };
+    let exists = db
+        .module_releases(module.id)?
+        .iter()
+        .any(|release| release.version == version);
+    if !exists {
+        return Err(CliError::ReleaseNotFound {
+            slug: slug.into(),
+            version,
+        });
+    }
+
+    db.yank_release(&slug, &version, reason.as_deref())?;
+    if output.is_verbose() {
+        println!("{}", t!("yank-done", version = &*version, identifier = &*slug));
+    }
+
+    Ok(())
+}
+
+fn note(
+    repo_mgr: &RepoManager,
+    slug: Identifier,
+    text: Option<String>,
+    clear: bool,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    if clear {
+        db.set_module_note(&slug, None)?;
+        if output.is_verbose() {
+            println!("{}", t!("note-removed", identifier = &*slug));
+        }
+        return Ok(());
+    }
+
+    let Some(text) = text else {
+        match db.module_note(&slug)? {
+            Some(note) if output == Output::Porcelain => println!("{}", field(&note.note)),
+            Some(note) => println!("{}", note.note),
+            None if output.is_verbose() => {
+                println!("{}", t!("note-none", identifier = &*slug));
+            }
+            None => {}
+        }
+        return Ok(());
+    };
+
+    require_module(&mut db, &slug)?;
+    db.set_module_note(&slug, Some(&text))?;
+    if output.is_verbose() {
+        println!("{}", t!("note-saved", identifier = &*slug));
+    }
+
+    Ok(())
+}
+
+fn favorites(repo_mgr: &RepoManager, command: FavCommand, output: Output) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+
+    match command {
+        FavCommand::List => {
+            for favorite in db.favorites()? {
+                if output == Output::Porcelain {
+                    let added_at = favorite.added_at.format(&Rfc3339).unwrap_or_default();
+                    println!("{}\t{added_at}", field(&favorite.slug));
+                } else {
+                    println!("{} {}", favorite.slug.bright_green(), "★".yellow());
+                }
+            }
+        }
+        FavCommand::Add { identifier } => {
+            require_module(&mut db, &identifier)?;
+            let message = if db.add_favorite(&identifier)? {
+                t!("fav-added", identifier = &*identifier)
+            } else {
+                t!("fav-already", identifier = &*identifier)
+            };
+            if output.is_verbose() {
+                println!("{message}");
+            }
+        }
+        FavCommand::Remove { identifier } => {
+            let message = if db.remove_favorite(&identifier)? {
+                t!("fav-removed", identifier = &*identifier)
+            } else {
+                t!("fav-not-favorite", identifier = &*identifier)
+            };
+            if output.is_verbose() {
+                println!("{message}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails unless a module with the given identifier is in the database, so that
+/// typos aren't saved.
+fn require_module(db: &mut RepoDB<DbConnection>, slug: &Identifier) -> Result<(), CliError> {
+    if db.find_module(slug)?.is_none() {
+        return Err(CliError::ModuleNotFound(slug.to_string()));
+    }
+
+    Ok(())
+}
+
+fn history(
+    repo_mgr: &RepoManager,
+    slug: Identifier,
+    version: Option<String>,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.db()?;
+    let history = db.module_history(&slug)?;
+    if history.is_empty() {
+        return Err(CliError::ModuleNotFound(slug.into()));
+    }
+
+    let repos = db.all_repos(false)?;
+    let repo_name = |id| {
+        repos
+            .iter()
+            .find(|repo| repo.id == id)
+            .map_or("", |repo| repo.name.as_str())
+    };
+    let entries = history
+        .iter()
+        .filter(|entry| version.as_ref().is_none_or(|v| *v == entry.version));
+
+    for entry in entries {
+        if output == Output::Porcelain {
+            let date = |date: OffsetDateTime| date.format(&Rfc3339).unwrap_or_default();
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                field(&entry.version),
+                field(repo_name(entry.repo_id)),
+                date(entry.first_seen),
+                date(entry.last_seen),
+                entry.removed_at.map(date).unwrap_or_default()
+            );
+            continue;
+        }
+
+        let date = |date: OffsetDateTime| date.format(DATE_TIME_FMT).unwrap_or_default();
+        let status = match entry.removed_at {
+            Some(removed_at) => t!("history-removed", date = date(removed_at)).red().to_string(),
+            None => t!("history-available").bright_green().to_string(),
+        };
+        println!(
+            "{} ({}) {}, {}",
+            entry.version.bold(),
+            repo_name(entry.repo_id),
+            t!("history-first-seen", date = date(entry.first_seen)),
+            status
+        );
+    }
+
+    Ok(())
+}
+
+fn why(
+    repo_mgr: &RepoManager,
+    identifier: Identifier,
+    requested: Vec<Identifier>,
+    recommends: bool,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.snapshot()?;
+    let graph = Graph::load(&mut db, None)?;
+    let requested = requested.into_iter().map(String::from).collect::<Vec<_>>();
+    let resolution = graph.resolve(&requested, recommends);
+
+    let chain = resolution.explain(&identifier);
+    if chain.is_empty() {
+        return Err(CliError::NotNeeded {
+            identifier: identifier.into(),
+            requested: requested.join(", "),
+        });
+    }
+
+    let name = |release| {
+        resolution
+            .selection(release)
+            .map(|s| format!("{} {}", s.identifier, s.version))
+            .unwrap_or_default()
+    };
+
+    if output == Output::Porcelain {
+        for selection in chain {
+            let fields = match &selection.reason {
+                Reason::Requested => ["requested".into(), String::new(), String::new()],
+                Reason::Relationship {
+                    dependent,
+                    rel_type,
+                    target,
+                    ..
+                } => [
+                    format!("{rel_type:?}").to_lowercase(),
+                    resolution
+                        .selection(*dependent)
+                        .map(|s| s.identifier.clone())
+                        .unwrap_or_default(),
+                    target.clone(),
+                ],
+            };
+            println!(
+                "{}\t{}\t{}",
+                selection.identifier,
+                field(&selection.version),
+                fields.join("\t")
+            );
+        }
+        return Ok(());
+    }
+
+    let release = format!("{} {}", chain[0].identifier, chain[0].version);
+    println!("{}", t!("why-header", release = release.bright_green().to_string()));
+
+    for selection in chain {
+        let Reason::Relationship {
+            dependent,
+            rel_type,
+            target,
+            any_of,
+            choice,
+        } = &selection.reason
+        else {
+            let release = format!("{} {}", selection.identifier, selection.version);
+            println!("  {}", t!("why-requested", release = release));
+            continue;
+        };
+
+        let rel_type = format!("{rel_type:?}").to_lowercase();
+        let mut line = if any_of.is_empty() {
+            t!(
+                "why-relationship",
+                dependent = name(*dependent),
+                rel_type = rel_type,
+                target = &**target,
+            )
+        } else {
+            t!(
+                "why-relationship-any-of",
+                dependent = name(*dependent),
+                rel_type = rel_type,
+                members = any_of.join(", "),
+                target = &**target,
+            )
+        };
+
+        let provider = &*selection.identifier;
+        let choice = match choice {
+            Choice::Newest => None,
+            Choice::OnlyProvider => Some(t!(
+                "why-only-provider",
+                provider = provider,
+                target = &**target
+            )),
+            Choice::FirstProvider => Some(t!(
+                "why-first-provider",
+                provider = provider,
+                target = &**target
+            )),
+        };
+        if let Some(choice) = choice {
+            line = format!("{line} ({choice})");
+        }
+        println!("  {line}");
+    }
+
+    Ok(())
+}
+
+fn plan(
+    repo_mgr: &RepoManager,
+    identifiers: Vec<Identifier>,
+    remove: Vec<Identifier>,
+    recommends: bool,
+    output: Output,
+) -> Result<(), CliError> {
+    let mut db = repo_mgr.snapshot()?;
+    let graph = Graph::load(&mut db, None)?;
+    let requested = identifiers.into_iter().map(String::from).collect::<Vec<_>>();
+    let remove = remove.into_iter().map(String::from).collect::<Vec<_>>();
+    let resolution = graph.resolve(&requested, recommends);
+    let diff = db.plan_diff(&resolution, &remove)?;
+
+    for warning in &diff.warnings {
+        let message = match warning {
+            PlanWarning::Unsatisfied {
+                dependent: Some(dependent),
+                targets,
+            } => t!(
+                "plan-unsatisfied-dependency",
+                dependent = &**dependent,
+                targets = targets.join(", ")
+            ),
+            PlanWarning::Unsatisfied {
+                dependent: None,
+                targets,
+            } => t!("plan-unsatisfied", identifier = targets.join(", ")),
+            PlanWarning::Downgrade {
+                identifier,
+                from_version,
+                to_version,
+            } => t!(
+                "plan-downgrade",
+                identifier = &**identifier,
+                from = &**from_version,
+                to = &**to_version
+            ),
+            PlanWarning::NotInstalled { identifier } => {
+                t!("plan-not-installed", identifier = &**identifier)
+            }
+            PlanWarning::StillNeeded {
+                identifier,
+                dependents,
+            } => t!(
+                "plan-still-needed",
+                identifier = &**identifier,
+                dependents = dependents.join(", ")
+            ),
+            PlanWarning::UnknownSize { identifier } => {
+                t!("plan-unknown-size", identifier = &**identifier)
+            }
+        };
+        eprintln!("{} {message}", "warning:".yellow());
+    }
+
+    if output == Output::Porcelain {
+        for install in &diff.installs {
+            println!("install\t{}\t{}", install.identifier, field(&install.version));
+        }
+        for upgrade in &diff.upgrades {
+            println!(
+                "upgrade\t{}\t{}\t{}",
+                upgrade.identifier,
+                field(&upgrade.from_version),
+                field(&upgrade.to_version)
+            );
+        }
+        for removal in &diff.removals {
+            println!("remove\t{}\t{}", removal.identifier, field(&removal.version));
+        }
+        return Ok(());
+    }
+
+    if diff.is_empty() {
+        if output.is_verbose() {
+            println!("{}", t!("plan-nothing"));
+        }
+        return Ok(());
+    }
+
+    if !diff.installs.is_empty() {
+        println!("{}", t!("plan-installs").bold());
+        for install in &diff.installs {
+            let release = format!("{} {}", install.identifier, install.version);
+            if install.requested {
+                println!("  {}", release.bright_green());
+            } else {
+                println!("  {} {}", release.bright_green(), t!("plan-dependency").dimmed());
+            }
+        }
+    }
+    if !diff.upgrades.is_empty() {
+        println!("{}", t!("plan-upgrades").bold());
+        for upgrade in &diff.upgrades {
+            println!(
+                "  {} {} → {}",
+                upgrade.identifier,
+                upgrade.from_version,
+                upgrade.to_version.bright_green()
+            );
+        }
+    }
+    if !diff.removals.is_empty() {
+        println!("{}", t!("plan-removals").bold());
+        for removal in &diff.removals {
+            let release = format!("{} {}", removal.identifier, removal.version);
+            println!(
+                "  {} {}",
+                release.red(),
+                t!("plan-files", count = removal.files).dimmed()
+            );
+        }
+    }
+
+    println!(
+        "{}",
+        t!(
+            "plan-sizes",
+            download = DecimalBytes(diff.download_size.max(0) as u64).to_string(),
+            install = DecimalBytes(diff.install_size.max(0) as u64).to_string()
+        )
+    );
+
+    Ok(())
+}
+
+fn export(
+    repo_mgr: &RepoManager,
+    filter: &ModuleFilter,
+    columns: &[Column],
+    format: TableFormat,
+) -> Result<(), CliError> {
+    let columns = if columns.is_empty() {
+        Column::DEFAULT
+    } else {
+        columns
+    };
+
+    // A repository updated partway through would mix old and new releases in
+    // the table.
+    repo_mgr
+        .snapshot()?
+        .export_modules(filter, columns, format, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+fn dlc_info(game_dir: &Path, output: Output) -> Result<(), CliError> {
+    let detected = detect_dlc(game_dir).map_err(camrete_core::Error::from)?;
+
+    for dlc in &detected {
+        if output == Output::Porcelain {
+            println!(
+                "{}\t{}",
+                dlc.identifier,
+                field(dlc.version.as_deref().unwrap_or_default())
+            );
+        } else {
+            println!(
+                "{} {}",
+                dlc.identifier.bright_green(),
+                dlc.version
+                    .clone()
+                    .unwrap_or_else(|| t!("dlc-unknown-version"))
+            );
+        }
+    }
+
+    if detected.is_empty() && output.is_verbose() {
+        println!("{}", t!("dlc-none", path = game_dir.display().to_string()));
+    }
+
+    Ok(())
+}
+
+fn cycles_info(repo_mgr: &RepoManager, output: Output) -> Result<(), CliError> {
+    let graph = Graph::load(&mut repo_mgr.snapshot()?, None)?;
+    let cycles = graph.dependency_cycles();
+
+    for (index, cycle) in cycles.iter().enumerate() {
+        if output == Output::Porcelain {
+            for step in &cycle.steps {
+                println!(
+                    "{index}\t{}\t{}\t{}",
+                    step.identifier,
+                    field(&step.version),
+                    step.depends_on
+                );
+            }
+            continue;
+        }
+
+        let first = &cycle.steps[0];
+        let release = format!("{} {}", first.identifier, first.version);
+        println!("{}", t!("cycle-header", count = cycle.steps.len(), release = release));
+        for step in &cycle.steps {
+            let release = format!("{} {}", step.identifier, step.version);
+            println!(
+                "  {}",
+                t!(
+                    "cycle-step",
+                    release = release.bright_green().to_string(),
+                    target = &*step.depends_on
+                )
+            );
+        }
+    }
+
+    if cycles.is_empty() && output.is_verbose() {
+        println!("{}", t!("cycles-none"));
+    }
+
+    Ok(())
+}
+
+fn stale_info(repo_mgr: &RepoManager, abandoned: bool, output: Output) -> Result<(), CliError> {
+    let at_least = if abandoned {
+        Staleness::Abandoned
+    } else {
+        Staleness::Outdated
+    };
+    let modules = repo_mgr.db()?.stale_modules(at_least)?;
+
+    for module in &modules {
+        if output == Output::Porcelain {
+            let date = (module.last_release_date)
+                .and_then(|date| date.format(&Rfc3339).ok())
+                .unwrap_or_default();
+            println!(
+                "{}\t{}\t{}\t{date}",
+                module.slug,
+                module.staleness.name(),
+                module.game_versions_behind
+            );
+            continue;
+        }
+
+        println!("{}: {}", module.slug.bright_green(), staleness_text(module));
+    }
+
+    if modules.is_empty() && output.is_verbose() {
+        println!("{}", t!("stale-none"));
+    }
+
+    Ok(())
+}
+
+fn verify_db(repo_mgr: &RepoManager, repair: bool, output: Output) -> Result<(), CliError> {
+    let report = repo_mgr.db()?.verify_derived(repair)?;
+
+    let mut problems = vec![
+        ("stale_sort_keys", report.stale_sort_keys),
+        ("search_index", report.search_index_mismatches),
+        (
+            "stale_content_hash",
+            report.stale_content_hashes.len() as u64,
+        ),
+        ("empty_modules", report.empty_modules),
+    ];
+    problems.extend(
+        report
+            .orphaned_rows
+            .iter()
+            .map(|orphans| (orphans.table.as_str(), orphans.count)),
+    );
+    problems.retain(|(_, count)| *count > 0);
+
+    for (check, count) in &problems {
+        if output == Output::Porcelain {
+            println!("{check}\t{count}");
+        } else {
+            println!(
+                "{}",
+                t!("verify-problem", check = *check, count = *count).bright_yellow()
+            );
+        }
+    }
+
+    if output == Output::Porcelain {
+        return Ok(());
+    }
+    if report.is_consistent() {
+        if output.is_verbose() {
+            println!("{}", t!("verify-consistent"));
+        }
+    } else if report.repaired {
+        println!("{}", t!("verify-repaired").bright_green());
+    } else {
+        println!("{}", t!("verify-repair-hint"));
+    }
+
+    Ok(())
+}
+
+fn repo_info(repo_mgr: &RepoManager, name: String, output: Output) -> Result<(), CliError> {
+    let Some(health) = repo_mgr.db()?.repo_health(&name)? else {
+        return Err(CliError::RepoNotFound(name));
+    };
+    let terms = repo_mgr.db()?.repo_terms(health.repo_id)?;
+
+    if output == Output::Porcelain {
+        let updated_at = health.updated_at.and_then(|date| date.format(&Rfc3339).ok());
+
+        println!("name\t{}", field(&health.name));
+        println!("url\t{}", health.url);
+        println!("modules\t{}", health.module_count);
+        println!("modules_without_releases\t{}", health.modules_without_releases);
+        println!("releases\t{}", health.release_count);
+        println!("updated_at\t{}", updated_at.unwrap_or_default());
+        println!("etag\t{}", field(health.etag.as_deref().unwrap_or_default()));
+        if let Some(filter) = &health.filter {
+            for tag in &filter.exclude_tags {
+                println!("exclude_tag\t{}", field(tag));
+            }
+            for kind in &filter.only_kinds {
+                println!("only_kind\t{}", format!("{kind:?}").to_lowercase());
+            }
+            if let Some(version) = filter.max_spec_version {
+                println!("max_spec_version\t{version}");
+            }
+            if let Some(status) = filter.max_release_status {
+                println!("max_release_status\t{}", format!("{status:?}").to_lowercase());
+            }
+        }
+        for repo_ref in &health.referenced_repos {
+            println!("ref\t{}\t{}", field(&repo_ref.name), repo_ref.url);
+        }
+        for file in &terms {
+            println!("terms\t{}", field(&file.file_name));
+        }
+        return Ok(());
+    }
+
+    println!("{} ({})", health.name.bright_green(), health.url);
+    println!(
+        "{}",
+        t!(
+            "repo-info-modules",
+            count = health.module_count,
+            without_releases = health.modules_without_releases
+        )
+    );
+    println!("{}", t!("repo-info-releases", count = health.release_count));
+
+    let updated_at = match health.updated_at.map(|date| date.format(DATE_TIME_FMT)) {
+        Some(Ok(date_str)) => date_str,
+        _ => t!("repo-info-never-updated").red().to_string(),
+    };
+    println!("{}", t!("repo-info-updated", date = updated_at));
+    let etag = health.etag.clone().unwrap_or_else(|| t!("repo-info-no-etag"));
+    println!("{}", t!("repo-info-etag", etag = etag));
+
+    if let Some(filter) = &health.filter {
+        println!("{}", t!("repo-info-filter"));
+        for tag in &filter.exclude_tags {
+            println!("  - {}", t!("repo-info-skip-tag", tag = tag.as_str()));
+        }
+        if !filter.only_kinds.is_empty() {
+            let kinds = filter.only_kinds.iter().map(|k| format!("{k:?}")).collect::<Vec<_>>();
+            println!("  - {}", t!("repo-info-only-kinds", kinds = kinds.join(", ")));
+        }
+        if let Some(version) = filter.max_spec_version {
+            let version = version.to_string();
+            println!("  - {}", t!("repo-info-max-spec-version", version = version));
+        }
+        if let Some(status) = filter.max_release_status {
+            let status = format!("{status:?}");
+            println!("  - {}", t!("repo-info-max-release-status", status = status));
+        }
+    }
+
+    println!("{}", t!("repo-info-refs"));
+    if health.referenced_repos.is_empty() {
+        println!("  {}", t!("list-none"));
+    }
+    for repo_ref in &health.referenced_repos {
+        println!("  - {} ({})", repo_ref.name, repo_ref.url);
+    }
+
+    if !terms.is_empty() {
+        println!("{}", t!("repo-info-terms"));
+        for file in &terms {
+            println!("  - {}", file.file_name);
+        }
+    }
+
+    Ok(())
+}
+
+async fn show(
+    repo_mgr: &mut RepoManager,
+    slug: Identifier,
+    compat: bool,
+    notes: bool,
+    files: bool,
+    check_bugtracker: bool,
+    output: Output,
+) -> Result<(), CliError> {
+    let md_skin = MadSkin::default();
+
+    let mut db = repo_mgr.db()?;
+
+    let Some(module) = db.find_module(&slug)? else {
+        return Err(CliError::ModuleNotFound(slug.into()));
+    };
+
+    let releases = db.module_releases(module.id)?;
+
+    // Hide prereleases unless the user wants them, or there's nothing else to show.
+    let max_status = db.max_release_status(&module.slug)?;
+    let (releases, hidden): (Vec<_>, Vec<_>) = releases
+        .into_iter()
+        .partition(|r| r.release_status <= max_status);
+    let (mut releases, hidden) = if releases.is_empty() {
+        (hidden, vec![])
+    } else {
+        (releases, hidden)
+    };
+
+    // Yanked releases are still listed, but the newest release that isn't
+    // yanked is shown first.
+    let yanked = db.yanked_releases(&module.slug)?;
+    let is_yanked = |release: &ModuleRelease| yanked.iter().any(|y| y.version == release.version);
+    if let Some(index) = releases.iter().position(|r| !is_yanked(r)) {
+        let newest = releases.remove(index);
+        releases.insert(0, newest);
+    }
+
+    let mut releases = releases.into_iter();
+    let Some(first) = releases.next() else {
+        return Err(CliError::ModuleNotFound(slug.into()));
+    };
+
+    let ReleaseDetail {
+        tags,
+        credits,
+        licenses,
+        relationships,
+    } = db.release_detail(first.id)?;
+    let authors = credits.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+    // Releases are checked when repositories are updated, so this is empty
+    // until the next update after upgrading.
+    let findings = db
+        .release_validations(&[first.id])?
+        .pop()
+        .map(|validation| validation.findings)
+        .unwrap_or_default();
+    let note = db.module_note(&module.slug)?;
+    let favorite = db.favorites()?.iter().any(|f| f.slug == module.slug);
+
+    let release_notes = if notes {
+        let found = repo_mgr.fetch_release_notes(&first).await?;
+        if found.is_none() && output.is_verbose() && !db.fetches_release_notes()? {
+            eprintln!("{}", t!("module-release-notes-off"));
+        }
+        found
+    } else {
+        None
+    };
+    let release_files = files.then(|| ReleaseFiles::new(&module.slug, &first));
+    if check_bugtracker {
+        repo_mgr.check_bugtracker(&module.slug, &first).await?;
+    }
+    let staleness = db.module_staleness(module.id)?;
+
+    if output == Output::Porcelain {
+        println!("identifier\t{}", field(&module.slug));
+        println!("name\t{}", field(&first.display_name));
+        println!("version\t{}", field(first.version.as_str()));
+        println!("status\t{}", format!("{:?}", first.release_status).to_lowercase());
+        println!("summary\t{}", field(&first.summary));
+        if favorite {
+            println!("favorite\ttrue");
+        }
+        if let Some(note) = &note {
+            println!("note\t{}", field(&note.note));
+        }
+        for (key, values) in [("author", &authors), ("license", &licenses), ("tag", &tags)] {
+            for value in values {
+                println!("{key}\t{}", field(value));
+            }
+        }
+        for credit in &credits {
+            if let Some(role) = &credit.role {
+                println!("author_role\t{}\t{}", field(&credit.name), field(role));
+            }
+        }
+
+        let resources = &first.metadata.resources;
+        for (key, link) in [
+            ("homepage", &resources.homepage),
+            ("bugtracker", &resources.bugtracker),
+            ("repository", &resources.repository),
+            ("spacedock", &resources.spacedock),
+        ] {
+            if let Some(link) = link {
+                println!("{key}\t{link}");
+            }
+        }
+        if let Some(date) = first.release_date.and_then(|date| date.format(&Rfc3339).ok()) {
+            println!("release_date\t{date}");
+        }
+        for yank in &yanked {
+            let reason = yank.reason.as_deref().unwrap_or_default();
+            println!("yanked\t{}\t{}", field(&yank.version), field(reason));
+        }
+        for finding in &findings {
+            let (kind, detail) = finding_fields(finding);
+            println!("finding\t{kind}\t{}", field(&detail));
+        }
+        if let Some(staleness) = &staleness {
+            println!("staleness\t{}", staleness.staleness.name());
+            println!("game_versions_behind\t{}", staleness.game_versions_behind);
+            if let Some(alive) = staleness.bugtracker_alive {
+                println!("bugtracker_alive\t{alive}");
+            }
+        }
+
+        // Members of an `any_of` group are separated by `|`.
+        for group in relationships.chunk_by(|a, b| a.group.id == b.group.id) {
+            let targets = group
+                .iter()
+                .map(|member| member.description.target_name.as_str())
+                .collect::<Vec<_>>()
+                .join("|");
+            let rel_type = format!("{:?}", group[0].group.rel_type).to_lowercase();
+            println!("{rel_type}\t{}", field(&targets));
+        }
+
+        if let Some(release_notes) = &release_notes {
+            println!("notes_tag\t{}", field(&release_notes.tag));
+            println!("notes_url\t{}", release_notes.url);
+            println!("notes\t{}", field(&release_notes.body));
+        }
+
+        if compat {
+            for row in db.compatibility(module.id)? {
+                let version = row.release.map_or("-".into(), |release| release.version);
+                println!("compat\t{}\t{}", game_version(row.game_version), field(&version));
+            }
+        }
+
+        if let Some(release_files) = &release_files {
+            print_files_porcelain(release_files);
+        }
+
+        return Ok(());
+    }
+
+    print!("{} {}", first.display_name.bright_green(), first.version);
+    for tag in tags {
+        print!(" {}", format!("#{tag}").blue());
+    }
+    if first.release_status != ReleaseStatus::Stable {
+        print!(" ({})", format!("{:?}", first.release_status).red());
+    }
+    if is_yanked(&first) {
+        print!(" ({})", t!("release-yanked").red());
+    }
+    if favorite {
+        print!(" {}", "★".yellow());
+    }
+    println!();
+
+    println!("\n{}", md_skin.term_text(&first.summary));
+
+    if let Some(description) = &first.description {
+        println!("{}", md_skin.term_text(&markdown::sanitize(description).markdown));
+        println!();
+    }
+
+    if let Some(note) = &note {
+        println!("{}", t!("module-note", note = &*note.note).cyan());
+        println!();
+    }
+
+    let resources = &first.metadata.resources;
+    if let Some(homepage) = &resources.homepage {
+        println!("{}", homepage.bold());
+    }
+
+    let authors = credits
+        .iter()
+        .map(|credit| match &credit.role {
+            Some(role) => format!("{} ({role})", credit.name),
+            None => credit.name.clone(),
+        })
+        .collect::<Vec<_>>();
+    println!("{}", t!("module-authors", authors = authors.join(", ")));
+    println!("{}", t!("module-license", licenses = licenses.join(" or ")));
+
+
+    if let Some(link) = &resources.bugtracker {
+        println!("{}", t!("module-bug-tracker", link = link.bold().to_string()));
+    }
+    if let Some(link) = &resources.repository {
+        println!("{}", t!("module-repository", link = link.bold().to_string()));
+    }
+    if let Some(link) = &resources.spacedock {
+        println!("{}", t!("module-spacedock", link = link.bold().to_string()));
+    }
+
+    if let Some(release_date) = first.release_date
+        && let Ok(date_str) = release_date.format(DATE_TIME_FMT)
+    {
+        println!("{}", t!("module-release-date", date = date_str));
+    }
+    if let Some(staleness) = &staleness {
+        let text = t!("module-staleness", staleness = staleness_text(staleness));
+        match staleness.staleness {
+            Staleness::Maintained | Staleness::Dormant => println!("{text}"),
+            Staleness::Outdated | Staleness::Abandoned => println!("{}", text.yellow()),
+        }
+    }
+
+    if !findings.is_empty() {
+        println!("{}", t!("module-findings").yellow());
+        for finding in &findings {
+            println!("  {}", finding_text(finding));
+        }
+    }
+
+    if releases.len() != 0 {
+        let versions = releases
+            .by_ref()
+            .map(|r| {
+                if is_yanked(&r) {
+                    format!("{} ({})", r.version, t!("release-yanked"))
+                } else {
+                    r.version
+                }
+            })
+            .take(3)
+            .collect::<Vec<_>>();
+        print!("{}", t!("module-other-versions", versions = versions.join(", ")));
 
         let remaining = releases.len();
         if remaining != 0 {
-            print!(" and {remaining} others");
+            print!(" {}", t!("module-more-versions", count = remaining));
         }
 
         println!();
     }
 
-    let dep_groups = ModuleRelationshipGroup::all()
-        .filter(ModuleRelationshipGroup::for_release(first.id))
-        .load(db.as_mut())?;
+    if !hidden.is_empty() {
+        println!(
+            "{}",
+            t!(
+                "module-hidden-prereleases",
+                count = hidden.len(),
+                identifier = &*module.slug,
+            )
+        );
+    }
 
-    println!("\nRelationships:");
+    println!("\n{}", t!("module-relationships"));
 
-    if dep_groups.is_empty() {
-        println!("  (None)");
+    if relationships.is_empty() {
+        println!("  {}", t!("list-none"));
     }
 
-    for group in dep_groups {
-        let members = ModuleRelationship::all()
-            .filter(ModuleRelationship::in_group(group.id))
-            .load(db.as_mut())?;
+    for members in relationships.chunk_by(|a, b| a.group.id == b.group.id) {
+        let group = &members[0].group;
         let is_any_of = members.len() > 1;
 
         print!("  ({:?}) ", group.rel_type);
 
         if is_any_of {
-            println!("- Any of:");
+            println!("- {}", t!("module-any-of"));
+            if let Some(help) = &group.choice_help_text {
+                println!("    {}", help.preview.dimmed());
+            }
         }
 
         for member in members {
             if is_any_of {
                 print!("    ");
             }
-            print!("- {}", member.target_name);
+            print!("- {}", member.description.target_name);
             println!()
         }
     }
 
+    if notes {
+        println!("\n{}", t!("module-release-notes"));
+
+        match &release_notes {
+            Some(release_notes) => {
+                println!("  {} ({})", release_notes.tag, release_notes.url.bold());
+                println!("{}", md_skin.term_text(&release_notes.body));
+            }
+            None => println!("  {}", t!("list-no-release-notes")),
+        }
+    }
+
+    if compat {
+        println!("\n{}", t!("module-compatibility"));
+
+        let compatibility = db.compatibility(module.id)?;
+        if compatibility.is_empty() {
+            println!("  {}", t!("list-no-game-versions"));
+        }
+
+        for row in compatibility {
+            let game_version = game_version(row.game_version);
+            match row.release {
+                Some(release) => println!("  {game_version:<6} {}", release.version),
+                None => println!("  {game_version:<6} {}", t!("release-incompatible").red()),
+            }
+        }
+    }
+
+    if let Some(release_files) = &release_files {
+        println!("\n{}", t!("module-files"));
+        print_files(release_files);
+    }
+
     Ok(())
 }
 
+/// Prints a release's downloads and install directives for porcelain output.
+/// Install directives are numbered from zero, and the lists of files under
+/// each one are printed on their own lines with the directive's number.
+fn print_files_porcelain(files: &ReleaseFiles) {
+    for url in &files.download {
+        println!("download\t{url}");
+    }
+    for (key, value) in [
+        ("download_size", files.download_size.map(|size| size.to_string())),
+        ("install_size", files.install_size.map(|size| size.to_string())),
+        ("content_type", files.download_content_type.clone()),
+        ("sha1", files.sha1.clone()),
+        ("sha256", files.sha256.clone()),
+    ] {
+        if let Some(value) = value {
+            println!("{key}\t{}", field(&value));
+        }
+    }
+
+    for (index, directive) in files.install.iter().enumerate() {
+        println!(
+            "install\t{index}\t{}\t{}\t{}\t{}",
+            directive.kind.name(),
+            field(&directive.source),
+            field(&directive.install_to),
+            field(directive.install_as.as_deref().unwrap_or_default()),
+        );
+        for (list, values) in directive.file_lists() {
+            for value in values {
+                println!("install_{list}\t{index}\t{}", field(value));
+            }
+        }
+    }
+}
+
+fn print_files(files: &ReleaseFiles) {
+    let mut download = files.download.iter();
+    match download.next() {
+        Some(url) => println!(
+            "  {}",
+            t!("files-download", url = url.as_str().bold().to_string())
+        ),
+        None => println!("  {}", t!("list-none")),
+    }
+    for url in download {
+        println!("  {}", t!("files-mirror", url = url.as_str()));
+    }
+
+    let size = |size: i64| DecimalBytes(size.max(0) as u64).to_string();
+    if let Some(download_size) = files.download_size {
+        println!(
+            "  {}",
+            t!("files-download-size", size = size(download_size))
+        );
+    }
+    if let Some(install_size) = files.install_size {
+        println!("  {}", t!("files-install-size", size = size(install_size)));
+    }
+    if let Some(content_type) = &files.download_content_type {
+        println!(
+            "  {}",
+            t!("files-content-type", content_type = content_type.as_str())
+        );
+    }
+    if let Some(sha1) = &files.sha1 {
+        println!("  SHA-1: {}", sha1.dimmed());
+    }
+    if let Some(sha256) = &files.sha256 {
+        println!("  SHA-256: {}", sha256.dimmed());
+    }
+
+    println!("  {}", t!("files-install"));
+    for (index, directive) in files.install.iter().enumerate() {
+        print!(
+            "    {}. {} {} → {}",
+            index + 1,
+            directive.kind.name(),
+            directive.source.bold(),
+            directive.install_to
+        );
+        if let Some(install_as) = &directive.install_as {
+            print!(" {}", t!("files-install-as", name = install_as.as_str()));
+        }
+        if directive.implied {
+            print!(" {}", t!("files-install-default").dimmed());
+        }
+        println!();
+
+        for (list, values) in directive.file_lists() {
+            println!("       {list}:");
+            for value in values {
+                println!("         - {value}");
+            }
+        }
+    }
+}
+
+/// Formats a game version from the compatibility grid, like `1.12`.
+fn game_version(version: GameVersion) -> String {
+    format!(
+        "{}.{}",
+        version.major().unwrap_or_default(),
+        version.minor().unwrap_or_default()
+    )
+}
+
+/// The kind of a problem with a release's metadata and what it's about, for
+/// porcelain output. Install directives are numbered from zero.
+fn finding_fields(finding: &ReleaseFinding) -> (&'static str, String) {
+    match finding {
+        ReleaseFinding::MissingSha256 => ("missing_sha256", String::new()),
+        ReleaseFinding::DeprecatedLicense { license } => ("deprecated_license", license.clone()),
+        ReleaseFinding::InvalidInstallRegex { directive, pattern } => {
+            ("invalid_install_regex", format!("{directive}:{pattern}"))
+        }
+        ReleaseFinding::InvalidInstallTarget {
+            directive,
+            install_to,
+        } => ("invalid_install_target", format!("{directive}:{install_to}")),
+        ReleaseFinding::InvalidInstallName { directive, name } => {
+            ("invalid_install_name", format!("{directive}:{name}"))
+        }
+    }
+}
+
+/// Describes a problem with a release's metadata. Install directives are
+/// numbered from one.
+fn staleness_text(module: &ModuleStaleness) -> String {
+    let mut text = match module.staleness {
+        Staleness::Maintained => t!("staleness-maintained"),
+        Staleness::Dormant => t!("staleness-dormant"),
+        Staleness::Outdated => t!("staleness-outdated", behind = module.game_versions_behind),
+        Staleness::Abandoned => t!("staleness-abandoned", behind = module.game_versions_behind),
+    };
+    if module.bugtracker_alive == Some(false) {
+        text = format!("{text} {}", t!("staleness-bugtracker-gone"));
+    }
+    text
+}
+
+fn finding_text(finding: &ReleaseFinding) -> String {
+    match finding {
+        ReleaseFinding::MissingSha256 => t!("finding-missing-sha256"),
+        ReleaseFinding::DeprecatedLicense { license } => {
+            t!("finding-deprecated-license", license = license.as_str())
+        }
+        ReleaseFinding::InvalidInstallRegex { directive, pattern } => t!(
+            "finding-invalid-install-regex",
+            directive = directive + 1,
+            pattern = pattern.as_str()
+        ),
+        ReleaseFinding::InvalidInstallTarget {
+            directive,
+            install_to,
+        } => t!(
+            "finding-invalid-install-target",
+            directive = directive + 1,
+            target = install_to.as_str()
+        ),
+        ReleaseFinding::InvalidInstallName { directive, name } => t!(
+            "finding-invalid-install-name",
+            directive = directive + 1,
+            name = name.as_str()
+        ),
+    }
+}
+
 const PROGRESS_CHARS: &str = "=> ";
 pub static PROGRESS_STYLE_DOWNLOAD: LazyLock<ProgressStyle> = LazyLock::new(|| {
     ProgressStyle::with_template(
@@ -0,0 +1,64 @@
+use std::{env, fs, hint::black_box};
+
+use camrete_core::{
+    json::{self, JsonModule},
+    repo::{RepoAssetLoader, RepoAssetVariant, TarGzAssetLoader},
+};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use futures_util::TryStreamExt;
+use tokio::runtime::Runtime;
+
+/// Parses every release in the archive as the assets are unpacked.
+async fn parse_releases<'a>(loader: impl RepoAssetLoader<'a>) -> usize {
+    let mut stream = loader.asset_stream().unwrap();
+    let mut count = 0;
+
+    while let Some(asset) = stream.try_next().await.unwrap() {
+        if asset.variant == RepoAssetVariant::Release {
+            let module: JsonModule = json::from_slice(&asset.data).unwrap();
+            black_box(module);
+            count += 1;
+        }
+    }
+
+    count
+}
+
+fn bench(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+
+    let mut archives = vec![("mini_repo", include_bytes!("mini_repo.tgz").to_vec())];
+    // A full repository snapshot, such as CKAN-meta's `master.tar.gz`, is too big to
+    // check in, so it's only benchmarked if one is provided.
+    if let Some(path) = env::var_os("CAMRETE_BENCH_SNAPSHOT") {
+        archives.push(("snapshot", fs::read(path).unwrap()));
+    }
+
+    let mut group = c.benchmark_group("decompress_repo");
+
+    for (name, archive) in &archives {
+        group.throughput(Throughput::Bytes(archive.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("serial", name), archive, |b, archive| {
+            b.to_async(&runtime).iter(|| {
+                let archive = black_box(archive.clone());
+                parse_releases(TarGzAssetLoader::from_buf(archive))
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("background", name), archive, |b, archive| {
+            b.to_async(&runtime).iter(|| {
+                let archive = black_box(archive.clone());
+                async move {
+                    let loader = TarGzAssetLoader::from_buf(archive).decompress_in_background();
+                    parse_releases(loader).await
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+use std::hint::black_box;
+
+use camrete_core::{
+    DbConnection,
+    database::{RepoDB, models::ModuleRelease, schema::module_releases},
+    json::JsonModule,
+    repo::RepoManager,
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use diesel::prelude::*;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use serde_json::{from_value, json};
+
+const MODULES: usize = 500;
+const RELEASES_PER_MODULE: usize = 20;
+
+/// Fills a database with modules which each have many releases, added in a
+/// random order so that insertion order doesn't match version order.
+fn populated_db(mgr: &RepoManager) -> RepoDB<DbConnection> {
+    let mut rng = SmallRng::seed_from_u64(2315873509131287432);
+    let mut db = mgr.db().unwrap();
+    let repo = db.all_repos(true).unwrap().remove(0);
+
+    db.transaction(|mut db| {
+        for module in 0..MODULES {
+            let mut module_id = None;
+            for _ in 0..RELEASES_PER_MODULE {
+                let mut version = format!(
+                    "{}.{}.{}",
+                    rng.random_range(0..3),
+                    rng.random_range(0..20),
+                    rng.random_range(0..20)
+                );
+                if rng.random_bool(0.2) {
+                    version += "-beta";
+                }
+
+                let release: JsonModule = from_value(json!({
+                    "spec_version": 1,
+                    "name": format!("Module {module}"),
+                    "identifier": format!("Module{module}"),
+                    "version": version,
+                    "abstract": "A mod",
+                    "author": "Linx",
+                }))
+                .unwrap();
+                let (id, _) = db.create_release(&release, repo.id, module_id)?;
+                module_id = Some(id);
+            }
+        }
+
+        Ok::<_, camrete_core::Error>(())
+    })
+    .unwrap();
+
+    db
+}
+
+fn bench(c: &mut Criterion) {
+    let mgr = RepoManager::new(":memory:").unwrap();
+    let mut db = populated_db(&mgr);
+
+    c.bench_function("list_summaries", |b| {
+        b.iter(|| {
+            db.module_summaries(black_box(None), black_box(MODULES as u32))
+                .unwrap()
+        })
+    });
+
+    c.bench_function("order_releases_by_collation", |b| {
+        b.iter(|| {
+            module_releases::table
+                .select(module_releases::release_id)
+                .order_by((module_releases::module_id, module_releases::version))
+                .load::<i32>(db.connection_mut())
+                .unwrap()
+        })
+    });
+
+    c.bench_function("order_releases_by_sort_key", |b| {
+        b.iter(|| {
+            module_releases::table
+                .select(module_releases::release_id)
+                .order_by(module_releases::module_id)
+                .then_order_by(ModuleRelease::by_version())
+                .load::<i32>(db.connection_mut())
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -53,6 +53,16 @@ fn bench(c: &mut Criterion) {
             BatchSize::SmallInput,
         );
     });
+
+    c.bench_function("sort_key_rand_versions", |b| {
+        let mut rng = SmallRng::seed_from_u64(8096041311318838857);
+
+        b.iter_batched_ref(
+            move || random_version(&mut rng),
+            |version| ModuleVersion::from(version.as_str()).sort_key(),
+            BatchSize::SmallInput,
+        );
+    });
 }
 
 criterion_group!(benches, bench);
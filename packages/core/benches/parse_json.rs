@@ -0,0 +1,52 @@
+use std::hint::black_box;
+
+use camrete_core::{
+    json::JsonModule,
+    repo::{RepoAssetVariant, TarGzAssetLoader, asset_stream::InMemoryAssetLoader},
+};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use tokio::runtime::Runtime;
+
+fn bench(c: &mut Criterion) {
+    let repo_data = include_bytes!("mini_repo.tgz");
+    let loader = TarGzAssetLoader::from_buf(repo_data);
+    let repo_assets = Runtime::new()
+        .unwrap()
+        .block_on(InMemoryAssetLoader::from_loader(loader))
+        .unwrap();
+
+    let releases = repo_assets
+        .assets
+        .iter()
+        .filter(|a| a.variant == RepoAssetVariant::Release)
+        .map(|a| a.data.to_vec())
+        .collect::<Vec<_>>();
+    let total_size = releases.iter().map(|r| r.len() as u64).sum();
+
+    let mut group = c.benchmark_group("parse_releases");
+    group.throughput(Throughput::Bytes(total_size));
+
+    group.bench_function("serde_json", |b| {
+        b.iter(|| {
+            for release in &releases {
+                let module: JsonModule = serde_json::from_slice(black_box(release)).unwrap();
+                black_box(module);
+            }
+        })
+    });
+
+    group.bench_function("simd_json", |b| {
+        b.iter(|| {
+            for release in &releases {
+                let mut scratch = black_box(release).clone();
+                let module: JsonModule = simd_json::serde::from_slice(&mut scratch).unwrap();
+                black_box(module);
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,129 @@
+//! Compares ways of putting releases in version order: the `MODULE_VERSION`
+//! collation, the precomputed sort key, and loading them unordered to sort in
+//! Rust. Each run has the same number of releases, split between fewer modules
+//! as each module gets more releases.
+
+use std::hint::black_box;
+
+use camrete_core::{
+    DbConnection,
+    database::{
+        RepoDB,
+        models::{ModuleRelease, module::ModuleVersion},
+        schema::module_releases,
+    },
+    json::JsonModule,
+    repo::RepoManager,
+};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use diesel::prelude::*;
+use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+use serde_json::{from_value, json};
+
+const TOTAL_RELEASES: usize = 10_000;
+const RELEASES_PER_MODULE: [usize; 3] = [10, 100, 1000];
+
+/// Fills a database with modules which each have `releases` releases, added
+/// in a random order so that insertion order doesn't match version order.
+fn populated_db(mgr: &RepoManager, releases: usize) -> RepoDB<DbConnection> {
+    let mut rng = SmallRng::seed_from_u64(6127794150214378829);
+    let mut db = mgr.db().unwrap();
+    let repo = db.all_repos(true).unwrap().remove(0);
+
+    db.transaction(|mut db| {
+        for module in 0..TOTAL_RELEASES / releases {
+            let mut versions = (0..releases)
+                .map(|i| {
+                    let version = format!("{}.{}.{}", i / 100, i / 10 % 10, i % 10);
+                    if i % 5 == 0 {
+                        version + "-beta"
+                    } else {
+                        version
+                    }
+                })
+                .collect::<Vec<_>>();
+            versions.shuffle(&mut rng);
+
+            let mut module_id = None;
+            for version in versions {
+                let release: JsonModule = from_value(json!({
+                    "spec_version": 1,
+                    "name": format!("Module {module}"),
+                    "identifier": format!("Module{module}"),
+                    "version": version,
+                    "abstract": "A mod",
+                    "author": "Linx",
+                }))
+                .unwrap();
+                let (id, _) = db.create_release(&release, repo.id, module_id)?;
+                module_id = Some(id);
+            }
+        }
+
+        Ok::<_, camrete_core::Error>(())
+    })
+    .unwrap();
+
+    db
+}
+
+/// Loads releases in whatever order SQLite finds them, leaving the sorting to
+/// Rust. Only used here, to see whether it beats sorting in the database.
+fn sort_in_rust(db: &mut RepoDB<DbConnection>) -> Vec<i32> {
+    let rows = module_releases::table
+        .select((
+            module_releases::module_id,
+            module_releases::release_id,
+            module_releases::version,
+        ))
+        .load::<(i32, i32, String)>(db.connection_mut())
+        .unwrap();
+
+    let mut rows = rows
+        .into_iter()
+        .map(|(module, release, version)| (module, ModuleVersion::from(version), release))
+        .collect::<Vec<_>>();
+    // Newest first, like the collation.
+    rows.sort_unstable_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(&a.1)));
+
+    rows.into_iter().map(|(_, _, release)| release).collect()
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sort_releases");
+
+    for releases in RELEASES_PER_MODULE {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = populated_db(&mgr, releases);
+
+        group.bench_function(BenchmarkId::new("collation", releases), |b| {
+            b.iter(|| {
+                module_releases::table
+                    .select(module_releases::release_id)
+                    .order_by((module_releases::module_id, module_releases::version))
+                    .load::<i32>(db.connection_mut())
+                    .unwrap()
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("sort_key", releases), |b| {
+            b.iter(|| {
+                module_releases::table
+                    .select(module_releases::release_id)
+                    .order_by(module_releases::module_id)
+                    .then_order_by(ModuleRelease::by_version())
+                    .load::<i32>(db.connection_mut())
+                    .unwrap()
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("rust", releases), |b| {
+            b.iter(|| black_box(sort_in_rust(&mut db)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
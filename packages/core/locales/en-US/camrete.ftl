@@ -0,0 +1,268 @@
+### Messages shown by Camrete's command-line app and GUI. Both apps use the same
+### message IDs, so that they describe things in the same words.
+
+## Errors. The `help` attribute suggests how to fix the problem.
+
+error-module-not-found = No such module: { $identifier }
+error-repo-not-found = No such repository: { $name }
+error-release-not-found = Module { $identifier } has no release "{ $version }"
+error-filter-not-found = No such filter: { $name }
+    .help = use `camrete filter list` to see the saved filters
+error-invalid-setting-value = Invalid setting value: "{ $value }"
+    .help = use `on` or `off`, or `default` to remove a module's override
+error-suggestion-not-found = No unused repository is suggested with the name "{ $name }"
+    .help = use `camrete repo suggestions` to see the suggested repositories
+error-global-setting = This setting applies to every module
+error-not-needed = { $identifier } isn't needed to install { $requested }
+error-internal = This is probably a bug in Camrete. Please report it at https://github.com/lewisfm/camrete/issues
+
+## Profiles
+
+profile-created = Created profile { $name } using { $path }
+profile-removed = Removed profile { $name }. Its database is still at { $path }
+
+## Usage metrics
+
+metrics-telemetry-off = Telemetry is off. Turn it on with `camrete config set telemetry on`.
+metrics-count =
+    { $name }: { $count ->
+        [one] { $count } time
+       *[other] { $count } times
+    }
+metrics-count-average =
+    { $name }: { $count ->
+        [one] { $count } time
+       *[other] { $count } times
+    }, { $average } ms on average
+
+## Backups
+
+backup-saved = Saved a backup to { $path }
+backup-restored = Restored the backup from { $path }
+
+## Database checks
+
+verify-consistent = The database is consistent
+verify-problem = { $count } { $check ->
+        [stale_sort_keys] releases have an out-of-date sort key
+        [search_index] modules are missing from the search index or shouldn't be in it
+        [stale_content_hash] repositories have an out-of-date content hash
+        [empty_modules] modules have no releases
+       *[other] rows in { $check } refer to rows which don't exist
+    }
+verify-repaired = Repaired the problems found
+verify-repair-hint = Run `camrete db verify --repair` to fix them
+
+## Repositories
+
+repo-added = Added repository { $name } ({ $url }). Run `camrete update` to download it and read its terms
+repo-url-changed = Repository { $name } now uses { $url }. Run `camrete update` to download it from there
+repo-channel-changed = Repository { $name } now follows { $channel } at { $url }. Run `camrete update` to download it
+repo-not-moved = Repository { $name } didn't permanently redirect at its last update
+repo-imported =
+    Added { $count ->
+        [one] { $count } repository
+       *[other] { $count } repositories
+    } from CKAN. Run `camrete update` to download them
+repo-import-name-taken = Skipped CKAN's repository { $name } ({ $url }), because another repository already has that name
+repo-info-modules = Modules: { $count } ({ $without_releases } without releases)
+repo-info-releases = Releases: { $count }
+repo-info-updated = Last updated: { $date }
+repo-info-never-updated = never
+repo-info-etag = ETag: { $etag }
+repo-info-no-etag = (none)
+repo-info-filter = Filter:
+repo-info-skip-tag = Skip releases tagged #{ $tag }
+repo-info-only-kinds = Only keep kinds: { $kinds }
+repo-info-max-spec-version = Skip releases newer than spec { $version }
+repo-info-max-release-status = Skip releases less stable than { $status }
+repo-info-refs = Referenced repositories:
+repo-info-terms = Terms:
+repo-list-mirror = mirror
+repo-list-mirror-comment = mirror: { $comment }
+repo-list-channel = channel { $channel }
+repo-list-moved = moved to { $url }
+repo-list-redirects = redirects to { $url }
+repo-list-content-hash = content hash { $hash }
+
+## Installed modules
+
+installed-imported =
+    Imported { $count ->
+        [one] { $count } installed mod
+       *[other] { $count } installed mods
+    } from CKAN{ $unknown ->
+        [0] {""}
+        [one] , including { $unknown } which isn't in any repository
+       *[other] , including { $unknown } which aren't in any repository
+    }
+installed-list-local = (local)
+installed-list-auto = (auto)
+installed-exported = Saved the installed mods to { $path }. Install it with CKAN to get the same mods
+installed-local =
+    Installed { $identifier } { $version } from { $path } ({ $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    })
+
+## Repository updates
+
+update-started = Updating { $name } ({ $url })
+update-checking = Checking { $name } ({ $url })
+update-waiting = Waiting for another update to finish...
+update-purging = Purging outdated modules...
+update-unpacked = { $count } items unpacked
+update-up-to-date = Already up to date
+update-complete = Update complete
+update-slowest-assets = Slowest assets to parse:
+update-no-changes = No changes
+update-summary = { $added } added, { $removed } removed, { $updated } updated
+update-new-terms = Repository { $name } has new terms of use:
+update-moved = Repository { $name } has moved to { $url }. Run `camrete repo follow-redirect { $name }` to use the new URL
+update-blocked =
+    The policy blocked { $count ->
+        [one] { $count } release
+       *[other] { $count } releases
+    } in repository { $name }
+update-invalid =
+    Skipped { $count ->
+        [one] { $count } release
+       *[other] { $count } releases
+    } with an invalid identifier in repository { $name }
+refresh-replaced = Refreshed { $identifier } { $version } in repository { $name }
+refresh-added = Added { $identifier } { $version } to repository { $name }
+
+## Installed DLC
+
+dlc-unknown-version = (unknown version)
+dlc-none = No DLC is installed in { $path }
+
+## Release history
+
+history-first-seen = first seen { $date }
+history-available = still available
+history-removed = removed { $date }
+
+## Yanked releases
+
+yank-done = Yanked release { $version } of { $identifier }. Undo this with `--undo`
+yank-undone = Release { $version } of { $identifier } is no longer yanked
+yank-not-yanked = Release { $version } of { $identifier } wasn't yanked
+
+## Favorites and notes
+
+fav-added = Added { $identifier } to your favorites
+fav-already = { $identifier } is already a favorite
+fav-removed = Removed { $identifier } from your favorites
+fav-not-favorite = { $identifier } wasn't a favorite
+note-saved = Saved your note about { $identifier }
+note-removed = Removed your note about { $identifier }
+note-none = You haven't written a note about { $identifier }
+
+## Why a module would be installed
+
+why-header = { $release } is needed because:
+why-requested = { $release } was requested
+why-relationship =
+    { $dependent } { $rel_type ->
+        [recommends] recommends
+       *[depends] depends on
+    } { $target }
+why-relationship-any-of =
+    { $dependent } { $rel_type ->
+        [recommends] recommends
+       *[depends] depends on
+    } any of { $members }, and { $target } is the first which can be installed
+why-only-provider = { $provider } is the only mod which provides { $target }
+why-first-provider = { $provider } comes first of the mods which provide { $target }
+
+## What a plan would change
+
+plan-installs = Mods to install:
+plan-upgrades = Mods to upgrade:
+plan-removals = Mods to remove:
+plan-dependency = (dependency)
+plan-files =
+    { $count ->
+        [one] (1 file)
+       *[other] ({ $count } files)
+    }
+plan-sizes = Download size: { $download }, size once installed: { $install }
+plan-nothing = Nothing would change
+plan-unsatisfied = Nothing can be installed for { $identifier }
+plan-unsatisfied-dependency = Nothing can be installed for { $dependent }'s dependency on { $targets }
+plan-downgrade = { $identifier } would be downgraded from { $from } to { $to }
+plan-not-installed = { $identifier } isn't installed, so it can't be removed
+plan-still-needed = { $identifier } would be removed, but { $dependents } still depend on it
+plan-unknown-size = { $identifier } doesn't say how big its download is, so it isn't counted in the download size
+
+## Dependency loops
+
+cycle-header =
+    { $count ->
+        [one] { $release } depends on itself:
+       *[other] { $count } mods depend on each other in a loop:
+    }
+cycle-step = { $release } depends on { $target }
+cycles-none = No mods depend on each other in a loop
+stale-none = No mods look unmaintained
+
+## A module's details
+
+release-yanked = yanked
+release-incompatible = incompatible
+module-authors = Authors: { $authors }
+module-note = Your note: { $note }
+module-license = License: { $licenses }
+module-bug-tracker = Bug tracker: { $link }
+module-repository = Repository: { $link }
+module-spacedock = Spacedock: { $link }
+module-release-date = Release date: { $date }
+module-other-versions = Other versions: { $versions }
+module-more-versions =
+    { $count ->
+        [one] and { $count } other
+       *[other] and { $count } others
+    }
+module-hidden-prereleases =
+    { $count ->
+        [one] { $count } prerelease hidden
+       *[other] { $count } prereleases hidden
+    } (use `camrete config set prerelease { $identifier } on` to show them)
+module-relationships = Relationships:
+module-any-of = Any of:
+module-release-notes = Release notes:
+module-release-notes-off = Release notes aren't fetched while the `release-notes` setting is off. Turn it on with `camrete config set release-notes on`.
+module-compatibility = Compatibility:
+module-findings = Problems with this release's metadata:
+module-files = Files:
+module-staleness = Maintenance: { $staleness }
+staleness-maintained = maintained, with a release in the last year
+staleness-dormant = no release for a year, but works with the newest version of the game
+staleness-outdated =
+    no release for a year, and { $behind ->
+        [one] { $behind } version
+       *[other] { $behind } versions
+    } of the game behind
+staleness-abandoned =
+    probably abandoned, { $behind ->
+        [one] { $behind } version
+       *[other] { $behind } versions
+    } of the game behind
+staleness-bugtracker-gone = (its bug tracker is gone)
+files-download = Download: { $url }
+files-mirror = Mirror: { $url }
+files-download-size = Download size: { $size }
+files-install-size = Install size: { $size }
+files-content-type = Content type: { $content_type }
+files-install = Install directives:
+files-install-as = (as { $name })
+files-install-default = (default, since the release doesn't list any)
+finding-missing-sha256 = The download has no SHA-256 checksum, so it can't be verified
+finding-deprecated-license = The license { $license } is deprecated
+finding-invalid-install-regex = Install directive { $directive } has an invalid pattern: { $pattern }
+finding-invalid-install-target = Install directive { $directive } installs outside the game's folders: { $target }
+finding-invalid-install-name = Install directive { $directive } renames files to an invalid name: { $name }
+list-none = (None)
+list-no-release-notes = (None found)
+list-no-game-versions = (No game versions known)
@@ -0,0 +1,458 @@
+//! Filling in values for releases which were saved before the columns holding
+//! them were added.
+//!
+//! A migration can add a column, but it can't always work out the column's
+//! value in SQL. Each [`Backfill`] instead re-reads the releases which are
+//! missing a value, with their metadata blobs parsed, and fills it in. This
+//! happens after the database is upgraded, so existing databases don't need to
+//! be deleted and downloaded again. A backfill is recorded once it's finished
+//! and never runs again; one which is interrupted carries on where it stopped.
+//!
+//! Some values come from fields of a release's document which older versions
+//! didn't keep. Their backfills can't work them out, so they instead forget
+//! which version of the release's repository was downloaded, and the next
+//! update saves each of its releases again in full.
+
+use std::{fmt, ops::DerefMut, sync::Arc};
+
+use diesel::{delete, dsl::not, insert_into, prelude::*, replace_into, update};
+use time::OffsetDateTime;
+use tracing::{debug, info, instrument};
+
+use crate::{
+    database::{
+        AuthorId, JsonbValue, ModAuthorId, Operation, QueryContext, QueryError, ReleaseId,
+        ReleaseValidation, RepoDB, RepoId, insert_author, models::ReleaseMetadata, schema::*,
+        url_key, validate_release,
+    },
+    json::AuthorCredit,
+};
+
+/// How many releases are filled in by each transaction.
+const BATCH_SIZE: usize = 500;
+
+/// Every backfill, in the order they run. New backfills are added to the end,
+/// and their names never change.
+const BACKFILLS: &[Backfill] = &[
+    Backfill {
+        name: "release_validations",
+        pending: missing_validations,
+        apply: fill_validation,
+    },
+    Backfill {
+        name: "author_roles",
+        pending: unsplit_authors,
+        apply: split_author_roles,
+    },
+    Backfill {
+        name: "display_names",
+        pending: unsplit_display_names,
+        apply: download_again,
+    },
+    Backfill {
+        name: "game_version_max",
+        pending: missing_game_version_max,
+        apply: download_again,
+    },
+    Backfill {
+        name: "spec_versions",
+        pending: missing_spec_versions,
+        apply: download_again,
+    },
+];
+
+/// A release as it was saved, which a backfill works out its value from.
+pub(crate) struct StoredRelease {
+    pub release_id: ReleaseId,
+    pub metadata: ReleaseMetadata<'static>,
+}
+
+/// A step which fills in a value for the releases saved before it existed.
+pub(crate) struct Backfill {
+    /// The name the backfill is recorded under once it's finished.
+    pub name: &'static str,
+    /// Finds the releases which are still missing the value.
+    pub pending: fn(&mut SqliteConnection) -> QueryResult<Vec<ReleaseId>>,
+    /// Fills in the value for one release.
+    pub apply: fn(&mut SqliteConnection, &StoredRelease) -> QueryResult<()>,
+}
+
+/// How far a backfill has got. Reported when it starts and after each batch
+/// of releases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfillProgress {
+    pub name: &'static str,
+    pub releases_done: u64,
+    pub releases_total: u64,
+}
+
+/// Receives the [`BackfillProgress`] of backfills run while a database is
+/// opened.
+#[derive(Clone)]
+pub struct BackfillReporter(pub Arc<dyn Fn(&BackfillProgress) + Send + Sync>);
+
+impl fmt::Debug for BackfillReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackfillReporter").finish_non_exhaustive()
+    }
+}
+
+/// Releases saved before their metadata was checked would otherwise have no
+/// findings until their repository is next updated.
+fn missing_validations(conn: &mut SqliteConnection) -> QueryResult<Vec<ReleaseId>> {
+    module_releases::table
+        .left_join(release_validations::table)
+        .filter(release_validations::release_id.is_null())
+        .select(module_releases::release_id)
+        .load(conn)
+}
+
+fn fill_validation(conn: &mut SqliteConnection, release: &StoredRelease) -> QueryResult<()> {
+    let licenses = module_licenses::table
+        .filter(module_licenses::release_id.eq(release.release_id))
+        .select(module_licenses::license)
+        .load::<String>(conn)?;
+
+    insert_into(release_validations::table)
+        .values(ReleaseValidation {
+            release_id: release.release_id,
+            findings: validate_release(&release.metadata, &licenses),
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Authors used to be saved as they were written, with any role in their
+/// name, like `Linx (maintainer)`.
+fn unsplit_authors(conn: &mut SqliteConnection) -> QueryResult<Vec<ReleaseId>> {
+    let credits = module_authors::table
+        .inner_join(authors::table)
+        .filter(module_authors::role.is_null())
+        .select((module_authors::release_id, authors::name))
+        .load::<(ReleaseId, String)>(conn)?;
+
+    let mut releases = credits
+        .into_iter()
+        .filter(|(_, name)| AuthorCredit::parse(name).name != *name)
+        .map(|(release_id, _)| release_id)
+        .collect::<Vec<_>>();
+    releases.sort_unstable();
+    releases.dedup();
+    Ok(releases)
+}
+
+fn split_author_roles(conn: &mut SqliteConnection, release: &StoredRelease) -> QueryResult<()> {
+    let credits = module_authors::table
+        .inner_join(authors::table)
+        .filter(module_authors::release_id.eq(release.release_id))
+        .select((module_authors::id, module_authors::author_id, authors::name))
+        .load::<(ModAuthorId, AuthorId, String)>(conn)?;
+
+    for (id, old_author, name) in credits {
+        let credit = AuthorCredit::parse(&name);
+        if credit.name == name {
+            continue;
+        }
+
+        let author = insert_author(conn, &credit.name)?;
+        update(module_authors::table.find(id))
+            .set((
+                module_authors::author_id.eq(author),
+                module_authors::role.eq(credit.role),
+            ))
+            .execute(conn)?;
+
+        // The name with the role in it is removed once nothing credits it.
+        let credited = module_authors::table.select(module_authors::author_id);
+        delete(authors::table.find(old_author))
+            .filter(not(authors::author_id.eq_any(credited)))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Releases saved before their name was kept apart from their identifier
+/// were named after it.
+fn unsplit_display_names(conn: &mut SqliteConnection) -> QueryResult<Vec<ReleaseId>> {
+    module_releases::table
+        .inner_join(modules::table)
+        .filter(module_releases::display_name.eq(modules::module_slug))
+        .select(module_releases::release_id)
+        .load(conn)
+}
+
+/// Releases saved before `game_version_max` was added have no maximum, which
+/// only matters for those without an exact game version.
+fn missing_game_version_max(conn: &mut SqliteConnection) -> QueryResult<Vec<ReleaseId>> {
+    module_releases::table
+        .filter(module_releases::game_version_max.is_null())
+        .filter(module_releases::game_version.eq(module_releases::game_version_min))
+        .select(module_releases::release_id)
+        .load(conn)
+}
+
+fn missing_spec_versions(conn: &mut SqliteConnection) -> QueryResult<Vec<ReleaseId>> {
+    module_releases::table
+        .filter(module_releases::spec_version.is_null())
+        .select(module_releases::release_id)
+        .load(conn)
+}
+
+/// Forgets the ETag and Git commit the release's repository was last updated
+/// from, so that its next update saves every release again instead of finding
+/// that nothing changed.
+fn download_again(conn: &mut SqliteConnection, release: &StoredRelease) -> QueryResult<()> {
+    let repo = module_releases::table
+        .inner_join(modules::table)
+        .filter(module_releases::release_id.eq(release.release_id))
+        .select(modules::repo_id)
+        .get_result::<RepoId>(conn)?;
+
+    let repo_url = repositories::table
+        .find(repo)
+        .select(url_key::url_key(repositories::url));
+    delete(etags::table.filter(etags::url.eq_any(repo_url))).execute(conn)?;
+    delete(repo_commits::table.find(repo)).execute(conn)?;
+    Ok(())
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Runs every backfill which hasn't finished yet, calling `progress` as
+    /// each one goes. Returns how many releases were filled in.
+    ///
+    /// This is done whenever a [`RepoManager`](crate::repo::RepoManager) is
+    /// built, so it only needs calling for databases opened another way.
+    #[instrument(skip_all)]
+    pub fn run_backfills(
+        &mut self,
+        mut progress: impl FnMut(&BackfillProgress),
+    ) -> Result<u64, QueryError> {
+        let finished = backfills::table
+            .select(backfills::name)
+            .load::<String>(&mut *self.connection)
+            .during(|| Operation::LoadBackfills)?;
+
+        let mut filled = 0;
+        for backfill in BACKFILLS {
+            if finished.iter().any(|name| name == backfill.name) {
+                continue;
+            }
+
+            let operation = || Operation::RunBackfill {
+                name: backfill.name,
+            };
+            filled += self
+                .run_backfill(backfill, &mut progress)
+                .during(operation)?;
+        }
+
+        Ok(filled)
+    }
+
+    fn run_backfill(
+        &mut self,
+        backfill: &Backfill,
+        progress: &mut impl FnMut(&BackfillProgress),
+    ) -> QueryResult<u64> {
+        let pending = (backfill.pending)(&mut self.connection)?;
+        let mut report = BackfillProgress {
+            name: backfill.name,
+            releases_done: 0,
+            releases_total: pending.len() as u64,
+        };
+
+        if !pending.is_empty() {
+            info!(
+                name = backfill.name,
+                releases = pending.len(),
+                "Running a backfill"
+            );
+            progress(&report);
+        }
+
+        for batch in pending.chunks(BATCH_SIZE) {
+            self.connection.transaction(|conn| {
+                let releases = module_releases::table
+                    .filter(module_releases::release_id.eq_any(batch))
+                    .select((module_releases::release_id, module_releases::metadata))
+                    .load::<(ReleaseId, JsonbValue)>(conn)?;
+
+                for (release_id, metadata) in releases {
+                    let metadata = ReleaseMetadata::try_from(metadata)
+                        .map_err(|e| diesel::result::Error::DeserializationError(e.into()))?;
+                    let release = StoredRelease {
+                        release_id,
+                        metadata,
+                    };
+                    (backfill.apply)(conn, &release)?;
+                }
+
+                QueryResult::Ok(())
+            })?;
+
+            report.releases_done += batch.len() as u64;
+            progress(&report);
+        }
+
+        replace_into(backfills::table)
+            .values((
+                backfills::name.eq(backfill.name),
+                backfills::completed_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(&mut *self.connection)?;
+
+        debug!(
+            name = backfill.name,
+            filled = report.releases_total,
+            "Finished a backfill"
+        );
+        Ok(report.releases_total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::models::ModuleRelease,
+        repo::test_support::{TestRelease, db_with_default_repo, release},
+    };
+
+    #[test]
+    fn fills_in_releases_saved_by_older_versions() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let release = release("Parallax", "2.0.1")
+            .set("name", "Parallax Continued")
+            .set("ksp_version_max", "1.12")
+            .build();
+        let (_, release_id) = db.create_release(&release, repo.id, None).unwrap();
+        insert_into(etags::table)
+            .values((
+                etags::url.eq(JsonbValue::url_key(&repo.url)),
+                etags::etag.eq("abc"),
+            ))
+            .execute(db.connection_mut())
+            .unwrap();
+        db.set_repo_commit(
+            repo.id,
+            "https://github.com/KSP-CKAN/CKAN-meta",
+            "master",
+            "abc",
+        )
+        .unwrap();
+
+        // Make the release look like it was saved before spec versions were
+        // kept.
+        update(module_releases::table.find(release_id))
+            .set(module_releases::spec_version.eq(None::<String>))
+            .execute(db.connection_mut())
+            .unwrap();
+        delete(backfills::table).execute(db.connection_mut()).unwrap();
+
+        let mut reports = vec![];
+        assert_eq!(db.run_backfills(|p| reports.push(p.clone())).unwrap(), 2);
+        let progress = |name, releases_done| BackfillProgress {
+            name,
+            releases_done,
+            releases_total: 1,
+        };
+        assert_eq!(
+            reports,
+            [
+                progress("release_validations", 0),
+                progress("release_validations", 1),
+                progress("spec_versions", 0),
+                progress("spec_versions", 1),
+            ]
+        );
+
+        // The spec version isn't in the release's metadata, so the repository
+        // is downloaded again in full.
+        assert_eq!(db.release_validations(&[release_id]).unwrap().len(), 1);
+        assert_eq!(db.etag(&repo.url).unwrap(), None);
+        assert_eq!(db.repo_commit(repo.id).unwrap(), None);
+
+        // A finished backfill never runs again.
+        update(module_releases::table.find(release_id))
+            .set(module_releases::spec_version.eq(None::<String>))
+            .execute(db.connection_mut())
+            .unwrap();
+        assert_eq!(db.run_backfills(|_| panic!("no backfill runs")).unwrap(), 0);
+    }
+
+    #[test]
+    fn finds_releases_missing_document_fields() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let mut create = |release: TestRelease| {
+            let (_, release_id) = db.create_release(&release.build(), repo.id, None).unwrap();
+            release_id
+        };
+        let parallax = create(release("Parallax", "2.0.1"));
+        let scatterer = create(release("Scatterer", "0.9").set("ksp_version", "1.12"));
+        create(
+            release("Kopernicus", "1.0")
+                .set("name", "Kopernicus Planetary System Modifier")
+                .set("ksp_version_max", "1.12"),
+        );
+
+        let mut unsplit = unsplit_display_names(db.connection_mut()).unwrap();
+        unsplit.sort_unstable();
+        assert_eq!(unsplit, [parallax, scatterer]);
+        assert_eq!(missing_game_version_max(db.connection_mut()).unwrap(), [parallax]);
+        assert_eq!(missing_spec_versions(db.connection_mut()).unwrap(), []);
+    }
+
+    #[test]
+    fn splits_roles_from_saved_authors() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let release = release("Parallax", "2.0.1")
+            .set("author", json!(["Linx (maintainer)", "Gameslinx"]))
+            .build();
+        let (_, release_id) = db.create_release(&release, repo.id, None).unwrap();
+
+        // Credit the author the way older versions did, with the role in
+        // their name.
+        let unsplit = db.register_author("Linx (maintainer)").unwrap();
+        update(module_authors::table)
+            .filter(module_authors::ordinal.eq(0))
+            .set((
+                module_authors::author_id.eq(unsplit),
+                module_authors::role.eq(None::<String>),
+            ))
+            .execute(db.connection_mut())
+            .unwrap();
+        delete(backfills::table.filter(backfills::name.eq("author_roles")))
+            .execute(db.connection_mut())
+            .unwrap();
+
+        assert_eq!(db.run_backfills(|_| {}).unwrap(), 1);
+        let credits = ModuleRelease::credits_for(release_id)
+            .load::<AuthorCredit>(db.connection_mut())
+            .unwrap();
+        assert_eq!(
+            credits,
+            [
+                AuthorCredit {
+                    name: "Linx".into(),
+                    role: Some("maintainer".into()),
+                },
+                AuthorCredit {
+                    name: "Gameslinx".into(),
+                    role: None,
+                },
+            ]
+        );
+
+        let names = authors::table
+            .select(authors::name)
+            .order(authors::name)
+            .load::<String>(db.connection_mut())
+            .unwrap();
+        assert_eq!(names, ["Gameslinx", "Linx"]);
+    }
+}
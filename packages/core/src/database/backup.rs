@@ -0,0 +1,301 @@
+//! Online backups of the repository database.
+
+use std::{
+    env, fs, io,
+    ops::DerefMut,
+    path::{self, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use diesel::prelude::*;
+use diesel_migrations::MigrationHarness;
+use libsqlite3_sys as ffi;
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::{
+    Error,
+    database::{
+        RepoDB,
+        raw::{code_error, last_error, raw_handle, register_handle_function},
+    },
+    repo::client::{ConnectionOptions, MIGRATIONS},
+};
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Writes a snapshot of the entire database to a new file at `path`.
+    ///
+    /// The snapshot is copied with SQLite's online backup API in a single
+    /// step, so it's safe to make a backup while other connections are
+    /// writing to the database. The backup of an encrypted database is
+    /// encrypted with the same key.
+    #[instrument(skip(self))]
+    pub fn backup_to(&mut self, path: &Path) -> Result<(), Error> {
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            )
+            .into());
+        }
+
+        info!("Backing up the database");
+
+        let mut backup = self.open_copy(&path.to_string_lossy())?;
+        copy_database(&mut self.connection, &mut backup)
+    }
+
+    /// Replaces the contents of the database with a backup previously made by
+    /// [`Self::backup_to`].
+    ///
+    /// Backups made by older versions of Camrete are upgraded to the current
+    /// schema before they are restored. The backup file itself is never
+    /// modified.
+    #[instrument(skip(self))]
+    pub fn restore_from(&mut self, path: &Path) -> Result<(), Error> {
+        info!("Restoring the database from a backup");
+
+        let staging = staging_path();
+        let result = self.restore_staged(path, &staging);
+        let _ = fs::remove_file(&staging);
+
+        result
+    }
+
+    fn restore_staged(&mut self, path: &Path, staging: &Path) -> Result<(), Error> {
+        // Open the backup read-only so that a mistyped path doesn't create an empty
+        // database, then copy it somewhere it can be upgraded.
+        let mut url = Url::from_file_path(path::absolute(path)?).expect("path is absolute");
+        url.set_query(Some("mode=ro"));
+
+        let mut backup = self.open_copy(url.as_str())?;
+        let mut staged = self.open_copy(&staging.to_string_lossy())?;
+        copy_database(&mut backup, &mut staged)?;
+        drop(backup);
+
+        staged
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(Error::DbMigrations)?;
+        RepoDB::new(&mut staged).run_backfills(|_| {})?;
+
+        // The backup API replaces every page of the database, so full-text
+        // indexes and the migrations table come back exactly as they were
+        // staged.
+        copy_database(&mut staged, &mut self.connection)
+    }
+
+    /// Opens another database with this one's settings. Backups are encrypted
+    /// with the same key as the database, if any, since SQLite can only copy
+    /// between databases with matching keys.
+    fn open_copy(&self, url: &str) -> Result<SqliteConnection, Error> {
+        let options = ConnectionOptions {
+            busy_timeout: self.retry_policy.busy_timeout,
+            #[cfg(feature = "sqlcipher")]
+            key: self.key.clone(),
+        };
+
+        // The copy is made through the connections' SQLite handles.
+        register_handle_function();
+        let mut conn = SqliteConnection::establish(url)?;
+        options.apply(&mut conn)?;
+        Ok(conn)
+    }
+}
+
+/// Copies every page of the database `from` is connected to over the database
+/// `to` is connected to, with SQLite's online backup API.
+///
+/// The pages are copied in one step, which reads `from` in a single
+/// transaction, so the copy is never taken partway through a write.
+fn copy_database(from: &mut SqliteConnection, to: &mut SqliteConnection) -> Result<(), Error> {
+    let source = raw_handle(from)?;
+    let destination = raw_handle(to)?;
+    let main = c"main".as_ptr();
+
+    // SAFETY: Both connections are borrowed, so they stay open until the
+    // backup is finished.
+    unsafe {
+        let backup = ffi::sqlite3_backup_init(destination.as_ptr(), main, source.as_ptr(), main);
+        if backup.is_null() {
+            return Err(last_error(destination).into());
+        }
+
+        let step = ffi::sqlite3_backup_step(backup, -1);
+        let finish = ffi::sqlite3_backup_finish(backup);
+        if step != ffi::SQLITE_DONE {
+            return Err(code_error(step).into());
+        }
+        if finish != ffi::SQLITE_OK {
+            return Err(last_error(destination).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// A unique temporary location for upgrading a backup before it's restored.
+fn staging_path() -> PathBuf {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("camrete-restore-{}-{n}.sqlite", process::id()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{database::models::RepositoryRef, repo::RepoManager};
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("camrete-{name}-{}.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let path = temp_file("backup-and-restore");
+        let url = Url::parse("https://example.com/repo.tar.gz").unwrap();
+
+        let original = RepoManager::new(":memory:").unwrap();
+        let mut db = original.db().unwrap();
+        db.create_empty_repo(RepositoryRef::shared("example", &url))
+            .unwrap();
+        db.backup_to(&path).unwrap();
+
+        let restored = RepoManager::new(":memory:").unwrap();
+        let mut db = restored.db().unwrap();
+        // This should be replaced by the backup's contents.
+        db.all_repos(true).unwrap();
+        db.restore_from(&path).unwrap();
+
+        let repos = db.all_repos(false).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].name, "example");
+        assert_eq!(repos[0].url, url);
+    }
+
+    #[test]
+    fn backup_does_not_overwrite() {
+        let path = temp_file("backup-does-not-overwrite");
+
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+        db.backup_to(&path).unwrap();
+        let second = db.backup_to(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(second, Err(Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists));
+    }
+
+    #[test]
+    fn migrates_populated_databases() {
+        use diesel::{
+            connection::SimpleConnection,
+            migration::{MigrationConnection, MigrationSource},
+            sql_query,
+            sql_types::{Binary, TimestamptzSqlite},
+            sqlite::Sqlite,
+        };
+        use time::OffsetDateTime;
+
+        use crate::database::JsonbValue;
+
+        let url = Url::parse("https://example.com/repo.tar.gz").unwrap();
+        let options = ConnectionOptions {
+            busy_timeout: Default::default(),
+            #[cfg(feature = "sqlcipher")]
+            key: None,
+        };
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        options.apply(&mut conn).unwrap();
+        conn.setup().unwrap();
+
+        // Fill in the tables as the first version of the schema had them, so
+        // that every later migration runs over existing rows.
+        for migration in MigrationSource::<Sqlite>::migrations(&MIGRATIONS).unwrap() {
+            conn.run_migration(&*migration).unwrap();
+            let name = migration.name().to_string();
+
+            if name.ends_with("_create_repos") {
+                sql_query("INSERT INTO repositories VALUES (1, 'example', ?, 0)")
+                    .bind::<Binary, _>(JsonbValue::from(&url))
+                    .execute(&mut conn)
+                    .unwrap();
+                conn.batch_execute(
+                    "INSERT INTO modules VALUES (1, 1, 'Parallax', 10);
+                    INSERT INTO module_releases (
+                        release_id, module_id, version, display_name, summary, metadata,
+                        game_version, game_version_min
+                    ) VALUES (1, 1, '1.0', 'Parallax', 'Rocks', X'0C', X'0C', X'0C');
+                    INSERT INTO module_authors VALUES (1, 1, 0, 'linx');",
+                )
+                .unwrap();
+                sql_query("INSERT INTO etags VALUES (?, 'abc')")
+                    .bind::<Binary, _>(JsonbValue::from(&url))
+                    .execute(&mut conn)
+                    .unwrap();
+            } else if name.ends_with("_etag_updated_at") {
+                sql_query("UPDATE etags SET updated_at = ?")
+                    .bind::<TimestamptzSqlite, _>(OffsetDateTime::UNIX_EPOCH)
+                    .execute(&mut conn)
+                    .unwrap();
+            }
+        }
+        assert!(!conn.has_pending_migration(MIGRATIONS).unwrap());
+
+        let history = RepoDB::new(&mut conn).module_history("Parallax").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].version, "1.0");
+        assert_eq!(history[0].first_seen, OffsetDateTime::UNIX_EPOCH);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn encrypted_backups_keep_the_key() {
+        use std::sync::Arc;
+
+        use crate::repo::StaticKey;
+
+        let path = temp_file("encrypted-database");
+        let backup = temp_file("encrypted-backup");
+        let url = Url::parse("https://example.com/repo.tar.gz").unwrap();
+
+        let key = Arc::new(StaticKey("it's a secret".into()));
+        let mgr = RepoManager::builder(&path.to_string_lossy())
+            .encryption_key(key)
+            .build()
+            .unwrap();
+        let mut db = mgr.db().unwrap();
+        db.create_empty_repo(RepositoryRef::shared("example", &url))
+            .unwrap();
+        db.backup_to(&backup).unwrap();
+
+        // The backup can't be read without the key.
+        assert!(RepoManager::new(&backup.to_string_lossy()).is_err());
+
+        db.restore_from(&backup).unwrap();
+        let repos = db.all_repos(false).unwrap();
+        drop(db);
+        drop(mgr);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].name, "example");
+    }
+
+    #[test]
+    fn restore_missing_file() {
+        let path = temp_file("restore-missing-file");
+
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let result = mgr.db().unwrap().restore_from(&path);
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,168 @@
+//! Applying several changes to the user's setup at once.
+//!
+//! Settings screens let the user change several things before saving, and
+//! saving should either apply all of them or none, rather than leaving the
+//! database half changed if one fails.
+
+use std::ops::DerefMut;
+
+use diesel::SqliteConnection;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{
+    Error,
+    database::{InstalledModule, RepoDB, RepoId, models::RepositoryRef},
+};
+
+/// One change made by [`RepoDB::apply_changes`].
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum StateChange {
+    /// Adds a repository. Adding one which already exists with the same URL
+    /// changes nothing, while adding one whose name is taken by a different
+    /// URL fails.
+    AddRepo { name: String, url: Url },
+    /// Changes a repository's priority.
+    SetRepoPriority { repo: RepoId, priority: i32 },
+    /// Pins a module to a version, or unpins it if `version` is `None`.
+    PinModule {
+        slug: String,
+        version: Option<String>,
+    },
+    /// Records a module as installed, replacing any earlier record of it.
+    MarkInstalled { module: InstalledModule },
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Makes every change in order, in one transaction. If any fails, none of
+    /// them are kept.
+    #[instrument(skip_all, fields(changes = changes.len()))]
+    pub fn apply_changes(&mut self, changes: Vec<StateChange>) -> Result<(), Error> {
+        self.transaction(|mut db| {
+            for change in changes {
+                debug!(?change, "Applying a change");
+                match change {
+                    StateChange::AddRepo { name, url } => {
+                        db.create_empty_repo(RepositoryRef::new(name, url))?;
+                    }
+                    StateChange::SetRepoPriority { repo, priority } => {
+                        db.set_repo_priority(repo, priority)?;
+                    }
+                    StateChange::PinModule { slug, version } => {
+                        db.set_module_pin(&slug, version.as_deref())?;
+                    }
+                    StateChange::MarkInstalled { module } => {
+                        db.record_installed(vec![module])?;
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::test_support::db_with_default_repo;
+
+    fn installed(slug: &str) -> InstalledModule {
+        InstalledModule {
+            slug: slug.into(),
+            version: "1.0".into(),
+            auto_installed: false,
+            installed_at: None,
+            files: vec![],
+            local_archive: None,
+        }
+    }
+
+    #[test]
+    fn applies_all_changes_or_none() {
+        let (mut db, repo) = db_with_default_repo();
+        let url = Url::parse("https://example.com/meta.tar.gz").unwrap();
+
+        db.apply_changes(vec![
+            StateChange::AddRepo {
+                name: "Example".into(),
+                url,
+            },
+            StateChange::SetRepoPriority {
+                repo: repo.id,
+                priority: 5,
+            },
+            StateChange::PinModule {
+                slug: "Parallax".into(),
+                version: Some("1.0".into()),
+            },
+            StateChange::MarkInstalled {
+                module: installed("Parallax"),
+            },
+        ])
+        .unwrap();
+
+        let repos = db.all_repos(false).unwrap();
+        assert_eq!(repos.len(), 2);
+        assert!(repos.iter().any(|r| r.id == repo.id && r.priority == 5));
+        assert_eq!(db.module_pin("Parallax").unwrap().as_deref(), Some("1.0"));
+        assert_eq!(db.installed_modules().unwrap(), [installed("Parallax")]);
+
+        // Changing a repository which doesn't exist fails, which undoes the
+        // changes before it.
+        let missing = RepoId::from(repo.id.get() + 100);
+        let result = db.apply_changes(vec![
+            StateChange::MarkInstalled {
+                module: installed("Scatterer"),
+            },
+            StateChange::SetRepoPriority {
+                repo: missing,
+                priority: 1,
+            },
+        ]);
+        assert!(result.is_err());
+        assert_eq!(db.installed_modules().unwrap(), [installed("Parallax")]);
+    }
+
+    #[test]
+    fn re_adding_a_repo_keeps_it() {
+        let (mut db, repo) = db_with_default_repo();
+
+        // The priority change still applies to the repository, since adding it
+        // again doesn't replace it.
+        db.apply_changes(vec![
+            StateChange::AddRepo {
+                name: repo.name.clone(),
+                url: repo.url.clone(),
+            },
+            StateChange::SetRepoPriority {
+                repo: repo.id,
+                priority: 7,
+            },
+        ])
+        .unwrap();
+
+        let repos = db.all_repos(true).unwrap();
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].id, repo.id);
+        assert_eq!(repos[0].priority, 7);
+
+        // A name which is taken by another URL is rejected.
+        let result = db.apply_changes(vec![
+            StateChange::SetRepoPriority {
+                repo: repo.id,
+                priority: 3,
+            },
+            StateChange::AddRepo {
+                name: repo.name.clone(),
+                url: Url::parse("https://example.com/other.tar.gz").unwrap(),
+            },
+        ]);
+        assert!(result.is_err());
+
+        let repos = db.all_repos(true).unwrap();
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].url, repo.url);
+        assert_eq!(repos[0].priority, 7);
+    }
+}
@@ -0,0 +1,277 @@
+//! Switching a repository between the branches or tags of its source.
+//!
+//! CKAN-meta publishes its metadata on several branches, such as `master`,
+//! `stable` and `testing`. A repository's URL template has `{channel}` in
+//! place of the branch, so that choosing another one doesn't mean retyping the
+//! URL. Templates are worked out from GitHub and GitLab archive URLs.
+
+use std::ops::DerefMut;
+
+use diesel::{prelude::*, update};
+use miette::Diagnostic;
+use tracing::instrument;
+use url::{Position, Url};
+
+use crate::{
+    Error,
+    database::{Operation, QueryContext, RepoDB, RepoId, models::Repository, schema::*},
+};
+
+/// The part of a URL template which is replaced by the channel.
+pub const CHANNEL_PLACEHOLDER: &str = "{channel}";
+
+/// The extensions of the archives GitHub and GitLab make of a branch or tag.
+const ARCHIVE_EXTENSIONS: [&str; 3] = [".tar.gz", ".tgz", ".zip"];
+
+#[derive(Debug, thiserror::Error, Diagnostic, PartialEq, Eq)]
+pub enum ChannelError {
+    #[error("can't tell which part of {url} names the channel")]
+    #[diagnostic(
+        code(camrete::channel::unknown_url),
+        severity(Warning),
+        help("pass a URL template with `{{channel}}` in place of the branch or tag")
+    )]
+    NoTemplate { url: Url },
+    #[error("the URL template {template:?} has no `{{channel}}` in it")]
+    #[diagnostic(code(camrete::channel::missing_placeholder), severity(Warning))]
+    MissingPlaceholder { template: String },
+    #[error("{channel:?} isn't the name of a branch or tag")]
+    #[diagnostic(
+        code(camrete::channel::invalid),
+        severity(Warning),
+        help("CKAN-meta's channels are `master`, `stable` and `testing`")
+    )]
+    InvalidChannel { channel: String },
+    #[error("the URL template {template:?} doesn't make a valid URL")]
+    #[diagnostic(code(camrete::channel::invalid_url), severity(Warning))]
+    InvalidUrl {
+        template: String,
+        source: url::ParseError,
+    },
+}
+
+/// Finds the branch or tag in a GitHub or GitLab archive URL, returning a
+/// template for the URL and the channel it names.
+pub fn detect_channel(url: &Url) -> Option<(String, String)> {
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    let archive = segments.iter().position(|s| *s == "archive")?;
+    let (before, rest) = (&segments[..archive], &segments[archive + 1..]);
+
+    // The channel, how much of the end of the path names it, and what that
+    // part of the path is in the template.
+    let (channel, replaced, suffix) = if let [.., project, "-"] = before {
+        // GitLab: `group/project/-/archive/master/project-master.tar.gz`
+        let [channel, file] = rest else {
+            return None;
+        };
+        let name = file.strip_prefix(project)?.strip_prefix('-')?;
+        let extension = extension_of(name)?;
+        if name.strip_suffix(extension)? != *channel {
+            return None;
+        }
+
+        let suffix = format!("{CHANNEL_PLACEHOLDER}/{project}-{CHANNEL_PLACEHOLDER}{extension}");
+        (channel.to_string(), channel.len() + 1 + file.len(), suffix)
+    } else {
+        // GitHub: `owner/repo/archive/[refs/heads/]master.tar.gz`
+        let rest = match rest {
+            ["refs", "heads" | "tags", rest @ ..] => rest,
+            rest => rest,
+        };
+        let path = rest.join("/");
+        let extension = extension_of(&path)?;
+        let channel = path.strip_suffix(extension)?.to_owned();
+
+        (
+            channel,
+            path.len(),
+            format!("{CHANNEL_PLACEHOLDER}{extension}"),
+        )
+    };
+    if !is_channel(&channel) {
+        return None;
+    }
+
+    let path = url.path();
+    let template = format!(
+        "{}{}{suffix}{}",
+        &url[..Position::BeforePath],
+        &path[..path.len() - replaced],
+        &url[Position::AfterPath..]
+    );
+    Some((template, channel))
+}
+
+/// The URL a template gives for a channel.
+pub fn expand_template(template: &str, channel: &str) -> Result<Url, ChannelError> {
+    if !template.contains(CHANNEL_PLACEHOLDER) {
+        return Err(ChannelError::MissingPlaceholder {
+            template: template.to_owned(),
+        });
+    }
+    if !is_channel(channel) {
+        return Err(ChannelError::InvalidChannel {
+            channel: channel.to_owned(),
+        });
+    }
+
+    Url::parse(&template.replace(CHANNEL_PLACEHOLDER, channel)).map_err(|source| {
+        ChannelError::InvalidUrl {
+            template: template.to_owned(),
+            source,
+        }
+    })
+}
+
+fn extension_of(file: &str) -> Option<&'static str> {
+    ARCHIVE_EXTENSIONS
+        .into_iter()
+        .find(|extension| file.len() > extension.len() && file.ends_with(extension))
+}
+
+/// Returns whether a channel could be the name of a branch or tag. Names are
+/// limited to characters which don't need escaping in a URL.
+fn is_channel(channel: &str) -> bool {
+    !channel.is_empty()
+        && !channel.starts_with(['.', '/'])
+        && !channel.ends_with('/')
+        && !channel.contains("..")
+        && channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+}
+
+impl Repository {
+    /// The channel the repository is downloaded from, either as it was chosen
+    /// or as it appears in its URL.
+    pub fn current_channel(&self) -> Option<String> {
+        self.channel
+            .clone()
+            .or_else(|| detect_channel(&self.url).map(|(_, channel)| channel))
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Switches a repository to another channel, by filling in its URL
+    /// template. The template is the given one, or else the one used last
+    /// time, or else one worked out from the repository's URL.
+    ///
+    /// The URL is changed like with [`update_repo_url`](Self::update_repo_url),
+    /// so the repository's releases are replaced at its next update.
+    #[instrument(skip(self))]
+    pub fn set_repo_channel(
+        &mut self,
+        repo: RepoId,
+        channel: &str,
+        template: Option<&str>,
+    ) -> Result<Repository, Error> {
+        let operation = || Operation::SetRepoChannel { repo };
+
+        self.transaction(|mut db| {
+            let current = repositories::table
+                .find(repo)
+                .select(Repository::as_select())
+                .get_result(&mut *db.connection)
+                .during(operation)?;
+
+            let template = match template.or(current.url_template.as_deref()) {
+                Some(template) => template.to_owned(),
+                None => match detect_channel(&current.url) {
+                    Some((template, _)) => template,
+                    None => return Err(ChannelError::NoTemplate { url: current.url }.into()),
+                },
+            };
+            let url = expand_template(&template, channel)?;
+
+            db.update_repo_url(repo, &url)?;
+            let updated = update(repositories::table.find(repo))
+                .set((
+                    repositories::channel.eq(channel),
+                    repositories::url_template.eq(&template),
+                ))
+                .returning(Repository::as_returning())
+                .get_result(&mut *db.connection)
+                .during(operation)?;
+
+            Ok(updated)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::test_support::db_with_default_repo;
+
+    fn detect(url: &str) -> Option<(String, String)> {
+        detect_channel(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn detects_channels() {
+        assert_eq!(
+            detect("https://github.com/KSP-CKAN/CKAN-meta/archive/master.tar.gz"),
+            Some((
+                "https://github.com/KSP-CKAN/CKAN-meta/archive/{channel}.tar.gz".into(),
+                "master".into()
+            ))
+        );
+        assert_eq!(
+            detect("https://github.com/KSP-CKAN/CKAN-meta/archive/refs/heads/release/1.2.zip"),
+            Some((
+                "https://github.com/KSP-CKAN/CKAN-meta/archive/refs/heads/{channel}.zip".into(),
+                "release/1.2".into()
+            ))
+        );
+        assert_eq!(
+            detect("https://gitlab.com/ksp/meta/-/archive/testing/meta-testing.tar.gz?x=1"),
+            Some((
+                "https://gitlab.com/ksp/meta/-/archive/{channel}/meta-{channel}.tar.gz?x=1".into(),
+                "testing".into()
+            ))
+        );
+        assert_eq!(detect("https://example.com/meta.tar.gz"), None);
+        assert_eq!(
+            detect("https://gitlab.com/ksp/meta/-/archive/testing/meta-stable.tar.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn switches_channels() {
+        let (mut db, repo) = db_with_default_repo();
+        let old_channel = repo.current_channel();
+
+        let stable = db.set_repo_channel(repo.id, "stable", None).unwrap();
+        assert_eq!(stable.channel.as_deref(), Some("stable"));
+        assert_ne!(stable.current_channel(), old_channel);
+        assert!(stable.url.path().ends_with("/stable.tar.gz"));
+
+        let template = "https://example.com/{channel}/meta.tar.gz";
+        let testing = db.set_repo_channel(repo.id, "testing", Some(template));
+        let testing = testing.unwrap();
+        assert_eq!(
+            testing.url.as_str(),
+            "https://example.com/testing/meta.tar.gz"
+        );
+
+        // The template is remembered, even though the URL doesn't match any
+        // that are recognised.
+        let master = db.set_repo_channel(repo.id, "master", None).unwrap();
+        assert_eq!(
+            master.url.as_str(),
+            "https://example.com/master/meta.tar.gz"
+        );
+
+        let error = db.set_repo_channel(repo.id, "../x", None).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Channel(ChannelError::InvalidChannel { .. })
+        ));
+
+        // Setting the URL directly forgets the channel.
+        let moved = db.update_repo_url(repo.id, &master.url).unwrap();
+        assert_eq!(moved.channel, None);
+        assert_eq!(moved.url_template, None);
+    }
+}
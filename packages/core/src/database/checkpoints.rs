@@ -0,0 +1,174 @@
+//! Progress of resumable repository updates.
+//!
+//! A resumable update saves the repository's archive to disk before unpacking
+//! it, then saves releases in batches, recording a hash of each saved release's
+//! path in the archive in the same transaction. If the update is interrupted,
+//! the next one unpacks the saved archive again and skips the releases which
+//! were already saved, rather than downloading and parsing everything again.
+
+use std::{
+    collections::HashSet,
+    ops::DerefMut,
+    path::{Path, PathBuf},
+};
+
+use diesel::{delete, insert_into, prelude::*, replace_into};
+#[cfg(feature = "network")]
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, RepoId, schema::*};
+
+/// A resumable update which hasn't finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumableUpdate {
+    /// Where the repository's archive was saved.
+    pub archive_path: PathBuf,
+    /// The ETag the archive was served with.
+    pub etag: Option<String>,
+    /// The content type the archive was served with, in case its format
+    /// can't be told from its contents.
+    pub content_type: Option<String>,
+    /// The [hashes](asset_hash) of the releases which were saved.
+    pub completed: HashSet<Vec<u8>>,
+}
+
+/// Hashes the path of an asset in a repository archive, to record that it
+/// was saved.
+#[cfg(feature = "network")]
+pub(crate) fn asset_hash(path: &Path) -> Vec<u8> {
+    Sha256::digest(path.as_os_str().as_encoded_bytes()).to_vec()
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns the unfinished resumable update of a repository, if it has
+    /// one.
+    pub fn resumable_update(&mut self, repo: RepoId) -> Result<Option<ResumableUpdate>, QueryError> {
+        let operation = || Operation::LoadResumableUpdate { repo };
+
+        let Some((archive_path, etag, content_type)) = resumable_updates::table
+            .find(repo)
+            .select((
+                resumable_updates::archive_path,
+                resumable_updates::etag,
+                resumable_updates::content_type,
+            ))
+            .get_result::<(String, Option<String>, Option<String>)>(&mut *self.connection)
+            .optional()
+            .during(operation)?
+        else {
+            return Ok(None);
+        };
+
+        let completed = unpack_checkpoints::table
+            .filter(unpack_checkpoints::repo_id.eq(repo))
+            .select(unpack_checkpoints::asset_hash)
+            .load::<Vec<u8>>(&mut *self.connection)
+            .during(operation)?;
+
+        Ok(Some(ResumableUpdate {
+            archive_path: archive_path.into(),
+            etag,
+            content_type,
+            completed: completed.into_iter().collect(),
+        }))
+    }
+
+    /// Records that a resumable update of a repository is starting from the
+    /// archive at `archive_path`, forgetting any earlier one.
+    #[instrument(skip(self))]
+    pub fn begin_resumable_update(
+        &mut self,
+        repo: RepoId,
+        archive_path: &Path,
+        etag: Option<&str>,
+        content_type: Option<&str>,
+    ) -> Result<(), QueryError> {
+        self.connection
+            .transaction(|conn| {
+                delete(unpack_checkpoints::table.filter(unpack_checkpoints::repo_id.eq(repo)))
+                    .execute(conn)?;
+                replace_into(resumable_updates::table)
+                    .values((
+                        resumable_updates::repo_id.eq(repo),
+                        resumable_updates::archive_path.eq(archive_path.to_string_lossy()),
+                        resumable_updates::etag.eq(etag),
+                        resumable_updates::content_type.eq(content_type),
+                        resumable_updates::started_at.eq(OffsetDateTime::now_utc()),
+                    ))
+                    .execute(conn)
+            })
+            .during(|| Operation::SaveCheckpoints { repo })?;
+
+        Ok(())
+    }
+
+    /// Records that the releases with the given [hashes](asset_hash) have
+    /// been saved by a repository's resumable update.
+    pub fn record_checkpoints<'a>(
+        &mut self,
+        repo: RepoId,
+        hashes: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<(), QueryError> {
+        let rows = hashes
+            .into_iter()
+            .map(|hash| {
+                (
+                    unpack_checkpoints::repo_id.eq(repo),
+                    unpack_checkpoints::asset_hash.eq(hash),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        insert_into(unpack_checkpoints::table)
+            .values(rows)
+            .on_conflict_do_nothing()
+            .execute(&mut *self.connection)
+            .during(|| Operation::SaveCheckpoints { repo })?;
+
+        Ok(())
+    }
+
+    /// Forgets a repository's resumable update once it has finished.
+    #[instrument(skip(self))]
+    pub fn finish_resumable_update(&mut self, repo: RepoId) -> Result<(), QueryError> {
+        delete(resumable_updates::table.find(repo))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SaveCheckpoints { repo })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "network"))]
+mod test {
+    use super::*;
+    use crate::repo::test_support::db_with_default_repo;
+
+    #[test]
+    fn records_saved_releases() {
+        let (mut db, repo) = db_with_default_repo();
+        assert_eq!(db.resumable_update(repo.id).unwrap(), None);
+
+        let archive = Path::new("/tmp/1.archive");
+        let parallax = asset_hash(Path::new("CKAN-meta/Parallax/Parallax-2.0.1.ckan"));
+        db.begin_resumable_update(repo.id, archive, Some("\"abc\""), None)
+            .unwrap();
+        db.record_checkpoints(repo.id, [parallax.as_slice()])
+            .unwrap();
+
+        let update = db.resumable_update(repo.id).unwrap().unwrap();
+        assert_eq!(update.archive_path, archive);
+        assert_eq!(update.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(update.completed, HashSet::from([parallax]));
+
+        // Starting again forgets what the last update saved.
+        db.begin_resumable_update(repo.id, archive, None, None)
+            .unwrap();
+        assert!(db.resumable_update(repo.id).unwrap().unwrap().completed.is_empty());
+
+        db.finish_resumable_update(repo.id).unwrap();
+        assert_eq!(db.resumable_update(repo.id).unwrap(), None);
+    }
+}
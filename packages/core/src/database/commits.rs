@@ -0,0 +1,177 @@
+//! The Git commits repositories were last updated from.
+//!
+//! A repository which is fetched from Git records the commit it was updated
+//! to, so that its next update only applies the files changed since then. A
+//! full update from an archive forgets the commit, since the database no
+//! longer matches it.
+
+use std::ops::DerefMut;
+
+use diesel::{delete, prelude::*, replace_into};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::{
+    database::{
+        ModuleId, Operation, QueryContext, QueryError, RepoDB, RepoId,
+        models::{Module, ModuleRelease},
+        schema::*,
+    },
+    json::Identifier,
+};
+
+/// The commit a repository was last updated from.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable)]
+pub struct RepoCommit {
+    /// The Git repository the commit was fetched from.
+    pub remote: String,
+    pub branch: String,
+    pub commit_id: String,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns the commit a repository was last updated from, if it was
+    /// updated from Git.
+    pub fn repo_commit(&mut self, repo: RepoId) -> Result<Option<RepoCommit>, QueryError> {
+        repo_commits::table
+            .find(repo)
+            .select((
+                repo_commits::remote,
+                repo_commits::branch,
+                repo_commits::commit_id,
+            ))
+            .get_result::<RepoCommit>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadRepoCommit { repo })
+    }
+
+    /// Records the commit on `branch` of `remote` that a repository was just
+    /// updated from.
+    #[instrument(skip(self))]
+    pub fn set_repo_commit(
+        &mut self,
+        repo: RepoId,
+        remote: &str,
+        branch: &str,
+        commit_id: &str,
+    ) -> Result<(), QueryError> {
+        replace_into(repo_commits::table)
+            .values((
+                repo_commits::repo_id.eq(repo),
+                repo_commits::remote.eq(remote),
+                repo_commits::branch.eq(branch),
+                repo_commits::commit_id.eq(commit_id),
+                repo_commits::fetched_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SaveRepoCommit { repo })?;
+
+        Ok(())
+    }
+
+    /// Forgets the commit a repository was updated from, so that its next
+    /// update from Git applies every file.
+    pub fn forget_repo_commit(&mut self, repo: RepoId) -> Result<(), QueryError> {
+        delete(repo_commits::table.find(repo))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SaveRepoCommit { repo })?;
+
+        Ok(())
+    }
+
+    /// Deletes a release from a repository, along with its module if it was
+    /// the module's last release. Returns whether there was a release to
+    /// delete.
+    #[instrument(skip(self))]
+    pub fn delete_release(
+        &mut self,
+        repo: RepoId,
+        slug: &Identifier,
+        version: &str,
+    ) -> Result<bool, QueryError> {
+        let operation = || Operation::DeleteRelease {
+            slug: slug.to_string(),
+            version: version.into(),
+        };
+        let conn = &mut *self.connection;
+
+        let Some(module_id) = modules::table
+            .filter(Module::in_repo(repo))
+            .filter(Module::with_slug(slug))
+            .select(modules::module_id)
+            .first::<ModuleId>(conn)
+            .optional()
+            .during(operation)?
+        else {
+            return Ok(false);
+        };
+
+        let deleted = delete(module_releases::table)
+            .filter(ModuleRelease::with_parent(module_id))
+            .filter(module_releases::version.eq(version))
+            .execute(conn)
+            .during(operation)?;
+
+        let remaining = module_releases::table
+            .filter(ModuleRelease::with_parent(module_id))
+            .count()
+            .get_result::<i64>(conn)
+            .during(operation)?;
+        if remaining == 0 {
+            delete(modules::table.find(module_id))
+                .execute(conn)
+                .during(operation)?;
+        }
+
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn records_commits_and_deletes_releases() {
+        let (mut db, repo) = db_with_default_repo();
+
+        assert_eq!(db.repo_commit(repo.id).unwrap(), None);
+        db.set_repo_commit(repo.id, "https://example.com/meta.git", "main", "abc123")
+            .unwrap();
+        assert_eq!(
+            db.repo_commit(repo.id).unwrap(),
+            Some(RepoCommit {
+                remote: "https://example.com/meta.git".into(),
+                branch: "main".into(),
+                commit_id: "abc123".into(),
+            })
+        );
+        db.forget_repo_commit(repo.id).unwrap();
+        assert_eq!(db.repo_commit(repo.id).unwrap(), None);
+
+        let slug = "Parallax".parse::<Identifier>().unwrap();
+        let (module, _) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(&release("Parallax", "1.1").build(), repo.id, Some(module))
+            .unwrap();
+
+        assert!(db.delete_release(repo.id, &slug, "1.0").unwrap());
+        assert!(!db.delete_release(repo.id, &slug, "1.0").unwrap());
+        let modules_left = modules::table
+            .find(module)
+            .count()
+            .get_result::<i64>(db.connection_mut())
+            .unwrap();
+        assert_eq!(modules_left, 1);
+
+        assert!(db.delete_release(repo.id, &slug, "1.1").unwrap());
+        let modules_left = modules::table
+            .find(module)
+            .count()
+            .get_result::<i64>(db.connection_mut())
+            .unwrap();
+        assert_eq!(modules_left, 0);
+    }
+}
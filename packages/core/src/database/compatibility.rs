@@ -0,0 +1,221 @@
+//! Which release of a module to use with each version of the game.
+
+use std::{collections::BTreeSet, ops::DerefMut};
+
+use diesel::prelude::*;
+
+use crate::{
+    database::{
+        JsonbValue, ModuleId, Operation, QueryContext, QueryError, ReleaseId, RepoDB,
+        models::ModuleRelease, schema::*,
+    },
+    repo::game::GameVersion,
+};
+
+/// The best release of a module for one minor version of the game, like 1.12.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct GameCompatibility {
+    /// The game version, with only its major and minor parts.
+    pub game_version: GameVersion,
+    /// The newest compatible release, or `None` if no release works with this
+    /// version of the game.
+    pub release: Option<CompatibleRelease>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct CompatibleRelease {
+    pub id: ReleaseId,
+    pub version: String,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Finds the newest release of a module for each minor version of the game
+    /// in the builds table, oldest game version first. Only releases allowed
+    /// by the user's prerelease settings, and which haven't been yanked, are
+    /// considered.
+    pub fn compatibility(
+        &mut self,
+        module: ModuleId,
+    ) -> Result<Vec<GameCompatibility>, QueryError> {
+        let operation = || Operation::Compatibility { module };
+
+        let slug = modules::table
+            .find(module)
+            .select(modules::module_slug)
+            .get_result::<String>(&mut *self.connection)
+            .during(operation)?;
+        let max_status = self.max_release_status(&slug)?;
+
+        let builds = builds::table
+            .select(builds::version)
+            .load::<JsonbValue>(&mut *self.connection)
+            .during(operation)?;
+
+        // Newest first, so the first match for each game version is the best.
+        let releases = module_releases::table
+            .filter(module_releases::module_id.eq(module))
+            .filter(module_releases::release_status.le(i32::from(max_status)))
+            .filter(ModuleRelease::not_yanked(slug))
+            .select(ModuleRelease::as_select())
+            .order_by(ModuleRelease::by_version())
+            .load(&mut *self.connection)
+            .during(operation)?;
+
+        // SQLite can only read JSONB from 3.45 onwards, so versions are
+        // compared here rather than in the query.
+        let game_versions = builds
+            .into_iter()
+            .filter_map(|version| GameVersion::try_from(version).ok())
+            .filter_map(|version| Some((version.major()?, version.minor()?)))
+            .collect::<BTreeSet<_>>();
+
+        let compatibility = game_versions
+            .into_iter()
+            .map(|(major, minor)| {
+                let release = releases
+                    .iter()
+                    .find(|release| supports(release, major, minor))
+                    .map(|release| CompatibleRelease {
+                        id: release.id,
+                        version: release.version.clone(),
+                    });
+
+                GameCompatibility {
+                    game_version: GameVersion::new(Some(major), Some(minor), None, None),
+                    release,
+                }
+            })
+            .collect();
+
+        Ok(compatibility)
+    }
+}
+
+/// Whether a release works with a minor version of the game.
+pub(super) fn supports(release: &ModuleRelease, major: u32, minor: u32) -> bool {
+    supports_game(
+        release.game_version,
+        release.game_version_min,
+        release.game_version_max,
+        major,
+        minor,
+    )
+}
+
+/// Whether a release with the given game versions works with a minor version
+/// of the game.
+///
+/// A release's `game_version` holds its `ksp_version` if it has one, which
+/// matches every version with the same major and minor parts. Otherwise its
+/// `game_version_min` and `game_version_max` bound the versions it supports,
+/// and a bound with fewer parts includes every version starting with them, so
+/// a maximum of 1.12 includes 1.12.5. A release with none of these supports
+/// every version.
+pub(super) fn supports_game(
+    exact: GameVersion,
+    min: GameVersion,
+    max: Option<GameVersion>,
+    major: u32,
+    minor: u32,
+) -> bool {
+    if !min.is_empty() || max.is_some() {
+        let above_min = (major, minor) >= (min.major().unwrap_or(0), min.minor().unwrap_or(0));
+        let below_max = max.is_none_or(|max| match (max.major(), max.minor()) {
+            (Some(max_major), Some(max_minor)) => (major, minor) <= (max_major, max_minor),
+            (Some(max_major), None) => major <= max_major,
+            (None, _) => true,
+        });
+        return above_min && below_max;
+    }
+
+    exact.major().is_none_or(|m| m == major) && exact.minor().is_none_or(|m| m == minor)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::models::BuildRecord,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    fn game(version: &str) -> GameVersion {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn best_release_per_game_version() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let builds = ["1.8.1", "1.10.0", "1.12.3", "1.12.5"];
+        db.register_builds(
+            builds
+                .iter()
+                .enumerate()
+                .map(|(id, version)| BuildRecord {
+                    build_id: id as i32,
+                    version: game(version),
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let (module, old) = db
+            .create_release(
+                &release("Parallax", "1.0")
+                    .merge(json!({ "ksp_version": "1.8" }))
+                    .build(),
+                repo.id,
+                None,
+            )
+            .unwrap();
+        let (_, bounded) = db
+            .create_release(
+                &release("Parallax", "3.0")
+                    .merge(json!({ "ksp_version_min": "1.9", "ksp_version_max": "1.10" }))
+                    .build(),
+                repo.id,
+                Some(module),
+            )
+            .unwrap();
+        let (_, new) = db
+            .create_release(
+                &release("Parallax", "2.0")
+                    .merge(json!({ "ksp_version_min": "1.12" }))
+                    .build(),
+                repo.id,
+                Some(module),
+            )
+            .unwrap();
+
+        let compatibility = db.compatibility(module).unwrap();
+        assert_eq!(
+            compatibility,
+            [
+                GameCompatibility {
+                    game_version: game("1.8"),
+                    release: Some(CompatibleRelease {
+                        id: old,
+                        version: "1.0".into(),
+                    }),
+                },
+                GameCompatibility {
+                    game_version: game("1.10"),
+                    release: Some(CompatibleRelease {
+                        id: bounded,
+                        version: "3.0".into(),
+                    }),
+                },
+                GameCompatibility {
+                    game_version: game("1.12"),
+                    release: Some(CompatibleRelease {
+                        id: new,
+                        version: "2.0".into(),
+                    }),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Checking the values which the database works out from other values.
+//!
+//! Sort keys, content hashes and the search index are derived from releases,
+//! and rows like tags and authors only make sense while what they refer to
+//! exists. They're kept right by updates, but a crash, an old version of
+//! Camrete or another program editing the database can leave them wrong.
+
+use std::ops::DerefMut;
+
+use diesel::{connection::SimpleConnection, prelude::*, sql_query, sql_types::BigInt, update};
+
+use crate::{
+    Error,
+    database::{Operation, QueryContext, RepoDB, RepoId, schema::*},
+};
+
+/// Tables whose rows refer to others, with the condition under which a row's
+/// referent is missing. Rows are deleted in this order, so rows which are
+/// orphaned by earlier deletions are deleted too.
+const ORPHAN_CHECKS: [(&str, &str); 19] = [
+    (
+        "modules",
+        "repo_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "module_releases",
+        "module_id NOT IN (SELECT module_id FROM modules)",
+    ),
+    (
+        "module_authors",
+        "release_id NOT IN (SELECT release_id FROM module_releases)
+            OR author_id NOT IN (SELECT author_id FROM authors)",
+    ),
+    (
+        "module_licenses",
+        "release_id NOT IN (SELECT release_id FROM module_releases)",
+    ),
+    (
+        "module_localizations",
+        "release_id NOT IN (SELECT release_id FROM module_releases)",
+    ),
+    (
+        "module_tags",
+        "release_id NOT IN (SELECT release_id FROM module_releases)",
+    ),
+    (
+        "module_replacements",
+        "release_id NOT IN (SELECT release_id FROM module_releases)",
+    ),
+    (
+        "module_relationship_groups",
+        "release_id NOT IN (SELECT release_id FROM module_releases)",
+    ),
+    (
+        "module_relationships",
+        "group_id NOT IN (SELECT group_id FROM module_relationship_groups)",
+    ),
+    (
+        "module_staleness",
+        "module_id NOT IN (SELECT module_id FROM modules)",
+    ),
+    (
+        "release_validations",
+        "release_id NOT IN (SELECT release_id FROM module_releases)",
+    ),
+    (
+        "release_history",
+        "repo_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "repo_commits",
+        "repo_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "repo_metadata",
+        "repo_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "repository_refs",
+        "referrer_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "resumable_updates",
+        "repo_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "unpack_checkpoints",
+        "repo_id NOT IN (SELECT repo_id FROM resumable_updates)",
+    ),
+    (
+        "update_locks",
+        "repo_id NOT IN (SELECT repo_id FROM repositories)",
+    ),
+    (
+        "authors",
+        "author_id NOT IN (SELECT author_id FROM module_authors)",
+    ),
+];
+
+const STALE_SORT_KEYS: &str = "
+FROM module_releases WHERE sort_key IS NOT VERSION_SORT_KEY(version)";
+
+/// Modules which only exist to hold a download count have no releases, so
+/// only those without a download count either are empty. SQLite doesn't allow
+/// an alias on the table a `DELETE` is from, so the condition names it in full.
+const EMPTY_MODULES: &str = "
+FROM modules
+WHERE download_count = 0
+    AND NOT EXISTS (SELECT 1 FROM module_releases r WHERE r.module_id = modules.module_id)";
+
+/// Every module with a release should have exactly one entry in the search
+/// index, and no entry should belong to a module which doesn't exist.
+const SEARCH_INDEX_MISMATCHES: &str = "
+SELECT (
+    SELECT COUNT(*) FROM modules m
+    WHERE EXISTS (SELECT 1 FROM module_releases WHERE module_id = m.module_id)
+        AND (SELECT COUNT(*) FROM module_search WHERE rowid = m.module_id) != 1
+) + (
+    SELECT COUNT(*) FROM module_search
+    WHERE rowid NOT IN (
+        SELECT module_id FROM modules
+        WHERE EXISTS (SELECT 1 FROM module_releases r WHERE r.module_id = modules.module_id)
+    )
+) AS count";
+
+/// What [`RepoDB::verify_derived`] found. Each count is of the problems found
+/// before any were repaired.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct ConsistencyReport {
+    /// Releases whose sort key doesn't match their version.
+    pub stale_sort_keys: u64,
+    /// Modules with releases which don't have exactly one entry in the search
+    /// index, plus entries for modules which no longer have any releases.
+    pub search_index_mismatches: u64,
+    /// Repositories whose content hash doesn't match their releases.
+    /// Repositories without a hash aren't checked.
+    pub stale_content_hashes: Vec<RepoId>,
+    /// Rows which refer to a row that doesn't exist, by table. Tables without
+    /// any are left out.
+    pub orphaned_rows: Vec<OrphanedRows>,
+    /// Modules with no releases and no download count.
+    pub empty_modules: u64,
+    /// Whether the problems were fixed.
+    pub repaired: bool,
+}
+
+/// Rows in one table which refer to a row that doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct OrphanedRows {
+    pub table: String,
+    pub count: u64,
+}
+
+impl ConsistencyReport {
+    /// Returns whether no problems were found.
+    pub fn is_consistent(&self) -> bool {
+        self.stale_sort_keys == 0
+            && self.search_index_mismatches == 0
+            && self.stale_content_hashes.is_empty()
+            && self.orphaned_rows.is_empty()
+            && self.empty_modules == 0
+    }
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+fn count(conn: &mut SqliteConnection, query: &str) -> QueryResult<u64> {
+    let row = sql_query(query).get_result::<Count>(conn)?;
+    Ok(row.count.max(0) as u64)
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Checks the values which are worked out from others: release sort keys,
+    /// repository content hashes and the search index. Also looks for rows
+    /// which refer to rows that don't exist, and for modules with neither
+    /// releases nor a download count.
+    ///
+    /// If `repair` is set, each problem is fixed by recomputing the value or
+    /// deleting the row. Either way, the report describes what was found.
+    pub fn verify_derived(&mut self, repair: bool) -> Result<ConsistencyReport, Error> {
+        self.transaction(|mut db| {
+            let mut report = db.check_derived().during(|| Operation::VerifyDerived)?;
+
+            if repair && !report.is_consistent() {
+                db.repair_derived(&report)
+                    .during(|| Operation::RepairDerived)?;
+                // Deleting modules or releases can leave the index out of date
+                // too, so it's always rebuilt.
+                db.rebuild_search_index()?;
+                report.repaired = true;
+            }
+
+            Ok(report)
+        })
+    }
+
+    fn check_derived(&mut self) -> QueryResult<ConsistencyReport> {
+        let conn = &mut *self.connection;
+
+        let mut orphaned_rows = vec![];
+        for (table, condition) in ORPHAN_CHECKS {
+            let count = count(
+                conn,
+                &format!("SELECT COUNT(*) AS count FROM {table} WHERE {condition}"),
+            )?;
+            if count > 0 {
+                orphaned_rows.push(OrphanedRows {
+                    table: table.into(),
+                    count,
+                });
+            }
+        }
+
+        let stale_sort_keys = count(conn, &format!("SELECT COUNT(*) AS count {STALE_SORT_KEYS}"))?;
+        let empty_modules = count(conn, &format!("SELECT COUNT(*) AS count {EMPTY_MODULES}"))?;
+        let search_index_mismatches = count(conn, SEARCH_INDEX_MISMATCHES)?;
+
+        let hashed = repositories::table
+            .filter(repositories::content_hash.is_not_null())
+            .select((repositories::repo_id, repositories::content_hash))
+            .load::<(RepoId, Option<String>)>(conn)?;
+        let mut stale_content_hashes = vec![];
+        for (repo, hash) in hashed {
+            if hash.as_deref() != Some(&*self.current_content_hash(repo)?) {
+                stale_content_hashes.push(repo);
+            }
+        }
+
+        Ok(ConsistencyReport {
+            stale_sort_keys,
+            search_index_mismatches,
+            stale_content_hashes,
+            orphaned_rows,
+            empty_modules,
+            repaired: false,
+        })
+    }
+
+    fn repair_derived(&mut self, report: &ConsistencyReport) -> QueryResult<()> {
+        let conn = &mut *self.connection;
+
+        // Empty modules go first, so the sweep below deletes the rows which
+        // referred to them.
+        conn.batch_execute(&format!("DELETE {EMPTY_MODULES};"))?;
+        for (table, condition) in ORPHAN_CHECKS {
+            conn.batch_execute(&format!("DELETE FROM {table} WHERE {condition};"))?;
+        }
+        conn.batch_execute(
+            "UPDATE module_releases SET sort_key = VERSION_SORT_KEY(version)
+            WHERE sort_key IS NOT VERSION_SORT_KEY(version);",
+        )?;
+
+        for &repo in &report.stale_content_hashes {
+            let hash = self.current_content_hash(repo)?;
+            update(repositories::table.find(repo))
+                .set(repositories::content_hash.eq(hash))
+                .execute(&mut *self.connection)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::insert_into;
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn repairs_derived_values() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let release = release("Parallax", "2.0.1")
+            .set("tags", json!(["graphics"]))
+            .build();
+        let (_, release_id) = db.create_release(&release, repo.id, None).unwrap();
+        db.update_content_hash(repo.id).unwrap();
+        db.rebuild_search_index().unwrap();
+        assert!(db.verify_derived(false).unwrap().is_consistent());
+
+        // Damage the database the way a program ignoring foreign keys might.
+        db.connection_mut()
+            .batch_execute("PRAGMA foreign_keys = OFF;")
+            .unwrap();
+        insert_into(module_tags::table)
+            .values((
+                module_tags::release_id.eq(release_id.0 + 100),
+                module_tags::ordinal.eq(0),
+                module_tags::tag.eq("parts"),
+            ))
+            .execute(db.connection_mut())
+            .unwrap();
+        insert_into(modules::table)
+            .values((
+                modules::repo_id.eq(repo.id),
+                modules::module_slug.eq("Empty"),
+                modules::download_count.eq(0),
+            ))
+            .execute(db.connection_mut())
+            .unwrap();
+        let empty = modules::table
+            .filter(modules::module_slug.eq("Empty"))
+            .select(modules::module_id)
+            .get_result::<i32>(db.connection_mut())
+            .unwrap();
+        insert_into(module_staleness::table)
+            .values((
+                module_staleness::module_id.eq(empty),
+                module_staleness::game_versions_behind.eq(0),
+            ))
+            .execute(db.connection_mut())
+            .unwrap();
+        update(module_releases::table)
+            .set(module_releases::sort_key.eq(None::<Vec<u8>>))
+            .execute(db.connection_mut())
+            .unwrap();
+        update(repositories::table)
+            .set(repositories::content_hash.eq("abc"))
+            .execute(db.connection_mut())
+            .unwrap();
+        sql_query("DELETE FROM module_search")
+            .execute(db.connection_mut())
+            .unwrap();
+
+        let report = db.verify_derived(true).unwrap();
+        assert_eq!(
+            report,
+            ConsistencyReport {
+                stale_sort_keys: 1,
+                search_index_mismatches: 1,
+                stale_content_hashes: vec![repo.id],
+                orphaned_rows: vec![OrphanedRows {
+                    table: "module_tags".into(),
+                    count: 1,
+                }],
+                empty_modules: 1,
+                repaired: true,
+            }
+        );
+
+        let report = db.verify_derived(false).unwrap();
+        assert!(report.is_consistent(), "{report:?}");
+        assert!(!report.repaired);
+    }
+}
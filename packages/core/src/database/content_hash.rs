@@ -0,0 +1,114 @@
+//! A hash of the releases in a repository, for telling whether two databases
+//! hold the same content without comparing them release by release.
+
+use std::ops::DerefMut;
+
+use diesel::{prelude::*, update};
+use sha2::{Digest, Sha256};
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, RepoId, schema::*};
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Works out the hash of a repository's releases and saves it on the
+    /// repository, returning the new hash. This is done after every update.
+    pub fn update_content_hash(&mut self, repo: RepoId) -> Result<String, QueryError> {
+        let hash = self
+            .current_content_hash(repo)
+            .during(|| Operation::HashRepo { repo })?;
+
+        update(repositories::table.find(repo))
+            .set(repositories::content_hash.eq(&hash))
+            .execute(&mut *self.connection)
+            .during(|| Operation::HashRepo { repo })?;
+
+        Ok(hash)
+    }
+
+    /// Works out the hash of a repository's releases, without saving it.
+    pub(super) fn current_content_hash(&mut self, repo: RepoId) -> QueryResult<String> {
+        let mut releases = module_releases::table
+            .inner_join(modules::table)
+            .filter(modules::repo_id.eq(repo))
+            .select((modules::module_slug, module_releases::version))
+            .load::<(String, String)>(&mut *self.connection)?;
+
+        Ok(content_hash(&mut releases))
+    }
+
+    /// The hash of a repository's releases as of its last update, or `None` if
+    /// it hasn't been updated since the hash was introduced.
+    pub fn content_hash(&mut self, repo: RepoId) -> Result<Option<String>, QueryError> {
+        let hash = repositories::table
+            .find(repo)
+            .select(repositories::content_hash)
+            .get_result::<Option<String>>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadRepos)?;
+
+        Ok(hash.flatten())
+    }
+}
+
+/// Hashes a list of module identifiers and release versions, as a lowercase
+/// hex SHA-256 digest.
+///
+/// The list is sorted by bytes first, rather than by the database, so that the
+/// hash doesn't depend on the order releases were saved in or on a collation.
+/// Neither part can contain a NUL, so it's used to separate them.
+fn content_hash(releases: &mut [(String, String)]) -> String {
+    releases.sort_unstable();
+
+    let mut hasher = Sha256::new();
+    for (slug, version) in releases.iter() {
+        hasher.update(slug);
+        hasher.update([0]);
+        hasher.update(version);
+        hasher.update([0]);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    /// Saves the given releases into a new database, in order, and returns
+    /// the repository's hash.
+    fn hash_of(releases: &[(&str, &str)]) -> String {
+        let (mut db, repo) = db_with_default_repo();
+        assert_eq!(db.content_hash(repo.id).unwrap(), None);
+
+        for (identifier, version) in releases {
+            db.create_release(&release(identifier, version).build(), repo.id, None)
+                .unwrap();
+        }
+
+        let hash = db.update_content_hash(repo.id).unwrap();
+        assert_eq!(db.content_hash(repo.id).unwrap().as_ref(), Some(&hash));
+        hash
+    }
+
+    #[test]
+    fn hash_depends_only_on_the_releases() {
+        let hash = hash_of(&[("Parallax", "2.0.8"), ("Scatterer", "0.0838")]);
+        assert_eq!(hash.len(), 64);
+
+        // The order releases were saved in doesn't matter.
+        assert_eq!(
+            hash,
+            hash_of(&[("Scatterer", "0.0838"), ("Parallax", "2.0.8")])
+        );
+
+        // A new version does.
+        assert_ne!(
+            hash,
+            hash_of(&[("Parallax", "2.0.9"), ("Scatterer", "0.0838")])
+        );
+        // So does where the identifier ends and the version starts.
+        assert_ne!(
+            hash_of(&[("Parallax", "2.0.8")]),
+            hash_of(&[("Parallax2", ".0.8")])
+        );
+    }
+}
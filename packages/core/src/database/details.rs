@@ -0,0 +1,346 @@
+//! Everything needed to show several modules at once, loaded together.
+//!
+//! Loading the details of one module takes several queries, so a GUI showing a
+//! selection of modules would make dozens of calls. Here each kind of detail
+//! is loaded for every module in a single query instead. The details of a
+//! single release, as shown by the command-line app, are loaded here too.
+
+use std::{collections::HashMap, ops::DerefMut};
+
+use diesel::prelude::*;
+
+use crate::{
+    database::{
+        ModuleId, Operation, QueryContext, QueryError, ReleaseId, RepoDB,
+        models::{
+            Module, ModuleRelease,
+            module::{ModuleRelationship, ModuleRelationshipGroup},
+        },
+        schema::*,
+    },
+    json::{AuthorCredit, ReleaseStatus},
+};
+
+/// A member of a relationship group, alongside the group.
+#[derive(Debug, Queryable, uniffi::Record)]
+pub struct FullRelationship {
+    pub group: ModuleRelationshipGroup,
+    pub description: ModuleRelationship,
+}
+
+/// A module with its latest release and everything attached to it.
+#[derive(Debug, uniffi::Record)]
+pub struct ModuleDetail {
+    pub module: Module,
+    /// The newest release allowed by the user's prerelease settings, leaving
+    /// out yanked releases, like [`RepoDB::latest_release`]. The lists below
+    /// belong to this release, and are empty if there isn't one.
+    pub latest: Option<ModuleRelease>,
+    pub tags: Vec<String>,
+    pub authors: Vec<String>,
+    pub licenses: Vec<String>,
+    pub locales: Vec<String>,
+    /// The release's relationships, ordered by type and then as they're
+    /// listed in its metadata.
+    pub relationships: Vec<FullRelationship>,
+}
+
+/// What's attached to one release.
+#[derive(Debug)]
+pub struct ReleaseDetail {
+    pub tags: Vec<String>,
+    pub credits: Vec<AuthorCredit>,
+    pub licenses: Vec<String>,
+    /// The release's relationships, ordered by type and then as they're
+    /// listed in its metadata, with each group's members next to each other.
+    pub relationships: Vec<FullRelationship>,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Finds the module with the given identifier, from any repository.
+    pub fn find_module(&mut self, slug: &str) -> Result<Option<Module>, QueryError> {
+        Module::all()
+            .filter(modules::module_slug.eq(slug))
+            .first(&mut *self.connection)
+            .optional()
+            .during(|| Operation::FindModule { slug: slug.into() })
+    }
+
+    /// Every release of a module, newest first, whatever its release status.
+    pub fn module_releases(&mut self, module: ModuleId) -> Result<Vec<ModuleRelease>, QueryError> {
+        ModuleRelease::all()
+            .filter(ModuleRelease::with_parent(module))
+            .order_by(ModuleRelease::by_version())
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadReleases { module })
+    }
+
+    /// Loads the tags, authors, licenses and relationships of a release.
+    pub fn release_detail(&mut self, release: ReleaseId) -> Result<ReleaseDetail, QueryError> {
+        let operation = || Operation::LoadReleaseDetails { release };
+
+        Ok(ReleaseDetail {
+            tags: ModuleRelease::tags_for(release)
+                .load(&mut *self.connection)
+                .during(operation)?,
+            credits: ModuleRelease::credits_for(release)
+                .load(&mut *self.connection)
+                .during(operation)?,
+            licenses: ModuleRelease::licenses_for(release)
+                .load(&mut *self.connection)
+                .during(operation)?,
+            relationships: ModuleRelease::relationships_for(release)
+                .then_order_by(module_relationships::ordinal)
+                .load(&mut *self.connection)
+                .during(operation)?,
+        })
+    }
+
+    /// Loads the details of several modules, in the order their IDs are given.
+    /// IDs which don't belong to a module are skipped.
+    ///
+    /// The number of queries doesn't depend on how many modules are loaded.
+    pub fn modules_detail(&mut self, ids: &[ModuleId]) -> Result<Vec<ModuleDetail>, QueryError> {
+        let operation = || Operation::LoadModuleDetails;
+
+        let mut modules = Module::all()
+            .filter(modules::module_id.eq_any(ids))
+            .load::<Module>(&mut *self.connection)
+            .during(operation)?
+            .into_iter()
+            .map(|module| (module.id, module))
+            .collect::<HashMap<_, _>>();
+
+        let latest = self.latest_releases(ids)?;
+        let release_ids = latest.values().copied().collect::<Vec<_>>();
+
+        let mut releases = ModuleRelease::all()
+            .filter(module_releases::release_id.eq_any(&release_ids))
+            .load::<ModuleRelease>(&mut *self.connection)
+            .during(operation)?
+            .into_iter()
+            .map(|release| (release.id, release))
+            .collect::<HashMap<_, _>>();
+
+        let mut tags = group_by_release(
+            module_tags::table
+                .filter(module_tags::release_id.eq_any(&release_ids))
+                .select((module_tags::release_id, module_tags::tag))
+                .order(module_tags::ordinal)
+                .load(&mut *self.connection)
+                .during(operation)?,
+        );
+        let mut authors = group_by_release(
+            module_authors::table
+                .inner_join(authors::table)
+                .filter(module_authors::release_id.eq_any(&release_ids))
+                .select((module_authors::release_id, authors::name))
+                .order(module_authors::ordinal)
+                .load(&mut *self.connection)
+                .during(operation)?,
+        );
+        let mut licenses = group_by_release(
+            module_licenses::table
+                .filter(module_licenses::release_id.eq_any(&release_ids))
+                .select((module_licenses::release_id, module_licenses::license))
+                .load(&mut *self.connection)
+                .during(operation)?,
+        );
+        let mut locales = group_by_release(
+            module_localizations::table
+                .filter(module_localizations::release_id.eq_any(&release_ids))
+                .select((
+                    module_localizations::release_id,
+                    module_localizations::locale,
+                ))
+                .load(&mut *self.connection)
+                .during(operation)?,
+        );
+        let mut relationships = group_by_release(
+            module_relationship_groups::table
+                .inner_join(module_relationships::table)
+                .filter(module_relationship_groups::release_id.eq_any(&release_ids))
+                .select((
+                    ModuleRelationshipGroup::as_select(),
+                    ModuleRelationship::as_select(),
+                ))
+                .order(module_relationship_groups::rel_type)
+                .then_order_by(module_relationship_groups::ordinal)
+                .then_order_by(module_relationships::ordinal)
+                .load::<FullRelationship>(&mut *self.connection)
+                .during(operation)?
+                .into_iter()
+                .map(|relationship| (relationship.group.release_id, relationship))
+                .collect(),
+        );
+
+        let details = ids
+            .iter()
+            .filter_map(|id| {
+                let module = modules.remove(id)?;
+                let release = latest.get(id).copied();
+
+                Some(ModuleDetail {
+                    module,
+                    latest: release.and_then(|release| releases.remove(&release)),
+                    tags: take(&mut tags, release),
+                    authors: take(&mut authors, release),
+                    licenses: take(&mut licenses, release),
+                    locales: take(&mut locales, release),
+                    relationships: take(&mut relationships, release),
+                })
+            })
+            .collect();
+
+        Ok(details)
+    }
+
+    /// Finds the release [`Self::latest_release`] would pick for each of the
+    /// given modules, using one query for all of them.
+    fn latest_releases(
+        &mut self,
+        ids: &[ModuleId],
+    ) -> Result<HashMap<ModuleId, ReleaseId>, QueryError> {
+        let operation = || Operation::LoadModuleDetails;
+
+        let prerelease_default = self.prerelease_default()?;
+        let overrides = modules::table
+            .inner_join(
+                module_settings::table.on(module_settings::module_slug.eq(modules::module_slug)),
+            )
+            .filter(modules::module_id.eq_any(ids))
+            .select((modules::module_id, module_settings::allow_prerelease))
+            .load::<(ModuleId, Option<bool>)>(&mut *self.connection)
+            .during(operation)?
+            .into_iter()
+            .filter_map(|(id, allow)| Some((id, allow?)))
+            .collect::<HashMap<_, _>>();
+
+        // Newest first, so the first allowed release of each module is the one.
+        let candidates = module_releases::table
+            .inner_join(modules::table)
+            .left_join(
+                yanked_releases::table.on(yanked_releases::module_slug
+                    .eq(modules::module_slug)
+                    .and(yanked_releases::version.eq(module_releases::version))),
+            )
+            .filter(module_releases::module_id.eq_any(ids))
+            .select((
+                module_releases::module_id,
+                module_releases::release_id,
+                module_releases::release_status,
+                yanked_releases::yanked_at.nullable().is_not_null(),
+            ))
+            .order_by(ModuleRelease::by_version())
+            .load::<(ModuleId, ReleaseId, i32, bool)>(&mut *self.connection)
+            .during(operation)?;
+
+        let mut latest = HashMap::new();
+        for (module, release, status, yanked) in candidates {
+            let allows_prerelease = overrides
+                .get(&module)
+                .copied()
+                .unwrap_or(prerelease_default);
+            let max_status = if allows_prerelease {
+                ReleaseStatus::Development
+            } else {
+                ReleaseStatus::Stable
+            };
+
+            if !yanked && status <= i32::from(max_status) {
+                latest.entry(module).or_insert(release);
+            }
+        }
+
+        Ok(latest)
+    }
+}
+
+/// Collects rows of `(release, item)` into a list of items for each release,
+/// keeping their order.
+fn group_by_release<V>(rows: Vec<(ReleaseId, V)>) -> HashMap<ReleaseId, Vec<V>> {
+    let mut groups = HashMap::<_, Vec<_>>::new();
+    for (release, value) in rows {
+        groups.entry(release).or_default().push(value);
+    }
+    groups
+}
+
+/// Takes a release's list out of the lists made by [`group_by_release`], or an
+/// empty list if there's no release or nothing in its list.
+fn take<V>(lists: &mut HashMap<ReleaseId, Vec<V>>, release: Option<ReleaseId>) -> Vec<V> {
+    release
+        .and_then(|release| lists.remove(&release))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn matches_single_module_queries() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (parallax, _) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        let prerelease = release("Parallax", "2.0")
+            .merge(json!({
+                "author": ["Linx", "Gameslinx"],
+                "license": "MIT",
+                "release_status": "testing",
+                "tags": ["graphics"],
+                "depends": [
+                    { "name": "Kopernicus" },
+                    {
+                        "any_of": [{ "name": "A" }, { "name": "B" }],
+                        "choice_help_text": "Pick <b>A</b> unless\nyou use B",
+                    },
+                ],
+            }))
+            .build();
+        db.create_release(&prerelease, repo.id, Some(parallax))
+            .unwrap();
+        let (scatterer, _) = db
+            .create_release(&release("Scatterer", "0.1").build(), repo.id, None)
+            .unwrap();
+        db.create_release(
+            &release("Scatterer", "0.2").build(),
+            repo.id,
+            Some(scatterer),
+        )
+        .unwrap();
+        db.yank_release("Scatterer", "0.2", None).unwrap();
+        db.set_module_prerelease("Parallax", Some(true)).unwrap();
+
+        let details = db.modules_detail(&[scatterer, parallax]).unwrap();
+        assert_eq!(details.len(), 2);
+
+        for detail in &details {
+            let latest = db.latest_release(detail.module.id).unwrap().unwrap();
+            assert_eq!(detail.latest.as_ref().unwrap().id, latest.id);
+        }
+        assert_eq!(details[0].latest.as_ref().unwrap().version, "0.1");
+        assert_eq!(details[1].latest.as_ref().unwrap().version, "2.0");
+
+        let parallax = &details[1];
+        assert_eq!(parallax.authors, ["Linx", "Gameslinx"]);
+        assert_eq!(parallax.licenses, ["MIT"]);
+        assert_eq!(parallax.tags, ["graphics"]);
+        let targets = parallax
+            .relationships
+            .iter()
+            .map(|r| r.description.target_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(targets, ["Kopernicus", "A", "B"]);
+
+        let help = parallax.relationships[0].group.choice_help_text.as_ref();
+        assert!(help.is_none());
+        let help = parallax.relationships[1].group.choice_help_text.as_ref().unwrap();
+        assert_eq!(help.markdown, "Pick **A** unless\nyou use B");
+        assert_eq!(help.preview, "Pick A unless you use B");
+    }
+}
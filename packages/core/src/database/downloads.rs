@@ -0,0 +1,394 @@
+//! A queue of module downloads which is kept across restarts.
+//!
+//! This is only the stored state of the queue. The downloads themselves are
+//! run by [`RepoManager::run_download_queue`](crate::repo::RepoManager::run_download_queue),
+//! which also reports changes as they happen.
+
+use std::ops::DerefMut;
+
+use derive_more::TryFrom;
+use diesel::{delete, insert_into, prelude::*, result::Error::DeserializationError, update};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::database::{
+    DownloadId, JsonbValue, Operation, QueryContext, QueryError, RepoDB, models::ModuleRelease,
+    schema::*,
+};
+
+/// Where a queued download is up to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFrom, uniffi::Enum)]
+#[try_from(repr)]
+#[repr(i32)]
+pub enum DownloadState {
+    /// Waiting for its turn.
+    Queued = 0,
+    Downloading,
+    /// Downloaded, and being checked before it's moved into place.
+    Verifying,
+    Done,
+    /// Stopped by an error. It isn't tried again until it's retried.
+    Failed,
+}
+
+impl From<DownloadState> for i32 {
+    fn from(value: DownloadState) -> Self {
+        value as i32
+    }
+}
+
+/// A file to add to the download queue.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct NewDownload {
+    pub slug: String,
+    pub version: String,
+    pub url: Url,
+    /// Other URLs for the same file, in case `url` doesn't work.
+    pub mirrors: Vec<Url>,
+    /// Where the file is saved once it's finished.
+    pub destination: String,
+    /// The size of the file, if it's known ahead of time.
+    pub expected_size: Option<i64>,
+    /// The file's SHA-1 checksum as hex, if it's known ahead of time.
+    pub sha1: Option<String>,
+    /// The file's SHA-256 checksum as hex, if it's known ahead of time.
+    pub sha256: Option<String>,
+}
+
+impl NewDownload {
+    /// Describes the download of a release, with the rest of its download
+    /// URLs as mirrors. Returns `None` if the release has nothing to download,
+    /// such as for a metapackage.
+    pub fn for_release(slug: &str, release: &ModuleRelease, destination: String) -> Option<Self> {
+        let (url, mirrors) = release.metadata.download.split_first()?;
+
+        Some(Self {
+            slug: slug.to_owned(),
+            version: release.version.clone(),
+            url: url.clone(),
+            mirrors: mirrors.to_vec(),
+            destination,
+            expected_size: release.download_size,
+            sha1: release.metadata.download_hash.sha1.clone(),
+            sha256: release.metadata.download_hash.sha256.clone(),
+        })
+    }
+
+    /// Every URL the file can be downloaded from, starting with `url`.
+    pub fn urls(&self) -> Vec<Url> {
+        [self.url.clone()]
+            .into_iter()
+            .chain(self.mirrors.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable, Identifiable, uniffi::Record)]
+#[diesel(table_name = download_queue)]
+#[diesel(primary_key(download_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct QueuedDownload {
+    #[diesel(column_name = download_id)]
+    pub id: DownloadId,
+    #[diesel(column_name = module_slug)]
+    pub slug: String,
+    pub version: String,
+    #[diesel(deserialize_as = JsonbValue)]
+    pub url: Url,
+    pub destination: String,
+    pub expected_size: Option<i64>,
+    #[diesel(deserialize_as = i32)]
+    pub state: DownloadState,
+    /// How much of the file had been saved when the queue was last updated.
+    /// This is updated periodically, so it can be behind while the file is
+    /// downloading.
+    pub bytes_downloaded: i64,
+    /// Why the download failed, if it did.
+    pub error: Option<String>,
+    pub queued_at: OffsetDateTime,
+    #[diesel(deserialize_as = JsonbValue)]
+    pub mirrors: Vec<Url>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl QueuedDownload {
+    /// Every URL the file can be downloaded from, starting with `url`.
+    pub fn urls(&self) -> Vec<Url> {
+        [self.url.clone()]
+            .into_iter()
+            .chain(self.mirrors.iter().cloned())
+            .collect()
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Adds a file to the end of the download queue. If a download to the same
+    /// destination is already queued, that download is returned instead.
+    pub fn queue_download(&mut self, new: &NewDownload) -> Result<QueuedDownload, QueryError> {
+        self.insert_download(new)
+            .during(|| Operation::QueueDownload {
+                destination: new.destination.clone(),
+            })
+    }
+
+    fn insert_download(&mut self, new: &NewDownload) -> QueryResult<QueuedDownload> {
+        insert_into(download_queue::table)
+            .values((
+                download_queue::module_slug.eq(&new.slug),
+                download_queue::version.eq(&new.version),
+                download_queue::url.eq(JsonbValue::from(&new.url)),
+                download_queue::mirrors.eq(JsonbValue::from(new.mirrors.as_slice())),
+                download_queue::destination.eq(&new.destination),
+                download_queue::expected_size.eq(new.expected_size),
+                download_queue::sha1.eq(&new.sha1),
+                download_queue::sha256.eq(&new.sha256),
+                download_queue::queued_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .on_conflict(download_queue::destination)
+            .do_nothing()
+            .execute(&mut *self.connection)?;
+
+        download_queue::table
+            .filter(download_queue::destination.eq(&new.destination))
+            .select(QueuedDownload::as_select())
+            .get_result(&mut *self.connection)
+    }
+
+    /// Lists every download in the queue, in the order they were queued.
+    pub fn downloads(&mut self) -> Result<Vec<QueuedDownload>, QueryError> {
+        download_queue::table
+            .select(QueuedDownload::as_select())
+            .order_by(download_queue::download_id)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadDownloads)
+    }
+
+    pub fn download(&mut self, id: DownloadId) -> Result<Option<QueuedDownload>, QueryError> {
+        download_queue::table
+            .find(id)
+            .select(QueuedDownload::as_select())
+            .get_result(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadDownloads)
+    }
+
+    /// Marks the oldest queued download as downloading and returns it, or
+    /// returns `None` if nothing is waiting.
+    pub fn start_next_download(&mut self) -> Result<Option<QueuedDownload>, QueryError> {
+        self.connection
+            .immediate_transaction(|conn| {
+                let next = download_queue::table
+                    .filter(download_queue::state.eq(i32::from(DownloadState::Queued)))
+                    .select(QueuedDownload::as_select())
+                    .order_by(download_queue::download_id)
+                    .first(conn)
+                    .optional()?;
+
+                let Some(mut next) = next else {
+                    return Ok(None);
+                };
+
+                update(download_queue::table.find(next.id))
+                    .set(download_queue::state.eq(i32::from(DownloadState::Downloading)))
+                    .execute(conn)?;
+                next.state = DownloadState::Downloading;
+
+                Ok(Some(next))
+            })
+            .during(|| Operation::StartDownload)
+    }
+
+    /// Records how much of a download has been saved.
+    pub fn set_download_progress(
+        &mut self,
+        id: DownloadId,
+        bytes_downloaded: i64,
+    ) -> Result<(), QueryError> {
+        update(download_queue::table.find(id))
+            .set(download_queue::bytes_downloaded.eq(bytes_downloaded))
+            .execute(&mut *self.connection)
+            .during(|| Operation::UpdateDownload { download: id })?;
+
+        Ok(())
+    }
+
+    pub fn set_download_state(
+        &mut self,
+        id: DownloadId,
+        state: DownloadState,
+    ) -> Result<(), QueryError> {
+        update(download_queue::table.find(id))
+            .set(download_queue::state.eq(i32::from(state)))
+            .execute(&mut *self.connection)
+            .during(|| Operation::UpdateDownload { download: id })?;
+
+        Ok(())
+    }
+
+    /// Returns the URL which the partial file of a download came from, or
+    /// `None` if it hasn't been started.
+    pub fn partial_download_url(&mut self, id: DownloadId) -> Result<Option<Url>, QueryError> {
+        download_queue::table
+            .find(id)
+            .select(download_queue::partial_url)
+            .get_result::<JsonbValue>(&mut *self.connection)
+            .and_then(|url| {
+                Option::<Url>::try_from(url).map_err(|e| DeserializationError(e.into()))
+            })
+            .during(|| Operation::UpdateDownload { download: id })
+    }
+
+    /// Records which URL the partial file of a download is being downloaded
+    /// from.
+    pub fn set_partial_download_url(
+        &mut self,
+        id: DownloadId,
+        url: &Url,
+    ) -> Result<(), QueryError> {
+        update(download_queue::table.find(id))
+            .set(download_queue::partial_url.eq(JsonbValue::from(url)))
+            .execute(&mut *self.connection)
+            .during(|| Operation::UpdateDownload { download: id })?;
+
+        Ok(())
+    }
+
+    /// Marks a download as failed, recording why.
+    pub fn fail_download(&mut self, id: DownloadId, error: &str) -> Result<(), QueryError> {
+        update(download_queue::table.find(id))
+            .set((
+                download_queue::state.eq(i32::from(DownloadState::Failed)),
+                download_queue::error.eq(error),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::UpdateDownload { download: id })?;
+
+        Ok(())
+    }
+
+    /// Puts a failed download back in the queue. Returns whether the download
+    /// had failed.
+    pub fn retry_download(&mut self, id: DownloadId) -> Result<bool, QueryError> {
+        let updated = update(
+            download_queue::table
+                .find(id)
+                .filter(download_queue::state.eq(i32::from(DownloadState::Failed))),
+        )
+        .set((
+            download_queue::state.eq(i32::from(DownloadState::Queued)),
+            download_queue::error.eq(None::<String>),
+        ))
+        .execute(&mut *self.connection)
+        .during(|| Operation::UpdateDownload { download: id })?;
+
+        Ok(updated > 0)
+    }
+
+    /// Puts downloads which were interrupted, such as by the program exiting,
+    /// back in the queue so they can be resumed. Returns how many there were.
+    ///
+    /// This must only be called when no downloads are running.
+    pub fn resume_interrupted_downloads(&mut self) -> Result<usize, QueryError> {
+        let interrupted = [DownloadState::Downloading, DownloadState::Verifying].map(i32::from);
+
+        update(download_queue::table.filter(download_queue::state.eq_any(interrupted)))
+            .set(download_queue::state.eq(i32::from(DownloadState::Queued)))
+            .execute(&mut *self.connection)
+            .during(|| Operation::ResumeDownloads)
+    }
+
+    /// Removes a download from the queue, whatever state it's in. Returns
+    /// whether it was in the queue.
+    pub fn remove_download(&mut self, id: DownloadId) -> Result<bool, QueryError> {
+        let deleted = delete(download_queue::table.find(id))
+            .execute(&mut *self.connection)
+            .during(|| Operation::UpdateDownload { download: id })?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Removes every finished download from the queue. Returns how many were
+    /// removed.
+    pub fn clear_finished_downloads(&mut self) -> Result<usize, QueryError> {
+        delete(
+            download_queue::table.filter(download_queue::state.eq(i32::from(DownloadState::Done))),
+        )
+        .execute(&mut *self.connection)
+        .during(|| Operation::ClearDownloads)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::RepoManager;
+
+    fn new_download(slug: &str) -> NewDownload {
+        NewDownload {
+            slug: slug.into(),
+            version: "1.0".into(),
+            url: format!("https://example.com/{slug}.zip").parse().unwrap(),
+            mirrors: vec![],
+            destination: format!("/cache/{slug}-1.0.zip"),
+            expected_size: Some(1024),
+            sha1: None,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn queue_survives_interruption() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        let parallax = db.queue_download(&new_download("Parallax")).unwrap();
+        let scatterer = db.queue_download(&new_download("Scatterer")).unwrap();
+        assert_eq!(parallax.state, DownloadState::Queued);
+        // Queuing the same file again doesn't download it twice.
+        let again = db.queue_download(&new_download("Parallax")).unwrap();
+        assert_eq!(again.id, parallax.id);
+
+        let started = db.start_next_download().unwrap().unwrap();
+        assert_eq!(started.id, parallax.id);
+        assert_eq!(started.state, DownloadState::Downloading);
+        db.set_download_progress(parallax.id, 512).unwrap();
+
+        // The program exits partway through, then starts again.
+        assert_eq!(db.resume_interrupted_downloads().unwrap(), 1);
+        let resumed = db.start_next_download().unwrap().unwrap();
+        assert_eq!(resumed.id, parallax.id);
+        assert_eq!(resumed.bytes_downloaded, 512);
+
+        db.set_download_state(parallax.id, DownloadState::Done)
+            .unwrap();
+        assert_eq!(db.start_next_download().unwrap().unwrap().id, scatterer.id);
+        assert!(db.start_next_download().unwrap().is_none());
+    }
+
+    #[test]
+    fn failed_downloads_wait_for_retry() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        let queued = db.queue_download(&new_download("Parallax")).unwrap();
+        db.start_next_download().unwrap();
+        db.fail_download(queued.id, "connection reset").unwrap();
+
+        assert_eq!(db.resume_interrupted_downloads().unwrap(), 0);
+        assert!(db.start_next_download().unwrap().is_none());
+        let failed = db.download(queued.id).unwrap().unwrap();
+        assert_eq!(failed.state, DownloadState::Failed);
+        assert_eq!(failed.error.as_deref(), Some("connection reset"));
+
+        assert!(db.retry_download(queued.id).unwrap());
+        assert!(!db.retry_download(queued.id).unwrap());
+        let retried = db.start_next_download().unwrap().unwrap();
+        assert_eq!(retried.error, None);
+
+        db.set_download_state(queued.id, DownloadState::Done)
+            .unwrap();
+        assert_eq!(db.clear_finished_downloads().unwrap(), 1);
+        assert!(db.downloads().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+//! Errors which say what the database was doing when a request failed.
+
+use std::fmt::{self, Display, Formatter};
+
+use diesel::{QueryResult, result::DatabaseErrorKind};
+use miette::Diagnostic;
+use thiserror::Error;
+use url::Url;
+
+use crate::database::{DownloadId, Metric, ModuleId, ReleaseId, RepoId, is_busy};
+
+/// A database request which failed, along with the operation it was part of.
+#[derive(Debug, Error, Diagnostic)]
+#[error("failed to {operation}")]
+#[diagnostic(code(camrete::database::query_failure))]
+pub struct QueryError {
+    /// Boxed, since some operations hold a URL, which would make every
+    /// result carrying this error much larger.
+    pub operation: Box<Operation>,
+    #[source]
+    pub source: diesel::result::Error,
+}
+
+impl QueryError {
+    /// Returns whether the request failed because another connection was
+    /// using the database.
+    pub fn is_busy(&self) -> bool {
+        is_busy(&self.source)
+    }
+
+    /// Returns whether the request failed because it would have replaced
+    /// something with the same name, such as a repository.
+    pub fn is_conflict(&self) -> bool {
+        matches!(
+            self.source,
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)
+        )
+    }
+}
+
+/// An operation performed on the database, with the keys it was performed
+/// on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    LoadRepos,
+    CreateRepo { name: String },
+    SetRepoFilter { repo: RepoId },
+    SetRepoPriority { repo: RepoId },
+    LoadRepoTerms { repo: RepoId },
+    SaveRepoTerms { repo: RepoId },
+    SetRepoUrl { repo: RepoId },
+    SetRepoChannel { repo: RepoId },
+    RecordRedirect { repo: RepoId },
+    RepoHealth { name: String },
+    AddRepoRef { repo: RepoId, name: String },
+    HashRepo { repo: RepoId },
+    LockUpdate { repo: RepoId },
+    UnlockUpdate { repo: RepoId },
+    SetEtag { url: Url },
+    LoadEtag { url: Url },
+    LoadRepoSuggestions,
+    RegisterModule { slug: String },
+    RegisterAuthor { name: String },
+    PruneAuthors,
+    CreateRelease { slug: String, version: String },
+    RegisterBuilds,
+    AddDownloadCounts { repo: RepoId },
+    LatestRelease { module: ModuleId },
+    FindUpgrades,
+    ModuleSummaries { limit: u32 },
+    ReleasePage { module: ModuleId, limit: u32 },
+    RepoPage { limit: u32 },
+    IndexSearch,
+    SearchModules { query: String },
+    VerifyDerived,
+    RepairDerived,
+    LoadModuleDetails,
+    FindModule { slug: String },
+    LoadReleases { module: ModuleId },
+    LoadReleaseDetails { release: ReleaseId },
+    YankRelease { slug: String, version: String },
+    LoadYanks { slug: String },
+    RecordHistory { repo: RepoId },
+    LoadHistory { slug: String },
+    ChangeFavorite { slug: String },
+    LoadFavorites,
+    SaveNote { slug: String },
+    LoadNotes,
+    /// Reading a setting, either globally or for one module.
+    LoadSetting {
+        name: &'static str,
+        slug: Option<String>,
+    },
+    /// Changing a setting, either globally or for one module.
+    ChangeSetting {
+        name: &'static str,
+        slug: Option<String>,
+    },
+    LoadGraph,
+    FilterModules,
+    LoadSavedFilters,
+    SaveFilter { name: String },
+    DeleteSavedFilter { name: String },
+    QueueDownload { destination: String },
+    LoadDownloads,
+    StartDownload,
+    UpdateDownload { download: DownloadId },
+    ResumeDownloads,
+    ClearDownloads,
+    RecordMetric { metric: Metric },
+    LoadMetrics,
+    ResetMetrics,
+    Compatibility { module: ModuleId },
+    RecordMirror { url: String },
+    LoadMirrors,
+    LoadTags,
+    LoadReleaseNotes { repo: String, version: String },
+    SaveReleaseNotes { repo: String, version: String },
+    RecordInstalled,
+    LoadInstalled,
+    FindRelease { slug: String, version: String },
+    TakeSnapshot,
+    ValidateReleases,
+    LoadValidations,
+    PlanChanges,
+    LoadBackfills,
+    RunBackfill { name: &'static str },
+    QueryJson { name: &'static str },
+    LoadResumableUpdate { repo: RepoId },
+    SaveCheckpoints { repo: RepoId },
+    UpdateStaleness,
+    LoadStaleness,
+    RecordBugtrackerCheck { slug: String },
+    LoadRepoCommit { repo: RepoId },
+    SaveRepoCommit { repo: RepoId },
+    DeleteRelease { slug: String, version: String },
+    CheckPolicy,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LoadRepos => write!(f, "load the repository list"),
+            Self::CreateRepo { name } => write!(f, "create repository {name:?}"),
+            Self::SetRepoFilter { repo } => {
+                write!(f, "change the filter of repository #{}", repo.get())
+            }
+            Self::SetRepoPriority { repo } => {
+                write!(f, "change the priority of repository #{}", repo.get())
+            }
+            Self::LoadRepoTerms { repo } => {
+                write!(f, "load the terms of repository #{}", repo.get())
+            }
+            Self::SaveRepoTerms { repo } => {
+                write!(f, "save the terms of repository #{}", repo.get())
+            }
+            Self::SetRepoUrl { repo } => {
+                write!(f, "change the URL of repository #{}", repo.get())
+            }
+            Self::SetRepoChannel { repo } => {
+                write!(f, "change the channel of repository #{}", repo.get())
+            }
+            Self::RecordRedirect { repo } => {
+                write!(f, "record where repository #{} redirects", repo.get())
+            }
+            Self::RepoHealth { name } => write!(f, "inspect repository {name:?}"),
+            Self::AddRepoRef { repo, name } => {
+                write!(f, "save reference {name:?} of repository #{}", repo.get())
+            }
+            Self::HashRepo { repo } => {
+                write!(f, "hash the releases of repository #{}", repo.get())
+            }
+            Self::LockUpdate { repo } => {
+                write!(f, "lock repository #{} for updating", repo.get())
+            }
+            Self::UnlockUpdate { repo } => {
+                write!(f, "unlock repository #{} after updating", repo.get())
+            }
+            Self::SetEtag { url } => write!(f, "save the ETag of {url}"),
+            Self::LoadEtag { url } => write!(f, "load the ETag of {url}"),
+            Self::LoadRepoSuggestions => write!(f, "list the suggested repositories"),
+            Self::RegisterModule { slug } => write!(f, "register module {slug:?}"),
+            Self::RegisterAuthor { name } => write!(f, "register author {name:?}"),
+            Self::PruneAuthors => write!(f, "remove uncredited authors"),
+            Self::CreateRelease { slug, version } => {
+                write!(f, "save release {version:?} of module {slug:?}")
+            }
+            Self::RegisterBuilds => write!(f, "save the game's build IDs"),
+            Self::AddDownloadCounts { repo } => {
+                write!(f, "save download counts for repository #{}", repo.get())
+            }
+            Self::LatestRelease { module } => {
+                write!(f, "find the latest release of module #{}", module.get())
+            }
+            Self::FindUpgrades => write!(f, "find newer releases of the installed modules"),
+            Self::ModuleSummaries { limit } => write!(f, "list a page of {limit} modules"),
+            Self::ReleasePage { module, limit } => {
+                write!(
+                    f,
+                    "list a page of {limit} releases of module #{}",
+                    module.get()
+                )
+            }
+            Self::RepoPage { limit } => write!(f, "list a page of {limit} repositories"),
+            Self::IndexSearch => write!(f, "index the modules for searching"),
+            Self::SearchModules { query } => write!(f, "search the modules for {query:?}"),
+            Self::VerifyDerived => write!(f, "check the values worked out from others"),
+            Self::RepairDerived => write!(f, "repair the values worked out from others"),
+            Self::LoadModuleDetails => write!(f, "load the details of the selected modules"),
+            Self::FindModule { slug } => write!(f, "find module {slug:?}"),
+            Self::LoadReleases { module } => {
+                write!(f, "list the releases of module #{}", module.get())
+            }
+            Self::LoadReleaseDetails { release } => {
+                write!(f, "load the details of release #{}", release.get())
+            }
+            Self::YankRelease { slug, version } => {
+                write!(f, "change whether release {version:?} of module {slug:?} is yanked")
+            }
+            Self::LoadYanks { slug } => write!(f, "list the yanked releases of module {slug:?}"),
+            Self::RecordHistory { repo } => {
+                write!(f, "record the release history of repository #{}", repo.get())
+            }
+            Self::LoadHistory { slug } => write!(f, "load the release history of module {slug:?}"),
+            Self::ChangeFavorite { slug } => {
+                write!(f, "change whether module {slug:?} is a favorite")
+            }
+            Self::LoadFavorites => write!(f, "list the favorite modules"),
+            Self::SaveNote { slug } => write!(f, "save the note about module {slug:?}"),
+            Self::LoadNotes => write!(f, "load the notes about modules"),
+            Self::LoadSetting { name, slug: None } => write!(f, "read setting {name:?}"),
+            Self::LoadSetting {
+                name,
+                slug: Some(slug),
+            } => write!(f, "read setting {name:?} for module {slug:?}"),
+            Self::ChangeSetting { name, slug: None } => write!(f, "change setting {name:?}"),
+            Self::ChangeSetting {
+                name,
+                slug: Some(slug),
+            } => write!(f, "change setting {name:?} for module {slug:?}"),
+            Self::LoadGraph => write!(f, "load the relationship graph"),
+            Self::FilterModules => write!(f, "search the module list"),
+            Self::LoadSavedFilters => write!(f, "load the saved filters"),
+            Self::SaveFilter { name } => write!(f, "save filter {name:?}"),
+            Self::DeleteSavedFilter { name } => write!(f, "delete filter {name:?}"),
+            Self::QueueDownload { destination } => {
+                write!(f, "queue the download of {destination:?}")
+            }
+            Self::LoadDownloads => write!(f, "load the download queue"),
+            Self::StartDownload => write!(f, "start the next queued download"),
+            Self::UpdateDownload { download } => {
+                write!(f, "update queued download #{}", download.get())
+            }
+            Self::ResumeDownloads => write!(f, "resume interrupted downloads"),
+            Self::ClearDownloads => write!(f, "clear finished downloads"),
+            Self::RecordMetric { metric } => write!(f, "record usage metric {:?}", metric.name()),
+            Self::LoadMetrics => write!(f, "load the usage metrics"),
+            Self::ResetMetrics => write!(f, "reset the usage metrics"),
+            Self::Compatibility { module } => {
+                write!(f, "check the game compatibility of module #{}", module.get())
+            }
+            Self::RecordMirror { url } => write!(f, "record the result of downloading {url}"),
+            Self::LoadMirrors => write!(f, "load the download history of a file's mirrors"),
+            Self::LoadTags => write!(f, "list the tags used by modules"),
+            Self::LoadReleaseNotes { repo, version } => {
+                write!(f, "load the release notes of {repo} {version:?}")
+            }
+            Self::SaveReleaseNotes { repo, version } => {
+                write!(f, "save the release notes of {repo} {version:?}")
+            }
+            Self::RecordInstalled => write!(f, "record which modules are installed"),
+            Self::LoadInstalled => write!(f, "load the installed modules"),
+            Self::FindRelease { slug, version } => {
+                write!(f, "find release {version:?} of module {slug:?}")
+            }
+            Self::TakeSnapshot => write!(f, "take a snapshot of the database"),
+            Self::ValidateReleases => write!(f, "check the releases' metadata"),
+            Self::LoadValidations => write!(f, "load the problems found in releases"),
+            Self::PlanChanges => write!(f, "work out what installing the chosen releases changes"),
+            Self::LoadBackfills => write!(f, "load the finished backfills"),
+            Self::RunBackfill { name } => {
+                write!(f, "fill in {name:?} for releases saved by an older version")
+            }
+            Self::QueryJson { name } => write!(f, "run query {name:?}"),
+            Self::LoadResumableUpdate { repo } => {
+                write!(f, "load the unfinished update of repository #{}", repo.get())
+            }
+            Self::SaveCheckpoints { repo } => {
+                write!(f, "record the progress of updating repository #{}", repo.get())
+            }
+            Self::UpdateStaleness => write!(f, "work out which modules are stale"),
+            Self::LoadStaleness => write!(f, "load which modules are stale"),
+            Self::RecordBugtrackerCheck { slug } => {
+                write!(f, "record whether the bug tracker of module {slug:?} can be reached")
+            }
+            Self::LoadRepoCommit { repo } => {
+                write!(f, "load the commit repository #{} was updated from", repo.get())
+            }
+            Self::SaveRepoCommit { repo } => {
+                write!(f, "record the commit repository #{} was updated from", repo.get())
+            }
+            Self::DeleteRelease { slug, version } => {
+                write!(f, "delete release {version:?} of module {slug:?}")
+            }
+            Self::CheckPolicy => write!(f, "check the releases against the policy"),
+        }
+    }
+}
+
+pub(crate) trait QueryContext<T> {
+    /// Records which operation a request was part of, if it failed. The
+    /// operation is only built when there's an error.
+    fn during(self, operation: impl FnOnce() -> Operation) -> Result<T, QueryError>;
+}
+
+impl<T> QueryContext<T> for QueryResult<T> {
+    fn during(self, operation: impl FnOnce() -> Operation) -> Result<T, QueryError> {
+        self.map_err(|source| QueryError {
+            operation: Box::new(operation()),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::{RunQueryDsl, sql_query};
+
+    use super::*;
+    use crate::repo::RepoManager;
+
+    #[test]
+    fn names_the_failed_operation() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+        sql_query("DROP TABLE module_settings")
+            .execute(&mut *db.connection)
+            .unwrap();
+
+        let err = db.set_module_prerelease("Parallax", Some(true)).unwrap_err();
+        assert_eq!(
+            *err.operation,
+            Operation::ChangeSetting {
+                name: "allow_prerelease",
+                slug: Some("Parallax".into()),
+            }
+        );
+        assert!(!err.is_busy());
+        assert_eq!(
+            err.to_string(),
+            "failed to change setting \"allow_prerelease\" for module \"Parallax\""
+        );
+    }
+}
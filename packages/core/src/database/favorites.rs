@@ -0,0 +1,153 @@
+//! Modules the user has marked as favorites or written notes about.
+//!
+//! Both are only stored in the local database, and are keyed by module
+//! identifier, so they're kept across repository updates and apply to a module
+//! whichever repository provides it. Module summaries and search results say
+//! whether each module is a favorite or has a note.
+
+use std::ops::DerefMut;
+
+use diesel::{delete, insert_into, prelude::*, replace_into};
+use time::OffsetDateTime;
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, schema::*};
+
+/// A module the user has marked as a favorite.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, uniffi::Record)]
+#[diesel(table_name = favorites)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Favorite {
+    #[diesel(column_name = module_slug)]
+    pub slug: String,
+    pub added_at: OffsetDateTime,
+}
+
+/// A note the user has written about a module.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, uniffi::Record)]
+#[diesel(table_name = module_notes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ModuleNote {
+    #[diesel(column_name = module_slug)]
+    pub slug: String,
+    pub note: String,
+    pub updated_at: OffsetDateTime,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Marks a module as a favorite. The module doesn't have to be in the
+    /// database. Returns whether it wasn't already a favorite.
+    pub fn add_favorite(&mut self, slug: &str) -> Result<bool, QueryError> {
+        let added = insert_into(favorites::table)
+            .values((
+                favorites::module_slug.eq(slug),
+                favorites::added_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&mut *self.connection)
+            .during(|| Operation::ChangeFavorite { slug: slug.into() })?;
+
+        Ok(added > 0)
+    }
+
+    /// Stops a module being a favorite. Returns whether it was one.
+    pub fn remove_favorite(&mut self, slug: &str) -> Result<bool, QueryError> {
+        let deleted = delete(favorites::table.find(slug))
+            .execute(&mut *self.connection)
+            .during(|| Operation::ChangeFavorite { slug: slug.into() })?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Every favorite module, ordered by identifier.
+    pub fn favorites(&mut self) -> Result<Vec<Favorite>, QueryError> {
+        favorites::table
+            .select(Favorite::as_select())
+            .order_by(favorites::module_slug)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadFavorites)
+    }
+
+    /// Replaces the note about a module. An empty note, or `None`, removes it.
+    pub fn set_module_note(&mut self, slug: &str, note: Option<&str>) -> Result<(), QueryError> {
+        let operation = || Operation::SaveNote { slug: slug.into() };
+
+        match note.map(str::trim).filter(|note| !note.is_empty()) {
+            Some(note) => replace_into(module_notes::table)
+                .values((
+                    module_notes::module_slug.eq(slug),
+                    module_notes::note.eq(note),
+                    module_notes::updated_at.eq(OffsetDateTime::now_utc()),
+                ))
+                .execute(&mut *self.connection)
+                .during(operation)?,
+            None => delete(module_notes::table.find(slug))
+                .execute(&mut *self.connection)
+                .during(operation)?,
+        };
+
+        Ok(())
+    }
+
+    /// The note about a module, if there is one.
+    pub fn module_note(&mut self, slug: &str) -> Result<Option<ModuleNote>, QueryError> {
+        module_notes::table
+            .find(slug)
+            .select(ModuleNote::as_select())
+            .get_result(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadNotes)
+    }
+
+    /// Every note, ordered by module identifier.
+    pub fn module_notes(&mut self) -> Result<Vec<ModuleNote>, QueryError> {
+        module_notes::table
+            .select(ModuleNote::as_select())
+            .order_by(module_notes::module_slug)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadNotes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        database::CancellationToken,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn favorites_and_notes_are_flagged() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let release = release("Parallax", "1.0")
+            .set("abstract", "Terrain scatter")
+            .build();
+        db.create_release(&release, repo.id, None).unwrap();
+        db.rebuild_search_index().unwrap();
+
+        assert!(db.add_favorite("Parallax").unwrap());
+        assert!(!db.add_favorite("Parallax").unwrap());
+        db.set_module_note("Parallax", Some("Needs Kopernicus"))
+            .unwrap();
+
+        let summary = db.module_summaries(None, 10).unwrap().modules.remove(0);
+        assert!(summary.favorite && summary.has_note);
+        let result = db
+            .search_modules("scatter", 10, &CancellationToken::new())
+            .unwrap()
+            .remove(0);
+        assert!(result.favorite && result.has_note);
+        assert_eq!(
+            db.module_note("Parallax").unwrap().unwrap().note,
+            "Needs Kopernicus"
+        );
+
+        assert!(db.remove_favorite("Parallax").unwrap());
+        db.set_module_note("Parallax", Some("  ")).unwrap();
+        assert!(db.favorites().unwrap().is_empty());
+        assert!(db.module_notes().unwrap().is_empty());
+
+        let summary = db.module_summaries(None, 10).unwrap().modules.remove(0);
+        assert!(!summary.favorite && !summary.has_note);
+    }
+}
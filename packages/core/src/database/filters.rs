@@ -0,0 +1,636 @@
+//! Searches of the module list, which can be saved by name.
+//!
+//! A [`ModuleFilter`] is written like `tag:parts -tag:graphics author:Linx`,
+//! and is translated into SQL by Camrete rather than by the user, so saved
+//! filters can't run arbitrary queries.
+
+use std::{
+    fmt::{self, Display, Formatter, Write},
+    iter::Peekable,
+    mem,
+    ops::DerefMut,
+    str::{Chars, FromStr},
+};
+
+use diesel::{
+    dsl::{self, sql},
+    prelude::*,
+    replace_into,
+    result::Error::DeserializationError,
+    sql_types::{Bool, Integer},
+    sqlite::Sqlite,
+};
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    database::{
+        JsonbValue, Operation, QueryContext, QueryError, RepoDB,
+        models::{
+            Module,
+            module::{RelationshipType, escape_like},
+        },
+        schema::*,
+    },
+    json::ModuleKind,
+};
+
+#[derive(Debug, Error, Diagnostic, PartialEq, Eq)]
+pub enum FilterParseError {
+    #[error("unknown filter `{key}:`")]
+    #[diagnostic(
+        code(camrete::filter::unknown_key),
+        severity(Warning),
+        help(
+            "use `tag:`, `author:`, `kind:`, `repo:`, `text:` or a relationship like \
+            `depends:`, or put text in quotes"
+        )
+    )]
+    UnknownKey { key: String },
+    #[error("`{key}:` needs a value")]
+    #[diagnostic(code(camrete::filter::missing_value), severity(Warning))]
+    MissingValue { key: String },
+    #[error("{value:?} is not a kind of module")]
+    #[diagnostic(
+        code(camrete::filter::invalid_kind),
+        severity(Warning),
+        help("use `package`, `metapackage` or `dlc`")
+    )]
+    InvalidKind { value: String },
+    #[error("a quote in the filter is never closed")]
+    #[diagnostic(code(camrete::filter::unclosed_quote), severity(Warning))]
+    UnclosedQuote,
+}
+
+/// Something which a module can be searched for. Modules match if any of
+/// their releases match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Enum)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterCondition {
+    /// Releases with this tag.
+    Tag { tag: String },
+    /// Releases credited to this author, ignoring case.
+    Author { name: String },
+    /// Releases of this kind.
+    Kind { kind: ModuleKind },
+    /// Modules from the repository with this name.
+    Repo { name: String },
+    /// Modules whose slug, or a release's name or summary, contains this text,
+    /// ignoring case.
+    Text { text: String },
+    /// Modules whose newest release has a relationship of this type with the
+    /// module or virtual module named `target`, such as those which depend on
+    /// `ModuleManager`. Unlike other conditions, older releases don't count.
+    Relationship {
+        rel_type: RelationshipType,
+        target: String,
+    },
+}
+
+/// A condition which modules must meet, or must not meet if it's negated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct FilterTerm {
+    #[serde(default)]
+    pub negated: bool,
+    pub condition: FilterCondition,
+}
+
+/// A search of the module list. Modules are listed if they meet every term.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct ModuleFilter {
+    pub terms: Vec<FilterTerm>,
+}
+
+/// A filter which the user saved.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct SavedFilter {
+    pub name: String,
+    pub filter: ModuleFilter,
+}
+
+type ModuleCondition = Box<dyn BoxableExpression<modules::table, Sqlite, SqlType = Bool>>;
+
+/// The newest release of the module of the release being filtered, ordered
+/// like `ModuleRelease::by_version`.
+const NEWEST_RELEASE: &str = "(
+    SELECT latest.release_id FROM module_releases latest
+    WHERE latest.module_id = module_releases.module_id
+    ORDER BY latest.sort_key DESC, latest.version
+    LIMIT 1
+)";
+
+/// The names of relationship types in filters, like `depends:`.
+const RELATIONSHIP_KEYS: [(&str, RelationshipType); 6] = [
+    ("depends", RelationshipType::Depends),
+    ("recommends", RelationshipType::Recommends),
+    ("suggests", RelationshipType::Suggests),
+    ("supports", RelationshipType::Supports),
+    ("conflicts", RelationshipType::Conflicts),
+    ("provides", RelationshipType::Provides),
+];
+
+impl FilterCondition {
+    fn to_sql(&self) -> ModuleCondition {
+        match self {
+            Self::Tag { tag } => Box::new(
+                modules::module_id.eq_any(
+                    module_releases::table
+                        .inner_join(module_tags::table)
+                        .filter(module_tags::tag.eq(tag.clone()))
+                        .select(module_releases::module_id),
+                ),
+            ),
+            Self::Author { name } => Box::new(
+                modules::module_id.eq_any(
+                    module_releases::table
+                        .inner_join(module_authors::table.inner_join(authors::table))
+                        .filter(authors::name.eq(name.clone()))
+                        .select(module_releases::module_id),
+                ),
+            ),
+            Self::Kind { kind } => Box::new(
+                modules::module_id.eq_any(
+                    module_releases::table
+                        .filter(module_releases::kind.eq(i32::from(*kind)))
+                        .select(module_releases::module_id),
+                ),
+            ),
+            Self::Repo { name } => Box::new(
+                modules::repo_id.eq_any(
+                    repositories::table
+                        .filter(repositories::name.eq(name.clone()))
+                        .select(repositories::repo_id),
+                ),
+            ),
+            Self::Text { text } => {
+                let pattern = format!("%{}%", escape_like(text));
+                let releases = module_releases::table
+                    .filter(
+                        module_releases::display_name
+                            .like(pattern.clone())
+                            .escape('\\')
+                            .or(module_releases::summary.like(pattern.clone()).escape('\\')),
+                    )
+                    .select(module_releases::module_id);
+
+                Box::new(
+                    modules::module_slug
+                        .like(pattern)
+                        .escape('\\')
+                        .or(modules::module_id.eq_any(releases)),
+                )
+            }
+            Self::Relationship { rel_type, target } => Box::new(
+                modules::module_id.eq_any(
+                    module_releases::table
+                        .inner_join(
+                            module_relationship_groups::table
+                                .inner_join(module_relationships::table),
+                        )
+                        .filter(module_relationships::target_name.eq(target.clone()))
+                        .filter(module_relationship_groups::rel_type.eq(*rel_type))
+                        .filter(module_releases::release_id.eq(sql::<Integer>(NEWEST_RELEASE)))
+                        .select(module_releases::module_id),
+                ),
+            ),
+        }
+    }
+}
+
+impl ModuleFilter {
+    pub(super) fn to_sql(&self) -> ModuleCondition {
+        let all: ModuleCondition = Box::new(true.into_sql::<Bool>());
+
+        self.terms.iter().fold(all, |all, term| {
+            let condition = term.condition.to_sql();
+            if term.negated {
+                Box::new(all.and(dsl::not(condition)))
+            } else {
+                Box::new(all.and(condition))
+            }
+        })
+    }
+}
+
+impl FromStr for ModuleFilter {
+    type Err = FilterParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut terms = vec![];
+        let mut chars = text.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let negated = chars.next_if_eq(&'-').is_some();
+            let mut key = None;
+            let mut value = String::new();
+            let mut quoted = false;
+
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                match c {
+                    '"' => {
+                        quoted = true;
+                        read_quoted(&mut chars, &mut value)?;
+                    }
+                    ':' if key.is_none() && !quoted => key = Some(mem::take(&mut value)),
+                    c => value.push(c),
+                }
+            }
+
+            terms.push(FilterTerm {
+                negated,
+                condition: parse_condition(key, value)?,
+            });
+        }
+
+        Ok(Self { terms })
+    }
+}
+
+/// Reads the rest of a quoted value, after the opening quote.
+fn read_quoted(chars: &mut Peekable<Chars>, value: &mut String) -> Result<(), FilterParseError> {
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(()),
+            Some('\\') => value.push(chars.next().ok_or(FilterParseError::UnclosedQuote)?),
+            Some(c) => value.push(c),
+            None => return Err(FilterParseError::UnclosedQuote),
+        }
+    }
+}
+
+fn parse_condition(
+    key: Option<String>,
+    value: String,
+) -> Result<FilterCondition, FilterParseError> {
+    let key = key.unwrap_or_else(|| "text".into());
+    if value.is_empty() {
+        return Err(FilterParseError::MissingValue { key });
+    }
+
+    Ok(match key.as_str() {
+        "tag" => FilterCondition::Tag { tag: value },
+        "author" => FilterCondition::Author { name: value },
+        "kind" => FilterCondition::Kind {
+            kind: match value.to_lowercase().as_str() {
+                "package" => ModuleKind::Package,
+                "metapackage" => ModuleKind::Metapackage,
+                "dlc" => ModuleKind::Dlc,
+                _ => return Err(FilterParseError::InvalidKind { value }),
+            },
+        },
+        "repo" => FilterCondition::Repo { name: value },
+        "text" => FilterCondition::Text { text: value },
+        key => match RELATIONSHIP_KEYS.iter().find(|(name, _)| *name == key) {
+            Some(&(_, rel_type)) => FilterCondition::Relationship {
+                rel_type,
+                target: value,
+            },
+            None => return Err(FilterParseError::UnknownKey { key: key.into() }),
+        },
+    })
+}
+
+/// Writes a value so that [`ModuleFilter::from_str`] reads it back unchanged.
+fn write_value(f: &mut Formatter<'_>, value: &str) -> fmt::Result {
+    let needs_quotes = value.is_empty()
+        || value.starts_with('-')
+        || value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\\' | ':'));
+    if !needs_quotes {
+        return f.write_str(value);
+    }
+
+    f.write_char('"')?;
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+    }
+    f.write_char('"')
+}
+
+impl Display for FilterTerm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            f.write_char('-')?;
+        }
+
+        match &self.condition {
+            FilterCondition::Tag { tag } => {
+                f.write_str("tag:")?;
+                write_value(f, tag)
+            }
+            FilterCondition::Author { name } => {
+                f.write_str("author:")?;
+                write_value(f, name)
+            }
+            FilterCondition::Kind { kind } => {
+                write!(f, "kind:{}", format!("{kind:?}").to_lowercase())
+            }
+            FilterCondition::Repo { name } => {
+                f.write_str("repo:")?;
+                write_value(f, name)
+            }
+            FilterCondition::Text { text } => write_value(f, text),
+            FilterCondition::Relationship { rel_type, target } => {
+                let (key, _) = RELATIONSHIP_KEYS
+                    .iter()
+                    .find(|(_, t)| t == rel_type)
+                    .expect("every relationship type has a key");
+                write!(f, "{key}:")?;
+                write_value(f, target)
+            }
+        }
+    }
+}
+
+impl Display for ModuleFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            if i > 0 {
+                f.write_char(' ')?;
+            }
+            write!(f, "{term}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Lists the modules which match a filter, ordered by slug.
+    pub fn filtered_modules(&mut self, filter: &ModuleFilter) -> Result<Vec<Module>, QueryError> {
+        Module::all()
+            .filter(filter.to_sql())
+            .order_by(Module::by_slug())
+            .load(&mut *self.connection)
+            .during(|| Operation::FilterModules)
+    }
+
+    /// Lists the modules whose newest release has a relationship of the given
+    /// type with `target`, ordered by slug.
+    pub fn modules_with_relationship(
+        &mut self,
+        rel_type: RelationshipType,
+        target: &str,
+    ) -> Result<Vec<Module>, QueryError> {
+        self.filtered_modules(&ModuleFilter {
+            terms: vec![FilterTerm {
+                negated: false,
+                condition: FilterCondition::Relationship {
+                    rel_type,
+                    target: target.into(),
+                },
+            }],
+        })
+    }
+
+    /// Lists the saved filters, ordered by name.
+    pub fn saved_filters(&mut self) -> Result<Vec<SavedFilter>, QueryError> {
+        self.load_saved_filters()
+            .during(|| Operation::LoadSavedFilters)
+    }
+
+    fn load_saved_filters(&mut self) -> QueryResult<Vec<SavedFilter>> {
+        saved_filters::table
+            .select((saved_filters::name, saved_filters::filter))
+            .order_by(saved_filters::name)
+            .load::<(String, JsonbValue)>(&mut *self.connection)?
+            .into_iter()
+            .map(|(name, filter)| {
+                let filter = filter
+                    .try_into()
+                    .map_err(|e| DeserializationError(Box::new(e)))?;
+                Ok(SavedFilter { name, filter })
+            })
+            .collect()
+    }
+
+    /// Returns the filter saved with the given name, if there is one.
+    pub fn saved_filter(&mut self, name: &str) -> Result<Option<ModuleFilter>, QueryError> {
+        let filter = self
+            .saved_filters()?
+            .into_iter()
+            .find(|saved| saved.name == name);
+
+        Ok(filter.map(|saved| saved.filter))
+    }
+
+    /// Saves a filter, replacing any other filter with the same name.
+    pub fn save_filter(&mut self, name: &str, filter: &ModuleFilter) -> Result<(), QueryError> {
+        replace_into(saved_filters::table)
+            .values((
+                saved_filters::name.eq(name),
+                saved_filters::filter.eq(JsonbValue::from(filter)),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SaveFilter { name: name.into() })?;
+
+        Ok(())
+    }
+
+    /// Deletes a saved filter. Returns whether there was a filter with the
+    /// given name.
+    pub fn delete_saved_filter(&mut self, name: &str) -> Result<bool, QueryError> {
+        let deleted = diesel::delete(saved_filters::table.find(name))
+            .execute(&mut *self.connection)
+            .during(|| Operation::DeleteSavedFilter { name: name.into() })?;
+
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::{
+        RepoManager,
+        test_support::{db_with_default_repo, release},
+    };
+
+    fn slugs(modules: Vec<Module>) -> Vec<String> {
+        modules.into_iter().map(|m| m.slug).collect()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let filter: ModuleFilter = r#"tag:parts -author:"Some One" kind:DLC "KSP:1.12" -tanks"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            filter.terms,
+            [
+                FilterTerm {
+                    negated: false,
+                    condition: FilterCondition::Tag {
+                        tag: "parts".into()
+                    },
+                },
+                FilterTerm {
+                    negated: true,
+                    condition: FilterCondition::Author {
+                        name: "Some One".into()
+                    },
+                },
+                FilterTerm {
+                    negated: false,
+                    condition: FilterCondition::Kind {
+                        kind: ModuleKind::Dlc
+                    },
+                },
+                FilterTerm {
+                    negated: false,
+                    condition: FilterCondition::Text {
+                        text: "KSP:1.12".into()
+                    },
+                },
+                FilterTerm {
+                    negated: true,
+                    condition: FilterCondition::Text {
+                        text: "tanks".into()
+                    },
+                },
+            ]
+        );
+
+        let text = filter.to_string();
+        assert_eq!(
+            text,
+            r#"tag:parts -author:"Some One" kind:dlc "KSP:1.12" -tanks"#
+        );
+        assert_eq!(text.parse::<ModuleFilter>().unwrap(), filter);
+        assert_eq!("".parse::<ModuleFilter>().unwrap(), ModuleFilter::default());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |text: &str| text.parse::<ModuleFilter>().unwrap_err();
+
+        assert_eq!(
+            parse("version:1.0"),
+            FilterParseError::UnknownKey {
+                key: "version".into()
+            }
+        );
+        assert_eq!(
+            parse("tag:"),
+            FilterParseError::MissingValue { key: "tag".into() }
+        );
+        assert_eq!(
+            parse("kind:mod"),
+            FilterParseError::InvalidKind {
+                value: "mod".into()
+            }
+        );
+        assert_eq!(parse(r#"author:"Linx"#), FilterParseError::UnclosedQuote);
+    }
+
+    #[test]
+    fn filter_modules() {
+        let (mut db, repo) = db_with_default_repo();
+
+        for (slug, author, tags, kind) in [
+            ("BoxTanks", "Linx", &["parts", "resources"][..], "package"),
+            ("Shiny", "Linx", &["graphics"], "package"),
+            ("Engines", "Someone", &["parts"], "package"),
+            ("MakingHistory", "Squad", &[], "dlc"),
+        ] {
+            let module = release(slug, "1.0")
+                .merge(json!({
+                    "name": format!("{slug} Mod"),
+                    "abstract": "Adds things",
+                    "author": author,
+                    "tags": tags,
+                    "kind": kind,
+                }))
+                .build();
+            db.create_release(&module, repo.id, None).unwrap();
+        }
+
+        let mut search = |text: &str| slugs(db.filtered_modules(&text.parse().unwrap()).unwrap());
+
+        assert_eq!(
+            search(""),
+            ["BoxTanks", "Engines", "MakingHistory", "Shiny"]
+        );
+        assert_eq!(search("tag:parts"), ["BoxTanks", "Engines"]);
+        assert_eq!(search("tag:parts -author:linx"), ["Engines"]);
+        assert_eq!(search("kind:dlc"), ["MakingHistory"]);
+        assert_eq!(search("-kind:dlc repo:KSP-default tank"), ["BoxTanks"]);
+        assert_eq!(search("repo:missing"), [] as [&str; 0]);
+        // Wildcards are matched literally.
+        assert_eq!(search("%"), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn filter_by_relationship() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let depends_on_mm = json!([{ "name": "ModuleManager" }]);
+
+        let (boxtanks, _) = db
+            .create_release(
+                &release("BoxTanks", "1.0")
+                    .set("depends", depends_on_mm.clone())
+                    .build(),
+                repo.id,
+                None,
+            )
+            .unwrap();
+        // The newest release no longer needs Module Manager.
+        db.create_release(&release("BoxTanks", "2.0").build(), repo.id, Some(boxtanks))
+            .unwrap();
+        db.create_release(
+            &release("Engines", "1.0")
+                .set("depends", depends_on_mm)
+                .build(),
+            repo.id,
+            None,
+        )
+        .unwrap();
+
+        let filter: ModuleFilter = "depends:ModuleManager".parse().unwrap();
+        assert_eq!(filter.to_string(), "depends:ModuleManager");
+        assert_eq!(slugs(db.filtered_modules(&filter).unwrap()), ["Engines"]);
+        assert_eq!(
+            slugs(
+                db.filtered_modules(&"-depends:ModuleManager".parse().unwrap())
+                    .unwrap()
+            ),
+            ["BoxTanks"]
+        );
+        assert!(
+            db.modules_with_relationship(RelationshipType::Recommends, "ModuleManager")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn saved_filters_round_trip() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        let parts = "tag:parts -tag:graphics".parse().unwrap();
+        let dlc = "kind:dlc".parse().unwrap();
+        db.save_filter("parts", &dlc).unwrap();
+        db.save_filter("parts", &parts).unwrap();
+        db.save_filter("dlc", &dlc).unwrap();
+
+        let saved = db.saved_filters().unwrap();
+        let names = saved.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["dlc", "parts"]);
+        assert_eq!(db.saved_filter("parts").unwrap(), Some(parts));
+
+        assert!(db.delete_saved_filter("parts").unwrap());
+        assert!(!db.delete_saved_filter("parts").unwrap());
+        assert_eq!(db.saved_filter("parts").unwrap(), None);
+    }
+}
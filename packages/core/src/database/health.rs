@@ -0,0 +1,325 @@
+//! Diagnostic details about the state of a repository.
+//!
+//! [`RepoOverview`] puts the details a settings screen shows next to each
+//! repository, so that a page of repositories can be loaded in one call.
+
+use std::ops::DerefMut;
+
+use diesel::{
+    dsl::exists,
+    prelude::*,
+    result::Error::DeserializationError,
+    sql_query,
+    sql_types::{BigInt, Binary, Integer, Nullable, Text, TimestamptzSqlite},
+};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::database::{
+    JsonbValue, Operation, QueryContext, QueryError, RepoCursor, RepoDB, RepoId,
+    models::{RepoFilter, Repository, RepositoryRef},
+    pagination::next_page,
+    schema::{repositories, resumable_updates},
+};
+
+/// Counts and download details for one repository, for working out why it
+/// looks empty or out of date.
+#[derive(Debug)]
+pub struct RepoHealth {
+    pub repo_id: RepoId,
+    pub name: String,
+    pub url: Url,
+    pub module_count: i64,
+    /// Modules which exist only to hold a download count.
+    pub modules_without_releases: i64,
+    pub release_count: i64,
+    /// When the repository was last downloaded, if ever.
+    pub updated_at: Option<OffsetDateTime>,
+    pub etag: Option<String>,
+    pub filter: Option<RepoFilter>,
+    pub referenced_repos: Vec<RepositoryRef<'static>>,
+}
+
+/// What state a repository is in, for showing next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum RepoStatus {
+    /// The repository has never been downloaded.
+    NeverUpdated,
+    /// The repository is being updated, by this program or another one.
+    Updating,
+    /// The repository's last update was interrupted. The next one carries on
+    /// from where it stopped.
+    Interrupted,
+    /// The repository's URL permanently redirected at its last update, so it
+    /// should be moved to its `moved_url`.
+    Moved,
+    /// The repository has been updated, and nothing needs doing.
+    Ready,
+}
+
+/// A repository with the details a settings screen shows next to it.
+#[derive(Debug, uniffi::Record)]
+pub struct RepoOverview {
+    pub repo: Repository,
+    pub module_count: i64,
+    pub release_count: i64,
+    /// When the repository was last downloaded, if ever.
+    pub updated_at: Option<OffsetDateTime>,
+    pub status: RepoStatus,
+}
+
+/// A page of repositories, ordered by name.
+#[derive(Debug, uniffi::Record)]
+pub struct RepoOverviewPage {
+    pub repos: Vec<RepoOverview>,
+    /// Where the next page starts, or `None` if this is the last page.
+    pub next: Option<RepoCursor>,
+}
+
+#[derive(QueryableByName)]
+struct HealthRow {
+    #[diesel(sql_type = Integer, deserialize_as = i32)]
+    repo_id: RepoId,
+    #[diesel(sql_type = Text)]
+    name: String,
+    #[diesel(sql_type = Binary)]
+    url: JsonbValue,
+    #[diesel(sql_type = BigInt)]
+    module_count: i64,
+    #[diesel(sql_type = BigInt)]
+    modules_without_releases: i64,
+    #[diesel(sql_type = BigInt)]
+    release_count: i64,
+    #[diesel(sql_type = Nullable<TimestamptzSqlite>)]
+    updated_at: Option<OffsetDateTime>,
+    #[diesel(sql_type = Nullable<Text>)]
+    etag: Option<String>,
+    #[diesel(sql_type = Nullable<Binary>)]
+    filter: Option<JsonbValue>,
+    #[diesel(sql_type = Nullable<Text>)]
+    ref_name: Option<String>,
+    #[diesel(sql_type = Nullable<Binary>)]
+    ref_url: Option<JsonbValue>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    ref_priority: Option<i32>,
+}
+
+impl RepoHealth {
+    /// Combines the rows returned for a repository, which only differ by the
+    /// repository reference they include.
+    fn from_rows(mut rows: Vec<HealthRow>) -> Result<Option<Self>, serde_json::Error> {
+        let referenced_repos = rows
+            .iter_mut()
+            .filter_map(|row| Some((row.ref_name.take()?, row.ref_url.take()?, row.ref_priority?)))
+            .map(|(name, url, priority)| {
+                let mut repo_ref = RepositoryRef::new(name, url.try_into()?);
+                repo_ref.priority = priority;
+                Ok(repo_ref)
+            })
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+
+        let Some(row) = rows.into_iter().next() else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            repo_id: row.repo_id,
+            name: row.name,
+            url: row.url.try_into()?,
+            module_count: row.module_count,
+            modules_without_releases: row.modules_without_releases,
+            release_count: row.release_count,
+            updated_at: row.updated_at,
+            etag: row.etag,
+            filter: row.filter.map(TryInto::try_into).transpose()?,
+            referenced_repos,
+        }))
+    }
+}
+
+const HEALTH_QUERY: &str = "
+SELECT
+    r.repo_id,
+    r.name,
+    r.url,
+    (SELECT COUNT(*) FROM modules WHERE repo_id = r.repo_id) AS module_count,
+    (
+        SELECT COUNT(*) FROM modules m
+        WHERE m.repo_id = r.repo_id
+            AND NOT EXISTS (SELECT 1 FROM module_releases WHERE module_id = m.module_id)
+    ) AS modules_without_releases,
+    (
+        SELECT COUNT(*) FROM module_releases
+        INNER JOIN modules m USING (module_id)
+        WHERE m.repo_id = r.repo_id
+    ) AS release_count,
+    e.updated_at,
+    e.etag,
+    r.filter,
+    rr.name AS ref_name,
+    rr.url AS ref_url,
+    rr.priority AS ref_priority
+FROM repositories r
+LEFT JOIN etags e ON e.url = URL_KEY(r.url)
+LEFT JOIN repository_refs rr ON rr.referrer_id = r.repo_id
+WHERE r.name = ?
+ORDER BY rr.name
+";
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns diagnostic details about the repository with the given name, or
+    /// `None` if there isn't one.
+    pub fn repo_health(&mut self, name: &str) -> Result<Option<RepoHealth>, QueryError> {
+        sql_query(HEALTH_QUERY)
+            .bind::<Text, _>(name)
+            .load::<HealthRow>(&mut *self.connection)
+            .and_then(|rows| {
+                RepoHealth::from_rows(rows).map_err(|e| DeserializationError(e.into()))
+            })
+            .during(|| Operation::RepoHealth { name: name.into() })
+    }
+
+    /// Returns up to `limit` repositories ordered by name, with their counts
+    /// and status, starting after `after`, or from the first repository if
+    /// it's `None`.
+    pub fn repo_overview_page(
+        &mut self,
+        after: Option<&RepoCursor>,
+        limit: u32,
+    ) -> Result<RepoOverviewPage, QueryError> {
+        let mut query = Repository::all()
+            .order_by(repositories::name)
+            .limit(i64::from(limit) + 1)
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(repositories::name.gt(&after.name));
+        }
+        let mut repos = query
+            .load::<Repository>(&mut *self.connection)
+            .during(|| Operation::RepoPage { limit })?;
+
+        let next = next_page(&mut repos, limit).map(|repo| RepoCursor {
+            name: repo.name.clone(),
+        });
+        let repos = repos
+            .into_iter()
+            .map(|repo| self.repo_overview(repo))
+            .collect::<Result<_, _>>()?;
+
+        Ok(RepoOverviewPage { repos, next })
+    }
+
+    fn repo_overview(&mut self, repo: Repository) -> Result<RepoOverview, QueryError> {
+        let health = self.repo_health(&repo.name)?;
+        let updating = self.update_lock_holder(repo.id)?.is_some();
+        let interrupted = diesel::select(exists(resumable_updates::table.find(repo.id)))
+            .get_result::<bool>(&mut *self.connection)
+            .during(|| Operation::LoadResumableUpdate { repo: repo.id })?;
+
+        let updated_at = health.as_ref().and_then(|health| health.updated_at);
+        let status = if updating {
+            RepoStatus::Updating
+        } else if interrupted {
+            RepoStatus::Interrupted
+        } else if repo.moved_url.is_some() {
+            RepoStatus::Moved
+        } else if updated_at.is_none() {
+            RepoStatus::NeverUpdated
+        } else {
+            RepoStatus::Ready
+        };
+
+        Ok(RepoOverview {
+            module_count: health.as_ref().map_or(0, |health| health.module_count),
+            release_count: health.as_ref().map_or(0, |health| health.release_count),
+            updated_at,
+            status,
+            repo,
+        })
+    }
+}
+
+// Setting an ETag needs a header from the HTTP client.
+#[cfg(all(test, feature = "network"))]
+mod test {
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use super::*;
+    use crate::{
+        database::DownloadCountMode,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn counts_repo_contents() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let health = db.repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.module_count, 0);
+        assert_eq!(health.updated_at, None);
+        assert_eq!(health.filter, None);
+        assert!(health.referenced_repos.is_empty());
+
+        db.create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.add_download_counts(
+            repo.id,
+            [(&"Scatterer".to_string(), &10)],
+            DownloadCountMode::CreateModules,
+        )
+        .unwrap();
+
+        let mirror = Url::parse("https://example.com/mirror.tar.gz").unwrap();
+        db.add_repo_ref(repo.id, RepositoryRef::shared("mirror", &mirror))
+            .unwrap();
+        db.set_etag(Arc::new(repo.url.clone()), Some(&"\"abc\"".parse().unwrap()))
+            .unwrap();
+
+        let health = db.repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.module_count, 2);
+        assert_eq!(health.modules_without_releases, 1);
+        assert_eq!(health.release_count, 1);
+        assert_eq!(health.etag.as_deref(), Some("\"abc\""));
+        assert!(health.updated_at.is_some());
+        assert_eq!(health.referenced_repos[0].name, "mirror");
+        assert_eq!(*health.referenced_repos[0].url, mirror);
+
+        assert!(db.repo_health("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn pages_repo_overviews() {
+        let (mut db, repo) = db_with_default_repo();
+        let url = Url::parse("https://example.com/repo.tar.gz").unwrap();
+        let other = db
+            .create_empty_repo(RepositoryRef::new("Zeta".into(), url))
+            .unwrap();
+
+        let first = db.repo_overview_page(None, 1).unwrap();
+        assert_eq!(first.repos.len(), 1);
+        assert_eq!(first.repos[0].repo.id, repo.id);
+        assert_eq!(first.repos[0].status, RepoStatus::NeverUpdated);
+
+        let cursor = first.next.unwrap().to_string().parse().unwrap();
+        let second = db.repo_overview_page(Some(&cursor), 1).unwrap();
+        assert_eq!(second.repos[0].repo.id, other.id);
+        assert!(second.next.is_none());
+
+        db.set_etag(Arc::new(repo.url.clone()), None).unwrap();
+        let first = db.repo_overview_page(None, 1).unwrap();
+        assert_eq!(first.repos[0].status, RepoStatus::Ready);
+        assert!(first.repos[0].updated_at.is_some());
+
+        db.begin_resumable_update(repo.id, Path::new("/tmp/1.archive"), None, None)
+            .unwrap();
+        let first = db.repo_overview_page(None, 1).unwrap();
+        assert_eq!(first.repos[0].status, RepoStatus::Interrupted);
+
+        let _lock = db
+            .try_lock_update(repo.id, Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+        let first = db.repo_overview_page(None, 1).unwrap();
+        assert_eq!(first.repos[0].status, RepoStatus::Updating);
+    }
+}
@@ -7,16 +7,21 @@ use std::{
 use diesel::{
     Queryable,
     backend::Backend,
-    deserialize::{FromSql, FromSqlRow},
+    deserialize::{self, FromSql, FromSqlRow},
     expression::AsExpression,
-    serialize::{IsNull, Output, ToSql},
-    sql_types::{Binary, Integer, Jsonb, Nullable},
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::{Binary, Integer, Jsonb, Nullable, Text},
+    sqlite::{Sqlite, SqliteValue},
 };
 use serde_json::{Value, from_value, to_value};
 use thiserror::Error;
 use url::Url;
 
-use crate::{database::models, repo::game::GameVersion};
+use crate::{
+    database::models,
+    markdown::{self, SanitizedText},
+    repo::game::GameVersion,
+};
 
 #[derive(Default, PartialEq, Eq, PartialOrd, Ord, Hash, AsExpression)]
 #[diesel(sql_type = Integer)]
@@ -105,6 +110,7 @@ mod id {
     }
 
     tag!(
+        Author,
         Repo,
         Module,
         Release,
@@ -114,10 +120,12 @@ mod id {
         ModLicense,
         ModLocale,
         ModTag,
+        Download,
     );
 }
 pub use id::*;
 
+uniffi::custom_type!(AuthorId, i32);
 uniffi::custom_type!(RepoId, i32);
 uniffi::custom_type!(ModuleId, i32);
 uniffi::custom_type!(ReleaseId, i32);
@@ -127,42 +135,53 @@ uniffi::custom_type!(ModAuthorId, i32);
 uniffi::custom_type!(ModLicenseId, i32);
 uniffi::custom_type!(ModLocaleId, i32);
 uniffi::custom_type!(ModTagId, i32);
+uniffi::custom_type!(DownloadId, i32);
 
 #[derive(Debug, FromSqlRow, AsExpression)]
 #[diesel(sql_type = Binary)]
 pub struct JsonbValue(pub Value);
 
+/// The first bytes of a zstd frame. A JSONB blob starting with these bytes
+/// would be a 3-byte string, so a compressed blob can't be mistaken for one.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The zstd compression level used for [`JsonBlob`]s.
+const COMPRESSION_LEVEL: i32 = 3;
+
+impl JsonbValue {
+    /// Reads a blob containing either JSONB or zstd-compressed JSON text.
+    fn from_blob(mut value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let bytes = value.read_blob();
+        if bytes.starts_with(&ZSTD_MAGIC) {
+            let json = zstd::decode_all(bytes)?;
+            return Ok(Self(serde_json::from_slice(&json)?));
+        }
+
+        <Value as FromSql<Jsonb, Sqlite>>::from_sql(value).map(Self)
+    }
+}
+
 // These traits are for converting this helper struct to serialized data for
 // SQL.
 
 // BLOB NOT NULL -> Self
 
-impl<DB> FromSql<Binary, DB> for JsonbValue
-where
-    DB: Backend,
-    Value: FromSql<Jsonb, DB>,
-{
-    fn from_sql(bytes: DB::RawValue<'_>) -> diesel::deserialize::Result<Self> {
-        Value::from_sql(bytes).map(Self)
+impl FromSql<Binary, Sqlite> for JsonbValue {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        Self::from_blob(bytes)
     }
 }
 
 // BLOB -> Self
 
-impl<DB> FromSql<Nullable<Binary>, DB> for JsonbValue
-where
-    DB: Backend,
-    Value: FromSql<Jsonb, DB>,
-{
-    fn from_sql(bytes: DB::RawValue<'_>) -> diesel::deserialize::Result<Self> {
-        Value::from_sql(bytes).map(Self)
+impl FromSql<Nullable<Binary>, Sqlite> for JsonbValue {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        Self::from_blob(bytes)
     }
 
-    fn from_nullable_sql(
-        bytes: Option<<DB as Backend>::RawValue<'_>>,
-    ) -> diesel::deserialize::Result<Self> {
+    fn from_nullable_sql(bytes: Option<SqliteValue<'_, '_, '_>>) -> deserialize::Result<Self> {
         if let Some(bytes) = bytes {
-            Value::from_sql(bytes).map(Self)
+            Self::from_blob(bytes)
         } else {
             Ok(Self(Value::Null))
         }
@@ -171,20 +190,66 @@ where
 
 // Self -> BLOB (NOT NULL)
 
-impl<DB> ToSql<Binary, DB> for JsonbValue
-where
-    DB: Backend,
-    Value: ToSql<Jsonb, DB>,
-{
-    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> diesel::serialize::Result {
+impl ToSql<Binary, Sqlite> for JsonbValue {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
         if self.0.is_null() {
             Ok(IsNull::Yes)
         } else {
-            self.0.to_sql(out)
+            <Value as ToSql<Jsonb, Sqlite>>::to_sql(&self.0, out)
         }
     }
 }
 
+/// A JSON value which can be saved as either JSONB or zstd-compressed JSON
+/// text. Compressed values are much smaller, but can't be used with SQLite's
+/// JSON functions. Both formats are read back as a [`JsonbValue`].
+#[derive(Debug, AsExpression)]
+#[diesel(sql_type = Binary)]
+pub struct JsonBlob {
+    pub value: JsonbValue,
+    pub compressed: bool,
+}
+
+impl JsonBlob {
+    pub fn new(value: impl Into<JsonbValue>, compressed: bool) -> Self {
+        Self {
+            value: value.into(),
+            compressed,
+        }
+    }
+}
+
+impl ToSql<Binary, Sqlite> for JsonBlob {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        if !self.compressed {
+            return <JsonbValue as ToSql<Binary, Sqlite>>::to_sql(&self.value, out);
+        }
+
+        let json = serde_json::to_vec(&self.value.0)?;
+        out.set_value(zstd::encode_all(&*json, COMPRESSION_LEVEL)?);
+        Ok(IsNull::No)
+    }
+}
+
+/// Markdown written by module authors, which is sanitized as it's read, so
+/// that it's never shown as it was saved.
+#[derive(Debug)]
+pub struct SanitizedColumn(pub Option<SanitizedText>);
+
+impl Queryable<Nullable<Text>, Sqlite> for SanitizedColumn {
+    type Row = Option<String>;
+
+    fn build(text: Option<String>) -> deserialize::Result<Self> {
+        Ok(Self(text.as_deref().map(markdown::sanitize)))
+    }
+}
+
+impl From<SanitizedColumn> for Option<SanitizedText> {
+    fn from(value: SanitizedColumn) -> Self {
+        value.0
+    }
+}
+
 // These traits are for converting this helper struct to and from strongly typed
 // data. Other types <-> Self
 
@@ -209,6 +274,12 @@ macro_rules! jsonb_convertable {
                 }
             }
 
+            impl From<Option<$type>> for JsonbValue {
+                fn from(value: Option<$type>) -> Self {
+                    value.as_ref().into()
+                }
+            }
+
             impl TryFrom<JsonbValue> for $type {
                 type Error = serde_json::Error;
 
@@ -239,8 +310,42 @@ impl TryFrom<JsonbValue> for Url {
         from_value(value.0)
     }
 }
+impl From<Option<&Url>> for JsonbValue {
+    fn from(value: Option<&Url>) -> Self {
+        Self(to_value(value).expect("failed to serialize value to json"))
+    }
+}
+impl TryFrom<JsonbValue> for Option<Url> {
+    type Error = serde_json::Error;
+    fn try_from(value: JsonbValue) -> Result<Self, Self::Error> {
+        from_value(value.0)
+    }
+}
+
+impl From<&[Url]> for JsonbValue {
+    fn from(value: &[Url]) -> Self {
+        Self(to_value(value).expect("failed to serialize value to json"))
+    }
+}
+// A missing list is the same as an empty one.
+impl TryFrom<JsonbValue> for Vec<Url> {
+    type Error = serde_json::Error;
+    fn try_from(value: JsonbValue) -> Result<Self, Self::Error> {
+        if value.0.is_null() {
+            return Ok(vec![]);
+        }
+        from_value(value.0)
+    }
+}
 
-jsonb_convertable!(models::ReleaseMetadata<'_>, GameVersion);
+jsonb_convertable!(
+    models::ReleaseMetadata<'_>,
+    models::RepoFilter,
+    GameVersion,
+    super::ModuleFilter,
+    Vec<String>,
+    Vec<super::ReleaseFinding>
+);
 
 // Support for Self <-> Cow<Other types>
 
@@ -0,0 +1,140 @@
+//! The versions each repository has provided over time.
+//!
+//! Releases are replaced every time their repository is updated, so once a
+//! version is removed from a repository, nothing else says it existed. At the
+//! end of each update, every version the repository provides is recorded with
+//! when it was first and last seen, and versions which are no longer provided
+//! are marked as removed. A version which comes back is no longer marked.
+
+use std::ops::DerefMut;
+
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{Integer, TimestamptzSqlite},
+    update,
+};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::database::{
+    Operation, QueryContext, QueryError, RepoDB, RepoId, models::module::version_sort_key,
+    schema::*,
+};
+
+/// Adds the repository's current releases to its history, or marks them as
+/// seen again.
+const RECORD_SEEN: &str = "
+INSERT INTO release_history (repo_id, module_slug, version, first_seen, last_seen)
+SELECT m.repo_id, m.module_slug, r.version, ?, ?
+FROM module_releases r
+INNER JOIN modules m ON m.module_id = r.module_id
+WHERE m.repo_id = ?
+ON CONFLICT (repo_id, module_slug, version) DO UPDATE
+SET last_seen = excluded.last_seen, removed_at = NULL
+";
+
+/// When a version of a module was provided by a repository.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, uniffi::Record)]
+#[diesel(table_name = release_history)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ReleaseHistory {
+    pub repo_id: RepoId,
+    pub version: String,
+    /// The first update which included the version.
+    pub first_seen: OffsetDateTime,
+    /// The last update which included the version.
+    pub last_seen: OffsetDateTime,
+    /// The first update which didn't include the version after it was last
+    /// seen, or `None` if the repository still provides it.
+    pub removed_at: Option<OffsetDateTime>,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Records the versions a repository provides as of now. Called at the
+    /// end of an update, once its releases have been saved.
+    #[instrument(skip(self))]
+    pub fn record_history(&mut self, repo: RepoId) -> Result<(), QueryError> {
+        let operation = || Operation::RecordHistory { repo };
+        let now = OffsetDateTime::now_utc();
+
+        sql_query(RECORD_SEEN)
+            .bind::<TimestamptzSqlite, _>(now)
+            .bind::<TimestamptzSqlite, _>(now)
+            .bind::<Integer, _>(repo)
+            .execute(&mut *self.connection)
+            .during(operation)?;
+
+        // Every version still provided was just seen, so the rest are gone.
+        update(release_history::table)
+            .filter(release_history::repo_id.eq(repo))
+            .filter(release_history::removed_at.is_null())
+            .filter(release_history::last_seen.ne(now))
+            .set(release_history::removed_at.eq(now))
+            .execute(&mut *self.connection)
+            .during(operation)?;
+
+        Ok(())
+    }
+
+    /// Every version of a module which any repository has provided, newest
+    /// first.
+    pub fn module_history(&mut self, slug: &str) -> Result<Vec<ReleaseHistory>, QueryError> {
+        release_history::table
+            .filter(release_history::module_slug.eq(slug))
+            .select(ReleaseHistory::as_select())
+            .order_by((
+                version_sort_key(release_history::version).desc(),
+                release_history::repo_id,
+            ))
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadHistory { slug: slug.into() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::delete;
+
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    fn versions(history: &[ReleaseHistory]) -> Vec<(&str, bool)> {
+        history
+            .iter()
+            .map(|h| (&*h.version, h.removed_at.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn remembers_removed_versions() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (module, _) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(&release("Parallax", "2.0").build(), repo.id, Some(module))
+            .unwrap();
+        db.record_history(repo.id).unwrap();
+        let first = db.module_history("Parallax").unwrap();
+        assert_eq!(versions(&first), [("2.0", false), ("1.0", false)]);
+
+        // The next update drops 1.0.
+        delete(module_releases::table.filter(module_releases::version.eq("1.0")))
+            .execute(db.connection_mut())
+            .unwrap();
+        db.record_history(repo.id).unwrap();
+        let second = db.module_history("Parallax").unwrap();
+        assert_eq!(versions(&second), [("2.0", false), ("1.0", true)]);
+        assert_eq!(second[1].last_seen, first[1].last_seen);
+        assert_eq!(second[0].first_seen, first[0].first_seen);
+
+        // Then it comes back.
+        db.create_release(&release("Parallax", "1.0").build(), repo.id, Some(module))
+            .unwrap();
+        db.record_history(repo.id).unwrap();
+        let third = db.module_history("Parallax").unwrap();
+        assert_eq!(versions(&third), [("2.0", false), ("1.0", false)]);
+        assert_eq!(third[1].first_seen, first[1].first_seen);
+    }
+}
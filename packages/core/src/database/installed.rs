@@ -0,0 +1,135 @@
+//! Modules installed in the game.
+//!
+//! Like yanks, installed modules are recorded by identifier and version rather
+//! than by release, since releases are replaced every time their repository is
+//! updated. [`RepoDB::find_release`] finds the release a record refers to.
+
+use std::ops::DerefMut;
+
+use diesel::{delete, prelude::*, replace_into};
+use time::OffsetDateTime;
+
+use crate::database::{
+    JsonbValue, Operation, QueryContext, QueryError, ReleaseId, RepoDB, schema::*,
+};
+
+/// A module which is installed in the game.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, Insertable, uniffi::Record)]
+#[diesel(table_name = installed_modules)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct InstalledModule {
+    #[diesel(column_name = module_slug)]
+    pub slug: String,
+    pub version: String,
+    /// Whether the module was only installed to satisfy another module's
+    /// relationships, rather than being chosen by the user.
+    pub auto_installed: bool,
+    pub installed_at: Option<OffsetDateTime>,
+    /// The module's files, relative to the game directory.
+    #[diesel(deserialize_as = JsonbValue)]
+    #[diesel(serialize_as = JsonbValue)]
+    pub files: Vec<String>,
+    /// The archive the module was installed from, if it was installed from a
+    /// file on disk rather than downloaded from a repository.
+    pub local_archive: Option<String>,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Records modules as installed, replacing the records of any which were
+    /// already installed.
+    pub fn record_installed(&mut self, modules: Vec<InstalledModule>) -> Result<(), QueryError> {
+        replace_into(installed_modules::table)
+            .values(modules)
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordInstalled)?;
+
+        Ok(())
+    }
+
+    /// Forgets that a module is installed. Returns whether it was.
+    pub fn remove_installed(&mut self, slug: &str) -> Result<bool, QueryError> {
+        let deleted = delete(installed_modules::table.find(slug))
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordInstalled)?;
+
+        Ok(deleted > 0)
+    }
+
+    /// The installed modules, ordered by identifier.
+    pub fn installed_modules(&mut self) -> Result<Vec<InstalledModule>, QueryError> {
+        installed_modules::table
+            .select(InstalledModule::as_select())
+            .order_by(installed_modules::module_slug)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadInstalled)
+    }
+
+    /// The release of a module with the given version, from whichever
+    /// repository has it. Versions are compared with the `MODULE_VERSION`
+    /// collation, so `1.01` finds a release numbered `1.1`.
+    pub fn find_release(
+        &mut self,
+        slug: &str,
+        version: &str,
+    ) -> Result<Option<ReleaseId>, QueryError> {
+        module_releases::table
+            .inner_join(modules::table)
+            .filter(modules::module_slug.eq(slug))
+            .filter(module_releases::version.eq(version))
+            .select(module_releases::release_id)
+            .first(&mut *self.connection)
+            .optional()
+            .during(|| Operation::FindRelease {
+                slug: slug.into(),
+                version: version.into(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    fn installed(slug: &str, version: &str) -> InstalledModule {
+        InstalledModule {
+            slug: slug.into(),
+            version: version.into(),
+            auto_installed: false,
+            installed_at: None,
+            files: vec![format!("GameData/{slug}/{slug}.dll")],
+            local_archive: None,
+        }
+    }
+
+    #[test]
+    fn records_installed_modules() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (_, release_id) = db
+            .create_release(&release("Parallax", "2.0.1").build(), repo.id, None)
+            .unwrap();
+
+        db.record_installed(vec![
+            installed("Scatterer", "0.1"),
+            installed("Parallax", "2.0"),
+        ])
+        .unwrap();
+        db.record_installed(vec![installed("Parallax", "2.0.01")])
+            .unwrap();
+
+        let modules = db.installed_modules().unwrap();
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0], installed("Parallax", "2.0.01"));
+        assert_eq!(modules[1].slug, "Scatterer");
+
+        assert_eq!(
+            db.find_release("Parallax", "2.0.01").unwrap(),
+            Some(release_id)
+        );
+        assert_eq!(db.find_release("Scatterer", "0.1").unwrap(), None);
+
+        assert!(db.remove_installed("Scatterer").unwrap());
+        assert!(!db.remove_installed("Scatterer").unwrap());
+    }
+}
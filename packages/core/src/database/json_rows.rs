@@ -0,0 +1,339 @@
+//! Untyped rows for dynamic consumers, like scripting layers and web APIs.
+//!
+//! Raw SQL is never accepted. Callers choose one of a fixed set of
+//! [`NamedQuery`]s and pass its parameters by name, and get back each row as a
+//! JSON object keyed by column name. SQLite builds the objects itself, so the
+//! columns of each query are listed in its SQL.
+
+use std::{ops::DerefMut, str::FromStr};
+
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Text},
+};
+use miette::Diagnostic;
+use serde_json::{Map, Value};
+use strum::{IntoStaticStr, VariantArray};
+use tracing::instrument;
+
+use crate::{
+    Error,
+    database::{Operation, QueryContext, RepoDB},
+};
+
+/// A query which can be run by name with [`RepoDB::query_json`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr, VariantArray)]
+#[strum(serialize_all = "snake_case")]
+pub enum NamedQuery {
+    /// Every repository, in priority order.
+    Repositories,
+    /// The modules of the repository named by the `repo` parameter.
+    Modules,
+    /// The releases of the module named by the `module` parameter, in every
+    /// repository, newest first.
+    Releases,
+    /// Every tag, with how many modules use it.
+    Tags,
+    /// The installed modules.
+    Installed,
+}
+
+/// The type a [`NamedQuery`] parameter is bound as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Text,
+    Integer,
+}
+
+impl NamedQuery {
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+
+    /// The parameters the query takes, in the order they're bound.
+    pub fn params(self) -> &'static [(&'static str, ParamKind)] {
+        match self {
+            Self::Repositories | Self::Tags | Self::Installed => &[],
+            Self::Modules => &[("repo", ParamKind::Text)],
+            Self::Releases => &[("module", ParamKind::Text)],
+        }
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            Self::Repositories => {
+                "SELECT json_object(
+                    'name', name,
+                    'url', url_text(url),
+                    'priority', priority,
+                    'mirror', json(iif(x_mirror, 'true', 'false')),
+                    'comment', x_comment
+                ) AS row
+                FROM repositories
+                ORDER BY priority, name"
+            }
+            Self::Modules => {
+                "SELECT json_object(
+                    'slug', m.module_slug,
+                    'download_count', m.download_count,
+                    'releases', (SELECT COUNT(*) FROM module_releases WHERE module_id = m.module_id)
+                ) AS row
+                FROM modules m
+                JOIN repositories r ON r.repo_id = m.repo_id
+                WHERE r.name = ?
+                ORDER BY m.module_slug"
+            }
+            Self::Releases => {
+                "SELECT json_object(
+                    'repo', repo.name,
+                    'version', r.version,
+                    'name', r.display_name,
+                    'summary', r.summary,
+                    'release_status', CASE r.release_status
+                        WHEN 1 THEN 'testing'
+                        WHEN 2 THEN 'development'
+                        ELSE 'stable'
+                    END,
+                    'download_size', r.download_size,
+                    'install_size', r.install_size,
+                    'release_date', r.release_date
+                ) AS row
+                FROM module_releases r
+                JOIN modules m ON m.module_id = r.module_id
+                JOIN repositories repo ON repo.repo_id = m.repo_id
+                WHERE m.module_slug = ?
+                ORDER BY repo.priority, IFNULL(r.sort_key, X'') DESC, r.version"
+            }
+            Self::Tags => {
+                "SELECT json_object('tag', tag, 'modules', COUNT(DISTINCT r.module_id)) AS row
+                FROM module_tags t
+                JOIN module_releases r ON r.release_id = t.release_id
+                GROUP BY tag
+                ORDER BY tag"
+            }
+            Self::Installed => {
+                "SELECT json_object(
+                    'slug', module_slug,
+                    'version', version,
+                    'auto_installed', json(iif(auto_installed, 'true', 'false')),
+                    'installed_at', installed_at
+                ) AS row
+                FROM installed_modules
+                ORDER BY module_slug"
+            }
+        }
+    }
+}
+
+/// A reason the parameters given to [`RepoDB::query_json`] can't be used.
+#[derive(Debug, thiserror::Error, Diagnostic, PartialEq, Eq)]
+pub enum JsonQueryError {
+    #[error("there's no query named {name:?}")]
+    #[diagnostic(code(camrete::database::unknown_query), severity(Warning))]
+    UnknownQuery { name: String },
+    #[error("query {query:?} needs a {param:?} parameter")]
+    #[diagnostic(code(camrete::database::missing_parameter), severity(Warning))]
+    MissingParameter {
+        query: &'static str,
+        param: &'static str,
+    },
+    #[error("query {query:?} doesn't take a {param:?} parameter")]
+    #[diagnostic(code(camrete::database::unexpected_parameter), severity(Warning))]
+    UnexpectedParameter { query: &'static str, param: String },
+    #[error("parameter {param:?} of query {query:?} must be {expected}")]
+    #[diagnostic(code(camrete::database::invalid_parameter), severity(Warning))]
+    InvalidParameter {
+        query: &'static str,
+        param: &'static str,
+        expected: &'static str,
+    },
+}
+
+impl FromStr for NamedQuery {
+    type Err = JsonQueryError;
+
+    fn from_str(s: &str) -> Result<Self, JsonQueryError> {
+        Self::VARIANTS
+            .iter()
+            .copied()
+            .find(|query| query.name() == s)
+            .ok_or_else(|| JsonQueryError::UnknownQuery { name: s.into() })
+    }
+}
+
+#[derive(QueryableByName)]
+struct JsonRow {
+    #[diesel(sql_type = Text)]
+    row: String,
+}
+
+/// A parameter's value, checked against the type the query expects.
+enum Bound {
+    Text(String),
+    Integer(i64),
+}
+
+fn bind_params(
+    query: NamedQuery,
+    params: &Map<String, Value>,
+) -> Result<Vec<Bound>, JsonQueryError> {
+    let expected = query.params();
+    if let Some(param) = params
+        .keys()
+        .find(|k| !expected.iter().any(|(name, _)| name == k))
+    {
+        return Err(JsonQueryError::UnexpectedParameter {
+            query: query.name(),
+            param: param.clone(),
+        });
+    }
+
+    expected
+        .iter()
+        .map(|&(param, kind)| -> Result<_, JsonQueryError> {
+            let value = params.get(param).ok_or(JsonQueryError::MissingParameter {
+                query: query.name(),
+                param,
+            })?;
+            let invalid = |expected| JsonQueryError::InvalidParameter {
+                query: query.name(),
+                param,
+                expected,
+            };
+
+            Ok(match kind {
+                ParamKind::Text => Bound::Text(
+                    value
+                        .as_str()
+                        .ok_or_else(|| invalid("a string"))?
+                        .to_owned(),
+                ),
+                ParamKind::Integer => {
+                    Bound::Integer(value.as_i64().ok_or_else(|| invalid("an integer"))?)
+                }
+            })
+        })
+        .collect()
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Runs a [`NamedQuery`] with the given parameters, returning each row as
+    /// a JSON object. Every parameter the query takes must be given, and no
+    /// others.
+    #[instrument(skip(self))]
+    pub fn query_json(
+        &mut self,
+        query: NamedQuery,
+        params: &Map<String, Value>,
+    ) -> Result<Vec<Value>, Error> {
+        let bound = bind_params(query, params)?;
+
+        let rows = self.read_with_retry(|conn| {
+            let mut sql = sql_query(query.sql()).into_boxed();
+            for value in &bound {
+                sql = match value {
+                    Bound::Text(text) => sql.bind::<Text, _>(text.clone()),
+                    Bound::Integer(int) => sql.bind::<BigInt, _>(*int),
+                };
+            }
+
+            sql.load::<JsonRow>(conn)
+                .during(|| Operation::QueryJson { name: query.name() })
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| serde_json::from_str(&row.row))
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::{
+        RepoManager,
+        test_support::{db_with_default_repo, release},
+    };
+
+    fn params(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn returns_rows_as_objects() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let release = release("Parallax", "2.0.1")
+            .set("release_status", "testing")
+            .build();
+        db.create_release(&release, repo.id, None).unwrap();
+
+        let repos = db
+            .query_json(NamedQuery::Repositories, &Map::new())
+            .unwrap();
+        assert_eq!(repos[0]["name"], "KSP-default");
+        assert_eq!(repos[0]["mirror"], false);
+        assert!(repos[0]["url"].is_string());
+
+        let modules = db
+            .query_json(
+                NamedQuery::Modules,
+                &params(json!({ "repo": "KSP-default" })),
+            )
+            .unwrap();
+        assert_eq!(
+            modules,
+            [json!({ "slug": "Parallax", "download_count": 0, "releases": 1 })]
+        );
+
+        let releases = db
+            .query_json(
+                "releases".parse().unwrap(),
+                &params(json!({ "module": "Parallax" })),
+            )
+            .unwrap();
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0]["version"], "2.0.1");
+        assert_eq!(releases[0]["release_status"], "testing");
+        assert_eq!(releases[0]["download_size"], Value::Null);
+    }
+
+    #[test]
+    fn checks_parameters() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        assert_eq!(
+            "DROP TABLE modules".parse::<NamedQuery>(),
+            Err(JsonQueryError::UnknownQuery {
+                name: "DROP TABLE modules".into()
+            })
+        );
+
+        let err = db.query_json(NamedQuery::Modules, &Map::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::JsonQuery(JsonQueryError::MissingParameter { param: "repo", .. })
+        ));
+
+        let err = db
+            .query_json(NamedQuery::Modules, &params(json!({ "repo": 1 })))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::JsonQuery(JsonQueryError::InvalidParameter { param: "repo", .. })
+        ));
+
+        let err = db
+            .query_json(NamedQuery::Tags, &params(json!({ "limit": 10 })))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::JsonQuery(JsonQueryError::UnexpectedParameter { .. })
+        ));
+    }
+}
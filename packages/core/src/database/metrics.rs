@@ -0,0 +1,206 @@
+//! Usage metrics, for diagnosing performance problems from real use.
+//!
+//! Metrics are only recorded once the user turns them on with
+//! [`RepoDB::set_telemetry`]. They're kept in the database and are never sent
+//! anywhere; reading or exporting them is up to the user.
+
+use std::{ops::DerefMut, str::FromStr, time::Duration};
+
+use diesel::{delete, insert_into, prelude::*, upsert::excluded};
+use strum::{EnumString, IntoStaticStr};
+use tracing::trace;
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, schema::*};
+
+/// Something which is counted by the usage metrics.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, IntoStaticStr, EnumString, uniffi::Enum,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum Metric {
+    /// A repository was downloaded and saved.
+    RepoUpdate,
+    RepoUpdateFailure,
+    /// A file in the download queue was downloaded.
+    Download,
+    DownloadFailure,
+    /// A module was installed.
+    Install,
+    /// The modules someone asked for couldn't be installed together.
+    ResolutionFailure,
+}
+
+impl Metric {
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// How often something has happened, and how long it took on average.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct MetricSummary {
+    pub metric: Metric,
+    pub count: u64,
+    /// The average duration of the events which were timed, if any were.
+    pub average_duration: Option<Duration>,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Counts an event, along with how long it took if that's known. Nothing
+    /// is recorded unless telemetry is turned on.
+    pub fn record_metric(
+        &mut self,
+        metric: Metric,
+        duration: Option<Duration>,
+    ) -> Result<(), QueryError> {
+        if !self.telemetry_enabled()? {
+            return Ok(());
+        }
+        trace!(?metric, ?duration, "Recording metric");
+
+        let millis = duration.map_or(0, |d| d.as_millis().try_into().unwrap_or(i64::MAX));
+        insert_into(usage_metrics::table)
+            .values((
+                usage_metrics::name.eq(metric.name()),
+                usage_metrics::count.eq(1),
+                usage_metrics::timed_count.eq(i64::from(duration.is_some())),
+                usage_metrics::total_millis.eq(millis),
+            ))
+            .on_conflict(usage_metrics::name)
+            .do_update()
+            .set((
+                usage_metrics::count.eq(usage_metrics::count + 1),
+                usage_metrics::timed_count
+                    .eq(usage_metrics::timed_count + excluded(usage_metrics::timed_count)),
+                usage_metrics::total_millis
+                    .eq(usage_metrics::total_millis + excluded(usage_metrics::total_millis)),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordMetric { metric })?;
+
+        Ok(())
+    }
+
+    /// Lists every metric which has been recorded, ordered by name.
+    pub fn metrics(&mut self) -> Result<Vec<MetricSummary>, QueryError> {
+        let rows = usage_metrics::table
+            .select((
+                usage_metrics::name,
+                usage_metrics::count,
+                usage_metrics::timed_count,
+                usage_metrics::total_millis,
+            ))
+            .order_by(usage_metrics::name)
+            .load::<(String, i64, i64, i64)>(&mut *self.connection)
+            .during(|| Operation::LoadMetrics)?;
+
+        let metrics = rows
+            .into_iter()
+            // Skip metrics recorded by newer versions.
+            .filter_map(|(name, count, timed_count, total_millis)| {
+                let metric = Metric::from_str(&name).ok()?;
+                let average_duration = (timed_count > 0)
+                    .then(|| Duration::from_millis((total_millis / timed_count) as u64));
+
+                Some(MetricSummary {
+                    metric,
+                    count: count as u64,
+                    average_duration,
+                })
+            })
+            .collect();
+
+        Ok(metrics)
+    }
+
+    /// Deletes every recorded metric.
+    pub fn reset_metrics(&mut self) -> Result<(), QueryError> {
+        delete(usage_metrics::table)
+            .execute(&mut *self.connection)
+            .during(|| Operation::ResetMetrics)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "network")]
+    use futures_util::future::BoxFuture;
+    #[cfg(feature = "network")]
+    use reqwest::header::HeaderMap;
+    #[cfg(feature = "network")]
+    use url::Url;
+
+    use super::*;
+    use crate::repo::RepoManager;
+    #[cfg(feature = "network")]
+    use crate::{
+        Result,
+        repo::{HttpResponse, HttpTransport},
+    };
+
+    #[test]
+    fn only_records_when_enabled() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        db.record_metric(Metric::RepoUpdate, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(db.metrics().unwrap().is_empty());
+
+        db.set_telemetry(true).unwrap();
+        db.record_metric(Metric::RepoUpdate, Some(Duration::from_secs(1)))
+            .unwrap();
+        db.record_metric(Metric::RepoUpdate, Some(Duration::from_secs(3)))
+            .unwrap();
+        db.record_metric(Metric::ResolutionFailure, None).unwrap();
+
+        let metrics = db.metrics().unwrap();
+        assert_eq!(
+            metrics,
+            [
+                MetricSummary {
+                    metric: Metric::RepoUpdate,
+                    count: 2,
+                    average_duration: Some(Duration::from_secs(2)),
+                },
+                MetricSummary {
+                    metric: Metric::ResolutionFailure,
+                    count: 1,
+                    average_duration: None,
+                },
+            ]
+        );
+
+        db.reset_metrics().unwrap();
+        assert!(db.metrics().unwrap().is_empty());
+    }
+
+    /// Fails the test if anything is sent over the network.
+    #[cfg(feature = "network")]
+    struct NoNetwork;
+
+    #[cfg(feature = "network")]
+    impl HttpTransport for NoNetwork {
+        fn get_streaming(&self, url: &Url, _: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>> {
+            panic!("metrics must stay on the device, but {url} was requested");
+        }
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn never_uses_the_network() {
+        let mgr = RepoManager::builder(":memory:")
+            .transport(NoNetwork)
+            .build()
+            .unwrap();
+        mgr.db().unwrap().set_telemetry(true).unwrap();
+
+        mgr.record_metric(Metric::Download, Some(Duration::from_secs(1)));
+        mgr.record_metric(Metric::DownloadFailure, None);
+        let mut db = mgr.db().unwrap();
+        assert_eq!(db.metrics().unwrap().len(), 2);
+        db.reset_metrics().unwrap();
+    }
+}
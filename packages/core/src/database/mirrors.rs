@@ -0,0 +1,191 @@
+//! What happened the last time each download URL was used.
+//!
+//! Releases can list several URLs for the same file. Remembering which of them
+//! have worked lets the download queue try the most reliable one first.
+
+use std::{collections::HashMap, ops::DerefMut};
+
+use diesel::{
+    dsl::sql,
+    insert_into,
+    prelude::*,
+    sql_types::{Nullable, Text},
+    upsert::excluded,
+};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::database::{
+    JsonbValue, Operation, QueryContext, QueryError, RepoDB, canonical_url, schema::*,
+};
+
+/// The download history of one URL.
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable, uniffi::Record)]
+#[diesel(table_name = download_mirrors)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct MirrorStats {
+    #[diesel(deserialize_as = JsonbValue)]
+    pub url: Url,
+    /// The `Content-Type` the server last sent for the file.
+    pub content_type: Option<String>,
+    /// The size of the file the last time it was downloaded.
+    pub observed_size: Option<i64>,
+    pub last_success: Option<OffsetDateTime>,
+    pub last_failure: Option<OffsetDateTime>,
+    /// Why the last failed download failed.
+    pub failure_reason: Option<String>,
+}
+
+impl MirrorStats {
+    /// Whether the last download from this URL worked.
+    pub fn last_succeeded(&self) -> bool {
+        match (self.last_success, self.last_failure) {
+            (Some(success), Some(failure)) => success >= failure,
+            (success, _) => success.is_some(),
+        }
+    }
+
+    /// A sort key which puts URLs that worked last time first, most recent
+    /// first. Those that failed last time go last, with the oldest failure
+    /// first since it's had the longest to recover.
+    fn preference(stats: Option<&Self>) -> (u8, i128) {
+        match stats {
+            Some(stats) if stats.last_succeeded() => {
+                let success = stats
+                    .last_success
+                    .map_or(0, |time| time.unix_timestamp_nanos());
+                (0, -success)
+            }
+            Some(stats) => {
+                let failure = stats
+                    .last_failure
+                    .map_or(0, |time| time.unix_timestamp_nanos());
+                (2, failure)
+            }
+            None => (1, 0),
+        }
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Records that a file was downloaded from a URL.
+    pub fn record_mirror_success(
+        &mut self,
+        url: &Url,
+        size: u64,
+        content_type: Option<&str>,
+    ) -> Result<(), QueryError> {
+        let size = i64::try_from(size).unwrap_or(i64::MAX);
+
+        insert_into(download_mirrors::table)
+            .values((
+                download_mirrors::url.eq(JsonbValue::url_key(url)),
+                download_mirrors::content_type.eq(content_type),
+                download_mirrors::observed_size.eq(size),
+                download_mirrors::last_success.eq(OffsetDateTime::now_utc()),
+            ))
+            .on_conflict(download_mirrors::url)
+            .do_update()
+            .set((
+                // Resumed downloads don't always say what they are.
+                download_mirrors::content_type.eq(sql::<Nullable<Text>>(
+                    "COALESCE(excluded.content_type, download_mirrors.content_type)",
+                )),
+                download_mirrors::observed_size.eq(excluded(download_mirrors::observed_size)),
+                download_mirrors::last_success.eq(excluded(download_mirrors::last_success)),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordMirror {
+                url: url.to_string(),
+            })?;
+
+        Ok(())
+    }
+
+    /// Records that downloading a file from a URL failed, and why.
+    pub fn record_mirror_failure(&mut self, url: &Url, reason: &str) -> Result<(), QueryError> {
+        insert_into(download_mirrors::table)
+            .values((
+                download_mirrors::url.eq(JsonbValue::url_key(url)),
+                download_mirrors::last_failure.eq(OffsetDateTime::now_utc()),
+                download_mirrors::failure_reason.eq(reason),
+            ))
+            .on_conflict(download_mirrors::url)
+            .do_update()
+            .set((
+                download_mirrors::last_failure.eq(excluded(download_mirrors::last_failure)),
+                download_mirrors::failure_reason.eq(excluded(download_mirrors::failure_reason)),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordMirror {
+                url: url.to_string(),
+            })?;
+
+        Ok(())
+    }
+
+    /// The download history of each of the given URLs which has been used.
+    /// History is kept per [canonical URL](canonical_url), which is the URL
+    /// each result holds.
+    pub fn mirror_stats(&mut self, urls: &[Url]) -> Result<Vec<MirrorStats>, QueryError> {
+        download_mirrors::table
+            .filter(download_mirrors::url.eq_any(urls.iter().map(JsonbValue::url_key)))
+            .select(MirrorStats::as_select())
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadMirrors)
+    }
+
+    /// Orders a file's download URLs by which is most likely to work. URLs
+    /// with the same history keep their original order.
+    pub fn rank_mirrors(&mut self, urls: &[Url]) -> Result<Vec<Url>, QueryError> {
+        let stats = self
+            .mirror_stats(urls)?
+            .into_iter()
+            .map(|stats| (stats.url.clone(), stats))
+            .collect::<HashMap<_, _>>();
+
+        let mut ranked = urls.to_vec();
+        ranked.sort_by_key(|url| MirrorStats::preference(stats.get(&canonical_url(url))));
+        Ok(ranked)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::RepoManager;
+
+    #[test]
+    fn prefers_mirrors_which_worked() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        let urls: Vec<Url> = ["https://github.com/a.zip", "https://spacedock.info/a.zip"]
+            .map(|url| url.parse().unwrap())
+            .into();
+        let archive: Url = "https://archive.org/a.zip".parse().unwrap();
+        let all = [urls.clone(), vec![archive.clone()]].concat();
+
+        // Nothing is known yet, so the original order is kept.
+        assert_eq!(db.rank_mirrors(&all).unwrap(), all);
+
+        db.record_mirror_failure(&urls[0], "404 Not Found").unwrap();
+        db.record_mirror_success(&archive, 1024, Some("application/zip"))
+            .unwrap();
+        assert_eq!(
+            db.rank_mirrors(&all).unwrap(),
+            [archive.clone(), urls[1].clone(), urls[0].clone()]
+        );
+
+        // A resumed download doesn't forget the content type.
+        db.record_mirror_success(&archive, 2048, None).unwrap();
+        let stats = db.mirror_stats(&[archive]).unwrap().remove(0);
+        assert_eq!(stats.content_type.as_deref(), Some("application/zip"));
+        assert_eq!(stats.observed_size, Some(2048));
+        assert!(stats.last_succeeded());
+
+        // Other spellings of a URL share its history.
+        let respelled: Url = "https://ARCHIVE.org/a.zip#download".parse().unwrap();
+        assert!(db.mirror_stats(&[respelled]).unwrap()[0].last_succeeded());
+    }
+}
@@ -1,8 +1,14 @@
-use std::{borrow::Cow, ops::DerefMut, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, ops::DerefMut, sync::Arc};
 
 use derive_more::From;
-use diesel::{insert_into, prelude::*, replace_into, upsert::excluded};
+use diesel::{
+    delete, dsl::not, insert_into, prelude::*, replace_into, update, upsert::excluded,
+};
+#[cfg(feature = "network")]
 use reqwest::header::HeaderValue;
+#[cfg(feature = "network")]
+use time::OffsetDateTime;
+#[cfg(feature = "network")]
 use tokio::{runtime::Handle, task::block_in_place};
 use tracing::{debug, info, instrument, trace};
 use url::Url;
@@ -11,31 +17,170 @@ use crate::{
     Error,
     database::{
         models::{
-            BuildRecord, NewModule, NewRelease, ReleaseMetadata, Repository, RepositoryRef,
+            BuildRecord, NewModule, NewRelease, ReleaseMetadata, RepoFilter, Repository,
+            RepositoryRef,
             module::{
-                NewModuleAuthor, NewModuleLicense, NewModuleLocale, NewModuleRelationship, NewModuleRelationshipGroup, NewModuleTag
+                ModuleVersion, NewModuleAuthor, NewModuleLicense, NewModuleLocale,
+                NewModuleRelationship, NewModuleRelationshipGroup, NewModuleTag, RelationshipType,
             },
         },
         schema::*,
     },
-    json::JsonModule,
-    repo::client::RepoUnpackError,
+    json::{AuthorCredit, JsonModule},
+    policy::Policy,
 };
-
+#[cfg(feature = "network")]
+use crate::repo::client::RepoUnpackError;
+#[cfg(feature = "sqlcipher")]
+use crate::repo::DatabaseKey;
+
+mod backfill;
+mod backup;
+mod changes;
+mod channels;
+mod checkpoints;
+mod commits;
+mod compatibility;
+mod consistency;
+mod details;
+mod content_hash;
+mod downloads;
+mod error;
+mod favorites;
+mod filters;
+mod health;
 mod helpers;
+mod history;
+mod installed;
+mod json_rows;
+mod metrics;
+mod mirrors;
 pub mod models;
+mod pagination;
+mod plan_diff;
+mod policy;
+#[cfg(test)]
+mod query_plans;
+mod raw;
+mod release_notes;
+mod repo_terms;
+mod repo_url;
+mod retry;
+mod search;
+#[doc(hidden)]
 pub mod schema;
-
+mod settings;
+mod snapshot;
+mod staleness;
+mod suggestions;
+mod summaries;
+mod tags;
+mod update_lock;
+mod upgrades;
+mod url_key;
+mod validation;
+mod yanks;
+
+pub use backfill::{BackfillProgress, BackfillReporter};
+pub use changes::StateChange;
+pub use channels::{CHANNEL_PLACEHOLDER, ChannelError, detect_channel, expand_template};
+#[cfg(feature = "network")]
+pub(crate) use checkpoints::asset_hash;
+pub use checkpoints::ResumableUpdate;
+pub use commits::RepoCommit;
+pub use compatibility::{CompatibleRelease, GameCompatibility};
+pub use consistency::{ConsistencyReport, OrphanedRows};
+pub use details::{FullRelationship, ModuleDetail, ReleaseDetail};
+pub use downloads::{DownloadState, NewDownload, QueuedDownload};
+pub(crate) use error::QueryContext;
+pub use error::{Operation, QueryError};
+pub use favorites::{Favorite, ModuleNote};
+pub use filters::{FilterCondition, FilterParseError, FilterTerm, ModuleFilter, SavedFilter};
+pub use health::{RepoHealth, RepoOverview, RepoOverviewPage, RepoStatus};
 pub use helpers::*;
+pub use history::ReleaseHistory;
+pub use installed::InstalledModule;
+pub use json_rows::{JsonQueryError, NamedQuery, ParamKind};
+pub use metrics::{Metric, MetricSummary};
+pub use mirrors::MirrorStats;
+pub use pagination::{
+    CursorError, ModuleCursor, ModuleSummaryPage, ReleaseCursor, ReleasePage, RepoCursor,
+};
+pub use plan_diff::{PlanDiff, PlanWarning, PlannedAddition, PlannedRemoval, PlannedUpgrade};
+pub use release_notes::ReleaseNotes;
+#[cfg(feature = "network")]
+pub(crate) use release_notes::github_repo;
+pub use repo_terms::RepoTerms;
+pub use retry::{RetryPolicy, RetryableError, is_busy};
+pub use search::{CancellationToken, SearchResult};
+pub use snapshot::Snapshot;
+pub use staleness::{ModuleStaleness, Staleness};
+pub use suggestions::RepoSuggestion;
+pub use summaries::ModuleSummary;
+pub use tags::{TAG_SEPARATOR, TagCount, TagGroup};
+pub use update_lock::{UpdateLock, UpdateLockHolder};
+pub use upgrades::AvailableUpgrade;
+pub use url_key::canonical_url;
+pub(crate) use raw::register_handle_function;
+pub(crate) use url_key::register_functions as register_url_functions;
+pub use validation::{ReleaseFinding, ReleaseValidation, validate_release};
+pub use yanks::YankedRelease;
+
+/// How download counts are handled for identifiers which don't match a module
+/// in the repository.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadCountMode {
+    /// Skip them, so that only modules with releases are listed.
+    #[default]
+    ExistingOnly,
+    /// Create a module with no releases to hold each count.
+    CreateModules,
+}
 
 #[derive(From)]
 pub struct RepoDB<T> {
-    pub connection: T,
+    pub(crate) connection: T,
+    pub retry_policy: RetryPolicy,
+    /// Whether release metadata is compressed when it's saved.
+    pub compress_metadata: bool,
+    /// Which releases can be chosen when modules are resolved.
+    pub policy: Arc<Policy>,
+    /// The key the database is encrypted with, if it is.
+    #[cfg(feature = "sqlcipher")]
+    pub(crate) key: Option<DatabaseKey>,
 }
 
 impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
     pub fn new(connection: T) -> Self {
-        Self { connection }
+        Self {
+            connection,
+            retry_policy: RetryPolicy::default(),
+            compress_metadata: false,
+            policy: Arc::default(),
+            #[cfg(feature = "sqlcipher")]
+            key: None,
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn with_metadata_compression(mut self, compress: bool) -> Self {
+        self.compress_metadata = compress;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: Arc<Policy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    pub(crate) fn with_key(mut self, key: Option<DatabaseKey>) -> Self {
+        self.key = key;
+        self
     }
 
     #[instrument(skip_all)]
@@ -44,19 +189,51 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
         func: impl FnOnce(RepoDB<&mut SqliteConnection>) -> Result<R, Error>,
     ) -> Result<R, Error> {
         trace!("Performing a transaction");
-        self.connection.transaction(|conn| func(RepoDB::new(conn)))
+        let policy = self.retry_policy;
+        let compress = self.compress_metadata;
+        let access_policy = self.policy.clone();
+        #[cfg(feature = "sqlcipher")]
+        let key = self.key.clone();
+        self.connection.transaction(|conn| {
+            let db = RepoDB::new(conn)
+                .with_retry_policy(policy)
+                .with_metadata_compression(compress)
+                .with_policy(access_policy);
+            #[cfg(feature = "sqlcipher")]
+            let db = db.with_key(key);
+            func(db)
+        })
+    }
+
+    /// Runs a read-only query, running it again (up to the limit set by this
+    /// database's [`RetryPolicy`]) if SQLite reports that the database is
+    /// busy.
+    ///
+    /// The query may be run more than once, so it must not modify the
+    /// database.
+    pub fn read_with_retry<R, E: RetryableError>(
+        &mut self,
+        mut query: impl FnMut(&mut SqliteConnection) -> Result<R, E>,
+    ) -> Result<R, Error> {
+        let conn = &mut *self.connection;
+
+        self.retry_policy
+            .run(|| query(conn))
+            .map_err(|(source, attempts)| source.into_error(attempts))
     }
 
     /// Fetches all repositories from the database, ordered by name. If
     /// `create_default` is specified and no repos currently exist, the
     /// default repo will be created and returned.
     #[instrument(skip(self))]
-    pub fn all_repos(&mut self, create_default: bool) -> QueryResult<Vec<Repository>> {
+    pub fn all_repos(&mut self, create_default: bool) -> Result<Vec<Repository>, QueryError> {
         use schema::repositories::dsl::*;
 
         debug!("Loading repository list");
 
-        let mut repos = Repository::all().get_results(&mut *self.connection)?;
+        let mut repos = Repository::all()
+            .get_results(&mut *self.connection)
+            .during(|| Operation::LoadRepos)?;
 
         if create_default && repos.is_empty() {
             info!("Creating default repository");
@@ -68,36 +245,96 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
             repos = insert_into(repositories)
                 .values(default_repo)
                 .returning(Repository::as_returning())
-                .get_results(&mut *self.connection)?;
+                .get_results(&mut *self.connection)
+                .during(|| Operation::CreateRepo {
+                    name: "KSP-default".into(),
+                })?;
         }
 
         Ok(repos)
     }
 
-    /// Create a new repository with the given name. Any previous repository
-    /// with the same name will be overwritten.
+    /// Create a new repository with the given name. If a repository with the
+    /// same name and URL already exists, it's returned unchanged. If one with
+    /// the same name has a different URL, it's kept and this fails with an
+    /// error for which [`QueryError::is_conflict`] is true.
     #[instrument(skip_all)]
-    pub fn create_empty_repo(&mut self, new_repo: RepositoryRef<'_>) -> QueryResult<Repository> {
+    pub fn create_empty_repo(
+        &mut self,
+        new_repo: RepositoryRef<'_>,
+    ) -> Result<Repository, QueryError> {
         use schema::repositories::dsl::*;
 
+        let repo_name = new_repo.name.clone().into_owned();
+        let existing = Repository::all()
+            .filter(name.eq(&repo_name))
+            .get_result::<Repository>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::CreateRepo {
+                name: repo_name.clone(),
+            })?;
+        if let Some(existing) = existing
+            && canonical_url(&existing.url) == canonical_url(&new_repo.url)
+        {
+            debug!(name = repo_name, "Repository already exists");
+            return Ok(existing);
+        }
+
         info!(
             name = ?new_repo.name,
             url = ?new_repo.url,
             "Creating an empty repository"
         );
 
-        let id = replace_into(repositories)
+        // Never replace: that would delete the repository with the same name
+        // and every module in it.
+        insert_into(repositories)
             .values(new_repo)
             .returning(Repository::as_returning())
-            .get_result(&mut *self.connection)?;
+            .get_result(&mut *self.connection)
+            .during(|| Operation::CreateRepo { name: repo_name })
+    }
 
-        Ok(id)
+    /// Change which releases are kept the next time the repository is
+    /// unpacked. An empty filter keeps every release.
+    #[instrument(skip(self))]
+    pub fn set_repo_filter(
+        &mut self,
+        repo: RepoId,
+        filter: &RepoFilter,
+    ) -> Result<(), QueryError> {
+        let value = JsonbValue::from((!filter.is_empty()).then_some(filter));
+        update(repositories::table.find(repo))
+            .set(repositories::filter.eq(value))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SetRepoFilter { repo })?;
+
+        Ok(())
+    }
+
+    /// Change a repository's priority, which is kept with the repository like
+    /// CKAN's. Fails if the repository doesn't exist.
+    #[instrument(skip(self))]
+    pub fn set_repo_priority(&mut self, repo: RepoId, priority: i32) -> Result<(), QueryError> {
+        update(repositories::table.find(repo))
+            .set(repositories::priority.eq(priority))
+            .returning(repositories::repo_id)
+            .get_result::<RepoId>(&mut *self.connection)
+            .during(|| Operation::SetRepoPriority { repo })?;
+
+        Ok(())
     }
 
     /// Register a module with the given name. This will never overwrite any
     /// module, it just ensures one exists and returns its ID.
     #[instrument(skip_all)]
-    pub fn register_module(&mut self, new_module: NewModule) -> QueryResult<ModuleId> {
+    pub fn register_module(&mut self, new_module: NewModule) -> Result<ModuleId, QueryError> {
+        let slug = new_module.slug;
+        self.insert_module(new_module)
+            .during(|| Operation::RegisterModule { slug: slug.to_string() })
+    }
+
+    fn insert_module(&mut self, new_module: NewModule) -> QueryResult<ModuleId> {
         use schema::modules::dsl::*;
 
         debug!(
@@ -117,6 +354,44 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
         Ok(id)
     }
 
+    /// Register an author with the given name, returning its ID. Names which
+    /// only differ by case refer to the same author.
+    #[instrument(skip(self))]
+    pub fn register_author(&mut self, name: &str) -> Result<AuthorId, QueryError> {
+        self.insert_author(name)
+            .during(|| Operation::RegisterAuthor { name: name.into() })
+    }
+
+    fn insert_author(&mut self, name: &str) -> QueryResult<AuthorId> {
+        insert_author(&mut self.connection, name)
+    }
+
+    /// Remove authors who are no longer credited on any release.
+    #[instrument(skip_all)]
+    pub fn prune_authors(&mut self) -> Result<usize, QueryError> {
+        let credited = module_authors::table.select(module_authors::author_id);
+
+        delete(authors::table)
+            .filter(not(authors::author_id.eq_any(credited)))
+            .execute(&mut *self.connection)
+            .during(|| Operation::PruneAuthors)
+    }
+
+    /// Brings everything derived from a repository's releases up to date
+    /// after an update changed them: its history, content hash and search
+    /// index, authors who are no longer credited, and the validation and
+    /// staleness of every release.
+    #[instrument(skip(self))]
+    pub fn finish_update(&mut self, repo: RepoId) -> Result<(), QueryError> {
+        self.record_history(repo)?;
+        self.prune_authors()?;
+        self.update_content_hash(repo)?;
+        self.rebuild_search_index()?;
+        self.validate_releases()?;
+        self.update_staleness()?;
+        Ok(())
+    }
+
     /// Registers a release for either a new or pre-existing module
     /// (in which case, the module id can be provided.)
     ///
@@ -127,17 +402,30 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
         json: &JsonModule,
         repo_id: RepoId,
         module_id: Option<ModuleId>,
-    ) -> QueryResult<(ModuleId, ReleaseId)> {
+    ) -> Result<(ModuleId, ReleaseId), QueryError> {
         debug!(
             mod_name = ?json.name,
             version = ?json.version,
             "Creating release"
         );
 
+        self.insert_release(json, repo_id, module_id)
+            .during(|| Operation::CreateRelease {
+                slug: json.identifier.to_string(),
+                version: json.version.clone(),
+            })
+    }
+
+    fn insert_release(
+        &mut self,
+        json: &JsonModule,
+        repo_id: RepoId,
+        module_id: Option<ModuleId>,
+    ) -> QueryResult<(ModuleId, ReleaseId)> {
         let module_id = if let Some(id) = module_id {
             id
         } else {
-            self.register_module(NewModule {
+            self.insert_module(NewModule {
                 repo_id,
                 slug: &json.identifier,
             })?
@@ -158,7 +446,7 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
             display_name: &json.name,
             kind: json.kind,
             summary: &json.r#abstract,
-            metadata,
+            metadata: JsonBlob::new(metadata, self.compress_metadata),
             description: json.description.as_deref(),
             release_status: json.release_status,
             game_version: if !json.ksp_version.is_empty() {
@@ -167,10 +455,13 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
                 json.ksp_version_min.into()
             },
             game_version_min: json.ksp_version_min.into(),
+            game_version_max: (!json.ksp_version_max.is_empty()).then_some(*json.ksp_version_max),
             game_version_strict: json.ksp_version_strict,
             download_size: json.download_size,
             install_size: json.install_size,
             release_date: json.release_date,
+            sort_key: ModuleVersion::from(json.version.as_str()).sort_key(),
+            spec_version: json.spec_version.to_string(),
         };
 
         // Some mods have duplicate releases, which isn't allowed but it's better to
@@ -202,12 +493,16 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
             .author
             .iter()
             .enumerate()
-            .map(|(ordinal, author)| NewModuleAuthor {
-                release_id,
-                ordinal: ordinal.try_into().unwrap(),
-                author,
+            .map(|(ordinal, author)| {
+                let credit = AuthorCredit::parse(author);
+                Ok(NewModuleAuthor {
+                    release_id,
+                    ordinal: ordinal.try_into().unwrap(),
+                    author_id: self.insert_author(&credit.name)?,
+                    role: credit.role,
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<QueryResult<Vec<_>>>()?;
 
         insert_into(module_authors::table)
             .values(authors)
@@ -267,7 +562,8 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
                     ordinal: ordinal.try_into().unwrap(),
                     target_name: &member.name,
                     target_version: member.max_version.as_deref().or(member.version.as_deref()),
-                    target_version_min: member.min_version.as_deref(),
+                    // An exact version is both the minimum and the maximum.
+                    target_version_min: member.min_version.as_deref().or(member.version.as_deref()),
                 })
                 .collect::<Vec<_>>();
 
@@ -276,27 +572,78 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
                 .execute(&mut *self.connection)?;
         }
 
+        // Provided identifiers are stored as single-member groups, so they can be
+        // looked up the same way as any other relationship.
+        let first_ordinal = json.relationships().count();
+        for (ordinal, provided) in json.provides.iter().enumerate() {
+            let group = NewModuleRelationshipGroup {
+                release_id,
+                ordinal: (first_ordinal + ordinal).try_into().unwrap(),
+                rel_type: RelationshipType::Provides,
+                choice_help_text: None,
+                suppress_recommendations: false,
+            };
+
+            let group_id = insert_into(module_relationship_groups::table)
+                .values(group)
+                .returning(module_relationship_groups::group_id)
+                .get_result::<DepGroupId>(&mut *self.connection)?;
+
+            insert_into(module_relationships::table)
+                .values(NewModuleRelationship {
+                    group_id,
+                    ordinal: 0,
+                    target_name: provided,
+                    target_version: None,
+                    target_version_min: None,
+                })
+                .execute(&mut *self.connection)?;
+        }
+
         Ok((module_id, release_id))
     }
 
     /// Add the given builds to the build-id/version map.
     #[instrument(skip_all)]
-    pub fn register_builds(&mut self, new_builds: Vec<BuildRecord>) -> QueryResult<()> {
+    pub fn register_builds(&mut self, new_builds: Vec<BuildRecord>) -> Result<(), QueryError> {
         use schema::builds::dsl::*;
 
         debug!(count = %new_builds.len(), "Registering new builds");
 
         replace_into(builds)
             .values(new_builds)
-            .execute(&mut *self.connection)?;
+            .execute(&mut *self.connection)
+            .during(|| Operation::RegisterBuilds)?;
 
         Ok(())
     }
 
-    /// Attach the given download counts to their corresponding modules. Creates
-    /// the modules if they don't exist yet.
+    /// Attach the given download counts to their corresponding modules.
+    ///
+    /// With [`DownloadCountMode::ExistingOnly`], only modules whose count has
+    /// changed are written, and the identifiers which don't match any module
+    /// are returned in alphabetical order.
     #[instrument(skip(self, counts))]
-    pub fn add_download_counts<'a, C>(&mut self, repo: RepoId, counts: C) -> QueryResult<()>
+    pub fn add_download_counts<'a, C>(
+        &mut self,
+        repo: RepoId,
+        counts: C,
+        mode: DownloadCountMode,
+    ) -> Result<Vec<String>, QueryError>
+    where
+        C: IntoIterator<Item = (&'a String, &'a i32)>,
+        C::IntoIter: ExactSizeIterator,
+    {
+        self.insert_download_counts(repo, counts, mode)
+            .during(|| Operation::AddDownloadCounts { repo })
+    }
+
+    fn insert_download_counts<'a, C>(
+        &mut self,
+        repo: RepoId,
+        counts: C,
+        mode: DownloadCountMode,
+    ) -> QueryResult<Vec<String>>
     where
         C: IntoIterator<Item = (&'a String, &'a i32)>,
         C::IntoIter: ExactSizeIterator,
@@ -306,37 +653,75 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
         let counts = counts.into_iter();
         debug!(num_counts = %counts.len(), "Adding download counts to modules");
 
-        let rows = counts
-            .map(|(slug, count)| {
-                (
-                    repo_id.eq(repo),
-                    module_slug.eq(slug),
-                    download_count.eq(count),
-                )
-            })
-            .collect::<Vec<_>>();
+        if mode == DownloadCountMode::CreateModules {
+            let rows = counts
+                .map(|(slug, count)| {
+                    (
+                        repo_id.eq(repo),
+                        module_slug.eq(slug),
+                        download_count.eq(count),
+                    )
+                })
+                .collect::<Vec<_>>();
 
-        insert_into(modules)
-            .values(rows)
-            .on_conflict((repo_id, module_slug))
-            .do_update()
-            .set(download_count.eq(excluded(download_count)))
-            .execute(&mut *self.connection)?;
+            insert_into(modules)
+                .values(rows)
+                .on_conflict((repo_id, module_slug))
+                .do_update()
+                .set(download_count.eq(excluded(download_count)))
+                .execute(&mut *self.connection)?;
 
-        Ok(())
+            return Ok(vec![]);
+        }
+
+        let existing = modules
+            .filter(repo_id.eq(repo))
+            .select((module_slug, (module_id, download_count)))
+            .load::<(String, (ModuleId, i32))>(&mut *self.connection)?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        let mut unmatched = vec![];
+
+        for (slug, &count) in counts {
+            match existing.get(slug) {
+                Some(&(_, current)) if current == count => {}
+                Some(&(id, _)) => {
+                    update(modules.find(id))
+                        .set(download_count.eq(count))
+                        .execute(&mut *self.connection)?;
+                }
+                None => unmatched.push(slug.clone()),
+            }
+        }
+
+        unmatched.sort_unstable();
+        Ok(unmatched)
     }
 
     #[instrument(skip(self))]
-    pub fn add_repo_ref(&mut self, referrer: RepoId, new_ref: RepositoryRef) -> QueryResult<()> {
+    pub fn add_repo_ref(
+        &mut self,
+        referrer: RepoId,
+        new_ref: RepositoryRef,
+    ) -> Result<(), QueryError> {
         use schema::repository_refs::dsl::*;
 
+        let ref_name = new_ref.name.clone().into_owned();
         replace_into(repository_refs)
             .values((referrer_id.eq(referrer), new_ref))
-            .execute(&mut *self.connection)?;
+            .execute(&mut *self.connection)
+            .during(|| Operation::AddRepoRef {
+                repo: referrer,
+                name: ref_name,
+            })?;
 
         Ok(())
     }
 
+    /// Records the ETag of a repository which was just downloaded, along with
+    /// the time of the download. The ETag is keyed on the URL's canonical form.
+    #[cfg(feature = "network")]
     pub fn set_etag(
         &mut self,
         source_url: Arc<Url>,
@@ -344,24 +729,67 @@ impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
     ) -> Result<(), Error> {
         use schema::etags::dsl::*;
 
-        let encoded_url = JsonbValue::from(&*source_url);
+        let encoded_url = JsonbValue::url_key(&source_url);
         let etag_str = if let Some(value) = etag_header {
             let str = value
                 .to_str()
-                .map_err(|_| RepoUnpackError::InvalidEtag { url: source_url })?;
+                .map_err(|_| RepoUnpackError::InvalidEtag { url: source_url.clone() })?;
             Some(str)
         } else {
             None
         };
 
         replace_into(etags)
-            .values((url.eq(encoded_url), etag.eq(etag_str)))
-            .execute(&mut *self.connection)?;
+            .values((
+                url.eq(encoded_url),
+                etag.eq(etag_str),
+                updated_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SetEtag {
+                url: (*source_url).clone(),
+            })?;
 
         Ok(())
     }
+
+    /// Records that the repository at `source_url` was just checked and found
+    /// to be unchanged since it was last downloaded, keeping its ETag.
+    #[cfg(feature = "network")]
+    pub fn touch_etag(&mut self, source_url: &Url) -> Result<(), QueryError> {
+        use schema::etags::dsl::*;
+
+        let encoded_url = JsonbValue::url_key(source_url);
+        update(etags.find(encoded_url))
+            .set(updated_at.eq(OffsetDateTime::now_utc()))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SetEtag {
+                url: source_url.clone(),
+            })?;
+
+        Ok(())
+    }
+
+    /// Returns the ETag of the repository at `source_url` when it was last
+    /// downloaded, if the server sent one.
+    pub fn etag(&mut self, source_url: &Url) -> Result<Option<String>, QueryError> {
+        use schema::etags::dsl::*;
+
+        let encoded_url = JsonbValue::url_key(source_url);
+        let saved = etags
+            .find(encoded_url)
+            .select(etag)
+            .get_result::<Option<String>>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadEtag {
+                url: source_url.clone(),
+            })?;
+
+        Ok(saved.flatten())
+    }
 }
 
+#[cfg(feature = "network")]
 impl<T: DerefMut<Target = SqliteConnection> + Send> RepoDB<T> {
     #[instrument(skip_all)]
     pub fn async_transaction<R>(
@@ -369,22 +797,52 @@ impl<T: DerefMut<Target = SqliteConnection> + Send> RepoDB<T> {
         func: impl AsyncFnOnce(RepoDB<&mut SqliteConnection>) -> Result<R, Error>,
     ) -> Result<R, Error> {
         trace!("Performing a transaction");
+        let policy = self.retry_policy;
+        let compress = self.compress_metadata;
+        let access_policy = self.policy.clone();
         block_in_place(|| {
             self.connection.transaction(|conn| {
-                Handle::current().block_on(async move { func(RepoDB::new(conn)).await })
+                Handle::current().block_on(async move {
+                    let db = RepoDB::new(conn)
+                        .with_retry_policy(policy)
+                        .with_metadata_compression(compress)
+                        .with_policy(access_policy);
+                    func(db).await
+                })
             })
         })
     }
 }
 
-impl<T: DerefMut<Target = SqliteConnection>> AsRef<T> for RepoDB<T> {
-    fn as_ref(&self) -> &T {
-        &self.connection
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// The connection, for tests and benchmarks of queries which don't have a
+    /// method. The schema isn't part of the supported API, so this is only
+    /// public with the `internals` feature.
+    #[cfg(any(test, feature = "internals"))]
+    #[doc(hidden)]
+    pub fn connection_mut(&mut self) -> &mut T {
+        &mut self.connection
     }
 }
 
-impl<T: DerefMut<Target = SqliteConnection>> AsMut<T> for RepoDB<T> {
-    fn as_mut(&mut self) -> &mut T {
-        &mut self.connection
+/// Adds an author, or finds the one with the same name, returning its ID.
+///
+/// Names are compared ignoring case, and an author keeps the capitalisation
+/// they were first added with.
+pub(crate) fn insert_author(conn: &mut SqliteConnection, name: &str) -> QueryResult<AuthorId> {
+    let inserted = insert_into(authors::table)
+        .values(authors::name.eq(name))
+        .on_conflict(authors::name)
+        .do_nothing()
+        .returning(authors::author_id)
+        .get_result(conn)
+        .optional()?;
+
+    match inserted {
+        Some(author) => Ok(author),
+        None => authors::table
+            .filter(authors::name.eq(name))
+            .select(authors::author_id)
+            .get_result(conn),
     }
 }
@@ -8,8 +8,8 @@ use crate::{
 pub mod module;
 pub mod repository;
 
-pub use module::{Module, ModuleRelease, NewModule, NewRelease, ReleaseMetadata};
-pub use repository::{Repository, RepositoryRef};
+pub use module::{Author, Module, ModuleRelease, NewModule, NewRelease, ReleaseMetadata};
+pub use repository::{RepoFilter, Repository, RepositoryRef};
 
 #[derive(Debug, Queryable, Selectable, Insertable, uniffi::Record)]
 #[diesel(table_name = builds)]
@@ -17,25 +17,32 @@ use url::Url;
 
 use crate::{
     database::{
-        DepGroupId, DepId, JsonbValue, ModAuthorId, ModuleId, ReleaseId, RepoId,
+        AuthorId, DepGroupId, DepId, JsonBlob, JsonbValue, ModAuthorId, ModuleId, ReleaseId,
+        RepoId, SanitizedColumn,
         models::Repository, schema::*,
     },
-    json::{DownloadChecksum, ModuleInstallDescriptor, ModuleKind, ModuleResources, ReleaseStatus},
+    json::{
+        DownloadChecksum, Identifier, ModuleInstallDescriptor, ModuleKind, ModuleResources,
+        ReleaseStatus,
+    },
+    markdown::SanitizedText,
     repo::game::GameVersion,
 };
 
 mod version;
 
-pub use version::ModuleVersion;
+pub(crate) use version::register_functions;
+pub use version::{ModuleVersion, version_sort_key};
 
 pub type AllModules = Select<modules::table, AsSelect<Module, Sqlite>>;
 pub type AllReleases = Select<module_releases::table, AsSelect<ModuleRelease, Sqlite>>;
+pub type AllAuthors = Select<authors::table, AsSelect<Author, Sqlite>>;
 type AllDepGroups =
     Select<module_relationship_groups::table, AsSelect<ModuleRelationshipGroup, Sqlite>>;
 type AllDeps = Select<module_relationships::table, AsSelect<ModuleRelationship, Sqlite>>;
 type Sel<T> = AsSelect<T, Sqlite>;
 
-#[derive(Debug, Queryable, Selectable, Identifiable, Associations, uniffi::Record)]
+#[derive(Debug, Queryable, Selectable, Identifiable, Associations)]
 #[diesel(table_name = modules)]
 #[diesel(primary_key(module_id))]
 #[diesel(belongs_to(Repository, foreign_key = repo_id))]
@@ -60,9 +67,26 @@ impl Module {
     }
 
     #[dsl::auto_type(no_type_alias)]
-    pub fn with_slug(slug: &'_ str) -> _ {
+    pub fn with_slug(slug: &'_ Identifier) -> _ {
         modules::module_slug.eq(slug)
     }
+
+    #[dsl::auto_type(no_type_alias)]
+    pub fn by_slug() -> _ {
+        modules::module_slug
+    }
+
+    /// Filters for modules with at least one release credited to the given
+    /// author. Author names are matched case-insensitively.
+    #[dsl::auto_type(no_type_alias)]
+    pub fn with_author(name: &'_ str) -> _ {
+        modules::module_id.eq_any(
+            module_releases::table
+                .inner_join(module_authors::table.inner_join(authors::table))
+                .filter(authors::name.eq(name))
+                .select(module_releases::module_id),
+        )
+    }
 }
 
 #[derive(Debug, Insertable)]
@@ -71,7 +95,7 @@ impl Module {
 pub struct NewModule<'a> {
     pub repo_id: RepoId,
     #[diesel(column_name = module_slug)]
-    pub slug: &'a str,
+    pub slug: &'a Identifier,
 }
 
 #[derive(Debug, Insertable)]
@@ -84,8 +108,8 @@ pub struct NewRelease<'a> {
     #[diesel(serialize_as = i32)]
     pub kind: ModuleKind,
     pub summary: &'a str,
-    #[diesel(serialize_as = JsonbValue)]
-    pub metadata: ReleaseMetadata<'a>,
+    /// The release's [`ReleaseMetadata`].
+    pub metadata: JsonBlob,
     pub description: Option<&'a str>,
     #[diesel(serialize_as = i32)]
     pub release_status: ReleaseStatus,
@@ -93,13 +117,19 @@ pub struct NewRelease<'a> {
     pub game_version: GameVersion,
     #[diesel(serialize_as = JsonbValue)]
     pub game_version_min: GameVersion,
+    #[diesel(serialize_as = JsonbValue)]
+    pub game_version_max: Option<GameVersion>,
     pub game_version_strict: bool,
     pub download_size: Option<i64>,
     pub install_size: Option<i64>,
     pub release_date: Option<OffsetDateTime>,
+    /// The version's [sort key](ModuleVersion::sort_key).
+    pub sort_key: Vec<u8>,
+    /// The release's `spec_version`, like `v1.4`.
+    pub spec_version: String,
 }
 
-#[derive(Debug, Queryable, Selectable, Identifiable, Associations, uniffi::Record)]
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, Associations)]
 #[diesel(table_name = module_releases)]
 #[diesel(primary_key(release_id))]
 #[diesel(belongs_to(Module))]
@@ -120,6 +150,10 @@ pub struct ModuleRelease {
     pub game_version: GameVersion,
     #[diesel(deserialize_as = JsonbValue)]
     pub game_version_min: GameVersion,
+    /// The newest version of the game the release supports, or `None` if it
+    /// has no upper bound.
+    #[diesel(deserialize_as = JsonbValue)]
+    pub game_version_max: Option<GameVersion>,
     pub game_version_strict: bool,
     pub download_size: Option<i64>,
     pub install_size: Option<i64>,
@@ -131,9 +165,11 @@ impl ModuleRelease {
         module_releases::table.select(ModuleRelease::as_select())
     }
 
+    /// Orders releases newest first. Releases saved before sort keys were
+    /// added fall back to the `MODULE_VERSION` collation.
     #[dsl::auto_type(no_type_alias)]
     pub fn by_version() -> _ {
-        module_releases::version
+        (module_releases::sort_key.desc(), module_releases::version)
     }
 
     #[dsl::auto_type(no_type_alias)]
@@ -141,6 +177,23 @@ impl ModuleRelease {
         module_releases::module_id.eq(module_id)
     }
 
+    /// Filters out releases which are less stable than the given status.
+    #[dsl::auto_type(no_type_alias)]
+    pub fn with_max_status(status: ReleaseStatus) -> _ {
+        let status: i32 = status.into();
+        module_releases::release_status.le(status)
+    }
+
+    /// Filters out the releases of the given module which have been yanked.
+    #[dsl::auto_type(no_type_alias)]
+    pub fn not_yanked(slug: String) -> _ {
+        module_releases::version.ne_all(
+            yanked_releases::table
+                .filter(yanked_releases::module_slug.eq(slug))
+                .select(yanked_releases::version),
+        )
+    }
+
     #[dsl::auto_type(no_type_alias)]
     pub fn tags_for(release: ReleaseId) -> _ {
         module_tags::table
@@ -152,7 +205,19 @@ impl ModuleRelease {
     #[dsl::auto_type(no_type_alias)]
     pub fn authors_for(release: ReleaseId) -> _ {
         module_authors::table
-            .select(module_authors::author)
+            .inner_join(authors::table)
+            .select(authors::name)
+            .filter(module_authors::release_id.eq(release))
+            .order(module_authors::ordinal)
+    }
+
+    /// The authors of a release with their roles, which load as
+    /// [`AuthorCredit`](crate::json::AuthorCredit)s.
+    #[dsl::auto_type(no_type_alias)]
+    pub fn credits_for(release: ReleaseId) -> _ {
+        module_authors::table
+            .inner_join(authors::table)
+            .select((authors::name, module_authors::role))
             .filter(module_authors::release_id.eq(release))
             .order(module_authors::ordinal)
     }
@@ -185,7 +250,7 @@ impl ModuleRelease {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReleaseMetadata<'a> {
     pub comment: Option<Cow<'a, str>>,
     pub download: Cow<'a, [Url]>,
@@ -198,20 +263,24 @@ pub struct ReleaseMetadata<'a> {
 #[derive(Debug, Insertable)]
 #[diesel(table_name = module_authors)]
 #[diesel(check_for_backend(Sqlite))]
-pub struct NewModuleAuthor<'a> {
+pub struct NewModuleAuthor {
     pub release_id: ReleaseId,
     pub ordinal: i32,
-    pub author: &'a str,
+    pub author_id: AuthorId,
+    pub role: Option<String>,
 }
 
 #[derive(Debug, Insertable, Identifiable, Associations, uniffi::Record)]
 #[diesel(table_name = module_authors)]
 #[diesel(belongs_to(ModuleRelease, foreign_key = release_id))]
+#[diesel(belongs_to(Author, foreign_key = author_id))]
 #[diesel(check_for_backend(Sqlite))]
 pub struct ModuleAuthor {
     pub id: ModAuthorId,
-    pub author: String,
+    pub author_id: AuthorId,
     pub release_id: ReleaseId,
+    /// What the author did on the release, like `maintainer`.
+    pub role: Option<String>,
 }
 
 impl ModuleAuthor {
@@ -221,6 +290,43 @@ impl ModuleAuthor {
     }
 }
 
+/// A person credited as an author of at least one release. Each name is only
+/// stored once, regardless of how many releases it appears on.
+#[derive(Debug, Queryable, Selectable, Identifiable, uniffi::Record)]
+#[diesel(table_name = authors)]
+#[diesel(primary_key(author_id))]
+#[diesel(check_for_backend(Sqlite))]
+pub struct Author {
+    #[diesel(column_name = author_id)]
+    pub id: AuthorId,
+    pub name: String,
+}
+
+impl Author {
+    pub fn all() -> AllAuthors {
+        authors::table.select(Self::as_select())
+    }
+
+    #[dsl::auto_type(no_type_alias)]
+    pub fn by_name() -> _ {
+        authors::name
+    }
+
+    /// Filters for authors whose name contains the given text, ignoring case.
+    pub fn name_contains(text: &str) -> dsl::Escape<dsl::Like<authors::name, String>> {
+        let pattern = format!("%{}%", escape_like(text));
+        authors::name.like(pattern).escape('\\')
+    }
+}
+
+/// Escapes the wildcard characters in `text` so it can be matched literally
+/// inside a `LIKE` pattern.
+pub(crate) fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[derive(Debug, Insertable)]
 #[diesel(table_name = module_licenses)]
 #[diesel(check_for_backend(Sqlite))]
@@ -268,6 +374,10 @@ pub struct ModuleRelationshipGroup {
     pub release_id: ReleaseId,
     pub ordinal: i32,
     pub rel_type: RelationshipType,
+    /// Help for choosing between the members of an `any_of` group, sanitized
+    /// like a module's description.
+    #[diesel(deserialize_as = SanitizedColumn)]
+    pub choice_help_text: Option<SanitizedText>,
 }
 
 impl ModuleRelationshipGroup {
@@ -286,11 +396,23 @@ impl ModuleRelationshipGroup {
 }
 
 #[derive(
-    Debug, AsExpression, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, TryFrom, uniffi::Enum,
+    Debug,
+    AsExpression,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    TryFrom,
+    Serialize,
+    Deserialize,
+    uniffi::Enum,
 )]
 #[diesel(sql_type = Integer)]
 #[try_from(repr)]
 #[repr(i32)]
+#[serde(rename_all = "snake_case")]
 pub enum RelationshipType {
     Depends,
     Recommends,
@@ -372,42 +494,3 @@ pub struct NewModuleReplacement<'a> {
     pub target_version: Option<&'a str>,
     pub target_version_min: Option<&'a str>,
 }
-
-type RMStatic = ReleaseMetadata<'static>;
-uniffi::custom_type!(RMStatic, ReleaseMetadataFFI);
-
-#[derive(uniffi::Record)]
-pub struct ReleaseMetadataFFI {
-    pub comment: Option<String>,
-    pub download: Vec<Url>,
-    pub download_hash: DownloadChecksum,
-    pub download_content_type: Option<String>,
-    pub resources: ModuleResources,
-    pub install: Vec<ModuleInstallDescriptor>,
-}
-
-impl From<ReleaseMetadata<'static>> for ReleaseMetadataFFI {
-    fn from(value: ReleaseMetadata) -> Self {
-        Self {
-            comment: value.comment.map(Cow::into_owned),
-            download: value.download.into_owned(),
-            download_hash: value.download_hash.into_owned(),
-            download_content_type: value.download_content_type.map(Cow::into_owned),
-            resources: value.resources.into_owned(),
-            install: value.install.into_owned(),
-        }
-    }
-}
-
-impl From<ReleaseMetadataFFI> for ReleaseMetadata<'static> {
-    fn from(value: ReleaseMetadataFFI) -> Self {
-        Self {
-            comment: value.comment.map(Cow::Owned),
-            download: Cow::Owned(value.download),
-            download_hash: Cow::Owned(value.download_hash),
-            download_content_type: value.download_content_type.map(Cow::Owned),
-            resources: Cow::Owned(value.resources),
-            install: Cow::Owned(value.install),
-        }
-    }
-}
@@ -5,7 +5,12 @@ use std::{
 };
 
 use diesel::{
-    Queryable, backend::Backend, deserialize::FromSql, expression::AsExpression, sql_types::Text,
+    QueryResult, Queryable,
+    backend::Backend,
+    deserialize::FromSql,
+    expression::{AsExpression, functions::declare_sql_function},
+    sql_types::Text,
+    sqlite::SqliteConnection,
 };
 
 #[derive(Debug, Clone, Eq, AsExpression, Hash)]
@@ -37,6 +42,43 @@ impl<'a> ModuleVersion<'a> {
     pub fn into_inner(self) -> Cow<'a, str> {
         self.string
     }
+
+    /// Encodes this version as bytes which compare the same way as the
+    /// version, so that releases can be ordered with a plain `BLOB`
+    /// comparison instead of calling the `MODULE_VERSION` collation.
+    ///
+    /// The key is the epoch, then one entry for each pair of text and number
+    /// that [`Ord::cmp`] walks through. A version with fewer pairs is a prefix
+    /// of one with more, so it sorts first.
+    pub fn sort_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(4 + self.string.len() * 2);
+        key.extend(self.epoch.unwrap_or_default().to_be_bytes());
+
+        let mut rest = self.mod_version();
+        while !rest.is_empty() {
+            let text = take_prefix(&mut rest, |c| !c.is_ascii_digit());
+            // See `str_cmp`: a bare dot beats a dot with metadata, which beats
+            // text without a dot.
+            key.push(match text {
+                "." => 3,
+                _ if text.starts_with('.') => 2,
+                _ => 1,
+            });
+            // Escape zeros so that the terminator sorts below any other byte.
+            for &byte in text.as_bytes() {
+                key.push(byte);
+                if byte == 0 {
+                    key.push(0xff);
+                }
+            }
+            key.extend([0, 0]);
+
+            let digits = take_prefix(&mut rest, |c| c.is_ascii_digit());
+            key.extend(parse_num(digits).to_be_bytes());
+        }
+
+        key
+    }
 }
 
 impl<'a> From<Cow<'a, str>> for ModuleVersion<'a> {
@@ -138,6 +180,21 @@ impl Ord for ModuleVersion<'_> {
     }
 }
 
+#[declare_sql_function]
+extern "SQL" {
+    /// The [sort key](ModuleVersion::sort_key) of a version, for ordering
+    /// versions which don't have a stored key, like those in the release
+    /// history. The newest version has the greatest key.
+    fn version_sort_key(version: Text) -> Binary;
+}
+
+/// Registers [`version_sort_key`] with a connection.
+pub(crate) fn register_functions(conn: &mut SqliteConnection) -> QueryResult<()> {
+    version_sort_key_utils::register_impl(&mut *conn, |version: String| {
+        ModuleVersion::from(version).sort_key()
+    })
+}
+
 /// Removes the non-digit prefix from the parameters, then compares those
 /// prefixes.
 fn str_cmp(left: &mut &str, right: &mut &str) -> Ordering {
@@ -192,10 +249,12 @@ fn num_cmp(left: &mut &str, right: &mut &str) -> Ordering {
     let left_prefix = take_prefix(left, |c| c.is_ascii_digit());
     let right_prefix = take_prefix(right, |c| c.is_ascii_digit());
 
-    let left_num = left_prefix.parse().unwrap_or(0);
-    let right_num = right_prefix.parse().unwrap_or(0);
+    parse_num(left_prefix).cmp(&parse_num(right_prefix))
+}
 
-    left_num.cmp(&right_num)
+/// Parses a run of digits, treating numbers too large to compare as zero.
+fn parse_num(digits: &str) -> i32 {
+    digits.parse().unwrap_or(0)
 }
 
 /// Returns the prefix of characters for which the given test function evaluates true.
@@ -433,4 +492,94 @@ mod test {
         assert_eq!(left, "kip");
         assert_eq!(right, "omega15");
     }
+
+    #[test]
+    fn sort_keys_match_cmp() {
+        let versions = [
+            "", "1", "1.0", "1.00", "1.0.", "1.0-rc", "1.0.1-rc", "1.0_rc", "1.0.rp", "1.0.1",
+            "1.1", "1.01", "1.1.0.0", "1.1.1", "1.4", "1.beta", "1.alpha", "1.15", "1.3", "v6a12",
+            "v6a5", "alpha", "banana", "0:alpha", "1:alpha", "2:banana", "1a", "1a0", "1.\0", "1.",
+            "0", "a:1", "1.1e99",
+        ];
+
+        for left in versions {
+            for right in versions {
+                let (left, right) = (ModuleVersion::from(left), ModuleVersion::from(right));
+                assert_eq!(
+                    left.sort_key().cmp(&right.sort_key()),
+                    left.cmp(&right),
+                    "{left} vs {right}"
+                );
+            }
+        }
+
+        // Numbers too large to compare count as zero.
+        assert_eq!(
+            ModuleVersion::from("1.99999999999").sort_key(),
+            ModuleVersion::from("1.0").sort_key()
+        );
+    }
+
+    #[test]
+    fn orders_versions_in_sql() {
+        use diesel::prelude::*;
+
+        use crate::{
+            database::schema::module_releases,
+            repo::test_support::{db_with_default_repo, release},
+        };
+
+        let (mut db, repo) = db_with_default_repo();
+
+        let mut module = None;
+        for version in ["1.3", "1.15", "1.4-beta", "0:2.0"] {
+            let release = release("Parallax", version).build();
+            let (id, _) = db.create_release(&release, repo.id, module).unwrap();
+            module = Some(id);
+        }
+
+        let versions = module_releases::table
+            .select(module_releases::version)
+            .order_by(version_sort_key(module_releases::version).desc())
+            .load::<String>(db.connection_mut())
+            .unwrap();
+        assert_eq!(versions, ["0:2.0", "1.15", "1.4-beta", "1.3"]);
+    }
+
+    #[test]
+    fn finds_newest_versions_with_max() {
+        use diesel::{
+            dsl::sql,
+            prelude::*,
+            sql_types::{Binary, Nullable},
+        };
+
+        use crate::{
+            database::schema::module_releases,
+            repo::test_support::{db_with_default_repo, release},
+        };
+
+        let (mut db, repo) = db_with_default_repo();
+
+        for (identifier, versions) in [
+            ("Parallax", ["1.3", "1.15", "1.4-beta"]),
+            ("Kopernicus", ["1:0.9", "2.0", "1.10"]),
+        ] {
+            let mut module = None;
+            for version in versions {
+                let release = release(identifier, version).build();
+                let (id, _) = db.create_release(&release, repo.id, module).unwrap();
+                module = Some(id);
+            }
+        }
+
+        let keys = module_releases::table
+            .group_by(module_releases::module_id)
+            .select(sql::<Nullable<Binary>>("MAX(sort_key)"))
+            .order_by(module_releases::module_id)
+            .load::<Option<Vec<u8>>>(db.connection_mut())
+            .unwrap();
+        let newest = ["1.15", "1:0.9"].map(|version| Some(ModuleVersion::from(version).sort_key()));
+        assert_eq!(keys, newest);
+    }
 }
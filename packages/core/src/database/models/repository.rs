@@ -8,11 +8,14 @@ use diesel::{
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::database::{JsonbValue, RepoId, schema::*};
+use crate::{
+    database::{JsonbValue, RepoId, schema::*},
+    json::{JsonModule, ModuleKind, ReleaseStatus, spec_version::SpecVersion},
+};
 
 type All = Select<repositories::table, AsSelect<Repository, Sqlite>>;
 
-#[derive(Debug, Queryable, Selectable, Identifiable, uniffi::Record)]
+#[derive(Debug, Queryable, Selectable, Identifiable)]
 #[diesel(table_name = repositories)]
 #[diesel(primary_key(repo_id))]
 #[diesel(check_for_backend(Sqlite))]
@@ -23,6 +26,30 @@ pub struct Repository {
     #[diesel(deserialize_as = JsonbValue)]
     pub url: Url,
     pub priority: i32,
+    /// Which releases are kept when the repository is unpacked, if they're
+    /// limited at all.
+    #[diesel(deserialize_as = JsonbValue)]
+    pub filter: Option<RepoFilter>,
+    /// Whether the repository is a mirror of another one.
+    pub x_mirror: bool,
+    /// A note about the repository, such as where a mirror is hosted.
+    pub x_comment: Option<String>,
+    /// A hash of the repository's releases as of its last update, which is
+    /// the same for any two databases holding the same releases.
+    pub content_hash: Option<String>,
+    /// The branch or tag the repository is downloaded from, if one was chosen.
+    pub channel: Option<String>,
+    /// The URL with `{channel}` in place of the channel, for switching to
+    /// another one.
+    pub url_template: Option<String>,
+    /// Where the repository was downloaded from at its last update, if its URL
+    /// redirected somewhere else.
+    #[diesel(deserialize_as = JsonbValue)]
+    pub final_url: Option<Url>,
+    /// Where the repository has moved, if its URL permanently redirected
+    /// somewhere else at its last update.
+    #[diesel(deserialize_as = JsonbValue)]
+    pub moved_url: Option<Url>,
 }
 
 impl Repository {
@@ -35,10 +62,49 @@ impl Repository {
             name: Cow::Borrowed(&self.name),
             url: Cow::Borrowed(&self.url),
             priority: self.priority,
+            x_mirror: self.x_mirror,
+            x_comment: self.x_comment.as_deref().map(Cow::Borrowed),
         }
     }
 }
 
+/// Limits on which releases are saved from a repository, for keeping only the
+/// parts of a large repository that are useful.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct RepoFilter {
+    /// Skip releases with any of these tags.
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    /// Only keep releases of these kinds. Every kind is kept if this is empty.
+    #[serde(default)]
+    pub only_kinds: Vec<ModuleKind>,
+    /// Skip releases which need a newer version of the metadata spec.
+    #[serde(default)]
+    pub max_spec_version: Option<SpecVersion>,
+    /// Skip releases which are less stable than this.
+    #[serde(default)]
+    pub max_release_status: Option<ReleaseStatus>,
+}
+
+impl RepoFilter {
+    /// Returns whether the filter keeps every release.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns whether the given release should be saved.
+    pub fn allows(&self, module: &JsonModule) -> bool {
+        !module.tags.iter().any(|tag| self.exclude_tags.contains(tag))
+            && (self.only_kinds.is_empty() || self.only_kinds.contains(&module.kind))
+            && self
+                .max_spec_version
+                .is_none_or(|max| module.spec_version <= max)
+            && self
+                .max_release_status
+                .is_none_or(|max| module.release_status <= max)
+    }
+}
+
 #[derive(Debug, Insertable, Deserialize, Serialize, PartialEq, Eq, Hash, Clone)]
 #[diesel(table_name = repositories)]
 #[diesel(table_name = repository_refs)]
@@ -50,6 +116,12 @@ pub struct RepositoryRef<'a> {
     pub url: Cow<'a, Url>,
     #[serde(default)]
     pub priority: i32,
+    /// Whether the repository is a mirror of another one.
+    #[serde(default)]
+    pub x_mirror: bool,
+    /// A note about the repository, such as where a mirror is hosted.
+    #[serde(default)]
+    pub x_comment: Option<Cow<'a, str>>,
 }
 
 impl<'a> RepositoryRef<'a> {
@@ -58,6 +130,8 @@ impl<'a> RepositoryRef<'a> {
             name: Cow::Owned(name),
             url: Cow::Owned(url),
             priority: 0,
+            x_mirror: false,
+            x_comment: None,
         }
     }
 
@@ -66,6 +140,8 @@ impl<'a> RepositoryRef<'a> {
             name: Cow::Borrowed(name),
             url: Cow::Borrowed(url),
             priority: 0,
+            x_mirror: false,
+            x_comment: None,
         }
     }
 }
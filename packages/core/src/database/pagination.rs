@@ -0,0 +1,282 @@
+//! Paging through long lists by where the last page ended, rather than by how
+//! many rows to skip.
+//!
+//! Skipping rows gets slower the further into a list a page is, and if modules
+//! are added or removed between two pages, rows are shown twice or not at all.
+//! A cursor instead holds the sort key of the last row of a page, so the next
+//! page starts straight after that row wherever it is now.
+//!
+//! Cursors are strings which callers should pass back unchanged. What's inside
+//! them may change between versions of Camrete.
+
+use std::{fmt, ops::DerefMut, str::FromStr};
+
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Binary, Bool, Nullable, Text},
+};
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use crate::database::{
+    ModuleId, ModuleSummary, Operation, QueryContext, QueryError, RepoDB, models::ModuleRelease,
+    schema::*,
+};
+
+/// Releases saved before sort keys were added have none, so they're sorted as
+/// if their key were empty, after every other release.
+const RELEASE_SORT_KEY: &str = "IFNULL(module_releases.sort_key, X'')";
+
+#[derive(Debug, Error, Diagnostic, PartialEq, Eq)]
+pub enum CursorError {
+    #[error("{cursor:?} isn't a cursor for this list")]
+    #[diagnostic(
+        code(camrete::database::invalid_cursor),
+        severity(Warning),
+        help("start again from the first page")
+    )]
+    Invalid { cursor: String },
+}
+
+/// Where a page of modules, ordered by slug, ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleCursor {
+    pub(crate) slug: String,
+    pub(crate) module_id: i32,
+}
+
+impl ModuleCursor {
+    pub(crate) fn after(summary: &ModuleSummary) -> Self {
+        Self {
+            slug: summary.slug.clone(),
+            module_id: summary.module_id.get(),
+        }
+    }
+}
+
+/// Where a page of a module's releases, ordered newest first, ended.
+/// Releases are ordered like [`ModuleRelease::by_version`], so releases with
+/// the same sort key are ordered by version, which is unique per module.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseCursor {
+    sort_key: Vec<u8>,
+    version: String,
+}
+
+/// Where a page of repositories, ordered by name, ended. Names are unique, so
+/// the name is enough.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoCursor {
+    pub(crate) name: String,
+}
+
+/// Implements the string form of a cursor: the name of its list, then its
+/// fields as hex-encoded JSON. The name stops a cursor being used with the
+/// wrong list.
+macro_rules! cursor_string {
+    ($cursor:ident, $list:literal) => {
+        impl fmt::Display for $cursor {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&encode($list, self))
+            }
+        }
+
+        impl FromStr for $cursor {
+            type Err = CursorError;
+
+            fn from_str(s: &str) -> Result<Self, CursorError> {
+                decode($list, s).ok_or_else(|| CursorError::Invalid { cursor: s.into() })
+            }
+        }
+
+        uniffi::custom_type!($cursor, String, {
+            lower: |cursor| cursor.to_string(),
+            try_lift: |s| Ok(s.parse::<$cursor>()?),
+        });
+    };
+}
+
+cursor_string!(ModuleCursor, "modules");
+cursor_string!(ReleaseCursor, "releases");
+cursor_string!(RepoCursor, "repos");
+
+fn encode(list: &str, cursor: &impl Serialize) -> String {
+    let json = serde_json::to_vec(cursor).expect("cursors can be serialized");
+    let hex = json.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    format!("{list}.{hex}")
+}
+
+fn decode<T: DeserializeOwned>(list: &str, cursor: &str) -> Option<T> {
+    let hex = cursor.strip_prefix(list)?.strip_prefix('.')?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    let json = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// A page of module summaries.
+#[derive(Debug, PartialEq, Eq, uniffi::Record)]
+pub struct ModuleSummaryPage {
+    pub modules: Vec<ModuleSummary>,
+    /// Where the next page starts, or `None` if this is the last page.
+    pub next: Option<ModuleCursor>,
+}
+
+/// A page of a module's releases, newest first.
+#[derive(Debug, uniffi::Record)]
+pub struct ReleasePage {
+    pub releases: Vec<ModuleRelease>,
+    /// Where the next page starts, or `None` if this is the last page.
+    pub next: Option<ReleaseCursor>,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns up to `limit` releases of a module, newest first, starting
+    /// after `after`, or from the newest release if it's `None`.
+    pub fn release_page(
+        &mut self,
+        module: ModuleId,
+        after: Option<&ReleaseCursor>,
+        limit: u32,
+    ) -> Result<ReleasePage, QueryError> {
+        let sort_key = after.map(|cursor| cursor.sort_key.clone());
+        let version = after.map(|cursor| cursor.version.clone());
+
+        let mut rows = module_releases::table
+            .filter(ModuleRelease::with_parent(module))
+            .filter(
+                sql::<Bool>("(")
+                    .bind::<Nullable<Text>, _>(version.clone())
+                    .sql(" IS NULL OR ")
+                    .sql(RELEASE_SORT_KEY)
+                    .sql(" < ")
+                    .bind::<Nullable<Binary>, _>(sort_key.clone())
+                    .sql(" OR (")
+                    .sql(RELEASE_SORT_KEY)
+                    .sql(" = ")
+                    .bind::<Nullable<Binary>, _>(sort_key)
+                    .sql(" AND module_releases.version > ")
+                    .bind::<Nullable<Text>, _>(version)
+                    .sql("))"),
+            )
+            .select((ModuleRelease::as_select(), sql::<Binary>(RELEASE_SORT_KEY)))
+            .order_by((
+                sql::<Binary>(RELEASE_SORT_KEY).desc(),
+                module_releases::version,
+            ))
+            .limit(i64::from(limit) + 1)
+            .load::<(ModuleRelease, Vec<u8>)>(&mut *self.connection)
+            .during(|| Operation::ReleasePage { module, limit })?;
+
+        let next = next_page(&mut rows, limit).map(|(release, sort_key)| ReleaseCursor {
+            sort_key: sort_key.clone(),
+            version: release.version.clone(),
+        });
+
+        Ok(ReleasePage {
+            releases: rows.into_iter().map(|(release, _)| release).collect(),
+            next,
+        })
+    }
+}
+
+/// Pages are loaded with one row more than `limit`, which is only there if
+/// another page follows. Drops that row, and returns the page's last row if
+/// there's another page.
+pub(crate) fn next_page<R>(rows: &mut Vec<R>, limit: u32) -> Option<&R> {
+    let limit = limit as usize;
+    if rows.len() <= limit {
+        return None;
+    }
+
+    rows.truncate(limit);
+    rows.last()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn pages_continue_after_changes() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (module, _) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        for version in ["1.1", "2.0", "2.1"] {
+            db.create_release(&release("Parallax", version).build(), repo.id, Some(module))
+                .unwrap();
+        }
+
+        let first = db.release_page(module, None, 2).unwrap();
+        let versions = first
+            .releases
+            .iter()
+            .map(|r| &*r.version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["2.1", "2.0"]);
+
+        // A release newer than the first page doesn't move the second.
+        db.create_release(&release("Parallax", "3.0").build(), repo.id, Some(module))
+            .unwrap();
+        let cursor = first.next.unwrap().to_string().parse().unwrap();
+        let second = db.release_page(module, Some(&cursor), 2).unwrap();
+        let versions = second
+            .releases
+            .iter()
+            .map(|r| &*r.version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["1.1", "1.0"]);
+        assert!(second.next.is_none());
+
+        assert!(ModuleCursor::from_str(&cursor.to_string()).is_err());
+        assert!(ReleaseCursor::from_str("releases.zz").is_err());
+    }
+
+    #[test]
+    fn releases_are_paged_in_version_order() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (module, _) = db
+            .create_release(&release("Parallax", "1.1").build(), repo.id, None)
+            .unwrap();
+        for version in ["1.0", "1.2"] {
+            db.create_release(&release("Parallax", version).build(), repo.id, Some(module))
+                .unwrap();
+        }
+
+        // With the same sort key, the order comes down to the tie-breaker.
+        diesel::update(module_releases::table)
+            .set(module_releases::sort_key.eq(vec![1u8]))
+            .execute(&mut *db.connection)
+            .unwrap();
+
+        let expected = module_releases::table
+            .filter(ModuleRelease::with_parent(module))
+            .order_by(ModuleRelease::by_version())
+            .select(module_releases::version)
+            .load::<String>(&mut *db.connection)
+            .unwrap();
+
+        let mut paged = vec![];
+        let mut cursor = None;
+        loop {
+            let page = db.release_page(module, cursor.as_ref(), 1).unwrap();
+            paged.extend(page.releases.into_iter().map(|r| r.version));
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(paged, expected);
+    }
+}
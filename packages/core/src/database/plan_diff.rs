@@ -0,0 +1,433 @@
+//! Working out what applying a plan would change, for showing before it's
+//! applied.
+//!
+//! Frontends show a [`PlanDiff`] when asking the user to confirm an install,
+//! upgrade or removal, so the CLI's prompt and the GUI's preview dialog agree
+//! about what's going to happen.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::DerefMut,
+};
+
+use diesel::prelude::*;
+
+use crate::{
+    database::{
+        DepGroupId, InstalledModule, Operation, QueryContext, QueryError, ReleaseId, RepoDB,
+        models::module::{ModuleVersion, RelationshipType},
+        schema::*,
+    },
+    resolver::{Reason, Resolution},
+};
+
+/// The changes a plan would make to the installed modules.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct PlanDiff {
+    /// Modules which aren't installed yet, in the order they were chosen.
+    pub installs: Vec<PlannedAddition>,
+    /// Installed modules which would be replaced by another release.
+    pub upgrades: Vec<PlannedUpgrade>,
+    pub removals: Vec<PlannedRemoval>,
+    /// The total size of the downloads, in bytes. Releases which don't say
+    /// how big their download is aren't counted.
+    pub download_size: i64,
+    /// The total size of the installed and upgraded modules once they're
+    /// installed, in bytes. Releases which don't say aren't counted.
+    pub install_size: i64,
+    pub warnings: Vec<PlanWarning>,
+}
+
+impl PlanDiff {
+    /// Whether the plan wouldn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.installs.is_empty() && self.upgrades.is_empty() && self.removals.is_empty()
+    }
+}
+
+/// A module which would be installed.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct PlannedAddition {
+    pub release: ReleaseId,
+    pub identifier: String,
+    pub version: String,
+    /// Whether the module was requested, rather than only being installed to
+    /// satisfy another module's relationships.
+    pub requested: bool,
+    pub download_size: Option<i64>,
+    pub install_size: Option<i64>,
+}
+
+/// An installed module which would be replaced by another release. The new
+/// release is usually newer, but see [`PlanWarning::Downgrade`].
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct PlannedUpgrade {
+    pub release: ReleaseId,
+    pub identifier: String,
+    pub from_version: String,
+    pub to_version: String,
+    pub download_size: Option<i64>,
+    pub install_size: Option<i64>,
+}
+
+/// An installed module which would be removed.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct PlannedRemoval {
+    pub identifier: String,
+    pub version: String,
+    /// How many files would be deleted from the game directory.
+    pub files: u32,
+}
+
+/// Something the user should know before applying a plan.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum PlanWarning {
+    /// A requested module or dependency which nothing installable satisfies.
+    Unsatisfied {
+        /// The module with the dependency, or `None` for a requested module.
+        dependent: Option<String>,
+        targets: Vec<String>,
+    },
+    /// An installed module would be replaced by an older release.
+    Downgrade {
+        identifier: String,
+        from_version: String,
+        to_version: String,
+    },
+    /// A module which was asked to be removed isn't installed.
+    NotInstalled { identifier: String },
+    /// A module which would still be installed depends on one which would be
+    /// removed.
+    StillNeeded {
+        identifier: String,
+        /// The installed modules which depend on it.
+        dependents: Vec<String>,
+    },
+    /// A release doesn't say how big its download is, so the total size is
+    /// lower than it should be.
+    UnknownSize { identifier: String },
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Compares the releases chosen by a [`Resolution`] and the modules in
+    /// `remove` with the installed modules.
+    ///
+    /// Chosen releases which are already installed are left out. A module
+    /// is compared by version, so a release numbered `1.01` is the same as an
+    /// installed `1.1`.
+    pub fn plan_diff(
+        &mut self,
+        resolution: &Resolution,
+        remove: &[String],
+    ) -> Result<PlanDiff, QueryError> {
+        let operation = || Operation::PlanChanges;
+
+        let installed = self
+            .installed_modules()?
+            .into_iter()
+            .map(|module| (module.slug.clone(), module))
+            .collect::<HashMap<_, _>>();
+
+        let chosen = resolution
+            .selected
+            .iter()
+            .map(|selection| selection.release)
+            .collect::<Vec<_>>();
+        let sizes = module_releases::table
+            .filter(module_releases::release_id.eq_any(&chosen))
+            .select((
+                module_releases::release_id,
+                module_releases::download_size,
+                module_releases::install_size,
+            ))
+            .load::<(ReleaseId, Option<i64>, Option<i64>)>(&mut *self.connection)
+            .during(operation)?
+            .into_iter()
+            .map(|(id, download, install)| (id, (download, install)))
+            .collect::<HashMap<_, _>>();
+
+        let mut diff = PlanDiff::default();
+
+        for selection in &resolution.selected {
+            let (download_size, install_size) =
+                sizes.get(&selection.release).copied().unwrap_or_default();
+            let version = ModuleVersion::from(selection.version.as_str());
+
+            match installed.get(&selection.identifier) {
+                Some(module) if ModuleVersion::from(module.version.as_str()) == version => {
+                    continue;
+                }
+                Some(module) => {
+                    if version < ModuleVersion::from(module.version.as_str()) {
+                        diff.warnings.push(PlanWarning::Downgrade {
+                            identifier: selection.identifier.clone(),
+                            from_version: module.version.clone(),
+                            to_version: selection.version.clone(),
+                        });
+                    }
+                    diff.upgrades.push(PlannedUpgrade {
+                        release: selection.release,
+                        identifier: selection.identifier.clone(),
+                        from_version: module.version.clone(),
+                        to_version: selection.version.clone(),
+                        download_size,
+                        install_size,
+                    });
+                }
+                None => diff.installs.push(PlannedAddition {
+                    release: selection.release,
+                    identifier: selection.identifier.clone(),
+                    version: selection.version.clone(),
+                    requested: selection.reason == Reason::Requested,
+                    download_size,
+                    install_size,
+                }),
+            }
+
+            match download_size {
+                Some(size) => diff.download_size += size,
+                None => diff.warnings.push(PlanWarning::UnknownSize {
+                    identifier: selection.identifier.clone(),
+                }),
+            }
+            diff.install_size += install_size.unwrap_or(0);
+        }
+
+        for slug in remove {
+            match installed.get(slug) {
+                Some(module) => diff.removals.push(PlannedRemoval {
+                    identifier: slug.clone(),
+                    version: module.version.clone(),
+                    files: module.files.len() as u32,
+                }),
+                None => diff.warnings.push(PlanWarning::NotInstalled {
+                    identifier: slug.clone(),
+                }),
+            }
+        }
+
+        let still_needed = self.still_needed(&installed, &diff.removals)?;
+        diff.warnings
+            .extend(still_needed.into_iter().map(|(identifier, dependents)| {
+                PlanWarning::StillNeeded {
+                    identifier,
+                    dependents,
+                }
+            }));
+
+        diff.warnings
+            .extend(resolution.unsatisfied.iter().map(|unsatisfied| {
+                PlanWarning::Unsatisfied {
+                    dependent: unsatisfied
+                        .dependent
+                        .and_then(|release| resolution.selection(release))
+                        .map(|selection| selection.identifier.clone()),
+                    targets: unsatisfied.targets.clone(),
+                }
+            }));
+
+        Ok(diff)
+    }
+
+    /// Finds the modules being removed which a module that's staying
+    /// installed depends on, with the modules which depend on each one. A
+    /// dependency with an `any_of` group only counts if every member of the
+    /// group is being removed.
+    fn still_needed(
+        &mut self,
+        installed: &HashMap<String, InstalledModule>,
+        removals: &[PlannedRemoval],
+    ) -> Result<BTreeMap<String, Vec<String>>, QueryError> {
+        if removals.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let removed = removals
+            .iter()
+            .map(|removal| removal.identifier.as_str())
+            .collect::<HashSet<_>>();
+
+        let remaining = installed
+            .values()
+            .filter(|module| !removed.contains(module.slug.as_str()))
+            .map(|module| module.slug.as_str())
+            .collect::<Vec<_>>();
+
+        // The dependencies of every release of the remaining modules are
+        // loaded at once, and those of releases which aren't installed are
+        // dropped here.
+        let targets = module_relationships::table
+            .inner_join(
+                module_relationship_groups::table
+                    .inner_join(module_releases::table.inner_join(modules::table)),
+            )
+            .filter(modules::module_slug.eq_any(&remaining))
+            .filter(module_relationship_groups::rel_type.eq(RelationshipType::Depends))
+            .select((
+                modules::module_slug,
+                module_releases::version,
+                module_relationship_groups::release_id,
+                module_relationships::group_id,
+                module_relationships::target_name,
+            ))
+            .load::<(String, String, ReleaseId, DepGroupId, String)>(&mut *self.connection)
+            .during(|| Operation::PlanChanges)?;
+
+        let mut dependents = HashMap::new();
+        let mut groups = BTreeMap::<(ReleaseId, DepGroupId), Vec<String>>::new();
+        for (slug, version, release, group, target) in targets {
+            if installed[&slug].version != version {
+                continue;
+            }
+            dependents.insert(release, slug);
+            groups.entry((release, group)).or_default().push(target);
+        }
+
+        let mut needed = BTreeMap::<String, Vec<String>>::new();
+        for ((release, _), targets) in groups {
+            if !targets
+                .iter()
+                .all(|target| removed.contains(target.as_str()))
+            {
+                continue;
+            }
+
+            let dependent = dependents[&release].clone();
+            for target in targets {
+                let dependents = needed.entry(target).or_default();
+                if !dependents.contains(&dependent) {
+                    dependents.push(dependent.clone());
+                }
+            }
+        }
+
+        for dependents in needed.values_mut() {
+            dependents.sort();
+        }
+
+        Ok(needed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        repo::test_support::{db_with_default_repo, release},
+        resolver::Graph,
+    };
+
+    fn installed(slug: &str, version: &str) -> InstalledModule {
+        InstalledModule {
+            slug: slug.into(),
+            version: version.into(),
+            auto_installed: false,
+            installed_at: None,
+            files: vec![format!("GameData/{slug}/{slug}.dll")],
+            local_archive: None,
+        }
+    }
+
+    #[test]
+    fn compares_plan_with_installed() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let json = release("Tantares", "1.0")
+            .merge(json!({
+                "depends": [{ "name": "Parallax" }, { "name": "Kopernicus" }],
+                "download_size": 1000,
+                "install_size": 4000,
+            }))
+            .build();
+        db.create_release(&json, repo.id, None).unwrap();
+        let json = release("Parallax", "2.0").set("download_size", 500).build();
+        db.create_release(&json, repo.id, None).unwrap();
+        db.create_release(&release("Scatterer", "1.0").build(), repo.id, None)
+            .unwrap();
+        let json = release("EVE", "1.0")
+            .set("depends", json!([{ "name": "Scatterer" }]))
+            .build();
+        db.create_release(&json, repo.id, None).unwrap();
+
+        db.record_installed(vec![
+            installed("Parallax", "1.0"),
+            installed("Scatterer", "1.0"),
+            installed("EVE", "1.0"),
+        ])
+        .unwrap();
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let resolution = graph.resolve(&["Tantares".into()], false);
+        let diff = db
+            .plan_diff(&resolution, &["Scatterer".into(), "Waterfall".into()])
+            .unwrap();
+
+        assert_eq!(diff.installs.len(), 1);
+        assert_eq!(diff.installs[0].identifier, "Tantares");
+        assert!(diff.installs[0].requested);
+
+        assert_eq!(diff.upgrades.len(), 1);
+        assert_eq!(diff.upgrades[0].identifier, "Parallax");
+        assert_eq!(diff.upgrades[0].from_version, "1.0");
+        assert_eq!(diff.upgrades[0].to_version, "2.0");
+
+        assert_eq!(
+            diff.removals,
+            [PlannedRemoval {
+                identifier: "Scatterer".into(),
+                version: "1.0".into(),
+                files: 1,
+            }]
+        );
+        assert_eq!(diff.download_size, 1500);
+        assert_eq!(diff.install_size, 4000);
+
+        assert_eq!(
+            diff.warnings,
+            [
+                PlanWarning::NotInstalled {
+                    identifier: "Waterfall".into()
+                },
+                PlanWarning::StillNeeded {
+                    identifier: "Scatterer".into(),
+                    dependents: vec!["EVE".into()],
+                },
+                PlanWarning::Unsatisfied {
+                    dependent: Some("Tantares".into()),
+                    targets: vec!["Kopernicus".into()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_downgrades() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let json = release("Parallax", "1.0").set("download_size", 500).build();
+        db.create_release(&json, repo.id, None).unwrap();
+        db.record_installed(vec![installed("Parallax", "1.1")])
+            .unwrap();
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let resolution = graph.resolve(&["Parallax".into()], false);
+        let diff = db.plan_diff(&resolution, &[]).unwrap();
+        assert_eq!(
+            diff.warnings,
+            [PlanWarning::Downgrade {
+                identifier: "Parallax".into(),
+                from_version: "1.1".into(),
+                to_version: "1.0".into(),
+            }]
+        );
+
+        // Nothing changes if the chosen release is already installed.
+        db.record_installed(vec![installed("Parallax", "1.00")])
+            .unwrap();
+        let diff = db.plan_diff(&resolution, &[]).unwrap();
+        assert!(diff.is_empty());
+        assert!(diff.warnings.is_empty());
+    }
+}
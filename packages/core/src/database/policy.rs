@@ -0,0 +1,119 @@
+//! Finding the saved releases which the database's
+//! [`Policy`](crate::policy::Policy) blocks.
+//!
+//! Blocked releases aren't saved when a repository is unpacked, but releases
+//! saved before the policy changed stay until their repository is next
+//! updated, so the resolver checks each release as it loads them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::DerefMut,
+};
+
+use diesel::prelude::*;
+use tracing::{debug, instrument};
+
+use crate::database::{
+    JsonbValue, Operation, QueryContext, QueryError, ReleaseId, RepoDB, RepoId,
+    models::ReleaseMetadata, schema::*,
+};
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns the releases in the given repositories, or in every repository
+    /// if `repo_filter` is `None`, which this database's
+    /// [`Policy`](crate::policy::Policy) blocks.
+    #[instrument(skip(self))]
+    pub fn blocked_releases(
+        &mut self,
+        repo_filter: Option<&[RepoId]>,
+    ) -> Result<HashSet<ReleaseId>, QueryError> {
+        let operation = || Operation::CheckPolicy;
+        if self.policy.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let mut releases = module_releases::table
+            .inner_join(modules::table)
+            .select((
+                module_releases::release_id,
+                modules::module_slug,
+                module_releases::metadata,
+            ))
+            .into_boxed();
+        let mut licenses = module_licenses::table
+            .inner_join(module_releases::table.inner_join(modules::table))
+            .select((module_licenses::release_id, module_licenses::license))
+            .into_boxed();
+
+        if let Some(repos) = repo_filter {
+            releases = releases.filter(modules::repo_id.eq_any(repos));
+            licenses = licenses.filter(modules::repo_id.eq_any(repos));
+        }
+
+        let releases = releases
+            .load::<(ReleaseId, String, JsonbValue)>(&mut *self.connection)
+            .during(operation)?;
+        let mut release_licenses = HashMap::<ReleaseId, Vec<String>>::new();
+        for (release, license) in licenses
+            .load::<(ReleaseId, String)>(&mut *self.connection)
+            .during(operation)?
+        {
+            release_licenses.entry(release).or_default().push(license);
+        }
+
+        let mut blocked = HashSet::new();
+        for (release_id, slug, metadata) in releases {
+            let metadata = ReleaseMetadata::try_from(metadata)
+                .map_err(|e| diesel::result::Error::DeserializationError(e.into()))
+                .during(operation)?;
+            let licenses = release_licenses.remove(&release_id).unwrap_or_default();
+
+            if let Err(violation) = self.policy.check(&slug, &licenses, &metadata.download) {
+                debug!(%slug, %violation, "Release is blocked by policy");
+                blocked.insert(release_id);
+            }
+        }
+
+        Ok(blocked)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_json::{from_value, json};
+
+    use crate::{
+        policy::Policy,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn finds_blocked_releases() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let download = json!({ "download": "https://cdn.example/mod.zip" });
+        let parallax = release("Parallax", "1.0")
+            .merge(download.clone())
+            .set("license", "MIT")
+            .build();
+        let (_, allowed) = db.create_release(&parallax, repo.id, None).unwrap();
+        let scatterer = release("Scatterer", "1.0")
+            .merge(download)
+            .set("license", "restricted")
+            .build();
+        let (_, restricted) = db.create_release(&scatterer, repo.id, None).unwrap();
+
+        assert!(db.blocked_releases(None).unwrap().is_empty());
+
+        let policy: Policy = from_value(json!({
+            "licenses": { "deny": ["restricted"] },
+        }))
+        .unwrap();
+        let mut db = db.with_policy(Arc::new(policy));
+        let blocked = db.blocked_releases(Some(&[repo.id])).unwrap();
+        assert!(blocked.contains(&restricted));
+        assert!(!blocked.contains(&allowed));
+    }
+}
@@ -0,0 +1,102 @@
+//! Checks that the queries run most often use the indexes meant for them.
+//!
+//! SQLite quietly falls back to scanning a whole table when a query or an
+//! index changes so that the index no longer applies, which is only noticed
+//! once a large repository is slow to browse. These tests ask SQLite how it
+//! would run each query with `EXPLAIN QUERY PLAN`, and fail if it would scan a
+//! table instead of searching an index.
+
+use diesel::{
+    debug_query,
+    prelude::*,
+    query_builder::QueryFragment,
+    sql_query,
+    sql_types::Text,
+    sqlite::Sqlite,
+};
+
+use crate::{
+    database::{
+        ModuleFilter, ModuleId,
+        models::{Module, ModuleRelease},
+    },
+    json::Identifier,
+    repo::RepoManager,
+};
+
+#[derive(QueryableByName)]
+struct PlanStep {
+    #[diesel(sql_type = Text)]
+    detail: String,
+}
+
+/// Describes how SQLite would run `query`, one step per line.
+fn query_plan(conn: &mut SqliteConnection, query: impl QueryFragment<Sqlite>) -> Vec<String> {
+    // The query's bind parameters are left unbound, which SQLite treats as
+    // nulls. That doesn't change which indexes the plan uses.
+    let sql = debug_query::<Sqlite, _>(&query).to_string();
+    let (sql, _binds) = sql.split_once(" -- binds:").unwrap_or((&sql, ""));
+
+    sql_query(format!("EXPLAIN QUERY PLAN {sql}"))
+        .load::<PlanStep>(conn)
+        .unwrap()
+        .into_iter()
+        .map(|step| step.detail)
+        .collect()
+}
+
+/// Asserts that the plan searches `table` with `index`, and doesn't scan any
+/// table or index from start to end.
+#[track_caller]
+fn assert_searches(plan: &[String], table: &str, index: &str) {
+    assert!(
+        !plan.iter().any(|step| step.starts_with("SCAN ")),
+        "query scans a table: {plan:#?}"
+    );
+
+    let search = format!("SEARCH {table} USING ");
+    assert!(
+        plan.iter()
+            .any(|step| step.starts_with(&search) && step.contains(&format!("INDEX {index} "))),
+        "query doesn't search {table} with {index}: {plan:#?}"
+    );
+}
+
+#[test]
+fn slug_lookup_uses_index() {
+    let mgr = RepoManager::new(":memory:").unwrap();
+    let mut db = mgr.db().unwrap();
+
+    let slug = Identifier::new("Parallax").unwrap();
+    let plan = query_plan(db.connection_mut(), Module::all().filter(Module::with_slug(&slug)));
+    assert_searches(&plan, "modules", "idx_modules_slug");
+}
+
+#[test]
+fn releases_by_version_use_index() {
+    let mgr = RepoManager::new(":memory:").unwrap();
+    let mut db = mgr.db().unwrap();
+
+    let query = ModuleRelease::all()
+        .filter(ModuleRelease::with_parent(ModuleId::new(1)))
+        .order_by(ModuleRelease::by_version());
+    let plan = query_plan(db.connection_mut(), query);
+    assert_searches(&plan, "module_releases", "idx_module_releases_by_sort_key");
+
+    // The index orders releases by sort key and then by version, so they're
+    // read in order without sorting any part of them afterwards.
+    assert!(
+        !plan.iter().any(|step| step.contains("TEMP B-TREE")),
+        "releases are sorted without the index: {plan:#?}"
+    );
+}
+
+#[test]
+fn tag_filter_uses_index() {
+    let mgr = RepoManager::new(":memory:").unwrap();
+    let mut db = mgr.db().unwrap();
+
+    let filter = "tag:parts".parse::<ModuleFilter>().unwrap();
+    let plan = query_plan(db.connection_mut(), Module::all().filter(filter.to_sql()));
+    assert_searches(&plan, "module_tags", "idx_module_tags_tag");
+}
@@ -0,0 +1,110 @@
+//! The SQLite handle behind a Diesel connection, for the parts of SQLite's C
+//! API which Diesel doesn't wrap, like progress handlers and the online backup
+//! API.
+//!
+//! Diesel doesn't give out the handle, so SQLite is asked to run an extension
+//! for each connection it opens, which gives the connection an SQL function
+//! returning its own handle. This applies to every connection the process
+//! opens once [`register_handle_function`] is called.
+
+use std::{
+    ffi::{CStr, c_char, c_int},
+    ptr::{self, NonNull},
+    sync::Once,
+};
+
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselError},
+    sql_types::BigInt,
+};
+use libsqlite3_sys as ffi;
+
+/// The name of the SQL function which returns a connection's handle.
+const HANDLE_FUNCTION: &CStr = c"camrete_connection_handle";
+
+/// Gives every connection opened from now on the SQL function which
+/// [`raw_handle`] reads its handle from.
+pub(crate) fn register_handle_function() {
+    static REGISTER: Once = Once::new();
+
+    REGISTER.call_once(|| {
+        // SAFETY: `add_handle_function` has the signature SQLite expects of
+        // an extension's entry point.
+        let result = unsafe { ffi::sqlite3_auto_extension(Some(add_handle_function)) };
+        assert_eq!(
+            result,
+            ffi::SQLITE_OK,
+            "SQLite extensions can be registered"
+        );
+    });
+}
+
+unsafe extern "C" fn add_handle_function(
+    db: *mut ffi::sqlite3,
+    _: *mut *mut c_char,
+    _: *const ffi::sqlite3_api_routines,
+) -> c_int {
+    // SAFETY: SQLite passes the connection it's opening, and the function
+    // has no state to free.
+    unsafe {
+        ffi::sqlite3_create_function_v2(
+            db,
+            HANDLE_FUNCTION.as_ptr(),
+            0,
+            ffi::SQLITE_UTF8,
+            ptr::null_mut(),
+            Some(connection_handle),
+            None,
+            None,
+            None,
+        )
+    }
+}
+
+unsafe extern "C" fn connection_handle(
+    ctx: *mut ffi::sqlite3_context,
+    _: c_int,
+    _: *mut *mut ffi::sqlite3_value,
+) {
+    // SAFETY: SQLite passes the context of the call it's making.
+    unsafe {
+        let db = ffi::sqlite3_context_db_handle(ctx);
+        ffi::sqlite3_result_int64(ctx, db as i64);
+    }
+}
+
+/// Returns the SQLite handle of `conn`. It's only valid while `conn` is open,
+/// and fails if `conn` was opened before [`register_handle_function`] was
+/// called.
+pub(crate) fn raw_handle(conn: &mut SqliteConnection) -> QueryResult<NonNull<ffi::sqlite3>> {
+    let function = HANDLE_FUNCTION.to_str().expect("the name is ASCII");
+    let handle = diesel::select(sql::<BigInt>(&format!("{function}()"))).get_result::<i64>(conn)?;
+
+    Ok(NonNull::new(handle as *mut ffi::sqlite3).expect("connections have a handle"))
+}
+
+/// The error SQLite last reported on `db`, as Diesel reports errors it doesn't
+/// have a kind for.
+pub(crate) fn last_error(db: NonNull<ffi::sqlite3>) -> DieselError {
+    // SAFETY: SQLite keeps the message until the next call on `db`, and it's
+    // copied before then.
+    let message = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(db.as_ptr())) };
+    unknown_error(message)
+}
+
+/// The error for a result code of SQLite's which isn't attached to a
+/// connection, like one returned by a backup step.
+pub(crate) fn code_error(code: c_int) -> DieselError {
+    // SAFETY: SQLite's messages for result codes are static strings.
+    let message = unsafe { CStr::from_ptr(ffi::sqlite3_errstr(code)) };
+    unknown_error(message)
+}
+
+fn unknown_error(message: &CStr) -> DieselError {
+    DieselError::DatabaseError(
+        DatabaseErrorKind::Unknown,
+        Box::new(message.to_string_lossy().into_owned()),
+    )
+}
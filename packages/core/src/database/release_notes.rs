@@ -0,0 +1,170 @@
+//! Release notes fetched from GitHub, cached so that each release's notes are
+//! only fetched once.
+//!
+//! CKAN metadata rarely includes a changelog, but modules hosted on GitHub
+//! usually publish one with each release. Notes are only fetched while the
+//! `release_notes` setting is on.
+
+use std::{ops::DerefMut, time::Duration};
+
+#[cfg(feature = "network")]
+use diesel::replace_into;
+use diesel::{prelude::*, result::Error::DeserializationError};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::database::{
+    JsonbValue, Operation, QueryContext, QueryError, RepoDB, models::ModuleRelease, schema::*,
+};
+
+/// The notes GitHub has for one release of a module.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ReleaseNotes {
+    /// The tag the release was found under, like `v1.2.0`.
+    pub tag: String,
+    /// The notes, in GitHub-flavoured Markdown. Empty if the release has a tag
+    /// but no notes.
+    pub body: String,
+    /// The release's page on GitHub.
+    pub url: Url,
+}
+
+/// Finds the GitHub repository a URL points to, like `KSP-CKAN/CKAN`.
+pub(crate) fn github_repo(url: &Url) -> Option<String> {
+    if !matches!(url.host_str()?, "github.com" | "www.github.com") {
+        return None;
+    }
+
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    let owner = segments.next()?;
+    let name = segments.next()?;
+    let name = name.strip_suffix(".git").unwrap_or(name);
+
+    Some(format!("{owner}/{name}"))
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// The cached release notes for a release, or `None` if its repository
+    /// isn't on GitHub, or its notes haven't been fetched or don't exist.
+    pub fn release_notes(
+        &mut self,
+        release: &ModuleRelease,
+    ) -> Result<Option<ReleaseNotes>, QueryError> {
+        let Some(repo) = release
+            .metadata
+            .resources
+            .repository
+            .as_ref()
+            .and_then(github_repo)
+        else {
+            return Ok(None);
+        };
+
+        let cached = self.cached_release_notes(&repo, &release.version, Duration::MAX)?;
+        Ok(cached.flatten())
+    }
+
+    /// What was found the last time a release's notes were fetched, where
+    /// `Some(None)` means GitHub had no release for the version. Returns `None`
+    /// if the notes haven't been fetched, or if GitHub had no release more than
+    /// `missing_ttl` ago, since it may have one now.
+    pub(crate) fn cached_release_notes(
+        &mut self,
+        repo: &str,
+        version: &str,
+        missing_ttl: Duration,
+    ) -> Result<Option<Option<ReleaseNotes>>, QueryError> {
+        let operation = || Operation::LoadReleaseNotes {
+            repo: repo.into(),
+            version: version.into(),
+        };
+
+        let row = release_notes::table
+            .find((repo, version))
+            .select((
+                release_notes::tag,
+                release_notes::body,
+                release_notes::url,
+                release_notes::fetched_at,
+            ))
+            .get_result::<(
+                Option<String>,
+                Option<String>,
+                Option<JsonbValue>,
+                OffsetDateTime,
+            )>(&mut *self.connection)
+            .optional()
+            .during(operation)?;
+
+        let Some((tag, body, url, fetched_at)) = row else {
+            return Ok(None);
+        };
+
+        let notes = match (tag, url) {
+            (Some(tag), Some(url)) => Some(ReleaseNotes {
+                tag,
+                body: body.unwrap_or_default(),
+                url: url
+                    .try_into()
+                    .map_err(|e: serde_json::Error| DeserializationError(e.into()))
+                    .during(operation)?,
+            }),
+            _ if OffsetDateTime::now_utc() - fetched_at < missing_ttl => None,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(notes))
+    }
+
+    /// Saves what was found when a release's notes were fetched. `None` means
+    /// GitHub had no release for the version.
+    #[cfg(feature = "network")]
+    pub(crate) fn cache_release_notes(
+        &mut self,
+        repo: &str,
+        version: &str,
+        notes: Option<&ReleaseNotes>,
+    ) -> Result<(), QueryError> {
+        replace_into(release_notes::table)
+            .values((
+                release_notes::github_repo.eq(repo),
+                release_notes::version.eq(version),
+                release_notes::tag.eq(notes.map(|n| &n.tag)),
+                release_notes::body.eq(notes.map(|n| &n.body)),
+                release_notes::url.eq(notes.map(|n| JsonbValue::from(&n.url))),
+                release_notes::fetched_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::SaveReleaseNotes {
+                repo: repo.into(),
+                version: version.into(),
+            })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_github_repos() {
+        let repo = |url| github_repo(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            repo("https://github.com/KSP-CKAN/CKAN").as_deref(),
+            Some("KSP-CKAN/CKAN")
+        );
+        assert_eq!(
+            repo("https://www.github.com/Linx/Parallax.git/").as_deref(),
+            Some("Linx/Parallax")
+        );
+        assert_eq!(
+            repo("https://github.com/Linx/Parallax/tree/main").as_deref(),
+            Some("Linx/Parallax")
+        );
+        assert_eq!(repo("https://github.com/Linx"), None);
+        assert_eq!(repo("https://gitlab.com/Linx/Parallax"), None);
+    }
+}
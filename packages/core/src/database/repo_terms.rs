@@ -0,0 +1,137 @@
+//! Licenses and terms of use which repositories publish alongside their
+//! metadata, like a `LICENSE.md` at the top of the archive.
+//!
+//! They're saved when a repository is updated, replacing the ones from the
+//! update before, so that the terms of a repository, or of whatever a mirror
+//! copies, can be shown to the user.
+
+use std::{collections::BTreeMap, ops::DerefMut};
+
+use diesel::{delete, insert_into, prelude::*};
+use tracing::instrument;
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, RepoId, schema::*};
+
+/// A license or terms file from a repository.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, uniffi::Record)]
+#[diesel(table_name = repo_metadata)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RepoTerms {
+    /// The file's name, like `LICENSE.md`.
+    pub file_name: String,
+    pub contents: String,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// The license and terms files of a repository as of its last update,
+    /// ordered by name.
+    pub fn repo_terms(&mut self, repo: RepoId) -> Result<Vec<RepoTerms>, QueryError> {
+        repo_metadata::table
+            .filter(repo_metadata::repo_id.eq(repo))
+            .select(RepoTerms::as_select())
+            .order_by(repo_metadata::file_name)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadRepoTerms { repo })
+    }
+
+    /// Replaces a repository's license and terms files. Returns the files
+    /// which are new, or whose contents changed, ordered by name, so they can
+    /// be shown to the user.
+    ///
+    /// If several files have the same name, like a `LICENSE` in two folders,
+    /// the last one is kept, as it would be if the archive were extracted.
+    #[instrument(skip(self, terms))]
+    pub fn replace_repo_terms(
+        &mut self,
+        repo: RepoId,
+        terms: Vec<RepoTerms>,
+    ) -> Result<Vec<RepoTerms>, QueryError> {
+        let operation = || Operation::SaveRepoTerms { repo };
+        let previous = self.repo_terms(repo)?;
+        let terms = terms
+            .into_iter()
+            .map(|file| (file.file_name, file.contents))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(file_name, contents)| RepoTerms {
+                file_name,
+                contents,
+            })
+            .collect::<Vec<_>>();
+
+        delete(repo_metadata::table.filter(repo_metadata::repo_id.eq(repo)))
+            .execute(&mut *self.connection)
+            .during(operation)?;
+
+        let rows = terms
+            .iter()
+            .map(|file| {
+                (
+                    repo_metadata::repo_id.eq(repo),
+                    repo_metadata::file_name.eq(&file.file_name),
+                    repo_metadata::contents.eq(&file.contents),
+                )
+            })
+            .collect::<Vec<_>>();
+        insert_into(repo_metadata::table)
+            .values(rows)
+            .execute(&mut *self.connection)
+            .during(operation)?;
+
+        Ok(terms
+            .into_iter()
+            .filter(|file| !previous.contains(file))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::test_support::db_with_default_repo;
+
+    fn terms(file_name: &str, contents: &str) -> RepoTerms {
+        RepoTerms {
+            file_name: file_name.into(),
+            contents: contents.into(),
+        }
+    }
+
+    #[test]
+    fn reports_new_and_changed_terms() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let first = vec![terms("LICENSE.md", "CC-0"), terms("TERMS", "Be nice")];
+        assert_eq!(
+            db.replace_repo_terms(repo.id, first.clone()).unwrap(),
+            first
+        );
+        assert_eq!(db.repo_terms(repo.id).unwrap(), first);
+
+        let second = vec![terms("LICENSE.md", "CC-BY")];
+        assert_eq!(
+            db.replace_repo_terms(repo.id, second.clone()).unwrap(),
+            second
+        );
+        assert!(
+            db.replace_repo_terms(repo.id, second.clone())
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(db.repo_terms(repo.id).unwrap(), second);
+    }
+
+    #[test]
+    fn keeps_the_last_file_with_a_name() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let files = vec![
+            terms("TERMS", "Be nice"),
+            terms("LICENSE", "CC-0"),
+            terms("LICENSE", "CC-BY"),
+        ];
+        let expected = [terms("LICENSE", "CC-BY"), terms("TERMS", "Be nice")];
+        assert_eq!(db.replace_repo_terms(repo.id, files).unwrap(), expected);
+        assert_eq!(db.repo_terms(repo.id).unwrap(), expected);
+    }
+}
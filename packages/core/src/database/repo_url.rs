@@ -0,0 +1,186 @@
+//! Moving a repository to a new URL, including where its old URL redirects.
+
+use std::ops::DerefMut;
+
+use diesel::{delete, prelude::*, update};
+use tracing::instrument;
+use url::Url;
+
+use crate::{
+    Error,
+    database::{
+        JsonbValue, Operation, QueryContext, QueryError, RepoDB, RepoId, models::Repository,
+        schema::*, url_key,
+    },
+};
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Changes the URL a repository is downloaded from, and removes what was
+    /// recorded about downloading it from the old URL: the old URL's ETag,
+    /// unless another repository still uses it, and the repositories it
+    /// suggested. Any ETag for the new URL is removed too, so the next update
+    /// downloads the repository in full. The repository's channel is
+    /// forgotten, since the new URL might not follow its template, as are the
+    /// redirects of the old URL.
+    ///
+    /// The repository's releases are kept until its next update.
+    #[instrument(skip(self))]
+    pub fn update_repo_url(&mut self, repo: RepoId, new_url: &Url) -> Result<Repository, Error> {
+        let operation = || Operation::SetRepoUrl { repo };
+
+        self.transaction(|db| {
+            let conn = db.connection;
+
+            let old_key = repositories::table
+                .find(repo)
+                .select(url_key::url_key(repositories::url))
+                .get_result::<JsonbValue>(conn)
+                .during(operation)?;
+            let new_key = JsonbValue::url_key(new_url);
+
+            let updated = update(repositories::table.find(repo))
+                .set((
+                    repositories::url.eq(JsonbValue::from(new_url)),
+                    repositories::channel.eq(None::<String>),
+                    repositories::url_template.eq(None::<String>),
+                    repositories::final_url.eq(JsonbValue::from(None::<&Url>)),
+                    repositories::moved_url.eq(JsonbValue::from(None::<&Url>)),
+                ))
+                .returning(Repository::as_returning())
+                .get_result(conn)
+                .during(operation)?;
+
+            let old_url_in_use = repositories::table
+                .filter(url_key::url_key(repositories::url).eq(&old_key))
+                .count()
+                .get_result::<i64>(conn)
+                .during(operation)?
+                > 0;
+            if !old_url_in_use {
+                delete(etags::table.find(&old_key))
+                    .execute(conn)
+                    .during(operation)?;
+            }
+            delete(etags::table.find(&new_key))
+                .execute(conn)
+                .during(operation)?;
+
+            delete(repository_refs::table)
+                .filter(repository_refs::referrer_id.eq(repo))
+                .execute(conn)
+                .during(operation)?;
+
+            Ok(updated)
+        })
+    }
+
+    /// Records where a repository's URL redirected at an update: where it was
+    /// downloaded from in the end, and where it has moved if the redirects
+    /// were permanent. Both are `None` if it wasn't redirected.
+    pub fn record_repo_redirect(
+        &mut self,
+        repo: RepoId,
+        final_url: Option<&Url>,
+        moved_url: Option<&Url>,
+    ) -> Result<(), QueryError> {
+        update(repositories::table.find(repo))
+            .set((
+                repositories::final_url.eq(JsonbValue::from(final_url)),
+                repositories::moved_url.eq(JsonbValue::from(moved_url)),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordRedirect { repo })?;
+
+        Ok(())
+    }
+
+    /// Moves a repository to where its URL permanently redirected at its last
+    /// update, like with [`update_repo_url`](Self::update_repo_url). Returns
+    /// `None` if the repository hasn't moved.
+    #[instrument(skip(self))]
+    pub fn follow_repo_redirect(&mut self, repo: RepoId) -> Result<Option<Repository>, Error> {
+        self.transaction(|mut db| {
+            let current = repositories::table
+                .find(repo)
+                .select(Repository::as_select())
+                .get_result(&mut *db.connection)
+                .during(|| Operation::SetRepoUrl { repo })?;
+
+            match current.moved_url {
+                Some(moved_url) => Ok(Some(db.update_repo_url(repo, &moved_url)?)),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::insert_into;
+
+    use super::*;
+    use crate::{
+        DbConnection, database::models::RepositoryRef, repo::test_support::db_with_default_repo,
+    };
+
+    fn add_etag(db: &mut RepoDB<DbConnection>, url: &Url) {
+        insert_into(etags::table)
+            .values((
+                etags::url.eq(JsonbValue::url_key(url)),
+                etags::etag.eq("abc"),
+            ))
+            .execute(db.connection_mut())
+            .unwrap();
+    }
+
+    fn etag_count(db: &mut RepoDB<DbConnection>) -> i64 {
+        etags::table.count().get_result(db.connection_mut()).unwrap()
+    }
+
+    #[test]
+    fn moving_a_repo_forgets_the_old_url() {
+        let (mut db, repo) = db_with_default_repo();
+        let old_url = repo.url.clone();
+        let new_url = Url::parse("https://example.com/meta.tar.gz").unwrap();
+
+        add_etag(&mut db, &old_url);
+        add_etag(&mut db, &new_url);
+        let suggested = Url::parse("https://example.com/other.tar.gz").unwrap();
+        db.add_repo_ref(repo.id, RepositoryRef::new("other".into(), suggested))
+            .unwrap();
+
+        let moved = db.update_repo_url(repo.id, &new_url).unwrap();
+        assert_eq!(moved.url, new_url);
+        assert_eq!(etag_count(&mut db), 0);
+        assert!(db.repo_suggestions().unwrap().is_empty());
+
+        // The old URL's ETag is kept while another repository uses it.
+        let mirror = db
+            .create_empty_repo(RepositoryRef::new("mirror".into(), old_url.clone()))
+            .unwrap();
+        db.update_repo_url(repo.id, &old_url).unwrap();
+        add_etag(&mut db, &old_url);
+        db.update_repo_url(mirror.id, &new_url).unwrap();
+        assert_eq!(etag_count(&mut db), 1);
+    }
+
+    #[test]
+    fn following_a_redirect_moves_the_repo() {
+        let (mut db, repo) = db_with_default_repo();
+        let moved_url = Url::parse("https://example.com/meta.tar.gz").unwrap();
+        let final_url = Url::parse("https://cdn.example.com/meta.tar.gz").unwrap();
+
+        assert!(db.follow_repo_redirect(repo.id).unwrap().is_none());
+
+        db.record_repo_redirect(repo.id, Some(&final_url), Some(&moved_url))
+            .unwrap();
+        let redirected = db.all_repos(true).unwrap().remove(0);
+        assert_eq!(redirected.final_url.as_ref(), Some(&final_url));
+        assert_eq!(redirected.moved_url.as_ref(), Some(&moved_url));
+
+        let moved = db.follow_repo_redirect(repo.id).unwrap().unwrap();
+        assert_eq!(moved.url, moved_url);
+        assert_eq!(moved.final_url, None);
+        assert_eq!(moved.moved_url, None);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{thread, time::Duration};
+
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use tracing::debug;
+
+use crate::{Error, database::QueryError};
+
+/// Controls how the database responds when SQLite reports that another
+/// connection (or another program) is holding a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Record)]
+pub struct RetryPolicy {
+    /// How long SQLite itself waits for a lock to be released before it
+    /// reports the database as busy.
+    pub busy_timeout: Duration,
+    /// The maximum number of times a read query is re-run after SQLite
+    /// reports the database as busy.
+    pub max_retries: u32,
+    /// The delay before the first retry. Each later retry waits twice as long
+    /// as the one before it.
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy which never retries queries.
+    pub const fn never() -> Self {
+        Self {
+            busy_timeout: Duration::ZERO,
+            max_retries: 0,
+            backoff: Duration::ZERO,
+        }
+    }
+
+    /// Runs `query`, re-running it while it fails because the database is
+    /// busy. Returns the final error alongside the number of attempts made.
+    pub(crate) fn run<R, E: RetryableError>(
+        &self,
+        mut query: impl FnMut() -> Result<R, E>,
+    ) -> Result<R, (E, u32)> {
+        let mut backoff = self.backoff;
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            match query() {
+                Err(err) if err.is_busy() && attempts <= self.max_retries => {
+                    debug!(attempts, ?backoff, "Database is busy, retrying query");
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                }
+                Err(err) => return Err((err, attempts)),
+                Ok(value) => return Ok(value),
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(2),
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Returns whether the given error was caused by the database being locked by
+/// another connection (`SQLITE_BUSY` or `SQLITE_LOCKED`).
+///
+/// Diesel reports both of these as unknown errors, so the only way to tell
+/// them apart is by their message.
+pub fn is_busy(err: &DieselError) -> bool {
+    let DieselError::DatabaseError(DatabaseErrorKind::Unknown, info) = err else {
+        return false;
+    };
+
+    let message = info.message();
+    message.contains("database is locked") || message.contains("database table is locked")
+}
+
+/// An error from a query which can be retried if the database was busy.
+pub trait RetryableError {
+    fn is_busy(&self) -> bool;
+
+    /// Converts the error which ended the final attempt into a crate error.
+    fn into_error(self, attempts: u32) -> Error;
+}
+
+impl RetryableError for DieselError {
+    fn is_busy(&self) -> bool {
+        is_busy(self)
+    }
+
+    fn into_error(self, attempts: u32) -> Error {
+        if is_busy(&self) {
+            Error::DatabaseBusy {
+                attempts,
+                source: self,
+            }
+        } else {
+            Error::Db(self)
+        }
+    }
+}
+
+impl RetryableError for QueryError {
+    fn is_busy(&self) -> bool {
+        QueryError::is_busy(self)
+    }
+
+    /// Busy errors lose their operation, since the advice for them is the
+    /// same whichever query was running.
+    fn into_error(self, attempts: u32) -> Error {
+        if self.is_busy() {
+            Error::DatabaseBusy {
+                attempts,
+                source: self.source,
+            }
+        } else {
+            Error::Query(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use diesel::result::DatabaseErrorInformation;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Message(&'static str);
+
+    impl DatabaseErrorInformation for Message {
+        fn message(&self) -> &str {
+            self.0
+        }
+
+        fn details(&self) -> Option<&str> {
+            None
+        }
+
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+
+        fn table_name(&self) -> Option<&str> {
+            None
+        }
+
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+
+        fn constraint_name(&self) -> Option<&str> {
+            None
+        }
+
+        fn statement_position(&self) -> Option<i32> {
+            None
+        }
+    }
+
+    fn busy() -> DieselError {
+        DieselError::DatabaseError(
+            DatabaseErrorKind::Unknown,
+            Box::new(Message("database is locked")),
+        )
+    }
+
+    fn instant() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            ..RetryPolicy::never()
+        }
+    }
+
+    #[test]
+    fn detects_busy() {
+        assert!(is_busy(&busy()));
+        assert!(!is_busy(&DieselError::NotFound));
+        assert!(!is_busy(&DieselError::DatabaseError(
+            DatabaseErrorKind::UniqueViolation,
+            Box::new(Message("database is locked")),
+        )));
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let calls = Cell::new(0);
+
+        let result = instant().run(|| {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 { Err(busy()) } else { Ok(calls.get()) }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn retries_are_bounded() {
+        let calls = Cell::new(0);
+
+        let result = instant().run(|| {
+            calls.set(calls.get() + 1);
+            Err::<(), _>(busy())
+        });
+
+        let (err, attempts) = result.unwrap_err();
+        assert!(is_busy(&err));
+        assert_eq!(attempts, 3);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let calls = Cell::new(0);
+
+        let result = instant().run(|| {
+            calls.set(calls.get() + 1);
+            Err::<(), _>(DieselError::NotFound)
+        });
+
+        assert_eq!(result.unwrap_err().1, 1);
+    }
+}
@@ -1,5 +1,27 @@
 use diesel::{allow_tables_to_appear_in_same_query, joinable, table};
 
+table! {
+    authors (author_id) {
+        author_id -> Integer,
+        name -> Text,
+    }
+}
+
+table! {
+    backfills (name) {
+        name -> Text,
+        completed_at -> TimestamptzSqlite,
+    }
+}
+
+table! {
+    bugtracker_checks (module_slug) {
+        module_slug -> Text,
+        alive -> Bool,
+        checked_at -> TimestamptzSqlite,
+    }
+}
+
 table! {
     builds (build_id) {
         build_id -> Integer,
@@ -7,10 +29,59 @@ table! {
     }
 }
 
+table! {
+    download_mirrors (url) {
+        url -> Binary,
+        content_type -> Nullable<Text>,
+        observed_size -> Nullable<BigInt>,
+        last_success -> Nullable<TimestamptzSqlite>,
+        last_failure -> Nullable<TimestamptzSqlite>,
+        failure_reason -> Nullable<Text>,
+    }
+}
+
+table! {
+    download_queue (download_id) {
+        download_id -> Integer,
+        module_slug -> Text,
+        version -> Text,
+        url -> Binary,
+        destination -> Text,
+        expected_size -> Nullable<BigInt>,
+        state -> Integer,
+        bytes_downloaded -> BigInt,
+        error -> Nullable<Text>,
+        queued_at -> TimestamptzSqlite,
+        mirrors -> Nullable<Binary>,
+        sha1 -> Nullable<Text>,
+        sha256 -> Nullable<Text>,
+        partial_url -> Nullable<Binary>,
+    }
+}
+
 table! {
     etags (url) {
         url -> Binary,
         etag -> Nullable<Text>,
+        updated_at -> Nullable<TimestamptzSqlite>,
+    }
+}
+
+table! {
+    favorites (module_slug) {
+        module_slug -> Text,
+        added_at -> TimestamptzSqlite,
+    }
+}
+
+table! {
+    installed_modules (module_slug) {
+        module_slug -> Text,
+        version -> Text,
+        auto_installed -> Bool,
+        installed_at -> Nullable<TimestamptzSqlite>,
+        files -> Binary,
+        local_archive -> Nullable<Text>,
     }
 }
 
@@ -19,7 +90,8 @@ table! {
         id -> Integer,
         release_id -> Integer,
         ordinal -> Integer,
-        author -> Text,
+        author_id -> Integer,
+        role -> Nullable<Text>,
     }
 }
 
@@ -39,6 +111,14 @@ table! {
     }
 }
 
+table! {
+    module_notes (module_slug) {
+        module_slug -> Text,
+        note -> Text,
+        updated_at -> TimestamptzSqlite,
+    }
+}
+
 table! {
     module_relationship_groups (group_id) {
         group_id -> Integer,
@@ -78,6 +158,9 @@ table! {
         install_size -> Nullable<BigInt>,
         release_date -> Nullable<TimestamptzSqlite>,
         kind -> Integer,
+        game_version_max -> Nullable<Binary>,
+        sort_key -> Nullable<Binary>,
+        spec_version -> Nullable<Text>,
     }
 }
 
@@ -91,6 +174,22 @@ table! {
     }
 }
 
+table! {
+    module_settings (module_slug) {
+        module_slug -> Text,
+        allow_prerelease -> Nullable<Bool>,
+        pinned_version -> Nullable<Text>,
+    }
+}
+
+table! {
+    module_staleness (module_id) {
+        module_id -> Integer,
+        last_release_date -> Nullable<TimestamptzSqlite>,
+        game_versions_behind -> Integer,
+    }
+}
+
 table! {
     module_tags (id) {
         id -> Integer,
@@ -109,12 +208,67 @@ table! {
     }
 }
 
+table! {
+    release_history (repo_id, module_slug, version) {
+        repo_id -> Integer,
+        module_slug -> Text,
+        version -> Text,
+        first_seen -> TimestamptzSqlite,
+        last_seen -> TimestamptzSqlite,
+        removed_at -> Nullable<TimestamptzSqlite>,
+    }
+}
+
+table! {
+    release_notes (github_repo, version) {
+        github_repo -> Text,
+        version -> Text,
+        tag -> Nullable<Text>,
+        body -> Nullable<Text>,
+        url -> Nullable<Binary>,
+        fetched_at -> TimestamptzSqlite,
+    }
+}
+
+table! {
+    release_validations (release_id) {
+        release_id -> Integer,
+        findings -> Binary,
+    }
+}
+
+table! {
+    repo_commits (repo_id) {
+        repo_id -> Integer,
+        remote -> Text,
+        branch -> Text,
+        commit_id -> Text,
+        fetched_at -> TimestamptzSqlite,
+    }
+}
+
+table! {
+    repo_metadata (repo_id, file_name) {
+        repo_id -> Integer,
+        file_name -> Text,
+        contents -> Text,
+    }
+}
+
 table! {
     repositories (repo_id) {
         repo_id -> Integer,
         url -> Binary,
         name -> Text,
         priority -> Integer,
+        filter -> Nullable<Binary>,
+        x_mirror -> Bool,
+        x_comment -> Nullable<Text>,
+        content_hash -> Nullable<Text>,
+        channel -> Nullable<Text>,
+        url_template -> Nullable<Text>,
+        final_url -> Nullable<Binary>,
+        moved_url -> Nullable<Binary>,
     }
 }
 
@@ -124,9 +278,70 @@ table! {
         name -> Text,
         url -> Binary,
         priority -> Integer,
+        x_mirror -> Bool,
+        x_comment -> Nullable<Text>,
+    }
+}
+
+table! {
+    resumable_updates (repo_id) {
+        repo_id -> Integer,
+        archive_path -> Text,
+        etag -> Nullable<Text>,
+        content_type -> Nullable<Text>,
+        started_at -> TimestamptzSqlite,
+    }
+}
+
+table! {
+    saved_filters (name) {
+        name -> Text,
+        filter -> Binary,
+    }
+}
+
+table! {
+    settings (name) {
+        name -> Text,
+        value -> Text,
+    }
+}
+
+table! {
+    unpack_checkpoints (repo_id, asset_hash) {
+        repo_id -> Integer,
+        asset_hash -> Binary,
+    }
+}
+
+table! {
+    update_locks (repo_id) {
+        repo_id -> Integer,
+        token -> Text,
+        process_id -> Integer,
+        expires_at -> TimestamptzSqlite,
+    }
+}
+
+table! {
+    usage_metrics (name) {
+        name -> Text,
+        count -> BigInt,
+        timed_count -> BigInt,
+        total_millis -> BigInt,
+    }
+}
+
+table! {
+    yanked_releases (module_slug, version) {
+        module_slug -> Text,
+        version -> Text,
+        reason -> Nullable<Text>,
+        yanked_at -> TimestamptzSqlite,
     }
 }
 
+joinable!(module_authors -> authors (author_id));
 joinable!(module_authors -> module_releases (release_id));
 joinable!(module_licenses -> module_releases (release_id));
 joinable!(module_localizations -> module_releases (release_id));
@@ -134,21 +349,51 @@ joinable!(module_relationship_groups -> module_releases (release_id));
 joinable!(module_relationships -> module_relationship_groups (group_id));
 joinable!(module_releases -> modules (module_id));
 joinable!(module_replacements -> module_releases (release_id));
+joinable!(module_staleness -> modules (module_id));
 joinable!(module_tags -> module_releases (release_id));
+joinable!(release_history -> repositories (repo_id));
+joinable!(release_validations -> module_releases (release_id));
 joinable!(modules -> repositories (repo_id));
+joinable!(repo_commits -> repositories (repo_id));
+joinable!(repo_metadata -> repositories (repo_id));
+joinable!(resumable_updates -> repositories (repo_id));
+joinable!(unpack_checkpoints -> resumable_updates (repo_id));
+joinable!(update_locks -> repositories (repo_id));
 
 allow_tables_to_appear_in_same_query!(
+    authors,
+    backfills,
+    bugtracker_checks,
     builds,
+    download_mirrors,
+    download_queue,
     etags,
+    favorites,
+    installed_modules,
     module_authors,
     module_licenses,
     module_localizations,
+    module_notes,
     module_relationship_groups,
     module_relationships,
     module_releases,
     module_replacements,
+    module_settings,
+    module_staleness,
     module_tags,
     modules,
+    release_history,
+    release_notes,
+    release_validations,
+    repo_commits,
+    repo_metadata,
     repositories,
     repository_refs,
+    resumable_updates,
+    saved_filters,
+    settings,
+    unpack_checkpoints,
+    update_locks,
+    usage_metrics,
+    yanked_releases,
 );
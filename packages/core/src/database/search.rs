@@ -0,0 +1,333 @@
+//! Searching modules as the user types.
+//!
+//! Searches use the `module_search` full-text index, ranked with BM25. Matches
+//! in a module's identifier or name count for the most, then its tags, then
+//! its summary. The index holds the newest release of each module, and is
+//! rebuilt whenever a repository is updated.
+
+use std::{
+    ffi::{c_int, c_void},
+    ops::DerefMut,
+    ptr::{self, NonNull},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use diesel::{
+    connection::SimpleConnection,
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Bool, Double, Integer, Text},
+    sqlite::Sqlite,
+};
+use libsqlite3_sys as ffi;
+
+use crate::{
+    Error,
+    database::{
+        ModuleId, Operation, QueryContext, QueryError, RepoDB, models::module::escape_like,
+        raw::raw_handle,
+    },
+};
+
+/// The trigram index can only match terms of at least this many characters.
+const MIN_TERM_LENGTH: usize = 3;
+/// How many SQLite instructions a search runs between checks for whether it
+/// was cancelled.
+const CANCELLATION_INTERVAL: c_int = 1000;
+
+const REBUILD_QUERY: &str = "
+DELETE FROM module_search;
+INSERT INTO module_search (rowid, slug, name, tags, summary)
+SELECT
+    m.module_id,
+    m.module_slug,
+    r.display_name,
+    (
+        SELECT COALESCE(group_concat(tag, ' '), '')
+        FROM (SELECT tag FROM module_tags WHERE release_id = r.release_id ORDER BY ordinal)
+    ),
+    r.summary
+FROM modules m
+INNER JOIN module_releases r ON r.release_id = (
+    SELECT latest.release_id
+    FROM module_releases latest
+    WHERE latest.module_id = m.module_id
+    ORDER BY latest.sort_key DESC, latest.version
+    LIMIT 1
+);
+";
+
+/// The columns' weights are the identifier, name, tags and summary. BM25 gives
+/// better matches lower scores, so they're negated.
+const SEARCH_QUERY: &str = "
+SELECT s.rowid AS module_id, s.slug, s.name, s.summary,
+    -bm25(module_search, 10.0, 10.0, 4.0, 1.0) AS rank,
+    EXISTS (SELECT 1 FROM favorites WHERE module_slug = s.slug) AS favorite,
+    EXISTS (SELECT 1 FROM module_notes WHERE module_slug = s.slug) AS has_note
+FROM module_search s
+INNER JOIN modules m ON m.module_id = s.rowid
+WHERE module_search MATCH ?
+ORDER BY rank DESC, m.download_count DESC, s.slug
+LIMIT ?
+";
+
+/// Used for queries too short for the trigram index, which only match the
+/// start of an identifier or name.
+const PREFIX_QUERY: &str = "
+SELECT s.rowid AS module_id, s.slug, s.name, s.summary, 0.0 AS rank,
+    EXISTS (SELECT 1 FROM favorites WHERE module_slug = s.slug) AS favorite,
+    EXISTS (SELECT 1 FROM module_notes WHERE module_slug = s.slug) AS has_note
+FROM module_search s
+INNER JOIN modules m ON m.module_id = s.rowid
+WHERE s.slug LIKE ?1 ESCAPE '\\' OR s.name LIKE ?1 ESCAPE '\\'
+ORDER BY m.download_count DESC, s.slug
+LIMIT ?2
+";
+
+/// A module which matches a search.
+#[derive(Debug, Clone, PartialEq, QueryableByName, uniffi::Record)]
+pub struct SearchResult {
+    #[diesel(sql_type = Integer, deserialize_as = i32)]
+    pub module_id: ModuleId,
+    #[diesel(sql_type = Text)]
+    pub slug: String,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = Text)]
+    pub summary: String,
+    /// How well the module matches. Higher is better, but ranks are only
+    /// comparable within one search.
+    #[diesel(sql_type = Double)]
+    pub rank: f64,
+    /// Whether the user has marked the module as a favorite.
+    #[diesel(sql_type = Bool)]
+    pub favorite: bool,
+    /// Whether the user has written a note about the module.
+    #[diesel(sql_type = Bool)]
+    pub has_note: bool,
+}
+
+/// Stops a search which is no longer needed, like when the user types another
+/// character. A token can be shared by any number of searches.
+#[derive(Debug, Default, uniffi::Object)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[uniffi::export]
+impl CancellationToken {
+    #[uniffi::constructor]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every search using this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// A progress handler which interrupts the statement running on a connection
+/// once a search is cancelled, so that a slow search stops part way through.
+/// The handler is removed when this is dropped.
+struct CancellationHandler {
+    db: NonNull<ffi::sqlite3>,
+    _cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationHandler {
+    fn install(conn: &mut SqliteConnection, cancellation: &CancellationToken) -> QueryResult<Self> {
+        let db = raw_handle(conn)?;
+        let cancelled = cancellation.cancelled.clone();
+        // SAFETY: The flag is kept alive until the handler is removed.
+        unsafe {
+            ffi::sqlite3_progress_handler(
+                db.as_ptr(),
+                CANCELLATION_INTERVAL,
+                Some(is_cancelled),
+                Arc::as_ptr(&cancelled).cast_mut().cast(),
+            );
+        }
+
+        Ok(Self {
+            db,
+            _cancelled: cancelled,
+        })
+    }
+}
+
+impl Drop for CancellationHandler {
+    fn drop(&mut self) {
+        // SAFETY: The connection outlives the handler, since the handler is
+        // only installed for one search.
+        unsafe { ffi::sqlite3_progress_handler(self.db.as_ptr(), 0, None, ptr::null_mut()) };
+    }
+}
+
+unsafe extern "C" fn is_cancelled(cancelled: *mut c_void) -> c_int {
+    // SAFETY: The handler was given the flag, which it keeps alive.
+    let cancelled = unsafe { &*cancelled.cast::<AtomicBool>() };
+    cancelled.load(Ordering::Relaxed).into()
+}
+
+/// Turns what the user typed into an FTS5 query which matches modules
+/// containing every term. Terms are quoted, so they can't use FTS5's syntax.
+/// Returns `None` if no term is long enough for the trigram index.
+fn match_query(query: &str) -> Option<String> {
+    let terms = query
+        .split_whitespace()
+        .filter(|term| term.chars().count() >= MIN_TERM_LENGTH)
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Indexes the newest release of every module for [`Self::search_modules`].
+    pub fn rebuild_search_index(&mut self) -> Result<(), QueryError> {
+        self.connection
+            .batch_execute(REBUILD_QUERY)
+            .during(|| Operation::IndexSearch)
+    }
+
+    /// Finds up to `limit` modules matching what the user typed, best first.
+    ///
+    /// Each word of the query can match any part of a module's identifier,
+    /// name, tags or summary. Words shorter than three characters are ignored,
+    /// unless the whole query is that short, in which case it only matches the
+    /// start of an identifier or name.
+    ///
+    /// Fails with [`Error::Cancelled`] if `cancellation` is cancelled before
+    /// the search finishes.
+    pub fn search_modules(
+        &mut self,
+        query: &str,
+        limit: u32,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<SearchResult>, Error> {
+        let operation = || Operation::SearchModules {
+            query: query.to_owned(),
+        };
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let statement = match match_query(query) {
+            Some(terms) => sql_query(SEARCH_QUERY)
+                .into_boxed::<Sqlite>()
+                .bind::<Text, _>(terms),
+            None => sql_query(PREFIX_QUERY)
+                .into_boxed::<Sqlite>()
+                .bind::<Text, _>(format!("{}%", escape_like(query))),
+        };
+        let statement = statement.bind::<BigInt, _>(i64::from(limit));
+
+        if cancellation.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let handler =
+            CancellationHandler::install(&mut self.connection, cancellation).during(operation)?;
+        let results = statement.load::<SearchResult>(&mut *self.connection);
+        drop(handler);
+
+        match results {
+            // The search stopped because it was cancelled, not because it
+            // failed.
+            Err(_) if cancellation.is_cancelled() => Err(Error::Cancelled),
+            results => Ok(results.during(operation)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::{
+        RepoManager,
+        test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn ranks_names_above_tags_and_summaries() {
+        let (mut db, repo) = db_with_default_repo();
+
+        for (identifier, summary, tags) in [
+            ("Scatterer", "Atmospheres which work with Parallax", &[][..]),
+            ("Parallax", "Tessellated terrain", &["graphics"]),
+            ("Rocks", "More rocks", &["parallax-addon"]),
+        ] {
+            let json = release(identifier, "1.0")
+                .merge(json!({ "abstract": summary, "tags": tags }))
+                .build();
+            db.create_release(&json, repo.id, None).unwrap();
+        }
+        db.rebuild_search_index().unwrap();
+
+        let token = CancellationToken::new();
+        let mut search = |query: &str| {
+            db.search_modules(query, 10, &token)
+                .unwrap()
+                .into_iter()
+                .map(|result| result.slug)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search("allax"), ["Parallax", "Rocks", "Scatterer"]);
+        assert_eq!(search("parallax terrain"), ["Parallax"]);
+        assert_eq!(search("pa"), ["Parallax"]);
+        // FTS5's syntax is treated as text.
+        assert!(search("NEAR(rocks)").is_empty());
+        assert!(search("  ").is_empty());
+
+        token.cancel();
+        let cancelled = db.search_modules("allax", 10, &token);
+        assert!(matches!(cancelled, Err(Error::Cancelled)), "{cancelled:?}");
+    }
+
+    #[test]
+    fn cancelling_stops_running_queries() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        let token = CancellationToken::new();
+        let handler = CancellationHandler::install(db.connection_mut(), &token).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                token.cancel();
+            });
+
+            // This counts for far longer than the test waits.
+            let started = Instant::now();
+            let count = sql_query(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n LIMIT 10000000000)
+                SELECT count(*) AS count FROM n",
+            )
+            .execute(db.connection_mut());
+            assert!(count.is_err());
+            assert!(started.elapsed() < Duration::from_secs(10));
+        });
+        drop(handler);
+
+        // Later queries on the connection aren't interrupted.
+        diesel::select(1.into_sql::<Integer>())
+            .get_result::<i32>(db.connection_mut())
+            .unwrap();
+    }
+}
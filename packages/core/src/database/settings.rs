@@ -0,0 +1,265 @@
+//! User preferences stored alongside the repository data.
+
+use std::ops::DerefMut;
+
+use diesel::{insert_into, prelude::*, replace_into, upsert::excluded};
+use tracing::instrument;
+
+use crate::{
+    database::{
+        ModuleId, Operation, QueryContext, QueryError, RepoDB, models::ModuleRelease, schema::*,
+    },
+    json::ReleaseStatus,
+};
+
+/// Whether testing and development releases are considered by default.
+const PRERELEASE: &str = "prerelease";
+/// Whether release metadata is compressed when it's saved.
+const COMPRESS_METADATA: &str = "compress_metadata";
+/// Whether usage metrics are recorded.
+const TELEMETRY: &str = "telemetry";
+/// Whether release notes are fetched from GitHub.
+const RELEASE_NOTES: &str = "release_notes";
+/// The name of the per-module override of [`PRERELEASE`], for error messages.
+const MODULE_PRERELEASE: &str = "allow_prerelease";
+/// The name of the version a module is pinned to, for error messages.
+const MODULE_PIN: &str = "pinned_version";
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns whether testing and development releases are considered for
+    /// modules which don't override this setting. Defaults to `false`.
+    pub fn prerelease_default(&mut self) -> Result<bool, QueryError> {
+        self.flag(PRERELEASE)
+    }
+
+    /// Changes whether testing and development releases are considered for
+    /// modules which don't override this setting.
+    #[instrument(skip(self))]
+    pub fn set_prerelease_default(&mut self, allow: bool) -> Result<(), QueryError> {
+        self.set_flag(PRERELEASE, allow)
+    }
+
+    /// Returns whether release metadata is compressed when repositories are
+    /// updated. Defaults to `false`.
+    pub fn compresses_metadata(&mut self) -> Result<bool, QueryError> {
+        self.flag(COMPRESS_METADATA)
+    }
+
+    /// Changes whether release metadata is compressed. Releases which are
+    /// already saved keep their format until their repository is next updated.
+    #[instrument(skip(self))]
+    pub fn set_metadata_compression(&mut self, compress: bool) -> Result<(), QueryError> {
+        self.set_flag(COMPRESS_METADATA, compress)
+    }
+
+    /// Returns whether usage metrics are recorded. Defaults to `false`.
+    pub fn telemetry_enabled(&mut self) -> Result<bool, QueryError> {
+        self.flag(TELEMETRY)
+    }
+
+    /// Changes whether usage metrics are recorded. Metrics which were already
+    /// recorded are kept until they're reset.
+    #[instrument(skip(self))]
+    pub fn set_telemetry(&mut self, enabled: bool) -> Result<(), QueryError> {
+        self.set_flag(TELEMETRY, enabled)
+    }
+
+    /// Returns whether release notes are fetched from GitHub for modules
+    /// hosted there. Defaults to `false`.
+    pub fn fetches_release_notes(&mut self) -> Result<bool, QueryError> {
+        self.flag(RELEASE_NOTES)
+    }
+
+    /// Changes whether release notes are fetched from GitHub. Notes which were
+    /// already fetched are still shown.
+    #[instrument(skip(self))]
+    pub fn set_release_notes(&mut self, enabled: bool) -> Result<(), QueryError> {
+        self.set_flag(RELEASE_NOTES, enabled)
+    }
+
+    fn flag(&mut self, name: &'static str) -> Result<bool, QueryError> {
+        let value = settings::table
+            .find(name)
+            .select(settings::value)
+            .get_result::<String>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadSetting { name, slug: None })?;
+
+        Ok(value.is_some_and(|v| v == "on"))
+    }
+
+    fn set_flag(&mut self, name: &'static str, value: bool) -> Result<(), QueryError> {
+        replace_into(settings::table)
+            .values((
+                settings::name.eq(name),
+                settings::value.eq(if value { "on" } else { "off" }),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::ChangeSetting { name, slug: None })?;
+
+        Ok(())
+    }
+
+    /// Returns whether testing and development releases are considered for the
+    /// given module, taking its override into account.
+    pub fn allows_prerelease(&mut self, slug: &str) -> Result<bool, QueryError> {
+        let module_override = module_settings::table
+            .find(slug)
+            .select(module_settings::allow_prerelease)
+            .get_result::<Option<bool>>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadSetting {
+                name: MODULE_PRERELEASE,
+                slug: Some(slug.into()),
+            })?
+            .flatten();
+
+        match module_override {
+            Some(allow) => Ok(allow),
+            None => self.prerelease_default(),
+        }
+    }
+
+    /// Overrides whether testing and development releases are considered for
+    /// the given module. Passing `None` removes the override.
+    #[instrument(skip(self))]
+    pub fn set_module_prerelease(
+        &mut self,
+        slug: &str,
+        allow: Option<bool>,
+    ) -> Result<(), QueryError> {
+        insert_into(module_settings::table)
+            .values((
+                module_settings::module_slug.eq(slug),
+                module_settings::allow_prerelease.eq(allow),
+            ))
+            .on_conflict(module_settings::module_slug)
+            .do_update()
+            .set(
+                module_settings::allow_prerelease
+                    .eq(excluded(module_settings::allow_prerelease)),
+            )
+            .execute(&mut *self.connection)
+            .during(|| Operation::ChangeSetting {
+                name: MODULE_PRERELEASE,
+                slug: Some(slug.into()),
+            })?;
+
+        Ok(())
+    }
+
+    /// Returns the version the given module is pinned to, if it's pinned.
+    pub fn module_pin(&mut self, slug: &str) -> Result<Option<String>, QueryError> {
+        Ok(module_settings::table
+            .find(slug)
+            .select(module_settings::pinned_version)
+            .get_result::<Option<String>>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LoadSetting {
+                name: MODULE_PIN,
+                slug: Some(slug.into()),
+            })?
+            .flatten())
+    }
+
+    /// Pins the given module to a version, so that it isn't offered upgrades.
+    /// Passing `None` unpins it.
+    #[instrument(skip(self))]
+    pub fn set_module_pin(&mut self, slug: &str, version: Option<&str>) -> Result<(), QueryError> {
+        insert_into(module_settings::table)
+            .values((
+                module_settings::module_slug.eq(slug),
+                module_settings::pinned_version.eq(version),
+            ))
+            .on_conflict(module_settings::module_slug)
+            .do_update()
+            .set(module_settings::pinned_version.eq(excluded(module_settings::pinned_version)))
+            .execute(&mut *self.connection)
+            .during(|| Operation::ChangeSetting {
+                name: MODULE_PIN,
+                slug: Some(slug.into()),
+            })?;
+
+        Ok(())
+    }
+
+    /// Returns the least stable release status the user is willing to see for
+    /// the given module.
+    pub fn max_release_status(&mut self, slug: &str) -> Result<ReleaseStatus, QueryError> {
+        Ok(if self.allows_prerelease(slug)? {
+            ReleaseStatus::Development
+        } else {
+            ReleaseStatus::Stable
+        })
+    }
+
+    /// Returns the newest release of a module whose release status is allowed
+    /// by the user's prerelease settings, leaving out yanked releases.
+    pub fn latest_release(
+        &mut self,
+        module: ModuleId,
+    ) -> Result<Option<ModuleRelease>, QueryError> {
+        let operation = || Operation::LatestRelease { module };
+
+        let slug = modules::table
+            .find(module)
+            .select(modules::module_slug)
+            .get_result::<String>(&mut *self.connection)
+            .during(operation)?;
+        let max_status = self.max_release_status(&slug)?;
+
+        ModuleRelease::all()
+            .filter(ModuleRelease::with_parent(module))
+            .filter(ModuleRelease::with_max_status(max_status))
+            .filter(ModuleRelease::not_yanked(slug))
+            .order_by(ModuleRelease::by_version())
+            .first(&mut *self.connection)
+            .optional()
+            .during(operation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repo::{
+        RepoManager,
+        test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn prerelease_overrides() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        assert!(!db.allows_prerelease("Parallax").unwrap());
+
+        db.set_prerelease_default(true).unwrap();
+        assert!(db.allows_prerelease("Parallax").unwrap());
+
+        db.set_module_prerelease("Parallax", Some(false)).unwrap();
+        assert!(!db.allows_prerelease("Parallax").unwrap());
+        assert!(db.allows_prerelease("Scatterer").unwrap());
+
+        db.set_module_prerelease("Parallax", None).unwrap();
+        assert!(db.allows_prerelease("Parallax").unwrap());
+    }
+
+    #[test]
+    fn latest_release_skips_prereleases() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (mid, _) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        let beta = release("Parallax", "2.0-beta").set("release_status", "testing");
+        db.create_release(&beta.build(), repo.id, Some(mid))
+            .unwrap();
+
+        let latest = db.latest_release(mid).unwrap().unwrap();
+        assert_eq!(latest.version, "1.0");
+
+        db.set_module_prerelease("Parallax", Some(true)).unwrap();
+        let latest = db.latest_release(mid).unwrap().unwrap();
+        assert_eq!(latest.version, "2.0-beta");
+    }
+}
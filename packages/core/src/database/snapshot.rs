@@ -0,0 +1,133 @@
+//! Reading the database as it was at one moment, while it's being updated.
+//!
+//! Exports and dependency graphs make many queries, and an update which
+//! finishes between two of them would mix its releases with the old ones. A
+//! [`Snapshot`] keeps a read transaction open on its connection, so with
+//! SQLite's write-ahead log every query sees the database as it was when the
+//! snapshot was taken, while updates carry on using other connections.
+
+use std::ops::{Deref, DerefMut};
+
+use diesel::{
+    connection::{AnsiTransactionManager, SimpleConnection, TransactionManager},
+    prelude::*,
+};
+use tracing::{trace, warn};
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB};
+
+/// A connection holding a read transaction open until it's dropped. Writes
+/// through it fail.
+///
+/// Use it through a [`RepoDB`], from
+/// [`RepoManager::snapshot`](crate::repo::RepoManager::snapshot). While it's
+/// open, SQLite can't shrink its write-ahead log past the snapshot, so it
+/// shouldn't be kept for longer than it's needed.
+#[derive(Debug)]
+pub struct Snapshot<T: DerefMut<Target = SqliteConnection>> {
+    connection: T,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> Snapshot<T> {
+    /// Starts a read transaction on `connection`, and reads from the
+    /// database so that the transaction's view of it is fixed from now on.
+    pub fn begin(mut connection: T) -> Result<Self, QueryError> {
+        AnsiTransactionManager::begin_transaction(&mut *connection)
+            .during(|| Operation::TakeSnapshot)?;
+
+        // A deferred transaction only settles on a version of the database at
+        // its first read.
+        let pinned = connection
+            .batch_execute("PRAGMA query_only = ON; SELECT 1 FROM repositories LIMIT 1;")
+            .during(|| Operation::TakeSnapshot);
+        // If that failed, dropping the snapshot ends the transaction.
+        let snapshot = Self { connection };
+        pinned?;
+
+        trace!("Took a snapshot of the database");
+        Ok(snapshot)
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> Deref for Snapshot<T> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.connection
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> DerefMut for Snapshot<T> {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        &mut self.connection
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> Drop for Snapshot<T> {
+    fn drop(&mut self) {
+        // Nothing was written, so rolling back only ends the transaction. If
+        // it fails, the connection is left in a transaction, and the pool
+        // discards it instead of reusing it.
+        if let Err(e) = AnsiTransactionManager::rollback_transaction(&mut *self.connection) {
+            warn!("Failed to end a snapshot of the database: {e}");
+        }
+        if let Err(e) = self.connection.batch_execute("PRAGMA query_only = OFF;") {
+            warn!("Failed to make a connection writable after a snapshot: {e}");
+        }
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Takes a snapshot using this database's connection, keeping its retry
+    /// policy and settings.
+    pub fn into_snapshot(self) -> Result<RepoDB<Snapshot<T>>, QueryError> {
+        Ok(RepoDB {
+            connection: Snapshot::begin(self.connection)?,
+            retry_policy: self.retry_policy,
+            compress_metadata: self.compress_metadata,
+            policy: self.policy,
+            #[cfg(feature = "sqlcipher")]
+            key: self.key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use url::Url;
+
+    use crate::{database::models::RepositoryRef, repo::RepoManager};
+
+    #[test]
+    fn snapshots_ignore_later_changes() {
+        let path = env::temp_dir().join(format!("camrete-snapshot-{}.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mgr = RepoManager::new(&path.to_string_lossy()).unwrap();
+        let mut db = mgr.db().unwrap();
+        db.all_repos(true).unwrap();
+
+        let mut snapshot = mgr.snapshot().unwrap();
+        let url = Url::parse("https://example.com/meta.tar.gz").unwrap();
+        db.create_empty_repo(RepositoryRef::shared("Example", &url))
+            .unwrap();
+
+        assert_eq!(db.all_repos(false).unwrap().len(), 2);
+        assert_eq!(snapshot.all_repos(false).unwrap().len(), 1);
+        assert!(snapshot.set_prerelease_default(true).is_err());
+
+        // Once the snapshot is dropped, its connection sees every change and
+        // can write again.
+        drop(snapshot);
+        let mut later = mgr.snapshot().unwrap();
+        assert_eq!(later.all_repos(false).unwrap().len(), 2);
+        drop(later);
+        mgr.db().unwrap().set_prerelease_default(true).unwrap();
+
+        drop(db);
+        drop(mgr);
+        fs::remove_file(&path).unwrap();
+    }
+}
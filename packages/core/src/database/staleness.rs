@@ -0,0 +1,421 @@
+//! Whether modules still look maintained.
+//!
+//! A module which hasn't had a release for a long time isn't necessarily
+//! abandoned, since the game may not have changed since. So the time since a
+//! module's last release is weighed against how many minor versions of the
+//! game have come out since the newest one it supports. Those are saved for
+//! each module after every repository update, and a module is
+//! [classified](Staleness) when it's loaded, so that the classification keeps
+//! up with the date between updates.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::DerefMut,
+};
+
+use diesel::{delete, insert_into, prelude::*, replace_into};
+use strum::IntoStaticStr;
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, instrument};
+
+use crate::{
+    database::{
+        JsonbValue, ModuleId, Operation, QueryContext, QueryError, RepoDB,
+        compatibility::supports_game, schema::*,
+    },
+    repo::game::GameVersion,
+};
+
+/// How long a module can go without a release before it's no longer
+/// [maintained](Staleness::Maintained).
+const QUIET_AFTER: Duration = Duration::days(365);
+/// How long an [outdated](Staleness::Outdated) module can go without a
+/// release before it's [abandoned](Staleness::Abandoned).
+const ABANDONED_AFTER: Duration = Duration::days(2 * 365);
+/// How many rows are saved at a time.
+const BATCH_SIZE: usize = 500;
+
+/// How likely a module is to still be maintained, from least to most stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, IntoStaticStr, uniffi::Enum)]
+#[strum(serialize_all = "snake_case")]
+pub enum Staleness {
+    /// The module has had a release in the last year.
+    Maintained,
+    /// The module hasn't had a release for a year, but supports the newest
+    /// version of the game, so it may not have needed one.
+    Dormant,
+    /// The module hasn't had a release for a year, and doesn't support the
+    /// newest version of the game.
+    Outdated,
+    /// The module is outdated, and either hasn't had a release for two years
+    /// while falling two or more versions of the game behind, or its bug
+    /// tracker is gone.
+    Abandoned,
+}
+
+impl Staleness {
+    /// Classifies a module. Modules whose releases have no dates are treated
+    /// as if they haven't had a release for a long time.
+    pub fn classify(
+        last_release_date: Option<OffsetDateTime>,
+        game_versions_behind: u32,
+        bugtracker_alive: Option<bool>,
+        now: OffsetDateTime,
+    ) -> Self {
+        let age = last_release_date.map(|date| now - date);
+        if age.is_some_and(|age| age < QUIET_AFTER) {
+            return Self::Maintained;
+        }
+        if game_versions_behind == 0 {
+            return Self::Dormant;
+        }
+
+        let long_gone = game_versions_behind >= 2 && age.is_none_or(|age| age >= ABANDONED_AFTER);
+        if long_gone || bugtracker_alive == Some(false) {
+            Self::Abandoned
+        } else {
+            Self::Outdated
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// What's known about whether a module is still maintained.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ModuleStaleness {
+    pub module_id: ModuleId,
+    pub slug: String,
+    pub staleness: Staleness,
+    /// The date of the module's newest release which has one.
+    pub last_release_date: Option<OffsetDateTime>,
+    /// How many minor versions of the game in the builds table are newer than
+    /// any the module supports.
+    pub game_versions_behind: u32,
+    /// Whether the module's bug tracker could be reached when it was last
+    /// checked, or `None` if it hasn't been checked.
+    pub bugtracker_alive: Option<bool>,
+}
+
+type GameRange = (GameVersion, GameVersion, Option<GameVersion>);
+
+/// Reads a release's `game_version`, `game_version_min` and
+/// `game_version_max`.
+fn game_range(
+    exact: JsonbValue,
+    min: JsonbValue,
+    max: Option<JsonbValue>,
+) -> Result<GameRange, serde_json::Error> {
+    let max = match max {
+        Some(max) => Option::<GameVersion>::try_from(max)?,
+        None => None,
+    };
+    Ok((exact.try_into()?, min.try_into()?, max))
+}
+
+type StalenessRow = (ModuleId, String, Option<OffsetDateTime>, i32, Option<bool>);
+
+impl ModuleStaleness {
+    fn from_row(row: StalenessRow, now: OffsetDateTime) -> Self {
+        let (module_id, slug, last_release_date, behind, bugtracker_alive) = row;
+        let game_versions_behind = behind.max(0) as u32;
+
+        Self {
+            module_id,
+            slug,
+            staleness: Staleness::classify(
+                last_release_date,
+                game_versions_behind,
+                bugtracker_alive,
+                now,
+            ),
+            last_release_date,
+            game_versions_behind,
+            bugtracker_alive,
+        }
+    }
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Works out how far behind the game every module with releases is, and
+    /// saves it. Returns how many modules were saved.
+    #[instrument(skip(self))]
+    pub fn update_staleness(&mut self) -> Result<u64, QueryError> {
+        let operation = || Operation::UpdateStaleness;
+
+        let builds = builds::table
+            .select(builds::version)
+            .load::<JsonbValue>(&mut *self.connection)
+            .during(operation)?;
+        // Newest first, so the number of versions before the first supported
+        // one is how many the module is behind.
+        let game_versions = builds
+            .into_iter()
+            .filter_map(|version| GameVersion::try_from(version).ok())
+            .filter_map(|version| Some((version.major()?, version.minor()?)))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>();
+
+        let releases = module_releases::table
+            .select((
+                module_releases::module_id,
+                module_releases::release_date,
+                module_releases::game_version,
+                module_releases::game_version_min,
+                module_releases::game_version_max,
+            ))
+            .load::<(
+                ModuleId,
+                Option<OffsetDateTime>,
+                JsonbValue,
+                JsonbValue,
+                Option<JsonbValue>,
+            )>(&mut *self.connection)
+            .during(operation)?;
+
+        // The newest release date and the game versions supported by each of
+        // a module's releases.
+        let mut by_module = HashMap::<ModuleId, (Option<OffsetDateTime>, Vec<GameRange>)>::new();
+        for (module_id, release_date, exact, min, max) in releases {
+            let range = game_range(exact, min, max)
+                .map_err(|e| diesel::result::Error::DeserializationError(e.into()))
+                .during(operation)?;
+
+            let (last_release, ranges) = by_module.entry(module_id).or_default();
+            *last_release = (*last_release).max(release_date);
+            ranges.push(range);
+        }
+
+        let rows = by_module
+            .into_iter()
+            .map(|(module_id, (last_release, ranges))| {
+                let behind = game_versions
+                    .iter()
+                    .take_while(|&&(major, minor)| {
+                        !ranges
+                            .iter()
+                            .any(|&(exact, min, max)| supports_game(exact, min, max, major, minor))
+                    })
+                    .count();
+
+                (
+                    module_staleness::module_id.eq(module_id),
+                    module_staleness::last_release_date.eq(last_release),
+                    module_staleness::game_versions_behind.eq(behind as i32),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.connection
+            .transaction(|conn| {
+                delete(module_staleness::table).execute(conn)?;
+                for batch in rows.chunks(BATCH_SIZE) {
+                    insert_into(module_staleness::table)
+                        .values(batch)
+                        .execute(conn)?;
+                }
+                QueryResult::Ok(())
+            })
+            .during(operation)?;
+
+        debug!(modules = rows.len(), "Updated module staleness");
+        Ok(rows.len() as u64)
+    }
+
+    fn staleness_rows(&mut self, module: Option<ModuleId>) -> QueryResult<Vec<StalenessRow>> {
+        let mut query = modules::table
+            .inner_join(module_staleness::table)
+            .left_join(
+                bugtracker_checks::table
+                    .on(bugtracker_checks::module_slug.eq(modules::module_slug)),
+            )
+            .select((
+                modules::module_id,
+                modules::module_slug,
+                module_staleness::last_release_date,
+                module_staleness::game_versions_behind,
+                bugtracker_checks::alive.nullable(),
+            ))
+            .order_by(modules::module_slug)
+            .into_boxed();
+        if let Some(module) = module {
+            query = query.filter(modules::module_id.eq(module));
+        }
+
+        query.load(&mut *self.connection)
+    }
+
+    /// Returns whether a module still looks maintained, or `None` if it hasn't
+    /// been worked out since the module was saved.
+    pub fn module_staleness(
+        &mut self,
+        module: ModuleId,
+    ) -> Result<Option<ModuleStaleness>, QueryError> {
+        let now = OffsetDateTime::now_utc();
+        let row = self
+            .staleness_rows(Some(module))
+            .during(|| Operation::LoadStaleness)?
+            .pop();
+
+        Ok(row.map(|row| ModuleStaleness::from_row(row, now)))
+    }
+
+    /// Returns every module which is at least as stale as `at_least`, ordered
+    /// by identifier.
+    pub fn stale_modules(
+        &mut self,
+        at_least: Staleness,
+    ) -> Result<Vec<ModuleStaleness>, QueryError> {
+        let now = OffsetDateTime::now_utc();
+        let rows = self
+            .staleness_rows(None)
+            .during(|| Operation::LoadStaleness)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ModuleStaleness::from_row(row, now))
+            .filter(|module| module.staleness >= at_least)
+            .collect())
+    }
+
+    /// Records whether a module's bug tracker could be reached.
+    pub fn record_bugtracker_check(&mut self, slug: &str, alive: bool) -> Result<(), QueryError> {
+        replace_into(bugtracker_checks::table)
+            .values((
+                bugtracker_checks::module_slug.eq(slug),
+                bugtracker_checks::alive.eq(alive),
+                bugtracker_checks::checked_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::RecordBugtrackerCheck { slug: slug.into() })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::models::BuildRecord,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn classifies_modules() {
+        let now = OffsetDateTime::now_utc();
+        let ago = |days| Some(now - Duration::days(days));
+
+        assert_eq!(
+            Staleness::classify(ago(30), 3, None, now),
+            Staleness::Maintained
+        );
+        assert_eq!(
+            Staleness::classify(ago(1000), 0, None, now),
+            Staleness::Dormant
+        );
+        assert_eq!(
+            Staleness::classify(ago(400), 2, None, now),
+            Staleness::Outdated
+        );
+        assert_eq!(
+            Staleness::classify(ago(1000), 1, None, now),
+            Staleness::Outdated
+        );
+        assert_eq!(
+            Staleness::classify(ago(1000), 2, None, now),
+            Staleness::Abandoned
+        );
+        assert_eq!(
+            Staleness::classify(None, 2, None, now),
+            Staleness::Abandoned
+        );
+        assert_eq!(
+            Staleness::classify(ago(400), 1, Some(false), now),
+            Staleness::Abandoned
+        );
+    }
+
+    #[test]
+    fn saves_staleness_per_module() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let builds = ["1.8.1", "1.10.0", "1.11.2", "1.12.5"];
+        db.register_builds(
+            builds
+                .iter()
+                .enumerate()
+                .map(|(id, version)| BuildRecord {
+                    build_id: id as i32,
+                    version: version.parse().unwrap(),
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let (parallax, _) = db
+            .create_release(
+                &release("Parallax", "1.0")
+                    .merge(json!({
+                        "ksp_version": "1.8",
+                        "release_date": "2019-10-01T00:00:00Z",
+                    }))
+                    .build(),
+                repo.id,
+                None,
+            )
+            .unwrap();
+        db.create_release(
+            &release("Parallax", "2.0")
+                .merge(json!({
+                    "ksp_version_max": "1.10",
+                    "release_date": "2020-06-01T00:00:00Z",
+                }))
+                .build(),
+            repo.id,
+            Some(parallax),
+        )
+        .unwrap();
+        let (scatterer, _) = db
+            .create_release(
+                &release("Scatterer", "1.0")
+                    .merge(json!({ "ksp_version_min": "1.12" }))
+                    .build(),
+                repo.id,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(db.module_staleness(parallax).unwrap(), None);
+        assert_eq!(db.update_staleness().unwrap(), 2);
+
+        let saved = db.module_staleness(parallax).unwrap().unwrap();
+        assert_eq!(saved.slug, "Parallax");
+        assert_eq!(saved.game_versions_behind, 2);
+        assert_eq!(
+            saved.last_release_date,
+            Some(OffsetDateTime::from_unix_timestamp(1_590_969_600).unwrap())
+        );
+        assert_eq!(saved.staleness, Staleness::Abandoned);
+
+        let saved = db.module_staleness(scatterer).unwrap().unwrap();
+        assert_eq!(saved.game_versions_behind, 0);
+        assert_eq!(saved.staleness, Staleness::Dormant);
+
+        // Bug tracker checks are kept when staleness is worked out again.
+        db.record_bugtracker_check("Scatterer", false).unwrap();
+        db.update_staleness().unwrap();
+        let stale = db.stale_modules(Staleness::Dormant).unwrap();
+        assert_eq!(stale.len(), 2);
+        assert_eq!(stale[1].bugtracker_alive, Some(false));
+        let abandoned = db.stale_modules(Staleness::Abandoned).unwrap();
+        assert_eq!(abandoned.len(), 1);
+        assert_eq!(abandoned[0].slug, "Parallax");
+    }
+}
@@ -0,0 +1,219 @@
+//! Repositories which other repositories suggest using.
+
+use std::{borrow::Cow, collections::BTreeMap, ops::DerefMut};
+
+use diesel::{prelude::*, result::Error::DeserializationError};
+use url::Url;
+
+use crate::database::{
+    JsonbValue, Operation, QueryContext, QueryError, RepoDB, canonical_url,
+    models::{Repository, RepositoryRef},
+    schema::*,
+};
+
+/// A repository listed in the `repositories.json` of at least one configured
+/// repository.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct RepoSuggestion {
+    pub name: String,
+    pub url: Url,
+    pub priority: i32,
+    /// Whether the repository is a mirror of another one.
+    pub x_mirror: bool,
+    /// A note about the repository, such as where a mirror is hosted.
+    pub x_comment: Option<String>,
+    /// The names of the repositories which suggest this one.
+    pub suggested_by: Vec<String>,
+    /// Whether a repository with this URL is already configured.
+    pub configured: bool,
+}
+
+impl RepoSuggestion {
+    pub fn as_ref(&self) -> RepositoryRef<'_> {
+        RepositoryRef {
+            name: Cow::Borrowed(&self.name),
+            url: Cow::Borrowed(&self.url),
+            priority: self.priority,
+            x_mirror: self.x_mirror,
+            x_comment: self.x_comment.as_deref().map(Cow::Borrowed),
+        }
+    }
+}
+
+/// A repository reference, and the name of the repository which suggests it.
+type SuggestionRow = (String, JsonbValue, i32, bool, Option<String>, String);
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Lists the repositories suggested by the configured ones, ordered by
+    /// name. Suggestions with the same URL are combined.
+    pub fn repo_suggestions(&mut self) -> Result<Vec<RepoSuggestion>, QueryError> {
+        self.load_suggestions()
+            .during(|| Operation::LoadRepoSuggestions)
+    }
+
+    /// Adds the suggested repository with the given name. Returns `None` if
+    /// no repository suggests one with that name, or if it's already
+    /// configured. Fails without changing anything if a repository with a
+    /// different URL already has that name.
+    pub fn add_suggested_repo(&mut self, name: &str) -> Result<Option<Repository>, QueryError> {
+        let suggestion = self
+            .repo_suggestions()?
+            .into_iter()
+            .find(|s| s.name == name && !s.configured);
+
+        suggestion
+            .map(|s| self.create_empty_repo(s.as_ref()))
+            .transpose()
+    }
+
+    fn load_suggestions(&mut self) -> QueryResult<Vec<RepoSuggestion>> {
+        let refs = repository_refs::table
+            .inner_join(
+                repositories::table.on(repositories::repo_id.eq(repository_refs::referrer_id)),
+            )
+            .select((
+                repository_refs::name,
+                repository_refs::url,
+                repository_refs::priority,
+                repository_refs::x_mirror,
+                repository_refs::x_comment,
+                repositories::name,
+            ))
+            .order_by((repository_refs::name, repositories::name))
+            .load::<SuggestionRow>(&mut *self.connection)?;
+
+        let configured = repositories::table
+            .select(repositories::url)
+            .load::<JsonbValue>(&mut *self.connection)?
+            .into_iter()
+            .map(|url| Url::try_from(url).map(|url| canonical_url(&url)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DeserializationError(e.into()))?;
+
+        // Different spellings of the same URL are one suggestion.
+        let mut suggestions = BTreeMap::<Url, RepoSuggestion>::new();
+        for (name, url, priority, x_mirror, x_comment, referrer) in refs {
+            let url = Url::try_from(url).map_err(|e| DeserializationError(e.into()))?;
+            let key = canonical_url(&url);
+
+            suggestions
+                .entry(key.clone())
+                .or_insert_with(|| RepoSuggestion {
+                    name,
+                    configured: configured.contains(&key),
+                    url,
+                    priority,
+                    x_mirror,
+                    x_comment,
+                    suggested_by: vec![],
+                })
+                .suggested_by
+                .push(referrer);
+        }
+
+        let mut suggestions = suggestions.into_values().collect::<Vec<_>>();
+        suggestions.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(suggestions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        database::models::RepositoryRef,
+        json::RepositoryRefList,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn suggestions_mark_configured_repos() {
+        let (mut db, default) = db_with_default_repo();
+
+        let mirror_url = "https://example.com/mirror.tar.gz".parse().unwrap();
+        let extra_url = "https://example.com/extra.tar.gz".parse().unwrap();
+        let other = db
+            .create_empty_repo(RepositoryRef::shared("other", &extra_url))
+            .unwrap();
+
+        db.add_repo_ref(default.id, RepositoryRef::shared("mirror", &mirror_url))
+            .unwrap();
+        db.add_repo_ref(default.id, RepositoryRef::shared("extra", &extra_url))
+            .unwrap();
+        db.add_repo_ref(other.id, RepositoryRef::shared("mirror", &mirror_url))
+            .unwrap();
+
+        let suggestions = db.repo_suggestions().unwrap();
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(suggestions[0].name, "extra");
+        assert!(suggestions[0].configured);
+        assert_eq!(suggestions[1].name, "mirror");
+        assert!(!suggestions[1].configured);
+        assert_eq!(suggestions[1].suggested_by, ["KSP-default", "other"]);
+
+        assert!(db.add_suggested_repo("extra").unwrap().is_none());
+        let added = db.add_suggested_repo("mirror").unwrap().unwrap();
+        assert_eq!(added.url, mirror_url);
+        assert!(db.repo_suggestions().unwrap().iter().all(|s| s.configured));
+    }
+
+    #[test]
+    fn keeps_mirror_designations() {
+        let (mut db, default) = db_with_default_repo();
+
+        let list: RepositoryRefList = serde_json::from_value(serde_json::json!({
+            "repositories": [{
+                "name": "KSP-default-mirror",
+                "uri": "https://example.com/mirror.tar.gz",
+                "x_mirror": true,
+                "x_comment": "Hosted in Europe",
+            }]
+        }))
+        .unwrap();
+        for new_ref in list.repositories {
+            db.add_repo_ref(default.id, new_ref).unwrap();
+        }
+
+        let suggestion = db.repo_suggestions().unwrap().remove(0);
+        assert!(suggestion.x_mirror);
+        assert_eq!(suggestion.x_comment.as_deref(), Some("Hosted in Europe"));
+
+        let added = db
+            .add_suggested_repo("KSP-default-mirror")
+            .unwrap()
+            .unwrap();
+        assert!(added.x_mirror);
+        assert_eq!(added.x_comment.as_deref(), Some("Hosted in Europe"));
+        assert!(!default.x_mirror);
+    }
+
+    #[test]
+    fn suggestion_keeps_repo_with_same_name() {
+        let (mut db, default) = db_with_default_repo();
+
+        let mirror_url = "https://example.com/mirror.tar.gz".parse().unwrap();
+        let local_url = "https://example.com/local.tar.gz".parse().unwrap();
+        let local = db
+            .create_empty_repo(RepositoryRef::shared("mirror", &local_url))
+            .unwrap();
+        let (module, _) = db
+            .create_release(&release("Parallax", "1.0").build(), local.id, None)
+            .unwrap();
+        db.add_repo_ref(default.id, RepositoryRef::shared("mirror", &mirror_url))
+            .unwrap();
+
+        let err = db.add_suggested_repo("mirror").unwrap_err();
+        assert!(err.is_conflict());
+
+        let repos = db.all_repos(false).unwrap();
+        let kept = repos.iter().find(|r| r.name == "mirror").unwrap();
+        assert_eq!(kept.id, local.id);
+        assert_eq!(kept.url, local_url);
+        assert!(db.latest_release(module).unwrap().is_some());
+
+        // Adding the same repository again is harmless.
+        let again = db
+            .create_empty_repo(RepositoryRef::shared("mirror", &local_url))
+            .unwrap();
+        assert_eq!(again.id, local.id);
+    }
+}
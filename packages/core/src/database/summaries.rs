@@ -0,0 +1,208 @@
+//! Compact descriptions of modules for displaying in lists.
+
+use std::ops::DerefMut;
+
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Bool, Integer, Nullable, Text},
+};
+
+use crate::database::{
+    ModuleCursor, ModuleId, ModuleSummaryPage, Operation, QueryContext, QueryError, ReleaseId,
+    RepoDB, pagination::next_page,
+};
+
+/// The details needed to show a module in a list, taken from its newest
+/// release which is allowed by the user's prerelease settings.
+#[derive(Debug, PartialEq, Eq, uniffi::Record)]
+pub struct ModuleSummary {
+    pub module_id: ModuleId,
+    pub slug: String,
+    pub download_count: i32,
+    pub release_id: ReleaseId,
+    pub display_name: String,
+    pub version: String,
+    pub summary: String,
+    pub tags: Vec<String>,
+    pub locales: Vec<String>,
+    /// Whether the user has marked the module as a favorite.
+    pub favorite: bool,
+    /// Whether the user has written a note about the module.
+    pub has_note: bool,
+}
+
+#[derive(QueryableByName)]
+struct SummaryRow {
+    #[diesel(sql_type = Integer, deserialize_as = i32)]
+    module_id: ModuleId,
+    #[diesel(sql_type = Text)]
+    module_slug: String,
+    #[diesel(sql_type = Integer)]
+    download_count: i32,
+    #[diesel(sql_type = Integer, deserialize_as = i32)]
+    release_id: ReleaseId,
+    #[diesel(sql_type = Text)]
+    display_name: String,
+    #[diesel(sql_type = Text)]
+    version: String,
+    #[diesel(sql_type = Text)]
+    summary: String,
+    #[diesel(sql_type = Text)]
+    tags: String,
+    #[diesel(sql_type = Text)]
+    locales: String,
+    #[diesel(sql_type = Bool)]
+    favorite: bool,
+    #[diesel(sql_type = Bool)]
+    has_note: bool,
+}
+
+impl From<SummaryRow> for ModuleSummary {
+    fn from(row: SummaryRow) -> Self {
+        // These are built by `json_group_array`, so they're always valid.
+        let parse = |list: &str| serde_json::from_str(list).expect("valid JSON array");
+
+        Self {
+            module_id: row.module_id,
+            slug: row.module_slug,
+            download_count: row.download_count,
+            release_id: row.release_id,
+            display_name: row.display_name,
+            version: row.version,
+            summary: row.summary,
+            tags: parse(&row.tags),
+            locales: parse(&row.locales),
+            favorite: row.favorite,
+            has_note: row.has_note,
+        }
+    }
+}
+
+/// Picks one release per module: the newest one allowed by the prerelease
+/// settings, or the newest one overall if none are allowed. Yanked releases are
+/// only picked if every release has been yanked. Tags and locales
+/// are aggregated so that a whole page can be loaded with one query.
+const SUMMARY_QUERY: &str = "
+SELECT
+    m.module_id,
+    m.module_slug,
+    m.download_count,
+    r.release_id,
+    r.display_name,
+    r.version,
+    r.summary,
+    (
+        SELECT json_group_array(tag)
+        FROM (SELECT tag FROM module_tags WHERE release_id = r.release_id ORDER BY ordinal)
+    ) AS tags,
+    (
+        SELECT json_group_array(locale)
+        FROM module_localizations WHERE release_id = r.release_id
+    ) AS locales,
+    EXISTS (SELECT 1 FROM favorites WHERE module_slug = m.module_slug) AS favorite,
+    EXISTS (SELECT 1 FROM module_notes WHERE module_slug = m.module_slug) AS has_note
+FROM modules m
+INNER JOIN module_releases r ON r.release_id = (
+    SELECT latest.release_id
+    FROM module_releases latest
+    LEFT JOIN module_settings ms ON ms.module_slug = m.module_slug
+    LEFT JOIN yanked_releases y
+        ON y.module_slug = m.module_slug AND y.version = latest.version
+    WHERE latest.module_id = m.module_id
+    ORDER BY
+        y.version IS NOT NULL,
+        latest.release_status > CASE
+            WHEN COALESCE(
+                ms.allow_prerelease,
+                (SELECT value = 'on' FROM settings WHERE name = 'prerelease'),
+                FALSE
+            ) THEN 2
+            ELSE 0
+        END,
+        latest.sort_key DESC,
+        latest.version
+    LIMIT 1
+)
+WHERE ? IS NULL OR (m.module_slug, m.module_id) > (?, ?)
+ORDER BY m.module_slug, m.module_id
+LIMIT ?
+";
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Returns up to `limit` module summaries, ordered by slug, starting
+    /// after `after`, or from the first module if it's `None`.
+    pub fn module_summaries(
+        &mut self,
+        after: Option<&ModuleCursor>,
+        limit: u32,
+    ) -> Result<ModuleSummaryPage, QueryError> {
+        let slug = after.map(|cursor| cursor.slug.clone());
+        let module_id = after.map(|cursor| cursor.module_id);
+
+        let rows = sql_query(SUMMARY_QUERY)
+            .bind::<Nullable<Text>, _>(slug.clone())
+            .bind::<Nullable<Text>, _>(slug)
+            .bind::<Nullable<Integer>, _>(module_id)
+            .bind::<BigInt, _>(i64::from(limit) + 1)
+            .load::<SummaryRow>(&mut *self.connection)
+            .during(|| Operation::ModuleSummaries { limit })?;
+
+        let mut modules = rows
+            .into_iter()
+            .map(ModuleSummary::from)
+            .collect::<Vec<_>>();
+        let next = next_page(&mut modules, limit).map(ModuleCursor::after);
+
+        Ok(ModuleSummaryPage { modules, next })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn summaries_use_latest_allowed_release() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let parallax = release("Parallax", "1.0")
+            .merge(json!({
+                "tags": ["graphics", "plugin"],
+                "localizations": ["en-us"],
+            }))
+            .build();
+        let (parallax, _) = db.create_release(&parallax, repo.id, None).unwrap();
+        let prerelease = release("Parallax", "2.0").set("release_status", "testing");
+        db.create_release(&prerelease.build(), repo.id, Some(parallax))
+            .unwrap();
+        let scatterer = release("Scatterer", "0.1").set("release_status", "testing");
+        db.create_release(&scatterer.build(), repo.id, None)
+            .unwrap();
+
+        let summaries = db.module_summaries(None, 10).unwrap().modules;
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].slug, "Parallax");
+        assert_eq!(summaries[0].version, "1.0");
+        assert_eq!(summaries[0].tags, ["graphics", "plugin"]);
+        assert_eq!(summaries[0].locales, ["en-us"]);
+        // Only prereleases are available, so show one anyway.
+        assert_eq!(summaries[1].version, "0.1");
+
+        db.set_prerelease_default(true).unwrap();
+        assert_eq!(
+            db.module_summaries(None, 1).unwrap().modules[0].version,
+            "2.0"
+        );
+        db.yank_release("Parallax", "2.0", None).unwrap();
+        let first = db.module_summaries(None, 1).unwrap();
+        assert_eq!(first.modules[0].version, "1.0");
+
+        let page = db.module_summaries(first.next.as_ref(), 10).unwrap();
+        assert_eq!(page.modules.len(), 1);
+        assert_eq!(page.modules[0].slug, "Scatterer");
+        assert!(page.next.is_none());
+    }
+}
@@ -0,0 +1,154 @@
+//! The tags used by modules, for browsing modules by tag.
+
+use std::ops::DerefMut;
+
+use diesel::{dsl::count, prelude::*};
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, schema::*};
+
+/// Separates the parts of a hierarchical tag, like `planet-pack/stock`.
+pub const TAG_SEPARATOR: char = '/';
+
+/// A tag, and how many modules use it.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct TagCount {
+    pub tag: String,
+    /// The number of modules with a release which has this tag.
+    pub modules: u64,
+}
+
+impl TagCount {
+    /// The part of the tag before the first `/`, or the whole tag if it
+    /// doesn't have one.
+    pub fn prefix(&self) -> &str {
+        self.tag
+            .split_once(TAG_SEPARATOR)
+            .map_or(&self.tag, |(prefix, _)| prefix)
+    }
+}
+
+/// Tags which share a prefix, like `planet-pack` and `planet-pack/stock`.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct TagGroup {
+    pub prefix: String,
+    /// The tags in the group, ordered by name. This includes the prefix
+    /// itself if it's used as a tag.
+    pub tags: Vec<TagCount>,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Lists every tag used by a module, ordered by name.
+    pub fn tags(&mut self) -> Result<Vec<TagCount>, QueryError> {
+        let rows = module_tags::table
+            .inner_join(module_releases::table)
+            .group_by(module_tags::tag)
+            .select((
+                module_tags::tag,
+                count(module_releases::module_id).aggregate_distinct(),
+            ))
+            .order_by(module_tags::tag)
+            .load::<(String, i64)>(&mut *self.connection)
+            .during(|| Operation::LoadTags)?;
+
+        let tags = rows
+            .into_iter()
+            .map(|(tag, modules)| TagCount {
+                tag,
+                modules: modules as u64,
+            })
+            .collect();
+
+        Ok(tags)
+    }
+
+    /// Lists every tag used by a module, grouped by the part of each tag
+    /// before the first `/`. Groups are ordered by prefix.
+    pub fn tag_groups(&mut self) -> Result<Vec<TagGroup>, QueryError> {
+        let mut groups = Vec::<TagGroup>::new();
+
+        // Tags are sorted, but `planet-pack-extras` sorts between `planet-pack`
+        // and `planet-pack/stock`, so a group can't just be appended to.
+        for tag in self.tags()? {
+            match groups.iter_mut().rev().find(|g| g.prefix == tag.prefix()) {
+                Some(group) => group.tags.push(tag),
+                None => groups.push(TagGroup {
+                    prefix: tag.prefix().to_owned(),
+                    tags: vec![tag],
+                }),
+            }
+        }
+
+        groups.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    fn tag(tag: &str, modules: u64) -> TagCount {
+        TagCount {
+            tag: tag.into(),
+            modules,
+        }
+    }
+
+    #[test]
+    fn counts_and_groups_tags() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (opm, _) = db
+            .create_release(
+                &release("OPM", "1.0")
+                    .set("tags", json!(["planet-pack"]))
+                    .build(),
+                repo.id,
+                None,
+            )
+            .unwrap();
+        // Tags on several releases of the same module are only counted once.
+        db.create_release(
+            &release("OPM", "2.0")
+                .set("tags", json!(["planet-pack", "planet-pack/stock"]))
+                .build(),
+            repo.id,
+            Some(opm),
+        )
+        .unwrap();
+        db.create_release(
+            &release("Kerbol", "1.0")
+                .set("tags", json!(["planet-pack-extras", "planet-pack/stock"]))
+                .build(),
+            repo.id,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            db.tags().unwrap(),
+            [
+                tag("planet-pack", 1),
+                tag("planet-pack-extras", 1),
+                tag("planet-pack/stock", 2),
+            ]
+        );
+
+        assert_eq!(
+            db.tag_groups().unwrap(),
+            [
+                TagGroup {
+                    prefix: "planet-pack".into(),
+                    tags: vec![tag("planet-pack", 1), tag("planet-pack/stock", 2)],
+                },
+                TagGroup {
+                    prefix: "planet-pack-extras".into(),
+                    tags: vec![tag("planet-pack-extras", 1)],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Advisory locks which stop two programs, like the command-line app and a GUI,
+//! from updating the same repository at once.
+//!
+//! A lock is a lease. It's released once its update finishes, but if the
+//! program holding it crashes, it expires instead of blocking updates forever.
+//! A long update renews its lease as it goes, so that it isn't taken over.
+
+use std::{
+    ops::DerefMut,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use diesel::{delete, insert_or_ignore_into, prelude::*};
+use time::OffsetDateTime;
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, RepoId, schema::*};
+
+/// Tells apart the locks taken by one process.
+static NEXT_LOCK: AtomicU64 = AtomicU64::new(0);
+
+/// A held lock on updating a repository. Pass it to
+/// [`RepoDB::unlock_update`] once the update is finished.
+#[derive(Debug, Clone)]
+#[must_use = "the lock is held until it's unlocked or expires"]
+pub struct UpdateLock {
+    repo: RepoId,
+    token: String,
+}
+
+/// The program holding a repository's update lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateLockHolder {
+    /// The holder's process ID, which may belong to another computer if the
+    /// database is shared.
+    pub process_id: u32,
+    /// When the lock expires if the holder doesn't unlock it first.
+    pub expires_at: OffsetDateTime,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Locks a repository for updating, if nobody else holds an unexpired lock
+    /// on it. The lock expires after `lease`.
+    pub fn try_lock_update(
+        &mut self,
+        repo: RepoId,
+        lease: Duration,
+    ) -> Result<Option<UpdateLock>, QueryError> {
+        let operation = || Operation::LockUpdate { repo };
+        let now = OffsetDateTime::now_utc();
+
+        delete(update_locks::table)
+            .filter(update_locks::repo_id.eq(repo))
+            .filter(update_locks::expires_at.le(now))
+            .execute(&mut *self.connection)
+            .during(operation)?;
+
+        let token = format!(
+            "{}-{}-{}",
+            process::id(),
+            now.unix_timestamp_nanos(),
+            NEXT_LOCK.fetch_add(1, Ordering::Relaxed)
+        );
+
+        // If someone else took the lock since the expired one was removed, this
+        // is ignored.
+        let inserted = insert_or_ignore_into(update_locks::table)
+            .values((
+                update_locks::repo_id.eq(repo),
+                update_locks::token.eq(&token),
+                update_locks::process_id.eq(process::id() as i32),
+                update_locks::expires_at.eq(now + lease),
+            ))
+            .execute(&mut *self.connection)
+            .during(operation)?;
+
+        Ok((inserted == 1).then_some(UpdateLock { repo, token }))
+    }
+
+    /// Extends a lock taken by [`Self::try_lock_update`] so that it expires
+    /// after `lease` from now. Returns `false` if the lock has already expired
+    /// and been taken by someone else, or released.
+    pub fn renew_update_lock(
+        &mut self,
+        lock: &UpdateLock,
+        lease: Duration,
+    ) -> Result<bool, QueryError> {
+        let renewed = diesel::update(update_locks::table)
+            .filter(update_locks::repo_id.eq(lock.repo))
+            .filter(update_locks::token.eq(&lock.token))
+            .set(update_locks::expires_at.eq(OffsetDateTime::now_utc() + lease))
+            .execute(&mut *self.connection)
+            .during(|| Operation::LockUpdate { repo: lock.repo })?;
+
+        Ok(renewed == 1)
+    }
+
+    /// The program holding a repository's update lock, or `None` if it isn't
+    /// locked or the lock has expired.
+    pub fn update_lock_holder(
+        &mut self,
+        repo: RepoId,
+    ) -> Result<Option<UpdateLockHolder>, QueryError> {
+        let holder = update_locks::table
+            .find(repo)
+            .filter(update_locks::expires_at.gt(OffsetDateTime::now_utc()))
+            .select((update_locks::process_id, update_locks::expires_at))
+            .get_result::<(i32, OffsetDateTime)>(&mut *self.connection)
+            .optional()
+            .during(|| Operation::LockUpdate { repo })?;
+
+        Ok(holder.map(|(process_id, expires_at)| UpdateLockHolder {
+            process_id: process_id as u32,
+            expires_at,
+        }))
+    }
+
+    /// Releases a lock taken by [`Self::try_lock_update`]. Nothing happens if
+    /// the lock has expired and been taken by someone else.
+    pub fn unlock_update(&mut self, lock: UpdateLock) -> Result<(), QueryError> {
+        delete(update_locks::table)
+            .filter(update_locks::repo_id.eq(lock.repo))
+            .filter(update_locks::token.eq(&lock.token))
+            .execute(&mut *self.connection)
+            .during(|| Operation::UnlockUpdate { repo: lock.repo })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repo::test_support::db_with_default_repo;
+
+    use super::*;
+
+    #[test]
+    fn only_one_update_holds_the_lock() {
+        let (mut db, repo) = db_with_default_repo();
+        let repo = repo.id;
+        let lease = Duration::from_secs(60);
+
+        let lock = db.try_lock_update(repo, lease).unwrap().unwrap();
+        assert!(db.try_lock_update(repo, lease).unwrap().is_none());
+        assert_eq!(
+            db.update_lock_holder(repo).unwrap().unwrap().process_id,
+            process::id()
+        );
+
+        db.unlock_update(lock).unwrap();
+        assert_eq!(db.update_lock_holder(repo).unwrap(), None);
+
+        // An expired lock doesn't stop anyone, and unlocking it afterwards
+        // doesn't release the new holder's lock.
+        let expired = db.try_lock_update(repo, Duration::ZERO).unwrap().unwrap();
+        let lock = db.try_lock_update(repo, lease).unwrap().unwrap();
+        db.unlock_update(expired).unwrap();
+        assert!(db.update_lock_holder(repo).unwrap().is_some());
+        db.unlock_update(lock).unwrap();
+    }
+
+    #[test]
+    fn renewing_extends_the_lease() {
+        let (mut db, repo) = db_with_default_repo();
+        let repo = repo.id;
+
+        let lock = db.try_lock_update(repo, Duration::ZERO).unwrap().unwrap();
+        assert!(
+            db.renew_update_lock(&lock, Duration::from_secs(60))
+                .unwrap()
+        );
+        assert!(
+            db.try_lock_update(repo, Duration::from_secs(60))
+                .unwrap()
+                .is_none()
+        );
+
+        // A released lock can't be renewed.
+        db.unlock_update(lock.clone()).unwrap();
+        assert!(
+            !db.renew_update_lock(&lock, Duration::from_secs(60))
+                .unwrap()
+        );
+        assert_eq!(db.update_lock_holder(repo).unwrap(), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Finding newer releases of installed modules.
+
+use std::ops::DerefMut;
+
+use diesel::prelude::*;
+
+use crate::{
+    database::{
+        Operation, QueryContext, QueryError, ReleaseId, RepoDB,
+        compatibility::supports,
+        models::{ModuleRelease, module::ModuleVersion},
+        schema::*,
+    },
+    json::ReleaseStatus,
+    repo::game::GameVersion,
+};
+
+/// An installed release, paired with a newer release of the same module.
+#[derive(Debug, uniffi::Record)]
+pub struct AvailableUpgrade {
+    pub slug: String,
+    pub installed: ModuleRelease,
+    /// The newest release allowed by the user's prerelease settings.
+    pub latest: ModuleRelease,
+}
+
+/// A release of a module with an installed release, with the user's settings
+/// for that module and whether the release was yanked.
+type UpgradeRow = (String, ModuleRelease, Option<bool>, Option<String>, bool);
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Pairs each of the given installed releases with the newest release of
+    /// its module, ordered by slug. Modules which are already up to date are
+    /// left out.
+    ///
+    /// The newest release is chosen like [`Self::latest_release`], so a
+    /// prerelease is only offered if it's allowed for that module, and a
+    /// yanked release is never offered. If `game_version` is given, only
+    /// releases which support its major and minor version are offered. An
+    /// installed release which is newer than every allowed release, such as a
+    /// beta installed before prereleases were turned off, is never paired with
+    /// an older one. Modules which the user pinned are left out too.
+    pub fn available_upgrades(
+        &mut self,
+        installed: &[ReleaseId],
+        game_version: Option<GameVersion>,
+    ) -> Result<Vec<AvailableUpgrade>, QueryError> {
+        let operation = || Operation::FindUpgrades;
+
+        let prerelease_default = self.prerelease_default()?;
+        // The outer query already joins `module_releases`, so the subquery
+        // reads it under another name.
+        let installed_releases = diesel::alias!(module_releases as installed_releases);
+        let installed_modules = installed_releases
+            .filter(
+                installed_releases
+                    .field(module_releases::release_id)
+                    .eq_any(installed),
+            )
+            .select(installed_releases.field(module_releases::module_id));
+
+        // Every release of each installed module, newest first.
+        let rows = module_releases::table
+            .inner_join(modules::table)
+            .left_join(
+                module_settings::table.on(module_settings::module_slug.eq(modules::module_slug)),
+            )
+            .left_join(
+                yanked_releases::table.on(yanked_releases::module_slug
+                    .eq(modules::module_slug)
+                    .and(yanked_releases::version.eq(module_releases::version))),
+            )
+            .filter(module_releases::module_id.eq_any(installed_modules))
+            .select((
+                modules::module_slug,
+                ModuleRelease::as_select(),
+                module_settings::allow_prerelease.nullable(),
+                module_settings::pinned_version.nullable(),
+                yanked_releases::version.nullable().is_not_null(),
+            ))
+            // Modules in different repositories can share a slug, so each
+            // module's releases are kept together by its ID.
+            .order_by((
+                modules::module_slug,
+                modules::module_id,
+                module_releases::sort_key.desc(),
+                module_releases::version,
+            ))
+            .load::<UpgradeRow>(&mut *self.connection)
+            .during(operation)?;
+
+        let game_version = game_version.and_then(|v| Some((v.major()?, v.minor()?)));
+
+        let mut upgrades = vec![];
+        for releases in rows.chunk_by(|a, b| a.1.module_id == b.1.module_id) {
+            let (slug, _, allow_prerelease, pinned_version, _) = &releases[0];
+            if pinned_version.is_some() {
+                continue;
+            }
+
+            let max_status = if allow_prerelease.unwrap_or(prerelease_default) {
+                ReleaseStatus::Development
+            } else {
+                ReleaseStatus::Stable
+            };
+            let latest = releases
+                .iter()
+                .filter(|(_, release, _, _, yanked)| {
+                    !yanked
+                        && release.release_status <= max_status
+                        && game_version.is_none_or(|(major, minor)| supports(release, major, minor))
+                })
+                .map(|(_, release, ..)| release)
+                .next();
+            let Some(latest) = latest else {
+                continue;
+            };
+
+            for (_, release, ..) in releases {
+                if installed.contains(&release.id)
+                    && ModuleVersion::from(latest.version.as_str())
+                        > ModuleVersion::from(release.version.as_str())
+                {
+                    upgrades.push(AvailableUpgrade {
+                        slug: slug.clone(),
+                        installed: release.clone(),
+                        latest: latest.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(upgrades)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use url::Url;
+
+    use crate::{
+        database::models::RepositoryRef,
+        repo::{
+            game::GameVersion,
+            test_support::{db_with_default_repo, release},
+        },
+    };
+
+    #[test]
+    fn pairs_installed_with_latest() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (scatterer, old_scatterer) = db
+            .create_release(&release("Scatterer", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(
+            &release("Scatterer", "1.1").build(),
+            repo.id,
+            Some(scatterer),
+        )
+        .unwrap();
+        let (parallax, old_parallax) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        let (_, beta) = db
+            .create_release(
+                &release("Parallax", "2.0-beta")
+                    .set("release_status", "testing")
+                    .build(),
+                repo.id,
+                Some(parallax),
+            )
+            .unwrap();
+        let (_, current) = db
+            .create_release(&release("Kopernicus", "1.0").build(), repo.id, None)
+            .unwrap();
+
+        let upgrades = db
+            .available_upgrades(&[old_scatterer, old_parallax, current], None)
+            .unwrap();
+        assert_eq!(upgrades.len(), 1);
+        assert_eq!(upgrades[0].slug, "Scatterer");
+        assert_eq!(upgrades[0].installed.id, old_scatterer);
+        assert_eq!(upgrades[0].latest.version, "1.1");
+
+        db.set_module_prerelease("Parallax", Some(true)).unwrap();
+        let upgrades = db.available_upgrades(&[old_parallax], None).unwrap();
+        assert_eq!(upgrades[0].latest.id, beta);
+
+        // Turning prereleases off again doesn't suggest going back to 1.0.
+        db.set_module_prerelease("Parallax", Some(false)).unwrap();
+        assert!(db.available_upgrades(&[beta], None).unwrap().is_empty());
+
+        // Pinned modules are held where they are.
+        db.set_module_pin("Scatterer", Some("1.0")).unwrap();
+        assert!(
+            db.available_upgrades(&[old_scatterer], None)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn keeps_modules_sharing_a_slug_apart() {
+        let (mut db, main) = db_with_default_repo();
+        let url = Url::parse("https://example.com/other.tar.gz").unwrap();
+        let other = db
+            .create_empty_repo(RepositoryRef::shared("other", &url))
+            .unwrap();
+
+        // Ordered newest first, the two modules' releases are interleaved.
+        let (main_module, main_old) = db
+            .create_release(&release("Parallax", "1.0").build(), main.id, None)
+            .unwrap();
+        let (_, main_new) = db
+            .create_release(
+                &release("Parallax", "2.0").build(),
+                main.id,
+                Some(main_module),
+            )
+            .unwrap();
+        let (other_module, other_old) = db
+            .create_release(&release("Parallax", "1.0").build(), other.id, None)
+            .unwrap();
+        let (_, other_new) = db
+            .create_release(
+                &release("Parallax", "1.5").build(),
+                other.id,
+                Some(other_module),
+            )
+            .unwrap();
+
+        let mut upgrades = db
+            .available_upgrades(&[main_old, other_old], None)
+            .unwrap()
+            .into_iter()
+            .map(|upgrade| (upgrade.installed.id, upgrade.latest.id))
+            .collect::<Vec<_>>();
+        upgrades.sort();
+        let mut expected = vec![(main_old, main_new), (other_old, other_new)];
+        expected.sort();
+        assert_eq!(upgrades, expected);
+    }
+
+    #[test]
+    fn only_offers_compatible_releases() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let old = release("Parallax", "1.0")
+            .set("ksp_version", "1.10")
+            .build();
+        let (parallax, old) = db.create_release(&old, repo.id, None).unwrap();
+        let compatible = release("Parallax", "1.1")
+            .merge(json!({"ksp_version_min": "1.10", "ksp_version_max": "1.11"}))
+            .build();
+        let (_, compatible) = db
+            .create_release(&compatible, repo.id, Some(parallax))
+            .unwrap();
+        let newest = release("Parallax", "2.0")
+            .set("ksp_version", "1.12")
+            .build();
+        let (_, newest) = db.create_release(&newest, repo.id, Some(parallax)).unwrap();
+
+        let game = |minor| Some(GameVersion::new(Some(1), Some(minor), Some(3), None));
+        let upgrades = db.available_upgrades(&[old], game(11)).unwrap();
+        assert_eq!(upgrades[0].latest.id, compatible);
+        let upgrades = db.available_upgrades(&[old], game(12)).unwrap();
+        assert_eq!(upgrades[0].latest.id, newest);
+        assert!(db.available_upgrades(&[old], game(9)).unwrap().is_empty());
+        let upgrades = db.available_upgrades(&[old], None).unwrap();
+        assert_eq!(upgrades[0].latest.id, newest);
+    }
+}
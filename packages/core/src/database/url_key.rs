@@ -0,0 +1,127 @@
+//! Canonical forms of URLs which are used as keys.
+//!
+//! Tables like `etags` and `download_mirrors` are keyed on a JSONB-encoded URL,
+//! so two spellings of the same URL would otherwise get separate rows.
+
+use diesel::{
+    QueryResult, expression::functions::declare_sql_function, sql_types::Binary,
+    sqlite::SqliteConnection,
+};
+use url::Url;
+
+use crate::database::JsonbValue;
+
+/// Returns the form of a URL which is used to key it in the database.
+///
+/// Fragments, empty queries and trailing slashes are removed, and the host is
+/// lowercased. The `url` crate already removes default ports and lowercases
+/// the hosts of `http` and `https` URLs when they are parsed.
+pub fn canonical_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    if url.query() == Some("") {
+        url.set_query(None);
+    }
+
+    let uppercase_host = url
+        .host_str()
+        .filter(|host| host.bytes().any(|b| b.is_ascii_uppercase()));
+    if let Some(host) = uppercase_host {
+        let host = host.to_ascii_lowercase();
+        // Only fails for hosts which couldn't have been parsed to begin with.
+        let _ = url.set_host(Some(&host));
+    }
+
+    let path = url.path();
+    if !url.cannot_be_a_base() && path.len() > 1 && path.ends_with('/') {
+        let trimmed = path.trim_end_matches('/').to_owned();
+        url.set_path(&trimmed);
+    }
+
+    url
+}
+
+impl JsonbValue {
+    /// Encodes the canonical form of a URL, for use as a key.
+    pub fn url_key(url: &Url) -> Self {
+        Self::from(&canonical_url(url))
+    }
+}
+
+#[declare_sql_function]
+extern "SQL" {
+    /// The canonical form of a JSONB-encoded URL, as returned by
+    /// [`JsonbValue::url_key`]. Values which aren't URLs are returned as-is.
+    fn url_key(url: Binary) -> Binary;
+
+    /// The text of a JSONB-encoded URL, for queries which build JSON, since
+    /// SQLite's own JSON functions can't read JSONB before 3.45.
+    fn url_text(url: Binary) -> Nullable<Text>;
+}
+
+/// Registers [`url_key`] and [`url_text`] with a connection.
+pub(crate) fn register_functions(conn: &mut SqliteConnection) -> QueryResult<()> {
+    url_key_utils::register_impl(conn, |url: JsonbValue| {
+        match url.0.as_str().map(Url::parse) {
+            Some(Ok(parsed)) => JsonbValue::url_key(&parsed),
+            _ => url,
+        }
+    })?;
+    url_text_utils::register_impl(conn, |url: JsonbValue| url.0.as_str().map(str::to_owned))
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::{dsl::select, prelude::*};
+
+    use super::*;
+    use crate::repo::RepoManager;
+
+    fn canonical(url: &str) -> String {
+        canonical_url(&url.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn canonicalizes_urls() {
+        assert_eq!(
+            canonical("HTTPS://GitHub.com:443/KSP-CKAN/CKAN-meta/archive/master.tar.gz"),
+            "https://github.com/KSP-CKAN/CKAN-meta/archive/master.tar.gz"
+        );
+        assert_eq!(
+            canonical("https://example.com/repo/"),
+            "https://example.com/repo"
+        );
+        assert_eq!(
+            canonical("https://example.com/repo//?#top"),
+            "https://example.com/repo"
+        );
+        assert_eq!(canonical("https://example.com/"), "https://example.com/");
+        assert_eq!(
+            canonical("https://example.com/a?b=c"),
+            "https://example.com/a?b=c"
+        );
+        assert_eq!(canonical("git://Example.com/a/"), "git://example.com/a");
+        assert_eq!(
+            canonical("mailto:someone@Example.com"),
+            "mailto:someone@Example.com"
+        );
+    }
+
+    #[test]
+    fn sql_function_matches() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+        let url: Url = "https://Example.com/meta.tar.gz/#latest".parse().unwrap();
+
+        let key = select(url_key(JsonbValue::from(&url)))
+            .get_result::<JsonbValue>(db.connection_mut())
+            .unwrap();
+        assert_eq!(key.0, JsonbValue::url_key(&url).0);
+
+        let not_a_url = JsonbValue(serde_json::json!(["a", "b"]));
+        let unchanged = select(url_key(&not_a_url))
+            .get_result::<JsonbValue>(db.connection_mut())
+            .unwrap();
+        assert_eq!(unchanged.0, not_a_url.0);
+    }
+}
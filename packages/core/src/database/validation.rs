@@ -0,0 +1,297 @@
+//! Checking release metadata for problems which don't stop it from being
+//! saved, like a download without a SHA-256 checksum.
+//!
+//! Checking every release is slow, so each release's findings are saved once
+//! it's been checked, for showing as badges in a module list. A trigger deletes
+//! a release's findings whenever the release changes, and
+//! [`RepoDB::validate_releases`] checks any release without them.
+
+use std::{collections::HashMap, ops::DerefMut};
+
+use diesel::{insert_into, prelude::*};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use crate::{
+    database::{
+        JsonbValue, Operation, QueryContext, QueryError, ReleaseId, RepoDB,
+        models::ReleaseMetadata, schema::*,
+    },
+    json::{ModuleInstallDescriptor, ModuleInstallSourceDirective},
+};
+
+/// How many releases are checked at a time, which limits how many licenses are
+/// looked up in one query.
+const BATCH_SIZE: usize = 500;
+
+/// Licenses which SPDX has deprecated, because they don't say whether later
+/// versions of the license are allowed too.
+const DEPRECATED_LICENSES: &[&str] = &[
+    "AGPL-1.0",
+    "AGPL-3.0",
+    "GFDL-1.1",
+    "GFDL-1.2",
+    "GFDL-1.3",
+    "GPL-1.0",
+    "GPL-2.0",
+    "GPL-3.0",
+    "LGPL-2.0",
+    "LGPL-2.1",
+    "LGPL-3.0",
+];
+
+/// The folders of a game directory which install directives can target, other
+/// than the game directory itself.
+const INSTALL_TARGETS: &[&str] = &["GameData", "Missions", "Ships", "Scenarios", "Tutorial"];
+
+/// Something wrong with a release's metadata. Install directives are numbered
+/// from zero, in the order they appear in the metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Enum)]
+#[serde(tag = "finding", rename_all = "snake_case")]
+pub enum ReleaseFinding {
+    /// The release can be downloaded, but its download can't be verified
+    /// because it has no SHA-256 checksum.
+    MissingSha256,
+    /// The release uses a license name which SPDX has deprecated, like
+    /// `GPL-3.0` instead of `GPL-3.0-only`.
+    DeprecatedLicense { license: String },
+    /// An install directive has a regular expression which can't be compiled,
+    /// so installing the release will fail.
+    InvalidInstallRegex { directive: u32, pattern: String },
+    /// An install directive installs outside the folders of the game which
+    /// modules are allowed to change.
+    InvalidInstallTarget { directive: u32, install_to: String },
+    /// An install directive renames what it installs to something which isn't
+    /// a plain file or folder name.
+    InvalidInstallName { directive: u32, name: String },
+}
+
+/// The findings saved for a release. An empty list means the release was
+/// checked and nothing was found.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, Insertable, uniffi::Record)]
+#[diesel(table_name = release_validations)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ReleaseValidation {
+    pub release_id: ReleaseId,
+    #[diesel(deserialize_as = JsonbValue)]
+    #[diesel(serialize_as = JsonbValue)]
+    pub findings: Vec<ReleaseFinding>,
+}
+
+/// Checks a release's metadata and licenses.
+pub fn validate_release(metadata: &ReleaseMetadata, licenses: &[String]) -> Vec<ReleaseFinding> {
+    let mut findings = vec![];
+
+    if !metadata.download.is_empty() && metadata.download_hash.sha256.is_none() {
+        findings.push(ReleaseFinding::MissingSha256);
+    }
+
+    for license in licenses {
+        if DEPRECATED_LICENSES.contains(&license.as_str()) {
+            findings.push(ReleaseFinding::DeprecatedLicense {
+                license: license.clone(),
+            });
+        }
+    }
+
+    for (directive, install) in (0..).zip(metadata.install.iter()) {
+        validate_directive(directive, install, &mut findings);
+    }
+
+    findings
+}
+
+fn validate_directive(
+    directive: u32,
+    install: &ModuleInstallDescriptor,
+    findings: &mut Vec<ReleaseFinding>,
+) {
+    let find_regexp = match &install.source {
+        ModuleInstallSourceDirective::FindRegexp(pattern) => Some(pattern),
+        _ => None,
+    };
+    let patterns = find_regexp
+        .into_iter()
+        .chain(&install.filter_regexp)
+        .chain(&install.include_only_regexp);
+    for pattern in patterns {
+        if Regex::new(pattern).is_err() {
+            findings.push(ReleaseFinding::InvalidInstallRegex {
+                directive,
+                pattern: pattern.clone(),
+            });
+        }
+    }
+
+    if !is_install_target(&install.install_to) {
+        findings.push(ReleaseFinding::InvalidInstallTarget {
+            directive,
+            install_to: install.install_to.clone(),
+        });
+    }
+
+    if let Some(name) = &install.r#as
+        && !is_plain_name(name)
+    {
+        findings.push(ReleaseFinding::InvalidInstallName {
+            directive,
+            name: name.clone(),
+        });
+    }
+}
+
+/// Returns whether a directive's `install_to` is the game directory, or a
+/// folder inside one of the folders modules can install to.
+fn is_install_target(install_to: &str) -> bool {
+    if install_to == "GameRoot" {
+        return true;
+    }
+
+    let mut parts = install_to.split('/');
+    parts
+        .next()
+        .is_some_and(|first| INSTALL_TARGETS.contains(&first))
+        && parts.all(is_plain_name)
+}
+
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Checks every release which doesn't have saved findings, and saves
+    /// them. Returns how many releases were checked.
+    #[instrument(skip(self))]
+    pub fn validate_releases(&mut self) -> Result<u64, QueryError> {
+        let operation = || Operation::ValidateReleases;
+
+        let unchecked = module_releases::table
+            .left_join(release_validations::table)
+            .filter(release_validations::release_id.is_null())
+            .select(module_releases::release_id)
+            .load::<ReleaseId>(&mut *self.connection)
+            .during(operation)?;
+
+        for batch in unchecked.chunks(BATCH_SIZE) {
+            let releases = module_releases::table
+                .filter(module_releases::release_id.eq_any(batch))
+                .select((module_releases::release_id, module_releases::metadata))
+                .load::<(ReleaseId, JsonbValue)>(&mut *self.connection)
+                .during(operation)?;
+
+            let mut licenses = HashMap::<ReleaseId, Vec<String>>::new();
+            for (release, license) in module_licenses::table
+                .filter(module_licenses::release_id.eq_any(batch))
+                .select((module_licenses::release_id, module_licenses::license))
+                .load::<(ReleaseId, String)>(&mut *self.connection)
+                .during(operation)?
+            {
+                licenses.entry(release).or_default().push(license);
+            }
+
+            let validations = releases
+                .into_iter()
+                .map(|(release_id, metadata)| {
+                    let metadata = ReleaseMetadata::try_from(metadata)
+                        .map_err(|e| diesel::result::Error::DeserializationError(e.into()))
+                        .during(operation)?;
+                    let licenses = licenses.remove(&release_id).unwrap_or_default();
+
+                    Ok(ReleaseValidation {
+                        release_id,
+                        findings: validate_release(&metadata, &licenses),
+                    })
+                })
+                .collect::<Result<Vec<_>, QueryError>>()?;
+
+            insert_into(release_validations::table)
+                .values(validations)
+                .execute(&mut *self.connection)
+                .during(operation)?;
+        }
+
+        debug!(checked = unchecked.len(), "Validated releases");
+        Ok(unchecked.len() as u64)
+    }
+
+    /// The saved findings of the given releases. Releases which haven't been
+    /// checked are left out.
+    pub fn release_validations(
+        &mut self,
+        releases: &[ReleaseId],
+    ) -> Result<Vec<ReleaseValidation>, QueryError> {
+        release_validations::table
+            .filter(release_validations::release_id.eq_any(releases))
+            .select(ReleaseValidation::as_select())
+            .order_by(release_validations::release_id)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadValidations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::update;
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn saves_findings_until_releases_change() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let release = release("Parallax", "2.0.1")
+            .merge(json!({
+                "license": ["GPL-3.0", "MIT"],
+                "download": "https://example.com/Parallax.zip",
+                "download_hash": { "sha1": "abc" },
+                "install": [
+                    { "find_regexp": "Parallax(", "install_to": "GameData" },
+                    { "find": "Ships", "install_to": "../Desktop" },
+                    { "find": "Configs", "install_to": "GameData/Parallax", "as": "a/b" },
+                ],
+            }))
+            .build();
+        let (_, release_id) = db.create_release(&release, repo.id, None).unwrap();
+
+        assert!(db.release_validations(&[release_id]).unwrap().is_empty());
+        assert_eq!(db.validate_releases().unwrap(), 1);
+        assert_eq!(db.validate_releases().unwrap(), 0);
+
+        let validations = db.release_validations(&[release_id]).unwrap();
+        assert_eq!(
+            validations,
+            [ReleaseValidation {
+                release_id,
+                findings: vec![
+                    ReleaseFinding::MissingSha256,
+                    ReleaseFinding::DeprecatedLicense {
+                        license: "GPL-3.0".into()
+                    },
+                    ReleaseFinding::InvalidInstallRegex {
+                        directive: 0,
+                        pattern: "Parallax(".into()
+                    },
+                    ReleaseFinding::InvalidInstallTarget {
+                        directive: 1,
+                        install_to: "../Desktop".into()
+                    },
+                    ReleaseFinding::InvalidInstallName {
+                        directive: 2,
+                        name: "a/b".into()
+                    },
+                ],
+            }]
+        );
+
+        // Changing the release forgets its findings.
+        update(module_releases::table.find(release_id))
+            .set(module_releases::summary.eq("Another mod"))
+            .execute(db.connection_mut())
+            .unwrap();
+        assert!(db.release_validations(&[release_id]).unwrap().is_empty());
+        assert_eq!(db.validate_releases().unwrap(), 1);
+    }
+}
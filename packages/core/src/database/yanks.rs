@@ -0,0 +1,105 @@
+//! Releases the user has yanked.
+//!
+//! A yanked release is kept in the database, so it's still shown as part of
+//! its module's history, but it's never chosen as the module's latest release
+//! or installed to satisfy a relationship. Yanks are kept across repository
+//! updates.
+
+use std::ops::DerefMut;
+
+use diesel::{delete, prelude::*, replace_into};
+use time::OffsetDateTime;
+
+use crate::database::{Operation, QueryContext, QueryError, RepoDB, schema::*};
+
+/// A release which has been yanked.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Selectable, uniffi::Record)]
+#[diesel(table_name = yanked_releases)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct YankedRelease {
+    #[diesel(column_name = module_slug)]
+    pub slug: String,
+    pub version: String,
+    /// Why the release was yanked, if a reason was given.
+    pub reason: Option<String>,
+    pub yanked_at: OffsetDateTime,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Yanks a release, replacing the reason if it was already yanked. The
+    /// release doesn't have to be in the database, so releases can be yanked
+    /// before a repository provides them.
+    pub fn yank_release(
+        &mut self,
+        slug: &str,
+        version: &str,
+        reason: Option<&str>,
+    ) -> Result<(), QueryError> {
+        replace_into(yanked_releases::table)
+            .values((
+                yanked_releases::module_slug.eq(slug),
+                yanked_releases::version.eq(version),
+                yanked_releases::reason.eq(reason),
+                yanked_releases::yanked_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(&mut *self.connection)
+            .during(|| Operation::YankRelease {
+                slug: slug.into(),
+                version: version.into(),
+            })?;
+
+        Ok(())
+    }
+
+    /// Reverses a yank. Returns whether the release had been yanked.
+    pub fn unyank_release(&mut self, slug: &str, version: &str) -> Result<bool, QueryError> {
+        let deleted = delete(yanked_releases::table.find((slug, version)))
+            .execute(&mut *self.connection)
+            .during(|| Operation::YankRelease {
+                slug: slug.into(),
+                version: version.into(),
+            })?;
+
+        Ok(deleted > 0)
+    }
+
+    /// The yanked releases of a module, ordered by version.
+    pub fn yanked_releases(&mut self, slug: &str) -> Result<Vec<YankedRelease>, QueryError> {
+        yanked_releases::table
+            .filter(yanked_releases::module_slug.eq(slug))
+            .select(YankedRelease::as_select())
+            .order_by(yanked_releases::version)
+            .load(&mut *self.connection)
+            .during(|| Operation::LoadYanks { slug: slug.into() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn yanked_releases_are_never_latest() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (module, old) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        let (_, new) = db
+            .create_release(&release("Parallax", "1.1").build(), repo.id, Some(module))
+            .unwrap();
+
+        db.yank_release("Parallax", "1.1", Some("Crashes on load"))
+            .unwrap();
+        assert_eq!(db.latest_release(module).unwrap().unwrap().id, old);
+        assert_eq!(db.available_upgrades(&[old], None).unwrap().len(), 0);
+
+        let yanked = db.yanked_releases("Parallax").unwrap();
+        assert_eq!(yanked.len(), 1);
+        assert_eq!(yanked[0].reason.as_deref(), Some("Crashes on load"));
+
+        assert!(db.unyank_release("Parallax", "1.1").unwrap());
+        assert!(!db.unyank_release("Parallax", "1.1").unwrap());
+        assert_eq!(db.latest_release(module).unwrap().unwrap().id, new);
+    }
+}
@@ -0,0 +1,269 @@
+//! Tables of modules for spreadsheets.
+//!
+//! A table has one row per module, describing its newest release allowed by
+//! the user's prerelease settings, and whichever [`Column`]s were asked for.
+//! It can be written as CSV or as tab-separated values.
+
+use std::{borrow::Cow, io::Write, ops::DerefMut};
+
+use diesel::prelude::*;
+use strum::{EnumString, IntoStaticStr};
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+    Result,
+    database::{
+        ModuleFilter, QueryError, RepoDB,
+        models::{Module, ModuleRelease},
+    },
+};
+
+/// A piece of information about a module which can be exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr, EnumString, uniffi::Enum)]
+#[strum(serialize_all = "snake_case")]
+pub enum Column {
+    Identifier,
+    Name,
+    Version,
+    /// The release status, like `stable` or `testing`.
+    Status,
+    Summary,
+    Authors,
+    Licenses,
+    Tags,
+    /// How many times the module has been downloaded.
+    Downloads,
+    /// When the release was published, in RFC 3339 format.
+    ReleaseDate,
+    /// The size of the release's download in bytes.
+    DownloadSize,
+    Homepage,
+    Repository,
+}
+
+impl Column {
+    /// The columns used when none are chosen.
+    pub const DEFAULT: &[Self] = &[
+        Self::Identifier,
+        Self::Name,
+        Self::Version,
+        Self::Authors,
+        Self::Tags,
+        Self::Downloads,
+    ];
+
+    /// The column's name, as used in the header row.
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+
+    /// The column's value for a row. Lists are joined with `, `.
+    fn value(self, row: &ExportRow) -> Cow<'_, str> {
+        let release = row.release.as_ref();
+        fn text(value: Option<&str>) -> Cow<'_, str> {
+            Cow::Borrowed(value.unwrap_or_default())
+        }
+
+        match self {
+            Self::Identifier => Cow::Borrowed(&row.module.slug),
+            Self::Name => text(release.map(|r| r.display_name.as_str())),
+            Self::Version => text(release.map(|r| r.version.as_str())),
+            Self::Status => release.map_or(Cow::Borrowed(""), |r| {
+                format!("{:?}", r.release_status).to_lowercase().into()
+            }),
+            Self::Summary => text(release.map(|r| r.summary.as_str())),
+            Self::Authors => row.authors.join(", ").into(),
+            Self::Licenses => row.licenses.join(", ").into(),
+            Self::Tags => row.tags.join(", ").into(),
+            Self::Downloads => row.module.download_count.to_string().into(),
+            Self::ReleaseDate => release
+                .and_then(|r| r.release_date)
+                .and_then(|date| date.format(&Rfc3339).ok())
+                .unwrap_or_default()
+                .into(),
+            Self::DownloadSize => release
+                .and_then(|r| r.download_size)
+                .map(|size| size.to_string())
+                .unwrap_or_default()
+                .into(),
+            Self::Homepage => text(
+                release
+                    .and_then(|r| r.metadata.resources.homepage.as_ref())
+                    .map(|url| url.as_str()),
+            ),
+            Self::Repository => text(
+                release
+                    .and_then(|r| r.metadata.resources.repository.as_ref())
+                    .map(|url| url.as_str()),
+            ),
+        }
+    }
+}
+
+/// How a table is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum TableFormat {
+    /// Comma-separated values, quoted as described by RFC 4180.
+    Csv,
+    /// Tab-separated values. Tabs and line breaks within a value are replaced
+    /// with spaces, since TSV has no way of quoting them.
+    Tsv,
+}
+
+impl TableFormat {
+    fn separator(self) -> char {
+        match self {
+            Self::Csv => ',',
+            Self::Tsv => '\t',
+        }
+    }
+
+    fn escape(self, value: &str) -> Cow<'_, str> {
+        match self {
+            Self::Csv if value.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", value.replace('"', "\"\"")).into()
+            }
+            Self::Tsv if value.contains(['\t', '\n', '\r']) => {
+                value.replace(['\t', '\n', '\r'], " ").into()
+            }
+            _ => value.into(),
+        }
+    }
+
+    /// Writes one row of a table, ending with a line break.
+    fn write_row<'a>(
+        self,
+        writer: &mut impl Write,
+        values: impl IntoIterator<Item = Cow<'a, str>>,
+    ) -> std::io::Result<()> {
+        let mut line = String::new();
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                line.push(self.separator());
+            }
+            line.push_str(&self.escape(&value));
+        }
+
+        // RFC 4180 ends lines with CRLF, which spreadsheets expect.
+        match self {
+            Self::Csv => line.push_str("\r\n"),
+            Self::Tsv => line.push('\n'),
+        }
+        writer.write_all(line.as_bytes())
+    }
+}
+
+/// A module and the details of its release which can be exported.
+#[derive(Debug)]
+pub struct ExportRow {
+    pub module: Module,
+    /// The newest release allowed by the user's prerelease settings, if any.
+    pub release: Option<ModuleRelease>,
+    pub authors: Vec<String>,
+    pub licenses: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// Writes a table with a header row followed by a row for each module.
+pub fn write_table<'a>(
+    writer: &mut impl Write,
+    format: TableFormat,
+    columns: &[Column],
+    rows: impl IntoIterator<Item = &'a ExportRow>,
+) -> std::io::Result<()> {
+    format.write_row(writer, columns.iter().map(|c| c.name().into()))?;
+
+    for row in rows {
+        format.write_row(writer, columns.iter().map(|c| c.value(row)))?;
+    }
+
+    Ok(())
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Loads the details of each module matching a filter, ordered by
+    /// identifier. The number of queries doesn't depend on how many modules
+    /// match.
+    pub fn export_rows(&mut self, filter: &ModuleFilter) -> Result<Vec<ExportRow>, QueryError> {
+        let ids = self
+            .filtered_modules(filter)?
+            .iter()
+            .map(|module| module.id)
+            .collect::<Vec<_>>();
+
+        Ok(self
+            .modules_detail(&ids)?
+            .into_iter()
+            .map(|detail| ExportRow {
+                module: detail.module,
+                release: detail.latest,
+                authors: detail.authors,
+                licenses: detail.licenses,
+                tags: detail.tags,
+            })
+            .collect())
+    }
+
+    /// Writes a table of the modules matching a filter, ordered by identifier.
+    pub fn export_modules(
+        &mut self,
+        filter: &ModuleFilter,
+        columns: &[Column],
+        format: TableFormat,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        let rows = self.export_rows(filter)?;
+        write_table(writer, format, columns, &rows)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn quotes_values() {
+        assert_eq!(TableFormat::Csv.escape("plain"), "plain");
+        assert_eq!(
+            TableFormat::Csv.escape("Parts, \"big\" ones"),
+            "\"Parts, \"\"big\"\" ones\""
+        );
+        assert_eq!(TableFormat::Tsv.escape("two\tlines\n"), "two lines ");
+    }
+
+    #[test]
+    fn exports_chosen_columns() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let module = release("Parallax", "2.0.8")
+            .merge(json!({
+                "abstract": "Tessellation, with \"better\" terrain",
+                "author": ["Linx", "Gameslinx"],
+                "license": "CC-BY-NC-ND-4.0",
+                "tags": ["graphics", "config"],
+                "download": "https://example.com/Parallax.zip",
+            }))
+            .build();
+        db.create_release(&module, repo.id, None).unwrap();
+
+        let mut table = Vec::new();
+        db.export_modules(
+            &ModuleFilter::default(),
+            &[Column::Identifier, Column::Summary, Column::Authors, Column::Tags],
+            TableFormat::Csv,
+            &mut table,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "identifier,summary,authors,tags\r\n\
+             Parallax,\"Tessellation, with \"\"better\"\" terrain\",\"Linx, Gameslinx\",\
+             \"graphics, config\"\r\n"
+        );
+    }
+}
@@ -3,19 +3,88 @@
 //! Having a separate API means that Camrete's main code can be idiomatic Rust while still allowing
 //! for an easy-to-use API from C#.
 
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(feature = "sqlcipher")]
+use crate::repo::KeyProvider;
 use crate::{
-    DbConnection, Result,
+    DbConnection,
     database::{
-        self, ModuleId, ReleaseId,
+        self, AvailableUpgrade, CancellationToken, ConsistencyReport, DownloadId, Favorite,
+        FullRelationship, GameCompatibility, InstalledModule, Metric, MetricSummary, MirrorStats,
+        ModuleCursor, ModuleDetail, ModuleFilter, ModuleId, ModuleNote, ModuleStaleness,
+        ModuleSummaryPage, NewDownload, PlanDiff, QueuedDownload, ReleaseCursor, ReleaseHistory,
+        ReleaseId, ReleaseNotes, ReleasePage, ReleaseValidation, RepoCursor, RepoId,
+        RepoOverviewPage, RepoSuggestion, RepoTerms, RetryPolicy, SavedFilter, SearchResult,
+        Snapshot, Staleness, StateChange, TagCount, TagGroup, YankedRelease,
         models::{
-            Module, ModuleRelease, Repository,
-            module::{ModuleRelationship, ModuleRelationshipGroup},
+            Author, Module, ModuleRelease, ReleaseMetadata, RepoFilter, Repository, RepositoryRef,
         },
     },
-    repo,
+    export::{self, Column, TableFormat},
+    install::{
+        self, CkanConfig, CkanRegistry, DetectedDlc, ImportedModule, ImportedRepo,
+        MetapackageOptions, ReleaseFiles,
+    },
+    json::{
+        AuthorCredit, DownloadChecksum, Identifier, ModuleInstallDescriptor, ModuleResources,
+        ReleaseStatus,
+    },
+    l10n,
+    markdown::{self, SanitizedText},
+    parse_url,
+    policy::Policy,
+    profile::ProfileRegistry,
+    repo::{self, game::GameVersion},
+    resolver::{DependencyCycle, Graph, Resolution, Selection},
 };
-use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
-use parking_lot::{Mutex, MutexGuard, RwLock};
+use diesel::{OptionalExtension, QueryDsl, QueryResult, RunQueryDsl};
+use miette::Diagnostic;
+use parking_lot::{Mutex, RwLock};
+use thiserror::Error;
+use time::OffsetDateTime;
+use url::Url;
+
+type Result<T, E = FfiError> = std::result::Result<T, E>;
+
+/// An [`Error`](crate::Error) as it's given to other languages, which can't
+/// tell its kinds apart. Each has the error's message and its diagnostic code,
+/// if it has one.
+#[derive(Debug, Error, uniffi::Error)]
+enum FfiError {
+    /// The user can fix the error or wait it out, like a mistyped URL, a
+    /// missing file or a network outage.
+    #[error("{message}")]
+    User {
+        code: Option<String>,
+        message: String,
+    },
+    /// The error suggests a bug in Camrete, which is worth reporting.
+    #[error("{message}")]
+    Internal {
+        code: Option<String>,
+        message: String,
+    },
+}
+
+impl<E: Into<crate::Error>> From<E> for FfiError {
+    fn from(error: E) -> Self {
+        let error = error.into();
+        let code = error.code().map(|code| code.to_string());
+        let message = error.to_string();
+
+        if error.is_user_error() {
+            Self::User { code, message }
+        } else {
+            Self::Internal { code, message }
+        }
+    }
+}
 
 #[derive(Debug, uniffi::Object)]
 struct RepoManager {
@@ -25,99 +94,1057 @@ struct RepoManager {
 #[uniffi::export]
 impl RepoManager {
     #[uniffi::constructor]
-    fn new(url: String) -> crate::Result<Self> {
+    fn new(url: String) -> Result<Self> {
         Ok(Self {
             mgr: RwLock::new(repo::RepoManager::new(&url)?),
         })
     }
 
-    fn database(&self) -> crate::Result<RepoDB> {
-        Ok(self.mgr.read().db()?.into())
+    #[uniffi::constructor]
+    fn with_retry_policy(url: String, retry_policy: RetryPolicy) -> Result<Self> {
+        let mgr = repo::RepoManager::builder(&url)
+            .retry_policy(retry_policy)
+            .build()?;
+
+        Ok(Self {
+            mgr: RwLock::new(mgr),
+        })
+    }
+
+    /// Opens the database of a profile from the user's profile list.
+    #[uniffi::constructor]
+    fn from_profile(name: String) -> Result<Self> {
+        let profile = ProfileRegistry::load()?.get(&name)?;
+
+        Ok(Self {
+            mgr: RwLock::new(profile.open()?),
+        })
+    }
+
+    fn database(&self) -> Result<RepoDB> {
+        Ok(RepoDB {
+            mgr: self.mgr.read().clone(),
+        })
+    }
+
+    /// The policy which decides which modules can be saved, installed and
+    /// downloaded.
+    fn policy(&self) -> Policy {
+        self.mgr.read().policy().clone()
+    }
+
+    /// Sets which modules can be saved, installed and downloaded. Databases
+    /// opened with [`Self::database`] before this is called keep the old
+    /// policy.
+    fn set_policy(&self, policy: Policy) {
+        self.mgr.write().set_policy(policy);
     }
 }
 
-#[derive(uniffi::Object)]
-struct RepoDB {
-    db: Mutex<database::RepoDB<DbConnection>>,
+#[cfg(feature = "network")]
+#[uniffi::export]
+impl RepoManager {
+    /// Opens a database whose repository updates keep at most
+    /// `memory_budget` bytes of unparsed documents in memory, writing the rest
+    /// to the system's temporary directory.
+    #[uniffi::constructor]
+    fn with_memory_budget(url: String, memory_budget: u64) -> Result<Self> {
+        let mgr = repo::RepoManager::builder(&url)
+            .spool_to_disk(repo::SpoolOptions::new(memory_budget as usize))
+            .build()?;
+
+        Ok(Self {
+            mgr: RwLock::new(mgr),
+        })
+    }
 }
 
-impl From<database::RepoDB<DbConnection>> for RepoDB {
-    fn from(value: database::RepoDB<DbConnection>) -> Self {
-        Self {
-            db: Mutex::new(value),
-        }
+#[cfg(feature = "sqlcipher")]
+#[uniffi::export]
+impl RepoManager {
+    /// Opens a database encrypted with SQLCipher. `key` is asked for the
+    /// passphrase whenever a connection is opened, so it can be kept in the
+    /// platform's keychain.
+    #[uniffi::constructor]
+    fn encrypted(url: String, key: Arc<dyn KeyProvider>) -> Result<Self> {
+        let mgr = repo::RepoManager::builder(&url)
+            .encryption_key(key)
+            .build()?;
+
+        Ok(Self {
+            mgr: RwLock::new(mgr),
+        })
     }
 }
 
+/// Queries the database of a [`RepoManager`].
+///
+/// Each call takes its own connection from the manager's pool and returns it
+/// afterwards, so calls made from several threads at once don't wait for each
+/// other, and an idle `RepoDB` doesn't hold on to a connection.
+#[derive(uniffi::Object)]
+struct RepoDB {
+    mgr: repo::RepoManager,
+}
+
 impl RepoDB {
-    fn db(&self) -> MutexGuard<'_, database::RepoDB<DbConnection>> {
-        self.db.lock()
+    fn db(&self) -> crate::Result<database::RepoDB<DbConnection>> {
+        self.mgr.db()
     }
 }
 
 #[uniffi::export]
 impl RepoDB {
     pub fn all_repos(&self, create_default: bool) -> Result<Vec<Repository>> {
-        Ok(self.db.lock().all_repos(create_default)?)
+        Ok(self.db()?.all_repos(create_default)?)
+    }
+
+    /// A page of repositories ordered by name, each with its module and
+    /// release counts, when it was last updated, and its status, for a
+    /// settings screen. Pass the previous page's `next` cursor to get the page
+    /// after it, or `None` for the first page.
+    pub fn repo_overview_page(
+        &self,
+        after: Option<RepoCursor>,
+        limit: u32,
+    ) -> Result<RepoOverviewPage> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            database::RepoDB::new(conn).repo_overview_page(after.as_ref(), limit)
+        })?)
+    }
+
+    /// Add a repository. If one with the same name and URL already exists,
+    /// it's returned unchanged; one with the same name and another URL is kept,
+    /// and this fails with an error coded `camrete::database::query_failure`.
+    /// Fails with a user error coded `camrete::invalid_url` if the URL can't
+    /// be parsed.
+    pub fn create_repo(&self, name: String, url: String) -> Result<Repository> {
+        let url = parse_url(&url)?;
+        Ok(self
+            .db()?
+            .create_empty_repo(RepositoryRef::new(name, url))?)
+    }
+
+    /// Move a repository to a new URL, forgetting the ETag and suggestions
+    /// recorded for the old one. Its releases are kept until its next update.
+    /// Fails with a user error coded `camrete::invalid_url` if the URL can't be
+    /// parsed.
+    pub fn set_repo_url(&self, repo: RepoId, url: String) -> Result<Repository> {
+        let url = parse_url(&url)?;
+        Ok(self.db()?.update_repo_url(repo, &url)?)
+    }
+
+    /// Switch a repository to another branch or tag of its source, such as
+    /// CKAN-meta's `stable` or `testing`. The URL is made from `template`, in
+    /// which `{channel}` is replaced by the channel, or else from the template
+    /// used last time, or else from the repository's GitHub or GitLab URL.
+    pub fn set_repo_channel(
+        &self,
+        repo: RepoId,
+        channel: String,
+        template: Option<String>,
+    ) -> Result<Repository> {
+        Ok(self
+            .db()?
+            .set_repo_channel(repo, &channel, template.as_deref())?)
+    }
+
+    /// Move a repository to where its URL permanently redirected at its last
+    /// update, which is its `moved_url`. Returns `None` if it hasn't moved.
+    pub fn follow_repo_redirect(&self, repo: RepoId) -> Result<Option<Repository>> {
+        Ok(self.db()?.follow_repo_redirect(repo)?)
+    }
+
+    /// A hash of a repository's releases as of its last update, which is the
+    /// same for any two databases holding the same releases. `None` if the
+    /// repository hasn't been updated yet.
+    pub fn content_hash(&self, repo: RepoId) -> Result<Option<String>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).content_hash(repo))?)
+    }
+
+    /// The license and terms files a repository published at its last
+    /// update, for showing before its modules are installed.
+    pub fn repo_terms(&self, repo: RepoId) -> Result<Vec<RepoTerms>> {
+        Ok(self.db()?.repo_terms(repo)?)
+    }
+
+    /// List the repositories suggested by the ones already added, including
+    /// whether each one has been added too.
+    pub fn repo_suggestions(&self) -> Result<Vec<RepoSuggestion>> {
+        Ok(self.db()?.repo_suggestions()?)
+    }
+
+    /// Add the suggested repository with the given name. Returns `None` if
+    /// there's no such suggestion, or if it has already been added.
+    pub fn add_suggested_repo(&self, name: String) -> Result<Option<Repository>> {
+        Ok(self.db()?.add_suggested_repo(&name)?)
+    }
+
+    /// Save a snapshot of the database to a new file.
+    pub fn backup_to(&self, path: String) -> Result<()> {
+        Ok(self.db()?.backup_to(Path::new(&path))?)
+    }
+
+    /// Replace the database's contents with a backup made by `backup_to`.
+    pub fn restore_from(&self, path: String) -> Result<()> {
+        Ok(self.db()?.restore_from(Path::new(&path))?)
+    }
+
+    pub fn module_by_slug(&self, slug: Identifier) -> Result<Option<Module>> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            Module::all()
+                .filter(Module::with_slug(&slug))
+                .get_result(conn)
+                .optional()
+        })?)
     }
 
-    pub fn module_by_slug(&self, slug: String) -> Result<Option<Module>> {
-        let module = Module::all()
-            .filter(Module::with_slug(&slug))
-            .get_result(self.db().as_mut())
-            .optional()?;
+    /// A page of modules, ordered by identifier, with the details needed to
+    /// show them in a list. Pass the previous page's `next` cursor to get the
+    /// page after it, or `None` for the first page.
+    pub fn module_summaries(
+        &self,
+        after: Option<ModuleCursor>,
+        limit: u32,
+    ) -> Result<ModuleSummaryPage> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            database::RepoDB::new(conn).module_summaries(after.as_ref(), limit)
+        })?)
+    }
+
+    /// Choose the releases needed to install the given modules, with the reason
+    /// each one was chosen. Recommendations are followed too if `recommends`
+    /// is set.
+    pub fn resolve_install(&self, requested: Vec<String>, recommends: bool) -> Result<Resolution> {
+        let graph = Graph::load(&mut self.db()?, None)?;
+        Ok(graph.resolve(&requested, recommends))
+    }
+
+    /// What installing the releases chosen by `resolve_install` and removing
+    /// the modules in `remove` would change, for showing before the user
+    /// confirms it.
+    pub fn plan_diff(&self, resolution: Resolution, remove: Vec<String>) -> Result<PlanDiff> {
+        Ok(self.db()?.plan_diff(&resolution, &remove)?)
+    }
+
+    /// Up to `limit` modules matching what the user typed, best first. Matches
+    /// in a module's identifier or name rank above those in its tags, and
+    /// those above matches in its summary.
+    ///
+    /// Fails with a user error coded `camrete::cancelled` if `cancellation`
+    /// is cancelled before the search finishes, such as because the user
+    /// typed another character.
+    pub fn search_modules(
+        &self,
+        query: String,
+        limit: u32,
+        cancellation: Arc<CancellationToken>,
+    ) -> Result<Vec<SearchResult>> {
+        Ok(self.db()?.search_modules(&query, limit, &cancellation)?)
+    }
+
+    /// The releases which depend on each other in a loop. They can still be
+    /// installed, but the loop is usually a mistake in their metadata.
+    pub fn dependency_cycles(&self) -> Result<Vec<DependencyCycle>> {
+        let graph = Graph::load(&mut self.db()?, None)?;
+        Ok(graph.dependency_cycles())
+    }
+
+    /// Checks the values the database works out from others, like sort keys
+    /// and the search index, and looks for rows left behind by deleted ones.
+    /// If `repair` is set, the problems found are fixed.
+    pub fn verify_derived(&self, repair: bool) -> Result<ConsistencyReport> {
+        Ok(self.db()?.verify_derived(repair)?)
+    }
+
+    /// Checks the metadata of releases which haven't been checked since they
+    /// last changed, and saves what's found. Returns how many were checked.
+    pub fn validate_releases(&self) -> Result<u64> {
+        Ok(self.db()?.validate_releases()?)
+    }
+
+    /// The saved findings of the given releases, for showing badges next to
+    /// them. Releases which haven't been checked are left out.
+    pub fn release_validations(&self, releases: Vec<ReleaseId>) -> Result<Vec<ReleaseValidation>> {
+        Ok(self.db()?.release_validations(&releases)?)
+    }
+
+    /// The newest release of a module for each minor version of the game, for
+    /// showing which versions of the game it works with.
+    pub fn compatibility(&self, module: ModuleId) -> Result<Vec<GameCompatibility>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).compatibility(module))?)
+    }
+
+    /// Whether a module still looks maintained, or `None` if it hasn't been
+    /// worked out since the module's repository was last updated.
+    pub fn module_staleness(&self, module: ModuleId) -> Result<Option<ModuleStaleness>> {
+        Ok(self.db()?.module_staleness(module)?)
+    }
+
+    /// Every module which is at least as stale as `at_least`, for warning
+    /// about abandoned modules.
+    pub fn stale_modules(&self, at_least: Staleness) -> Result<Vec<ModuleStaleness>> {
+        Ok(self.db()?.stale_modules(at_least)?)
+    }
+
+    /// Every tag used by a module, with how many modules use it.
+    pub fn tags(&self) -> Result<Vec<TagCount>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).tags())?)
+    }
+
+    /// Every tag used by a module, grouped by the part before the first `/`.
+    pub fn tag_groups(&self) -> Result<Vec<TagGroup>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).tag_groups())?)
+    }
+
+    /// Authors whose name contains the given text, ignoring case.
+    pub fn search_authors(&self, text: String) -> Result<Vec<Author>> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            Author::all()
+                .filter(Author::name_contains(&text))
+                .order_by(Author::by_name())
+                .load(conn)
+        })?)
+    }
+
+    /// Modules with at least one release credited to the given author.
+    pub fn modules_by_author(&self, author: String) -> Result<Vec<Module>> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            Module::all()
+                .filter(Module::with_author(&author))
+                .order_by(Module::by_slug())
+                .load(conn)
+        })?)
+    }
+
+    /// The newest release of a module which is allowed by the user's
+    /// prerelease settings.
+    pub fn latest_release(&self, module_id: ModuleId) -> Result<Option<ModuleRelease>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).latest_release(module_id))?)
+    }
+
+    /// Pair each installed release with the newest release of its module,
+    /// leaving out modules which are up to date. If `game_version` is given,
+    /// only releases which support it are offered.
+    pub fn available_upgrades(
+        &self,
+        installed: Vec<ReleaseId>,
+        game_version: Option<GameVersion>,
+    ) -> Result<Vec<AvailableUpgrade>> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            database::RepoDB::new(conn).available_upgrades(&installed, game_version)
+        })?)
+    }
+
+    pub fn set_prerelease_default(&self, allow: bool) -> Result<()> {
+        Ok(self.db()?.set_prerelease_default(allow)?)
+    }
+
+    /// Whether release metadata is compressed when repositories are updated.
+    pub fn compresses_metadata(&self) -> Result<bool> {
+        Ok(self.db()?.compresses_metadata()?)
+    }
+
+    /// Change whether release metadata is compressed, starting from the next
+    /// repository update.
+    pub fn set_metadata_compression(&self, compress: bool) -> Result<()> {
+        Ok(self.db()?.set_metadata_compression(compress)?)
+    }
+
+    /// Override whether prereleases are considered for one module. Pass `None`
+    /// to follow the global setting again.
+    pub fn set_module_prerelease(&self, slug: String, allow: Option<bool>) -> Result<()> {
+        Ok(self.db()?.set_module_prerelease(&slug, allow)?)
+    }
+
+    /// The version a module is pinned to, if it's pinned.
+    pub fn module_pin(&self, slug: String) -> Result<Option<String>> {
+        Ok(self.db()?.module_pin(&slug)?)
+    }
+
+    /// Make several changes at once, such as when a settings screen is saved.
+    /// They're made in order, and if any fails, none of them are kept.
+    pub fn apply_changes(&self, changes: Vec<StateChange>) -> Result<()> {
+        Ok(self.db()?.apply_changes(changes)?)
+    }
+
+    /// Yank a release, so that it's never chosen as its module's latest
+    /// release or installed to satisfy a relationship.
+    pub fn yank_release(
+        &self,
+        slug: String,
+        version: String,
+        reason: Option<String>,
+    ) -> Result<()> {
+        Ok(self
+            .db()?
+            .yank_release(&slug, &version, reason.as_deref())?)
+    }
+
+    /// Reverse a yank. Returns whether the release had been yanked.
+    pub fn unyank_release(&self, slug: String, version: String) -> Result<bool> {
+        Ok(self.db()?.unyank_release(&slug, &version)?)
+    }
+
+    pub fn yanked_releases(&self, slug: String) -> Result<Vec<YankedRelease>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).yanked_releases(&slug))?)
+    }
+
+    /// Mark a module as a favorite. Returns whether it wasn't one already.
+    pub fn add_favorite(&self, slug: String) -> Result<bool> {
+        Ok(self.db()?.add_favorite(&slug)?)
+    }
+
+    /// Stop a module being a favorite. Returns whether it was one.
+    pub fn remove_favorite(&self, slug: String) -> Result<bool> {
+        Ok(self.db()?.remove_favorite(&slug)?)
+    }
 
-        Ok(module)
+    pub fn favorites(&self) -> Result<Vec<Favorite>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).favorites())?)
+    }
+
+    /// Replace the note about a module. An empty note, or `None`, removes it.
+    pub fn set_module_note(&self, slug: String, note: Option<String>) -> Result<()> {
+        Ok(self.db()?.set_module_note(&slug, note.as_deref())?)
+    }
+
+    pub fn module_note(&self, slug: String) -> Result<Option<ModuleNote>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).module_note(&slug))?)
+    }
+
+    pub fn module_notes(&self) -> Result<Vec<ModuleNote>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).module_notes())?)
+    }
+
+    /// Every version of a module which a repository has provided, newest
+    /// first, with when it was first and last seen and when it was removed.
+    pub fn module_history(&self, slug: String) -> Result<Vec<ReleaseHistory>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).module_history(&slug))?)
     }
 
     pub fn releases_with_parent(&self, parent_id: ModuleId) -> Result<Vec<ModuleRelease>> {
-        let releases = ModuleRelease::all()
-            .filter(ModuleRelease::with_parent(parent_id))
-            .order_by(ModuleRelease::by_version())
-            .load(self.db().as_mut())?;
+        Ok(self.db()?.read_with_retry(|conn| {
+            ModuleRelease::all()
+                .filter(ModuleRelease::with_parent(parent_id))
+                .order_by(ModuleRelease::by_version())
+                .load(conn)
+        })?)
+    }
 
-        Ok(releases)
+    /// A page of a module's releases, newest first. Pass the previous page's
+    /// `next` cursor to get the page after it, or `None` for the first page.
+    pub fn release_page(
+        &self,
+        module: ModuleId,
+        after: Option<ReleaseCursor>,
+        limit: u32,
+    ) -> Result<ReleasePage> {
+        Ok(self.db()?.read_with_retry(|conn| {
+            database::RepoDB::new(conn).release_page(module, after.as_ref(), limit)
+        })?)
+    }
+
+    /// The details of several modules, loaded together: each module's latest
+    /// release, with its tags, authors, licenses, locales and relationships.
+    /// Modules are returned in the order of `ids`, skipping any which don't
+    /// exist.
+    pub fn modules_detail(&self, ids: Vec<ModuleId>) -> Result<Vec<ModuleDetail>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).modules_detail(&ids))?)
     }
 
     pub fn associated_release_data(&self, release_id: ReleaseId) -> Result<AssociatedReleaseData> {
-        let mut db = self.db();
-
-        let tags = ModuleRelease::tags_for(release_id).load(db.as_mut())?;
-        let authors = ModuleRelease::authors_for(release_id).load(db.as_mut())?;
-        let licenses = ModuleRelease::licenses_for(release_id).load(db.as_mut())?;
-        let locales = ModuleRelease::locales_for(release_id).load(db.as_mut())?;
-
-        Ok(AssociatedReleaseData {
-            tags,
-            authors,
-            licenses,
-            locales,
-        })
+        Ok(self.db()?.read_with_retry(|conn| -> QueryResult<_> {
+            Ok(AssociatedReleaseData {
+                tags: ModuleRelease::tags_for(release_id).load(conn)?,
+                authors: ModuleRelease::authors_for(release_id).load(conn)?,
+                credits: ModuleRelease::credits_for(release_id).load(conn)?,
+                licenses: ModuleRelease::licenses_for(release_id).load(conn)?,
+                locales: ModuleRelease::locales_for(release_id).load(conn)?,
+            })
+        })?)
+    }
+
+    /// Modules which match a filter, ordered by slug.
+    pub fn filtered_modules(&self, filter: ModuleFilter) -> Result<Vec<Module>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).filtered_modules(&filter))?)
+    }
+
+    /// A table of the modules which match a filter, as CSV or TSV text.
+    pub fn export_modules(
+        &self,
+        filter: ModuleFilter,
+        columns: Vec<Column>,
+        format: TableFormat,
+    ) -> Result<String> {
+        let rows = self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).export_rows(&filter))?;
+
+        let mut table = Vec::new();
+        export::write_table(&mut table, format, &columns, &rows)?;
+        Ok(String::from_utf8(table).expect("tables are written as UTF-8"))
+    }
+
+    pub fn saved_filters(&self) -> Result<Vec<SavedFilter>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).saved_filters())?)
+    }
+
+    /// Save a filter, replacing any other filter with the same name.
+    pub fn save_filter(&self, name: String, filter: ModuleFilter) -> Result<()> {
+        Ok(self.db()?.save_filter(&name, &filter)?)
+    }
+
+    /// Delete a saved filter, returning whether it existed.
+    pub fn delete_saved_filter(&self, name: String) -> Result<bool> {
+        Ok(self.db()?.delete_saved_filter(&name)?)
+    }
+
+    /// Every download in the queue, in the order they were queued.
+    pub fn downloads(&self) -> Result<Vec<QueuedDownload>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).downloads())?)
+    }
+
+    /// Add a file to the download queue, or return the download which is
+    /// already saving to the same destination.
+    pub fn queue_download(&self, download: NewDownload) -> Result<QueuedDownload> {
+        Ok(self.db()?.queue_download(&download)?)
+    }
+
+    /// Put a failed download back in the queue, returning whether it had
+    /// failed.
+    pub fn retry_download(&self, id: DownloadId) -> Result<bool> {
+        Ok(self.db()?.retry_download(id)?)
+    }
+
+    pub fn remove_download(&self, id: DownloadId) -> Result<bool> {
+        Ok(self.db()?.remove_download(id)?)
+    }
+
+    pub fn clear_finished_downloads(&self) -> Result<u64> {
+        Ok(self.db()?.clear_finished_downloads()? as u64)
+    }
+
+    /// The download history of each of the given URLs which has been used,
+    /// for showing which of a release's mirrors work.
+    pub fn mirror_stats(&self, urls: Vec<Url>) -> Result<Vec<MirrorStats>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).mirror_stats(&urls))?)
+    }
+
+    pub fn telemetry_enabled(&self) -> Result<bool> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).telemetry_enabled())?)
+    }
+
+    /// Turn the recording of usage metrics on or off. Metrics are never sent
+    /// anywhere.
+    pub fn set_telemetry(&self, enabled: bool) -> Result<()> {
+        Ok(self.db()?.set_telemetry(enabled)?)
+    }
+
+    /// Whether release notes are fetched from GitHub for modules hosted there.
+    pub fn fetches_release_notes(&self) -> Result<bool> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).fetches_release_notes())?)
+    }
+
+    /// Turn the fetching of release notes from GitHub on or off. Notes which
+    /// were already fetched are kept.
+    pub fn set_release_notes(&self, enabled: bool) -> Result<()> {
+        Ok(self.db()?.set_release_notes(enabled)?)
+    }
+
+    /// The notes GitHub has for a release, if they've been fetched.
+    pub fn release_notes(&self, release: ModuleRelease) -> Result<Option<ReleaseNotes>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).release_notes(&release))?)
+    }
+
+    /// Count an event which happened outside of Camrete, such as an install.
+    /// Nothing is recorded unless telemetry is turned on.
+    pub fn record_metric(&self, metric: Metric, duration: Option<Duration>) -> Result<()> {
+        Ok(self.db()?.record_metric(metric, duration)?)
+    }
+
+    pub fn metrics(&self) -> Result<Vec<MetricSummary>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).metrics())?)
+    }
+
+    pub fn reset_metrics(&self) -> Result<()> {
+        Ok(self.db()?.reset_metrics()?)
     }
 
     pub fn relationships_for_release(
         &self,
         release_id: ReleaseId,
     ) -> Result<Vec<FullRelationship>> {
-        let mut db = self.db();
+        Ok(self.db()?.read_with_retry(|conn| {
+            ModuleRelease::relationships_for(release_id).load::<FullRelationship>(conn)
+        })?)
+    }
+
+    pub fn installed_modules(&self) -> Result<Vec<InstalledModule>> {
+        Ok(self
+            .db()?
+            .read_with_retry(|conn| database::RepoDB::new(conn).installed_modules())?)
+    }
+
+    /// Import the modules installed by the original CKAN client, from either a
+    /// game directory or its `CKAN/registry.json`.
+    pub fn import_ckan_registry(&self, path: String) -> Result<Vec<ImportedModule>> {
+        let registry = CkanRegistry::load(Path::new(&path))?;
+        Ok(self.db()?.import_ckan_registry(registry)?)
+    }
 
-        let relationships =
-            ModuleRelease::relationships_for(release_id).load::<FullRelationship>(db.as_mut())?;
+    /// Add the repositories used by the original CKAN client, keeping their
+    /// priorities. They're read from a game directory or its
+    /// `CKAN/registry.json` if `path` is given, or else from every game
+    /// directory in CKAN's settings. Repositories already in use aren't
+    /// replaced.
+    pub fn import_ckan_repos(&self, path: Option<String>) -> Result<Vec<ImportedRepo>> {
+        let repos = match path {
+            Some(path) => CkanRegistry::load(Path::new(&path))?.repositories(),
+            None => CkanConfig::load_default()?.repositories(),
+        };
+        Ok(self.db()?.import_ckan_repos(repos)?)
+    }
+
+    /// Install a module from a zip archive on disk into a game directory, and
+    /// record it as installed from that archive. The module's metadata is read
+    /// from `metadata_path` if given, or else from the `.ckan` file inside the
+    /// archive. It's recorded as `identifier` if given.
+    pub fn install_local_archive(
+        &self,
+        archive_path: String,
+        game_dir: String,
+        metadata_path: Option<String>,
+        identifier: Option<Identifier>,
+    ) -> Result<InstalledModule> {
+        let metadata = metadata_path
+            .map(|path| install::read_metadata(Path::new(&path)))
+            .transpose()?;
+        Ok(self.db()?.install_local_archive(
+            Path::new(&archive_path),
+            Path::new(&game_dir),
+            metadata,
+            identifier.as_ref(),
+        )?)
+    }
+
+    /// A metapackage which installs the installed modules, as the JSON text of
+    /// an `installed-*.ckan` file.
+    pub fn export_installed(&self, options: MetapackageOptions) -> Result<String> {
+        let mut metapackage = Vec::new();
+        self.db()?.export_installed(&options, &mut metapackage)?;
+        Ok(String::from_utf8(metapackage).expect("JSON is written as UTF-8"))
+    }
 
-        Ok(relationships)
+    /// A view of the database which doesn't change while it's held, for
+    /// reads made of many queries which shouldn't see an update half done.
+    pub fn snapshot(&self) -> Result<Arc<ReadSnapshot>> {
+        Ok(Arc::new(ReadSnapshot {
+            db: Mutex::new(self.mgr.snapshot()?),
+        }))
     }
 }
 
+/// Reads the database as it was when the snapshot was taken. Repositories can
+/// be updated meanwhile, but their changes aren't seen through it.
+///
+/// Unlike a [`RepoDB`], a snapshot keeps its connection until it's dropped,
+/// and calls on it from several threads wait for each other.
+#[derive(uniffi::Object)]
+struct ReadSnapshot {
+    db: Mutex<database::RepoDB<Snapshot<DbConnection>>>,
+}
+
+#[uniffi::export]
+impl ReadSnapshot {
+    pub fn all_repos(&self) -> Result<Vec<Repository>> {
+        Ok(self.db.lock().all_repos(false)?)
+    }
+
+    /// Modules which match a filter, ordered by slug.
+    pub fn filtered_modules(&self, filter: ModuleFilter) -> Result<Vec<Module>> {
+        Ok(self.db.lock().filtered_modules(&filter)?)
+    }
+
+    /// A table of the modules which match a filter, as CSV or TSV text.
+    pub fn export_modules(
+        &self,
+        filter: ModuleFilter,
+        columns: Vec<Column>,
+        format: TableFormat,
+    ) -> Result<String> {
+        let rows = self.db.lock().export_rows(&filter)?;
+
+        let mut table = Vec::new();
+        export::write_table(&mut table, format, &columns, &rows)?;
+        Ok(String::from_utf8(table).expect("tables are written as UTF-8"))
+    }
+
+    /// A metapackage which installs the installed modules, as the JSON text of
+    /// an `installed-*.ckan` file.
+    pub fn export_installed(&self, options: MetapackageOptions) -> Result<String> {
+        let mut metapackage = Vec::new();
+        self.db
+            .lock()
+            .export_installed(&options, &mut metapackage)?;
+        Ok(String::from_utf8(metapackage).expect("JSON is written as UTF-8"))
+    }
+
+    /// Choose the releases needed to install the given modules, with the reason
+    /// each one was chosen. Recommendations are followed too if `recommends`
+    /// is set.
+    pub fn resolve_install(&self, requested: Vec<String>, recommends: bool) -> Result<Resolution> {
+        let graph = Graph::load(&mut self.db.lock(), None)?;
+        Ok(graph.resolve(&requested, recommends))
+    }
+
+    /// The releases which depend on each other in a loop.
+    pub fn dependency_cycles(&self) -> Result<Vec<DependencyCycle>> {
+        let graph = Graph::load(&mut self.db.lock(), None)?;
+        Ok(graph.dependency_cycles())
+    }
+}
+
+/// List the DLC installed in a game directory. DLC can't be downloaded, so
+/// this is the only way to know whether it's installed.
+#[uniffi::export]
+fn detect_dlc(game_dir: String) -> Result<Vec<DetectedDlc>> {
+    Ok(install::detect_dlc(Path::new(&game_dir))?)
+}
+
+/// Where a release of the module `identifier` is downloaded from, and its
+/// install directives, for a details tab. A release without any directives
+/// gets the one used in their place.
+#[uniffi::export]
+fn release_files(identifier: String, release: ModuleRelease) -> ReleaseFiles {
+    ReleaseFiles::new(&identifier, &release)
+}
+
+/// Write an encrypted copy of a plain database to a new file. The original
+/// isn't changed.
+#[cfg(feature = "sqlcipher")]
+#[uniffi::export]
+fn encrypt_database(plain: String, encrypted: String, key: Arc<dyn KeyProvider>) -> Result<()> {
+    Ok(repo::encrypt_database(
+        Path::new(&plain),
+        Path::new(&encrypted),
+        &*key,
+    )?)
+}
+
+/// Write a plain copy of an encrypted database to a new file. The original
+/// isn't changed.
+#[cfg(feature = "sqlcipher")]
+#[uniffi::export]
+fn decrypt_database(encrypted: String, plain: String, key: Arc<dyn KeyProvider>) -> Result<()> {
+    Ok(repo::decrypt_database(
+        Path::new(&encrypted),
+        Path::new(&plain),
+        &*key,
+    )?)
+}
+
+/// Read a filter written like `tag:parts -tag:graphics author:Linx`.
+#[uniffi::export]
+fn parse_module_filter(text: String) -> Result<ModuleFilter> {
+    Ok(text.parse()?)
+}
+
+/// Write a filter in the form read by `parse_module_filter`.
+#[uniffi::export]
+fn module_filter_to_string(filter: ModuleFilter) -> String {
+    filter.to_string()
+}
+
+/// Load the policy kept in the user's config directory, or in the file at
+/// `path` if it's given. A missing file allows everything.
+#[uniffi::export]
+fn load_policy(path: Option<String>) -> Result<Policy> {
+    let path = path.map_or_else(Policy::config_dir_path, PathBuf::from);
+    Ok(Policy::load_from(path)?)
+}
+
+/// Explain why a module is part of a resolution: its selection, then the
+/// selection whose relationship it satisfies and so on, ending with a
+/// requested module. Empty if the module wasn't chosen.
+#[uniffi::export]
+fn explain_selection(resolution: Resolution, identifier: String) -> Vec<Selection> {
+    resolution
+        .explain(&identifier)
+        .into_iter()
+        .cloned()
+        .collect()
+}
+
+/// The locales which have a message catalog.
+#[uniffi::export]
+fn message_locales() -> Vec<String> {
+    l10n::locales().map(String::from).collect()
+}
+
+/// Pick the locale whose messages best suit the user's language, like `en-GB`.
+#[uniffi::export]
+fn negotiate_locale(requested: String) -> String {
+    l10n::negotiate(&requested).into()
+}
+
+/// The message catalog for a locale, in Fluent's format. These are the same
+/// messages the command-line app shows.
+#[uniffi::export]
+fn message_catalog(locale: String) -> Option<String> {
+    l10n::catalog(&locale).map(String::from)
+}
+
+/// Clean up a module's description, giving markdown without HTML or unsafe
+/// links, and the same text without markdown. The command-line app shows
+/// descriptions the same way.
+#[uniffi::export]
+fn sanitize_markdown(text: String) -> SanitizedText {
+    markdown::sanitize(&text)
+}
+
+/// Put plain text, like the `plain_text` of a sanitized description, on one
+/// line and cut it to at most `max_chars` characters, ending with `…` if it's
+/// cut short. Sanitized text already has a preview of the default length.
+#[uniffi::export]
+fn preview_text(plain_text: String, max_chars: u32) -> String {
+    markdown::preview(&plain_text, max_chars as usize)
+}
+
 #[derive(uniffi::Record)]
 struct AssociatedReleaseData {
     tags: Vec<String>,
     authors: Vec<String>,
+    /// The same authors as `authors`, with the roles they were credited with.
+    credits: Vec<AuthorCredit>,
     licenses: Vec<String>,
     locales: Vec<String>,
 }
 
-#[derive(Debug, diesel::Queryable, uniffi::Record)]
-struct FullRelationship {
-    group: ModuleRelationshipGroup,
-    description: ModuleRelationship,
+// The database models are exported through these records, so that changes to
+// the database don't change the API. Each field is mapped explicitly.
+
+uniffi::custom_type!(Repository, RepositoryFFI);
+uniffi::custom_type!(Module, ModuleFFI);
+uniffi::custom_type!(ModuleRelease, ModuleReleaseFFI);
+
+#[derive(uniffi::Record)]
+pub struct RepositoryFFI {
+    pub id: RepoId,
+    pub name: String,
+    pub url: Url,
+    pub priority: i32,
+    pub filter: Option<RepoFilter>,
+    pub x_mirror: bool,
+    pub x_comment: Option<String>,
+    pub content_hash: Option<String>,
+    pub channel: Option<String>,
+    pub url_template: Option<String>,
+    pub final_url: Option<Url>,
+    pub moved_url: Option<Url>,
+}
+
+impl From<Repository> for RepositoryFFI {
+    fn from(value: Repository) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            url: value.url,
+            priority: value.priority,
+            filter: value.filter,
+            x_mirror: value.x_mirror,
+            x_comment: value.x_comment,
+            content_hash: value.content_hash,
+            channel: value.channel,
+            url_template: value.url_template,
+            final_url: value.final_url,
+            moved_url: value.moved_url,
+        }
+    }
+}
+
+impl From<RepositoryFFI> for Repository {
+    fn from(value: RepositoryFFI) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            url: value.url,
+            priority: value.priority,
+            filter: value.filter,
+            x_mirror: value.x_mirror,
+            x_comment: value.x_comment,
+            content_hash: value.content_hash,
+            channel: value.channel,
+            url_template: value.url_template,
+            final_url: value.final_url,
+            moved_url: value.moved_url,
+        }
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct ModuleFFI {
+    pub id: ModuleId,
+    pub repo_id: RepoId,
+    pub slug: String,
+    pub download_count: i32,
+}
+
+impl From<Module> for ModuleFFI {
+    fn from(value: Module) -> Self {
+        Self {
+            id: value.id,
+            repo_id: value.repo_id,
+            slug: value.slug,
+            download_count: value.download_count,
+        }
+    }
+}
+
+impl From<ModuleFFI> for Module {
+    fn from(value: ModuleFFI) -> Self {
+        Self {
+            id: value.id,
+            repo_id: value.repo_id,
+            slug: value.slug,
+            download_count: value.download_count,
+        }
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct ModuleReleaseFFI {
+    pub id: ReleaseId,
+    pub module_id: ModuleId,
+    pub version: String,
+    pub display_name: String,
+    pub summary: String,
+    pub metadata: ReleaseMetadataFFI,
+    pub description: Option<String>,
+    pub release_status: ReleaseStatus,
+    pub game_version: GameVersion,
+    pub game_version_min: GameVersion,
+    pub game_version_max: Option<GameVersion>,
+    pub game_version_strict: bool,
+    pub download_size: Option<i64>,
+    pub install_size: Option<i64>,
+    pub release_date: Option<OffsetDateTime>,
+}
+
+impl From<ModuleRelease> for ModuleReleaseFFI {
+    fn from(value: ModuleRelease) -> Self {
+        Self {
+            id: value.id,
+            module_id: value.module_id,
+            version: value.version,
+            display_name: value.display_name,
+            summary: value.summary,
+            metadata: value.metadata.into(),
+            description: value.description,
+            release_status: value.release_status,
+            game_version: value.game_version,
+            game_version_min: value.game_version_min,
+            game_version_max: value.game_version_max,
+            game_version_strict: value.game_version_strict,
+            download_size: value.download_size,
+            install_size: value.install_size,
+            release_date: value.release_date,
+        }
+    }
+}
+
+impl From<ModuleReleaseFFI> for ModuleRelease {
+    fn from(value: ModuleReleaseFFI) -> Self {
+        Self {
+            id: value.id,
+            module_id: value.module_id,
+            version: value.version,
+            display_name: value.display_name,
+            summary: value.summary,
+            metadata: value.metadata.into(),
+            description: value.description,
+            release_status: value.release_status,
+            game_version: value.game_version,
+            game_version_min: value.game_version_min,
+            game_version_max: value.game_version_max,
+            game_version_strict: value.game_version_strict,
+            download_size: value.download_size,
+            install_size: value.install_size,
+            release_date: value.release_date,
+        }
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct ReleaseMetadataFFI {
+    pub comment: Option<String>,
+    pub download: Vec<Url>,
+    pub download_hash: DownloadChecksum,
+    pub download_content_type: Option<String>,
+    pub resources: ModuleResources,
+    pub install: Vec<ModuleInstallDescriptor>,
+}
+
+impl From<ReleaseMetadata<'static>> for ReleaseMetadataFFI {
+    fn from(value: ReleaseMetadata) -> Self {
+        Self {
+            comment: value.comment.map(Cow::into_owned),
+            download: value.download.into_owned(),
+            download_hash: value.download_hash.into_owned(),
+            download_content_type: value.download_content_type.map(Cow::into_owned),
+            resources: value.resources.into_owned(),
+            install: value.install.into_owned(),
+        }
+    }
+}
+
+impl From<ReleaseMetadataFFI> for ReleaseMetadata<'static> {
+    fn from(value: ReleaseMetadataFFI) -> Self {
+        Self {
+            comment: value.comment.map(Cow::Owned),
+            download: Cow::Owned(value.download),
+            download_hash: Cow::Owned(value.download_hash),
+            download_content_type: value.download_content_type.map(Cow::Owned),
+            resources: Cow::Owned(value.resources),
+            install: Cow::Owned(value.install),
+        }
+    }
 }
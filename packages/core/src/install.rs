@@ -0,0 +1,489 @@
+//! Plans for installing modules into a game directory.
+//!
+//! A [`PlannedInstall`] maps each file in a release's download to the location
+//! it will be copied to, following the release's install directives. Several
+//! of these are combined into an [`InstallPlan`], which can be checked for
+//! files that more than one module would write before anything is installed.
+//!
+//! The files in a download can be listed with an [`ArchiveListing`], which
+//! only needs the end of the archive. DLC isn't downloaded at all, and is
+//! instead found in the game directory by [`detect_dlc`].
+//!
+//! A module can also be installed from a [`LocalArchive`] on disk, such as an
+//! unpublished build.
+//!
+//! Modules which were installed by the original CKAN client can be imported
+//! from its [`CkanRegistry`], and exported back as a metapackage by
+//! [`installed_metapackage`]. The repositories it uses can be imported too,
+//! from one registry or from every game directory in its [`CkanConfig`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+use miette::Diagnostic;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::json::{ModuleInstallDescriptor, ModuleInstallSourceDirective};
+
+mod dlc;
+mod files;
+#[cfg(feature = "network")]
+mod listing;
+mod local;
+mod metapackage;
+mod registry;
+
+pub use dlc::{DetectedDlc, detect_dlc};
+pub use files::{InstallDirective, InstallSourceKind, ReleaseFiles};
+#[cfg(feature = "network")]
+pub(crate) use listing::ArchiveSource;
+#[cfg(feature = "network")]
+pub use listing::{ArchiveListing, ListingError};
+pub use local::{LocalArchive, LocalInstallError, read_metadata};
+pub use metapackage::{ExportRelationship, MetapackageOptions, VersionPin, installed_metapackage};
+pub use registry::{
+    CkanConfig, CkanInstance, CkanRegistry, ImportedModule, ImportedRepo, RegistryError, RepoImport,
+};
+
+/// Files which are never installed, even if an install directive matches them.
+const IGNORED_NAMES: &[&str] = &["__MACOSX", ".DS_Store", "Thumbs.db"];
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum InstallPlanError {
+    #[error("no files in the download for {module} {version} match `{directive}`")]
+    #[diagnostic(
+        code(camrete::install::no_matches),
+        severity(Warning),
+        help("the module's metadata may be out of date with its download")
+    )]
+    NoMatches {
+        module: String,
+        version: String,
+        directive: String,
+    },
+    #[error("invalid regular expression in the install directives for {module} {version}")]
+    #[diagnostic(code(camrete::install::invalid_regex), severity(Warning))]
+    InvalidRegex {
+        module: String,
+        version: String,
+        source: regex::Error,
+    },
+    #[error("some files would be installed by more than one module:\n{}", List(conflicts))]
+    #[diagnostic(
+        code(camrete::install::file_conflict),
+        severity(Warning),
+        help("only one of the conflicting modules can be installed at a time")
+    )]
+    FileConflicts { conflicts: Vec<FileConflict> },
+}
+
+/// A file which would be copied from a module's download into the game
+/// directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedFile {
+    /// The file's path within the module's download.
+    pub source: String,
+    /// Where the file will be installed, relative to the game directory.
+    pub destination: PathBuf,
+}
+
+/// The files that installing one release would write.
+#[derive(Debug, Clone)]
+pub struct PlannedInstall {
+    pub module: String,
+    pub version: String,
+    pub files: Vec<PlannedFile>,
+}
+
+impl PlannedInstall {
+    /// Maps the files in a release's download to their installed locations.
+    ///
+    /// `archive_files` lists the paths of every file in the download, using
+    /// `/` as the separator. Releases without any install directives are
+    /// installed by finding a folder named after the module and placing it in
+    /// `GameData`.
+    pub fn new(
+        module: &str,
+        version: &str,
+        directives: &[ModuleInstallDescriptor],
+        archive_files: &[&str],
+    ) -> Result<Self, InstallPlanError> {
+        let default_directive;
+        let directives = if directives.is_empty() {
+            default_directive = [ModuleInstallDescriptor::find_in_game_data(module)];
+            &default_directive[..]
+        } else {
+            directives
+        };
+
+        let mut files = Vec::new();
+
+        for directive in directives {
+            let planner = DirectivePlanner::new(directive).map_err(|source| {
+                InstallPlanError::InvalidRegex {
+                    module: module.to_string(),
+                    version: version.to_string(),
+                    source,
+                }
+            })?;
+
+            let Some(root) = planner.find_root(archive_files) else {
+                return Err(InstallPlanError::NoMatches {
+                    module: module.to_string(),
+                    version: version.to_string(),
+                    directive: directive.source.to_string(),
+                });
+            };
+
+            files.extend(planner.plan(root, archive_files));
+        }
+
+        Ok(Self {
+            module: module.to_string(),
+            version: version.to_string(),
+            files,
+        })
+    }
+}
+
+/// A set of releases which are to be installed together.
+#[derive(Debug, Clone, Default)]
+pub struct InstallPlan {
+    pub installs: Vec<PlannedInstall>,
+}
+
+impl InstallPlan {
+    pub fn push(&mut self, install: PlannedInstall) {
+        self.installs.push(install);
+    }
+
+    /// Finds every destination which more than one module would write to.
+    ///
+    /// Paths are compared case-insensitively, because that's how they're
+    /// treated by the file systems most players use.
+    pub fn conflicts(&self) -> Vec<FileConflict> {
+        let mut writers = BTreeMap::<String, FileConflict>::new();
+
+        for install in &self.installs {
+            for file in &install.files {
+                let key = file.destination.to_string_lossy().to_lowercase();
+                let entry = writers.entry(key).or_insert_with(|| FileConflict {
+                    destination: file.destination.clone(),
+                    modules: vec![],
+                });
+
+                if !entry.modules.contains(&install.module) {
+                    entry.modules.push(install.module.clone());
+                }
+            }
+        }
+
+        writers
+            .into_values()
+            .filter(|conflict| conflict.modules.len() > 1)
+            .collect()
+    }
+
+    /// Returns an error listing the conflicting files if more than one module
+    /// would write to the same destination.
+    pub fn check_conflicts(&self) -> Result<(), InstallPlanError> {
+        let conflicts = self.conflicts();
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(InstallPlanError::FileConflicts { conflicts })
+        }
+    }
+}
+
+/// A destination which would be written to by more than one module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    pub destination: PathBuf,
+    /// The modules which would write to the destination, in the order they
+    /// were added to the plan.
+    pub modules: Vec<String>,
+}
+
+impl Display for FileConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (from {})",
+            self.destination.display(),
+            self.modules.join(", ")
+        )
+    }
+}
+
+struct List<'a, T>(&'a [T]);
+
+impl<T: Display> Display for List<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in self.0 {
+            writeln!(f, "  {item}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ModuleInstallDescriptor {
+    /// The directive used for releases which don't specify any.
+    pub(crate) fn find_in_game_data(module: &str) -> Self {
+        Self {
+            source: ModuleInstallSourceDirective::Find(module.to_string()),
+            install_to: "GameData".to_string(),
+            find_matches_files: false,
+            r#as: None,
+            filter: vec![],
+            filter_regexp: vec![],
+            include_only: vec![],
+            include_only_regexp: vec![],
+        }
+    }
+}
+
+impl Display for ModuleInstallSourceDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file: {path}"),
+            Self::Find(name) => write!(f, "find: {name}"),
+            Self::FindRegexp(pattern) => write!(f, "find_regexp: {pattern}"),
+        }
+    }
+}
+
+/// An install directive with its regular expressions compiled.
+struct DirectivePlanner<'a> {
+    directive: &'a ModuleInstallDescriptor,
+    find_regexp: Option<Regex>,
+    filter_regexp: Vec<Regex>,
+    include_only_regexp: Vec<Regex>,
+}
+
+impl<'a> DirectivePlanner<'a> {
+    fn new(directive: &'a ModuleInstallDescriptor) -> Result<Self, regex::Error> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            directive,
+            find_regexp: match &directive.source {
+                ModuleInstallSourceDirective::FindRegexp(pattern) => Some(Regex::new(pattern)?),
+                _ => None,
+            },
+            filter_regexp: compile(&directive.filter_regexp)?,
+            include_only_regexp: compile(&directive.include_only_regexp)?,
+        })
+    }
+
+    /// Finds the file or folder in the download which the directive installs.
+    fn find_root(&self, archive_files: &[&'a str]) -> Option<&'a str> {
+        let name = match &self.directive.source {
+            ModuleInstallSourceDirective::File(path) => {
+                let path = path.trim_end_matches('/');
+
+                // Borrow the matching prefix from the archive listing.
+                return archive_files
+                    .iter()
+                    .find(|file| is_within(file, path))
+                    .map(|file| &file[..path.len()]);
+            }
+            ModuleInstallSourceDirective::Find(name) => Some(name.as_str()),
+            ModuleInstallSourceDirective::FindRegexp(_) => None,
+        };
+
+        // Every folder and (if allowed) file in the download is a candidate. Like
+        // CKAN, the match with the shortest path is used, so that nested copies of
+        // a folder are ignored.
+        let mut candidates = HashMap::<&str, bool>::new();
+        for file in archive_files {
+            for (i, _) in file.match_indices('/') {
+                candidates.entry(&file[..i]).or_insert(true);
+            }
+            if self.directive.find_matches_files {
+                candidates.entry(file).or_insert(false);
+            }
+        }
+
+        candidates
+            .into_keys()
+            .filter(|path| match (name, &self.find_regexp) {
+                (Some(name), _) => file_name(path) == name,
+                (None, Some(regex)) => regex.is_match(path),
+                (None, None) => false,
+            })
+            .min_by_key(|path| (path.len(), *path))
+    }
+
+    /// Maps the files inside `root` to their installed locations.
+    fn plan(&self, root: &str, archive_files: &[&str]) -> Vec<PlannedFile> {
+        // Files keep their path relative to the folder containing the root, so
+        // the root's own name is included in the destination.
+        let parent_len = root.rfind('/').map_or(0, |i| i + 1);
+        let install_to = match self.directive.install_to.as_str() {
+            "GameRoot" => "",
+            other => other,
+        };
+
+        archive_files
+            .iter()
+            .filter(|file| is_within(file, root))
+            .filter_map(|file| {
+                let relative = &file[parent_len..];
+                let relative = match (&self.directive.r#as, relative.split_once('/')) {
+                    (Some(new_name), Some((_, rest))) => format!("{new_name}/{rest}"),
+                    (Some(new_name), None) => new_name.clone(),
+                    (None, _) => relative.to_string(),
+                };
+
+                self.includes(file, &relative).then(|| PlannedFile {
+                    source: file.to_string(),
+                    destination: PathBuf::from(install_to).join(relative),
+                })
+            })
+            .collect()
+    }
+
+    /// Applies the directive's filters to a file.
+    fn includes(&self, source: &str, relative: &str) -> bool {
+        let has_component = |names: &[String]| {
+            relative
+                .split('/')
+                .any(|part| names.iter().any(|name| name.eq_ignore_ascii_case(part)))
+        };
+
+        if relative.split('/').any(|part| IGNORED_NAMES.contains(&part)) {
+            return false;
+        }
+
+        if has_component(&self.directive.filter)
+            || self.filter_regexp.iter().any(|re| re.is_match(source))
+        {
+            return false;
+        }
+
+        let has_include_only =
+            !self.directive.include_only.is_empty() || !self.include_only_regexp.is_empty();
+
+        !has_include_only
+            || has_component(&self.directive.include_only)
+            || self.include_only_regexp.iter().any(|re| re.is_match(source))
+    }
+}
+
+/// Returns whether `file` is `path` itself or is inside the folder `path`.
+fn is_within(file: &str, path: &str) -> bool {
+    file.strip_prefix(path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{from_value, json};
+
+    use super::*;
+
+    fn directives(value: serde_json::Value) -> Vec<ModuleInstallDescriptor> {
+        from_value(value).unwrap()
+    }
+
+    fn destinations(install: &PlannedInstall) -> Vec<String> {
+        install
+            .files
+            .iter()
+            .map(|f| f.destination.to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    const PARALLAX: &[&str] = &[
+        "Parallax-1.0/GameData/Parallax/Parallax.dll",
+        "Parallax-1.0/GameData/Parallax/Shaders/terrain.shader",
+        "Parallax-1.0/GameData/Parallax/Shaders/.DS_Store",
+        "Parallax-1.0/GameData/Parallax_StockTextures/Kerbin.dds",
+        "Parallax-1.0/Extras/Docs/Parallax/readme.txt",
+        "Parallax-1.0/README.md",
+    ];
+
+    #[test]
+    fn default_directive_finds_shortest_path() {
+        let install = PlannedInstall::new("Parallax", "1.0", &[], PARALLAX).unwrap();
+
+        assert_eq!(
+            destinations(&install),
+            [
+                "GameData/Parallax/Parallax.dll",
+                "GameData/Parallax/Shaders/terrain.shader",
+            ]
+        );
+    }
+
+    #[test]
+    fn file_directive_with_rename_and_filter() {
+        let directives = directives(json!([{
+            "file": "Parallax-1.0/GameData/Parallax",
+            "install_to": "GameData",
+            "as": "ParallaxContinued",
+            "filter": "shaders",
+        }]));
+
+        let install = PlannedInstall::new("Parallax", "1.0", &directives, PARALLAX).unwrap();
+
+        assert_eq!(
+            destinations(&install),
+            ["GameData/ParallaxContinued/Parallax.dll"]
+        );
+        assert_eq!(install.files[0].source, PARALLAX[0]);
+    }
+
+    #[test]
+    fn find_regexp_and_game_root() {
+        let directives = directives(json!([{
+            "find_regexp": "README\\.md$",
+            "find_matches_files": true,
+            "install_to": "GameRoot",
+        }]));
+
+        let install = PlannedInstall::new("Parallax", "1.0", &directives, PARALLAX).unwrap();
+
+        assert_eq!(destinations(&install), ["README.md"]);
+    }
+
+    #[test]
+    fn missing_match_is_an_error() {
+        let directives = directives(json!([{ "find": "Scatterer", "install_to": "GameData" }]));
+        let result = PlannedInstall::new("Parallax", "1.0", &directives, PARALLAX);
+
+        assert!(matches!(result, Err(InstallPlanError::NoMatches { .. })));
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let mut plan = InstallPlan::default();
+        plan.push(PlannedInstall::new("Parallax", "1.0", &[], PARALLAX).unwrap());
+
+        let fork = ["GameData/parallax/parallax.dll", "GameData/ParallaxFork/Fork.dll"];
+        let directives = directives(json!([{ "file": "GameData", "install_to": "GameRoot" }]));
+        plan.push(PlannedInstall::new("ParallaxFork", "1.0", &directives, &fork).unwrap());
+
+        let conflicts = plan.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].modules, ["Parallax", "ParallaxFork"]);
+
+        let err = plan.check_conflicts().unwrap_err();
+        assert!(err.to_string().contains("Parallax.dll (from Parallax, ParallaxFork)"));
+    }
+}
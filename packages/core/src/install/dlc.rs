@@ -0,0 +1,124 @@
+//! Detecting which DLC is installed in a game directory.
+//!
+//! DLC can't be downloaded, so its releases in a repository only describe it.
+//! Whether it's installed is worked out from the folder it adds to `GameData`,
+//! and its version from the readme in that folder.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, instrument};
+
+/// A DLC the game can have, and the folder it adds.
+struct KnownDlc {
+    identifier: &'static str,
+    directory: &'static str,
+}
+
+/// Every DLC which can be detected, by the identifier CKAN-meta uses for it.
+const KNOWN_DLC: &[KnownDlc] = &[
+    KnownDlc {
+        identifier: "MakingHistory-DLC",
+        directory: "MakingHistory",
+    },
+    KnownDlc {
+        identifier: "BreakingGround-DLC",
+        directory: "Serenity",
+    },
+];
+
+/// A DLC found in a game directory.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct DetectedDlc {
+    /// The identifier of the DLC's module, like `MakingHistory-DLC`.
+    pub identifier: String,
+    /// The version named in the DLC's readme, or `None` if the readme is
+    /// missing or doesn't name one.
+    pub version: Option<String>,
+}
+
+impl KnownDlc {
+    fn path(&self, game_dir: &Path) -> PathBuf {
+        game_dir
+            .join("GameData")
+            .join("SquadExpansion")
+            .join(self.directory)
+    }
+}
+
+/// Lists the DLC installed in the given game directory.
+#[instrument]
+pub fn detect_dlc(game_dir: &Path) -> io::Result<Vec<DetectedDlc>> {
+    let mut detected = Vec::new();
+
+    for dlc in KNOWN_DLC {
+        let path = dlc.path(game_dir);
+        if !path.is_dir() {
+            continue;
+        }
+
+        let version = match fs::read_to_string(path.join("readme.txt")) {
+            Ok(readme) => readme_version(&readme),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        debug!(dlc = dlc.identifier, ?version, "Found a DLC");
+        detected.push(DetectedDlc {
+            identifier: dlc.identifier.to_owned(),
+            version,
+        });
+    }
+
+    Ok(detected)
+}
+
+/// Finds the version in a DLC's readme, which has a line like `Version 1.12.1`.
+fn readme_version(readme: &str) -> Option<String> {
+    readme.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("Version"), Some(version), None) => Some(version.to_owned()),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn detects_installed_dlc() {
+        let game_dir = env::temp_dir().join(format!("camrete-dlc-{}", process::id()));
+        let expansions = game_dir.join("GameData/SquadExpansion");
+        fs::create_dir_all(expansions.join("MakingHistory")).unwrap();
+        fs::write(
+            expansions.join("MakingHistory/readme.txt"),
+            "Kerbal Space Program: Making History\r\n\r\nVersion 1.12.1\r\n",
+        )
+        .unwrap();
+
+        let detected = detect_dlc(&game_dir).unwrap();
+        assert_eq!(
+            detected,
+            [DetectedDlc {
+                identifier: "MakingHistory-DLC".into(),
+                version: Some("1.12.1".into()),
+            }]
+        );
+
+        // Without a readme, the DLC is still installed, but its version isn't
+        // known.
+        fs::create_dir_all(expansions.join("Serenity")).unwrap();
+        let detected = detect_dlc(&game_dir).unwrap();
+        assert_eq!(detected[1].identifier, "BreakingGround-DLC");
+        assert_eq!(detected[1].version, None);
+
+        fs::remove_dir_all(&game_dir).unwrap();
+    }
+}
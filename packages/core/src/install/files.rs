@@ -0,0 +1,202 @@
+//! What a release downloads and how it's installed, for showing to users.
+//!
+//! Install directives are nested, with lists of filters under each one.
+//! [`ReleaseFiles`] gathers them with the release's download details into
+//! records a GUI can show in a details tab, and fills in the directive used
+//! when a release doesn't list any.
+
+use strum::IntoStaticStr;
+use url::Url;
+
+use crate::{
+    database::models::ModuleRelease,
+    json::{ModuleInstallDescriptor, ModuleInstallSourceDirective},
+};
+
+/// How an install directive chooses what to install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr, uniffi::Enum)]
+#[strum(serialize_all = "snake_case")]
+pub enum InstallSourceKind {
+    /// A file or folder at an exact path in the download.
+    File,
+    /// The first file or folder with a name.
+    Find,
+    /// The first file or folder whose path matches a pattern.
+    FindRegexp,
+}
+
+impl InstallSourceKind {
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// One of a release's install directives.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct InstallDirective {
+    pub kind: InstallSourceKind,
+    /// The path, name or pattern, depending on the `kind`.
+    pub source: String,
+    /// Where the matched file or folder is installed, relative to the game
+    /// directory.
+    pub install_to: String,
+    /// What the matched file or folder is renamed to.
+    pub install_as: Option<String>,
+    /// Whether a `find` directive can match files, and not just folders.
+    pub find_matches_files: bool,
+    /// Names of files which are left out.
+    pub filter: Vec<String>,
+    /// Patterns of files which are left out.
+    pub filter_regexp: Vec<String>,
+    /// Names of the only files which are installed.
+    pub include_only: Vec<String>,
+    /// Patterns of the only files which are installed.
+    pub include_only_regexp: Vec<String>,
+    /// Whether the release doesn't list any directives, so this one is used
+    /// instead.
+    pub implied: bool,
+}
+
+impl InstallDirective {
+    fn new(descriptor: &ModuleInstallDescriptor, implied: bool) -> Self {
+        let (kind, source) = match &descriptor.source {
+            ModuleInstallSourceDirective::File(path) => (InstallSourceKind::File, path),
+            ModuleInstallSourceDirective::Find(name) => (InstallSourceKind::Find, name),
+            ModuleInstallSourceDirective::FindRegexp(pattern) => {
+                (InstallSourceKind::FindRegexp, pattern)
+            }
+        };
+
+        Self {
+            kind,
+            source: source.clone(),
+            install_to: descriptor.install_to.clone(),
+            install_as: descriptor.r#as.clone(),
+            find_matches_files: descriptor.find_matches_files,
+            filter: descriptor.filter.clone(),
+            filter_regexp: descriptor.filter_regexp.clone(),
+            include_only: descriptor.include_only.clone(),
+            include_only_regexp: descriptor.include_only_regexp.clone(),
+            implied,
+        }
+    }
+
+    /// The directive's lists of file names and patterns, by the name they
+    /// have in a `.ckan` file. Lists which are empty are left out.
+    pub fn file_lists(&self) -> impl Iterator<Item = (&'static str, &[String])> {
+        [
+            ("filter", &self.filter),
+            ("filter_regexp", &self.filter_regexp),
+            ("include_only", &self.include_only),
+            ("include_only_regexp", &self.include_only_regexp),
+        ]
+        .into_iter()
+        .filter(|(_, list)| !list.is_empty())
+        .map(|(name, list)| (name, list.as_slice()))
+    }
+}
+
+/// Where a release is downloaded from, and how it's installed.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ReleaseFiles {
+    /// The URLs the release can be downloaded from, the first being the
+    /// primary one.
+    pub download: Vec<Url>,
+    pub download_content_type: Option<String>,
+    pub download_size: Option<i64>,
+    pub install_size: Option<i64>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    pub install: Vec<InstallDirective>,
+}
+
+impl ReleaseFiles {
+    /// Describes the files of a release of the module `identifier`.
+    pub fn new(identifier: &str, release: &ModuleRelease) -> Self {
+        let metadata = &release.metadata;
+        let install = if metadata.install.is_empty() {
+            let default = ModuleInstallDescriptor::find_in_game_data(identifier);
+            vec![InstallDirective::new(&default, true)]
+        } else {
+            (metadata.install.iter())
+                .map(|descriptor| InstallDirective::new(descriptor, false))
+                .collect()
+        };
+
+        Self {
+            download: metadata.download.to_vec(),
+            download_content_type: metadata.download_content_type.as_deref().map(Into::into),
+            download_size: release.download_size,
+            install_size: release.install_size,
+            sha1: metadata.download_hash.sha1.clone(),
+            sha256: metadata.download_hash.sha256.clone(),
+            install,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::schema::module_releases,
+        repo::test_support::{db_with_default_repo, release},
+    };
+
+    #[test]
+    fn lists_install_directives() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let downloaded = |version| {
+            release("Parallax", version).merge(json!({
+                "download": "https://example.com/Parallax.zip",
+                "download_hash": { "sha256": "ABCD" },
+            }))
+        };
+        let (module_id, implied) = db
+            .create_release(&downloaded("2.0.1").build(), repo.id, None)
+            .unwrap();
+        let install = json!([{
+            "find": "Shaders",
+            "install_to": "GameData/Parallax",
+            "filter": ["Thumbs.db", ".DS_Store"],
+        }]);
+        let listed = downloaded("2.0.2").set("install", install).build();
+        let (_, listed) = db
+            .create_release(&listed, repo.id, Some(module_id))
+            .unwrap();
+
+        let mut files = |release_id| {
+            let release = ModuleRelease::all()
+                .filter(module_releases::release_id.eq(release_id))
+                .get_result::<ModuleRelease>(db.connection_mut())
+                .unwrap();
+            ReleaseFiles::new("Parallax", &release)
+        };
+
+        let implied = files(implied);
+        assert_eq!(
+            implied.download[0].as_str(),
+            "https://example.com/Parallax.zip"
+        );
+        assert_eq!(implied.sha256.as_deref(), Some("ABCD"));
+        assert_eq!(implied.install.len(), 1);
+        assert!(implied.install[0].implied);
+        assert_eq!(implied.install[0].kind, InstallSourceKind::Find);
+        assert_eq!(implied.install[0].install_to, "GameData");
+
+        let listed = files(listed).install.remove(0);
+        assert!(!listed.implied);
+        assert_eq!(listed.source, "Shaders");
+        assert_eq!(
+            listed.file_lists().collect::<Vec<_>>(),
+            [(
+                "filter",
+                ["Thumbs.db".to_string(), ".DS_Store".into()].as_slice()
+            )]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Listing the files in a zip archive without reading all of it.
+//!
+//! Zip archives end with a central directory which names every file they
+//! contain, so the files can be listed by reading only the end of the archive.
+//! This lets install directives be checked against a release's download before
+//! it's been downloaded, as long as the server supports range requests.
+
+use std::{io::SeekFrom, path::Path};
+
+use miette::Diagnostic;
+use thiserror::Error;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::Result;
+
+/// How much of the end of an archive is read to find its central directory:
+/// the end of central directory record, plus the longest comment it can have.
+const TAIL_LEN: u64 = END_RECORD_LEN as u64 + u16::MAX as u64;
+
+/// The largest central directory which will be read, in bytes.
+const MAX_DIRECTORY_LEN: u64 = 64 * 1024 * 1024;
+
+const END_RECORD_SIGNATURE: u32 = 0x06054b50;
+const END_RECORD_LEN: usize = 22;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const ZIP64_END_RECORD_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_END_RECORD_LEN: usize = 56;
+const ENTRY_SIGNATURE: u32 = 0x02014b50;
+const ENTRY_LEN: usize = 46;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum ListingError {
+    #[error("the file isn't a zip archive")]
+    #[diagnostic(code(camrete::install::listing::not_zip), severity(Warning))]
+    NotZip,
+    #[error("the zip archive's central directory is damaged: {reason}")]
+    #[diagnostic(code(camrete::install::listing::damaged), severity(Warning))]
+    Damaged { reason: &'static str },
+    #[error("{url} can't be read in parts")]
+    #[diagnostic(
+        code(camrete::install::listing::ranges_unsupported),
+        severity(Warning),
+        help("the file has to be downloaded before its contents can be listed")
+    )]
+    RangesUnsupported { url: String },
+}
+
+/// Somewhere an archive can be read from a piece at a time.
+pub(crate) trait ArchiveSource {
+    /// Reads up to `len` bytes from the end of the archive, returning them
+    /// along with their offset from the start of the archive.
+    async fn read_tail(&mut self, len: u64) -> Result<(u64, Vec<u8>)>;
+
+    /// Reads `len` bytes starting at `offset`.
+    async fn read_range(&mut self, offset: u64, len: u64) -> Result<Vec<u8>>;
+}
+
+/// The files in a release's download.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveListing {
+    /// The path of every file in the archive, using `/` as the separator.
+    /// Folders aren't included.
+    pub files: Vec<String>,
+}
+
+impl ArchiveListing {
+    /// Lists the files in a zip archive which has already been downloaded.
+    pub async fn read_file(path: &Path) -> Result<Self> {
+        Self::read(&mut File::open(path).await?).await
+    }
+
+    /// Lists the files in a zip archive, reading as little of it as possible.
+    pub(crate) async fn read(source: &mut impl ArchiveSource) -> Result<Self> {
+        let (tail_offset, tail) = source.read_tail(TAIL_LEN).await?;
+        let directory = CentralDirectory::locate(&tail, tail_offset)?;
+
+        let data = match directory.offset.checked_sub(tail_offset) {
+            // The directory is usually small enough to be in the tail already.
+            Some(start) => {
+                let start = start as usize;
+                tail.get(start..start + directory.len as usize)
+                    .ok_or(ListingError::Damaged {
+                        reason: "the directory runs past the end of the archive",
+                    })?
+                    .to_vec()
+            }
+            None => source.read_range(directory.offset, directory.len).await?,
+        };
+
+        Ok(Self {
+            files: directory.entries(&data)?,
+        })
+    }
+
+    /// The paths of the files, in the form taken by
+    /// [`PlannedInstall::new`](super::PlannedInstall::new).
+    pub fn paths(&self) -> Vec<&str> {
+        self.files.iter().map(String::as_str).collect()
+    }
+}
+
+impl ArchiveSource for File {
+    async fn read_tail(&mut self, len: u64) -> Result<(u64, Vec<u8>)> {
+        let file_len = self.metadata().await?.len();
+        let offset = file_len.saturating_sub(len);
+        Ok((offset, self.read_range(offset, file_len - offset).await?))
+    }
+
+    async fn read_range(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut data = vec![0; len as usize];
+        self.seek(SeekFrom::Start(offset)).await?;
+        self.read_exact(&mut data).await?;
+        Ok(data)
+    }
+}
+
+/// Where an archive's central directory is, and how many entries it has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CentralDirectory {
+    offset: u64,
+    len: u64,
+    entries: u64,
+}
+
+impl CentralDirectory {
+    /// Finds the central directory using the records at the end of an
+    /// archive. `tail` holds the end of the archive, starting at `tail_offset`.
+    fn locate(tail: &[u8], tail_offset: u64) -> Result<Self, ListingError> {
+        // The record is followed by a comment, which could contain anything, so
+        // search backwards for the first signature which could be the record.
+        let end = (0..=tail.len().saturating_sub(END_RECORD_LEN))
+            .rev()
+            .find(|&i| read_u32(tail, i) == Some(END_RECORD_SIGNATURE))
+            .ok_or(ListingError::NotZip)?;
+
+        let mut directory = Self {
+            entries: read_u16(tail, end + 10).unwrap_or_default().into(),
+            len: read_u32(tail, end + 12).unwrap_or_default().into(),
+            offset: read_u32(tail, end + 16).unwrap_or_default().into(),
+        };
+
+        // Archives over 4 GiB, or with more than 65535 entries, keep the real
+        // values in a larger record just before this one.
+        let is_zip64 = directory.entries == u64::from(u16::MAX)
+            || directory.len == u64::from(u32::MAX)
+            || directory.offset == u64::from(u32::MAX);
+        let locator = end.checked_sub(ZIP64_LOCATOR_LEN);
+
+        if let Some(locator) = locator
+            && is_zip64
+            && read_u32(tail, locator) == Some(ZIP64_LOCATOR_SIGNATURE)
+        {
+            let record = read_u64(tail, locator + 8)
+                .and_then(|offset| offset.checked_sub(tail_offset))
+                .map(|start| start as usize)
+                .filter(|&start| start + ZIP64_END_RECORD_LEN <= tail.len())
+                .filter(|&start| read_u32(tail, start) == Some(ZIP64_END_RECORD_SIGNATURE))
+                .ok_or(ListingError::Damaged {
+                    reason: "the zip64 end of central directory record is missing",
+                })?;
+
+            directory = Self {
+                entries: read_u64(tail, record + 32).unwrap_or_default(),
+                len: read_u64(tail, record + 40).unwrap_or_default(),
+                offset: read_u64(tail, record + 48).unwrap_or_default(),
+            };
+        }
+
+        if directory.len > MAX_DIRECTORY_LEN {
+            return Err(ListingError::Damaged {
+                reason: "the directory is unreasonably large",
+            });
+        }
+
+        Ok(directory)
+    }
+
+    /// Reads the names of the files in the directory.
+    fn entries(&self, data: &[u8]) -> Result<Vec<String>, ListingError> {
+        let damaged = |reason| ListingError::Damaged { reason };
+        let mut files = Vec::new();
+        let mut position = 0;
+
+        for _ in 0..self.entries {
+            if read_u32(data, position) != Some(ENTRY_SIGNATURE) {
+                return Err(damaged("an entry is missing its signature"));
+            }
+
+            let field = |offset| read_u16(data, position + offset).map(usize::from);
+            let (Some(name_len), Some(extra_len), Some(comment_len)) =
+                (field(28), field(30), field(32))
+            else {
+                return Err(damaged("an entry is cut off"));
+            };
+
+            let name_start = position + ENTRY_LEN;
+            let name = data
+                .get(name_start..name_start + name_len)
+                .ok_or(damaged("an entry's name is cut off"))?;
+            position = name_start + name_len + extra_len + comment_len;
+
+            // Some tools on Windows write paths with backslashes.
+            let name = String::from_utf8_lossy(name).replace('\\', "/");
+            if !name.ends_with('/') {
+                files.push(name);
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds the central directory and end record of a zip archive, after
+    /// `padding` bytes standing in for the compressed files.
+    fn zip(names: &[&str], padding: usize) -> Vec<u8> {
+        let mut data = vec![0; padding];
+
+        let directory_start = data.len();
+        for name in names {
+            data.extend(ENTRY_SIGNATURE.to_le_bytes());
+            data.extend([0; 24]);
+            data.extend((name.len() as u16).to_le_bytes());
+            data.extend([0; 16]);
+            data.extend(name.as_bytes());
+        }
+        let directory_len = data.len() - directory_start;
+
+        data.extend(END_RECORD_SIGNATURE.to_le_bytes());
+        data.extend([0; 6]);
+        data.extend((names.len() as u16).to_le_bytes());
+        data.extend((directory_len as u32).to_le_bytes());
+        data.extend((directory_start as u32).to_le_bytes());
+        data.extend(7u16.to_le_bytes());
+        data.extend(b"comment");
+        data
+    }
+
+    /// An archive in memory, which counts how many times it's read.
+    struct InMemory(Vec<u8>, usize);
+
+    impl ArchiveSource for InMemory {
+        async fn read_tail(&mut self, len: u64) -> Result<(u64, Vec<u8>)> {
+            self.1 += 1;
+            let offset = self.0.len().saturating_sub(len as usize);
+            Ok((offset as u64, self.0[offset..].to_vec()))
+        }
+
+        async fn read_range(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+            self.1 += 1;
+            Ok(self.0[offset as usize..(offset + len) as usize].to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_files_from_the_central_directory() {
+        let names = [
+            "Parallax/",
+            "Parallax/Parallax.dll",
+            "Parallax\\Shaders\\terrain.shader",
+        ];
+
+        // The whole directory is in the tail, so only one read is needed.
+        let mut archive = InMemory(zip(&names, 1024 * 1024), 0);
+        let listing = ArchiveListing::read(&mut archive).await.unwrap();
+        assert_eq!(
+            listing.paths(),
+            ["Parallax/Parallax.dll", "Parallax/Shaders/terrain.shader"]
+        );
+        assert_eq!(archive.1, 1);
+
+        // A directory which doesn't fit needs a second read.
+        let long_name = format!("GameData/{}", "a".repeat(1000));
+        let many = vec![long_name.as_str(); 100];
+        let mut archive = InMemory(zip(&many, 0), 0);
+        let listing = ArchiveListing::read(&mut archive).await.unwrap();
+        assert_eq!(listing.files.len(), 100);
+        assert_eq!(archive.1, 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_files() {
+        let mut archive = InMemory(b"{\"spec_version\": 1}".to_vec(), 0);
+        let error = ArchiveListing::read(&mut archive).await.unwrap_err();
+        assert!(matches!(
+            error,
+            crate::Error::Listing(ListingError::NotZip)
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+//! Installing a module from a zip archive on disk, rather than from a release
+//! in a repository.
+//!
+//! Modders use this to test builds which haven't been published. The module's
+//! metadata is read from a `.ckan` file inside the archive, unless other
+//! metadata is given, and the module is recorded along with the archive it
+//! came from.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    ops::DerefMut,
+    path::{Path, PathBuf},
+};
+
+use diesel::SqliteConnection;
+use miette::Diagnostic;
+use thiserror::Error;
+use time::OffsetDateTime;
+use tracing::{debug, instrument, warn};
+use zip::{ZipArchive, result::ZipError};
+
+use crate::{
+    Result,
+    database::{InstalledModule, RepoDB},
+    install::{InstallPlan, InstallPlanError, PlannedFile, PlannedInstall},
+    io::{DirTransaction, SyncPolicy},
+    json::{self, Identifier, JsonError, JsonModule},
+};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum LocalInstallError {
+    #[error("failed to read the zip archive at {}", path.display())]
+    #[diagnostic(code(camrete::install::local::unreadable), severity(Warning))]
+    Archive { path: PathBuf, source: ZipError },
+    #[error("the archive at {} doesn't contain a `.ckan` file", path.display())]
+    #[diagnostic(
+        code(camrete::install::local::no_metadata),
+        severity(Warning),
+        help("pass the module's metadata separately")
+    )]
+    NoMetadata { path: PathBuf },
+    #[error("the module metadata in {name} is invalid")]
+    #[diagnostic(code(camrete::install::local::invalid_metadata), severity(Warning))]
+    InvalidMetadata { name: String, source: JsonError },
+}
+
+/// A zip archive on disk holding a module.
+pub struct LocalArchive {
+    path: PathBuf,
+    zip: ZipArchive<BufReader<File>>,
+}
+
+impl LocalArchive {
+    /// Opens a zip archive and reads its central directory.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let zip = ZipArchive::new(BufReader::new(file)).map_err(archive_error(path))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            zip,
+        })
+    }
+
+    /// The path of every file in the archive, using `/` as the separator.
+    /// Folders aren't included.
+    pub fn files(&self) -> Vec<String> {
+        self.zip
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Reads the module's metadata from the `.ckan` file in the archive, if it
+    /// has one. If it has several, the one nearest the top of the archive is
+    /// used.
+    pub fn embedded_metadata(&mut self) -> Result<Option<JsonModule>> {
+        let Some(name) = self
+            .files()
+            .into_iter()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".ckan"))
+            .min_by(|a, b| (a.matches('/').count(), a).cmp(&(b.matches('/').count(), b)))
+        else {
+            return Ok(None);
+        };
+
+        let mut data = Vec::new();
+        self.zip
+            .by_name(&name)
+            .map_err(archive_error(&self.path))?
+            .read_to_end(&mut data)?;
+
+        let module = json::from_slice(&data)
+            .map_err(|source| LocalInstallError::InvalidMetadata { name, source })?;
+        Ok(Some(module))
+    }
+
+    /// Maps the module's files to their installed locations, following the
+    /// install directives in its metadata. Releases without install
+    /// directives are installed by finding the folder named in the metadata.
+    pub fn plan(&self, module: &JsonModule) -> Result<PlannedInstall> {
+        let files = self.files();
+        let paths = files.iter().map(String::as_str).collect::<Vec<_>>();
+        Ok(PlannedInstall::new(
+            &module.identifier,
+            &module.version,
+            &module.install,
+            &paths,
+        )?)
+    }
+
+    /// Stages the planned files in a game directory, without moving them into
+    /// place until the returned transaction is committed.
+    #[instrument(skip_all, fields(archive = ?self.path))]
+    pub fn stage(&mut self, plan: &PlannedInstall, game_dir: &Path) -> Result<DirTransaction> {
+        let mut transaction = DirTransaction::begin(game_dir, SyncPolicy::default())?;
+        for file in &plan.files {
+            let mut entry = self
+                .zip
+                .by_name(&file.source)
+                .map_err(archive_error(&self.path))?;
+            transaction.write_with(&file.destination, |out| {
+                io::copy(&mut entry, out)?;
+                Ok(())
+            })?;
+        }
+        Ok(transaction)
+    }
+
+    /// Copies the module's files into a game directory. Either every file is
+    /// installed or none are. Returns the record of the installed module,
+    /// which is named `identifier` if given, instead of the identifier in the
+    /// metadata.
+    ///
+    /// This doesn't check whether any installed modules own the files; see
+    /// [`RepoDB::install_local_archive`].
+    pub fn install(
+        &mut self,
+        module: &JsonModule,
+        identifier: Option<&Identifier>,
+        game_dir: &Path,
+    ) -> Result<InstalledModule> {
+        let plan = self.plan(module)?;
+        self.stage(&plan, game_dir)?.commit()?;
+        debug!(files = plan.files.len(), "Installed local archive");
+
+        Ok(self.record(&plan, identifier))
+    }
+
+    fn record(&self, plan: &PlannedInstall, identifier: Option<&Identifier>) -> InstalledModule {
+        InstalledModule {
+            slug: identifier.map_or_else(|| plan.module.clone(), Identifier::to_string),
+            version: plan.version.clone(),
+            auto_installed: false,
+            installed_at: Some(OffsetDateTime::now_utc()),
+            files: plan
+                .files
+                .iter()
+                .map(|file| file.destination.to_string_lossy().replace('\\', "/"))
+                .collect(),
+            local_archive: Some(self.path.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+fn archive_error(path: &Path) -> impl FnOnce(ZipError) -> LocalInstallError {
+    move |source| LocalInstallError::Archive {
+        path: path.to_owned(),
+        source,
+    }
+}
+
+/// Reads module metadata from a `.ckan` file, for archives which don't contain
+/// their own or whose own is out of date.
+pub fn read_metadata(path: &Path) -> Result<JsonModule> {
+    let data = fs::read(path)?;
+
+    let module = json::from_slice(&data).map_err(|source| LocalInstallError::InvalidMetadata {
+        name: path.display().to_string(),
+        source,
+    })?;
+    Ok(module)
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Installs a module from a zip archive on disk into a game directory, and
+    /// records it as installed from that archive.
+    ///
+    /// The module's metadata is `metadata` if given, or else the `.ckan` file
+    /// inside the archive. It's recorded as `identifier` if given, so that a
+    /// build can stand in for a published module.
+    ///
+    /// Nothing is written if any of the module's files belong to another
+    /// installed module. The files are only moved into place once the record
+    /// is written, and are removed again if it can't be committed.
+    pub fn install_local_archive(
+        &mut self,
+        archive: &Path,
+        game_dir: &Path,
+        metadata: Option<JsonModule>,
+        identifier: Option<&Identifier>,
+    ) -> Result<InstalledModule> {
+        let mut archive = LocalArchive::open(archive)?;
+        let module = match metadata {
+            Some(module) => module,
+            None => archive
+                .embedded_metadata()?
+                .ok_or_else(|| LocalInstallError::NoMetadata {
+                    path: archive.path.clone(),
+                })?,
+        };
+
+        let plan = archive.plan(&module)?;
+        let installed = archive.record(&plan, identifier);
+        self.check_local_conflicts(&installed)?;
+
+        let files = archive.stage(&plan, game_dir)?;
+        let mut moved = false;
+        let result = self.transaction(|mut db| {
+            db.record_installed(vec![installed.clone()])?;
+            files.commit()?;
+            moved = true;
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            // The files are only moved into place once the record is written,
+            // but the record can still fail to commit after that.
+            if moved {
+                warn!("Failed to record local install, removing its files: {e}");
+                remove_files(game_dir, &installed.files);
+            }
+            return Err(e);
+        }
+
+        debug!(files = installed.files.len(), "Installed local archive");
+        Ok(installed)
+    }
+
+    /// Checks that none of the files a module would install are owned by
+    /// another installed module. Files owned by an earlier install of the
+    /// same module are fine, since its record will be replaced.
+    fn check_local_conflicts(&mut self, module: &InstalledModule) -> Result<()> {
+        let mut plan = InstallPlan::default();
+        for installed in self.installed_modules()? {
+            if installed.slug != module.slug {
+                plan.push(owned_files(&installed));
+            }
+        }
+        plan.push(owned_files(module));
+
+        let conflicts = plan
+            .conflicts()
+            .into_iter()
+            .filter(|conflict| conflict.modules.contains(&module.slug))
+            .collect::<Vec<_>>();
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(InstallPlanError::FileConflicts { conflicts }.into())
+        }
+    }
+}
+
+/// The files recorded for an installed module, as if they were to be
+/// installed again.
+fn owned_files(module: &InstalledModule) -> PlannedInstall {
+    PlannedInstall {
+        module: module.slug.clone(),
+        version: module.version.clone(),
+        files: module
+            .files
+            .iter()
+            .map(|path| PlannedFile {
+                source: path.clone(),
+                destination: PathBuf::from(path),
+            })
+            .collect(),
+    }
+}
+
+fn remove_files(game_dir: &Path, files: &[String]) {
+    for file in files {
+        let path = game_dir.join(file);
+        if let Err(e) = fs::remove_file(&path) {
+            warn!(?path, "Failed to remove file: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, io::Write, process};
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+    use crate::repo::{RepoManager, test_support::release};
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn installs_local_archive() {
+        let dir = env::temp_dir().join(format!("camrete-local-install-{}", process::id()));
+        let game_dir = dir.join("KSP");
+        fs::create_dir_all(&game_dir).unwrap();
+
+        let metadata = release("Parallax", "2.1-dev");
+        let archive = dir.join("Parallax.zip");
+        write_zip(
+            &archive,
+            &[
+                ("Parallax.ckan", &metadata.to_json().to_string()),
+                ("Parallax/Parallax.dll", "dll"),
+                ("Parallax/Textures/rock.dds", "dds"),
+            ],
+        );
+
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+        let installed = db
+            .install_local_archive(&archive, &game_dir, None, None)
+            .unwrap();
+        assert_eq!(installed.slug, "Parallax");
+        assert_eq!(installed.version, "2.1-dev");
+        assert_eq!(
+            installed.files,
+            [
+                "GameData/Parallax/Parallax.dll",
+                "GameData/Parallax/Textures/rock.dds"
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(game_dir.join("GameData/Parallax/Parallax.dll")).unwrap(),
+            "dll"
+        );
+        assert_eq!(db.installed_modules().unwrap()[0].files, installed.files);
+
+        // Without a `.ckan` file, metadata has to be given, and the module can
+        // be installed under another identifier.
+        let bare = dir.join("Bare.zip");
+        write_zip(&bare, &[("Parallax/Parallax.dll", "new dll")]);
+        assert!(matches!(
+            db.install_local_archive(&bare, &game_dir, None, None),
+            Err(crate::Error::LocalInstall(
+                LocalInstallError::NoMetadata { .. }
+            ))
+        ));
+
+        // Its files belong to Parallax, so it can't be installed alongside it.
+        let identifier = Identifier::new("Parallax-Dev").unwrap();
+        let err = db
+            .install_local_archive(&bare, &game_dir, Some(metadata.clone().build()), Some(&identifier))
+            .unwrap_err();
+        let crate::Error::InstallPlan(InstallPlanError::FileConflicts { conflicts }) = err else {
+            panic!("expected a file conflict, got {err:?}");
+        };
+        assert_eq!(
+            conflicts[0].destination,
+            Path::new("GameData/Parallax/Parallax.dll")
+        );
+        assert_eq!(conflicts[0].modules, ["Parallax", "Parallax-Dev"]);
+        assert_eq!(
+            fs::read_to_string(game_dir.join("GameData/Parallax/Parallax.dll")).unwrap(),
+            "dll"
+        );
+        assert_eq!(db.installed_modules().unwrap().len(), 1);
+
+        db.remove_installed("Parallax").unwrap();
+        let installed = db
+            .install_local_archive(&bare, &game_dir, Some(metadata.build()), Some(&identifier))
+            .unwrap();
+        assert_eq!(installed.slug, "Parallax-Dev");
+        assert_eq!(
+            installed.local_archive.as_deref(),
+            Some(&*bare.to_string_lossy())
+        );
+        assert_eq!(
+            fs::read_to_string(game_dir.join("GameData/Parallax/Parallax.dll")).unwrap(),
+            "new dll"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
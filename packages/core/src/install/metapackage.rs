@@ -0,0 +1,224 @@
+//! Exporting the installed modules as a metapackage.
+//!
+//! This is the format of the `installed-*.ckan` files the original CKAN client
+//! exports: a metapackage which depends on (or recommends) each installed
+//! module. Installing it with either client installs the same modules.
+
+use std::{io::Write, ops::DerefMut};
+
+use diesel::SqliteConnection;
+use serde_json::{Map, Value, json};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::{
+    Result,
+    database::{InstalledModule, RepoDB},
+    json::Identifier,
+};
+
+/// The version of the metadata spec which exported metapackages follow.
+const SPEC_VERSION: &str = "v1.6";
+
+/// Which relationship the metapackage has with each installed module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum ExportRelationship {
+    /// Every module is installed along with the metapackage.
+    #[default]
+    Depends,
+    /// The user can choose which modules are installed.
+    Recommends,
+}
+
+/// How closely the metapackage's relationships match the installed versions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum VersionPin {
+    /// Any version of each module.
+    #[default]
+    None,
+    /// The installed version of each module, or a newer one.
+    Minimum,
+    /// Exactly the installed version of each module.
+    Exact,
+}
+
+/// How a metapackage is exported.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct MetapackageOptions {
+    /// The name of the game instance. The metapackage's identifier is
+    /// `installed-{name}`, with any characters which can't be in an identifier
+    /// replaced with `-`.
+    pub name: String,
+    pub relationship: ExportRelationship,
+    pub pin: VersionPin,
+    /// Also list the modules which were only installed to satisfy another
+    /// module's relationships. They're left out by default, since installing
+    /// the others brings them in again.
+    pub include_auto_installed: bool,
+}
+
+impl MetapackageOptions {
+    /// The metapackage's identifier. The instance's name is slugified like
+    /// CKAN does, which also keeps it from naming another folder in the file
+    /// name.
+    pub fn identifier(&self) -> Identifier {
+        let name = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect::<String>();
+
+        Identifier::new(format!("installed-{name}"))
+            .expect("slugified names make valid identifiers")
+    }
+
+    /// The name the metapackage's file is given by CKAN.
+    pub fn file_name(&self) -> String {
+        format!("{}.ckan", self.identifier())
+    }
+}
+
+/// Builds a metapackage which installs the given modules. Its version is the
+/// time it was exported, so that later exports are newer.
+pub fn installed_metapackage(
+    modules: &[InstalledModule],
+    options: &MetapackageOptions,
+    exported_at: OffsetDateTime,
+) -> Value {
+    let relationships = modules
+        .iter()
+        .filter(|module| options.include_auto_installed || !module.auto_installed)
+        .map(|module| {
+            let mut relationship = Map::new();
+            relationship.insert("name".into(), module.slug.clone().into());
+            match options.pin {
+                VersionPin::None => {}
+                VersionPin::Minimum => {
+                    relationship.insert("min_version".into(), module.version.clone().into());
+                }
+                VersionPin::Exact => {
+                    relationship.insert("version".into(), module.version.clone().into());
+                }
+            }
+            Value::Object(relationship)
+        })
+        .collect::<Vec<_>>();
+
+    let relationship = match options.relationship {
+        ExportRelationship::Depends => "depends",
+        ExportRelationship::Recommends => "recommends",
+    };
+    let version = format!(
+        "{}.{:02}.{:02}.{:02}.{:02}.{:02}",
+        exported_at.year(),
+        u8::from(exported_at.month()),
+        exported_at.day(),
+        exported_at.hour(),
+        exported_at.minute(),
+        exported_at.second(),
+    );
+
+    json!({
+        "spec_version": SPEC_VERSION,
+        "identifier": options.identifier().as_str(),
+        "name": options.identifier().as_str(),
+        "abstract": format!("A list of modules installed on the {} KSP instance", options.name),
+        "kind": "metapackage",
+        "version": version,
+        "license": "unknown",
+        "author": "camrete",
+        "release_date": exported_at.format(&Rfc3339).ok(),
+        (relationship): relationships,
+    })
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Writes a metapackage which installs the installed modules, as pretty
+    /// printed JSON.
+    pub fn export_installed(
+        &mut self,
+        options: &MetapackageOptions,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        let modules = self.installed_modules()?;
+        let metapackage = installed_metapackage(&modules, options, OffsetDateTime::now_utc());
+
+        serde_json::to_writer_pretty(&mut *writer, &metapackage)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::from_value;
+
+    use super::*;
+    use crate::json::JsonModule;
+
+    fn installed(slug: &str, version: &str, auto_installed: bool) -> InstalledModule {
+        InstalledModule {
+            slug: slug.into(),
+            version: version.into(),
+            auto_installed,
+            installed_at: None,
+            files: vec![],
+            local_archive: None,
+        }
+    }
+
+    #[test]
+    fn exports_metapackage() {
+        let modules = [
+            installed("ModuleManager", "4.2.3", true),
+            installed("Parallax", "2.0.8", false),
+        ];
+        let mut options = MetapackageOptions {
+            name: "default".into(),
+            relationship: ExportRelationship::Depends,
+            pin: VersionPin::Exact,
+            include_auto_installed: false,
+        };
+        // 2024-03-01 09:05:00 UTC
+        let exported_at = OffsetDateTime::from_unix_timestamp(1709283900).unwrap();
+
+        let metapackage = installed_metapackage(&modules, &options, exported_at);
+        assert_eq!(options.file_name(), "installed-default.ckan");
+        assert_eq!(metapackage["identifier"], "installed-default");
+        assert_eq!(metapackage["version"], "2024.03.01.09.05.00");
+        assert_eq!(
+            metapackage["depends"],
+            json!([{ "name": "Parallax", "version": "2.0.8" }])
+        );
+
+        options.relationship = ExportRelationship::Recommends;
+        options.pin = VersionPin::Minimum;
+        options.include_auto_installed = true;
+        let metapackage = installed_metapackage(&modules, &options, exported_at);
+        assert_eq!(metapackage.get("depends"), None);
+        assert_eq!(
+            metapackage["recommends"],
+            json!([
+                { "name": "ModuleManager", "min_version": "4.2.3" },
+                { "name": "Parallax", "min_version": "2.0.8" },
+            ])
+        );
+
+        // The metapackage can be read back like any other module.
+        let module: JsonModule = from_value(metapackage).unwrap();
+        assert_eq!(module.recommends.len(), 2);
+    }
+
+    #[test]
+    fn slugifies_instance_names() {
+        let mut options = MetapackageOptions {
+            name: "My Career (1.12)".into(),
+            relationship: ExportRelationship::Depends,
+            pin: VersionPin::None,
+            include_auto_installed: false,
+        };
+        assert_eq!(options.identifier().as_str(), "installed-My-Career--1-12-");
+
+        options.name = "../../evil".into();
+        assert_eq!(options.file_name(), "installed-------evil.ckan");
+    }
+}
@@ -0,0 +1,520 @@
+//! Importing the installed modules and repositories recorded by the original
+//! CKAN client.
+//!
+//! CKAN keeps its state for each game directory in `CKAN/registry.json`. Only
+//! the `installed_modules` and `sorted_repositories` sections are read; the
+//! rest describes the repositories' contents, which Camrete downloads itself.
+//!
+//! The game directories CKAN knows about are listed in its settings, which
+//! [`CkanConfig::load_default`] finds, so repositories can be imported without
+//! knowing where the game is.
+
+use std::{
+    collections::{BTreeMap, btree_map::Entry},
+    fs, io,
+    ops::DerefMut,
+    path::{Path, PathBuf},
+};
+
+use diesel::SqliteConnection;
+use directories::BaseDirs;
+use miette::Diagnostic;
+use serde::Deserialize;
+use thiserror::Error;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+use crate::{
+    Result,
+    database::{InstalledModule, ReleaseId, RepoDB, canonical_url, models::RepositoryRef},
+};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum RegistryError {
+    #[error("failed to read the CKAN registry at {}", path.display())]
+    #[diagnostic(
+        code(camrete::registry::unreadable),
+        severity(Warning),
+        help("pass either a game directory or the path of its `CKAN/registry.json`")
+    )]
+    Read { path: PathBuf, source: io::Error },
+    #[error("the CKAN registry at {} is damaged", path.display())]
+    #[diagnostic(code(camrete::registry::corrupt), severity(Warning))]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("couldn't find CKAN's settings")]
+    #[diagnostic(
+        code(camrete::registry::no_config),
+        severity(Warning),
+        help("pass the game directory CKAN manages instead")
+    )]
+    NoConfig,
+    #[error("failed to read CKAN's settings at {}", path.display())]
+    #[diagnostic(
+        code(camrete::registry::config_unreadable),
+        severity(Warning),
+        help("CKAN may not have been run on this device, so pass a game directory instead")
+    )]
+    ReadConfig { path: PathBuf, source: io::Error },
+    #[error("CKAN's settings at {} are damaged", path.display())]
+    #[diagnostic(code(camrete::registry::config_corrupt), severity(Warning))]
+    ParseConfig {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// The parts of a CKAN `registry.json` which Camrete understands.
+#[derive(Debug, Default, Deserialize)]
+pub struct CkanRegistry {
+    #[serde(default)]
+    installed_modules: BTreeMap<String, RegistryModule>,
+    /// The repositories the game directory uses, by name.
+    #[serde(default)]
+    sorted_repositories: BTreeMap<String, RepositoryRef<'static>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryModule {
+    source_module: SourceModule,
+    #[serde(default)]
+    install_time: Option<String>,
+    #[serde(default)]
+    auto_installed: bool,
+    #[serde(default)]
+    installed_files: InstalledFiles,
+}
+
+/// The metadata of the installed release. Only the fields needed to find it
+/// again are read.
+#[derive(Debug, Deserialize)]
+struct SourceModule {
+    identifier: String,
+    version: String,
+}
+
+/// Current versions of CKAN map each file to its checksum, while older ones
+/// only listed the files.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum InstalledFiles {
+    Map(BTreeMap<String, serde_json::Value>),
+    List(Vec<String>),
+}
+
+impl Default for InstalledFiles {
+    fn default() -> Self {
+        Self::List(Vec::new())
+    }
+}
+
+impl InstalledFiles {
+    fn into_paths(self) -> Vec<String> {
+        match self {
+            Self::Map(files) => files.into_keys().collect(),
+            Self::List(mut files) => {
+                files.sort();
+                files
+            }
+        }
+    }
+}
+
+/// A module which was imported from a CKAN registry.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ImportedModule {
+    pub slug: String,
+    pub version: String,
+    /// The matching release in Camrete's repositories, or `None` if none of
+    /// them have it, like when the module was installed from a `.ckan` file.
+    pub release: Option<ReleaseId>,
+}
+
+impl CkanRegistry {
+    /// Reads a CKAN registry. If `path` is a directory, it's treated as a game
+    /// directory and its `CKAN/registry.json` is read.
+    #[instrument]
+    pub fn load(path: &Path) -> Result<Self, RegistryError> {
+        let path = if path.is_dir() {
+            path.join("CKAN").join("registry.json")
+        } else {
+            path.to_owned()
+        };
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(source) => return Err(RegistryError::Read { path, source }),
+        };
+
+        serde_json::from_slice(&data).map_err(|source| RegistryError::Parse { path, source })
+    }
+
+    /// The installed modules, in the form Camrete records them.
+    pub fn installed_modules(self) -> Vec<InstalledModule> {
+        self.installed_modules
+            .into_values()
+            .map(|module| InstalledModule {
+                slug: module.source_module.identifier,
+                version: module.source_module.version,
+                auto_installed: module.auto_installed,
+                installed_at: module
+                    .install_time
+                    .and_then(|time| OffsetDateTime::parse(&time, &Rfc3339).ok()),
+                files: module.installed_files.into_paths(),
+                local_archive: None,
+            })
+            .collect()
+    }
+
+    /// The repositories the game directory uses, ordered by priority and then
+    /// by name.
+    pub fn repositories(&self) -> Vec<RepositoryRef<'static>> {
+        let mut repos = self
+            .sorted_repositories
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_repos(&mut repos);
+        repos
+    }
+}
+
+/// Orders repositories by priority and then by name, like CKAN lists them.
+fn sort_repos(repos: &mut [RepositoryRef<'_>]) {
+    repos.sort_by(|a, b| (a.priority, &a.name).cmp(&(b.priority, &b.name)));
+}
+
+/// The parts of CKAN's settings which Camrete understands.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CkanConfig {
+    #[serde(default)]
+    game_instances: Vec<CkanInstance>,
+}
+
+/// A game directory which CKAN manages.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, uniffi::Record)]
+#[serde(rename_all = "PascalCase")]
+pub struct CkanInstance {
+    pub name: String,
+    pub path: String,
+    /// The game the directory holds, like `KSP` or `KSP2`.
+    #[serde(default)]
+    pub game: Option<String>,
+}
+
+impl CkanConfig {
+    /// Where CKAN keeps its settings on this device, which is
+    /// `CKAN/config.json` in the user's local application data.
+    pub fn default_path() -> Option<PathBuf> {
+        let dirs = BaseDirs::new()?;
+
+        // Outside Windows, CKAN runs on Mono, which puts local application
+        // data in the XDG location on macOS too.
+        let data_dir = if cfg!(target_os = "macos") {
+            dirs.home_dir().join(".local").join("share")
+        } else {
+            dirs.data_local_dir().to_owned()
+        };
+
+        Some(data_dir.join("CKAN").join("config.json"))
+    }
+
+    /// Reads CKAN's settings from where it keeps them on this device.
+    pub fn load_default() -> Result<Self, RegistryError> {
+        Self::load(&Self::default_path().ok_or(RegistryError::NoConfig)?)
+    }
+
+    /// Reads CKAN's settings from a `config.json` file.
+    #[instrument]
+    pub fn load(path: &Path) -> Result<Self, RegistryError> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(source) => {
+                return Err(RegistryError::ReadConfig {
+                    path: path.to_owned(),
+                    source,
+                });
+            }
+        };
+
+        serde_json::from_slice(&data).map_err(|source| RegistryError::ParseConfig {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// The game directories CKAN manages, in the order it lists them.
+    pub fn instances(&self) -> &[CkanInstance] {
+        &self.game_instances
+    }
+
+    /// The repositories used by every game directory CKAN manages, ordered
+    /// like [`CkanRegistry::repositories`]. If several directories have a
+    /// repository with the same name, the first one's is used.
+    ///
+    /// CKAN doesn't forget directories which have been deleted, so those whose
+    /// registry can't be read are skipped.
+    pub fn repositories(&self) -> Vec<RepositoryRef<'static>> {
+        let mut repos = BTreeMap::new();
+
+        for instance in &self.game_instances {
+            let registry = match CkanRegistry::load(Path::new(&instance.path)) {
+                Ok(registry) => registry,
+                Err(error) => {
+                    warn!(instance.name, %error, "Skipping a game directory");
+                    continue;
+                }
+            };
+
+            for repo in registry.repositories() {
+                if let Entry::Vacant(entry) = repos.entry(repo.name.clone().into_owned()) {
+                    entry.insert(repo);
+                }
+            }
+        }
+
+        let mut repos = repos.into_values().collect::<Vec<_>>();
+        sort_repos(&mut repos);
+        repos
+    }
+}
+
+/// A repository which was imported from CKAN.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ImportedRepo {
+    pub name: String,
+    pub url: Url,
+    pub priority: i32,
+    pub outcome: RepoImport,
+}
+
+/// What importing a repository did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum RepoImport {
+    /// The repository was added.
+    Added,
+    /// A repository with the same name and URL was already in use, so only
+    /// its priority was changed to CKAN's.
+    AlreadyAdded,
+    /// A repository with the same name but a different URL is already in use,
+    /// so it was left alone.
+    NameTaken,
+}
+
+impl<T: DerefMut<Target = SqliteConnection>> RepoDB<T> {
+    /// Adds the repositories imported from CKAN, keeping their priorities.
+    ///
+    /// Repositories which are already in use aren't replaced, so their
+    /// releases are kept. URLs are compared by their canonical form.
+    pub fn import_ckan_repos(
+        &mut self,
+        repos: Vec<RepositoryRef<'static>>,
+    ) -> Result<Vec<ImportedRepo>> {
+        self.transaction(|mut db| {
+            let existing = db.all_repos(false)?;
+            let mut imported = Vec::with_capacity(repos.len());
+
+            for repo in repos {
+                let current = existing.iter().find(|r| r.name == repo.name);
+                let outcome = match current {
+                    None => {
+                        db.create_empty_repo(repo.clone())?;
+                        RepoImport::Added
+                    }
+                    Some(current) if canonical_url(&current.url) == canonical_url(&repo.url) => {
+                        db.set_repo_priority(current.id, repo.priority)?;
+                        RepoImport::AlreadyAdded
+                    }
+                    Some(_) => RepoImport::NameTaken,
+                };
+                debug!(name = &*repo.name, ?outcome, "Importing a repository");
+
+                imported.push(ImportedRepo {
+                    name: repo.name.into_owned(),
+                    url: repo.url.into_owned(),
+                    priority: repo.priority,
+                    outcome,
+                });
+            }
+
+            Ok(imported)
+        })
+    }
+
+    /// Records every module in a CKAN registry as installed, replacing the
+    /// records of any which were already installed. Returns the imported
+    /// modules, ordered by identifier.
+    pub fn import_ckan_registry(&mut self, registry: CkanRegistry) -> Result<Vec<ImportedModule>> {
+        let modules = registry.installed_modules();
+
+        self.transaction(|mut db| {
+            let mut imported = Vec::with_capacity(modules.len());
+            for module in &modules {
+                let release = db.find_release(&module.slug, &module.version)?;
+                debug!(module.slug, module.version, ?release, "Importing a module");
+
+                imported.push(ImportedModule {
+                    slug: module.slug.clone(),
+                    version: module.version.clone(),
+                    release,
+                });
+            }
+
+            db.record_installed(modules)?;
+            Ok(imported)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{from_value, json};
+
+    use super::*;
+    use crate::repo::test_support::{db_with_default_repo, release};
+
+    #[test]
+    fn imports_registry() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (_, release_id) = db
+            .create_release(&release("Parallax", "2.0.1").build(), repo.id, None)
+            .unwrap();
+
+        let registry: CkanRegistry = from_value(json!({
+            "registry_version": 3,
+            "sorted_repositories": {},
+            "installed_modules": {
+                "Parallax": {
+                    "source_module": { "identifier": "Parallax", "version": "2.0.1" },
+                    "install_time": "2024-03-01T12:30:00.1234567+01:00",
+                    "auto_installed": false,
+                    "installed_files": {
+                        "GameData/Parallax/Parallax.dll": { "sha1_sum": "abc" },
+                        "GameData/Parallax": { "sha1_sum": null },
+                    },
+                },
+                "ModuleManager": {
+                    "source_module": { "identifier": "ModuleManager", "version": "4.2.3" },
+                    "install_time": "2024-03-01T12:30:00",
+                    "auto_installed": true,
+                    "installed_files": ["GameData/ModuleManager.4.2.3.dll"],
+                },
+            },
+        }))
+        .unwrap();
+
+        let imported = db.import_ckan_registry(registry).unwrap();
+        assert_eq!(
+            imported,
+            [
+                ImportedModule {
+                    slug: "ModuleManager".into(),
+                    version: "4.2.3".into(),
+                    release: None,
+                },
+                ImportedModule {
+                    slug: "Parallax".into(),
+                    version: "2.0.1".into(),
+                    release: Some(release_id),
+                },
+            ]
+        );
+
+        let installed = db.installed_modules().unwrap();
+        assert!(installed[0].auto_installed);
+        assert_eq!(installed[0].installed_at, None);
+        assert_eq!(
+            installed[1].files,
+            ["GameData/Parallax", "GameData/Parallax/Parallax.dll"]
+        );
+        assert_eq!(
+            installed[1].installed_at.unwrap().unix_timestamp(),
+            1709292600
+        );
+    }
+
+    #[test]
+    fn imports_repositories() {
+        let (mut db, default) = db_with_default_repo();
+        db.create_empty_repo(RepositoryRef::new(
+            "KSP-SpaceDock".into(),
+            Url::parse("https://example.com/spacedock.tar.gz").unwrap(),
+        ))
+        .unwrap();
+
+        let registry: CkanRegistry = from_value(json!({
+            "registry_version": 3,
+            "sorted_repositories": {
+                "KSP-default": {
+                    "name": "KSP-default",
+                    "uri": "https://github.com/KSP-CKAN/CKAN-meta/archive/master.tar.gz",
+                    "priority": 2,
+                },
+                "KSP-SpaceDock": {
+                    "name": "KSP-SpaceDock",
+                    "uri": "https://spacedock.info/ckan.tar.gz",
+                    "priority": 1,
+                },
+                "Tantares": {
+                    "name": "Tantares",
+                    "uri": "https://example.com/tantares.tar.gz",
+                    "priority": 1,
+                    "x_comment": "Beta builds",
+                },
+            },
+            "installed_modules": {},
+        }))
+        .unwrap();
+
+        let repos = registry.repositories();
+        let names = repos.iter().map(|r| &*r.name).collect::<Vec<_>>();
+        assert_eq!(names, ["KSP-SpaceDock", "Tantares", "KSP-default"]);
+
+        let imported = db.import_ckan_repos(repos).unwrap();
+        let outcomes = imported.iter().map(|r| r.outcome).collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [
+                RepoImport::NameTaken,
+                RepoImport::Added,
+                RepoImport::AlreadyAdded
+            ]
+        );
+
+        let repos = db.all_repos(false).unwrap();
+        assert_eq!(repos.len(), 3);
+        let default = repos.iter().find(|r| r.id == default.id).unwrap();
+        assert_eq!(default.priority, 2);
+        let spacedock = repos.iter().find(|r| r.name == "KSP-SpaceDock").unwrap();
+        assert_eq!(spacedock.url.host_str(), Some("example.com"));
+        let tantares = repos.iter().find(|r| r.name == "Tantares").unwrap();
+        assert_eq!(tantares.x_comment.as_deref(), Some("Beta builds"));
+    }
+
+    #[test]
+    fn reads_ckan_config() {
+        let config: CkanConfig = from_value(json!({
+            "AutoStartInstance": "Main",
+            "GameInstances": [
+                { "Name": "Main", "Path": "/nonexistent/KSP", "Game": "KSP" },
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(
+            config.instances(),
+            [CkanInstance {
+                name: "Main".into(),
+                path: "/nonexistent/KSP".into(),
+                game: Some("KSP".into()),
+            }]
+        );
+        // Directories which can't be read are skipped.
+        assert!(config.repositories().is_empty());
+    }
+}
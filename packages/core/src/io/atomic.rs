@@ -0,0 +1,395 @@
+//! Writing files so that an interrupted write never leaves a half-written
+//! file behind.
+//!
+//! Data is written to a temporary file next to its destination, which then
+//! replaces the destination with a rename. Renames within a directory are
+//! atomic, so readers see either the old file or the new one.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tracing::{debug, warn};
+
+/// When written data is flushed to the disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the operating system. After a power cut, a replaced file
+    /// may be empty.
+    Never,
+    /// Flush each file before it replaces the old one, so that after a power
+    /// cut the destination holds either the old contents or the new ones.
+    #[default]
+    Files,
+    /// Also flush the directory after each rename, so that the new contents
+    /// are kept after a power cut. Only supported on Unix.
+    FilesAndDirectories,
+}
+
+impl SyncPolicy {
+    fn sync_file(self, file: &File) -> io::Result<()> {
+        match self {
+            Self::Never => Ok(()),
+            Self::Files | Self::FilesAndDirectories => file.sync_all(),
+        }
+    }
+
+    fn sync_dir(self, dir: &Path) -> io::Result<()> {
+        if self != Self::FilesAndDirectories {
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = dir;
+
+        Ok(())
+    }
+}
+
+/// Replaces the contents of `path` with `data`.
+pub fn atomic_write(path: &Path, data: &[u8], sync: SyncPolicy) -> io::Result<()> {
+    atomic_write_with(path, sync, |file| file.write_all(data))
+}
+
+/// Replaces the contents of `path` with whatever `write` writes to the given
+/// file. If `write` fails, `path` is left untouched.
+///
+/// An existing file's permissions are kept.
+pub fn atomic_write_with(
+    path: &Path,
+    sync: SyncPolicy,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let dir = parent_dir(path);
+    let (temp_path, mut file) = create_temp_file(dir, path)?;
+
+    let result = (|| {
+        write(&mut file)?;
+        file.flush()?;
+
+        match fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        sync.sync_file(&file)?;
+        drop(file);
+
+        fs::rename(&temp_path, path)?;
+        sync.sync_dir(dir)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// The directory holding `path`, which is the current directory for bare file
+/// names.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Creates a new, uniquely-named file in `dir` to hold the next contents of
+/// `target`.
+fn create_temp_file(dir: &Path, target: &Path) -> io::Result<(PathBuf, File)> {
+    let name = target
+        .file_name()
+        .unwrap_or(target.as_os_str())
+        .to_string_lossy();
+
+    create_unique(dir, &format!(".{name}"), |path| {
+        OpenOptions::new().write(true).create_new(true).open(path)
+    })
+}
+
+/// Calls `create` with new paths in `dir` starting with `prefix` until it
+/// doesn't fail because the path already exists.
+fn create_unique<T>(
+    dir: &Path,
+    prefix: &str,
+    create: impl Fn(&Path) -> io::Result<T>,
+) -> io::Result<(PathBuf, T)> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    loop {
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{prefix}.{}-{id}.tmp", process::id()));
+
+        match create(&path) {
+            Ok(created) => return Ok((path, created)),
+            // Left behind by an earlier process with the same ID.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A set of files to be written to a directory all at once.
+///
+/// Files are written to a staging folder inside the directory, and only moved
+/// into place by [`Self::commit`]. If the transaction is dropped without being
+/// committed, the directory is left untouched.
+#[derive(Debug)]
+pub struct DirTransaction {
+    root: PathBuf,
+    staging: PathBuf,
+    /// Paths relative to `root`, in the order they were staged.
+    staged: Vec<PathBuf>,
+    sync: SyncPolicy,
+}
+
+impl DirTransaction {
+    /// Starts a transaction which writes to `root`. The directory must
+    /// already exist.
+    pub fn begin(root: impl Into<PathBuf>, sync: SyncPolicy) -> io::Result<Self> {
+        let root = root.into();
+        let (staging, ()) = create_unique(&root, ".camrete-staging", |path| fs::create_dir(path))?;
+
+        Ok(Self {
+            root,
+            staging,
+            staged: vec![],
+            sync,
+        })
+    }
+
+    /// Stages a file at `path`, relative to the root directory.
+    pub fn write(&mut self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.write_with(path, |file| file.write_all(data))
+    }
+
+    /// Stages a file at `path` holding whatever `write` writes to it. Staging
+    /// the same path twice replaces the first file.
+    pub fn write_with(
+        &mut self,
+        path: &Path,
+        write: impl FnOnce(&mut File) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let path = relative_path(path)?;
+        let staged = self.staging.join("new").join(&path);
+        fs::create_dir_all(parent_dir(&staged))?;
+
+        let mut file = File::create(&staged)?;
+        write(&mut file)?;
+        file.flush()?;
+        self.sync.sync_file(&file)?;
+
+        if !self.staged.contains(&path) {
+            self.staged.push(path);
+        }
+        Ok(())
+    }
+
+    /// Moves every staged file into place. If any of them can't be moved, the
+    /// files which were already moved are put back the way they were, although
+    /// any new folders are kept.
+    pub fn commit(self) -> io::Result<()> {
+        let mut moved = vec![];
+
+        for path in &self.staged {
+            if let Err(e) = self.move_into_place(path) {
+                warn!(?path, "Failed to commit file, rolling back: {e}");
+                for path in moved.into_iter().rev() {
+                    if let Err(e) = self.roll_back(path) {
+                        warn!(?path, "Failed to roll back file: {e}");
+                    }
+                }
+                return Err(e);
+            }
+            moved.push(path);
+        }
+
+        debug!(root = ?self.root, files = self.staged.len(), "Committed files");
+        Ok(())
+    }
+
+    fn move_into_place(&self, path: &Path) -> io::Result<()> {
+        let target = self.root.join(path);
+        let dir = parent_dir(&target);
+        fs::create_dir_all(dir)?;
+
+        match fs::symlink_metadata(&target) {
+            Ok(_) => {
+                let backup = self.staging.join("old").join(path);
+                fs::create_dir_all(parent_dir(&backup))?;
+                fs::rename(&target, backup)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        fs::rename(self.staging.join("new").join(path), &target)?;
+        self.sync.sync_dir(dir)
+    }
+
+    fn roll_back(&self, path: &Path) -> io::Result<()> {
+        let target = self.root.join(path);
+        let backup = self.staging.join("old").join(path);
+
+        fs::remove_file(&target)?;
+        if backup.exists() {
+            fs::rename(backup, target)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DirTransaction {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.staging) {
+            warn!(staging = ?self.staging, "Failed to remove staging folder: {e}");
+        }
+    }
+}
+
+/// Checks that `path` names a file inside the transaction's root directory.
+fn relative_path(path: &Path) -> io::Result<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => relative.push(part),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is outside of the directory", path.display()),
+                ));
+            }
+        }
+    }
+
+    if relative.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a file name is required",
+        ));
+    }
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("camrete-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn failed_write_keeps_old_contents() {
+        let dir = temp_dir("atomic-write");
+        let path = dir.join("settings.cfg");
+
+        atomic_write(&path, b"old", SyncPolicy::Files).unwrap();
+        let result = atomic_write_with(&path, SyncPolicy::Files, |file| {
+            file.write_all(b"half")?;
+            Err(io::Error::other("disk full"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(entries(&dir), ["settings.cfg"]);
+
+        atomic_write(&path, b"new", SyncPolicy::FilesAndDirectories).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("atomic-permissions");
+        let path = dir.join("run.sh");
+
+        atomic_write(&path, b"old", SyncPolicy::Never).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        atomic_write(&path, b"new", SyncPolicy::Never).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transaction_commits_all_or_nothing() {
+        let dir = temp_dir("dir-transaction");
+        fs::write(dir.join("kept.cfg"), "old").unwrap();
+
+        let mut transaction = DirTransaction::begin(&dir, SyncPolicy::Files).unwrap();
+        transaction.write(Path::new("kept.cfg"), b"new").unwrap();
+        transaction
+            .write(Path::new("GameData/Parallax/Parallax.dll"), b"dll")
+            .unwrap();
+        assert!(transaction.write(Path::new("../escape.cfg"), b"").is_err());
+        drop(transaction);
+
+        assert_eq!(entries(&dir), ["kept.cfg"]);
+        assert_eq!(fs::read(dir.join("kept.cfg")).unwrap(), b"old");
+
+        let mut transaction = DirTransaction::begin(&dir, SyncPolicy::Files).unwrap();
+        transaction.write(Path::new("kept.cfg"), b"new").unwrap();
+        transaction
+            .write(Path::new("GameData/Parallax/Parallax.dll"), b"dll")
+            .unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(entries(&dir), ["GameData", "kept.cfg"]);
+        assert_eq!(fs::read(dir.join("kept.cfg")).unwrap(), b"new");
+        let dll = dir.join("GameData/Parallax/Parallax.dll");
+        assert_eq!(fs::read(dll).unwrap(), b"dll");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_rolls_back() {
+        let dir = temp_dir("dir-rollback");
+        fs::write(dir.join("a.cfg"), "old").unwrap();
+        // A file where a folder is needed can't be replaced by the commit.
+        fs::write(dir.join("blocked"), "").unwrap();
+
+        let mut transaction = DirTransaction::begin(&dir, SyncPolicy::Never).unwrap();
+        transaction.write(Path::new("a.cfg"), b"new").unwrap();
+        transaction.write(Path::new("b.cfg"), b"new").unwrap();
+        transaction
+            .write(Path::new("blocked/c.cfg"), b"new")
+            .unwrap();
+        assert!(transaction.commit().is_err());
+
+        assert_eq!(entries(&dir), ["a.cfg", "blocked"]);
+        assert_eq!(fs::read(dir.join("a.cfg")).unwrap(), b"old");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Async readers used while downloading repositories.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use pin_project::pin_project;
+use tokio::{
+    io::{AsyncBufRead, AsyncRead, AsyncReadExt as _, ReadBuf},
+    spawn,
+    sync::mpsc,
+};
+
+/// The size of each chunk read by a [`ReadAhead`].
+const READ_AHEAD_CHUNK_LEN: usize = 64 * 1024;
+
+/// How many chunks a [`ReadAhead`] will read before they're consumed.
+const READ_AHEAD_CHUNKS: usize = 16;
+
+pub trait AsyncReadExt: Sized {
+    fn progress<F>(self, f: F) -> ProgressReader<Self, F>
+    where
+        F: FnMut(u64);
+
+    /// Reads from this reader on a separate task, staying up to a fixed number
+    /// of bytes ahead of the returned reader. This lets expensive readers such
+    /// as decompressors run in parallel with whatever consumes their output.
+    ///
+    /// Must be called from within a Tokio runtime.
+    fn read_ahead(self) -> ReadAhead
+    where
+        Self: AsyncRead + Unpin + Send + 'static;
+}
+
+impl<T: AsyncRead> AsyncReadExt for T {
+    fn progress<F>(self, f: F) -> ProgressReader<Self, F>
+    where
+        F: FnMut(u64),
+    {
+        ProgressReader {
+            reader: self,
+            bytes_read: 0,
+            on_progress: f,
+        }
+    }
+
+    fn read_ahead(mut self) -> ReadAhead
+    where
+        Self: AsyncRead + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(READ_AHEAD_CHUNKS);
+
+        spawn(async move {
+            loop {
+                let mut chunk = BytesMut::with_capacity(READ_AHEAD_CHUNK_LEN);
+                let result = match self.read_buf(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(_) => Ok(chunk.freeze()),
+                    Err(e) => Err(e),
+                };
+
+                let failed = result.is_err();
+                // Stop early if the reader was dropped.
+                if tx.send(result).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        ReadAhead {
+            rx,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+/// A reader which receives data read by a background task. Created by
+/// [`AsyncReadExt::read_ahead`].
+pub struct ReadAhead {
+    rx: mpsc::Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl AsyncRead for ReadAhead {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        self.consume(len);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncBufRead for ReadAhead {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+
+        while this.chunk.is_empty() {
+            match ready!(this.rx.poll_recv(cx)) {
+                Some(chunk) => this.chunk = chunk?,
+                // The background task has reached the end of the data.
+                None => break,
+            }
+        }
+
+        Poll::Ready(Ok(&this.chunk))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.chunk.advance(amt);
+    }
+}
+
+#[pin_project]
+pub struct ProgressReader<R, F> {
+    #[pin]
+    reader: R,
+    bytes_read: u64,
+    on_progress: F,
+}
+
+impl<R: AsyncRead, F: FnMut(u64)> AsyncRead for ProgressReader<R, F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let before = buf.filled().len();
+
+        let outcome = this.reader.poll_read(cx, buf);
+
+        let after = buf.filled().len();
+        let change = after - before;
+        if change != 0 {
+            *this.bytes_read += change as u64;
+            let bytes = *this.bytes_read;
+            (this.on_progress)(bytes);
+        }
+
+        outcome
+    }
+}
+
+impl<R: AsyncBufRead, F: FnMut(u64)> AsyncBufRead for ProgressReader<R, F> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.project().reader.poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.project();
+        *this.bytes_read += amt as u64;
+
+        let bytes = *this.bytes_read;
+        (this.on_progress)(bytes);
+
+        this.reader.consume(amt)
+    }
+}
@@ -1,6 +1,11 @@
 //! Adapter structs for reading JSON-based NetKAN archives.
 
+mod author;
 pub mod game_version;
+mod identifier;
+mod legacy;
+mod lenient_url;
+mod limits;
 mod one_or_many;
 pub mod spec_version;
 
@@ -9,7 +14,7 @@ use std::{borrow::Cow, collections::HashMap};
 use derive_more::TryFrom;
 use game_version::MetaGameVersion;
 use miette::Diagnostic;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use spec_version::SpecVersion;
 use thiserror::Error;
 use time::{OffsetDateTime, serde::iso8601};
@@ -20,6 +25,10 @@ use crate::{
     repo::game::GameVersion,
 };
 
+pub use author::AuthorCredit;
+pub use identifier::{Identifier, IdentifierError};
+pub use limits::{ParseLimitError, ParseLimits};
+
 #[derive(Debug, Error, Diagnostic)]
 pub enum JsonError {
     #[error(
@@ -28,18 +37,49 @@ pub enum JsonError {
         if *specific_is_max { "ksp_version_max" } else { "ksp_version_min" },
         specific_constraint,
     )]
-    #[diagnostic(code(camrete::json::duplicate_module_version_constraint))]
+    #[diagnostic(code(camrete::json::duplicate_module_version_constraint), severity(Warning))]
     DuplicateVersionConstraint {
         generic_constraint: GameVersion,
         specific_is_max: bool,
         specific_constraint: GameVersion,
     },
+    #[error("The module is missing its `abstract`.")]
+    #[diagnostic(code(camrete::json::missing_abstract), severity(Warning))]
+    MissingAbstract,
     #[error("The module incorrectly specifies `max_version` in its `replaced_by` relationship.")]
-    #[diagnostic(code(camrete::json::disallowed_replaced_by_max_version))]
+    #[diagnostic(code(camrete::json::disallowed_replaced_by_max_version), severity(Warning))]
     DisallowedMaxVersionInReplacement,
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Limit(#[from] ParseLimitError),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Identifier(#[from] IdentifierError),
     #[diagnostic(code(camrete::json::parse))]
     #[error(transparent)]
     Parse(#[from] serde_json::Error),
+    #[cfg(feature = "simd-json")]
+    #[diagnostic(code(camrete::json::parse))]
+    #[error(transparent)]
+    SimdParse(#[from] simd_json::Error),
+}
+
+/// Deserializes a JSON document from a repository archive.
+///
+/// With the `simd-json` feature enabled, documents are parsed using SIMD
+/// instructions. simd-json parses in place, so the data is copied into a
+/// scratch buffer first.
+pub fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, JsonError> {
+    #[cfg(feature = "simd-json")]
+    {
+        let mut scratch = data.to_vec();
+        Ok(simd_json::serde::from_slice(&mut scratch)?)
+    }
+
+    #[cfg(not(feature = "simd-json"))]
+    {
+        Ok(serde_json::from_slice(data)?)
+    }
 }
 
 /// A full complete release of a module, suitable for encoding into JSON.
@@ -47,8 +87,9 @@ pub enum JsonError {
 pub struct JsonModule {
     pub spec_version: SpecVersion,
     pub name: String,
-    pub identifier: String,
+    pub identifier: Identifier,
     pub version: String,
+    #[serde(default)]
     pub r#abstract: String,
     #[serde(with = "one_or_many")]
     pub author: Vec<String>,
@@ -99,6 +140,10 @@ pub struct JsonModule {
     pub supports: Vec<MetaRelationship>,
     #[serde(default)]
     pub conflicts: Vec<MetaRelationship>,
+    /// Virtual identifiers which this module can stand in for when another
+    /// module depends on them.
+    #[serde(default)]
+    pub provides: Vec<String>,
     #[serde(default)]
     pub replaced_by: Option<DirectRelationshipDescriptor>,
     #[serde(default)]
@@ -109,6 +154,10 @@ pub struct JsonModule {
 
 impl JsonModule {
     pub fn verify(&self) -> Result<(), JsonError> {
+        if self.r#abstract.is_empty() {
+            return Err(JsonError::MissingAbstract);
+        }
+
         if !self.ksp_version.is_empty() {
             let has_max = !self.ksp_version_max.is_empty();
             if has_max || !self.ksp_version_min.is_empty() {
@@ -162,7 +211,10 @@ impl JsonModule {
     }
 }
 
-#[derive(Debug, Deserialize, Default, TryFrom, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, uniffi::Enum)]
+#[derive(
+    Debug, Serialize, Deserialize, Default, TryFrom, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+    uniffi::Enum,
+)]
 #[serde(rename_all = "lowercase")]
 #[try_from(repr)]
 #[repr(i32)]
@@ -181,13 +233,18 @@ impl From<ModuleKind> for i32 {
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone, uniffi::Record)]
 pub struct ModuleResources {
-    pub homepage: Option<String>,
-    pub spacedock: Option<String>,
-    pub repository: Option<String>,
-    pub bugtracker: Option<String>,
-    #[serde(rename = "remote-avc")]
-    pub remote_avc: Option<String>,
-    pub x_screenshot: Option<String>,
+    #[serde(default, deserialize_with = "lenient_url::deserialize")]
+    pub homepage: Option<Url>,
+    #[serde(default, deserialize_with = "lenient_url::deserialize")]
+    pub spacedock: Option<Url>,
+    #[serde(default, deserialize_with = "lenient_url::deserialize")]
+    pub repository: Option<Url>,
+    #[serde(default, deserialize_with = "lenient_url::deserialize")]
+    pub bugtracker: Option<Url>,
+    #[serde(rename = "remote-avc", default, deserialize_with = "lenient_url::deserialize")]
+    pub remote_avc: Option<Url>,
+    #[serde(default, deserialize_with = "lenient_url::deserialize")]
+    pub x_screenshot: Option<Url>,
 }
 
 #[derive(Debug, Serialize, Deserialize, uniffi::Record)]
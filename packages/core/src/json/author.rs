@@ -0,0 +1,97 @@
+//! Author names as they're written in module metadata.
+//!
+//! The spec expects each author to be a plain name, but some are credited
+//! with a role, like `Linx (maintainer)`, or with markup, like a Markdown link
+//! to their profile. Each is split into a name and a role, so that a person is
+//! the same author however they're credited on each release.
+
+use std::sync::LazyLock;
+
+use diesel::Queryable;
+use regex::Regex;
+
+/// Markdown links, which are replaced by their text.
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[([^\]]*)\]\([^)]*\)").expect("pattern is valid"));
+/// HTML tags and Markdown emphasis, which are removed.
+static MARKUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<[^>]*>|\*\*|__").expect("pattern is valid"));
+
+/// An author as they're credited on a release.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, uniffi::Record)]
+pub struct AuthorCredit {
+    pub name: String,
+    /// What the author did, like `maintainer` or `original author`.
+    pub role: Option<String>,
+}
+
+impl AuthorCredit {
+    /// Splits an author string into a name and role. Markup is removed,
+    /// whitespace is collapsed, and a role in parentheses or brackets at the
+    /// end is taken off the name. A string which is only a role is kept
+    /// whole, as a name.
+    pub fn parse(author: &str) -> Self {
+        let author = LINK.replace_all(author, "$1");
+        let author = MARKUP.replace_all(&author, "");
+        let author = author.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        match split_role(&author) {
+            Some((name, role)) => Self {
+                name: name.into(),
+                role: Some(role.into()),
+            },
+            None => Self {
+                name: author,
+                role: None,
+            },
+        }
+    }
+}
+
+fn split_role(author: &str) -> Option<(&str, &str)> {
+    let open = match author.chars().last()? {
+        ')' => '(',
+        ']' => '[',
+        _ => return None,
+    };
+    let start = author.rfind(open)?;
+
+    let name = author[..start].trim_end_matches([' ', ',', '-']);
+    let role = author[start + 1..author.len() - 1].trim();
+    (!name.is_empty() && !role.is_empty()).then_some((name, role))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn credit(name: &str, role: Option<&str>) -> AuthorCredit {
+        AuthorCredit {
+            name: name.into(),
+            role: role.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn splits_roles_from_names() {
+        assert_eq!(AuthorCredit::parse("Linx"), credit("Linx", None));
+        assert_eq!(
+            AuthorCredit::parse("  Linx   (maintainer) "),
+            credit("Linx", Some("maintainer"))
+        );
+        assert_eq!(
+            AuthorCredit::parse("Linx - [original author]"),
+            credit("Linx", Some("original author"))
+        );
+        assert_eq!(
+            AuthorCredit::parse("[**Linx**](https://github.com/Linx) (maintainer)"),
+            credit("Linx", Some("maintainer"))
+        );
+        // Only the last parentheses hold a role.
+        assert_eq!(
+            AuthorCredit::parse("Linx (the Lynx) (adopter)"),
+            credit("Linx (the Lynx)", Some("adopter"))
+        );
+        assert_eq!(AuthorCredit::parse("(unknown)"), credit("(unknown)", None));
+    }
+}
@@ -0,0 +1,213 @@
+//! Module identifiers.
+//!
+//! The spec requires identifiers to match `^[A-Za-z0-9][A-Za-z0-9-]+$`, which
+//! keeps them usable as file names and command line arguments.
+
+use std::{
+    borrow::Borrow,
+    fmt::{self, Formatter},
+    ops::Deref,
+    str::FromStr,
+};
+
+use diesel::{
+    expression::AsExpression,
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    sqlite::Sqlite,
+};
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The unique name of a module, such as `ModuleManager`.
+///
+/// Identifiers are case-sensitive. Surrounding whitespace is trimmed when one
+/// is parsed, but anything else outside the allowed characters is rejected.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, AsExpression,
+)]
+#[serde(try_from = "String", into = "String")]
+#[diesel(sql_type = Text)]
+pub struct Identifier(String);
+
+uniffi::custom_type!(Identifier, String, {
+    lower: |id| id.0,
+    try_lift: |s| Ok(Identifier::new(s)?),
+});
+
+#[derive(Debug, Error, Diagnostic, PartialEq, Eq)]
+pub enum IdentifierError {
+    #[error("{identifier:?} is too short to be a module identifier")]
+    #[diagnostic(
+        code(camrete::json::identifier_too_short),
+        help("identifiers are at least two characters long")
+    )]
+    TooShort { identifier: String },
+    #[error("{identifier:?} is not a module identifier (it can't start with {character:?})")]
+    #[diagnostic(
+        code(camrete::json::invalid_identifier_start),
+        help("identifiers start with a letter or digit")
+    )]
+    InvalidStart { identifier: String, character: char },
+    #[error("{identifier:?} is not a module identifier (it can't contain {character:?})")]
+    #[diagnostic(
+        code(camrete::json::invalid_identifier_character),
+        help("identifiers only contain letters, digits, and hyphens")
+    )]
+    InvalidCharacter { identifier: String, character: char },
+}
+
+impl Identifier {
+    /// Validates an identifier, after trimming any surrounding whitespace.
+    pub fn new(identifier: impl Into<String>) -> Result<Self, IdentifierError> {
+        let mut identifier = identifier.into();
+        let trimmed = identifier.trim();
+        if trimmed.len() != identifier.len() {
+            identifier = trimmed.to_owned();
+        }
+
+        let mut chars = identifier.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphanumeric() => {}
+            Some(character) => {
+                return Err(IdentifierError::InvalidStart {
+                    identifier,
+                    character,
+                });
+            }
+            None => return Err(IdentifierError::TooShort { identifier }),
+        }
+
+        if let Some(character) = chars
+            .clone()
+            .find(|&c| !c.is_ascii_alphanumeric() && c != '-')
+        {
+            return Err(IdentifierError::InvalidCharacter {
+                identifier,
+                character,
+            });
+        }
+        if chars.next().is_none() {
+            return Err(IdentifierError::TooShort { identifier });
+        }
+
+        Ok(Self(identifier))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Identifier {
+    type Err = IdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for Identifier {
+    type Error = IdentifierError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<Identifier> for String {
+    fn from(value: Identifier) -> Self {
+        value.0
+    }
+}
+
+impl Deref for Identifier {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Identifier {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Identifier {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Identifier {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Identifier {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl ToSql<Text, Sqlite> for Identifier {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(&self.0, out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{from_value, json};
+
+    use super::{Identifier, IdentifierError};
+
+    #[test]
+    fn accepts_spec_identifiers() {
+        for id in ["ModuleManager", "KSP-Recall", "000Toolbar", "B9"] {
+            assert_eq!(Identifier::new(id).unwrap(), id);
+        }
+        assert_eq!(Identifier::new(" Scatterer\n").unwrap(), "Scatterer");
+    }
+
+    #[test]
+    fn rejects_invalid_identifiers() {
+        assert_eq!(
+            Identifier::new("X"),
+            Err(IdentifierError::TooShort {
+                identifier: "X".into()
+            })
+        );
+        assert_eq!(
+            Identifier::new("  "),
+            Err(IdentifierError::TooShort {
+                identifier: "".into()
+            })
+        );
+        assert_eq!(
+            Identifier::new("-Parallax"),
+            Err(IdentifierError::InvalidStart {
+                identifier: "-Parallax".into(),
+                character: '-',
+            })
+        );
+        assert_eq!(
+            Identifier::new("Real_Solar_System"),
+            Err(IdentifierError::InvalidCharacter {
+                identifier: "Real_Solar_System".into(),
+                character: '_',
+            })
+        );
+        assert!(from_value::<Identifier>(json!("Kopernicus 2")).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Compatibility with metadata written for older versions of the spec.
+//!
+//! Old documents are still served by CKAN repositories, and some of them break
+//! rules which were only added or enforced later. Rather than rejecting them,
+//! they're rewritten the way CKAN interpreted them at the time.
+
+use tracing::debug;
+
+use crate::json::{JsonModule, MetaGameVersion, spec_version::SpecVersion};
+
+impl JsonModule {
+    /// Rewrites quirks of older spec versions into their modern equivalents.
+    /// This should be done before the module is verified.
+    pub fn normalize_legacy(&mut self) {
+        if self.spec_version > SpecVersion::V1 {
+            return;
+        }
+
+        // Some v1 documents don't have an abstract. The name is the next best thing
+        // to show in its place.
+        if self.r#abstract.is_empty() {
+            self.r#abstract.clone_from(&self.name);
+        }
+
+        // Before `ksp_version_min` and `ksp_version_max` were validated, documents
+        // could set them alongside `ksp_version`, which took priority.
+        if !self.ksp_version.is_empty()
+            && (!self.ksp_version_min.is_empty() || !self.ksp_version_max.is_empty())
+        {
+            debug!(
+                identifier = ?self.identifier,
+                version = ?self.version,
+                "Ignoring game version bounds which conflict with `ksp_version`"
+            );
+            self.ksp_version_min = MetaGameVersion::default();
+            self.ksp_version_max = MetaGameVersion::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, from_str, from_value};
+
+    use crate::json::{JsonError, JsonModule};
+
+    const TWEAKSCALE_1_44: &str = include_str!("fixtures/TweakScale-v1.44.ckan");
+    const TWEAKSCALE_1_52_1: &str = include_str!("fixtures/TweakScale-v1.52.1.ckan");
+
+    fn parse(document: Value) -> Result<JsonModule, JsonError> {
+        let mut module: JsonModule = from_value(document)?;
+        module.normalize_legacy();
+        module.verify()?;
+        Ok(module)
+    }
+
+    #[test]
+    fn historical_documents_parse() {
+        for document in [TWEAKSCALE_1_44, TWEAKSCALE_1_52_1] {
+            let module = parse(from_str(document).unwrap()).unwrap();
+            assert_eq!(module.identifier, "TweakScale");
+            assert_eq!(module.license.len(), 1);
+            assert_eq!(module.download.len(), 1);
+        }
+    }
+
+    #[test]
+    fn missing_abstract_uses_name() {
+        let mut document: Value = from_str(TWEAKSCALE_1_52_1).unwrap();
+        document.as_object_mut().unwrap().remove("abstract");
+
+        let module = parse(document.clone()).unwrap();
+        assert_eq!(module.r#abstract, "TweakScale - Rescale Everything!");
+
+        // Newer documents have always needed an abstract.
+        document["spec_version"] = "v1.4".into();
+        assert!(matches!(parse(document), Err(JsonError::MissingAbstract)));
+    }
+
+    #[test]
+    fn ksp_version_overrides_bounds() {
+        let mut document: Value = from_str(TWEAKSCALE_1_44).unwrap();
+        document["ksp_version_max"] = "0.90".into();
+
+        let module = parse(document.clone()).unwrap();
+        assert_eq!(*module.ksp_version, "0.25".parse().unwrap());
+        assert!(module.ksp_version_max.is_empty());
+
+        document["spec_version"] = "v1.4".into();
+        assert!(matches!(
+            parse(document),
+            Err(JsonError::DuplicateVersionConstraint { .. })
+        ));
+    }
+}
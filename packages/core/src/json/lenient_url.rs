@@ -0,0 +1,49 @@
+//! Deserializing method for optional links which may be malformed. Invalid
+//! links are logged and treated as missing, so that one bad link doesn't stop
+//! a whole release from loading.
+
+use serde::{Deserialize, Deserializer};
+use tracing::warn;
+use url::Url;
+
+pub fn deserialize<'a, D>(d: D) -> Result<Option<Url>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    let Some(link) = Option::<String>::deserialize(d)? else {
+        return Ok(None);
+    };
+
+    match Url::parse(&link) {
+        Ok(url) => Ok(Some(url)),
+        Err(error) => {
+            warn!(?link, %error, "Ignoring an invalid link");
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_json::{from_value, json};
+    use url::Url;
+
+    #[derive(Debug, Deserialize)]
+    struct Test {
+        #[serde(default, deserialize_with = "super::deserialize")]
+        link: Option<Url>,
+    }
+
+    #[test]
+    fn invalid_links_are_dropped() {
+        let valid: Test = from_value(json!({ "link": "https://example.com/" })).unwrap();
+        assert_eq!(valid.link.unwrap().as_str(), "https://example.com/");
+
+        let invalid: Test = from_value(json!({ "link": "example dot com" })).unwrap();
+        assert_eq!(invalid.link, None);
+
+        let missing: Test = from_value(json!({})).unwrap();
+        assert_eq!(missing.link, None);
+    }
+}
@@ -0,0 +1,161 @@
+//! Limits on the size and shape of repository documents.
+//!
+//! Repository archives come from the network, so a corrupted or malicious one
+//! could contain documents which take far too long or far too much memory to
+//! parse. Documents over these limits are rejected before they're parsed, or
+//! before they're saved.
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::json::JsonModule;
+
+/// The largest documents which are accepted from a repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The largest size of a single document, in bytes.
+    pub max_asset_size: usize,
+    /// How deeply arrays and objects can be nested inside each other.
+    pub max_depth: usize,
+    /// The most relationships a release can have, counting each member of an
+    /// `any_of` group separately.
+    pub max_relationships: usize,
+}
+
+impl Default for ParseLimits {
+    /// Limits well above anything in the official repositories, where the
+    /// largest documents, the download counts, are around a megabyte.
+    fn default() -> Self {
+        Self {
+            max_asset_size: 64 * 1024 * 1024,
+            max_depth: 64,
+            max_relationships: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+pub enum ParseLimitError {
+    #[error("the document is {size} bytes, which is over the limit of {limit} bytes")]
+    #[diagnostic(code(camrete::json::limit::asset_size), severity(Warning))]
+    AssetTooLarge { size: usize, limit: usize },
+    #[error("the document is nested more than {limit} levels deep")]
+    #[diagnostic(code(camrete::json::limit::depth), severity(Warning))]
+    TooDeep { limit: usize },
+    #[error("the release has {count} relationships, which is over the limit of {limit}")]
+    #[diagnostic(code(camrete::json::limit::relationships), severity(Warning))]
+    TooManyRelationships { count: usize, limit: usize },
+}
+
+impl ParseLimits {
+    /// Checks the size and nesting of a document before it's parsed.
+    pub fn check_document(&self, data: &[u8]) -> Result<(), ParseLimitError> {
+        if data.len() > self.max_asset_size {
+            return Err(ParseLimitError::AssetTooLarge {
+                size: data.len(),
+                limit: self.max_asset_size,
+            });
+        }
+
+        if exceeds_depth(data, self.max_depth) {
+            return Err(ParseLimitError::TooDeep {
+                limit: self.max_depth,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks a release once it's been parsed.
+    pub fn check_release(&self, release: &JsonModule) -> Result<(), ParseLimitError> {
+        let count = [
+            &release.depends,
+            &release.recommends,
+            &release.suggests,
+            &release.supports,
+            &release.conflicts,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|relationship| relationship.descriptor.flatten().len())
+        .sum::<usize>();
+
+        if count > self.max_relationships {
+            return Err(ParseLimitError::TooManyRelationships {
+                count,
+                limit: self.max_relationships,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether arrays and objects in a JSON document are nested more than
+/// `limit` levels deep. The document doesn't have to be valid; anything which
+/// isn't will fail to parse later.
+fn exceeds_depth(data: &[u8], limit: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &byte in data {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > limit {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let limits = ParseLimits {
+            max_depth: 3,
+            ..ParseLimits::default()
+        };
+
+        assert_eq!(limits.check_document(br#"{"a": [{"b": "[[[["}]}"#), Ok(()));
+        assert_eq!(
+            limits.check_document(br#"{"a": [[{"b": 1}]]}"#),
+            Err(ParseLimitError::TooDeep { limit: 3 })
+        );
+        // Brackets inside strings, even after escaped quotes, don't count.
+        assert_eq!(limits.check_document(br#"["\"[[[[", "\\"]"#), Ok(()));
+    }
+
+    #[test]
+    fn rejects_large_documents() {
+        let limits = ParseLimits {
+            max_asset_size: 8,
+            ..ParseLimits::default()
+        };
+
+        assert_eq!(
+            limits.check_document(br#"{"a": "long"}"#),
+            Err(ParseLimitError::AssetTooLarge { size: 13, limit: 8 })
+        );
+    }
+}
@@ -3,20 +3,59 @@
 //! It has a special case for v1.0, which is serialized as integer `1` instead
 //! of string `"v1.0"` like other versions would be.
 
-use std::fmt::{self, Formatter};
+use std::{
+    fmt::{self, Formatter},
+    str::FromStr,
+};
 
 use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
     de::{self, Unexpected, Visitor},
 };
+use thiserror::Error;
 
 /// The version of a CKAN metadata file.
-#[derive(Debug, PartialEq, Eq, uniffi::Record)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, uniffi::Record)]
 pub struct SpecVersion {
     pub major: u16,
     pub minor: u16,
 }
 
+impl SpecVersion {
+    /// The first version of the spec, written as `1` instead of `"v1.0"`.
+    pub const V1: Self = Self::new(1, 0);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl fmt::Display for SpecVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("{0:?} is not a spec version (expected \"vN.N\")")]
+pub struct SpecVersionParseError(String);
+
+impl FromStr for SpecVersion {
+    type Err = SpecVersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || SpecVersionParseError(s.to_owned());
+
+        let trimmed = s.strip_prefix('v').ok_or_else(err)?;
+        let (major, minor) = trimmed.split_once('.').ok_or_else(err)?;
+
+        let major: u16 = major.parse().ok().ok_or_else(err)?;
+        let minor: u16 = minor.parse().ok().ok_or_else(err)?;
+
+        Ok(SpecVersion { major, minor })
+    }
+}
+
 impl<'a> Deserialize<'a> for SpecVersion {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -46,15 +85,8 @@ impl<'a> Deserialize<'a> for SpecVersion {
             where
                 E: de::Error,
             {
-                let err = || de::Error::invalid_value(Unexpected::Str(v), &Visit);
-
-                let trimmed = v.strip_prefix('v').ok_or_else(err)?;
-                let (major, minor) = trimmed.split_once('.').ok_or_else(err)?;
-
-                let major: u16 = major.parse().ok().ok_or_else(err)?;
-                let minor: u16 = minor.parse().ok().ok_or_else(err)?;
-
-                Ok(SpecVersion { major, minor })
+                v.parse()
+                    .map_err(|_| de::Error::invalid_value(Unexpected::Str(v), &Visit))
             }
         }
 
@@ -0,0 +1,64 @@
+//! Catalogs of the messages shown by Camrete's apps, in [Fluent]'s format.
+//!
+//! The command-line app and the GUI share these catalogs, so that they use the
+//! same terminology. Each app formats the messages itself; this module only
+//! holds the catalogs and picks which one to use.
+//!
+//! [Fluent]: https://projectfluent.org
+
+/// The locale whose catalog has every message. Messages missing from another
+/// catalog should be taken from this one.
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+static CATALOGS: &[(&str, &str)] = &[("en-US", include_str!("../locales/en-US/camrete.ftl"))];
+
+/// The locales which have a catalog.
+pub fn locales() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(locale, _)| *locale)
+}
+
+/// The catalog for a locale, like `en-US`, if there is one.
+pub fn catalog(locale: &str) -> Option<&'static str> {
+    CATALOGS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(locale))
+        .map(|(_, catalog)| *catalog)
+}
+
+/// Picks the best locale with a catalog for a requested one. The requested
+/// locale can be a BCP 47 tag like `en-GB`, or a POSIX locale like
+/// `en_GB.UTF-8`. A locale with the same language is used if there isn't an
+/// exact match, and [`DEFAULT_LOCALE`] if there isn't one of those either.
+pub fn negotiate(requested: &str) -> &'static str {
+    let requested = requested
+        .split(['.', '@'])
+        .next()
+        .unwrap_or_default()
+        .replace('_', "-");
+    let language = requested.split('-').next().unwrap_or_default();
+
+    let exact = locales().find(|locale| locale.eq_ignore_ascii_case(&requested));
+    let same_language = || {
+        locales().find(|locale| {
+            let other = locale.split('-').next().unwrap_or_default();
+            other.eq_ignore_ascii_case(language)
+        })
+    };
+
+    exact.or_else(same_language).unwrap_or(DEFAULT_LOCALE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiates_locales() {
+        assert_eq!(negotiate("en-US"), "en-US");
+        assert_eq!(negotiate("en_GB.UTF-8"), "en-US");
+        assert_eq!(negotiate("EN"), "en-US");
+        assert_eq!(negotiate("C"), DEFAULT_LOCALE);
+        assert!(catalog("en-us").is_some());
+        assert!(catalog("xx").is_none());
+    }
+}
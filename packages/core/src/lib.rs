@@ -5,7 +5,7 @@ use diesel::{
     r2d2::{ConnectionManager, Pool, PooledConnection},
 };
 use directories::ProjectDirs;
-use miette::Diagnostic;
+use miette::{Diagnostic, Severity};
 use repo::client::RepoUnpackError;
 use thiserror::Error;
 use time::OffsetDateTime;
@@ -14,20 +14,32 @@ use url::Url;
 use crate::json::JsonError;
 
 pub mod database;
+pub mod export;
 mod ffi;
-mod io;
+pub mod install;
+pub mod io;
 pub mod json;
+pub mod l10n;
+pub mod markdown;
+pub mod policy;
+pub mod prelude;
+pub mod profile;
 pub mod repo;
+pub mod resolver;
 
+#[doc(hidden)]
 pub use diesel;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+#[doc(hidden)]
 pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+#[doc(hidden)]
 pub type DbConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
 
 pub static DIRS: LazyLock<ProjectDirs> =
     LazyLock::new(|| ProjectDirs::from("", "", "CKAN").expect("user home dir available"));
 
+#[cfg(feature = "network")]
 static USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
     "/",
@@ -35,6 +47,13 @@ static USER_AGENT: &str = concat!(
     " <https://github.com/lewisfm/camrete>"
 );
 
+/// Everything that can go wrong in Camrete.
+///
+/// Errors the user can do something about, like a mistyped URL, a file which
+/// can't be read, a network outage or modules which conflict, are reported with
+/// [`Severity::Warning`]. Anything else, like a failed database query,
+/// suggests a bug in Camrete and keeps the default severity of
+/// [`Severity::Error`]. See [`Error::is_user_error`].
 #[derive(Debug, Error, Diagnostic, uniffi::Error)]
 #[uniffi(flat_error)]
 pub enum Error {
@@ -49,25 +68,188 @@ pub enum Error {
     #[diagnostic(code(camrete::database::upgrade_failure))]
     DbMigrations(Box<dyn std::error::Error + Send + Sync>),
 
+    #[error("failed to upgrade the on-device CKAN database in the background")]
+    #[diagnostic(code(camrete::database::background_upgrade_failure))]
+    BackgroundUpgrade(#[source] Box<Error>),
+
+    #[error("the on-device CKAN database is damaged: {0}")]
+    #[diagnostic(code(camrete::database::corrupt))]
+    DatabaseCorrupt(String),
+
     #[error("a request to the on-device CKAN database failed")]
     #[diagnostic(code(camrete::database::request_failure))]
-    Db(#[from] diesel::result::Error),
+    Db(#[source] diesel::result::Error),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Query(#[from] database::QueryError),
 
+    #[error("the on-device CKAN database is in use by another program (tried {attempts} times)")]
+    #[diagnostic(
+        code(camrete::database::busy),
+        severity(Warning),
+        help("wait for any other running copies of CKAN or Camrete to finish, then try again")
+    )]
+    DatabaseBusy {
+        attempts: u32,
+        source: diesel::result::Error,
+    },
+
+    #[cfg(feature = "network")]
+    #[error("repository {repo:?} is already being updated by process {process_id}")]
+    #[diagnostic(
+        code(camrete::repo::update_in_progress),
+        severity(Warning),
+        help("wait for the other update to finish, then try again")
+    )]
+    UpdateAlreadyInProgress { repo: String, process_id: u32 },
+
+    #[cfg(feature = "network")]
     #[error("HTTP request failed")]
-    #[diagnostic(code(camrete::http))]
+    #[diagnostic(code(camrete::http), severity(Warning))]
     Http(#[from] reqwest::Error),
 
+    #[cfg(feature = "network")]
+    #[error("{url} responded with HTTP status {status}")]
+    #[diagnostic(code(camrete::http::status), severity(Warning))]
+    HttpStatus { url: Url, status: u16 },
+
+    #[cfg(feature = "network")]
+    #[error("{url} redirected too many times")]
+    #[diagnostic(code(camrete::http::too_many_redirects), severity(Warning))]
+    TooManyRedirects { url: Url },
+
+    #[cfg(feature = "network")]
+    #[error("{url} redirected to {location:?}, which isn't a valid URL")]
+    #[diagnostic(code(camrete::http::invalid_redirect), severity(Warning))]
+    InvalidRedirect { url: Url, location: String },
+
+    #[cfg(feature = "network")]
+    #[error("{from} redirected to {to}, which isn't encrypted")]
+    #[diagnostic(
+        code(camrete::http::insecure_redirect),
+        severity(Warning),
+        help("the server may be misconfigured, or the connection tampered with")
+    )]
+    InsecureRedirect { from: Box<Url>, to: Box<Url> },
+
+    #[error("{url:?} is not a valid URL")]
+    #[diagnostic(code(camrete::invalid_url), severity(Warning))]
+    InvalidUrl {
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    InstallPlan(#[from] install::InstallPlanError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Registry(#[from] install::RegistryError),
+
+    #[cfg(feature = "network")]
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Listing(#[from] install::ListingError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    LocalInstall(#[from] install::LocalInstallError),
+
     #[error("failed to unpack a CKAN repository")]
     #[diagnostic(transparent)]
     Network(#[from] RepoUnpackError),
 
+    #[cfg(feature = "network")]
     #[error(transparent)]
-    #[diagnostic(code(camrete::io))]
+    #[diagnostic(transparent)]
+    Download(#[from] repo::DownloadError),
+
+    #[cfg(feature = "network")]
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Refresh(#[from] repo::RefreshError),
+
+    #[cfg(feature = "network")]
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Git(#[from] repo::GitError),
+
+    #[error(transparent)]
+    #[diagnostic(code(camrete::io), severity(Warning))]
     Io(#[from] std::io::Error),
 
+    #[error("the operation was cancelled")]
+    #[diagnostic(code(camrete::cancelled), severity(Warning))]
+    Cancelled,
+
     #[error(transparent)]
     #[diagnostic(transparent)]
     Json(#[from] JsonError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Profile(#[from] profile::ProfileError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Policy(#[from] policy::PolicyError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Filter(#[from] database::FilterParseError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Channel(#[from] database::ChannelError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Cursor(#[from] database::CursorError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    JsonQuery(#[from] database::JsonQueryError),
+
+    #[cfg(feature = "sqlcipher")]
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Key(#[from] repo::KeyError),
+}
+
+impl Error {
+    /// Returns whether the operation which caused this error might succeed if
+    /// it is tried again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::DatabaseBusy { .. } => true,
+            #[cfg(feature = "network")]
+            Self::UpdateAlreadyInProgress { .. } => true,
+            Self::Query(e) => e.is_busy(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the user can fix this error or wait it out, rather
+    /// than it being a bug in Camrete. Only errors which aren't the user's
+    /// are worth reporting as bugs.
+    pub fn is_user_error(&self) -> bool {
+        self.severity() == Some(Severity::Warning)
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(value: diesel::result::Error) -> Self {
+        if database::is_busy(&value) {
+            Self::DatabaseBusy {
+                attempts: 1,
+                source: value,
+            }
+        } else {
+            Self::Db(value)
+        }
+    }
 }
 
 impl From<diesel::r2d2::Error> for Error {
@@ -85,12 +267,50 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+/// Parses a URL which was provided by the user or another program.
+pub(crate) fn parse_url(url: &str) -> Result<Url> {
+    Url::parse(url).map_err(|source| Error::InvalidUrl {
+        url: url.to_string(),
+        source,
+    })
+}
+
 uniffi::custom_type!(Url, String, {
     remote,
     lower: |s| s.to_string(),
-    try_lift: |s| Ok(Url::parse(&s)?),
+    try_lift: |s| Ok(parse_url(&s)?),
 });
 
 uniffi::custom_type!(OffsetDateTime, SystemTime, { remote });
 
 uniffi::setup_scaffolding!();
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::ModuleFilter;
+
+    #[test]
+    fn separates_user_errors() {
+        let invalid_url = parse_url("not a url").unwrap_err();
+        assert!(invalid_url.is_user_error());
+
+        // Transparent errors take their severity from the error they wrap.
+        let filter = Error::from("size:large".parse::<ModuleFilter>().unwrap_err());
+        assert!(filter.is_user_error());
+
+        let query = Error::from(diesel::result::Error::NotFound);
+        assert!(!query.is_user_error());
+        assert_eq!(query.severity(), None);
+
+        // Missing files and denied permissions are the user's to fix.
+        let io = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(io.is_user_error());
+
+        let policy = Error::from(crate::policy::PolicyError::Read {
+            path: "policy.json".into(),
+            source: std::io::ErrorKind::PermissionDenied.into(),
+        });
+        assert!(policy.is_user_error());
+    }
+}
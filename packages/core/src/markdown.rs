@@ -0,0 +1,682 @@
+//! Cleaning up module descriptions before they're shown.
+//!
+//! Descriptions are written by module authors, and some contain HTML, links to
+//! `javascript:` URLs or markdown which doesn't parse, like a code block that
+//! never ends. [`sanitize`] turns them into markdown without any HTML, which
+//! both the command-line app and the GUI render, and into plain text for
+//! places which can't show markdown.
+//!
+//! The same goes for the help text of relationship groups, which lists show
+//! as a one-line [preview](preview) instead.
+
+use std::sync::LazyLock;
+
+use pulldown_cmark::{
+    CodeBlockKind, Event, LinkType, Options, Parser, Tag, TagEnd, TextMergeStream,
+};
+use regex::{Captures, Regex};
+
+/// The most characters in the preview of a [`SanitizedText`], including the
+/// ellipsis added when it's cut short.
+pub const PREVIEW_LENGTH: usize = 120;
+
+/// URL schemes which links may use. Links without a scheme are relative, and
+/// are kept too.
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+macro_rules! regex {
+    ($name:ident, $pattern:literal) => {
+        static $name: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new($pattern).expect("pattern is valid"));
+    };
+}
+
+regex!(ENTITY, r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]+);");
+regex!(
+    HTML_TOKEN,
+    r#"(?s)<!--.*?(?:-->|\z)|<(/?)([a-zA-Z][a-zA-Z0-9]*)\b((?:[^>"']|"[^"]*"|'[^']*')*)>|<[!?][^>]*>"#
+);
+regex!(
+    HREF,
+    r#"(?i)\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#
+);
+regex!(BLANK_LINES, r"\n{3,}");
+
+/// A description in the forms it can be shown in.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct SanitizedText {
+    /// Markdown without HTML or unsafe links.
+    pub markdown: String,
+    /// The same text without any markdown.
+    pub plain_text: String,
+    /// The plain text on one line, cut to at most [`PREVIEW_LENGTH`]
+    /// characters, for showing in lists.
+    pub preview: String,
+}
+
+/// Cleans up markdown which may contain HTML or mistakes.
+///
+/// HTML formatting and links are turned into their markdown equivalents, and
+/// any other tags are removed, along with scripts and styles. Links to other
+/// schemes than `http`, `https` and `mailto` are replaced by their text, and
+/// images by links to them, so that showing a description never loads
+/// anything. A code block that isn't closed is closed at the end.
+pub fn sanitize(source: &str) -> SanitizedText {
+    let text = source.replace("\r\n", "\n").replace('\r', "\n");
+    let text = text
+        .chars()
+        .filter(|&c| !c.is_control() || c == '\n' || c == '\t')
+        .collect::<String>();
+
+    let mut writer = Writer::default();
+    for event in TextMergeStream::new(Parser::new_ext(&text, Options::empty())) {
+        writer.event(event);
+    }
+    let (markdown, plain_text) = writer.finish();
+    let preview = preview(&plain_text, PREVIEW_LENGTH);
+
+    SanitizedText {
+        markdown,
+        plain_text,
+        preview,
+    }
+}
+
+/// Puts plain text on one line and cuts it to at most `max_chars` characters.
+///
+/// Text which is too long is cut at the end of a word if there's one in its
+/// second half, and ends with `…`. Characters are counted rather than bytes,
+/// so the text is never cut in the middle of one.
+pub fn preview(plain_text: &str, max_chars: usize) -> String {
+    let text = plain_text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+
+    let Some(kept) = max_chars.checked_sub(1) else {
+        return String::new();
+    };
+    let end = text.char_indices().nth(kept).map_or(text.len(), |(i, _)| i);
+    let cut = &text[..end];
+    let cut = match cut.rfind(' ') {
+        Some(space) if cut[..space].chars().count() >= kept / 2 => &cut[..space],
+        _ => cut,
+    };
+
+    format!("{}…", cut.trim_end())
+}
+
+/// Writes parsed markdown back out as markdown and as plain text, leaving out
+/// anything unsafe.
+#[derive(Default)]
+struct Writer {
+    markdown: String,
+    plain: String,
+    /// What starts each line of the current block, in markdown and in plain
+    /// text, for block quotes and list items.
+    prefixes: Vec<(String, String)>,
+    /// The next number of each list being written, or `None` for bulleted
+    /// lists.
+    lists: Vec<Option<u64>>,
+    /// The destination of each link being written, or `None` for links which
+    /// are only written as their text.
+    links: Vec<Option<String>>,
+    /// Newlines written since anything else.
+    newlines: usize,
+    /// Whether a list item's marker was the last thing written.
+    after_marker: bool,
+    /// Whether the next block should be separated from the last by a blank
+    /// line.
+    blank: bool,
+    /// The contents of the code block being written, which is only written
+    /// once its fence can be chosen.
+    code_block: Option<(String, String)>,
+    /// The raw HTML of the HTML block being written.
+    html_block: Option<String>,
+    /// The element whose contents are being left out, like a script.
+    skipping: Option<String>,
+    /// How many HTML code elements are open, whose text isn't escaped.
+    verbatim: usize,
+}
+
+impl Writer {
+    fn event(&mut self, event: Event) {
+        if let Some((_, code)) = &mut self.code_block {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => self.end_code_block(),
+                _ => {}
+            }
+            return;
+        }
+        if let Some(html) = &mut self.html_block {
+            match event {
+                Event::Html(text) => html.push_str(&text),
+                Event::End(TagEnd::HtmlBlock) => {
+                    let html = self.html_block.take().unwrap_or_default();
+                    self.html(&html);
+                    self.blank = true;
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => self.write(&code_span(&code), &code),
+            Event::Html(html) | Event::InlineHtml(html) => self.html(&html),
+            Event::SoftBreak => self.newline(),
+            Event::HardBreak => {
+                self.write("\\", "");
+                self.newline();
+            }
+            Event::Rule => {
+                self.start_block();
+                self.write("---", "");
+                self.blank = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading { level, .. } => {
+                self.start_block();
+                self.write(&format!("{} ", "#".repeat(level as usize)), "");
+            }
+            Tag::BlockQuote(_) => {
+                self.start_block();
+                self.write("> ", "");
+                self.prefixes.push(("> ".into(), String::new()));
+                self.after_marker = true;
+            }
+            Tag::CodeBlock(kind) => {
+                self.start_block();
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code_block = Some((info, String::new()));
+            }
+            Tag::HtmlBlock => {
+                self.start_block();
+                self.html_block = Some(String::new());
+            }
+            Tag::List(start) => {
+                self.start_block();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.start_block();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_owned(),
+                };
+                self.write(&marker, &marker);
+                let indent = " ".repeat(marker.len());
+                self.prefixes.push((indent.clone(), indent));
+                self.after_marker = true;
+            }
+            Tag::Emphasis => self.write("*", ""),
+            Tag::Strong => self.write("**", ""),
+            Tag::Link {
+                link_type: LinkType::Email,
+                dest_url,
+                ..
+            } => self.start_link(&format!("mailto:{dest_url}")),
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.start_link(&dest_url);
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::Heading(_) => self.blank = true,
+            TagEnd::BlockQuote(_) => {
+                self.prefixes.pop();
+                self.blank = true;
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                self.blank = self.lists.is_empty();
+            }
+            TagEnd::Item => {
+                self.prefixes.pop();
+            }
+            TagEnd::Emphasis => self.write("*", ""),
+            TagEnd::Strong => self.write("**", ""),
+            TagEnd::Link | TagEnd::Image => self.end_link(),
+            _ => {}
+        }
+    }
+
+    /// Starts a new line, with a blank line before it if the last block asked
+    /// for one.
+    fn start_block(&mut self) {
+        let wanted = if self.blank { 2 } else { 1 };
+        self.blank = false;
+        if self.after_marker {
+            return;
+        }
+        while self.newlines < wanted && !self.markdown.is_empty() {
+            self.newline();
+        }
+    }
+
+    fn newline(&mut self) {
+        // Runs of blank lines are kept to one, except in code.
+        if self.markdown.is_empty() || (self.newlines >= 2 && self.verbatim == 0) {
+            return;
+        }
+
+        self.markdown.push('\n');
+        self.plain.push('\n');
+        for (markdown, plain) in &self.prefixes {
+            self.markdown.push_str(markdown);
+            self.plain.push_str(plain);
+        }
+        self.newlines += 1;
+        self.after_marker = false;
+    }
+
+    fn write(&mut self, markdown: &str, plain: &str) {
+        if self.skipping.is_some() {
+            return;
+        }
+
+        self.markdown.push_str(markdown);
+        self.plain.push_str(plain);
+        if !markdown.is_empty() {
+            self.newlines = 0;
+            self.after_marker = false;
+        }
+    }
+
+    /// Writes text, escaping anything a renderer would take as markdown or
+    /// HTML.
+    fn text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            if self.verbatim > 0 {
+                self.write(line, line);
+            } else {
+                let at_line_start =
+                    self.newlines > 0 || self.after_marker || self.markdown.is_empty();
+                self.write(&escape(line, at_line_start), line);
+            }
+        }
+    }
+
+    fn end_code_block(&mut self) {
+        let Some((info, code)) = self.code_block.take() else {
+            return;
+        };
+        let code = code.strip_suffix('\n').unwrap_or(&code);
+        let fence = "`".repeat(longest_run(code, '`').max(2) + 1);
+        let info = if info.contains('`') { "" } else { info.trim() };
+
+        self.write(&format!("{fence}{info}"), "");
+        self.verbatim += 1;
+        self.newline();
+        self.text(code);
+        self.newline();
+        self.verbatim -= 1;
+        self.write(&fence, "");
+        self.blank = true;
+    }
+
+    fn start_link(&mut self, url: &str) {
+        if is_safe_url(url) {
+            self.write("[", "");
+            self.links.push(Some(url.to_owned()));
+        } else {
+            self.links.push(None);
+        }
+    }
+
+    fn end_link(&mut self) {
+        if let Some(Some(url)) = self.links.pop() {
+            // Links without any text, like autolinks, show their URL.
+            if self.markdown.ends_with('[') {
+                self.write(&escape(&url, false), &url);
+            }
+            self.write(&format!("]({})", link_destination(&url)), "");
+        }
+    }
+
+    /// Writes raw HTML as markdown. Tags are picked out first, so that escaped
+    /// text like `&lt;b&gt;` is kept as text rather than being taken for one.
+    fn html(&mut self, html: &str) {
+        let mut last = 0;
+        for caps in HTML_TOKEN.captures_iter(html) {
+            let token = caps.get(0).expect("a match has a whole capture");
+            self.html_text(&html[last..token.start()]);
+            last = token.end();
+
+            // Comments, doctypes and processing instructions are left out.
+            if let Some(name) = caps.get(2) {
+                let closing = !caps[1].is_empty();
+                self.html_tag(&name.as_str().to_ascii_lowercase(), closing, &caps[3]);
+            }
+        }
+        self.html_text(&html[last..]);
+    }
+
+    fn html_text(&mut self, text: &str) {
+        if !text.is_empty() && self.skipping.is_none() {
+            self.text(&ENTITY.replace_all(text, decode_entity));
+        }
+    }
+
+    fn html_tag(&mut self, name: &str, closing: bool, attributes: &str) {
+        if let Some(skipping) = &self.skipping {
+            if closing && skipping == name {
+                self.skipping = None;
+            }
+            return;
+        }
+
+        let (newlines, markdown, plain) = match (name, closing) {
+            ("script" | "style", false) => {
+                self.skipping = Some(name.to_owned());
+                return;
+            }
+            ("a", false) => {
+                let href = HREF
+                    .captures(attributes)
+                    .and_then(|caps| caps.iter().skip(1).flatten().next())
+                    .map(|href| ENTITY.replace_all(href.as_str(), decode_entity))
+                    .filter(|href| !href.is_empty());
+                match href {
+                    Some(href) => self.start_link(&href),
+                    None => self.links.push(None),
+                }
+                return;
+            }
+            ("a", true) => {
+                if !self.links.is_empty() {
+                    self.end_link();
+                }
+                return;
+            }
+            ("br" | "hr" | "tr", _) => (1, "", ""),
+            ("p" | "div" | "ul" | "ol" | "table" | "blockquote", _) => (2, "", ""),
+            ("b" | "strong", _) => (0, "**", ""),
+            ("i" | "em", _) => (0, "*", ""),
+            ("code" | "tt", _) => {
+                self.verbatim = if closing {
+                    self.verbatim.saturating_sub(1)
+                } else {
+                    self.verbatim + 1
+                };
+                (0, "`", "")
+            }
+            ("pre", false) => {
+                self.newline();
+                self.write("```", "");
+                self.verbatim += 1;
+                self.newline();
+                return;
+            }
+            ("pre", true) => {
+                self.newline();
+                self.write("```", "");
+                self.verbatim = self.verbatim.saturating_sub(1);
+                self.newline();
+                return;
+            }
+            ("li", false) => (1, "- ", "- "),
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                let level = name[1..].parse().unwrap_or(1);
+                for _ in 0..2 {
+                    self.newline();
+                }
+                self.write(&format!("{} ", "#".repeat(level)), "");
+                return;
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => (2, "", ""),
+            _ => return,
+        };
+
+        for _ in 0..newlines {
+            self.newline();
+        }
+        self.write(markdown, plain);
+    }
+
+    fn finish(mut self) -> (String, String) {
+        // Anything left open is closed, so that it doesn't carry on into
+        // whatever the text is shown next to.
+        if self.code_block.is_some() {
+            self.end_code_block();
+        }
+        if let Some(html) = self.html_block.take() {
+            self.html(&html);
+        }
+        while !self.links.is_empty() {
+            self.end_link();
+        }
+
+        let markdown = trim_lines(&self.markdown);
+        let plain = trim_lines(&self.plain);
+        let plain = BLANK_LINES.replace_all(&plain, "\n\n").trim().to_owned();
+        (markdown, plain)
+    }
+}
+
+fn trim_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned()
+}
+
+/// Escapes the characters in text which would otherwise be read as markdown
+/// or the start of an HTML tag.
+fn escape(text: &str, at_line_start: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut first = at_line_start;
+
+    // A number followed by `.` or `)` at the start of a line would start a
+    // numbered list.
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let numbered = at_line_start && digits > 0 && text[digits..].starts_with(['.', ')']);
+    if numbered {
+        escaped.push_str(&text[..digits]);
+        escaped.push('\\');
+        chars = text[digits..].chars().peekable();
+        first = false;
+    }
+
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' | '>' | '-' | '+' | '=' if first => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '<' if next.is_none_or(|next| next.is_ascii_alphabetic() || "/!?".contains(next)) => {
+                escaped.push_str("&lt;");
+            }
+            '&' if next.is_some_and(|next| next.is_ascii_alphanumeric() || next == '#') => {
+                escaped.push_str("&amp;");
+            }
+            _ => escaped.push(c),
+        }
+        first = false;
+    }
+
+    escaped
+}
+
+/// Wraps inline code in enough backticks that none inside it end it early.
+fn code_span(code: &str) -> String {
+    let fence = "`".repeat(longest_run(code, '`') + 1);
+    let pad = if code.starts_with(['`', ' ']) || code.ends_with(['`', ' ']) {
+        " "
+    } else {
+        ""
+    };
+
+    format!("{fence}{pad}{code}{pad}{fence}")
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c)
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Writes a link's URL so that spaces and parentheses in it don't end it.
+fn link_destination(url: &str) -> String {
+    let url = url.replace('<', "%3C").replace('>', "%3E");
+    if url.contains([' ', '(', ')']) {
+        format!("<{url}>")
+    } else {
+        url
+    }
+}
+
+fn decode_entity(caps: &Captures) -> String {
+    let entity = &caps[1];
+    let decoded = match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => entity
+            .strip_prefix("#x")
+            .or_else(|| entity.strip_prefix("#X"))
+            .map(|hex| u32::from_str_radix(hex, 16))
+            .or_else(|| entity.strip_prefix('#').map(str::parse))
+            .and_then(Result::ok)
+            .and_then(char::from_u32)
+            .filter(|c| !c.is_control() || *c == '\n' || *c == '\t'),
+    };
+
+    decoded.map_or_else(|| caps[0].to_owned(), String::from)
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+
+    scheme.is_none_or(|scheme| {
+        SAFE_SCHEMES
+            .iter()
+            .any(|safe| safe.eq_ignore_ascii_case(scheme.trim()))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn html_becomes_markdown() {
+        let text = sanitize(
+            "<h2>Parallax</h2><p>Adds <b>terrain</b> &amp; <i>scatter</i>.<br>\
+             See <a href=\"https://example.com\">the wiki</a>.</p>\
+             <script>alert(1)</script><ul><li>One</li><li>Two</li></ul>",
+        );
+
+        assert_eq!(
+            text.markdown,
+            "## Parallax\n\nAdds **terrain** & *scatter*.\nSee [the wiki](https://example.com).\n\n- One\n- Two"
+        );
+        assert_eq!(
+            text.plain_text,
+            "Parallax\n\nAdds terrain & scatter.\nSee the wiki.\n\n- One\n- Two"
+        );
+    }
+
+    #[test]
+    fn unsafe_content_is_removed() {
+        let text = sanitize(
+            "[click](javascript:alert(1)) ![logo](https://example.com/logo.png)\r\n\
+             &lt;script&gt;alert(1)&lt;/script&gt; 1 &lt; 2 <img src=x onerror=alert(1)\n\
+             ```\nunclosed",
+        );
+
+        assert_eq!(
+            text.markdown,
+            "click [logo](https://example.com/logo.png)\n&lt;script>alert(1)&lt;/script> 1 < 2 &lt;img src=x onerror=alert(1)\n\n```\nunclosed\n```"
+        );
+        assert_eq!(
+            text.plain_text,
+            "click logo\n<script>alert(1)</script> 1 < 2 <img src=x onerror=alert(1)\n\nunclosed"
+        );
+        assert_eq!(
+            text.preview,
+            "click logo <script>alert(1)</script> 1 < 2 <img src=x onerror=alert(1) unclosed"
+        );
+    }
+
+    #[test]
+    fn reference_links_are_checked() {
+        let text = sanitize(
+            "[docs][1] and [home][2]\n\n[1]: javascript:alert(1)\n[2]: https://example.com",
+        );
+
+        assert_eq!(text.markdown, "docs and [home](https://example.com)");
+        assert_eq!(text.plain_text, "docs and home");
+    }
+
+    #[test]
+    fn code_and_escaped_text_are_kept() {
+        let text = sanitize("Returns a `Vec<T>` of <b>parts</b>");
+        assert_eq!(text.markdown, "Returns a `Vec<T>` of **parts**");
+        assert_eq!(text.plain_text, "Returns a Vec<T> of parts");
+
+        let text = sanitize("Use &lt;b&gt; for bold");
+        assert_eq!(text.markdown, "Use &lt;b> for bold");
+        assert_eq!(text.plain_text, "Use <b> for bold");
+
+        let text = sanitize("<p>&lt;b&gt;bold&lt;/b&gt;</p>");
+        assert_eq!(text.markdown, "&lt;b>bold&lt;/b>");
+        assert_eq!(text.plain_text, "<b>bold</b>");
+    }
+
+    #[test]
+    fn previews_are_cut_between_words() {
+        let text = "Pick **Kopernicus** if you use\nplanet packs, or Kopernicus-Expansion for its shaders.";
+        let text = sanitize(text);
+
+        assert_eq!(
+            text.preview,
+            "Pick Kopernicus if you use planet packs, or Kopernicus-Expansion for its shaders."
+        );
+        assert_eq!(preview(&text.plain_text, 30), "Pick Kopernicus if you use…");
+        assert_eq!(preview("Kopernicus-Expansion", 10), "Kopernicu…");
+        assert_eq!(preview("Ünïcödé", 4), "Ünï…");
+        assert_eq!(preview("Kopernicus", 0), "");
+    }
+}
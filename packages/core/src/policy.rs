@@ -0,0 +1,332 @@
+//! Rules set by an administrator about which modules can be used.
+//!
+//! Organisations which mirror repositories may need to keep some modules out,
+//! whether by their identifier, their license, or the hosts they're
+//! downloaded from. A [`Policy`] is enforced at three points: releases it
+//! blocks aren't saved when a repository is unpacked, releases saved before
+//! the policy changed aren't chosen by the [resolver](crate::resolver), and
+//! files aren't downloaded from hosts it blocks.
+//!
+//! The policy is usually kept in `policy.json` in the user's config directory,
+//! and is given to a [`RepoManager`](crate::repo::RepoManager) when it's
+//! opened.
+
+use std::{
+    fmt::{self, Formatter},
+    fs, io,
+    path::PathBuf,
+};
+
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{DIRS, json::JsonModule};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum PolicyError {
+    #[error("failed to read the policy at {}", path.display())]
+    #[diagnostic(
+        code(camrete::policy::unreadable),
+        severity(Warning),
+        help("check that the policy file can be read by this user")
+    )]
+    Read { path: PathBuf, source: io::Error },
+    #[error("the policy at {} is damaged", path.display())]
+    #[diagnostic(
+        code(camrete::policy::corrupt),
+        severity(Warning),
+        help("the policy must be a JSON object with `modules`, `licenses` and `hosts` rules")
+    )]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{identifier} {version} is blocked by policy, since {violation}")]
+    #[diagnostic(
+        code(camrete::policy::blocked),
+        severity(Warning),
+        help("ask whoever manages this computer's policy to allow it")
+    )]
+    Blocked {
+        identifier: String,
+        version: String,
+        violation: PolicyViolation,
+    },
+    #[error("every host that {url} can be downloaded from is blocked by policy")]
+    #[diagnostic(
+        code(camrete::policy::blocked_host),
+        severity(Warning),
+        help("ask whoever manages this computer's policy to allow one of its hosts")
+    )]
+    BlockedHost { url: Url },
+    #[error("{url} redirected to {to}, whose host is blocked by policy")]
+    #[diagnostic(
+        code(camrete::policy::blocked_redirect),
+        severity(Warning),
+        help("ask whoever manages this computer's policy to allow the host it redirects to")
+    )]
+    BlockedRedirect { url: Box<Url>, to: Box<Url> },
+}
+
+/// Why a [`Policy`] blocks a release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// The release's identifier isn't allowed.
+    Module,
+    /// None of the release's licenses are allowed.
+    License,
+    /// None of the hosts the release can be downloaded from are allowed.
+    Host,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Module => "its identifier isn't allowed",
+            Self::License => "none of its licenses are allowed",
+            Self::Host => "none of its download hosts are allowed",
+        })
+    }
+}
+
+/// Patterns which allow or deny a value, such as a module identifier.
+///
+/// Patterns are matched case-insensitively, and can use `*` to match any run
+/// of characters and `?` to match any one character, so `*.example.com`
+/// matches every subdomain of `example.com`. Denying a value takes priority
+/// over allowing it. If any patterns are allowed, values which none of them
+/// match are denied.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct PolicyRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl PolicyRules {
+    /// Returns whether the rules allow every value.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Returns whether the rules allow the given value.
+    pub fn allows(&self, value: &str) -> bool {
+        let matches = |pattern: &String| glob_matches(pattern, value);
+
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+/// Which modules can be saved, installed and downloaded.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct Policy {
+    /// Rules for module identifiers.
+    #[serde(default)]
+    pub modules: PolicyRules,
+    /// Rules for licenses, such as `MIT` or `CC-BY-NC-*`. A release is allowed
+    /// if any of its licenses are, since it can be used under any of them.
+    #[serde(default)]
+    pub licenses: PolicyRules,
+    /// Rules for the hosts that files are downloaded from. Blocked hosts are
+    /// skipped when a release has other download URLs; releases without any
+    /// allowed host are blocked.
+    #[serde(default)]
+    pub hosts: PolicyRules,
+}
+
+impl Policy {
+    /// The location of the policy used by [`Self::load`].
+    pub fn config_dir_path() -> PathBuf {
+        DIRS.config_dir().join("policy.json")
+    }
+
+    /// Loads the policy from the user's config directory.
+    pub fn load() -> Result<Self, PolicyError> {
+        Self::load_from(Self::config_dir_path())
+    }
+
+    /// Loads a policy from the given file. A missing file is treated as a
+    /// policy which allows everything.
+    #[instrument]
+    pub fn load_from(path: PathBuf) -> Result<Self, PolicyError> {
+        match fs::read(&path) {
+            Ok(data) => {
+                serde_json::from_slice(&data).map_err(|source| PolicyError::Parse { path, source })
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("No policy, allowing everything");
+                Ok(Self::default())
+            }
+            Err(source) => Err(PolicyError::Read { path, source }),
+        }
+    }
+
+    /// Returns whether the policy allows everything.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty() && self.licenses.is_empty() && self.hosts.is_empty()
+    }
+
+    /// Returns whether files can be downloaded from the given URL.
+    pub fn allows_url(&self, url: &Url) -> bool {
+        self.hosts.allows(url.host_str().unwrap_or_default())
+    }
+
+    /// Checks a release with the given identifier, licenses and download URLs
+    /// against the policy. Releases without any download URLs, like
+    /// metapackages, aren't checked against the host rules.
+    pub fn check(
+        &self,
+        identifier: &str,
+        licenses: &[String],
+        downloads: &[Url],
+    ) -> Result<(), PolicyViolation> {
+        if !self.modules.allows(identifier) {
+            return Err(PolicyViolation::Module);
+        }
+
+        let license_allowed = if licenses.is_empty() {
+            self.licenses.allow.is_empty()
+        } else {
+            licenses.iter().any(|license| self.licenses.allows(license))
+        };
+        if !license_allowed {
+            return Err(PolicyViolation::License);
+        }
+
+        if !downloads.is_empty() && !downloads.iter().any(|url| self.allows_url(url)) {
+            return Err(PolicyViolation::Host);
+        }
+
+        Ok(())
+    }
+
+    /// Checks a release's metadata against the policy.
+    pub fn check_release(&self, module: &JsonModule) -> Result<(), PolicyViolation> {
+        self.check(
+            module.identifier.as_str(),
+            &module.license,
+            &module.download,
+        )
+    }
+
+    /// Like [`Self::check_release`], but returns an error naming the release.
+    pub fn enforce(&self, module: &JsonModule) -> Result<(), PolicyError> {
+        self.check_release(module)
+            .map_err(|violation| PolicyError::Blocked {
+                identifier: module.identifier.to_string(),
+                version: module.version.clone(),
+                violation,
+            })
+    }
+}
+
+/// Matches `text` against a pattern of literal characters, `*` and `?`,
+/// ignoring case.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to carry on from if the text after the last `*` doesn't match.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, from_value, json};
+
+    use super::*;
+    use crate::repo::test_support;
+
+    #[test]
+    fn match_patterns() {
+        assert!(glob_matches("Parallax", "parallax"));
+        assert!(glob_matches("Parallax*", "Parallax-Textures"));
+        assert!(glob_matches("*.example.com", "cdn.example.com"));
+        assert!(!glob_matches("*.example.com", "example.com"));
+        assert!(glob_matches("CC-BY-NC-?.0", "CC-BY-NC-4.0"));
+        assert!(glob_matches("*a*b", "xaab"));
+        assert!(!glob_matches("*a*b", "xaabc"));
+        assert!(glob_matches("*", ""));
+    }
+
+    #[test]
+    fn check_releases() {
+        let policy: Policy = from_value(json!({
+            "modules": { "deny": ["Bad*"] },
+            "licenses": { "allow": ["MIT", "GPL-*"] },
+            "hosts": { "deny": ["*.blocked.example"] },
+        }))
+        .unwrap();
+        let release = |identifier: &str, license: &str, download: Value| -> JsonModule {
+            test_support::release(identifier, "1.0")
+                .set("license", license)
+                .set("download", download)
+                .build()
+        };
+
+        let allowed = release("Parallax", "MIT", json!("https://cdn.example/a.zip"));
+        assert_eq!(policy.check_release(&allowed), Ok(()));
+        assert_eq!(
+            policy.check_release(&release(
+                "BadMod",
+                "MIT",
+                json!("https://cdn.example/a.zip")
+            )),
+            Err(PolicyViolation::Module)
+        );
+        assert_eq!(
+            policy.check_release(&release(
+                "Parallax",
+                "CC-BY-NC-4.0",
+                json!("https://cdn.example/a.zip")
+            )),
+            Err(PolicyViolation::License)
+        );
+        assert_eq!(
+            policy.check_release(&release(
+                "Parallax",
+                "GPL-3.0",
+                json!("https://dl.blocked.example/a.zip")
+            )),
+            Err(PolicyViolation::Host)
+        );
+
+        let mirrored = release(
+            "Parallax",
+            "MIT",
+            json!([
+                "https://dl.blocked.example/a.zip",
+                "https://cdn.example/a.zip"
+            ]),
+        );
+        assert_eq!(policy.check_release(&mirrored), Ok(()));
+        assert!(Policy::default().is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+//! The supported public API of Camrete.
+//!
+//! Everything exported here follows semantic versioning, so it's safe to
+//! depend on from other crates:
+//!
+//! ```
+//! use camrete_core::prelude::*;
+//!
+//! let repo_mgr = RepoManager::new(":memory:")?;
+//! let repos: Vec<Repository> = repo_mgr.db()?.all_repos(true)?;
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! Other public items in this crate, such as the database schema and the
+//! re-exported `diesel` crate, are implementation details which may change in
+//! any release. They're hidden from the documentation, and the database's
+//! connection can't be reached through [`RepoDB`]:
+//!
+//! ```compile_fail
+//! use camrete_core::prelude::*;
+//!
+//! let repo_mgr = RepoManager::new(":memory:")?;
+//! let _connection = repo_mgr.db()?.connection;
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! ```compile_fail
+//! use camrete_core::{DbConnection, prelude::*};
+//!
+//! let repo_mgr = RepoManager::new(":memory:")?;
+//! let _connection: &mut DbConnection = repo_mgr.db()?.as_mut();
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! ```compile_fail
+//! use camrete_core::prelude::{DbPool, schema};
+//! ```
+
+pub use crate::{
+    Error, Result,
+    database::{
+        AvailableUpgrade, CompatibleRelease, DownloadCountMode, DownloadState, FilterCondition,
+        FilterTerm, GameCompatibility, Metric, MetricSummary, MirrorStats, ModuleDetail,
+        ModuleFilter, ModuleSummary, NewDownload, QueuedDownload, ReleaseDetail, ReleaseNotes,
+        RepoDB, RepoHealth, RepoSuggestion, RetryPolicy, SavedFilter, Snapshot, TagCount, TagGroup,
+        YankedRelease,
+        models::{
+            Author, Module, ModuleRelease, Repository, RepositoryRef, module::RelationshipType,
+        },
+    },
+    export::{Column, TableFormat},
+    json::ReleaseStatus,
+    policy::{Policy, PolicyRules},
+    profile::{Profile, ProfileRegistry},
+    repo::{RepoManager, RepoManagerBuilder, game::GameVersion},
+};
+#[cfg(feature = "network")]
+pub use crate::repo::{
+    DownloadError, DownloadEvent, DownloadProgress, ModuleChange, PendingRepoManager,
+    UpdatePreview, UpdateReport,
+};
+
+#[cfg(test)]
+mod test {
+    /// Everything the prelude exports. Adding an item here adds it to the
+    /// supported API, so check that it doesn't expose the database first.
+    const EXPORTS: [&str; 51] = [
+        "Author",
+        "AvailableUpgrade",
+        "Column",
+        "CompatibleRelease",
+        "DownloadCountMode",
+        "DownloadError",
+        "DownloadEvent",
+        "DownloadProgress",
+        "DownloadState",
+        "Error",
+        "FilterCondition",
+        "FilterTerm",
+        "GameCompatibility",
+        "GameVersion",
+        "Metric",
+        "MetricSummary",
+        "MirrorStats",
+        "Module",
+        "ModuleChange",
+        "ModuleDetail",
+        "ModuleFilter",
+        "ModuleRelease",
+        "ModuleSummary",
+        "NewDownload",
+        "PendingRepoManager",
+        "Policy",
+        "PolicyRules",
+        "Profile",
+        "ProfileRegistry",
+        "QueuedDownload",
+        "RelationshipType",
+        "ReleaseDetail",
+        "ReleaseNotes",
+        "ReleaseStatus",
+        "RepoDB",
+        "RepoHealth",
+        "RepoManager",
+        "RepoManagerBuilder",
+        "RepoSuggestion",
+        "Repository",
+        "RepositoryRef",
+        "Result",
+        "RetryPolicy",
+        "SavedFilter",
+        "Snapshot",
+        "TableFormat",
+        "TagCount",
+        "TagGroup",
+        "UpdatePreview",
+        "UpdateReport",
+        "YankedRelease",
+    ];
+
+    /// Items which would let other crates query the database themselves.
+    const DENIED: [&str; 6] = [
+        "diesel",
+        "schema",
+        "DbPool",
+        "DbConnection",
+        "SqliteConnection",
+        "PooledConnection",
+    ];
+
+    /// The names exported by the `pub use` items in `source`: the last
+    /// segment of each path.
+    fn exported_names(source: &str) -> Vec<&str> {
+        let mut names = vec![];
+
+        for item in source.split("pub use ").skip(1) {
+            let item = &item[..item.find(';').expect("uses end with a semicolon")];
+            let mut rest = item;
+            while let Some(start) = rest.find(|c: char| c.is_alphanumeric() || c == '_') {
+                rest = &rest[start..];
+                let end = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                let (name, after) = rest.split_at(end);
+                if !after.trim_start().starts_with("::") {
+                    names.push(name);
+                }
+                rest = after;
+            }
+        }
+
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn exports_only_the_supported_api() {
+        let source = include_str!("prelude.rs");
+        let (code, _) = source
+            .split_once("#[cfg(test)]")
+            .expect("the tests are at the end");
+        let names = exported_names(code);
+
+        for name in &names {
+            assert!(!DENIED.contains(name), "the prelude exports {name}");
+        }
+        assert_eq!(names, EXPORTS);
+    }
+}
@@ -0,0 +1,295 @@
+//! Named databases for keeping unrelated game setups apart.
+//!
+//! Each [`Profile`] has its own database file, and so its own set of
+//! repositories and settings. The list of profiles is kept in a
+//! [`ProfileRegistry`] file in the user's config directory.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{self, Path, PathBuf},
+};
+
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{
+    DIRS,
+    io::{SyncPolicy, atomic_write},
+    repo::RepoManager,
+};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum ProfileError {
+    #[error("no profile is named {0:?}")]
+    #[diagnostic(
+        code(camrete::profile::not_found),
+        severity(Warning),
+        help("use `camrete profile list` to see the available profiles")
+    )]
+    NotFound(String),
+    #[error("a profile named {0:?} already exists")]
+    #[diagnostic(code(camrete::profile::already_exists), severity(Warning))]
+    AlreadyExists(String),
+    #[error("{0:?} is not a valid profile name")]
+    #[diagnostic(
+        code(camrete::profile::invalid_name),
+        severity(Warning),
+        help("profile names can only contain letters, numbers, `-`, `_` and `.`")
+    )]
+    InvalidName(String),
+    #[error("failed to read the profile list at {}", path.display())]
+    #[diagnostic(code(camrete::profile::unreadable))]
+    Read { path: PathBuf, source: io::Error },
+    #[error("the profile list at {} is damaged", path.display())]
+    #[diagnostic(code(camrete::profile::corrupt))]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("failed to save the profile list to {}", path.display())]
+    #[diagnostic(code(camrete::profile::unwritable))]
+    Write { path: PathBuf, source: io::Error },
+}
+
+/// A named database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    /// The profile's database file.
+    pub database: PathBuf,
+}
+
+impl Profile {
+    /// Opens the profile's database, creating it if necessary.
+    pub fn open(&self) -> crate::Result<RepoManager> {
+        if let Some(parent) = self.database.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let url = Url::from_file_path(path::absolute(&self.database)?).expect("path is absolute");
+        RepoManager::new(url.as_str())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, PathBuf>,
+}
+
+/// The list of profiles, along with which one is used by default.
+///
+/// Changes are only saved by [`Self::save`].
+#[derive(Debug)]
+pub struct ProfileRegistry {
+    path: PathBuf,
+    file: RegistryFile,
+}
+
+impl ProfileRegistry {
+    /// The location of the profile list used by [`Self::load`].
+    pub fn config_dir_path() -> PathBuf {
+        DIRS.config_dir().join("profiles.json")
+    }
+
+    /// Loads the profile list from the user's config directory.
+    pub fn load() -> Result<Self, ProfileError> {
+        Self::load_from(Self::config_dir_path())
+    }
+
+    /// Loads a profile list from the given file. A missing file is treated as
+    /// an empty list.
+    #[instrument]
+    pub fn load_from(path: PathBuf) -> Result<Self, ProfileError> {
+        let file = match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(file) => file,
+                Err(source) => return Err(ProfileError::Parse { path, source }),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("No profile list, starting with an empty one");
+                RegistryFile::default()
+            }
+            Err(source) => return Err(ProfileError::Read { path, source }),
+        };
+
+        Ok(Self { path, file })
+    }
+
+    /// Saves any changes to the profile list.
+    pub fn save(&self) -> Result<(), ProfileError> {
+        let write = || {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let data = serde_json::to_vec_pretty(&self.file).map_err(io::Error::other)?;
+            atomic_write(&self.path, &data, SyncPolicy::default())
+        };
+
+        write().map_err(|source| ProfileError::Write {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    /// All profiles, ordered by name.
+    pub fn profiles(&self) -> impl Iterator<Item = Profile> {
+        self.file.profiles.iter().map(|(name, database)| Profile {
+            name: name.clone(),
+            database: database.clone(),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Result<Profile, ProfileError> {
+        let database = self
+            .file
+            .profiles
+            .get(name)
+            .ok_or_else(|| ProfileError::NotFound(name.into()))?;
+
+        Ok(Profile {
+            name: name.into(),
+            database: database.clone(),
+        })
+    }
+
+    /// Adds a profile. If `database` isn't given, the profile gets a new
+    /// database file in the user's data directory.
+    pub fn create(
+        &mut self,
+        name: &str,
+        database: Option<&Path>,
+    ) -> Result<Profile, ProfileError> {
+        validate_name(name)?;
+        if self.file.profiles.contains_key(name) {
+            return Err(ProfileError::AlreadyExists(name.into()));
+        }
+
+        let database = match database {
+            // Relative paths would otherwise change meaning with the working
+            // directory.
+            Some(path) => path::absolute(path).unwrap_or_else(|_| path.to_owned()),
+            None => DIRS
+                .data_local_dir()
+                .join("profiles")
+                .join(format!("{name}.sqlite")),
+        };
+        self.file.profiles.insert(name.into(), database.clone());
+
+        Ok(Profile {
+            name: name.into(),
+            database,
+        })
+    }
+
+    /// Removes a profile from the list. Its database file is left in place.
+    pub fn remove(&mut self, name: &str) -> Result<Profile, ProfileError> {
+        let database = self
+            .file
+            .profiles
+            .remove(name)
+            .ok_or_else(|| ProfileError::NotFound(name.into()))?;
+
+        if self.file.default.as_deref() == Some(name) {
+            self.file.default = None;
+        }
+
+        Ok(Profile {
+            name: name.into(),
+            database,
+        })
+    }
+
+    /// The profile used when none is chosen, if one has been set.
+    pub fn default_profile(&self) -> Option<Profile> {
+        let name = self.file.default.as_deref()?;
+        self.get(name).ok()
+    }
+
+    /// Changes the profile used when none is chosen. `None` goes back to
+    /// using the database in the user's data directory.
+    pub fn set_default(&mut self, name: Option<&str>) -> Result<(), ProfileError> {
+        if let Some(name) = name {
+            self.get(name)?;
+        }
+
+        self.file.default = name.map(Into::into);
+        Ok(())
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), ProfileError> {
+    let is_valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(ProfileError::InvalidName(name.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn registry_round_trip() {
+        let dir = env::temp_dir().join(format!("camrete-profiles-{}", process::id()));
+        let path = dir.join("profiles.json");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut registry = ProfileRegistry::load_from(path.clone()).unwrap();
+        assert_eq!(registry.profiles().count(), 0);
+
+        let stable = registry
+            .create("KSP1-stable", Some(&dir.join("stable.sqlite")))
+            .unwrap();
+        registry.create("KSP2-testing", None).unwrap();
+        registry.set_default(Some("KSP1-stable")).unwrap();
+        assert!(matches!(
+            registry.create("KSP1-stable", None),
+            Err(ProfileError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            registry.set_default(Some("missing")),
+            Err(ProfileError::NotFound(_))
+        ));
+        registry.save().unwrap();
+
+        let mut registry = ProfileRegistry::load_from(path).unwrap();
+        let names = registry.profiles().map(|p| p.name).collect::<Vec<_>>();
+        assert_eq!(names, ["KSP1-stable", "KSP2-testing"]);
+        assert_eq!(registry.default_profile(), Some(stable.clone()));
+
+        let mgr = stable.open().unwrap();
+        mgr.db().unwrap().all_repos(true).unwrap();
+        assert!(stable.database.exists());
+
+        registry.remove("KSP1-stable").unwrap();
+        assert_eq!(registry.default_profile(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profile_names_are_file_names() {
+        for name in ["KSP1-stable", "ksp_2.testing", "RO"] {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["", ".hidden", "../escape", "with space", "a/b"] {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+    }
+}
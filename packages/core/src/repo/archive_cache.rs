@@ -0,0 +1,157 @@
+//! The last archive downloaded for each repository, kept on disk.
+//!
+//! Each archive is saved alongside the ETag and content type it was served
+//! with, keyed on a hash of the repository's URL so that databases sharing a
+//! cache directory can't confuse each other's repositories. When the server
+//! says the archive hasn't changed, it can be unpacked again without
+//! downloading it, such as after the database is deleted.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::debug;
+use url::Url;
+
+use crate::database::canonical_url;
+
+/// A directory of repository archives.
+#[derive(Debug, Clone)]
+pub(crate) struct ArchiveCache {
+    dir: PathBuf,
+}
+
+/// An archive in an [`ArchiveCache`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CachedArchive {
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// The ETag the archive was served with.
+    pub etag: String,
+    /// The content type the archive was served with, in case its format
+    /// can't be told from its contents.
+    pub content_type: Option<String>,
+}
+
+impl ArchiveCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Hashes the URL's canonical form, so that different spellings of the
+    /// same URL share an archive.
+    fn key(url: &Url) -> String {
+        format!("{:x}", Sha256::digest(canonical_url(url).as_str()))
+    }
+
+    fn archive_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.archive", Self::key(url)))
+    }
+
+    fn info_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.json", Self::key(url)))
+    }
+
+    /// Where a new archive for the repository at `url` is downloaded to,
+    /// before it's [stored](Self::store).
+    pub fn download_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.download", Self::key(url)))
+    }
+
+    /// Finds the cached archive of the repository at `url`. Archives which
+    /// are missing or whose details can't be read are treated as uncached.
+    pub async fn load(&self, url: &Url) -> Option<CachedArchive> {
+        let info = fs::read(self.info_path(url)).await.ok()?;
+        let archive = match serde_json::from_slice::<CachedArchive>(&info) {
+            Ok(archive) => archive,
+            Err(error) => {
+                debug!(%error, "Ignoring a cached archive with unreadable details");
+                return None;
+            }
+        };
+
+        let path = self.archive_path(url);
+        fs::try_exists(&path)
+            .await
+            .ok()?
+            .then_some(CachedArchive { path, ..archive })
+    }
+
+    /// Replaces the cached archive of the repository at `url` with the one
+    /// just downloaded to its [download path](Self::download_path).
+    pub async fn store(
+        &self,
+        url: &Url,
+        etag: &str,
+        content_type: Option<&str>,
+    ) -> io::Result<CachedArchive> {
+        // The details are removed first, so that an interrupted store can't
+        // leave the old details describing the new archive.
+        remove_if_exists(&self.info_path(url)).await?;
+        let path = self.archive_path(url);
+        fs::rename(self.download_path(url), &path).await?;
+
+        let archive = CachedArchive {
+            path,
+            etag: etag.into(),
+            content_type: content_type.map(Into::into),
+        };
+        fs::write(self.info_path(url), serde_json::to_vec(&archive)?).await?;
+
+        Ok(archive)
+    }
+
+    /// Removes the cached archive of the repository at `url`, such as when the
+    /// server stops sending an ETag for it.
+    pub async fn remove(&self, url: &Url) -> io::Result<()> {
+        remove_if_exists(&self.info_path(url)).await?;
+        remove_if_exists(&self.archive_path(url)).await
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stores_archives_by_url() {
+        let dir = env::temp_dir().join(format!("camrete-archive-cache-{}", process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let cache = ArchiveCache::new(dir.clone());
+        let url = Url::parse("https://example.com/repo.tar.gz").unwrap();
+        let other = Url::parse("https://example.com/other.tar.gz").unwrap();
+        assert_eq!(cache.load(&url).await, None);
+
+        fs::write(cache.download_path(&url), b"archive")
+            .await
+            .unwrap();
+        let stored = cache
+            .store(&url, "\"abc\"", Some("application/gzip"))
+            .await
+            .unwrap();
+        assert_eq!(cache.load(&url).await, Some(stored.clone()));
+        assert_eq!(fs::read(&stored.path).await.unwrap(), b"archive");
+        assert_eq!(cache.load(&other).await, None);
+
+        let respelled = Url::parse("https://EXAMPLE.com/repo.tar.gz#latest").unwrap();
+        assert_eq!(cache.load(&respelled).await, Some(stored.clone()));
+
+        cache.remove(&url).await.unwrap();
+        assert_eq!(cache.load(&url).await, None);
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -5,26 +5,34 @@
 //! is received. The loaders don't need the entire archive's contents to start unpacking, making
 //! them ideal for using with internet downloads.
 //!
+//! Use [`RepoFormat::sniff`] to find out which format an archive is in, and a
+//! [`FormatRegistry`] to find the loader for that format.
+//!
 //! Assets can then be parsed into [`RepoAsset`]s for usage or inclusion in a database.
 
 use std::{
     collections::HashMap,
-    io::Cursor,
-    path::{Path, PathBuf},
+    io::{self, Cursor},
+    path::{Component, Path, PathBuf},
+    pin::Pin,
 };
 
 use async_compression::tokio::bufread::GzipDecoder;
 use derive_more::From;
 use futures_core::stream::BoxStream;
-use futures_util::{StreamExt, TryStreamExt};
+use futures_util::{StreamExt, TryStreamExt, stream};
 use strum::EnumDiscriminants;
-use tokio::io::{AsyncBufRead, AsyncReadExt};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, Chain};
 use tokio_tar::Archive;
+use tracing::warn;
 
+pub use crate::io::ReadAhead;
 use crate::{
     Error, Result,
-    database::models::BuildRecord,
+    database::{RepoTerms, models::BuildRecord},
+    io::AsyncReadExt as _,
     json::{JsonModule, RepositoryRefList},
+    repo::RepoUnpackError,
 };
 
 /// A parsed asset contained in a repository archive.
@@ -43,8 +51,20 @@ pub enum RepoAsset {
     DownloadCounts(HashMap<String, i32>),
     /// A list of other repositories which this repo suggests using.
     RepositoryRefList(RepositoryRefList),
+    /// A license or terms of use covering the repository's contents.
+    Terms(RepoTerms),
 }
 
+/// The names of files which hold a repository's license or terms of use.
+const TERMS_FILES: &[&[u8]] = &[
+    b"LICENSE",
+    b"LICENSE.md",
+    b"LICENSE.txt",
+    b"COPYING",
+    b"TERMS",
+    b"TERMS.md",
+];
+
 impl RepoAssetVariant {
     /// Determine which asset the given file contains based on its path.
     pub fn from_path(path: &Path) -> Option<Self> {
@@ -55,9 +75,21 @@ impl RepoAssetVariant {
             b"repositories.json" => Self::RepositoryRefList,
             b"download_counts.json" => Self::DownloadCounts,
             name if name.ends_with(b".ckan") => Self::Release,
+            // Only the repository's own terms count, not ones inside a
+            // module's folder. The archive may be wrapped in one folder.
+            name if TERMS_FILES.contains(&name) && path.components().count() <= 2 => Self::Terms,
             _ => return None,
         })
     }
+
+    /// Like [`Self::from_path`], for a path in a repository which isn't
+    /// wrapped in a folder, such as a Git checkout.
+    pub fn from_root_path(path: &Path) -> Option<Self> {
+        match Self::from_path(path)? {
+            Self::Terms if path.components().count() > 1 => None,
+            variant => Some(variant),
+        }
+    }
 }
 
 /// A byte buffer containing the serialized data for an asset.
@@ -77,9 +109,280 @@ pub trait RepoAssetLoader<'a> {
     fn asset_stream(self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>>;
 }
 
+/// The ways a repository's assets can be packaged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RepoFormat {
+    /// An uncompressed tar archive, unpacked by [`TarAssetLoader`].
+    Tar,
+    /// A gzipped tar archive, unpacked by [`TarGzAssetLoader`].
+    TarGz,
+    /// A single gzipped asset such as `download_counts.json.gz`, unpacked by
+    /// [`GzipAssetLoader`].
+    Gzip,
+    /// A zip archive.
+    Zip,
+}
+
+/// The number of bytes read from the start of a stream to identify its format.
+const SNIFF_LEN: u64 = 4096;
+
+/// The smallest amount of decompressed data needed to recognize a tar archive.
+const TAR_HEADER_LEN: usize = 512;
+
+impl RepoFormat {
+    /// The name of the format, as used in messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::Gzip => "gzip",
+            Self::Zip => "zip",
+        }
+    }
+
+    /// Identifies the format of an archive from its first few bytes, so that
+    /// servers which mislabel their downloads can still be unpacked.
+    ///
+    /// Returns the format (if it could be identified) along with a stream
+    /// which yields the entire archive, including the bytes already read.
+    pub async fn sniff<R: AsyncBufRead + Unpin>(
+        mut stream: R,
+    ) -> io::Result<(Option<Self>, Chain<Cursor<Vec<u8>>, R>)> {
+        let mut prefix = Vec::new();
+        (&mut stream).take(SNIFF_LEN).read_to_end(&mut prefix).await?;
+
+        let format = Self::from_magic(&prefix).await;
+
+        Ok((format, Cursor::new(prefix).chain(stream)))
+    }
+
+    async fn from_magic(prefix: &[u8]) -> Option<Self> {
+        if prefix.starts_with(b"PK\x03\x04") {
+            return Some(Self::Zip);
+        }
+
+        if is_tar(prefix) {
+            return Some(Self::Tar);
+        }
+
+        if !prefix.starts_with(&[0x1f, 0x8b]) {
+            return None;
+        }
+
+        // Decompress as much of the prefix as possible to see whether it holds a
+        // tar archive. The prefix is a truncated gzip stream, so this will always
+        // end in an error once the available data runs out.
+        let mut decoder = GzipDecoder::new(prefix);
+        let mut header = Vec::with_capacity(TAR_HEADER_LEN);
+        let mut buf = [0; TAR_HEADER_LEN];
+
+        while header.len() < TAR_HEADER_LEN {
+            match decoder.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => header.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        Some(if is_tar(&header) {
+            Self::TarGz
+        } else {
+            Self::Gzip
+        })
+    }
+}
+
+/// A repository archive to be unpacked by a loader from a [`FormatRegistry`].
+pub struct PackedRepo {
+    /// The contents of the archive.
+    pub stream: Pin<Box<dyn AsyncBufRead + Send>>,
+    /// The path of the asset held by an archive of a single asset, which the
+    /// archive itself doesn't record.
+    pub asset_path: PathBuf,
+    /// Assets over this many bytes aren't read into memory.
+    pub max_asset_size: usize,
+}
+
+/// Creates the asset stream of an archive in a particular format.
+pub type LoaderFn = fn(PackedRepo) -> Result<BoxStream<'static, Result<RepoAssetBuf>>>;
+
+/// The loaders used to unpack archives of each [`RepoFormat`].
+///
+/// The default registry unpacks tar, tar.gz and single gzipped assets. Zip
+/// archives are recognized by [`RepoFormat::sniff`], but have no loader.
+#[derive(Debug, Clone)]
+pub struct FormatRegistry {
+    loaders: HashMap<RepoFormat, LoaderFn>,
+}
+
+impl FormatRegistry {
+    /// Creates a registry without any loaders.
+    pub fn empty() -> Self {
+        Self {
+            loaders: HashMap::new(),
+        }
+    }
+
+    /// Unpacks archives in `format` with `loader`, replacing the loader
+    /// previously registered for it.
+    pub fn register(&mut self, format: RepoFormat, loader: LoaderFn) {
+        self.loaders.insert(format, loader);
+    }
+
+    /// Returns the loader for archives in `format`, if one is registered.
+    pub fn loader(&self, format: RepoFormat) -> Option<LoaderFn> {
+        self.loaders.get(&format).copied()
+    }
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+
+        registry.register(RepoFormat::Tar, |repo| {
+            TarAssetLoader::new(repo.stream)
+                .with_max_asset_size(repo.max_asset_size)
+                .asset_stream()
+        });
+        registry.register(RepoFormat::TarGz, |repo| {
+            TarGzAssetLoader::new(repo.stream)
+                .with_max_asset_size(repo.max_asset_size)
+                .decompress_in_background()
+                .asset_stream()
+        });
+        registry.register(RepoFormat::Gzip, |repo| {
+            GzipAssetLoader::new(repo.asset_path, repo.stream).asset_stream()
+        });
+
+        registry
+    }
+}
+
+/// Checks for the `ustar` magic string in a tar header, which is used by both
+/// POSIX and GNU tar archives.
+fn is_tar(header: &[u8]) -> bool {
+    header.get(257..262) == Some(b"ustar")
+}
+
+/// Checks that a path from an archive stays inside the archive, and removes
+/// any `.` components from it.
+///
+/// Assets are found by their file names, so the archive's contents can be
+/// wrapped in any number of folders (like the `CKAN-meta-master/` folder of a
+/// GitHub download) or none at all.
+fn archive_path(path: &Path) -> Result<PathBuf, RepoUnpackError> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(RepoUnpackError::UnsafePath {
+                    path: path.to_owned(),
+                });
+            }
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Streams the assets contained in a tar archive.
+///
+/// An asset over `max_asset_size` bytes isn't read into memory. Instead, the
+/// stream gives a [`RepoUnpackError::AssetTooLarge`] for it, and carries on
+/// with the next one.
+fn tar_asset_stream<'a, R: AsyncRead + Unpin + Send + 'a>(
+    mut archive: Archive<R>,
+    max_asset_size: usize,
+) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+    let entries = archive.entries()?;
+
+    Ok(entries
+        .map_err(Error::from)
+        .try_filter_map(move |mut item| async move {
+            let path = archive_path(&item.path()?)?;
+            let Some(variant) = RepoAssetVariant::from_path(path.as_ref()) else {
+                return Ok(None);
+            };
+
+            // Links have no contents of their own, and could point anywhere.
+            let entry_type = item.header().entry_type();
+            if !entry_type.is_file() {
+                if entry_type.is_symlink() || entry_type.is_hard_link() {
+                    warn!(?path, "Skipping a linked asset");
+                }
+                return Ok(None);
+            }
+
+            // The header is checked first so that a huge asset is never
+            // read, and the read is limited in case the header is wrong.
+            let limit = max_asset_size as u64;
+            let size = item.header().size()?;
+            if size > limit {
+                return Err(RepoUnpackError::AssetTooLarge {
+                    path,
+                    size,
+                    limit: max_asset_size,
+                }
+                .into());
+            }
+
+            let mut buf = Vec::with_capacity(size as usize);
+            (&mut item).take(limit.saturating_add(1)).read_to_end(&mut buf).await?;
+            if buf.len() > max_asset_size {
+                return Err(RepoUnpackError::AssetTooLarge {
+                    path,
+                    size: buf.len() as u64,
+                    limit: max_asset_size,
+                }
+                .into());
+            }
+
+            let asset = RepoAssetBuf {
+                variant,
+                path,
+                data: buf.into_boxed_slice(),
+            };
+
+            Ok(Some(asset))
+        })
+        .boxed())
+}
+
+/// Unpacks a streamed, uncompressed tar archive of a repository.
+pub struct TarAssetLoader<R: AsyncRead + Unpin> {
+    archive: Archive<R>,
+    max_asset_size: usize,
+}
+
+impl<R: AsyncRead + Unpin> TarAssetLoader<R> {
+    /// Create the loader using a byte stream such as a download.
+    pub fn new(stream: R) -> Self {
+        Self {
+            archive: Archive::new(stream),
+            max_asset_size: usize::MAX,
+        }
+    }
+
+    /// Skips assets over `limit` bytes without reading them into memory. The
+    /// asset stream gives a [`RepoUnpackError::AssetTooLarge`] for each one.
+    pub fn with_max_asset_size(mut self, limit: usize) -> Self {
+        self.max_asset_size = limit;
+        self
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin + Send + 'a> RepoAssetLoader<'a> for TarAssetLoader<R> {
+    fn asset_stream(self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+        tar_asset_stream(self.archive, self.max_asset_size)
+    }
+}
+
 /// Unpacks a streamed gzipped tar archive of a repository.
 pub struct TarGzAssetLoader<R: AsyncBufRead + Unpin> {
-    archive: Archive<GzipDecoder<R>>,
+    decoder: GzipDecoder<R>,
+    max_asset_size: usize,
 }
 
 impl<R: AsyncBufRead + Unpin> TarGzAssetLoader<R> {
@@ -91,46 +394,82 @@ impl<R: AsyncBufRead + Unpin> TarGzAssetLoader<R> {
     /// For files already completely in memory, use [`Self::from_buf`].
     pub fn new(stream: R) -> Self {
         Self {
-            archive: Archive::new(GzipDecoder::new(stream)),
+            decoder: GzipDecoder::new(stream),
+            max_asset_size: usize::MAX,
         }
     }
+
+    /// Skips assets over `limit` bytes, like
+    /// [`TarAssetLoader::with_max_asset_size`].
+    pub fn with_max_asset_size(mut self, limit: usize) -> Self {
+        self.max_asset_size = limit;
+        self
+    }
+}
+
+impl<R: AsyncBufRead + Unpin + Send + 'static> TarGzAssetLoader<R> {
+    /// Decompresses the archive on a separate task, so that decompression isn't
+    /// held up by whatever is consuming the assets. A bounded amount of
+    /// decompressed data is buffered.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn decompress_in_background(self) -> TarAssetLoader<ReadAhead> {
+        TarAssetLoader::new(self.decoder.read_ahead()).with_max_asset_size(self.max_asset_size)
+    }
 }
 
 impl<T: AsRef<[u8]> + Unpin> TarGzAssetLoader<Cursor<T>> {
     /// Creates a tar asset loader from an archive already completely in memory,
     /// such as a [`Vec<u8>`] or byte slice.
     pub fn from_buf(buf: T) -> Self {
-        let cursor = Cursor::new(buf);
-        Self {
-            archive: Archive::new(GzipDecoder::new(cursor)),
-        }
+        Self::new(Cursor::new(buf))
     }
 }
 
 impl<'a, R: AsyncBufRead + Unpin + Send + 'a> RepoAssetLoader<'a> for TarGzAssetLoader<R> {
-    fn asset_stream(mut self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
-        let entries = self.archive.entries()?;
+    fn asset_stream(self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+        tar_asset_stream(Archive::new(self.decoder), self.max_asset_size)
+    }
+}
 
-        Ok(entries
-            .map_err(Error::from)
-            .try_filter_map(async |mut item| {
-                let path = item.path()?.into_owned();
-                let Some(variant) = RepoAssetVariant::from_path(path.as_ref()) else {
-                    return Ok(None);
-                };
+/// Unpacks a single gzipped asset, such as `download_counts.json.gz`.
+pub struct GzipAssetLoader<R: AsyncBufRead + Unpin> {
+    path: PathBuf,
+    decoder: GzipDecoder<R>,
+}
 
-                let mut buf = Vec::new();
-                item.read_to_end(&mut buf).await?;
+impl<R: AsyncBufRead + Unpin> GzipAssetLoader<R> {
+    /// Create the loader using a byte stream such as a download.
+    ///
+    /// A gzip file doesn't reliably record the name of the file it contains, so
+    /// the asset's `path` (for example, `download_counts.json`) must be
+    /// provided to tell which kind of asset it is.
+    pub fn new(path: PathBuf, stream: R) -> Self {
+        Self {
+            path,
+            decoder: GzipDecoder::new(stream),
+        }
+    }
+}
 
-                let asset = RepoAssetBuf {
-                    variant,
-                    path,
-                    data: buf.into_boxed_slice(),
-                };
+impl<'a, R: AsyncBufRead + Unpin + Send + 'a> RepoAssetLoader<'a> for GzipAssetLoader<R> {
+    fn asset_stream(mut self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+        let Some(variant) = RepoAssetVariant::from_path(&self.path) else {
+            return Err(RepoUnpackError::UnknownAsset { path: self.path }.into());
+        };
+
+        let asset = async move {
+            let mut buf = Vec::new();
+            self.decoder.read_to_end(&mut buf).await?;
 
-                Ok(Some(asset))
+            Ok(RepoAssetBuf {
+                variant,
+                path: self.path,
+                data: buf.into_boxed_slice(),
             })
-            .boxed())
+        };
+
+        Ok(stream::once(asset).boxed())
     }
 }
 
@@ -161,8 +500,76 @@ impl<'a> RepoAssetLoader<'a> for InMemoryAssetLoader {
     }
 }
 
+/// Reads the assets of a repository which is unpacked in a directory, such as
+/// a Git checkout. Hidden files and folders, like `.git`, are skipped.
+#[derive(Debug, Clone)]
+pub struct DirAssetLoader {
+    root: PathBuf,
+}
+
+impl DirAssetLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl<'a> RepoAssetLoader<'a> for DirAssetLoader {
+    fn asset_stream(self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+        let mut files = vec![];
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                if entry.file_name().as_encoded_bytes().starts_with(b".") {
+                    continue;
+                }
+
+                let path = entry.path();
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                // Links are skipped, as they are in archives.
+                if !file_type.is_file() {
+                    continue;
+                }
+
+                let relative = path
+                    .strip_prefix(&self.root)
+                    .expect("files are inside the root")
+                    .to_path_buf();
+                if let Some(variant) = RepoAssetVariant::from_root_path(&relative) {
+                    files.push((path, relative, variant));
+                }
+            }
+        }
+        files.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let stream = stream::iter(files).then(|(path, relative, variant)| async move {
+            let data = tokio::fs::read(&path).await?;
+            Ok::<_, Error>(RepoAssetBuf {
+                path: relative,
+                variant,
+                data: data.into_boxed_slice(),
+            })
+        });
+        Ok(stream.boxed())
+    }
+}
+
+/// A stream of assets which have already been unpacked by another loader.
+impl<'a> RepoAssetLoader<'a> for BoxStream<'a, Result<RepoAssetBuf>> {
+    fn asset_stream(self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+        Ok(self)
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod test {
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio_tar::{Builder, EntryType, Header};
+
     use super::*;
 
     #[test]
@@ -171,6 +578,7 @@ pub(crate) mod test {
         let p2 = PathBuf::from("CKAN-meta-master/repositories.json");
         let p3 = PathBuf::from("builds.json");
         let p4 = PathBuf::from("/absolute/path/to/download_counts.json");
+        let p5 = PathBuf::from("CKAN-meta-master/LICENSE.md");
 
         assert_eq!(
             RepoAssetVariant::from_path(&p1).unwrap(),
@@ -188,6 +596,11 @@ pub(crate) mod test {
             RepoAssetVariant::from_path(&p4).unwrap(),
             RepoAssetVariant::DownloadCounts
         );
+        assert_eq!(
+            RepoAssetVariant::from_path(&p5).unwrap(),
+            RepoAssetVariant::Terms
+        );
+        assert!(RepoAssetVariant::from_path(Path::new("root/Parallax/LICENSE")).is_none());
     }
 
     #[test]
@@ -217,6 +630,239 @@ pub(crate) mod test {
         stream.try_collect::<Vec<_>>().await.unwrap()
     }
 
+    async fn decompress(buf: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        GzipDecoder::new(buf).read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    async fn compress(buf: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        GzipEncoder::new(buf).read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn sniff_formats() {
+        let tgz = include_bytes!("../../benches/mini_repo.tgz");
+        let tar = decompress(tgz).await;
+        let gzip = compress(br#"{"Parallax": 100}"#).await;
+
+        let (format, _) = RepoFormat::sniff(&tgz[..]).await.unwrap();
+        assert_eq!(format, Some(RepoFormat::TarGz));
+
+        let (format, _) = RepoFormat::sniff(&tar[..]).await.unwrap();
+        assert_eq!(format, Some(RepoFormat::Tar));
+
+        let (format, _) = RepoFormat::sniff(&gzip[..]).await.unwrap();
+        assert_eq!(format, Some(RepoFormat::Gzip));
+
+        let (format, _) = RepoFormat::sniff(&b"<html></html>"[..]).await.unwrap();
+        assert_eq!(format, None);
+    }
+
+    #[tokio::test]
+    async fn sniffing_preserves_data() {
+        let tgz = include_bytes!("../../benches/mini_repo.tgz");
+
+        let (_, mut stream) = RepoFormat::sniff(&tgz[..]).await.unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+
+        assert_eq!(data, tgz);
+    }
+
+    #[tokio::test]
+    async fn registry_loads_sniffed_formats() {
+        let tgz = include_bytes!("../../benches/mini_repo.tgz");
+        let tar = decompress(tgz).await;
+        let gzip = compress(br#"{"Parallax": 100}"#).await;
+        let registry = FormatRegistry::default();
+        let expected = load_test_repo().await.len();
+
+        for (archive, count) in [(tgz.to_vec(), expected), (tar, expected), (gzip, 1)] {
+            let (format, stream) = RepoFormat::sniff(Cursor::new(archive)).await.unwrap();
+            let loader = registry.loader(format.unwrap()).unwrap();
+            let assets = loader(PackedRepo {
+                stream: Box::pin(stream),
+                asset_path: "download_counts.json".into(),
+                max_asset_size: usize::MAX,
+            })
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+            assert_eq!(assets.len(), count, "{format:?}");
+        }
+
+        assert!(registry.loader(RepoFormat::Zip).is_none());
+        assert!(FormatRegistry::empty().loader(RepoFormat::Tar).is_none());
+    }
+
+    #[tokio::test]
+    async fn load_plain_tar() {
+        let tar = decompress(include_bytes!("../../benches/mini_repo.tgz")).await;
+
+        let assets = TarAssetLoader::new(&tar[..])
+            .asset_stream()
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(assets.len(), load_test_repo().await.len());
+    }
+
+    #[tokio::test]
+    async fn load_tgz_in_background() {
+        let repo_buf = include_bytes!("../../benches/mini_repo.tgz");
+
+        let assets = TarGzAssetLoader::from_buf(repo_buf)
+            .decompress_in_background()
+            .asset_stream()
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let expected = load_test_repo().await;
+        assert_eq!(assets.len(), expected.len());
+        for (asset, expected) in assets.iter().zip(&expected) {
+            assert_eq!(asset.path, expected.path);
+            assert_eq!(asset.data, expected.data);
+        }
+    }
+
+    #[tokio::test]
+    async fn load_gzipped_asset() {
+        let json = br#"{"Parallax": 100}"#;
+        let gzip = compress(json).await;
+
+        let loader = GzipAssetLoader::new("download_counts.json".into(), &gzip[..]);
+        let assets = loader
+            .asset_stream()
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(assets.len(), 1);
+        assert_eq!(assets[0].variant, RepoAssetVariant::DownloadCounts);
+        assert_eq!(&*assets[0].data, json);
+    }
+
+    #[test]
+    fn gzipped_asset_needs_known_name() {
+        let loader = GzipAssetLoader::new("unknown.json".into(), &[][..]);
+        assert!(loader.asset_stream().is_err());
+    }
+
+    /// Builds a tar archive from `(path, type, contents)` entries. Paths are
+    /// written as-is, so that archives which `tokio_tar` would refuse to
+    /// create can be tested. The contents of links are their targets.
+    async fn crafted_tar(entries: &[(&str, EntryType, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for &(path, entry_type, contents) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(entry_type);
+            header.set_mode(0o644);
+
+            let data = if entry_type.is_file() {
+                contents.as_bytes()
+            } else {
+                header.set_link_name(contents).unwrap();
+                &[]
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+
+            builder.append(&header, data).await.unwrap();
+        }
+
+        builder.into_inner().await.unwrap()
+    }
+
+    async fn load_tar(tar: &[u8]) -> Result<Vec<RepoAssetBuf>> {
+        TarAssetLoader::new(tar).asset_stream()?.try_collect().await
+    }
+
+    #[tokio::test]
+    async fn any_archive_root() {
+        for root in ["", "./", "CKAN-meta-master/", "mirror/nested/"] {
+            let release = format!("{root}Parallax/Parallax-1.0.ckan");
+            let counts = format!("{root}download_counts.json");
+            let tar = crafted_tar(&[
+                (&release, EntryType::Regular, "{}"),
+                (&counts, EntryType::Regular, "{}"),
+            ])
+            .await;
+
+            let assets = load_tar(&tar).await.unwrap();
+            assert_eq!(assets.len(), 2, "{root}");
+            assert_eq!(assets[0].variant, RepoAssetVariant::Release);
+            assert_eq!(assets[1].variant, RepoAssetVariant::DownloadCounts);
+
+            let root = root.trim_start_matches("./");
+            assert_eq!(assets[0].path, Path::new(root).join("Parallax/Parallax-1.0.ckan"));
+        }
+    }
+
+    #[tokio::test]
+    async fn traversal_is_rejected() {
+        for path in ["../Evil/Evil-1.0.ckan", "root/../../Evil.ckan", "/etc/Evil.ckan"] {
+            let tar = crafted_tar(&[(path, EntryType::Regular, "{}")]).await;
+
+            let err = load_tar(&tar).await.unwrap_err();
+            assert!(
+                matches!(err, Error::Network(RepoUnpackError::UnsafePath { .. })),
+                "{path}: {err:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn links_are_skipped() {
+        let tar = crafted_tar(&[
+            ("root/A/A-1.0.ckan", EntryType::Regular, "{}"),
+            ("root/B/B-1.0.ckan", EntryType::Symlink, "../../../etc/passwd"),
+            ("root/C/C-1.0.ckan", EntryType::Link, "root/A/A-1.0.ckan"),
+        ])
+        .await;
+
+        let assets = load_tar(&tar).await.unwrap();
+        assert_eq!(assets.len(), 1);
+        assert_eq!(assets[0].path, Path::new("root/A/A-1.0.ckan"));
+    }
+
+    #[tokio::test]
+    async fn large_assets_are_not_read() {
+        let big = "x".repeat(64);
+        let tar = crafted_tar(&[
+            ("A/A-1.0.ckan", EntryType::Regular, &big),
+            ("B/B-1.0.ckan", EntryType::Regular, "{}"),
+        ])
+        .await;
+
+        let results = TarAssetLoader::new(&tar[..])
+            .with_max_asset_size(16)
+            .asset_stream()
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        // The stream carries on past the large asset.
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            &results[0],
+            Err(Error::Network(RepoUnpackError::AssetTooLarge { path, size: 64, limit: 16 }))
+                if path == Path::new("A/A-1.0.ckan")
+        ));
+        assert_eq!(results[1].as_ref().unwrap().path, Path::new("B/B-1.0.ckan"));
+    }
+
     #[tokio::test]
     async fn load_tgz_in_memory() {
         let assets = load_test_repo().await;
@@ -227,4 +873,41 @@ pub(crate) mod test {
                 .any(|a| a.variant == RepoAssetVariant::Release)
         );
     }
+
+    #[tokio::test]
+    async fn load_directory() {
+        let dir = std::env::temp_dir().join(format!("camrete-dir-assets-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, contents) in [
+            ("Parallax/Parallax-1.0.ckan", "{}"),
+            ("Parallax/LICENSE", "MIT"),
+            ("LICENSE.md", "CC-BY"),
+            ("builds.json", "{}"),
+            (".git/objects/Evil-1.0.ckan", "{}"),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let assets: Vec<RepoAssetBuf> = DirAssetLoader::new(&dir)
+            .asset_stream()
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let paths = assets.iter().map(|a| a.path.as_path()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                Path::new("LICENSE.md"),
+                Path::new("Parallax/Parallax-1.0.ckan"),
+                Path::new("builds.json"),
+            ]
+        );
+        assert_eq!(assets[0].variant, RepoAssetVariant::Terms);
+        assert_eq!(&*assets[2].data, b"{}");
+    }
 }
@@ -0,0 +1,150 @@
+//! Opening a database without waiting for it to be upgraded.
+
+use std::{panic, path};
+
+use diesel::{prelude::*, sql_query, sql_types::Text};
+use tokio::task::{JoinHandle, spawn_blocking};
+use tracing::{debug, info, instrument};
+use url::Url;
+
+use crate::{
+    Error, Result,
+    repo::{RepoManager, RepoManagerBuilder},
+};
+
+/// A database which is being upgraded and checked in the background.
+///
+/// Until it's ready, a read-only view of the database as it was before the
+/// upgrade may be available from [`Self::stale`]. Queries on this view can
+/// fail if they use tables which the upgrade changes.
+#[derive(Debug)]
+pub struct PendingRepoManager {
+    stale: Option<RepoManager>,
+    task: JoinHandle<Result<RepoManager>>,
+}
+
+impl PendingRepoManager {
+    /// A read-only view of the database, or `None` if the database didn't
+    /// exist before it was opened.
+    pub fn stale(&self) -> Option<&RepoManager> {
+        self.stale.as_ref()
+    }
+
+    /// Returns whether the upgrade has finished, successfully or not.
+    pub fn is_ready(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the upgrade to finish, then returns the up-to-date database.
+    pub async fn ready(self) -> Result<RepoManager> {
+        match self.task.await {
+            Ok(result) => result.map_err(|e| Error::BackgroundUpgrade(Box::new(e))),
+            Err(e) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct QuickCheck {
+    #[diesel(sql_type = Text)]
+    quick_check: String,
+}
+
+impl RepoManagerBuilder {
+    /// Starts opening the database on a background thread, returning a
+    /// read-only view of it straight away. This avoids waiting for large
+    /// databases to be upgraded when the program starts.
+    ///
+    /// Must be called from within a Tokio runtime.
+    #[instrument(skip(self))]
+    pub fn build_in_background(self) -> PendingRepoManager {
+        let stale = match self.open_read_only() {
+            Ok(stale) => Some(stale),
+            Err(error) => {
+                debug!(%error, "No read-only view of the database is available");
+                None
+            }
+        };
+
+        let task = spawn_blocking(move || {
+            let mgr = self.build()?;
+
+            let check = sql_query("PRAGMA quick_check")
+                .get_result::<QuickCheck>(&mut mgr.db()?.connection)?;
+            if check.quick_check != "ok" {
+                return Err(Error::DatabaseCorrupt(check.quick_check));
+            }
+
+            info!("Database is ready");
+            Ok(mgr)
+        });
+
+        PendingRepoManager { stale, task }
+    }
+
+    fn open_read_only(&self) -> Result<RepoManager> {
+        let mut url = if self.url().starts_with("file:") {
+            crate::parse_url(self.url())?
+        } else {
+            Url::from_file_path(path::absolute(self.url())?).expect("path is absolute")
+        };
+        url.query_pairs_mut().append_pair("mode", "ro");
+
+        // Fail quickly if the database doesn't exist, instead of waiting for the pool
+        // to time out.
+        let mut conn = SqliteConnection::establish(url.as_str())?;
+        self.connection_options().apply(&mut conn)?;
+        drop(conn);
+
+        let pool = self.pool(url.as_str())?;
+        Ok(self.clone().manager(pool))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn temp_file(name: &str) -> path::PathBuf {
+        let path = env::temp_dir().join(format!("camrete-{name}-{}.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn stale_view_is_read_only() {
+        let path = temp_file("stale-view-is-read-only");
+        let url = path.to_string_lossy();
+
+        RepoManager::new(&url)
+            .unwrap()
+            .db()
+            .unwrap()
+            .all_repos(true)
+            .unwrap();
+
+        let pending = RepoManager::builder(&url).build_in_background();
+        let mut stale = pending.stale().unwrap().db().unwrap();
+        assert_eq!(stale.all_repos(false).unwrap().len(), 1);
+        assert!(stale.set_prerelease_default(true).is_err());
+        drop(stale);
+
+        let mgr = pending.ready().await.unwrap();
+        mgr.db().unwrap().set_prerelease_default(true).unwrap();
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_database_has_no_stale_view() {
+        let path = temp_file("new-database-has-no-stale-view");
+
+        let pending = RepoManager::builder(&path.to_string_lossy()).build_in_background();
+        assert!(pending.stale().is_none());
+        pending.ready().await.unwrap();
+
+        fs::remove_file(&path).unwrap();
+    }
+}
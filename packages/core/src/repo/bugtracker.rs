@@ -0,0 +1,80 @@
+//! Checking whether a module's bug tracker can still be reached, as a sign of
+//! whether the module is maintained.
+
+use reqwest::{StatusCode, header::HeaderMap};
+use tracing::{debug, instrument};
+
+use crate::{Result, database::models::ModuleRelease, repo::RepoManager};
+
+impl RepoManager {
+    /// Checks whether the bug tracker a release links to can still be
+    /// reached, and saves the result so that it counts towards the module's
+    /// [staleness](crate::database::Staleness).
+    ///
+    /// A bug tracker is only recorded as gone if its server says it's missing.
+    /// Other errors are returned, and other statuses aren't recorded, since
+    /// they don't say much about the bug tracker. Returns `None` if nothing
+    /// was recorded.
+    #[instrument(skip(self, release))]
+    pub async fn check_bugtracker(
+        &self,
+        slug: &str,
+        release: &ModuleRelease,
+    ) -> Result<Option<bool>> {
+        let Some(url) = &release.metadata.resources.bugtracker else {
+            return Ok(None);
+        };
+
+        let status = self.transport.get(url, HeaderMap::new()).await?.status;
+        let alive = match status {
+            status if status.is_success() => true,
+            StatusCode::NOT_FOUND | StatusCode::GONE => false,
+            status => {
+                debug!(%url, %status, "Couldn't tell whether the bug tracker is alive");
+                return Ok(None);
+            }
+        };
+
+        self.db()?.record_bugtracker_check(slug, alive)?;
+        Ok(Some(alive))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::{Staleness, schema::module_releases},
+        repo::test_support::{MockRepo, RepoResponse, release},
+    };
+
+    #[tokio::test]
+    async fn records_missing_bug_trackers() {
+        let server = MockRepo::start().await;
+        server.serve("/issues", RepoResponse::status(404)).await;
+        let (mgr, repo) = server.manager("/repo.tar.gz");
+
+        let release = release("Parallax", "1.0")
+            .set("resources", json!({ "bugtracker": server.url("/issues") }))
+            .build();
+        let mut db = mgr.db().unwrap();
+        let (module, release_id) = db.create_release(&release, repo.id, None).unwrap();
+        let release = ModuleRelease::all()
+            .filter(module_releases::release_id.eq(release_id))
+            .get_result::<ModuleRelease>(db.connection_mut())
+            .unwrap();
+        db.update_staleness().unwrap();
+        drop(db);
+
+        assert_eq!(
+            mgr.check_bugtracker("Parallax", &release).await.unwrap(),
+            Some(false)
+        );
+        let staleness = mgr.db().unwrap().module_staleness(module).unwrap().unwrap();
+        assert_eq!(staleness.bugtracker_alive, Some(false));
+        assert_eq!(staleness.staleness, Staleness::Dormant);
+    }
+}
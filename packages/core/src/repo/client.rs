@@ -1,190 +1,696 @@
+#[cfg(feature = "network")]
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    path::PathBuf,
-    sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering},
-    },
+    collections::{BTreeSet, HashMap, HashSet},
+    io,
+    path::Path,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
 };
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
 
+#[cfg(feature = "network")]
+use diesel::delete;
 use diesel::{
     connection::SimpleConnection,
-    delete,
     prelude::*,
-    r2d2::{ConnectionManager, Pool},
+    r2d2::{ConnectionManager, CustomizeConnection, Pool},
 };
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
-use futures_util::TryStreamExt;
+#[cfg(feature = "network")]
+use futures_core::stream::BoxStream;
+#[cfg(feature = "network")]
+use futures_util::{StreamExt, TryStreamExt};
 use miette::Diagnostic;
+#[cfg(feature = "network")]
+use parking_lot::Mutex;
+#[cfg(feature = "network")]
 use reqwest::{
-    Response,
-    header::{ACCEPT, CONTENT_TYPE, ETAG, HeaderValue},
+    StatusCode,
+    header::{ACCEPT, ACCEPT_ENCODING, CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_NONE_MATCH},
 };
+#[cfg(feature = "network")]
+use serde::Deserialize;
+#[cfg(feature = "network")]
 use tokio::{
-    io::{self},
+    io::{AsyncBufRead, BufReader},
     spawn,
-    sync::mpsc,
-    task::JoinSet,
+    sync::{broadcast, mpsc},
+    task::{JoinHandle, JoinSet, spawn_blocking},
 };
+#[cfg(feature = "network")]
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tracing::{debug, info, instrument, trace};
+#[cfg(feature = "network")]
+use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 
 use crate::{
-    DIRS, DbConnection, DbPool, Error, Result, USER_AGENT,
+    DIRS, DbConnection, DbPool, Error, Result,
+    database::{
+        self, BackfillProgress, BackfillReporter, RepoDB, RetryPolicy, Snapshot,
+        models::module::{self, ModuleVersion},
+    },
+    json::JsonError,
+    policy::Policy,
+    repo::game::GameVersionParseError,
+};
+#[cfg(feature = "network")]
+use crate::{
+    USER_AGENT,
     database::{
-        RepoDB,
-        models::{BuildRecord, Repository, module::ModuleVersion},
+        DownloadCountMode, Metric, RepoId, RepoTerms, UpdateLock, asset_hash,
+        models::{BuildRecord, Repository},
     },
     io::AsyncReadExt as _,
-    json::{JsonBuilds, JsonError, JsonModule, RepositoryRefList},
+    json::{
+        self, Identifier, IdentifierError, JsonBuilds, JsonModule, ParseLimitError, ParseLimits,
+        RepositoryRefList,
+    },
     repo::{
-        RepoAsset, RepoAssetBuf, RepoAssetLoader, RepoAssetVariant, TarGzAssetLoader,
-        game::GameVersionParseError,
+        DownloadEvent, FormatRegistry, LoaderFn, PackedRepo, PendingRepoManager, ReleaseTransform,
+        RepoAsset, RepoAssetBuf, RepoAssetLoader, RepoAssetVariant, RepoFormat, SpoolOptions,
+        TransformRegistry, UpdatePreview,
+        archive_cache::ArchiveCache,
+        downloads,
+        encoding::{ACCEPTED_ENCODINGS, ContentEncoding},
+        git::GitSource,
+        in_flight::{InFlight, Join},
+        spool::Spool,
+        transport::{HttpResponse, HttpTransport, Redirect, ReqwestTransport, Transport},
     },
 };
+#[cfg(feature = "parse-timing")]
+use crate::repo::timing::{AssetTiming, SLOWEST_ASSETS, SlowestAssets};
+#[cfg(feature = "sqlcipher")]
+use crate::repo::{DatabaseKey, KeyProvider};
 
+#[cfg(feature = "network")]
 mod mime {
     pub const GZIP: &str = "application/gzip";
     pub const X_GZIP: &str = "application/x-gzip";
+    pub const X_TAR: &str = "application/x-tar";
     pub const ZIP: &str = "application/zip";
 }
 
 #[derive(Debug, Error, Diagnostic)]
 pub enum RepoUnpackError {
     #[error("cannot determine the repository's data format\n(from {url})")]
-    #[diagnostic(code(camrete::repo::download::content_type_missing))]
+    #[diagnostic(code(camrete::repo::download::content_type_missing), severity(Warning))]
     MissingContentType { url: Url },
     #[error("cannot unpack {content_type:?} resources\n(from {url})")]
-    #[diagnostic(code(camrete::repo::download::unsupported_format))]
+    #[diagnostic(code(camrete::repo::download::unsupported_format), severity(Warning))]
     UnsupportedContentType { content_type: String, url: Url },
+    #[error(
+        "the server compressed the repository with {encoding:?}, which isn't supported\n(from {url})"
+    )]
+    #[diagnostic(
+        code(camrete::repo::download::unsupported_encoding),
+        severity(Warning),
+        help("Camrete may have been built without the `http-compression` feature")
+    )]
+    UnsupportedEncoding { encoding: String, url: Url },
+    #[error("cannot unpack repositories packaged as {format} archives\n(from {url})")]
+    #[diagnostic(
+        code(camrete::repo::download::unsupported_archive),
+        severity(Warning),
+        help("use a .tar.gz archive of the repository instead")
+    )]
+    UnsupportedArchive { format: String, url: Url },
+    #[error("cannot tell which repository asset {path:?} contains")]
+    #[diagnostic(
+        code(camrete::repo::unknown_asset),
+        severity(Warning),
+        help("name the file after the asset it contains, like `download_counts.json.gz`")
+    )]
+    UnknownAsset { path: PathBuf },
+    #[error("the repository archive contains a file outside of it: {path:?}")]
+    #[diagnostic(
+        code(camrete::repo::unsafe_path),
+        severity(Warning),
+        help("the archive may have been tampered with")
+    )]
+    UnsafePath { path: PathBuf },
+    #[error("the repository document {path:?} is {size} bytes, over the limit of {limit}")]
+    #[diagnostic(code(camrete::repo::asset_too_large), severity(Warning))]
+    AssetTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: usize,
+    },
     #[error(transparent)]
-    #[diagnostic(code(camrete::repo::game_version_invalid))]
+    #[diagnostic(code(camrete::repo::game_version_invalid), severity(Warning))]
     GameVersionParse(#[from] GameVersionParseError),
     #[error(
         "a JSON document in the repository was invalid\n\tdocument path: {path}\n\t(from {url})"
     )]
-    #[diagnostic(code(camrete::repo::invalid_json))]
+    #[diagnostic(code(camrete::repo::invalid_json), severity(Warning))]
     InvalidJsonFile {
         source: JsonError,
         url: Arc<Url>,
         path: PathBuf,
     },
     #[error("the online repository's ETag was not valid UTF-8")]
-    #[diagnostic(code(camrete::repo::bad_etag))]
+    #[diagnostic(code(camrete::repo::bad_etag), severity(Warning))]
     InvalidEtag { url: Arc<Url> },
-    #[error("a release could not be saved to the database: {name:?}, {version:?}")]
-    #[diagnostic(code(camrete::repo::bad_release_save))]
-    InsertRelease {
-        name: String,
-        version: String,
-        source: diesel::result::Error,
-    },
-    #[error("couldn't attach download counts to modules")]
-    #[diagnostic(code(camrete::repo::bad_download_count_save))]
-    InsertDownloadCounts(#[source] diesel::result::Error),
-    #[error("couldn't save build ids")]
-    #[diagnostic(code(camrete::repo::bad_builds_save))]
-    InsertBuilds(#[source] diesel::result::Error),
-    #[error("couldn't save repository ref for {name:?} ({url})")]
-    #[diagnostic(code(camrete::repo::bad_repo_refs_save))]
-    InsertRepoRefs {
-        name: String,
-        url: Box<Url>,
-        source: diesel::result::Error,
-    },
 }
 
 const MAX_DB_CONNS: u32 = 16;
-const MIGRATIONS: EmbeddedMigrations = embed_migrations!("../../migrations");
+
+/// How long a repository stays locked for updating if its update never
+/// finishes, such as when the program crashes.
+#[cfg(feature = "network")]
+const UPDATE_LOCK_LEASE: Duration = Duration::from_secs(15 * 60);
+/// How often an update which is waiting for another one checks whether it's
+/// finished.
+#[cfg(feature = "network")]
+const UPDATE_LOCK_POLL: Duration = Duration::from_millis(500);
+/// How many releases a resumable update saves in each transaction.
+#[cfg(feature = "network")]
+const CHECKPOINT_BATCH: usize = 500;
+/// How long a server can take to connect, or to send any more of a response,
+/// before the request fails. See [`RepoManagerBuilder::read_timeout`].
+#[cfg(feature = "network")]
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub(crate) const MIGRATIONS: EmbeddedMigrations = embed_migrations!("../../migrations");
 
 #[derive(Debug, Clone)]
 pub struct RepoManager {
     database: DbPool,
-    http: reqwest::Client,
+    #[cfg(feature = "network")]
+    pub(super) transport: Transport,
+    retry_policy: RetryPolicy,
+    pub(super) policy: Arc<Policy>,
+    #[cfg(feature = "network")]
+    pub(super) download_count_mode: DownloadCountMode,
+    #[cfg(feature = "network")]
+    pub(super) parse_limits: ParseLimits,
+    #[cfg(feature = "network")]
+    spool: Option<SpoolOptions>,
+    #[cfg(feature = "network")]
+    pub(super) transforms: TransformRegistry,
+    #[cfg(feature = "network")]
+    formats: FormatRegistry,
+    #[cfg(feature = "network")]
+    pub(super) download_events: broadcast::Sender<DownloadEvent>,
+    #[cfg(feature = "network")]
+    wait_for_updates: bool,
+    #[cfg(feature = "network")]
+    updates_in_flight: InFlight<RepoId, UpdateReport, DownloadProgress>,
+    /// Where repository archives are saved for resumable updates, if updates
+    /// are resumable.
+    #[cfg(feature = "network")]
+    resumable_updates: Option<PathBuf>,
+    #[cfg(feature = "network")]
+    archive_cache: Option<ArchiveCache>,
+    #[cfg(feature = "network")]
+    pub(super) force_reunpack: bool,
+    /// Where Git checkouts of repositories are kept, if repositories are
+    /// updated from Git.
+    #[cfg(feature = "network")]
+    git_checkouts: Option<PathBuf>,
+    /// Archive downloads in progress, by URL, with where each is saved.
+    #[cfg(feature = "network")]
+    pub(super) downloads_in_flight: InFlight<Url, PathBuf, u64>,
+    #[cfg(feature = "sqlcipher")]
+    key: Option<DatabaseKey>,
 }
 
 impl RepoManager {
     pub async fn from_data_dir() -> Result<Self> {
-        let repos_file = DIRS.data_local_dir().join("repos.sqlite");
-        let url = Url::from_file_path(repos_file).expect("path is valid");
+        fs::create_dir_all(DIRS.data_local_dir())?;
 
+        let url = Url::from_file_path(Self::data_dir_path()).expect("path is valid");
         Self::new(url.as_str())
     }
 
-    pub fn new(url: &str) -> Result<Self> {
-        let manager = ConnectionManager::<SqliteConnection>::new(url);
-        let pool = Pool::builder().max_size(MAX_DB_CONNS).build(manager)?;
+    /// Like [`Self::from_data_dir`], but upgrades the database in the
+    /// background. See [`RepoManagerBuilder::build_in_background`].
+    #[cfg(feature = "network")]
+    pub fn from_data_dir_in_background() -> Result<PendingRepoManager> {
+        fs::create_dir_all(DIRS.data_local_dir())?;
 
-        let mut conn = pool.get()?;
+        let url = Url::from_file_path(Self::data_dir_path()).expect("path is valid");
+        Ok(Self::builder(url.as_str()).build_in_background())
+    }
 
-        // see https://fractaledmind.github.io/2023/09/07/enhancing-rails-sqlite-fine-tuning/
-        // sleep if the database is busy, this corresponds to up to 2 seconds sleeping
-        // time.
-        conn.batch_execute("PRAGMA busy_timeout = 2000;")?;
-        // better write-concurrency
-        conn.batch_execute("PRAGMA journal_mode = WAL;")?;
-        // fsync only in critical moments
-        conn.batch_execute("PRAGMA synchronous = NORMAL;")?;
-        // write WAL changes back every 1000 pages, for an in average 1MB WAL file.
-        // May affect readers if number is increased
-        conn.batch_execute("PRAGMA wal_autocheckpoint = 1000;")?;
-        // free some space by truncating possibly massive WAL files from the last run
-        conn.batch_execute("PRAGMA wal_checkpoint(TRUNCATE);")?;
+    /// The location of the database used by [`Self::from_data_dir`].
+    pub fn data_dir_path() -> PathBuf {
+        DIRS.data_local_dir().join("repos.sqlite")
+    }
 
-        conn.batch_execute("PRAGMA foreign_keys = ON;")?;
+    /// The usual directory for [cached repository
+    /// archives](RepoManager::set_archive_cache).
+    #[cfg(feature = "network")]
+    pub fn archive_cache_path() -> PathBuf {
+        DIRS.cache_dir().join("repos")
+    }
 
-        conn.register_collation("MODULE_VERSION", |left: &str, right: &str| {
-            ModuleVersion::from(left).cmp(&ModuleVersion::from(right))
-        })?;
+    /// The usual directory for [Git checkouts of
+    /// repositories](RepoManager::set_git_checkouts).
+    #[cfg(feature = "network")]
+    pub fn git_checkouts_path() -> PathBuf {
+        DIRS.cache_dir().join("git")
+    }
 
-        conn.run_pending_migrations(MIGRATIONS)
-            .map_err(Error::DbMigrations)?;
+    pub fn new(url: &str) -> Result<Self> {
+        Self::builder(url).build()
+    }
 
-        Ok(Self {
-            database: pool,
-            http: reqwest::Client::builder()
-                .user_agent(USER_AGENT)
-                .build()
-                .expect("http client initialized"),
-        })
+    /// Configure a repository manager before opening its database.
+    pub fn builder(url: &str) -> RepoManagerBuilder {
+        RepoManagerBuilder {
+            url: url.to_string(),
+            retry_policy: RetryPolicy::default(),
+            policy: Policy::default(),
+            backfill_progress: None,
+            #[cfg(feature = "network")]
+            download_count_mode: DownloadCountMode::default(),
+            #[cfg(feature = "network")]
+            parse_limits: ParseLimits::default(),
+            #[cfg(feature = "network")]
+            spool: None,
+            #[cfg(feature = "network")]
+            resumable_updates: None,
+            #[cfg(feature = "network")]
+            transforms: TransformRegistry::default(),
+            #[cfg(feature = "network")]
+            formats: FormatRegistry::default(),
+            #[cfg(feature = "network")]
+            transport: None,
+            #[cfg(feature = "network")]
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            #[cfg(feature = "sqlcipher")]
+            key: None,
+        }
     }
 
     pub fn db(&self) -> Result<RepoDB<DbConnection>, Error> {
-        Ok(RepoDB::new(self.database.get()?))
+        let db = RepoDB::new(self.database.get()?)
+            .with_retry_policy(self.retry_policy)
+            .with_policy(self.policy.clone());
+        #[cfg(feature = "sqlcipher")]
+        let db = db.with_key(self.key.clone());
+        Ok(db)
+    }
+
+    /// A view of the database which doesn't change while it's held, even if
+    /// repositories are updated meanwhile. Long reads, like exports, use one
+    /// so that they never see an update half done.
+    pub fn snapshot(&self) -> Result<RepoDB<Snapshot<DbConnection>>, Error> {
+        Ok(self.db()?.into_snapshot()?)
+    }
+
+    /// The policy which decides which modules can be saved, installed and
+    /// downloaded.
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Sets which modules can be saved, installed and downloaded. Releases
+    /// saved before the policy changed stay in the database until their
+    /// repository is next updated, but the resolver won't choose them.
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = Arc::new(policy);
+    }
+}
+
+#[cfg(feature = "network")]
+impl RepoManager {
+    /// Records a usage metric if telemetry is turned on. Failing to record one
+    /// isn't worth failing the operation it describes, so errors are only
+    /// logged.
+    pub(crate) fn record_metric(&self, metric: Metric, duration: Option<Duration>) {
+        let result = self
+            .db()
+            .and_then(|mut db| Ok(db.record_metric(metric, duration)?));
+
+        if let Err(error) = result {
+            warn!(%error, ?metric, "Failed to record a usage metric");
+        }
+    }
+
+    /// Sets whether [`Self::download`] waits for another program's update of
+    /// the same repository to finish, rather than failing with
+    /// [`Error::UpdateAlreadyInProgress`]. While it waits, its progress
+    /// reports have [`DownloadProgress::waiting_for_update`] set.
+    pub fn set_wait_for_updates(&mut self, wait: bool) {
+        self.wait_for_updates = wait;
+    }
+
+    /// Sets where the last archive downloaded for each repository is kept.
+    /// While a repository's server says its archive hasn't changed, it isn't
+    /// downloaded again, and it's only unpacked again if the database doesn't
+    /// already hold it, such as after the database was deleted.
+    ///
+    /// Only repositories which are served with an ETag are cached.
+    pub fn set_archive_cache(&mut self, dir: Option<PathBuf>) {
+        self.archive_cache = dir.map(ArchiveCache::new);
+    }
+
+    /// Sets whether updates unpack a repository again even if the database
+    /// already holds its current archive. Along with an [archive
+    /// cache](Self::set_archive_cache), this re-unpacks the cached archive
+    /// without downloading it again.
+    pub fn set_force_reunpack(&mut self, force: bool) {
+        self.force_reunpack = force;
+    }
+
+    /// Sets where Git checkouts of repositories are kept. With a directory,
+    /// repositories whose archives are on GitHub or GitLab are fetched with
+    /// the `git` program instead, and only the files which changed since the
+    /// last update are applied. The archive is downloaded if Git isn't
+    /// installed or the fetch fails.
+    ///
+    /// Git updates aren't resumable, so this takes priority over [resumable
+    /// updates](RepoManagerBuilder::resumable_updates).
+    pub fn set_git_checkouts(&mut self, dir: Option<PathBuf>) {
+        self.git_checkouts = dir;
     }
 
     /// Downloads the given repository from an online URL, unpacks it, then
     /// inserts it into the repository database.
+    ///
+    /// The repository is locked while it's updated, so that two programs
+    /// sharing a database can't update it at once. If this process is already
+    /// updating the same repository, this waits for that update and returns
+    /// its report instead of downloading the repository again.
     #[instrument(skip(self, progress_reporter))]
     pub async fn download(
         &mut self,
         repo: &Repository,
         progress_reporter: Box<dyn Fn(DownloadProgress) + Send + Sync>,
-    ) -> Result<(), Error> {
-        info!("Downloading an online CKAN repository");
+    ) -> Result<UpdateReport, Error> {
+        loop {
+            let leader = match self.updates_in_flight.join(&repo.id) {
+                Join::Lead(leader) => leader,
+                Join::Follow(follower) => {
+                    debug!("Joining an update of the same repository");
+                    match follower.wait(&progress_reporter).await {
+                        Some(report) => return Ok(report),
+                        // The other update failed, so this one tries for itself.
+                        None => continue,
+                    }
+                }
+            };
+
+            let shared = leader.reporter();
+            let result = self
+                .download_locked(
+                    repo,
+                    Box::new(move |progress: DownloadProgress| {
+                        shared(progress.clone());
+                        progress_reporter(progress);
+                    }),
+                )
+                .await;
+            leader.finish(result.as_ref().ok().cloned());
+            return result;
+        }
+    }
+
+    /// Updates a repository while holding its update lock.
+    async fn download_locked(
+        &mut self,
+        repo: &Repository,
+        progress_reporter: Box<dyn Fn(DownloadProgress) + Send + Sync>,
+    ) -> Result<UpdateReport, Error> {
+        let lock = self.lock_update(repo, &progress_reporter).await?;
+        let lock = UpdateLockGuard::new(self.clone(), lock);
+        let mut progress_reporter = lock.renewing(progress_reporter);
+
+        let start = Instant::now();
+        let result = async {
+            let git_source = self.git_checkouts.clone().and_then(|checkouts| {
+                GitSource::for_archive(&repo.url).map(|source| (checkouts, source))
+            });
+            if let Some((checkouts, source)) = git_source {
+                let shared: Arc<dyn Fn(DownloadProgress) + Send + Sync> = progress_reporter.into();
+                let reporter = shared.clone();
+                let progress = Arc::new(DownloadProgressReporter::new(
+                    None,
+                    Box::new(move |progress| reporter(progress)),
+                ));
+
+                let update = self.update_from_git(repo, &source, &checkouts, progress);
+                match update.await {
+                    Ok(report) => return Ok(report),
+                    Err(error) => warn!(
+                        %error,
+                        "Couldn't update from Git, so the archive is downloaded instead"
+                    ),
+                }
+                progress_reporter = Box::new(move |progress| shared(progress));
+            }
+
+            if let Some(archive_dir) = self.resumable_updates.clone() {
+                return self
+                    .update_resumable(repo, &archive_dir, progress_reporter)
+                    .await;
+            }
+
+            let download = self.fetch(repo, progress_reporter, None).await?;
+            let (final_url, redirects) = (download.final_url, download.redirects);
+
+            if download.from_cache && !self.force_reunpack {
+                let mut db = self.db()?;
+                let saved = db.etag(&repo.url)?;
+                let cached = download.etag.as_ref().and_then(|etag| etag.to_str().ok());
+                if saved.is_some() && saved.as_deref() == cached {
+                    info!("The repository hasn't changed since it was last unpacked");
+                    db.touch_etag(&repo.url)?;
+                    drop(db);
+                    return Ok(UpdateReport {
+                        unchanged: true,
+                        moved_to: self.record_redirects(repo, &final_url, &redirects)?,
+                        ..Default::default()
+                    });
+                }
+            }
+
+            let mut report = self
+                .unpack_repo(repo, download.assets, download.etag, download.progress)
+                .await?;
+            report.moved_to = self.record_redirects(repo, &final_url, &redirects)?;
+            Ok(report)
+        }
+        .await;
 
-        let response = self
-            .http
-            .get(repo.url.clone())
-            .header(
-                ACCEPT,
-                "application/gzip,application/x-gzip,application/zip",
+        match result {
+            Ok(_) => self.record_metric(Metric::RepoUpdate, Some(start.elapsed())),
+            Err(_) => self.record_metric(Metric::RepoUpdateFailure, None),
+        }
+
+        drop(lock);
+        result
+    }
+
+    /// Updates a repository through an archive saved in `archive_dir`,
+    /// carrying on from where the last update stopped if it was interrupted.
+    async fn update_resumable(
+        &mut self,
+        repo: &Repository,
+        archive_dir: &Path,
+        progress_reporter: Box<dyn Fn(DownloadProgress) + Send + Sync>,
+    ) -> Result<UpdateReport, Error> {
+        let unfinished = self.db()?.resumable_update(repo.id)?;
+        if let Some(update) = unfinished.filter(|update| update.archive_path.exists()) {
+            info!(
+                saved = update.completed.len(),
+                "Resuming an interrupted update from its saved archive"
+            );
+            let archive = open_archive(&update.archive_path).await?;
+            let assets = archive_assets(
+                &self.formats,
+                repo,
+                archive,
+                update.content_type.as_deref(),
+                self.parse_limits.max_asset_size,
             )
-            .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+            let etag = update
+                .etag
+                .as_deref()
+                .and_then(|etag| HeaderValue::from_str(etag).ok());
+            let progress = Arc::new(DownloadProgressReporter::new(None, progress_reporter));
+
+            let report = self
+                .unpack(repo, assets, etag, progress, Some(update.completed))
+                .await?;
+            remove_archive(&update.archive_path).await;
+            return Ok(report);
+        }
 
-        let download_size = response.content_length();
-        let new_etag = response.headers().get(ETAG).cloned();
+        let archive_path = archive_dir.join(format!("{}.archive", repo.id.get()));
+        let download = self
+            .fetch(repo, progress_reporter, Some(&archive_path))
+            .await?;
+        self.db()?.begin_resumable_update(
+            repo.id,
+            &archive_path,
+            download.etag.as_ref().and_then(|etag| etag.to_str().ok()),
+            download.content_type.as_deref(),
+        )?;
+
+        let mut report = self
+            .unpack(
+                repo,
+                download.assets,
+                download.etag,
+                download.progress,
+                Some(HashSet::new()),
+            )
+            .await?;
+        report.moved_to = self.record_redirects(repo, &download.final_url, &download.redirects)?;
+        remove_archive(&archive_path).await;
+        Ok(report)
+    }
 
-        let content_type =
-            content_type(&response).ok_or_else(|| RepoUnpackError::MissingContentType {
-                url: repo.url.clone(),
-            })?;
+    /// Records where a repository's URL redirected during an update, returning
+    /// where it has moved if the redirects were permanent.
+    fn record_redirects(
+        &self,
+        repo: &Repository,
+        final_url: &Url,
+        redirects: &[Redirect],
+    ) -> Result<Option<Url>, Error> {
+        let moved_to = Redirect::moved_to(redirects).cloned();
+        if let Some(moved_to) = &moved_to {
+            warn!(
+                %moved_to,
+                "The repository has moved permanently; its URL should be updated"
+            );
+        }
+
+        let final_url = (*final_url != repo.url).then_some(final_url);
+        self.db()?
+            .record_repo_redirect(repo.id, final_url, moved_to.as_ref())?;
+        Ok(moved_to)
+    }
+
+    /// Locks a repository for updating, waiting for any other update of it to
+    /// finish if the manager is set to.
+    async fn lock_update(
+        &self,
+        repo: &Repository,
+        progress_reporter: &(dyn Fn(DownloadProgress) + Send + Sync),
+    ) -> Result<UpdateLock, Error> {
+        loop {
+            let mut db = self.db()?;
+            if let Some(lock) = db.try_lock_update(repo.id, UPDATE_LOCK_LEASE)? {
+                return Ok(lock);
+            }
+
+            // The other update may have finished since the lock was tried.
+            let Some(holder) = db.update_lock_holder(repo.id)? else {
+                continue;
+            };
+            drop(db);
+
+            if !self.wait_for_updates {
+                return Err(Error::UpdateAlreadyInProgress {
+                    repo: repo.name.clone(),
+                    process_id: holder.process_id,
+                });
+            }
 
-        trace!(%content_type);
+            debug!(process_id = holder.process_id, "Waiting for another update");
+            progress_reporter(DownloadProgress {
+                bytes_downloaded: 0,
+                bytes_expected: None,
+                items_unpacked: 0,
+                waiting_for_update: true,
+            });
+            tokio::time::sleep(UPDATE_LOCK_POLL).await;
+        }
+    }
+
+    /// Downloads the given repository from an online URL and compares it to the
+    /// copy in the database, without changing the database.
+    #[instrument(skip(self, progress_reporter))]
+    pub async fn preview_download(
+        &self,
+        repo: &Repository,
+        progress_reporter: Box<dyn Fn(DownloadProgress) + Send + Sync>,
+    ) -> Result<UpdatePreview, Error> {
+        let download = self.fetch(repo, progress_reporter, None).await?;
+
+        self.preview_unpack(repo, download.assets, download.progress)
+            .await
+    }
+
+    /// Starts downloading a repository, choosing an unpacker for its format.
+    /// If `archive_path` is given, the whole repository is saved there before
+    /// it's unpacked. Otherwise, if the manager has an [archive
+    /// cache](Self::set_archive_cache), the cached archive is used if it's
+    /// still current.
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        progress_reporter: Box<dyn Fn(DownloadProgress) + Send + Sync>,
+        archive_path: Option<&Path>,
+    ) -> Result<RepoDownload, Error> {
+        info!("Downloading an online CKAN repository");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static(
+                "application/gzip,application/x-gzip,application/x-tar,application/zip",
+            ),
+        );
+        headers.insert(ACCEPT_ENCODING, ACCEPTED_ENCODINGS);
+
+        let cache = self
+            .archive_cache
+            .as_ref()
+            .filter(|_| archive_path.is_none());
+        let cached = match cache {
+            Some(cache) => cache.load(&repo.url).await,
+            None => None,
+        };
+        if let Some(etag) = cached
+            .as_ref()
+            .and_then(|c| HeaderValue::from_str(&c.etag).ok())
+        {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+
+        let (response, redirects) = self.transport.get_redirected(&repo.url, headers).await?;
+        let response = response.error_for_status()?;
+        let final_url = response.url.clone();
+        if !redirects.is_empty() {
+            debug!(%final_url, redirects = redirects.len(), "Followed redirects");
+        }
+
+        if let Some(cached) = cached.filter(|_| response.status == StatusCode::NOT_MODIFIED) {
+            debug!("Using the cached archive, which is still current");
+            let archive = open_archive(&cached.path).await?;
+            return Ok(RepoDownload {
+                assets: archive_assets(
+                    &self.formats,
+                    repo,
+                    archive,
+                    cached.content_type.as_deref(),
+                    self.parse_limits.max_asset_size,
+                )
+                .await?,
+                etag: HeaderValue::from_str(&cached.etag).ok(),
+                content_type: cached.content_type,
+                progress: Arc::new(DownloadProgressReporter::new(None, progress_reporter)),
+                from_cache: true,
+                final_url,
+                redirects,
+            });
+        }
+
+        // The size of the compressed body, which is what progress is counted in.
+        let download_size = response.content_length();
+        let encoding = ContentEncoding::of(&response)?;
+        let etag = response.headers.get(ETAG).cloned();
+        let content_type = content_type(&response);
+
+        trace!(?content_type);
 
         let progress = Arc::new(DownloadProgressReporter::new(
             download_size,
@@ -192,8 +698,7 @@ impl RepoManager {
         ));
 
         let download_stream = response
-            .bytes_stream()
-            .map_err(io::Error::other)
+            .body
             .into_async_read()
             .compat()
             .progress({
@@ -202,26 +707,48 @@ impl RepoManager {
                     progress.report_download_progress(bytes);
                 }
             });
-
-        match content_type.as_ref() {
-            mime::GZIP | mime::X_GZIP => {
-                debug!("Using tar.gz unpacker");
-
-                let loader = TarGzAssetLoader::new(download_stream);
-                self.unpack_repo(repo, loader, new_etag, progress.clone())
+        trace!(?encoding);
+        let download_stream = encoding.decode(download_stream);
+        let etag_str = etag.as_ref().and_then(|etag| etag.to_str().ok());
+        let download_stream = match (archive_path, cache, etag_str) {
+            (Some(path), ..) => {
+                save_archive(download_stream, path).await?;
+                open_archive(path).await?
+            }
+            (None, Some(cache), Some(etag)) => {
+                let download_path = cache.download_path(&repo.url);
+                save_archive(download_stream, &download_path).await?;
+                let cached = cache
+                    .store(&repo.url, etag, content_type.as_deref())
                     .await?;
+                open_archive(&cached.path).await?
             }
-            mime::ZIP => todo!("unpacking of .zip repos"),
-            _ => {
-                return Err(RepoUnpackError::UnsupportedContentType {
-                    content_type: content_type.to_string(),
-                    url: repo.url.clone(),
-                }
-                .into());
+            (None, Some(cache), None) => {
+                // Without an ETag, there's no telling whether a cached
+                // archive is still current.
+                cache.remove(&repo.url).await?;
+                download_stream
             }
+            (None, None, _) => download_stream,
         };
-
-        Ok(())
+        let assets = archive_assets(
+            &self.formats,
+            repo,
+            download_stream,
+            content_type.as_deref(),
+            self.parse_limits.max_asset_size,
+        )
+        .await?;
+
+        Ok(RepoDownload {
+            assets,
+            etag,
+            content_type: content_type.map(Cow::into_owned),
+            progress,
+            from_cache: false,
+            final_url,
+            redirects,
+        })
     }
 
     /// Uses the given unpacker to save a repository to the database.
@@ -231,122 +758,895 @@ impl RepoManager {
         loader: impl RepoAssetLoader<'static>,
         etag: Option<HeaderValue>,
         progress: Arc<DownloadProgressReporter>,
-    ) -> Result<(), Error> {
-        let mut asset_stream = loader.asset_stream()?;
+    ) -> Result<UpdateReport, Error> {
+        self.unpack(repo, loader, etag, progress, None).await
+    }
+
+    /// Saves a repository to the database. Without `checkpoints`, the whole
+    /// repository is saved in one transaction. With them, releases are saved
+    /// in batches and checkpointed as they go, and the releases a previous
+    /// attempt already saved are skipped.
+    async fn unpack(
+        &mut self,
+        repo: &Repository,
+        loader: impl RepoAssetLoader<'static>,
+        etag: Option<HeaderValue>,
+        progress: Arc<DownloadProgressReporter>,
+        checkpoints: Option<HashSet<Vec<u8>>>,
+    ) -> Result<UpdateReport, Error> {
+        let resumable = checkpoints.is_some();
+        let saved = checkpoints.unwrap_or_default();
+        let resuming = !saved.is_empty();
+
+        let mut report = UpdateReport {
+            releases_resumed: saved.len() as u64,
+            ..Default::default()
+        };
+
+        let repo_url = Arc::new(repo.url.clone());
+        let mut parser = AssetParser::spawn(
+            loader.asset_stream()?,
+            repo_url.clone(),
+            self.parse_limits,
+            self.spool.clone(),
+            saved,
+        );
+
+        let mut db = self.db()?;
+        let compress_metadata = db.compresses_metadata()?;
+        let mut db = db.with_metadata_compression(compress_metadata);
+
+        let mut unpack = async |mut db: RepoDB<&mut SqliteConnection>| {
+            use crate::database::schema::*;
+
+            // Remove any previous modules so that we are only left with the ones currently
+            // included in the repo. A resumed update keeps the ones it already saved.
+            if !resuming {
+                delete(modules::table)
+                    .filter(modules::repo_id.eq(repo.id))
+                    .execute(db.connection)?;
+            }
+
+            let mut updated_mods = HashMap::new();
+            // Releases parsed by a resumable update which haven't been saved yet.
+            let mut batch = vec![];
+            // Counts can arrive before the modules they belong to, so they're saved last.
+            let mut download_counts = vec![];
+            let mut filtered_mods = HashSet::new();
+            let mut terms = vec![];
+
+            while let Some(asset) = parser.rx.recv().await {
+                let (mut asset, path) = match asset? {
+                    ParsedAsset::Asset { asset, path } => (asset, path),
+                    ParsedAsset::Quarantined(quarantined) => {
+                        warn!(
+                            path = %quarantined.path.display(),
+                            error = %quarantined.error,
+                            "Skipping a repository document over the parse limits"
+                        );
+                        report.quarantined.push(quarantined);
+                        continue;
+                    }
+                    ParsedAsset::InvalidIdentifier { path, error } => {
+                        warn!(
+                            path = %path.display(),
+                            %error,
+                            "Skipping a release with an invalid identifier"
+                        );
+                        report.releases_invalid += 1;
+                        continue;
+                    }
+                };
+
+                if let RepoAsset::Release(json) = &mut asset {
+                    self.transforms.apply(json, repo);
+                }
+
+                match asset {
+                    RepoAsset::Release(json)
+                        if repo.filter.as_ref().is_some_and(|f| !f.allows(&json)) =>
+                    {
+                        report.releases_filtered += 1;
+                        filtered_mods.insert(String::from(json.identifier));
+                    }
+                    RepoAsset::Release(json) if self.policy.check_release(&json).is_err() => {
+                        report.releases_blocked += 1;
+                        filtered_mods.insert(String::from(json.identifier));
+                    }
+                    RepoAsset::Release(json) if resumable => {
+                        batch.push((asset_hash(&path), json));
+                        if batch.len() >= CHECKPOINT_BATCH {
+                            save_checkpointed(&mut db, repo.id, &mut batch)?;
+                        }
+                    }
+                    RepoAsset::Release(json) => {
+                        let existing_mod_id = updated_mods.get(&json.name).cloned();
+
+                        let (mod_id, _) = db.create_release(&json, repo.id, existing_mod_id)?;
+
+                        updated_mods.insert(json.name, mod_id);
+                    }
+                    RepoAsset::Builds(builds) => {
+                        db.register_builds(builds)?;
+                    }
+                    RepoAsset::DownloadCounts(counts) => {
+                        download_counts.push(counts);
+                    }
+                    RepoAsset::RepositoryRefList(ref_list) => {
+                        for new_ref in ref_list.repositories {
+                            db.add_repo_ref(repo.id, new_ref)?;
+                        }
+                    }
+                    RepoAsset::Terms(file) => {
+                        terms.push(file);
+                    }
+                }
+
+                progress.report_unpacked_item();
+                report.items_unpacked += 1;
+            }
+
+            (&mut parser.task).await.unwrap()?;
+
+            // Everything else is saved along with the last releases, so the
+            // repository is only marked as updated once all of it is saved.
+            db.transaction(|mut db| {
+                save_checkpointed(&mut db, repo.id, &mut batch)?;
+                db.set_etag(repo_url.clone(), etag.as_ref())?;
+                db.forget_repo_commit(repo.id)?;
+
+                for counts in download_counts {
+                    let unmatched =
+                        db.add_download_counts(repo.id, &counts, self.download_count_mode)?;
+                    // Modules which were filtered out aren't missing.
+                    report.unmatched_download_counts.extend(
+                        unmatched
+                            .into_iter()
+                            .filter(|slug| !filtered_mods.contains(slug)),
+                    );
+                }
+
+                report.new_terms = db.replace_repo_terms(repo.id, terms)?;
+                db.finish_update(repo.id)?;
+                if resumable {
+                    db.finish_resumable_update(repo.id)?;
+                }
+
+                Ok(())
+            })
+        };
+
+        if resumable {
+            // Each batch of releases is committed by itself, so that it's kept
+            // if the update is interrupted.
+            let conn = RepoDB::new(&mut *db.connection)
+                .with_retry_policy(self.retry_policy)
+                .with_metadata_compression(compress_metadata)
+                .with_policy(self.policy.clone());
+            unpack(conn).await?;
+        } else {
+            db.async_transaction(unpack)?;
+        }
+
+        #[cfg(feature = "parse-timing")]
+        {
+            report.slowest_assets = parser.slowest_assets();
+        }
+
+        Ok(report)
+    }
+
+    /// Compares the modules provided by the given unpacker to the ones in the
+    /// database, without changing the database.
+    pub async fn preview_unpack(
+        &self,
+        repo: &Repository,
+        loader: impl RepoAssetLoader<'static>,
+        progress: Arc<DownloadProgressReporter>,
+    ) -> Result<UpdatePreview, Error> {
+        use crate::database::schema::*;
+
         let repo_url = Arc::new(repo.url.clone());
+        let mut parser = AssetParser::spawn(
+            loader.asset_stream()?,
+            repo_url,
+            self.parse_limits,
+            self.spool.clone(),
+            HashSet::new(),
+        );
+
+        let mut incoming = HashMap::<String, BTreeSet<String>>::new();
+
+        while let Some(asset) = parser.rx.recv().await {
+            if let ParsedAsset::Asset {
+                asset: RepoAsset::Release(mut json),
+                ..
+            } = asset?
+            {
+                self.transforms.apply(&mut json, repo);
+
+                if repo.filter.as_ref().is_none_or(|f| f.allows(&json))
+                    && self.policy.check_release(&json).is_ok()
+                {
+                    incoming
+                        .entry(json.identifier.into())
+                        .or_default()
+                        .insert(json.version);
+                }
+            }
+
+            progress.report_unpacked_item();
+        }
 
-        // Parse all the assets in the background as we receive them. The fastest-parsed ones
-        // will be inserted into the database first.
-        let (tx, mut rx) = mpsc::channel(1000);
-        let stream_loader = spawn({
-            let repo_url = repo_url.clone();
+        parser.task.await.unwrap()?;
+
+        let existing = self.db()?.read_with_retry(|conn| {
+            modules::table
+                .inner_join(module_releases::table)
+                .filter(modules::repo_id.eq(repo.id))
+                .select((modules::module_slug, module_releases::version))
+                .load::<(String, String)>(conn)
+        })?;
+
+        let mut current = HashMap::<String, BTreeSet<String>>::new();
+        for (slug, version) in existing {
+            current.entry(slug).or_default().insert(version);
+        }
+
+        Ok(UpdatePreview::compare(current, incoming))
+    }
+}
+
+/// Holds a repository's update lock while it's updated. The lock's lease is
+/// renewed as the update reports progress, so that a slow update doesn't lose
+/// it, and the lock is released when the guard is dropped.
+#[cfg(feature = "network")]
+struct UpdateLockGuard {
+    mgr: RepoManager,
+    lock: Option<UpdateLock>,
+    renewed_at: Arc<Mutex<Instant>>,
+}
+
+#[cfg(feature = "network")]
+impl UpdateLockGuard {
+    fn new(mgr: RepoManager, lock: UpdateLock) -> Self {
+        Self {
+            mgr,
+            lock: Some(lock),
+            renewed_at: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Wraps a progress reporter so that reports renew the lock's lease once a
+    /// third of it has passed.
+    fn renewing(
+        &self,
+        progress_reporter: Box<dyn Fn(DownloadProgress) + Send + Sync>,
+    ) -> Box<dyn Fn(DownloadProgress) + Send + Sync> {
+        let mgr = self.mgr.clone();
+        let lock = self.lock.clone();
+        let renewed_at = self.renewed_at.clone();
+
+        Box::new(move |progress| {
+            if let Some(lock) = &lock {
+                let mut renewed_at = renewed_at.lock();
+                if renewed_at.elapsed() >= UPDATE_LOCK_LEASE / 3 {
+                    *renewed_at = Instant::now();
+                    renew_update_lock(mgr.clone(), lock.clone());
+                }
+            }
+            progress_reporter(progress);
+        })
+    }
+}
+
+#[cfg(feature = "network")]
+impl Drop for UpdateLockGuard {
+    fn drop(&mut self) {
+        let Some(lock) = self.lock.take() else {
+            return;
+        };
+
+        // The lock expires by itself, so failing to release it isn't worth
+        // failing the update.
+        if let Err(error) = self.mgr.db().and_then(|mut db| Ok(db.unlock_update(lock)?)) {
+            warn!(%error, "Failed to unlock the repository after updating it");
+        }
+    }
+}
+
+/// Renews an update lock's lease in the background. The update may be saving
+/// to the database meanwhile, and shouldn't wait for the renewal.
+#[cfg(feature = "network")]
+fn renew_update_lock(mgr: RepoManager, lock: UpdateLock) {
+    spawn_blocking(move || {
+        match mgr
+            .db()
+            .and_then(|mut db| Ok(db.renew_update_lock(&lock, UPDATE_LOCK_LEASE)?))
+        {
+            Ok(true) => trace!("Renewed the update lock"),
+            Ok(false) => warn!("The update lock expired before it could be renewed"),
+            Err(error) => warn!(%error, "Failed to renew the update lock"),
+        }
+    });
+}
+
+#[cfg(feature = "network")]
+/// A repository which is being downloaded.
+struct RepoDownload {
+    assets: BoxStream<'static, Result<RepoAssetBuf>>,
+    etag: Option<HeaderValue>,
+    content_type: Option<String>,
+    progress: Arc<DownloadProgressReporter>,
+    /// Whether the server said the cached archive is still current, so it's
+    /// being unpacked instead.
+    from_cache: bool,
+    /// Where the repository was downloaded from, after any redirects.
+    final_url: Url,
+    redirects: Vec<Redirect>,
+}
+
+#[cfg(feature = "network")]
+/// Chooses an unpacker for a repository archive from `formats`. Assets in a
+/// tar archive which are over `max_asset_size` bytes aren't read.
+async fn archive_assets(
+    formats: &FormatRegistry,
+    repo: &Repository,
+    archive: Pin<Box<dyn AsyncBufRead + Send>>,
+    content_type: Option<&str>,
+    max_asset_size: usize,
+) -> Result<BoxStream<'static, Result<RepoAssetBuf>>> {
+    // Servers don't always label their downloads correctly, so the data itself
+    // is trusted over the content type.
+    let (format, archive) = RepoFormat::sniff(archive).await?;
+    let Some(format) = format.or_else(|| content_type.and_then(format_for_mime)) else {
+        let Some(content_type) = content_type else {
+            return Err(RepoUnpackError::MissingContentType {
+                url: repo.url.clone(),
+            }
+            .into());
+        };
+
+        return Err(RepoUnpackError::UnsupportedContentType {
+            content_type: content_type.to_string(),
+            url: repo.url.clone(),
+        }
+        .into());
+    };
+
+    let Some(loader) = formats.loader(format) else {
+        return Err(RepoUnpackError::UnsupportedArchive {
+            format: format.name().into(),
+            url: repo.url.clone(),
+        }
+        .into());
+    };
+
+    debug!("Using {} unpacker", format.name());
+    loader(PackedRepo {
+        stream: Box::pin(archive),
+        asset_path: gzip_asset_path(&repo.url),
+        max_asset_size,
+    })
+}
+
+#[cfg(feature = "network")]
+/// Saves a repository archive to `path` as it's downloaded.
+async fn save_archive(
+    mut archive: Pin<Box<dyn AsyncBufRead + Send>>,
+    path: &Path,
+) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let mut file = tokio::fs::File::create(path).await?;
+    tokio::io::copy_buf(&mut archive, &mut file).await?;
+    file.sync_all().await
+}
+
+#[cfg(feature = "network")]
+async fn open_archive(path: &Path) -> io::Result<Pin<Box<dyn AsyncBufRead + Send>>> {
+    let file = tokio::fs::File::open(path).await?;
+    Ok(Box::pin(BufReader::new(file)))
+}
+
+#[cfg(feature = "network")]
+/// Deletes the archive of a finished resumable update. A leftover archive only
+/// takes up space, so failing to delete it isn't worth failing the update.
+async fn remove_archive(path: &Path) {
+    if let Err(error) = tokio::fs::remove_file(path).await {
+        warn!(%error, "Failed to delete a saved repository archive");
+    }
+}
+
+#[cfg(feature = "network")]
+/// Saves the releases a resumable update has parsed, recording that they were
+/// saved in the same transaction.
+fn save_checkpointed(
+    db: &mut RepoDB<&mut SqliteConnection>,
+    repo: RepoId,
+    batch: &mut Vec<(Vec<u8>, Box<JsonModule>)>,
+) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    db.transaction(|mut db| {
+        for (_, json) in batch.iter() {
+            db.create_release(json, repo, None)?;
+        }
+        db.record_checkpoints(repo, batch.iter().map(|(hash, _)| hash.as_slice()))?;
+        Ok(())
+    })?;
+
+    batch.clear();
+    Ok(())
+}
+
+#[cfg(feature = "network")]
+/// An asset which has been parsed, or set aside without being parsed.
+enum ParsedAsset {
+    Asset { asset: RepoAsset, path: PathBuf },
+    Quarantined(QuarantinedAsset),
+    /// A release whose identifier isn't valid, which is skipped so that the
+    /// rest of the repository can still be saved.
+    InvalidIdentifier {
+        path: PathBuf,
+        error: IdentifierError,
+    },
+}
+
+#[cfg(feature = "network")]
+/// A repository document which was skipped because it's over the
+/// [`ParseLimits`]. The rest of the repository is still saved.
+#[derive(Debug, Clone)]
+pub struct QuarantinedAsset {
+    /// The path of the document in the repository archive.
+    pub path: PathBuf,
+    pub error: ParseLimitError,
+}
+
+#[cfg(feature = "network")]
+/// Parses repository assets in the background as they are received. The
+/// fastest-parsed ones are sent first. Assets waiting to be parsed are held in
+/// a [`Spool`].
+struct AssetParser {
+    rx: mpsc::Receiver<Result<ParsedAsset>>,
+    task: JoinHandle<Result<()>>,
+    #[cfg(feature = "parse-timing")]
+    slowest_assets: Arc<Mutex<SlowestAssets>>,
+}
+
+#[cfg(feature = "network")]
+impl AssetParser {
+    fn spawn(
+        mut asset_stream: BoxStream<'static, Result<RepoAssetBuf>>,
+        repo_url: Arc<Url>,
+        limits: ParseLimits,
+        spool: Option<SpoolOptions>,
+        skip: HashSet<Vec<u8>>,
+    ) -> Self {
+        #[cfg(feature = "parse-timing")]
+        let slowest_assets = Arc::new(Mutex::new(SlowestAssets::new(SLOWEST_ASSETS)));
+
+        let (tx, rx) = mpsc::channel(1000);
+        let task = spawn({
+            #[cfg(feature = "parse-timing")]
+            let slowest_assets = slowest_assets.clone();
             async move {
                 let mut tasks = JoinSet::new();
+                let mut spool = Spool::new(spool);
+
+                while let Some(asset) = asset_stream.next().await {
+                    let asset = match asset {
+                        Ok(asset) => asset,
+                        // Assets the unpacker found too large weren't read.
+                        Err(Error::Network(RepoUnpackError::AssetTooLarge {
+                            path,
+                            size,
+                            limit,
+                        })) => {
+                            let error = ParseLimitError::AssetTooLarge {
+                                size: usize::try_from(size).unwrap_or(usize::MAX),
+                                limit,
+                            };
+                            let quarantined = QuarantinedAsset { path, error };
+                            tx.send(Ok(ParsedAsset::Quarantined(quarantined)))
+                                .await
+                                .unwrap();
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+
+                    // Releases which an interrupted update already saved
+                    // aren't parsed again.
+                    if asset.variant == RepoAssetVariant::Release
+                        && !skip.is_empty()
+                        && skip.contains(&asset_hash(&asset.path))
+                    {
+                        continue;
+                    }
 
-                while let Some(asset) = asset_stream.try_next().await? {
+                    let pending = spool.hold(asset);
                     let repo_url = repo_url.clone();
                     let tx = tx.clone();
+                    #[cfg(feature = "parse-timing")]
+                    let slowest_assets = slowest_assets.clone();
 
                     tasks.spawn(async move {
-                        tx.send(match parse_asset(&asset) {
+                        let loaded = match pending.load().await {
+                            Ok(loaded) => loaded,
+                            Err(err) => {
+                                tx.send(Err(err)).await.unwrap();
+                                return;
+                            }
+                        };
+
+                        #[cfg(feature = "parse-timing")]
+                        let start = Instant::now();
+
+                        let parsed = parse_asset(&loaded.asset, &limits);
+
+                        #[cfg(feature = "parse-timing")]
+                        slowest_assets.lock().record(AssetTiming {
+                            duration: start.elapsed(),
+                            size: loaded.asset.data.len(),
+                            path: loaded.asset.path.clone(),
+                        });
+
+                        // The document's contents aren't needed any more.
+                        let path = loaded.asset.path.clone();
+                        drop(loaded);
+
+                        tx.send(match parsed {
+                            Ok(asset) => Ok(ParsedAsset::Asset { asset, path }),
+                            Err(Error::Json(JsonError::Limit(error))) => {
+                                Ok(ParsedAsset::Quarantined(QuarantinedAsset { path, error }))
+                            }
+                            Err(Error::Json(JsonError::Identifier(error))) => {
+                                Ok(ParsedAsset::InvalidIdentifier { path, error })
+                            }
                             Err(Error::Json(err)) => Err(RepoUnpackError::InvalidJsonFile {
                                 source: err,
                                 url: repo_url,
-                                path: asset.path,
+                                path,
                             }
                             .into()),
-                            other => other,
+                            Err(other) => Err(other),
                         })
                         .await
                         .unwrap();
                     });
                 }
 
-                tasks.join_all().await;
+                tasks.join_all().await;
+
+                Ok::<_, Error>(())
+            }
+        });
+
+        Self {
+            rx,
+            task,
+            #[cfg(feature = "parse-timing")]
+            slowest_assets,
+        }
+    }
+
+    /// Returns the slowest assets to parse. Only call this once parsing has
+    /// finished.
+    #[cfg(feature = "parse-timing")]
+    fn slowest_assets(self) -> Vec<AssetTiming> {
+        let slowest_assets = Arc::into_inner(self.slowest_assets).expect("parsing has finished");
+        slowest_assets.into_inner().into_vec()
+    }
+}
+
+/// Options for opening a [`RepoManager`].
+#[derive(Debug, Clone)]
+pub struct RepoManagerBuilder {
+    url: String,
+    retry_policy: RetryPolicy,
+    policy: Policy,
+    backfill_progress: Option<BackfillReporter>,
+    #[cfg(feature = "network")]
+    download_count_mode: DownloadCountMode,
+    #[cfg(feature = "network")]
+    parse_limits: ParseLimits,
+    #[cfg(feature = "network")]
+    spool: Option<SpoolOptions>,
+    #[cfg(feature = "network")]
+    resumable_updates: Option<PathBuf>,
+    #[cfg(feature = "network")]
+    transforms: TransformRegistry,
+    #[cfg(feature = "network")]
+    formats: FormatRegistry,
+    #[cfg(feature = "network")]
+    transport: Option<Transport>,
+    #[cfg(feature = "network")]
+    read_timeout: Duration,
+    #[cfg(feature = "sqlcipher")]
+    key: Option<DatabaseKey>,
+}
+
+impl RepoManagerBuilder {
+    /// Sets how database queries respond when the database is locked by
+    /// another connection or program.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets which modules can be saved, installed and downloaded. See
+    /// [`RepoManager::set_policy`].
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Calls `progress` while releases saved by an older version of Camrete
+    /// are filled in, after the database is upgraded. This can take a while
+    /// for large databases, but only happens once.
+    pub fn backfill_progress(
+        mut self,
+        progress: impl Fn(&BackfillProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.backfill_progress = Some(BackfillReporter(Arc::new(progress)));
+        self
+    }
+
+    /// Sets whether repository updates create modules for download counts which
+    /// don't belong to any module in the repository.
+    #[cfg(feature = "network")]
+    pub fn download_count_mode(mut self, mode: DownloadCountMode) -> Self {
+        self.download_count_mode = mode;
+        self
+    }
+
+    /// Sets the largest repository documents which are accepted. Documents over
+    /// these limits are skipped when a repository is updated.
+    #[cfg(feature = "network")]
+    pub fn parse_limits(mut self, limits: ParseLimits) -> Self {
+        self.parse_limits = limits;
+        self
+    }
+
+    /// Writes repository documents to disk while they wait to be parsed once
+    /// the ones in memory are over a budget, so that updates don't need much
+    /// memory. Useful on phones and other memory-constrained devices, at the
+    /// cost of slower updates.
+    #[cfg(feature = "network")]
+    pub fn spool_to_disk(mut self, options: SpoolOptions) -> Self {
+        self.spool = Some(options);
+        self
+    }
+
+    /// Makes repository updates resumable, for very large repositories on
+    /// slow devices. Each repository's archive is saved in `archive_dir`
+    /// before it's unpacked, and its releases are saved in batches, so an
+    /// update which is interrupted carries on from the saved archive the next
+    /// time the repository is updated.
+    ///
+    /// Unlike other updates, a resumable update isn't all-or-nothing: until it
+    /// finishes, the repository only has the releases saved so far.
+    #[cfg(feature = "network")]
+    pub fn resumable_updates(mut self, archive_dir: impl Into<PathBuf>) -> Self {
+        self.resumable_updates = Some(archive_dir.into());
+        self
+    }
+
+    /// Adds a transform which changes each release's metadata when a
+    /// repository is unpacked, before the repository's filter is checked and
+    /// the release is saved. Transforms run in the order they're added.
+    #[cfg(feature = "network")]
+    pub fn transform(mut self, transform: impl ReleaseTransform + 'static) -> Self {
+        self.transforms.register(transform);
+        self
+    }
+
+    /// Unpacks repository archives in `format` with `loader`, replacing the
+    /// loader for that format. Tar, tar.gz and single gzipped assets can be
+    /// unpacked by default.
+    #[cfg(feature = "network")]
+    pub fn archive_format(mut self, format: RepoFormat, loader: LoaderFn) -> Self {
+        self.formats.register(format, loader);
+        self
+    }
+
+    /// Sets how long a server can take to connect, or to send any more of a
+    /// response, before the request fails. A slow download which keeps
+    /// receiving data doesn't time out. Defaults to 30 seconds, and doesn't
+    /// apply to a [transport](Self::transport) given instead of reqwest.
+    #[cfg(feature = "network")]
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sends HTTP requests with the given transport instead of reqwest.
+    #[cfg(feature = "network")]
+    pub fn transport(mut self, transport: impl HttpTransport + 'static) -> Self {
+        self.transport = Some(Transport(Arc::new(transport)));
+        self
+    }
+
+    /// Opens a database encrypted with SQLCipher, asking `provider` for its
+    /// passphrase whenever a connection is opened. If the database doesn't
+    /// exist yet, it's created encrypted. Plain databases can't be opened
+    /// with a key; convert them with
+    /// [`encrypt_database`](crate::repo::encrypt_database) first.
+    #[cfg(feature = "sqlcipher")]
+    pub fn encryption_key(mut self, provider: Arc<dyn KeyProvider>) -> Self {
+        self.key = Some(DatabaseKey(provider));
+        self
+    }
+
+    /// Opens the database, upgrading it to the latest schema if necessary.
+    pub fn build(self) -> Result<RepoManager> {
+        let pool = self.pool(&self.url)?;
+        let mut conn = pool.get()?;
 
-                Ok::<_, Error>(())
+        // see https://fractaledmind.github.io/2023/09/07/enhancing-rails-sqlite-fine-tuning/
+        // better write-concurrency
+        conn.batch_execute("PRAGMA journal_mode = WAL;")?;
+        // free some space by truncating possibly massive WAL files from the last run
+        conn.batch_execute("PRAGMA wal_checkpoint(TRUNCATE);")?;
+
+        conn.run_pending_migrations(MIGRATIONS)
+            .map_err(Error::DbMigrations)?;
+        RepoDB::new(conn).run_backfills(|progress| {
+            if let Some(reporter) = &self.backfill_progress {
+                (reporter.0)(progress);
             }
-        });
+        })?;
 
-        let mut db = RepoDB::new(self.database.get()?);
+        Ok(self.manager(pool))
+    }
 
-        db.async_transaction(async |mut db| {
-            use crate::database::schema::*;
+    pub(crate) fn pool(&self, url: &str) -> Result<DbPool> {
+        database::register_handle_function();
+        let manager = ConnectionManager::<SqliteConnection>::new(url);
 
-            db.set_etag(repo_url.clone(), etag.as_ref())?;
+        Ok(Pool::builder()
+            .max_size(MAX_DB_CONNS)
+            .connection_customizer(Box::new(self.connection_options()))
+            .build(manager)?)
+    }
 
-            // Remove any previous modules so that we are only left with the ones currently
-            // included in the repo.
-            delete(modules::table)
-                .filter(modules::repo_id.eq(repo.id))
-                .execute(db.connection)?;
+    pub(crate) fn connection_options(&self) -> ConnectionOptions {
+        ConnectionOptions {
+            busy_timeout: self.retry_policy.busy_timeout,
+            #[cfg(feature = "sqlcipher")]
+            key: self.key.clone(),
+        }
+    }
 
-            let mut updated_mods = HashMap::new();
+    pub(crate) fn manager(self, pool: DbPool) -> RepoManager {
+        RepoManager {
+            database: pool,
+            #[cfg(feature = "network")]
+            transport: self.transport.unwrap_or_else(|| {
+                let client = reqwest::Client::builder()
+                    .user_agent(USER_AGENT)
+                    .redirect(reqwest::redirect::Policy::none())
+                    .connect_timeout(self.read_timeout)
+                    .read_timeout(self.read_timeout)
+                    .build()
+                    .expect("http client initialized");
+                Transport(Arc::new(ReqwestTransport::new(client)))
+            }),
+            retry_policy: self.retry_policy,
+            policy: Arc::new(self.policy),
+            #[cfg(feature = "network")]
+            download_count_mode: self.download_count_mode,
+            #[cfg(feature = "network")]
+            parse_limits: self.parse_limits,
+            #[cfg(feature = "network")]
+            spool: self.spool,
+            #[cfg(feature = "network")]
+            resumable_updates: self.resumable_updates,
+            #[cfg(feature = "network")]
+            archive_cache: None,
+            #[cfg(feature = "network")]
+            force_reunpack: false,
+            #[cfg(feature = "network")]
+            git_checkouts: None,
+            #[cfg(feature = "network")]
+            transforms: self.transforms,
+            #[cfg(feature = "network")]
+            formats: self.formats,
+            #[cfg(feature = "network")]
+            download_events: broadcast::channel(downloads::EVENT_CAPACITY).0,
+            #[cfg(feature = "network")]
+            wait_for_updates: false,
+            #[cfg(feature = "network")]
+            updates_in_flight: InFlight::default(),
+            #[cfg(feature = "network")]
+            downloads_in_flight: InFlight::default(),
+            #[cfg(feature = "sqlcipher")]
+            key: self.key,
+        }
+    }
 
-            while let Some(asset) = rx.recv().await {
-                match asset? {
-                    RepoAsset::Release(json) => {
-                        let existing_mod_id = updated_mods.get(&json.name).cloned();
+    #[cfg(feature = "network")]
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+}
 
-                        let (mod_id, _) = db
-                            .create_release(&json, repo.id, existing_mod_id)
-                            .map_err(|source| RepoUnpackError::InsertRelease {
-                            name: json.name.clone(),
-                            version: json.version.clone(),
-                            source,
-                        })?;
+/// Settings which SQLite stores per-connection, applied to every connection as
+/// it is added to the pool.
+#[derive(Debug)]
+pub(crate) struct ConnectionOptions {
+    pub busy_timeout: Duration,
+    #[cfg(feature = "sqlcipher")]
+    pub key: Option<DatabaseKey>,
+}
 
-                        updated_mods.insert(json.name, mod_id);
-                    }
-                    RepoAsset::Builds(builds) => {
-                        db.register_builds(builds)
-                            .map_err(RepoUnpackError::InsertBuilds)?;
-                    }
-                    RepoAsset::DownloadCounts(counts) => {
-                        db.add_download_counts(repo.id, &counts)
-                            .map_err(RepoUnpackError::InsertDownloadCounts)?;
-                    }
-                    RepoAsset::RepositoryRefList(ref_list) => {
-                        for new_ref in ref_list.repositories {
-                            db.add_repo_ref(repo.id, new_ref.clone())
-                                .map_err(|source| RepoUnpackError::InsertRepoRefs {
-                                    source,
-                                    name: new_ref.name.into_owned(),
-                                    url: new_ref.url.into_owned().into(),
-                                })?;
-                        }
-                    }
-                }
+impl ConnectionOptions {
+    pub fn apply(&self, conn: &mut SqliteConnection) -> QueryResult<()> {
+        // SQLCipher needs the key before anything else is done with the
+        // database.
+        #[cfg(feature = "sqlcipher")]
+        if let Some(key) = &self.key {
+            key.unlock(conn)?;
+        }
 
-                progress.report_unpacked_item();
-            }
+        // sleep if the database is busy, for up to the configured timeout.
+        let busy_timeout = format!("PRAGMA busy_timeout = {};", self.busy_timeout.as_millis());
+        conn.batch_execute(&busy_timeout)?;
 
-            stream_loader.await.unwrap()?;
+        // fsync only in critical moments
+        // write WAL changes back every 1000 pages, for an in average 1MB WAL file.
+        // May affect readers if number is increased
+        conn.batch_execute(
+            "PRAGMA synchronous = NORMAL;\
+            PRAGMA wal_autocheckpoint = 1000;\
+            PRAGMA foreign_keys = ON;",
+        )?;
 
-            Ok(())
+        conn.register_collation("MODULE_VERSION", |left: &str, right: &str| {
+            ModuleVersion::from(left).cmp(&ModuleVersion::from(right))
         })?;
 
-        Ok(())
+        database::register_url_functions(conn)?;
+        module::register_functions(conn)
+    }
+}
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        self.apply(conn).map_err(diesel::r2d2::Error::QueryError)
     }
 }
 
-fn parse_asset(asset: &RepoAssetBuf) -> Result<RepoAsset> {
+#[cfg(feature = "network")]
+pub(super) fn parse_asset(asset: &RepoAssetBuf, limits: &ParseLimits) -> Result<RepoAsset> {
+    limits
+        .check_document(&asset.data)
+        .map_err(JsonError::from)?;
+
     match asset.variant {
         RepoAssetVariant::Release => {
-            let parsed: Box<JsonModule> = serde_json::from_slice(&asset.data)?;
+            let mut parsed: Box<JsonModule> = json::from_slice(&asset.data)
+                .map_err(|err| identifier_error(&asset.data).unwrap_or(err))?;
+            limits.check_release(&parsed).map_err(JsonError::from)?;
+            parsed.normalize_legacy();
             parsed.verify()?;
             Ok(RepoAsset::Release(parsed))
         }
         RepoAssetVariant::DownloadCounts => {
-            let map = serde_json::from_slice(&asset.data)?;
+            let map = json::from_slice(&asset.data)?;
             Ok(RepoAsset::DownloadCounts(map))
         }
         RepoAssetVariant::RepositoryRefList => {
-            let parsed: RepositoryRefList = serde_json::from_slice(&asset.data)?;
+            let parsed: RepositoryRefList = json::from_slice(&asset.data)?;
             Ok(RepoAsset::RepositoryRefList(parsed))
         }
         RepoAssetVariant::Builds => {
-            let parsed: JsonBuilds = serde_json::from_slice(&asset.data)?;
+            let parsed: JsonBuilds = json::from_slice(&asset.data)?;
             let versions = parsed
                 .builds
                 .into_iter()
@@ -360,9 +1660,38 @@ fn parse_asset(asset: &RepoAssetBuf) -> Result<RepoAsset> {
 
             Ok(RepoAsset::Builds(versions))
         }
+        RepoAssetVariant::Terms => {
+            let file_name = asset
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let contents = String::from_utf8_lossy(&asset.data).into_owned();
+            Ok(RepoAsset::Terms(RepoTerms {
+                file_name,
+                contents,
+            }))
+        }
     }
 }
 
+#[cfg(feature = "network")]
+/// Works out whether a release which couldn't be parsed has an invalid
+/// identifier, so that it can be skipped instead of failing the whole update.
+fn identifier_error(data: &[u8]) -> Option<JsonError> {
+    #[derive(Deserialize)]
+    struct Release {
+        identifier: String,
+    }
+
+    let release = json::from_slice::<Release>(data).ok()?;
+    Identifier::new(release.identifier)
+        .err()
+        .map(JsonError::from)
+}
+
+#[cfg(feature = "network")]
 /// Keeps track of the most recent progress updates and calls an external
 /// function when there is a change.
 pub struct DownloadProgressReporter {
@@ -372,6 +1701,7 @@ pub struct DownloadProgressReporter {
     items_unpacked: AtomicU64,
 }
 
+#[cfg(feature = "network")]
 impl DownloadProgressReporter {
     pub fn new(
         bytes_expected: Option<u64>,
@@ -393,23 +1723,66 @@ impl DownloadProgressReporter {
             bytes_downloaded: total_bytes,
             bytes_expected: self.bytes_expected,
             items_unpacked: self.items_unpacked.load(Ordering::Relaxed),
+            waiting_for_update: false,
         });
     }
 
     /// Reports that an item has been unpacked.
-    fn report_unpacked_item(&self) {
+    pub(super) fn report_unpacked_item(&self) {
         let items = self.items_unpacked.fetch_add(1, Ordering::Relaxed) + 1;
 
         (self.report_fn)(DownloadProgress {
             bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
             bytes_expected: self.bytes_expected,
             items_unpacked: items,
+            waiting_for_update: false,
         });
     }
 }
 
+#[cfg(feature = "network")]
+/// A summary of a completed repository update.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct UpdateReport {
+    /// The number of repository assets that were unpacked.
+    pub items_unpacked: u64,
+    /// Identifiers with a download count but no module in the repository. Only
+    /// filled in when using [`DownloadCountMode::ExistingOnly`].
+    pub unmatched_download_counts: Vec<String>,
+    /// The number of releases skipped because of the repository's
+    /// [`RepoFilter`](crate::database::models::RepoFilter).
+    pub releases_filtered: u64,
+    /// The number of releases skipped because the
+    /// [`Policy`](crate::policy::Policy) blocks them.
+    pub releases_blocked: u64,
+    /// The number of releases skipped because their identifier isn't valid.
+    pub releases_invalid: u64,
+    /// The number of releases which an interrupted
+    /// [resumable update](RepoManagerBuilder::resumable_updates) had already
+    /// saved, so weren't unpacked again.
+    pub releases_resumed: u64,
+    /// Whether the repository was left as it was, because the server said its
+    /// [cached archive](RepoManager::set_archive_cache) is still current and
+    /// the database already holds it.
+    pub unchanged: bool,
+    /// Documents which were skipped because they're over the
+    /// [`ParseLimits`].
+    pub quarantined: Vec<QuarantinedAsset>,
+    /// Where the repository has moved, if its URL permanently redirected
+    /// there. [`RepoDB::follow_repo_redirect`] updates its URL to match.
+    pub moved_to: Option<Url>,
+    /// License and terms files which the repository didn't have at its last
+    /// update, or whose contents changed, so they can be shown to the user.
+    pub new_terms: Vec<RepoTerms>,
+    /// The assets which took the longest to parse, slowest first.
+    #[cfg(feature = "parse-timing")]
+    pub slowest_assets: Vec<AssetTiming>,
+}
+
+#[cfg(feature = "network")]
 /// A snapshot of the progress of a repository download.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DownloadProgress {
     /// The number of bytes that have been downloaded.
     pub bytes_downloaded: u64,
@@ -417,10 +1790,14 @@ pub struct DownloadProgress {
     pub bytes_expected: Option<u64>,
     /// The number of repository assets that have been unpacked so far.
     pub items_unpacked: u64,
+    /// Whether the update hasn't started because another program is updating
+    /// the same repository.
+    pub waiting_for_update: bool,
 }
 
-fn content_type(response: &Response) -> Option<Cow<'static, str>> {
-    if let Some(header) = response.headers().get(CONTENT_TYPE)
+#[cfg(feature = "network")]
+fn content_type(response: &HttpResponse) -> Option<Cow<'static, str>> {
+    if let Some(header) = response.headers.get(CONTENT_TYPE)
         && let Ok(header_str) = header.to_str()
     {
         return Some(header_str.to_owned().into());
@@ -428,13 +1805,16 @@ fn content_type(response: &Response) -> Option<Cow<'static, str>> {
 
     // Fallback - server didn't tell us what it sent.
 
-    let url = response.url();
-    let path = url.path();
+    let path = response.url.path();
 
-    if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+    if path.ends_with(".gz") || path.ends_with(".tgz") {
         return Some(mime::GZIP.into());
     }
 
+    if path.ends_with(".tar") {
+        return Some(mime::X_TAR.into());
+    }
+
     if path.ends_with(".zip") {
         return Some(mime::ZIP.into());
     }
@@ -442,13 +1822,57 @@ fn content_type(response: &Response) -> Option<Cow<'static, str>> {
     None
 }
 
-#[cfg(test)]
-mod test {
-    use std::sync::Mutex;
+/// Picks an unpacker based on the content type reported by the server.
+#[cfg(feature = "network")]
+fn format_for_mime(content_type: &str) -> Option<RepoFormat> {
+    match content_type {
+        mime::GZIP | mime::X_GZIP => Some(RepoFormat::TarGz),
+        mime::X_TAR => Some(RepoFormat::Tar),
+        mime::ZIP => Some(RepoFormat::Zip),
+        _ => None,
+    }
+}
 
-    use serde_json::{from_value, json};
+/// The name of the asset held by a single-file gzip repository, taken from the
+/// last segment of its URL without the `.gz` extension.
+#[cfg(feature = "network")]
+fn gzip_asset_path(url: &Url) -> PathBuf {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default();
 
-    use crate::{database::models::ModuleRelease, repo::asset_stream::test::load_test_repo};
+    PathBuf::from(name.strip_suffix(".gz").unwrap_or(name))
+}
+
+#[cfg(all(test, feature = "network"))]
+mod test {
+    use std::{
+        env, io, process,
+        sync::{
+            Mutex,
+            atomic::{AtomicBool, AtomicUsize},
+        },
+    };
+
+    use bytes::Bytes;
+    use diesel::{dsl::sql, sql_types::BigInt};
+    use futures_util::{FutureExt, StreamExt, future::BoxFuture, stream};
+    use serde_json::{json, to_value};
+    use time::OffsetDateTime;
+
+    use crate::{
+        database::{
+            ModuleId,
+            models::{Module, ModuleRelease, RepoFilter, RepositoryRef},
+            schema::*,
+        },
+        policy::PolicyRules,
+        repo::{
+            asset_stream::{InMemoryAssetLoader, test::load_test_repo},
+            test_support::{Fault, FaultyAssetLoader, MINI_REPO, MockRepo, RepoResponse, release},
+        },
+    };
 
     use super::*;
 
@@ -480,6 +1904,7 @@ mod test {
                 bytes_downloaded: 300, // last call, not sum
                 bytes_expected: None,
                 items_unpacked: 2,
+                waiting_for_update: false,
             })
         );
     }
@@ -494,7 +1919,7 @@ mod test {
             .find(|a| a.variant == RepoAssetVariant::Release)
             .unwrap();
 
-        let parsed = parse_asset(release_asset).unwrap();
+        let parsed = parse_asset(release_asset, &ParseLimits::default()).unwrap();
 
         let RepoAsset::Release(release) = &parsed else {
             panic!("Wrong asset type: {parsed:?}");
@@ -503,6 +1928,109 @@ mod test {
         assert_eq!(release.name, "4kSP_Expanded");
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn preview_matches_unpack() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let assets = InMemoryAssetLoader::from(load_test_repo().await);
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let preview = mgr
+            .preview_unpack(&repo, assets.clone(), progress())
+            .await
+            .unwrap();
+        assert!(!preview.added.is_empty());
+        assert!(preview.removed.is_empty());
+        assert!(preview.updated.is_empty());
+
+        let mut db = mgr.db().unwrap();
+        let modules: i64 = modules::table.count().get_result(db.connection_mut()).unwrap();
+        assert_eq!(modules, 0);
+        drop(db);
+
+        mgr.unpack_repo(&repo, assets.clone(), None, progress())
+            .await
+            .unwrap();
+
+        let preview = mgr.preview_unpack(&repo, assets, progress()).await.unwrap();
+        assert!(preview.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn waits_for_other_updates() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let held = mgr
+            .db()
+            .unwrap()
+            .try_lock_update(repo.id, UPDATE_LOCK_LEASE)
+            .unwrap()
+            .unwrap();
+
+        let error = mgr.lock_update(&repo, &|_| {}).await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::UpdateAlreadyInProgress { process_id, .. } if process_id == std::process::id()
+        ));
+
+        mgr.set_wait_for_updates(true);
+        let other = mgr.clone();
+        spawn(async move {
+            tokio::time::sleep(UPDATE_LOCK_POLL).await;
+            other.db().unwrap().unlock_update(held).unwrap();
+        });
+
+        let waited = AtomicBool::new(false);
+        let lock = mgr
+            .lock_update(&repo, &|p| waited.store(p.waiting_for_update, Ordering::Relaxed))
+            .await
+            .unwrap();
+        assert!(waited.load(Ordering::Relaxed));
+        mgr.db().unwrap().unlock_update(lock).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn releases_lock_when_dropped() {
+        // The lease is renewed on another connection, which wouldn't share an
+        // in-memory database.
+        let path = env::temp_dir().join(format!("camrete-lock-guard-{}.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+        let mgr = RepoManager::new(&path.to_string_lossy()).unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+
+        let lock = mgr.lock_update(&repo, &|_| {}).await.unwrap();
+        let guard = UpdateLockGuard::new(mgr.clone(), lock);
+        let reporter = guard.renewing(Box::new(|_| {}));
+
+        // The lease isn't renewed until a third of it has passed.
+        *guard.renewed_at.lock() -= UPDATE_LOCK_LEASE / 2;
+        reporter(DownloadProgress {
+            bytes_downloaded: 0,
+            bytes_expected: None,
+            items_unpacked: 0,
+            waiting_for_update: false,
+        });
+        assert!(guard.renewed_at.lock().elapsed() < UPDATE_LOCK_LEASE / 3);
+        let mut db = mgr.db().unwrap();
+        assert!(db.update_lock_holder(repo.id).unwrap().is_some());
+
+        drop(guard);
+        assert_eq!(db.update_lock_holder(repo.id).unwrap(), None);
+
+        drop(db);
+        drop(mgr);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parse_all_assets() {
+        for asset in load_test_repo().await {
+            if let Err(err) = parse_asset(&asset, &ParseLimits::default()) {
+                panic!("{}: {err}", asset.path.display());
+            }
+        }
+    }
+
     #[test]
     fn module_versions_smart_cmp() {
         let mgr = RepoManager::new(":memory:").unwrap();
@@ -510,34 +2038,782 @@ mod test {
 
         let repo = db.all_repos(true).unwrap().remove(0);
 
-        let mod1 = from_value(json!({
-            "spec_version": 1,
-            "name": "Parallax",
-            "identifier": "Parallax",
-            "version": "1.15",
-            "abstract": "A mod",
-            "author": "Linx",
-        })).unwrap();
-
-        let mod2 = from_value(json!({
-            "spec_version": 1,
-            "name": "Parallax",
-            "identifier": "Parallax",
-            "version": "1.3",
-            "abstract": "A mod",
-            "author": "Linx",
-        })).unwrap();
+        let mod1 = release("Parallax", "1.15").build();
+        let mod2 = release("Parallax", "1.3").build();
 
         let (mid, _) = db.create_release(&mod1, repo.id, None).unwrap();
         db.create_release(&mod2, repo.id, Some(mid)).unwrap();
 
         let releases: Vec<ModuleRelease> = ModuleRelease::all()
             .order(ModuleRelease::by_version())
-            .load(db.as_mut())
+            .load(db.connection_mut())
             .unwrap();
 
         assert_eq!(releases[0].module_id, mid);
         assert_eq!(releases[0].display_name, "Parallax");
         assert_eq!(releases[0].version, "1.15");
     }
+
+    #[test]
+    fn authors_are_shared() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+
+        let repo = db.all_repos(true).unwrap().remove(0);
+
+        let parallax = release("Parallax", "1.15")
+            .set("author", json!(["Linx", "Gameslinx"]))
+            .build();
+        let scatterer = release("Scatterer", "0.08").set("author", "linx").build();
+
+        let (parallax_id, release_id) = db.create_release(&parallax, repo.id, None).unwrap();
+        let (scatterer_id, _) = db.create_release(&scatterer, repo.id, None).unwrap();
+
+        let authors: i64 = authors::table.count().get_result(db.connection_mut()).unwrap();
+        assert_eq!(authors, 2);
+
+        // Scatterer's lowercase spelling doesn't replace the original.
+        let names: Vec<String> = ModuleRelease::authors_for(release_id)
+            .load(db.connection_mut())
+            .unwrap();
+        assert_eq!(names, ["Linx", "Gameslinx"]);
+
+        let by_linx: Vec<ModuleId> = Module::all()
+            .filter(Module::with_author("LINX"))
+            .order_by(Module::by_slug())
+            .select(modules::module_id)
+            .load(db.connection_mut())
+            .unwrap();
+        assert_eq!(by_linx, [parallax_id, scatterer_id]);
+
+        delete(modules::table)
+            .filter(modules::module_id.eq(parallax_id))
+            .execute(db.connection_mut())
+            .unwrap();
+        assert_eq!(db.prune_authors().unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn download_counts_skip_missing_modules() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        // Sent before any of the modules it refers to.
+        let mut assets = vec![RepoAssetBuf {
+            path: "download_counts.json".into(),
+            variant: RepoAssetVariant::DownloadCounts,
+            data: br#"{"Parallax": 100, "Missing": 1}"#.as_slice().into(),
+        }];
+        assets.extend(load_test_repo().await);
+
+        let report = mgr
+            .unpack_repo(&repo, InMemoryAssetLoader::from(assets), None, progress)
+            .await
+            .unwrap();
+        assert_eq!(report.unmatched_download_counts, ["Missing"]);
+
+        let mut db = mgr.db().unwrap();
+        let health = db.repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.modules_without_releases, 0);
+
+        let count: i32 = modules::table
+            .filter(modules::module_slug.eq("Parallax"))
+            .select(modules::download_count)
+            .get_result(db.connection_mut())
+            .unwrap();
+        assert_eq!(count, 100);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn repo_filter_skips_releases() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let mut repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let filter = RepoFilter {
+            exclude_tags: vec!["graphics".into()],
+            ..Default::default()
+        };
+        mgr.db().unwrap().set_repo_filter(repo.id, &filter).unwrap();
+        repo = mgr.db().unwrap().all_repos(false).unwrap().remove(0);
+        assert_eq!(repo.filter.as_ref(), Some(&filter));
+
+        let mut assets = vec![RepoAssetBuf {
+            path: "download_counts.json".into(),
+            variant: RepoAssetVariant::DownloadCounts,
+            data: br#"{"Parallax": 100, "TweakScale": 5}"#.as_slice().into(),
+        }];
+        assets.extend(load_test_repo().await);
+
+        let report = mgr
+            .unpack_repo(&repo, InMemoryAssetLoader::from(assets), None, progress)
+            .await
+            .unwrap();
+        // Every release of Parallax and 4kSPExpanded is tagged as graphics.
+        assert_eq!(report.releases_filtered, 27);
+        assert!(report.unmatched_download_counts.is_empty());
+
+        let slugs = Module::all()
+            .order_by(Module::by_slug())
+            .load(mgr.db().unwrap().connection_mut())
+            .unwrap()
+            .into_iter()
+            .map(|m| m.slug)
+            .collect::<Vec<_>>();
+        assert_eq!(slugs, ["RationalResources", "TweakScale", "UKS"]);
+
+        mgr.db()
+            .unwrap()
+            .set_repo_filter(repo.id, &RepoFilter::default())
+            .unwrap();
+        let repo = mgr.db().unwrap().all_repos(false).unwrap().remove(0);
+        assert_eq!(repo.filter, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn policy_blocks_releases() {
+        let policy = Policy {
+            modules: PolicyRules {
+                deny: vec!["tweak*".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut mgr = RepoManager::builder(":memory:")
+            .policy(policy)
+            .build()
+            .unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let mut assets = vec![RepoAssetBuf {
+            path: "download_counts.json".into(),
+            variant: RepoAssetVariant::DownloadCounts,
+            data: br#"{"Parallax": 100, "TweakScale": 5}"#.as_slice().into(),
+        }];
+        assets.extend(load_test_repo().await);
+
+        let report = mgr
+            .unpack_repo(&repo, InMemoryAssetLoader::from(assets), None, progress)
+            .await
+            .unwrap();
+        assert!(report.releases_blocked > 0);
+        assert_eq!(report.releases_filtered, 0);
+        // Blocked modules aren't missing, so their counts aren't unmatched.
+        assert!(report.unmatched_download_counts.is_empty());
+
+        let slugs = Module::all()
+            .order_by(Module::by_slug())
+            .load(mgr.db().unwrap().connection_mut())
+            .unwrap()
+            .into_iter()
+            .map(|m| m.slug)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            slugs,
+            ["4kSPExpanded", "Parallax", "RationalResources", "UKS"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_unpack_keeps_previous_contents() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+        let assets = load_test_repo().await;
+
+        mgr.unpack_repo(&repo, InMemoryAssetLoader::from(assets.clone()), None, progress())
+            .await
+            .unwrap();
+        let before = mgr.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+
+        for fault in [Fault::Io(io::ErrorKind::ConnectionReset), Fault::MalformedJson] {
+            let loader = FaultyAssetLoader::new(assets.clone()).inject(100, fault.clone());
+            let etag = Some(HeaderValue::from_static("\"new\""));
+
+            let err = mgr
+                .unpack_repo(&repo, loader, etag, progress())
+                .await
+                .unwrap_err();
+            match fault {
+                Fault::Io(_) => assert!(matches!(err, Error::Io(_)), "{err:?}"),
+                Fault::MalformedJson => assert!(
+                    matches!(
+                        &err,
+                        Error::Network(RepoUnpackError::InvalidJsonFile { path, .. })
+                            if path.to_str() == Some(Fault::MALFORMED_PATH)
+                    ),
+                    "{err:?}"
+                ),
+                _ => unreachable!(),
+            }
+
+            let after = mgr.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+            assert_eq!(after.module_count, before.module_count);
+            assert_eq!(after.release_count, before.release_count);
+            assert_eq!(after.etag, None);
+        }
+    }
+
+    /// Serves the test repository with an ETag, counting how many times the
+    /// whole archive is sent.
+    struct EtagRepo(Arc<AtomicUsize>);
+
+    impl HttpTransport for EtagRepo {
+        fn get_streaming(
+            &self,
+            url: &Url,
+            headers: HeaderMap,
+        ) -> BoxFuture<'_, Result<HttpResponse>> {
+            let etag = HeaderValue::from_static("\"mini\"");
+            let (status, body) = if headers.get(IF_NONE_MATCH) == Some(&etag) {
+                (StatusCode::NOT_MODIFIED, stream::empty().boxed())
+            } else {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                let archive = include_bytes!("../../benches/mini_repo.tgz");
+                let body = stream::once(async { Ok(Bytes::from_static(archive)) });
+                (StatusCode::OK, body.boxed())
+            };
+
+            let response = HttpResponse {
+                url: url.clone(),
+                status,
+                headers: HeaderMap::from_iter([(ETAG, etag)]),
+                body,
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reunpacks_cached_archives() {
+        let dir = env::temp_dir().join(format!("camrete-archive-reuse-{}", process::id()));
+        let sent = Arc::new(AtomicUsize::new(0));
+        let manager = || {
+            let mut mgr = RepoManager::builder(":memory:")
+                .transport(EtagRepo(sent.clone()))
+                .build()
+                .unwrap();
+            mgr.set_archive_cache(Some(dir.clone()));
+            mgr
+        };
+
+        let mut mgr = manager();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(!report.unchanged);
+
+        // The server says the archive hasn't changed, and it's already saved.
+        // The repository still counts as just updated.
+        diesel::update(etags::table)
+            .set(etags::updated_at.eq(OffsetDateTime::UNIX_EPOCH))
+            .execute(mgr.db().unwrap().connection_mut())
+            .unwrap();
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(report.unchanged);
+        let health = mgr.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+        assert!(health.updated_at > Some(OffsetDateTime::UNIX_EPOCH));
+
+        mgr.set_force_reunpack(true);
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(!report.unchanged);
+        assert!(report.items_unpacked > 0);
+
+        // A new database is filled in from the cached archive.
+        let mut wiped = manager();
+        let repo = wiped.db().unwrap().all_repos(true).unwrap().remove(0);
+        let report = wiped.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(!report.unchanged);
+        let health = wiped.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+        assert!(health.release_count > 0);
+
+        assert_eq!(sent.load(Ordering::Relaxed), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resumes_interrupted_updates() {
+        let dir = env::temp_dir().join(format!("camrete-resumable-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("interrupted.archive");
+        fs::write(&archive, include_bytes!("../../benches/mini_repo.tgz")).unwrap();
+
+        let mut complete = RepoManager::new(":memory:").unwrap();
+        let repo = complete.db().unwrap().all_repos(true).unwrap().remove(0);
+        let assets = load_test_repo().await;
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+        complete
+            .unpack_repo(&repo, InMemoryAssetLoader::from(assets.clone()), None, progress)
+            .await
+            .unwrap();
+        let expected = complete.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+
+        // Pretend an update saved one release before it was interrupted. The
+        // release isn't actually saved, so it's missing if it's skipped.
+        let mut mgr = RepoManager::builder(":memory:")
+            .resumable_updates(&dir)
+            .build()
+            .unwrap();
+        let saved = assets
+            .iter()
+            .find(|asset| asset.variant == RepoAssetVariant::Release)
+            .unwrap();
+        let mut db = mgr.db().unwrap();
+        assert_eq!(db.all_repos(true).unwrap()[0].id, repo.id);
+        db.begin_resumable_update(repo.id, &archive, Some("\"abc\""), None)
+            .unwrap();
+        db.record_checkpoints(repo.id, [asset_hash(&saved.path).as_slice()])
+            .unwrap();
+        drop(db);
+
+        // The saved archive is used, so nothing is downloaded.
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert_eq!(report.releases_resumed, 1);
+
+        let mut db = mgr.db().unwrap();
+        let health = db.repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.release_count, expected.release_count - 1);
+        assert_eq!(health.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(db.resumable_update(repo.id).unwrap(), None);
+        assert!(!archive.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn duplicate_releases_are_replaced() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+        let assets = load_test_repo().await;
+        let releases = assets
+            .iter()
+            .filter(|a| a.variant == RepoAssetVariant::Release)
+            .count();
+
+        let loader = FaultyAssetLoader::new(assets)
+            .inject(50, Fault::DuplicateRelease)
+            .inject(usize::MAX, Fault::DuplicateRelease);
+        mgr.unpack_repo(&repo, loader, None, progress).await.unwrap();
+
+        let health = mgr.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.release_count as usize, releases);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unpack_giant_asset() {
+        const SIZE: usize = 32 * 1024 * 1024;
+
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let loader = FaultyAssetLoader::new(load_test_repo().await)
+            .inject(10, Fault::GiantAsset { size: SIZE });
+        mgr.unpack_repo(&repo, loader, None, progress).await.unwrap();
+
+        let description: Option<String> = module_releases::table
+            .inner_join(modules::table)
+            .filter(modules::module_slug.eq(Fault::GIANT_IDENTIFIER))
+            .select(module_releases::description)
+            .get_result(mgr.db().unwrap().connection_mut())
+            .unwrap();
+        assert_eq!(description.map(|d| d.len()), Some(SIZE));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unpack_spooled_to_disk() {
+        let dir = env::temp_dir().join(format!("camrete-spool-unpack-{}", process::id()));
+        let mut mgr = RepoManager::builder(":memory:")
+            .spool_to_disk(SpoolOptions::new(1024).in_directory(&dir))
+            .build()
+            .unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let assets = load_test_repo().await;
+        let count = assets.len();
+        let loader = InMemoryAssetLoader::from(assets);
+        let report = mgr.unpack_repo(&repo, loader, None, progress).await.unwrap();
+        assert_eq!(report.items_unpacked, count as u64);
+
+        let modules: i64 = modules::table
+            .count()
+            .get_result(mgr.db().unwrap().connection_mut())
+            .unwrap();
+        assert!(modules > 0);
+
+        // Every spooled document is deleted once it's been parsed.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quarantines_assets_over_limits() {
+        let mut mgr = RepoManager::builder(":memory:")
+            .parse_limits(ParseLimits {
+                max_asset_size: 1024 * 1024,
+                ..ParseLimits::default()
+            })
+            .build()
+            .unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let loader = FaultyAssetLoader::new(load_test_repo().await)
+            .inject(10, Fault::GiantAsset { size: 2 * 1024 * 1024 });
+        let report = mgr.unpack_repo(&repo, loader, None, progress).await.unwrap();
+
+        assert_eq!(report.quarantined.len(), 1);
+        assert!(matches!(
+            report.quarantined[0].error,
+            ParseLimitError::AssetTooLarge { limit: 1048576, .. }
+        ));
+
+        // The rest of the repository is still saved.
+        let mut db = mgr.db().unwrap();
+        let giant: i64 = modules::table
+            .filter(modules::module_slug.eq(Fault::GIANT_IDENTIFIER))
+            .count()
+            .get_result(db.connection_mut())
+            .unwrap();
+        assert_eq!(giant, 0);
+        let modules: i64 = modules::table.count().get_result(db.connection_mut()).unwrap();
+        assert!(modules > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skips_releases_with_invalid_identifiers() {
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+        let count_releases = |mgr: &RepoManager| -> i64 {
+            module_releases::table
+                .count()
+                .get_result(mgr.db().unwrap().connection_mut())
+                .unwrap()
+        };
+
+        let assets = load_test_repo().await;
+        let loader = FaultyAssetLoader::new(assets.clone());
+        mgr.unpack_repo(&repo, loader, None, progress()).await.unwrap();
+        let releases = count_releases(&mgr);
+
+        let loader = FaultyAssetLoader::new(assets).inject(10, Fault::InvalidIdentifier);
+        let report = mgr.unpack_repo(&repo, loader, None, progress()).await.unwrap();
+        assert_eq!(report.releases_invalid, 1);
+
+        // Every good release is still saved.
+        assert_eq!(count_releases(&mgr), releases);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transforms_change_releases() {
+        let mut mgr = RepoManager::builder(":memory:")
+            .transform(|release: &mut JsonModule, _: &Repository| {
+                release.tags.push("mirrored".into());
+            })
+            .transform(|release: &mut JsonModule, _: &Repository| {
+                for url in &mut release.download {
+                    url.set_host(Some("mirror.example.com")).unwrap();
+                }
+            })
+            .build()
+            .unwrap();
+        let mut repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        // Transforms run before the filter, so it sees the added tags.
+        let filter = RepoFilter {
+            exclude_tags: vec!["mirrored".into()],
+            ..Default::default()
+        };
+        mgr.db().unwrap().set_repo_filter(repo.id, &filter).unwrap();
+        repo = mgr.db().unwrap().all_repos(false).unwrap().remove(0);
+        let assets = InMemoryAssetLoader::from(load_test_repo().await);
+        let report = mgr
+            .unpack_repo(&repo, assets.clone(), None, progress())
+            .await
+            .unwrap();
+        assert!(report.releases_filtered > 0);
+
+        mgr.db()
+            .unwrap()
+            .set_repo_filter(repo.id, &RepoFilter::default())
+            .unwrap();
+        repo = mgr.db().unwrap().all_repos(false).unwrap().remove(0);
+        mgr.unpack_repo(&repo, assets, None, progress())
+            .await
+            .unwrap();
+
+        let mut db = mgr.db().unwrap();
+        let releases: Vec<ModuleRelease> = ModuleRelease::all().load(db.connection_mut()).unwrap();
+        assert!(!releases.is_empty());
+        for release in releases {
+            let tags = ModuleRelease::tags_for(release.id)
+                .load::<String>(db.connection_mut())
+                .unwrap();
+            assert!(tags.contains(&"mirrored".to_string()));
+            assert!(
+                release
+                    .metadata
+                    .download
+                    .iter()
+                    .all(|url| url.host_str() == Some("mirror.example.com"))
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compressed_metadata_round_trips() {
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+        let assets = InMemoryAssetLoader::from(load_test_repo().await);
+        let mut unpacked = vec![];
+
+        for compress in [false, true] {
+            let mut mgr = RepoManager::new(":memory:").unwrap();
+            let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+            mgr.db().unwrap().set_metadata_compression(compress).unwrap();
+            mgr.unpack_repo(&repo, assets.clone(), None, progress())
+                .await
+                .unwrap();
+
+            let mut db = mgr.db().unwrap();
+            let size: i64 = module_releases::table
+                .select(sql::<BigInt>("SUM(length(metadata))"))
+                .get_result(db.connection_mut())
+                .unwrap();
+            let releases: Vec<ModuleRelease> = ModuleRelease::all()
+                .order_by(ModuleRelease::by_version())
+                .load(db.connection_mut())
+                .unwrap();
+            unpacked.push((size, releases));
+        }
+
+        let (jsonb_size, jsonb) = &unpacked[0];
+        let (compressed_size, compressed) = &unpacked[1];
+        assert!(compressed_size < jsonb_size);
+        assert_eq!(jsonb.len(), compressed.len());
+        for (a, b) in jsonb.iter().zip(compressed) {
+            assert_eq!(to_value(&a.metadata).unwrap(), to_value(&b.metadata).unwrap());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_content_types() {
+        let server = MockRepo::start().await;
+        // The data is trusted over the content type the server sends.
+        let mislabelled = RepoResponse::archive().content_type("text/plain");
+        server.serve("/mislabelled", mislabelled).await;
+        // Without a content type, the URL's extension is used instead.
+        let unlabelled = RepoResponse::status(200).body(b"not an archive");
+        server.serve("/unlabelled", unlabelled.clone()).await;
+        server.serve("/unlabelled.tar.gz", unlabelled).await;
+        let page = RepoResponse::status(200)
+            .body(b"<html></html>")
+            .content_type("text/html");
+        server.serve("/page", page).await;
+
+        let (mut mgr, repo) = server.manager("/mislabelled");
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(report.items_unpacked > 0);
+
+        let (mut mgr, repo) = server.manager("/unlabelled");
+        let err = mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        assert!(
+            matches!(err, Error::Network(RepoUnpackError::MissingContentType { .. })),
+            "{err:?}"
+        );
+
+        // The extension says it's a `.tar.gz`, so it's unpacked as one and
+        // fails to decompress, rather than being refused.
+        let (mut mgr, repo) = server.manager("/unlabelled.tar.gz");
+        let err = mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Io(e) if e.kind() == io::ErrorKind::InvalidData),
+            "{err:?}"
+        );
+
+        let (mut mgr, repo) = server.manager("/page");
+        let err = mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                Error::Network(RepoUnpackError::UnsupportedContentType { content_type, .. })
+                    if content_type == "text/html"
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_zip_archive() {
+        let server = MockRepo::start().await;
+        let zip = RepoResponse::status(200)
+            .body(b"PK\x03\x04\x14\0\0\0\0\0")
+            .content_type("application/zip");
+        server.serve("/repo.zip", zip).await;
+
+        let (mut mgr, repo) = server.manager("/repo.zip");
+        let err = mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                Error::Network(RepoUnpackError::UnsupportedArchive { format, .. })
+                    if format == "zip"
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_not_modified() {
+        let dir = env::temp_dir().join(format!("camrete-mock-etag-{}", process::id()));
+        let server = MockRepo::start().await;
+        server
+            .serve("/repo.tar.gz", RepoResponse::archive().etag("\"v1\""))
+            .await;
+        server
+            .serve_if_none_match("/repo.tar.gz", "\"v1\"", RepoResponse::status(304))
+            .await;
+
+        let (mut mgr, repo) = server.manager("/repo.tar.gz");
+        mgr.set_archive_cache(Some(dir.clone()));
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(!report.unchanged);
+
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(report.unchanged);
+        assert_eq!(server.requests("/repo.tar.gz").await, 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_redirects() {
+        let server = MockRepo::start().await;
+        server
+            .serve("/moved", RepoResponse::redirect(301, "/repo.tar.gz"))
+            .await;
+        server
+            .serve("/mirrored", RepoResponse::redirect(302, "/repo.tar.gz"))
+            .await;
+        server.serve("/repo.tar.gz", RepoResponse::archive()).await;
+
+        let (mut mgr, repo) = server.manager("/moved");
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert_eq!(report.moved_to, Some(server.url("/repo.tar.gz")));
+
+        // A temporary redirect doesn't mean the repository has moved.
+        let (mut mgr, repo) = server.manager("/mirrored");
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert_eq!(report.moved_to, None);
+        assert!(report.items_unpacked > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_failures() {
+        let server = MockRepo::start().await;
+        let truncated = RepoResponse::archive().truncated(MINI_REPO.len() / 2);
+        server.serve("/truncated.tar.gz", truncated).await;
+        server
+            .serve("/broken.tar.gz", RepoResponse::status(500))
+            .await;
+
+        let (mut mgr, repo) = server.manager("/truncated.tar.gz");
+        mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        // Nothing from the part which was downloaded is kept.
+        let health = mgr.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.release_count, 0);
+        assert_eq!(health.etag, None);
+
+        let (mut mgr, repo) = server.manager("/broken.tar.gz");
+        let err = mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        assert!(matches!(err, Error::HttpStatus { status: 500, .. }), "{err:?}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_slow_responses() {
+        let server = MockRepo::start().await;
+        let slow = RepoResponse::archive().delay(Duration::from_millis(500));
+        server.serve("/repo.tar.gz", slow).await;
+
+        let (mut mgr, repo) = server.manager("/repo.tar.gz");
+        let downloaded = Arc::new(AtomicUsize::new(0));
+        let progress = Box::new({
+            let downloaded = downloaded.clone();
+            move |progress: DownloadProgress| {
+                downloaded.fetch_max(progress.bytes_downloaded as usize, Ordering::Relaxed);
+            }
+        });
+        let report = mgr.download(&repo, progress).await.unwrap();
+        assert!(report.items_unpacked > 0);
+        assert_eq!(downloaded.load(Ordering::Relaxed), MINI_REPO.len());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_timeouts() {
+        let server = MockRepo::start().await;
+        let stalled = RepoResponse::archive().delay(Duration::from_secs(5));
+        server.serve("/repo.tar.gz", stalled).await;
+
+        let mut mgr = RepoManager::builder(":memory:")
+            .read_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let mut db = mgr.db().unwrap();
+        let repo = db.all_repos(true).unwrap().remove(0);
+        let repo = db
+            .update_repo_url(repo.id, &server.url("/repo.tar.gz"))
+            .unwrap();
+        drop(db);
+
+        let started = Instant::now();
+        let err = mgr.download(&repo, Box::new(|_| {})).await.unwrap_err();
+        assert!(matches!(&err, Error::Http(e) if e.is_timeout()), "{err:?}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let health = mgr.db().unwrap().repo_health(&repo.name).unwrap().unwrap();
+        assert_eq!(health.release_count, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_server_repos_sharing_a_url() {
+        let server = MockRepo::start().await;
+        let slow = RepoResponse::archive().delay(Duration::from_millis(200));
+        server.serve("/repo.tar.gz", slow).await;
+
+        // The updates run at once, so they need connections to the same
+        // database, which in-memory databases don't share.
+        let path = env::temp_dir().join(format!("camrete-shared-url-{}.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+        let mgr = RepoManager::new(&path.to_string_lossy()).unwrap();
+        let mut db = mgr.db().unwrap();
+        let first = db.all_repos(true).unwrap().remove(0);
+        let first = db
+            .update_repo_url(first.id, &server.url("/repo.tar.gz"))
+            .unwrap();
+        drop(db);
+        let second = mgr
+            .db()
+            .unwrap()
+            .create_empty_repo(RepositoryRef::new("Copy".into(), first.url.clone()))
+            .unwrap();
+
+        // Each repository is updated itself, rather than one joining the
+        // other's update because their URLs match. Each runs on a task of its
+        // own, since unpacking blocks the task it runs on.
+        let update = |repo: Repository| {
+            let mut mgr = mgr.clone();
+            tokio::spawn(async move { mgr.download(&repo, Box::new(|_| {})).await })
+        };
+        let (first_report, second_report) = tokio::join!(update(first), update(second));
+        assert!(first_report.unwrap().unwrap().items_unpacked > 0);
+        assert!(second_report.unwrap().unwrap().items_unpacked > 0);
+        assert_eq!(server.requests("/repo.tar.gz").await, 2);
+
+        drop(mgr);
+        fs::remove_file(&path).unwrap();
+    }
 }
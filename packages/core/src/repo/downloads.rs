@@ -0,0 +1,759 @@
+//! Running the download queue.
+//!
+//! Files are downloaded next to their destination with a `.part` extension,
+//! then moved into place once their size and checksums have been checked. If the program exits
+//! partway through, the partial file is kept, and the download continues from
+//! where it stopped the next time the queue is run, as long as it's run from
+//! the same URL. A partial file from a different mirror is started again.
+
+use std::{
+    ffi::OsString,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use miette::Diagnostic;
+use reqwest::{
+    StatusCode,
+    header::{CONTENT_TYPE, HeaderMap, RANGE},
+};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::broadcast,
+};
+use tracing::{debug, info, instrument, warn};
+use url::Url;
+
+use crate::{
+    Result,
+    database::{DownloadId, DownloadState, Metric, NewDownload, QueuedDownload},
+    policy::PolicyError,
+    repo::{RepoManager, in_flight::Join},
+};
+
+/// How many events are kept for subscribers which fall behind.
+pub(crate) const EVENT_CAPACITY: usize = 256;
+/// How often the progress of a download is saved to the queue.
+const SAVE_INTERVAL: u64 = 1024 * 1024;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum DownloadError {
+    #[error("expected {url} to be {expected} bytes, but it was {actual} bytes")]
+    #[diagnostic(
+        code(camrete::download::size_mismatch),
+        severity(Warning),
+        help("the file may have changed since the module's metadata was written")
+    )]
+    SizeMismatch {
+        url: Url,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("expected {url} to have the {algorithm} checksum {expected}, but it was {actual}")]
+    #[diagnostic(
+        code(camrete::download::checksum_mismatch),
+        severity(Warning),
+        help("the file may be corrupt, or have changed since the module's metadata was written")
+    )]
+    ChecksumMismatch {
+        url: Box<Url>,
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// A change to a download in the queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadEvent {
+    pub download: DownloadId,
+    pub state: DownloadState,
+    pub bytes_downloaded: u64,
+    pub bytes_expected: Option<u64>,
+    /// Why the download failed, if it did.
+    pub error: Option<String>,
+}
+
+impl DownloadEvent {
+    fn new(download: &QueuedDownload, state: DownloadState, bytes_downloaded: u64) -> Self {
+        Self {
+            download: download.id,
+            state,
+            bytes_downloaded,
+            bytes_expected: download.expected_size.map(|size| size as u64),
+            error: None,
+        }
+    }
+}
+
+/// Hashes a file as it's downloaded, with each algorithm the queue has a
+/// checksum for.
+struct Checksums {
+    sha1: Option<(Sha1, String)>,
+    sha256: Option<(Sha256, String)>,
+}
+
+impl Checksums {
+    fn new(download: &QueuedDownload) -> Self {
+        Self {
+            sha1: download
+                .sha1
+                .clone()
+                .map(|expected| (Sha1::new(), expected)),
+            sha256: download
+                .sha256
+                .clone()
+                .map(|expected| (Sha256::new(), expected)),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        if let Some((hasher, _)) = &mut self.sha1 {
+            hasher.update(data);
+        }
+        if let Some((hasher, _)) = &mut self.sha256 {
+            hasher.update(data);
+        }
+    }
+
+    /// Hashes what's already in a file, such as the start of a download which
+    /// is being resumed.
+    async fn update_from_file(&mut self, path: &Path) -> io::Result<()> {
+        if self.sha1.is_none() && self.sha256.is_none() {
+            return Ok(());
+        }
+
+        let mut file = File::open(path).await?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.update(&buf[..read]);
+        }
+    }
+
+    /// Checks the hashes against the expected checksums. Hex digits are
+    /// compared ignoring case, since metadata uses both.
+    fn verify(self, url: &Url) -> Result<(), DownloadError> {
+        let actual = [
+            self.sha1
+                .map(|(hasher, expected)| ("SHA-1", expected, format!("{:x}", hasher.finalize()))),
+            self.sha256.map(|(hasher, expected)| {
+                ("SHA-256", expected, format!("{:x}", hasher.finalize()))
+            }),
+        ];
+
+        for (algorithm, expected, actual) in actual.into_iter().flatten() {
+            if !expected.eq_ignore_ascii_case(&actual) {
+                return Err(DownloadError::ChecksumMismatch {
+                    url: Box::new(url.clone()),
+                    algorithm,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A file which was downloaded successfully.
+struct Fetched {
+    size: u64,
+    /// The `Content-Type` the server sent, if it sent one.
+    content_type: Option<String>,
+}
+
+impl RepoManager {
+    /// Receives an event whenever a download is queued or its progress
+    /// changes. Subscribers which fall too far behind miss the oldest events.
+    pub fn download_events(&self) -> broadcast::Receiver<DownloadEvent> {
+        self.download_events.subscribe()
+    }
+
+    /// Adds a file to the end of the download queue. It isn't downloaded until
+    /// the queue is run.
+    pub fn queue_download(&self, new: &NewDownload) -> Result<QueuedDownload> {
+        let queued = self.db()?.queue_download(new)?;
+        self.emit(DownloadEvent::new(
+            &queued,
+            queued.state,
+            queued.bytes_downloaded as u64,
+        ));
+
+        Ok(queued)
+    }
+
+    /// Downloads each queued file in turn, until the queue is empty. Returns
+    /// how many files were downloaded.
+    ///
+    /// Downloads which were interrupted are resumed first. A download which
+    /// fails is marked as failed, and the rest of the queue carries on. Only
+    /// one program should run the queue at a time.
+    #[instrument(skip(self))]
+    pub async fn run_download_queue(&self) -> Result<usize> {
+        let resumed = self.db()?.resume_interrupted_downloads()?;
+        if resumed > 0 {
+            info!(resumed, "Resuming interrupted downloads");
+        }
+
+        let mut finished = 0;
+        // The connection is released before the download starts, since it's
+        // needed again while the download runs.
+        loop {
+            let Some(download) = self.db()?.start_next_download()? else {
+                break;
+            };
+            let start = Instant::now();
+            match self.run_download_from_mirrors(&download).await {
+                Ok(size) => {
+                    self.record_metric(Metric::Download, Some(start.elapsed()));
+                    self.db()?
+                        .set_download_state(download.id, DownloadState::Done)?;
+                    self.emit(DownloadEvent::new(&download, DownloadState::Done, size));
+                    finished += 1;
+                }
+                Err(error) => {
+                    self.record_metric(Metric::DownloadFailure, None);
+                    let message = error.to_string();
+                    self.db()?.fail_download(download.id, &message)?;
+                    self.emit(DownloadEvent {
+                        error: Some(message),
+                        ..DownloadEvent::new(&download, DownloadState::Failed, 0)
+                    });
+                }
+            }
+        }
+
+        Ok(finished)
+    }
+
+    /// Downloads a file, unless this process is already downloading the same
+    /// URL for another download in the queue. Then the other download is
+    /// waited for and its file copied, rather than being downloaded twice.
+    async fn run_download_from_mirrors(&self, download: &QueuedDownload) -> Result<u64> {
+        let leader = loop {
+            match self.downloads_in_flight.join(&download.url) {
+                Join::Lead(leader) => break leader,
+                Join::Follow(follower) => {
+                    debug!(url = %download.url, "Waiting for another download of the same URL");
+                    let on_progress = |bytes| {
+                        self.emit(DownloadEvent::new(
+                            download,
+                            DownloadState::Downloading,
+                            bytes,
+                        ))
+                    };
+                    if let Some(finished) = follower.wait(on_progress).await {
+                        return self.copy_finished_download(download, &finished).await;
+                    }
+                }
+            }
+        };
+
+        let result = self
+            .run_download_from_ranked_mirrors(download, &leader.reporter())
+            .await;
+        leader.finish(
+            result
+                .as_ref()
+                .ok()
+                .map(|_| PathBuf::from(&download.destination)),
+        );
+        result
+    }
+
+    /// Tries each of a download's URLs in turn, starting with the one most
+    /// likely to work, and records how each attempt went. Returns the size of
+    /// the file, or the error from the last URL if none of them worked.
+    ///
+    /// URLs on hosts which the [`Policy`](crate::policy::Policy) blocks are
+    /// never tried, and neither are URLs which redirect to them.
+    async fn run_download_from_ranked_mirrors(
+        &self,
+        download: &QueuedDownload,
+        on_progress: &impl Fn(u64),
+    ) -> Result<u64> {
+        let mut urls = self.db()?.rank_mirrors(&download.urls())?;
+        urls.retain(|url| self.policy.allows_url(url));
+        if urls.is_empty() {
+            return Err(PolicyError::BlockedHost {
+                url: download.url.clone(),
+            }
+            .into());
+        }
+
+        let mut last_error = None;
+        for url in urls {
+            match self.run_download(download, &url, on_progress).await {
+                Ok(fetched) => {
+                    self.db()?.record_mirror_success(
+                        &url,
+                        fetched.size,
+                        fetched.content_type.as_deref(),
+                    )?;
+                    return Ok(fetched.size);
+                }
+                Err(error) => {
+                    warn!(%url, %error, "Download failed");
+                    self.db()?.record_mirror_failure(&url, &error.to_string())?;
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.expect("a download always has at least one URL"))
+    }
+
+    /// Downloads a file from one of its URLs, then checks it and moves it into
+    /// place.
+    async fn run_download(
+        &self,
+        download: &QueuedDownload,
+        url: &Url,
+        on_progress: &impl Fn(u64),
+    ) -> Result<Fetched> {
+        let destination = Path::new(&download.destination);
+        let partial = partial_path(destination);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let mut existing = match fs::metadata(&partial).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if existing > 0 && self.db()?.partial_download_url(download.id)?.as_ref() != Some(url) {
+            debug!(existing, "Discarding a partial download from another URL");
+            fs::remove_file(&partial).await?;
+            existing = 0;
+        }
+
+        let mut headers = HeaderMap::new();
+        if existing > 0 {
+            debug!(existing, "Resuming a partial download");
+            let range = format!("bytes={existing}-");
+            headers.insert(RANGE, range.parse().expect("range is a valid header"));
+        }
+        let mut response = self
+            .transport
+            .get_allowed(url, headers, &self.policy)
+            .await?;
+
+        let content_type = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let mut bytes_downloaded = existing;
+        let mut checksums = Checksums::new(download);
+        // The partial file is already complete.
+        let complete = existing > 0 && response.status == StatusCode::RANGE_NOT_SATISFIABLE;
+        if complete {
+            checksums.update_from_file(&partial).await?;
+        } else {
+            response = response.error_for_status()?;
+            self.db()?.set_partial_download_url(download.id, url)?;
+
+            let mut file = if response.status == StatusCode::PARTIAL_CONTENT {
+                checksums.update_from_file(&partial).await?;
+                OpenOptions::new().append(true).open(&partial).await?
+            } else {
+                // The server sent the whole file, so start again.
+                bytes_downloaded = 0;
+                File::create(&partial).await?
+            };
+
+            let mut last_saved = bytes_downloaded;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+                checksums.update(&chunk);
+                bytes_downloaded += chunk.len() as u64;
+
+                if bytes_downloaded - last_saved >= SAVE_INTERVAL {
+                    self.db()?
+                        .set_download_progress(download.id, bytes_downloaded as i64)?;
+                    last_saved = bytes_downloaded;
+                }
+                on_progress(bytes_downloaded);
+                self.emit(DownloadEvent::new(
+                    download,
+                    DownloadState::Downloading,
+                    bytes_downloaded,
+                ));
+            }
+
+            file.sync_all().await?;
+        }
+
+        let mut db = self.db()?;
+        db.set_download_progress(download.id, bytes_downloaded as i64)?;
+        db.set_download_state(download.id, DownloadState::Verifying)?;
+        drop(db);
+        self.emit(DownloadEvent::new(
+            download,
+            DownloadState::Verifying,
+            bytes_downloaded,
+        ));
+
+        if let Some(expected) = download.expected_size
+            && expected as u64 != bytes_downloaded
+        {
+            // Downloading it again is more likely to work than continuing.
+            fs::remove_file(&partial).await?;
+            return Err(DownloadError::SizeMismatch {
+                url: url.clone(),
+                expected: expected as u64,
+                actual: bytes_downloaded,
+            }
+            .into());
+        }
+        if let Err(error) = checksums.verify(url) {
+            fs::remove_file(&partial).await?;
+            return Err(error.into());
+        }
+
+        fs::rename(&partial, destination).await?;
+        Ok(Fetched {
+            size: bytes_downloaded,
+            content_type,
+        })
+    }
+
+    /// Copies the file which another download of the same URL saved, checking
+    /// it the same way as a file which was downloaded.
+    async fn copy_finished_download(
+        &self,
+        download: &QueuedDownload,
+        finished: &Path,
+    ) -> Result<u64> {
+        let destination = Path::new(&download.destination);
+        let partial = partial_path(destination);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let size = fs::copy(finished, &partial).await?;
+        if let Some(expected) = download.expected_size
+            && expected as u64 != size
+        {
+            fs::remove_file(&partial).await?;
+            return Err(DownloadError::SizeMismatch {
+                url: download.url.clone(),
+                expected: expected as u64,
+                actual: size,
+            }
+            .into());
+        }
+
+        // Another download of the same URL may have expected a different file.
+        let mut checksums = Checksums::new(download);
+        let verified: Result<()> = match checksums.update_from_file(&partial).await {
+            Ok(()) => checksums.verify(&download.url).map_err(Into::into),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(error) = verified {
+            fs::remove_file(&partial).await?;
+            return Err(error);
+        }
+
+        fs::rename(&partial, destination).await?;
+        Ok(size)
+    }
+
+    fn emit(&self, event: DownloadEvent) {
+        // It's fine for nobody to be listening.
+        let _ = self.download_events.send(event);
+    }
+}
+
+/// The path of a file which is still being downloaded.
+fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(".part");
+    destination.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use bytes::Bytes;
+    use futures_util::{FutureExt, StreamExt, future::BoxFuture, stream};
+    use reqwest::header::LOCATION;
+
+    use super::*;
+    use crate::{
+        policy::{Policy, PolicyRules},
+        repo::{HttpResponse, HttpTransport},
+    };
+
+    const FILE: &[u8] = b"Parallax 1.0";
+
+    /// Serves [`FILE`] at every URL.
+    struct OneFile;
+
+    impl HttpTransport for OneFile {
+        fn get_streaming(&self, url: &Url, _: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>> {
+            let response = HttpResponse {
+                url: url.clone(),
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: stream::iter([Ok(Bytes::from_static(FILE))]).boxed(),
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    /// Serves a different build of the file from the mirror, resuming from
+    /// where a `Range` header asks. The main URL is down.
+    struct Mirrors;
+
+    impl Mirrors {
+        const MIRRORED: &[u8] = b"Parallax 1.0 (mirrored)";
+    }
+
+    impl HttpTransport for Mirrors {
+        fn get_streaming(
+            &self,
+            url: &Url,
+            headers: HeaderMap,
+        ) -> BoxFuture<'_, Result<HttpResponse>> {
+            let start = headers
+                .get(RANGE)
+                .and_then(|range| {
+                    range
+                        .to_str()
+                        .ok()?
+                        .strip_prefix("bytes=")?
+                        .strip_suffix('-')
+                })
+                .map_or(0, |start| start.parse().unwrap());
+            let (status, body) = match url.host_str() {
+                Some("mirror.example.com") if start > 0 => {
+                    (StatusCode::PARTIAL_CONTENT, &Self::MIRRORED[start..])
+                }
+                Some("mirror.example.com") => (StatusCode::OK, Self::MIRRORED),
+                _ => (StatusCode::SERVICE_UNAVAILABLE, &[][..]),
+            };
+
+            let response = HttpResponse {
+                url: url.clone(),
+                status,
+                headers: HeaderMap::new(),
+                body: stream::iter([Ok(Bytes::from_static(body))]).boxed(),
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    /// Redirects every URL to the same path on `dl.blocked.example`, which
+    /// must never be requested.
+    struct RedirectsToBlocked;
+
+    impl HttpTransport for RedirectsToBlocked {
+        fn get_streaming(&self, url: &Url, _: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>> {
+            assert_ne!(
+                url.host_str(),
+                Some("dl.blocked.example"),
+                "the blocked host was requested"
+            );
+            let mut headers = HeaderMap::new();
+            let location = format!("https://dl.blocked.example{}", url.path());
+            headers.insert(LOCATION, location.parse().unwrap());
+
+            let response = HttpResponse {
+                url: url.clone(),
+                status: StatusCode::FOUND,
+                headers,
+                body: stream::empty().boxed(),
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    fn new_download(dir: &Path, sha1: Option<&str>, sha256: Option<&str>) -> NewDownload {
+        NewDownload {
+            slug: "Parallax".into(),
+            version: "1.0".into(),
+            url: "https://example.com/Parallax.zip".parse().unwrap(),
+            mirrors: vec![],
+            destination: dir.join("Parallax-1.0.zip").to_string_lossy().into_owned(),
+            expected_size: Some(FILE.len() as i64),
+            sha1: sha1.map(str::to_owned),
+            sha256: sha256.map(str::to_owned),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checks_checksums() {
+        let dir = env::temp_dir().join(format!("camrete-download-checksums-{}", process::id()));
+        let mgr = RepoManager::builder(":memory:")
+            .transport(OneFile)
+            .build()
+            .unwrap();
+
+        let sha256 = format!("{:X}", Sha256::digest(FILE));
+        let good = mgr
+            .queue_download(&new_download(&dir.join("good"), None, Some(&sha256)))
+            .unwrap();
+        assert_eq!(mgr.run_download_queue().await.unwrap(), 1);
+        assert_eq!(fs::read(&good.destination).await.unwrap(), FILE);
+
+        let sha1 = format!("{:x}", Sha1::digest(b"something else"));
+        let bad = mgr
+            .queue_download(&new_download(&dir.join("bad"), Some(&sha1), Some(&sha256)))
+            .unwrap();
+        assert_eq!(mgr.run_download_queue().await.unwrap(), 0);
+
+        let failed = mgr.db().unwrap().download(bad.id).unwrap().unwrap();
+        assert_eq!(failed.state, DownloadState::Failed);
+        assert!(failed.error.unwrap().contains("SHA-1"));
+        // Neither the file nor the partial download is kept.
+        let destination = Path::new(&bad.destination);
+        assert!(!destination.exists());
+        assert!(!partial_path(destination).exists());
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refuses_redirects_to_blocked_hosts() {
+        let dir = env::temp_dir().join(format!("camrete-download-redirects-{}", process::id()));
+        let policy = Policy {
+            hosts: PolicyRules {
+                allow: vec![],
+                deny: vec!["*.blocked.example".into()],
+            },
+            ..Default::default()
+        };
+        let mgr = RepoManager::builder(":memory:")
+            .transport(RedirectsToBlocked)
+            .policy(policy)
+            .build()
+            .unwrap();
+
+        let queued = mgr.queue_download(&new_download(&dir, None, None)).unwrap();
+        assert_eq!(mgr.run_download_queue().await.unwrap(), 0);
+
+        let failed = mgr.db().unwrap().download(queued.id).unwrap().unwrap();
+        assert_eq!(failed.state, DownloadState::Failed);
+        assert!(failed.error.unwrap().contains("dl.blocked.example"));
+        // Nothing from the blocked host is written.
+        let destination = Path::new(&queued.destination);
+        assert!(!destination.exists());
+        assert!(!partial_path(destination).exists());
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lists_archives_within_the_policy() {
+        let dir = env::temp_dir().join(format!("camrete-download-listing-{}", process::id()));
+        let policy = Policy {
+            hosts: PolicyRules {
+                allow: vec![],
+                deny: vec!["*.blocked.example".into()],
+            },
+            ..Default::default()
+        };
+        let mgr = RepoManager::builder(":memory:")
+            .transport(RedirectsToBlocked)
+            .policy(policy)
+            .build()
+            .unwrap();
+
+        let err = mgr
+            .archive_listing(&new_download(&dir, None, None))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                crate::Error::Policy(PolicyError::BlockedRedirect { .. })
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checks_copies_of_other_downloads() {
+        let dir = env::temp_dir().join(format!("camrete-download-copies-{}", process::id()));
+        let mgr = RepoManager::builder(":memory:")
+            .transport(OneFile)
+            .build()
+            .unwrap();
+
+        // Another download of the URL finished with a file of the right size,
+        // but not the one this download expects.
+        let finished = dir.join("finished.zip");
+        fs::create_dir_all(&dir).await.unwrap();
+        fs::write(&finished, FILE).await.unwrap();
+
+        let sha256 = format!("{:x}", Sha256::digest(b"Parallax 2.0"));
+        let queued = mgr
+            .queue_download(&new_download(&dir.join("copy"), None, Some(&sha256)))
+            .unwrap();
+        let err = mgr
+            .copy_finished_download(&queued, &finished)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                crate::Error::Download(DownloadError::ChecksumMismatch { .. })
+            ),
+            "{err:?}"
+        );
+
+        let destination = Path::new(&queued.destination);
+        assert!(!destination.exists());
+        assert!(!partial_path(destination).exists());
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restarts_partial_downloads_from_other_mirrors() {
+        let dir = env::temp_dir().join(format!("camrete-download-mirrors-{}", process::id()));
+        let mgr = RepoManager::builder(":memory:")
+            .transport(Mirrors)
+            .build()
+            .unwrap();
+
+        let sha256 = format!("{:x}", Sha256::digest(Mirrors::MIRRORED));
+        let mut new = new_download(&dir, None, Some(&sha256));
+        new.mirrors = vec!["https://mirror.example.com/Parallax.zip".parse().unwrap()];
+        new.expected_size = Some(Mirrors::MIRRORED.len() as i64);
+        let queued = mgr.queue_download(&new).unwrap();
+
+        // An earlier run saved the start of the main URL's build, which is
+        // different from the mirror's.
+        let partial = partial_path(Path::new(&queued.destination));
+        fs::create_dir_all(&dir).await.unwrap();
+        fs::write(&partial, FILE).await.unwrap();
+        mgr.db()
+            .unwrap()
+            .set_partial_download_url(queued.id, &queued.url)
+            .unwrap();
+
+        assert_eq!(mgr.run_download_queue().await.unwrap(), 1);
+        assert_eq!(
+            fs::read(&queued.destination).await.unwrap(),
+            Mirrors::MIRRORED
+        );
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
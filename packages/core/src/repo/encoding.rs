@@ -0,0 +1,117 @@
+//! Compression applied to HTTP responses by the server.
+//!
+//! reqwest can decompress responses itself, but then the bytes it yields are
+//! the decompressed ones, and the length of the download isn't known. Instead,
+//! a [`ContentEncoding`] decompresses a response after its progress has been
+//! counted, so progress reflects the bytes sent over the network.
+
+use std::pin::Pin;
+
+use async_compression::tokio::bufread::GzipDecoder;
+#[cfg(feature = "http-compression")]
+use async_compression::tokio::bufread::{BrotliDecoder, ZlibDecoder, ZstdDecoder};
+use reqwest::header::{CONTENT_ENCODING, HeaderValue};
+use tokio::io::{AsyncBufRead, BufReader};
+
+use crate::repo::{HttpResponse, RepoUnpackError};
+
+/// The value of the `Accept-Encoding` header, listing every encoding which can
+/// be decompressed.
+#[cfg(feature = "http-compression")]
+pub const ACCEPTED_ENCODINGS: HeaderValue = HeaderValue::from_static("gzip, deflate, br, zstd");
+#[cfg(not(feature = "http-compression"))]
+pub const ACCEPTED_ENCODINGS: HeaderValue = HeaderValue::from_static("gzip");
+
+/// How the body of a response was compressed by the server, as named by its
+/// `Content-Encoding` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    /// The body wasn't compressed.
+    Identity,
+    Gzip,
+    /// A zlib stream, which HTTP calls `deflate`.
+    #[cfg(feature = "http-compression")]
+    Deflate,
+    #[cfg(feature = "http-compression")]
+    Brotli,
+    #[cfg(feature = "http-compression")]
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// Finds out how a response was compressed. Encodings which can't be
+    /// decompressed are an error, since the body would be unreadable.
+    pub fn of(response: &HttpResponse) -> Result<Self, RepoUnpackError> {
+        let Some(header) = response.headers.get(CONTENT_ENCODING) else {
+            return Ok(Self::Identity);
+        };
+        let encoding = header.to_str().unwrap_or_default().trim();
+
+        Ok(match encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" => Self::Identity,
+            "gzip" | "x-gzip" => Self::Gzip,
+            #[cfg(feature = "http-compression")]
+            "deflate" => Self::Deflate,
+            #[cfg(feature = "http-compression")]
+            "br" => Self::Brotli,
+            #[cfg(feature = "http-compression")]
+            "zstd" => Self::Zstd,
+            _ => {
+                return Err(RepoUnpackError::UnsupportedEncoding {
+                    encoding: encoding.to_owned(),
+                    url: response.url.clone(),
+                });
+            }
+        })
+    }
+
+    /// Decompresses a response body.
+    pub fn decode<'a, R>(self, body: R) -> Pin<Box<dyn AsyncBufRead + Send + 'a>>
+    where
+        R: AsyncBufRead + Send + 'a,
+    {
+        match self {
+            Self::Identity => Box::pin(body),
+            Self::Gzip => Box::pin(BufReader::new(GzipDecoder::new(body))),
+            #[cfg(feature = "http-compression")]
+            Self::Deflate => Box::pin(BufReader::new(ZlibDecoder::new(body))),
+            #[cfg(feature = "http-compression")]
+            Self::Brotli => Box::pin(BufReader::new(BrotliDecoder::new(body))),
+            #[cfg(feature = "http-compression")]
+            Self::Zstd => Box::pin(BufReader::new(ZstdDecoder::new(body))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn decodes_gzip() {
+        let mut compressed = Vec::new();
+        GzipEncoder::new(&b"builds.json"[..])
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        let mut decoded = String::new();
+        ContentEncoding::Gzip
+            .decode(compressed.as_slice())
+            .read_to_string(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, "builds.json");
+
+        let mut unchanged = Vec::new();
+        ContentEncoding::Identity
+            .decode(compressed.as_slice())
+            .read_to_end(&mut unchanged)
+            .await
+            .unwrap();
+        assert_eq!(unchanged, compressed);
+    }
+}
@@ -0,0 +1,191 @@
+//! Encrypting the database with SQLCipher, for users who keep their data
+//! directory somewhere other people can read it, like cloud storage.
+//!
+//! The passphrase comes from a [`KeyProvider`], which is asked for it whenever
+//! a connection is opened, so that it can stay in the platform's keychain
+//! instead of being held by Camrete. Existing databases are converted with
+//! [`encrypt_database`] and [`decrypt_database`], which write a copy rather
+//! than changing the original.
+
+use std::{fmt, io, path::Path, sync::Arc};
+
+use diesel::{connection::SimpleConnection, prelude::*, sql_query, sql_types::Text};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{info, instrument};
+
+use crate::{Result, database::RetryPolicy, repo::client::ConnectionOptions};
+
+/// Supplies the passphrase the database is encrypted with.
+#[uniffi::export(with_foreign)]
+pub trait KeyProvider: Send + Sync {
+    /// Returns the passphrase. Called each time a connection to the database
+    /// is opened.
+    fn database_key(&self) -> Result<String, KeyError>;
+}
+
+#[derive(Debug, Error, Diagnostic, uniffi::Error)]
+pub enum KeyError {
+    #[error("the database's passphrase is unavailable: {reason}")]
+    #[diagnostic(
+        code(camrete::database::key_unavailable),
+        severity(Warning),
+        help("unlock the keychain the passphrase is kept in, then try again")
+    )]
+    Unavailable { reason: String },
+}
+
+impl From<uniffi::UnexpectedUniFFICallbackError> for KeyError {
+    fn from(value: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        Self::Unavailable {
+            reason: value.reason,
+        }
+    }
+}
+
+/// A passphrase which is known up front, such as one the user typed.
+pub struct StaticKey(pub String);
+
+impl KeyProvider for StaticKey {
+    fn database_key(&self) -> Result<String, KeyError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// The key a [`RepoManager`](crate::repo::RepoManager) opens its database
+/// with. Never printed, so that it can't end up in logs.
+#[derive(Clone)]
+pub(crate) struct DatabaseKey(pub Arc<dyn KeyProvider>);
+
+impl DatabaseKey {
+    /// Unlocks a connection which was just opened. Fails if the passphrase is
+    /// wrong, or the database isn't encrypted.
+    pub fn unlock(&self, conn: &mut SqliteConnection) -> QueryResult<()> {
+        set_key(conn, &self.passphrase()?)
+    }
+
+    /// Asks the provider for the passphrase.
+    pub fn passphrase(&self) -> QueryResult<String> {
+        self.0
+            .database_key()
+            .map_err(|e| diesel::result::Error::QueryBuilderError(e.into()))
+    }
+}
+
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DatabaseKey(..)")
+    }
+}
+
+/// SQLCipher reads the key as a string literal, since pragmas can't have
+/// parameters. Reading from the database checks that the key is right, which
+/// SQLCipher otherwise only finds out at the first query.
+fn set_key(conn: &mut SqliteConnection, key: &str) -> QueryResult<()> {
+    conn.batch_execute(&format!(
+        "PRAGMA key = '{}'; SELECT count(*) FROM sqlite_master;",
+        key.replace('\'', "''")
+    ))
+}
+
+/// Writes an encrypted copy of the plain database at `plain` to `encrypted`,
+/// which mustn't exist yet.
+#[instrument(skip(key))]
+pub fn encrypt_database(plain: &Path, encrypted: &Path, key: &dyn KeyProvider) -> Result<()> {
+    info!("Encrypting the database");
+    export(plain, None, encrypted, &key.database_key()?)
+}
+
+/// Writes a plain copy of the encrypted database at `encrypted` to `plain`,
+/// which mustn't exist yet.
+#[instrument(skip(key))]
+pub fn decrypt_database(encrypted: &Path, plain: &Path, key: &dyn KeyProvider) -> Result<()> {
+    info!("Decrypting the database");
+    export(encrypted, Some(&key.database_key()?), plain, "")
+}
+
+/// Copies every table, index and trigger to a new database using
+/// `sqlcipher_export`. An empty `to_key` writes a plain database.
+fn export(from: &Path, from_key: Option<&str>, to: &Path, to_key: &str) -> Result<()> {
+    // Opening a database which doesn't exist would create an empty one.
+    if !from.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} doesn't exist", from.display()),
+        )
+        .into());
+    }
+    if to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        )
+        .into());
+    }
+
+    // The schema refers to the collation and functions the pool registers, so
+    // the connection needs them too.
+    let options = ConnectionOptions {
+        busy_timeout: RetryPolicy::default().busy_timeout,
+        key: from_key.map(|key| DatabaseKey(Arc::new(StaticKey(key.to_owned())))),
+    };
+    let mut conn = SqliteConnection::establish(&from.to_string_lossy())?;
+    options.apply(&mut conn)?;
+
+    export_to(&mut conn, to, to_key)?;
+    Ok(())
+}
+
+/// Copies the database `conn` is connected to into a new database at `to`,
+/// encrypted with `to_key`, or plain if it's empty.
+fn export_to(conn: &mut SqliteConnection, to: &Path, to_key: &str) -> QueryResult<()> {
+    sql_query("ATTACH DATABASE ? AS export KEY ?")
+        .bind::<Text, _>(to.to_string_lossy())
+        .bind::<Text, _>(to_key)
+        .execute(conn)?;
+    conn.batch_execute("SELECT sqlcipher_export('export'); DETACH DATABASE export;")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::repo::RepoManager;
+
+    #[test]
+    fn converts_between_plain_and_encrypted() {
+        let dir = env::temp_dir().join(format!("camrete-encryption-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let plain = dir.join("plain.sqlite");
+        let encrypted = dir.join("encrypted.sqlite");
+        let decrypted = dir.join("decrypted.sqlite");
+
+        let mgr = RepoManager::new(&plain.to_string_lossy()).unwrap();
+        mgr.db().unwrap().all_repos(true).unwrap();
+        drop(mgr);
+
+        let key = Arc::new(StaticKey("it's a secret".into()));
+        encrypt_database(&plain, &encrypted, &*key).unwrap();
+        assert!(encrypt_database(&plain, &encrypted, &*key).is_err());
+
+        let mgr = RepoManager::builder(&encrypted.to_string_lossy())
+            .encryption_key(key.clone())
+            .build()
+            .unwrap();
+        assert_eq!(mgr.db().unwrap().all_repos(false).unwrap().len(), 1);
+        drop(mgr);
+
+        // Without the key, the database can't be read.
+        assert!(RepoManager::new(&encrypted.to_string_lossy()).is_err());
+        let wrong = StaticKey("a guess".into());
+        assert!(decrypt_database(&encrypted, &decrypted, &wrong).is_err());
+
+        decrypt_database(&encrypted, &decrypted, &*key).unwrap();
+        let mgr = RepoManager::new(&decrypted.to_string_lossy()).unwrap();
+        assert_eq!(mgr.db().unwrap().all_repos(false).unwrap().len(), 1);
+        drop(mgr);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
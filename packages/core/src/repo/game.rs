@@ -129,7 +129,7 @@ impl FromStr for GameVersion {
         version.major = get_next()?;
         version.minor = get_next()?;
         version.patch = get_next()?;
-        version.patch = get_next()?;
+        version.build = get_next()?;
 
         if parts.next().is_some() {
             return Err(GameVersionParseError::TooManyParts);
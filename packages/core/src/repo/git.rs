@@ -0,0 +1,624 @@
+//! Updating repositories from Git instead of downloading their archives.
+//!
+//! CKAN-meta's archive is built from a Git repository, and is downloaded in
+//! full at every update even when only a few releases changed. A repository
+//! whose archive is on GitHub or GitLab can instead be fetched with the `git`
+//! program into a shallow checkout, which is kept between updates. Only the
+//! newest commit is fetched, and only the files which changed since the commit
+//! the database was last updated from are applied. If anything goes wrong, the
+//! archive is downloaded as usual.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
+
+use miette::Diagnostic;
+use sha2::{Digest, Sha256};
+use tokio::{fs, process::Command};
+use tracing::{debug, info, instrument, warn};
+use url::Url;
+
+use crate::{
+    Error, Result,
+    database::{RepoCommit, detect_channel, models::Repository},
+    json::JsonError,
+    repo::{
+        DirAssetLoader, RepoAsset, RepoAssetBuf, RepoAssetVariant, RepoManager, UpdateReport,
+        client::{self, DownloadProgressReporter, QuarantinedAsset},
+    },
+};
+
+#[derive(Debug, thiserror::Error, Diagnostic)]
+pub enum GitError {
+    #[error("couldn't run git")]
+    #[diagnostic(
+        code(camrete::git::unavailable),
+        severity(Warning),
+        help("install Git, or update without it")
+    )]
+    Unavailable(#[source] io::Error),
+    #[error("`git {command}` failed: {stderr}")]
+    #[diagnostic(code(camrete::git::failed))]
+    Failed { command: String, stderr: String },
+}
+
+/// The Git repository and branch a repository's archive is built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GitSource {
+    pub remote: String,
+    pub branch: String,
+}
+
+impl GitSource {
+    /// Works out where a GitHub or GitLab archive is built from. The branch
+    /// is the archive's channel.
+    pub fn for_archive(url: &Url) -> Option<Self> {
+        let (_, branch) = detect_channel(url)?;
+        let segments = url.path_segments()?.collect::<Vec<_>>();
+        let archive = segments.iter().position(|s| *s == "archive")?;
+        let host = url.host_str()?;
+
+        let project = match (host, &segments[..archive]) {
+            ("github.com", [owner, project]) => format!("{owner}/{project}"),
+            (_, [project @ .., "-"]) if !project.is_empty() => project.join("/"),
+            _ => return None,
+        };
+        Some(Self {
+            remote: format!("{}://{host}/{project}.git", url.scheme()),
+            branch,
+        })
+    }
+
+    /// The name of the source's checkout, keyed on a hash so that databases
+    /// sharing a directory of checkouts can't confuse each other's.
+    fn key(&self) -> String {
+        format!(
+            "{:x}",
+            Sha256::digest(format!("{}#{}", self.remote, self.branch))
+        )
+    }
+}
+
+/// How a file changed between two commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// Reads the output of `git diff --name-status -z`. Returns `None` if a file
+/// changed in a way which can't be applied by itself, such as its type
+/// changing.
+fn parse_changes(output: &[u8]) -> Option<Vec<(ChangeKind, PathBuf)>> {
+    let mut fields = output.split(|b| *b == 0).filter(|field| !field.is_empty());
+    let mut changes = vec![];
+
+    while let Some(status) = fields.next() {
+        let kind = match status {
+            b"A" => ChangeKind::Added,
+            b"M" => ChangeKind::Modified,
+            b"D" => ChangeKind::Deleted,
+            _ => return None,
+        };
+        let path = String::from_utf8_lossy(fields.next()?).into_owned();
+        changes.push((kind, path.into()));
+    }
+
+    Some(changes)
+}
+
+/// Runs `git` in `dir`, returning what it printed.
+async fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, GitError> {
+    debug!(?args, "Running git");
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // Fail rather than asking for credentials.
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(GitError::Unavailable)?;
+
+    if !output.status.success() {
+        return Err(GitError::Failed {
+            command: args.join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+impl RepoManager {
+    /// Updates a repository from the Git repository its archive is built
+    /// from, keeping a checkout of it in `checkouts`.
+    #[instrument(skip(self, checkouts, progress))]
+    pub(super) async fn update_from_git(
+        &mut self,
+        repo: &Repository,
+        source: &GitSource,
+        checkouts: &Path,
+        progress: Arc<DownloadProgressReporter>,
+    ) -> Result<UpdateReport> {
+        let key = source.key();
+        let checkout = checkouts.join(&key);
+
+        let fetched = if fs::try_exists(checkout.join(".git")).await? {
+            let args = [
+                "fetch",
+                "--quiet",
+                "--depth",
+                "1",
+                "origin",
+                source.branch.as_str(),
+            ];
+            git(&checkout, &args).await?;
+            "FETCH_HEAD"
+        } else {
+            fs::create_dir_all(checkouts).await?;
+            // A clone which was interrupted leaves a folder Git won't clone
+            // into.
+            if fs::try_exists(&checkout).await? {
+                fs::remove_dir_all(&checkout).await?;
+            }
+            let args = [
+                "clone",
+                "--quiet",
+                "--depth",
+                "1",
+                "--single-branch",
+                "--branch",
+                source.branch.as_str(),
+                source.remote.as_str(),
+                key.as_str(),
+            ];
+            git(checkouts, &args).await?;
+            "HEAD"
+        };
+        let commit = git(&checkout, &["rev-parse", fetched]).await?;
+        let commit = String::from_utf8_lossy(&commit).trim().to_string();
+
+        let previous = (self.db()?.repo_commit(repo.id)?)
+            .filter(|saved| saved.remote == source.remote && saved.branch == source.branch)
+            .map(|saved| saved.commit_id);
+        let changes = match &previous {
+            _ if self.force_reunpack => None,
+            Some(previous) if *previous == commit => {
+                info!("The repository hasn't changed since it was last updated");
+                return Ok(UpdateReport {
+                    unchanged: true,
+                    ..Default::default()
+                });
+            }
+            Some(previous) => {
+                let args = [
+                    "diff",
+                    "--name-status",
+                    "--no-renames",
+                    "-z",
+                    previous.as_str(),
+                    commit.as_str(),
+                ];
+                match git(&checkout, &args).await {
+                    Ok(output) => parse_changes(&output),
+                    // The commit may have been cleaned up, or the checkout
+                    // cloned again.
+                    Err(error) => {
+                        debug!(%error, "Couldn't compare with the last commit");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        git(
+            &checkout,
+            &["checkout", "--quiet", "--force", "--detach", &commit],
+        )
+        .await?;
+
+        // Other files, like `builds.json`, are only saved by a full unpack.
+        let changes = changes.filter(|changes| {
+            changes.iter().all(|(_, path)| {
+                matches!(
+                    RepoAssetVariant::from_root_path(path),
+                    None | Some(RepoAssetVariant::Release | RepoAssetVariant::DownloadCounts)
+                )
+            })
+        });
+
+        let report = match (previous, changes) {
+            (Some(previous), Some(changes)) => {
+                info!(
+                    changed = changes.len(),
+                    "Applying the files changed since the last update"
+                );
+                let commit = RepoCommit {
+                    remote: source.remote.clone(),
+                    branch: source.branch.clone(),
+                    commit_id: commit,
+                };
+                self.apply_changes(repo, &checkout, &previous, &commit, changes, progress)
+                    .await?
+            }
+            _ => {
+                info!("Unpacking the whole repository from its checkout");
+                let report = self
+                    .unpack_repo(repo, DirAssetLoader::new(&checkout), None, progress)
+                    .await?;
+                // If this isn't reached, the next update unpacks everything
+                // again.
+                self.db()?
+                    .set_repo_commit(repo.id, &source.remote, &source.branch, &commit)?;
+                report
+            }
+        };
+
+        Ok(report)
+    }
+
+    /// Applies the releases and download counts which changed since the
+    /// commit `previous` to `commit`, which is checked out, and records that
+    /// the repository was updated to it.
+    async fn apply_changes(
+        &mut self,
+        repo: &Repository,
+        checkout: &Path,
+        previous: &str,
+        commit: &RepoCommit,
+        changes: Vec<(ChangeKind, PathBuf)>,
+        progress: Arc<DownloadProgressReporter>,
+    ) -> Result<UpdateReport> {
+        let mut report = UpdateReport::default();
+        let mut removed = vec![];
+        let mut added = vec![];
+        let mut download_counts = None;
+
+        for (kind, path) in changes {
+            let Some(variant) = RepoAssetVariant::from_root_path(&path) else {
+                continue;
+            };
+
+            // The old document says which release to remove. If it was over
+            // the parse limits, it wasn't saved in the first place.
+            if kind != ChangeKind::Added && variant == RepoAssetVariant::Release {
+                let spec = format!("{previous}:{}", path.to_string_lossy());
+                let old = RepoAssetBuf {
+                    path: path.clone(),
+                    variant,
+                    data: git(checkout, &["show", &spec]).await?.into_boxed_slice(),
+                };
+                if let Ok(RepoAsset::Release(mut json)) =
+                    client::parse_asset(&old, &self.parse_limits)
+                {
+                    self.transforms.apply(&mut json, repo);
+                    removed.push((json.identifier, json.version));
+                }
+            }
+
+            // Links are skipped, as they are by `DirAssetLoader`, so that a
+            // repository can't have files outside its checkout read.
+            let file = checkout.join(&path);
+            if kind != ChangeKind::Deleted && fs::symlink_metadata(&file).await?.is_file() {
+                let new = RepoAssetBuf {
+                    data: fs::read(&file).await?.into_boxed_slice(),
+                    path,
+                    variant,
+                };
+                match self.parse_checkout_asset(repo, new, &mut report)? {
+                    Some(RepoAsset::Release(mut json)) => {
+                        self.transforms.apply(&mut json, repo);
+                        if repo.filter.as_ref().is_some_and(|f| !f.allows(&json)) {
+                            report.releases_filtered += 1;
+                        } else if self.policy.check_release(&json).is_err() {
+                            report.releases_blocked += 1;
+                        } else {
+                            added.push(json);
+                        }
+                    }
+                    Some(RepoAsset::DownloadCounts(counts)) => download_counts = Some(counts),
+                    _ => {}
+                }
+            }
+
+            progress.report_unpacked_item();
+            report.items_unpacked += 1;
+        }
+
+        // A release whose document changed is replaced rather than removed.
+        removed.retain(|(identifier, version)| {
+            !added
+                .iter()
+                .any(|json| json.identifier == *identifier && json.version == *version)
+        });
+
+        let repo_url = Arc::new(repo.url.clone());
+        let mut db = self.db()?;
+        let compress_metadata = db.compresses_metadata()?;
+        let mut db = db.with_metadata_compression(compress_metadata);
+
+        db.transaction(|mut db| {
+            for (identifier, version) in &removed {
+                db.delete_release(repo.id, identifier, version)?;
+            }
+            for json in &added {
+                db.create_release(json, repo.id, None)?;
+            }
+            db.set_etag(repo_url.clone(), None)?;
+            if let Some(counts) = &download_counts {
+                report.unmatched_download_counts =
+                    db.add_download_counts(repo.id, counts, self.download_count_mode)?;
+            }
+
+            db.set_repo_commit(repo.id, &commit.remote, &commit.branch, &commit.commit_id)?;
+            db.finish_update(repo.id)?;
+            Ok(())
+        })?;
+
+        Ok(report)
+    }
+
+    /// Parses a document from a checkout, quarantining it if it's over the
+    /// parse limits, and skipping it if it's a release with an invalid
+    /// identifier.
+    fn parse_checkout_asset(
+        &self,
+        repo: &Repository,
+        asset: RepoAssetBuf,
+        report: &mut UpdateReport,
+    ) -> Result<Option<RepoAsset>> {
+        match client::parse_asset(&asset, &self.parse_limits) {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(Error::Json(JsonError::Limit(error))) => {
+                warn!(
+                    path = %asset.path.display(),
+                    %error,
+                    "Skipping a repository document over the parse limits"
+                );
+                report.quarantined.push(QuarantinedAsset {
+                    path: asset.path,
+                    error,
+                });
+                Ok(None)
+            }
+            Err(Error::Json(JsonError::Identifier(error))) => {
+                warn!(
+                    path = %asset.path.display(),
+                    %error,
+                    "Skipping a release with an invalid identifier"
+                );
+                report.releases_invalid += 1;
+                Ok(None)
+            }
+            Err(Error::Json(source)) => Err(client::RepoUnpackError::InvalidJsonFile {
+                source,
+                url: Arc::new(repo.url.clone()),
+                path: asset.path,
+            }
+            .into()),
+            Err(other) => Err(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use diesel::prelude::*;
+
+    use super::*;
+    use crate::{
+        database::schema::*,
+        repo::test_support::{TestRelease, release},
+    };
+
+    #[test]
+    fn finds_git_sources() {
+        let source = |s| GitSource::for_archive(&Url::parse(s).unwrap());
+
+        assert_eq!(
+            source("https://github.com/KSP-CKAN/CKAN-meta/archive/master.tar.gz"),
+            Some(GitSource {
+                remote: "https://github.com/KSP-CKAN/CKAN-meta.git".into(),
+                branch: "master".into(),
+            })
+        );
+        assert_eq!(
+            source("https://gitlab.com/ksp/meta/-/archive/testing/meta-testing.tar.gz"),
+            Some(GitSource {
+                remote: "https://gitlab.com/ksp/meta.git".into(),
+                branch: "testing".into(),
+            })
+        );
+        assert_eq!(source("https://example.com/repo.tar.gz"), None);
+    }
+
+    #[test]
+    fn reads_changed_files() {
+        let output = b"A\0Scatterer/Scatterer-0.1.ckan\0M\0download_counts.json\0D\0Parallax/Parallax-1.0.ckan\0";
+        assert_eq!(
+            parse_changes(output),
+            Some(vec![
+                (ChangeKind::Added, "Scatterer/Scatterer-0.1.ckan".into()),
+                (ChangeKind::Modified, "download_counts.json".into()),
+                (ChangeKind::Deleted, "Parallax/Parallax-1.0.ckan".into()),
+            ])
+        );
+        assert_eq!(parse_changes(b""), Some(vec![]));
+        assert_eq!(parse_changes(b"T\0Parallax/Parallax-1.0.ckan\0"), None);
+    }
+
+    /// Commits every change in `dir` as a new commit.
+    fn commit(dir: &Path) {
+        for args in [
+            &["add", "--all"][..],
+            &[
+                "-c",
+                "user.name=Linx",
+                "-c",
+                "user.email=linx@example.com",
+                "commit",
+                "--quiet",
+                "--message",
+                "Update",
+            ],
+        ] {
+            let status = process::Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn applies_changed_files() {
+        let dir = env::temp_dir().join(format!("camrete-git-{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let upstream = dir.join("upstream");
+        let checkouts = dir.join("checkouts");
+        let write = |path: &str, release: TestRelease| {
+            let path = upstream.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, release.to_json().to_string()).unwrap();
+        };
+
+        std::fs::create_dir_all(&upstream).unwrap();
+        let status = process::Command::new("git")
+            .args(["init", "--quiet", "--initial-branch", "main"])
+            .arg(&upstream)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        write("Parallax/Parallax-1.0.ckan", release("Parallax", "1.0"));
+        write("Parallax/Parallax-1.1.ckan", release("Parallax", "1.1"));
+        commit(&upstream);
+
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let source = GitSource {
+            remote: format!("file://{}", upstream.display()),
+            branch: "main".into(),
+        };
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+
+        let report = mgr
+            .update_from_git(&repo, &source, &checkouts, progress())
+            .await
+            .unwrap();
+        assert_eq!(report.items_unpacked, 2);
+        let report = mgr
+            .update_from_git(&repo, &source, &checkouts, progress())
+            .await
+            .unwrap();
+        assert!(report.unchanged);
+
+        std::fs::remove_file(upstream.join("Parallax/Parallax-1.0.ckan")).unwrap();
+        write(
+            "Parallax/Parallax-1.1.ckan",
+            release("Parallax", "1.1").set("abstract", "A better mod"),
+        );
+        write(
+            "Scatterer/Scatterer-0.1.ckan",
+            release("Scatterer", "0.1").set("abstract", "Another mod"),
+        );
+        commit(&upstream);
+
+        let report = mgr
+            .update_from_git(&repo, &source, &checkouts, progress())
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!report.unchanged);
+        assert_eq!(report.items_unpacked, 3);
+
+        let mut db = mgr.db().unwrap();
+        let releases = module_releases::table
+            .inner_join(modules::table)
+            .filter(modules::repo_id.eq(repo.id))
+            .order_by((modules::module_slug, module_releases::version))
+            .select((
+                modules::module_slug,
+                module_releases::version,
+                module_releases::summary,
+            ))
+            .load::<(String, String, String)>(db.connection_mut())
+            .unwrap();
+        assert_eq!(
+            releases,
+            [
+                ("Parallax".into(), "1.1".into(), "A better mod".into()),
+                ("Scatterer".into(), "0.1".into(), "Another mod".into()),
+            ]
+        );
+        assert_eq!(
+            db.repo_commit(repo.id).unwrap().map(|saved| saved.remote),
+            Some(source.remote)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skips_linked_files() {
+        let dir = env::temp_dir().join(format!("camrete-git-links-{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let upstream = dir.join("upstream");
+        let checkouts = dir.join("checkouts");
+
+        std::fs::create_dir_all(upstream.join("Parallax")).unwrap();
+        let status = process::Command::new("git")
+            .args(["init", "--quiet", "--initial-branch", "main"])
+            .arg(&upstream)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::write(
+            upstream.join("Parallax/Parallax-1.0.ckan"),
+            release("Parallax", "1.0").to_json().to_string(),
+        )
+        .unwrap();
+        commit(&upstream);
+
+        let mut mgr = RepoManager::new(":memory:").unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+        let source = GitSource {
+            remote: format!("file://{}", upstream.display()),
+            branch: "main".into(),
+        };
+        let progress = || Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+        mgr.update_from_git(&repo, &source, &checkouts, progress())
+            .await
+            .unwrap();
+
+        // A file outside the checkout, which only the link points to.
+        let outside = dir.join("Secret-1.0.ckan");
+        let secret = release("Secret", "1.0").set("abstract", "Not in the repository");
+        std::fs::write(&outside, secret.to_json().to_string()).unwrap();
+        std::fs::create_dir_all(upstream.join("Secret")).unwrap();
+        std::os::unix::fs::symlink(&outside, upstream.join("Secret/Secret-1.0.ckan")).unwrap();
+        commit(&upstream);
+
+        let report = mgr
+            .update_from_git(&repo, &source, &checkouts, progress())
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!report.unchanged);
+
+        let slugs = modules::table
+            .filter(modules::repo_id.eq(repo.id))
+            .select(modules::module_slug)
+            .load::<String>(mgr.db().unwrap().connection_mut())
+            .unwrap();
+        assert_eq!(slugs, ["Parallax"]);
+    }
+}
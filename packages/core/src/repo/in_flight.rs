@@ -0,0 +1,182 @@
+//! Sharing one download between callers which want the same thing at once.
+//!
+//! The first caller to ask for something, like a URL or a repository, downloads
+//! it, and callers which ask while it's running wait for it instead, seeing its progress as they wait. Only
+//! callers in this process are joined this way; other programs sharing the
+//! database are kept apart by the update lock.
+
+use std::{collections::HashMap, hash::Hash, sync::Arc};
+
+use parking_lot::Mutex;
+use tokio::sync::watch;
+
+/// How a download which others may be waiting for is going.
+#[derive(Debug, Clone)]
+enum Status<T, P> {
+    /// The download is running, with its latest progress if it's reported any.
+    Running(Option<P>),
+    /// The download has ended, with what it produced if it succeeded.
+    Done(Option<T>),
+}
+
+type Channel<T, P> = Arc<watch::Sender<Status<T, P>>>;
+type Running<K, T, P> = Arc<Mutex<HashMap<K, Channel<T, P>>>>;
+
+/// The downloads running in this process, by what they download. Clones share
+/// the same downloads.
+#[derive(Debug)]
+pub(crate) struct InFlight<K, T, P> {
+    running: Running<K, T, P>,
+}
+
+impl<K, T, P> Clone for InFlight<K, T, P> {
+    fn clone(&self) -> Self {
+        Self {
+            running: self.running.clone(),
+        }
+    }
+}
+
+impl<K, T, P> Default for InFlight<K, T, P> {
+    fn default() -> Self {
+        Self {
+            running: Default::default(),
+        }
+    }
+}
+
+/// Whether a caller should download something itself, or wait for another
+/// caller which already is.
+pub(crate) enum Join<K: Eq + Hash, T, P> {
+    Lead(Leader<K, T, P>),
+    Follow(Follower<T, P>),
+}
+
+impl<K: Eq + Hash + Clone, T: Clone, P: Clone> InFlight<K, T, P> {
+    /// Starts a download of `key`, or joins the one which is already running.
+    pub(crate) fn join(&self, key: &K) -> Join<K, T, P> {
+        let mut running = self.running.lock();
+        if let Some(status) = running.get(key) {
+            return Join::Follow(Follower {
+                status: status.subscribe(),
+            });
+        }
+
+        let status = Arc::new(watch::channel(Status::Running(None)).0);
+        running.insert(key.clone(), status.clone());
+        Join::Lead(Leader {
+            running: self.running.clone(),
+            key: key.clone(),
+            status,
+            finished: false,
+        })
+    }
+}
+
+/// The caller which does a download. If it's dropped without finishing, the
+/// callers waiting for it are told the download failed.
+pub(crate) struct Leader<K: Eq + Hash, T, P> {
+    running: Running<K, T, P>,
+    key: K,
+    status: Channel<T, P>,
+    finished: bool,
+}
+
+impl<K: Eq + Hash, T, P> Leader<K, T, P> {
+    /// Returns a function which passes progress on to the callers waiting for
+    /// the download.
+    pub(crate) fn reporter(&self) -> impl Fn(P) + use<K, T, P> {
+        let status = self.status.clone();
+        move |progress| {
+            status.send_replace(Status::Running(Some(progress)));
+        }
+    }
+
+    /// Ends the download, handing what it produced to the callers waiting for
+    /// it, or `None` if it failed.
+    pub(crate) fn finish(mut self, outcome: Option<T>) {
+        self.end(outcome);
+    }
+
+    fn end(&mut self, outcome: Option<T>) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        // Removed first, so that a caller which tries again after a failure
+        // starts a new download rather than joining this one.
+        self.running.lock().remove(&self.key);
+        self.status.send_replace(Status::Done(outcome));
+    }
+}
+
+impl<K: Eq + Hash, T, P> Drop for Leader<K, T, P> {
+    fn drop(&mut self) {
+        self.end(None);
+    }
+}
+
+/// A caller waiting for another caller's download.
+pub(crate) struct Follower<T, P> {
+    status: watch::Receiver<Status<T, P>>,
+}
+
+impl<T: Clone, P: Clone> Follower<T, P> {
+    /// Waits for the download to end, passing its progress to `on_progress`.
+    /// Returns what it produced, or `None` if it failed, in which case the
+    /// caller should try the download itself.
+    pub(crate) async fn wait(mut self, on_progress: impl Fn(P)) -> Option<T> {
+        loop {
+            let status = self.status.borrow_and_update().clone();
+            match status {
+                Status::Running(Some(progress)) => on_progress(progress),
+                Status::Running(None) => {}
+                Status::Done(outcome) => return outcome,
+            }
+
+            if self.status.changed().await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn followers_share_the_download() {
+        let in_flight = InFlight::<Url, &str, u64>::default();
+        let url = Url::parse("https://example.com/repository.tar.gz").unwrap();
+
+        let Join::Lead(leader) = in_flight.join(&url) else {
+            panic!("nothing was downloading the URL");
+        };
+        let Join::Follow(follower) = in_flight.join(&url) else {
+            panic!("the first download wasn't joined");
+        };
+
+        leader.reporter()(10);
+        let seen = Mutex::new(vec![]);
+        let (outcome, ()) = tokio::join!(follower.wait(|bytes| seen.lock().push(bytes)), async {
+            leader.finish(Some("done"));
+        });
+        assert_eq!(outcome, Some("done"));
+        assert_eq!(seen.into_inner(), [10]);
+
+        // A failed download isn't joined by callers which try again.
+        let Join::Lead(leader) = in_flight.join(&url) else {
+            panic!("the finished download was joined");
+        };
+        let Join::Follow(follower) = in_flight.join(&url) else {
+            panic!("the second download wasn't joined");
+        };
+        drop(leader);
+        assert_eq!(follower.wait(|_| {}).await, None);
+        assert!(matches!(in_flight.join(&url), Join::Lead(_)));
+    }
+}
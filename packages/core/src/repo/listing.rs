@@ -0,0 +1,127 @@
+//! Listing the files in a release's download before it's been downloaded.
+
+use std::path::Path;
+
+use reqwest::{
+    StatusCode,
+    header::{CONTENT_RANGE, HeaderMap, RANGE},
+};
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+use crate::{
+    Result,
+    database::NewDownload,
+    install::{ArchiveListing, ArchiveSource, ListingError},
+    policy::{Policy, PolicyError},
+    repo::{HttpResponse, RepoManager, Transport},
+};
+
+impl RepoManager {
+    /// Lists the files in a download, so that its install directives can be
+    /// checked before it's been downloaded.
+    ///
+    /// If the download has already finished, its files are read from disk.
+    /// Otherwise only the end of the archive is requested from each of its
+    /// URLs in turn, which needs a server that supports range requests. Like
+    /// downloads, URLs on hosts the [`Policy`] blocks are never requested.
+    #[instrument(skip_all, fields(slug = download.slug, version = download.version))]
+    pub async fn archive_listing(&self, download: &NewDownload) -> Result<ArchiveListing> {
+        let destination = Path::new(&download.destination);
+        if destination.is_file() {
+            debug!("Listing a downloaded archive");
+            return ArchiveListing::read_file(destination).await;
+        }
+
+        let mut urls = self.db()?.rank_mirrors(&download.urls())?;
+        urls.retain(|url| self.policy.allows_url(url));
+        if urls.is_empty() {
+            return Err(PolicyError::BlockedHost {
+                url: download.url.clone(),
+            }
+            .into());
+        }
+
+        let mut last_error = None;
+        for url in urls {
+            let mut archive = RemoteArchive {
+                transport: &self.transport,
+                policy: &self.policy,
+                url: &url,
+            };
+
+            match ArchiveListing::read(&mut archive).await {
+                Ok(listing) => return Ok(listing),
+                Err(error) => {
+                    warn!(%url, %error, "Couldn't list the files in a download");
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.expect("a download always has at least one URL"))
+    }
+}
+
+/// An archive on a server which supports range requests.
+struct RemoteArchive<'a> {
+    transport: &'a Transport,
+    policy: &'a Policy,
+    url: &'a Url,
+}
+
+impl RemoteArchive<'_> {
+    async fn get(&self, range: String) -> Result<HttpResponse> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, range.parse().expect("range is a valid header"));
+
+        self.transport
+            .get_allowed(self.url, headers, self.policy)
+            .await?
+            .error_for_status()
+    }
+
+    fn ranges_unsupported(&self) -> ListingError {
+        ListingError::RangesUnsupported {
+            url: self.url.to_string(),
+        }
+    }
+}
+
+impl ArchiveSource for RemoteArchive<'_> {
+    async fn read_tail(&mut self, len: u64) -> Result<(u64, Vec<u8>)> {
+        let response = self.get(format!("bytes=-{len}")).await?;
+
+        if response.status != StatusCode::PARTIAL_CONTENT {
+            // A server which ignores the range sends the whole file, which is
+            // only worth reading if it's small anyway.
+            if response.content_length().is_some_and(|size| size <= len) {
+                return Ok((0, response.bytes().await?.to_vec()));
+            }
+            return Err(self.ranges_unsupported().into());
+        }
+
+        // Like `bytes 1024-2047/2048`.
+        let offset = response
+            .headers
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("bytes "))
+            .and_then(|value| value.split_once('-'))
+            .and_then(|(start, _)| start.parse().ok())
+            .ok_or_else(|| self.ranges_unsupported())?;
+
+        Ok((offset, response.bytes().await?.to_vec()))
+    }
+
+    async fn read_range(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let last = (offset + len).saturating_sub(1);
+        let response = self.get(format!("bytes={offset}-{last}")).await?;
+
+        if response.status != StatusCode::PARTIAL_CONTENT {
+            return Err(self.ranges_unsupported().into());
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+}
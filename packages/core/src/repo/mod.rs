@@ -1,8 +1,69 @@
+#[cfg(feature = "network")]
+mod archive_cache;
+#[cfg(feature = "network")]
 pub mod asset_stream;
+#[cfg(feature = "network")]
+mod background;
+#[cfg(feature = "network")]
+mod bugtracker;
 pub mod client;
+#[cfg(feature = "network")]
+mod downloads;
+#[cfg(feature = "network")]
+mod encoding;
+#[cfg(feature = "sqlcipher")]
+mod encryption;
 pub mod game;
+#[cfg(feature = "network")]
+mod git;
+#[cfg(feature = "network")]
+mod in_flight;
+#[cfg(feature = "network")]
+mod listing;
+#[cfg(feature = "network")]
+mod preview;
+#[cfg(feature = "network")]
+mod refresh;
+#[cfg(feature = "network")]
+mod release_notes;
+#[cfg(feature = "network")]
+mod spool;
+#[cfg(test)]
+pub(crate) mod test_support;
+#[cfg(feature = "parse-timing")]
+pub mod timing;
+#[cfg(feature = "network")]
+mod transform;
+#[cfg(feature = "network")]
+mod transport;
 
+#[cfg(feature = "network")]
 pub use asset_stream::{
-    RepoAsset, RepoAssetBuf, RepoAssetLoader, RepoAssetVariant, TarGzAssetLoader,
+    DirAssetLoader, FormatRegistry, GzipAssetLoader, LoaderFn, PackedRepo, RepoAsset, RepoAssetBuf,
+    RepoAssetLoader, RepoAssetVariant, RepoFormat, TarAssetLoader, TarGzAssetLoader,
 };
-pub use client::{DownloadProgress, RepoManager, RepoUnpackError};
+#[cfg(feature = "network")]
+pub use background::PendingRepoManager;
+#[cfg(feature = "network")]
+pub use client::{DownloadProgress, QuarantinedAsset, UpdateReport};
+pub use client::{RepoManager, RepoManagerBuilder, RepoUnpackError};
+#[cfg(feature = "network")]
+pub use downloads::{DownloadError, DownloadEvent};
+#[cfg(feature = "sqlcipher")]
+pub(crate) use encryption::DatabaseKey;
+#[cfg(feature = "sqlcipher")]
+pub use encryption::{KeyError, KeyProvider, StaticKey, decrypt_database, encrypt_database};
+#[cfg(feature = "network")]
+pub use git::GitError;
+#[cfg(feature = "network")]
+pub use preview::{ModuleChange, UpdatePreview};
+#[cfg(feature = "network")]
+pub use refresh::{RefreshError, RefreshSource, RefreshedRelease};
+#[cfg(feature = "network")]
+pub use spool::SpoolOptions;
+#[cfg(feature = "network")]
+pub use transform::{ReleaseTransform, TransformRegistry};
+#[cfg(feature = "network")]
+pub(crate) use transport::Transport;
+#[cfg(feature = "network")]
+pub use transport::{HttpResponse, HttpTransport, Redirect, ReqwestTransport};
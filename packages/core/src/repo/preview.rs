@@ -0,0 +1,128 @@
+//! Comparisons between a repository's new contents and the copy in the database.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// The changes that updating a repository would make, as found by
+/// [`RepoManager::preview_download`](super::RepoManager::preview_download).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UpdatePreview {
+    /// Modules which would be added to the database.
+    pub added: Vec<ModuleChange>,
+    /// Modules which would be removed from the database.
+    pub removed: Vec<ModuleChange>,
+    /// Modules which would gain or lose releases.
+    pub updated: Vec<ModuleChange>,
+}
+
+/// The releases of a module which would be added or removed by an update.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ModuleChange {
+    pub slug: String,
+    pub added_versions: Vec<String>,
+    pub removed_versions: Vec<String>,
+}
+
+impl UpdatePreview {
+    /// Compares the versions of each module currently in the database to the
+    /// versions an update would provide.
+    pub(crate) fn compare(
+        mut current: HashMap<String, BTreeSet<String>>,
+        incoming: HashMap<String, BTreeSet<String>>,
+    ) -> Self {
+        let mut preview = Self::default();
+
+        for (slug, new_versions) in incoming {
+            let old_versions = current.remove(&slug).unwrap_or_default();
+
+            let change = ModuleChange {
+                added_versions: new_versions.difference(&old_versions).cloned().collect(),
+                removed_versions: old_versions.difference(&new_versions).cloned().collect(),
+                slug,
+            };
+
+            if old_versions.is_empty() {
+                preview.added.push(change);
+            } else if !change.added_versions.is_empty() || !change.removed_versions.is_empty() {
+                preview.updated.push(change);
+            }
+        }
+
+        for (slug, old_versions) in current {
+            preview.removed.push(ModuleChange {
+                slug,
+                added_versions: vec![],
+                removed_versions: old_versions.into_iter().collect(),
+            });
+        }
+
+        for changes in [&mut preview.added, &mut preview.removed, &mut preview.updated] {
+            changes.sort_by(|l, r| l.slug.cmp(&r.slug));
+        }
+
+        preview
+    }
+
+    /// Returns whether the update wouldn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn modules(list: &[(&str, &[&str])]) -> HashMap<String, BTreeSet<String>> {
+        list.iter()
+            .map(|(slug, versions)| {
+                let versions = versions.iter().map(|v| v.to_string()).collect();
+                (slug.to_string(), versions)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare_modules() {
+        let current = modules(&[
+            ("Parallax", &["1.0", "1.1"]),
+            ("Scatterer", &["0.08"]),
+            ("Kopernicus", &["1.0"]),
+        ]);
+        let incoming = modules(&[
+            ("Parallax", &["1.1", "1.2"]),
+            ("Scatterer", &["0.08"]),
+            ("EVE", &["1.0"]),
+        ]);
+
+        let preview = UpdatePreview::compare(current, incoming);
+
+        assert_eq!(
+            preview,
+            UpdatePreview {
+                added: vec![ModuleChange {
+                    slug: "EVE".into(),
+                    added_versions: vec!["1.0".into()],
+                    removed_versions: vec![],
+                }],
+                removed: vec![ModuleChange {
+                    slug: "Kopernicus".into(),
+                    added_versions: vec![],
+                    removed_versions: vec!["1.0".into()],
+                }],
+                updated: vec![ModuleChange {
+                    slug: "Parallax".into(),
+                    added_versions: vec!["1.2".into()],
+                    removed_versions: vec!["1.0".into()],
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn unchanged_is_empty() {
+        let current = modules(&[("Parallax", &["1.0"])]);
+        let preview = UpdatePreview::compare(current.clone(), current);
+
+        assert!(preview.is_empty());
+    }
+}
@@ -0,0 +1,448 @@
+//! Refreshing a single release from its `.ckan` document, without downloading
+//! the whole repository.
+//!
+//! Metadata authors can check a change to a module's metadata as soon as it's
+//! pushed, rather than waiting for the repository's archive to be rebuilt.
+//! The refreshed release is replaced again at the repository's next update.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use diesel::prelude::*;
+use miette::Diagnostic;
+use reqwest::header::{ACCEPT, HeaderMap, HeaderValue};
+use thiserror::Error;
+use tracing::{debug, info, instrument};
+use url::Url;
+
+use crate::{
+    Result,
+    database::{
+        ModuleId,
+        models::{Module, ModuleRelease, Repository},
+        schema::*,
+    },
+    json::{Identifier, IdentifierError, JsonModule},
+    repo::{RepoAsset, RepoAssetBuf, RepoAssetVariant, RepoManager, RepoUnpackError, client},
+};
+
+#[derive(Debug, Error, Diagnostic, PartialEq, Eq)]
+pub enum RefreshError {
+    #[error("no repository has a mod called {identifier}")]
+    #[diagnostic(code(camrete::refresh::unknown_module), severity(Warning))]
+    UnknownModule { identifier: String },
+    #[error(
+        "can't tell where {identifier}'s metadata is in {repo_url}, which isn't on GitHub or GitLab"
+    )]
+    #[diagnostic(
+        code(camrete::refresh::no_document_url),
+        severity(Warning),
+        help("pass the URL of the mod's `.ckan` file instead")
+    )]
+    NoDocumentUrl { identifier: String, repo_url: Url },
+    #[error("{identifier} {version} is left out by the filter of repository {repo:?}")]
+    #[diagnostic(
+        code(camrete::refresh::filtered),
+        severity(Warning),
+        help("change the repository's filter with `camrete config repo-filter`")
+    )]
+    Filtered {
+        identifier: String,
+        version: String,
+        repo: String,
+    },
+    #[error("there are no repositories to save {identifier} to")]
+    #[diagnostic(
+        code(camrete::refresh::no_repository),
+        severity(Warning),
+        help("add a repository with `camrete repo add`")
+    )]
+    NoRepository { identifier: String },
+}
+
+/// Where [`RepoManager::refresh_module`] fetches a release's metadata from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshSource {
+    /// A `.ckan` document, such as a raw file on GitHub.
+    Url(Url),
+    /// The document for a module's newest release, in the repository the
+    /// module is from. Only repositories downloaded from a GitHub or GitLab
+    /// archive say where their documents are.
+    Module(Identifier),
+}
+
+impl FromStr for RefreshSource {
+    type Err = IdentifierError;
+
+    /// Reads an HTTP or HTTPS URL, or otherwise a module identifier.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Url::parse(s) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(Self::Url(url)),
+            _ => Ok(Self::Module(s.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for RefreshSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Url(url) => fmt::Display::fmt(url, f),
+            Self::Module(identifier) => fmt::Display::fmt(identifier, f),
+        }
+    }
+}
+
+/// A release which was saved by [`RepoManager::refresh_module`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshedRelease {
+    pub identifier: String,
+    pub version: String,
+    /// The name of the repository the release was saved to.
+    pub repo: String,
+    /// Where the release's metadata was fetched from.
+    pub url: Url,
+    /// Whether the release replaced one with the same version.
+    pub replaced: bool,
+}
+
+impl RepoManager {
+    /// Fetches a single release's metadata and saves it, replacing the
+    /// release with the same version if there is one. The rest of the
+    /// repository is left as it is.
+    ///
+    /// The release is saved to `repo` if one is given. Otherwise it's saved to
+    /// the highest-priority repository which already has the module, or to the
+    /// highest-priority repository if none do.
+    #[instrument(skip(self, repo), fields(source = %source))]
+    pub async fn refresh_module(
+        &self,
+        source: &RefreshSource,
+        repo: Option<&Repository>,
+    ) -> Result<RefreshedRelease> {
+        let url = match source {
+            RefreshSource::Url(url) => url.clone(),
+            RefreshSource::Module(identifier) => self.newest_document_url(identifier)?,
+        };
+
+        debug!(%url, "Fetching release metadata");
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let response = self.transport.get(&url, headers).await?.error_for_status()?;
+        let data = response.bytes().await?;
+
+        let asset = RepoAssetBuf {
+            path: url.path().trim_start_matches('/').into(),
+            variant: RepoAssetVariant::Release,
+            data: data.to_vec().into_boxed_slice(),
+        };
+        let mut json = match client::parse_asset(&asset, &self.parse_limits) {
+            Ok(RepoAsset::Release(json)) => json,
+            Ok(_) => unreachable!("releases are parsed as releases"),
+            Err(crate::Error::Json(source)) => {
+                return Err(RepoUnpackError::InvalidJsonFile {
+                    source,
+                    url: Arc::new(url),
+                    path: asset.path,
+                }
+                .into());
+            }
+            Err(other) => return Err(other),
+        };
+
+        let found;
+        let repo = match repo {
+            Some(repo) => repo,
+            None => {
+                found = self.repo_for(&json)?;
+                &found
+            }
+        };
+
+        self.transforms.apply(&mut json, repo);
+        if repo.filter.as_ref().is_some_and(|f| !f.allows(&json)) {
+            return Err(RefreshError::Filtered {
+                identifier: json.identifier.to_string(),
+                version: json.version.clone(),
+                repo: repo.name.clone(),
+            }
+            .into());
+        }
+        self.policy.enforce(&json)?;
+
+        let mut db = self.db()?;
+        let compress_metadata = db.compresses_metadata()?;
+        let mut db = db.with_metadata_compression(compress_metadata);
+
+        let replaced = db.transaction(|mut db| {
+            let module_id = modules::table
+                .filter(Module::in_repo(repo.id))
+                .filter(Module::with_slug(&json.identifier))
+                .select(modules::module_id)
+                .first::<ModuleId>(&mut *db.connection)
+                .optional()?;
+            let replaced = match module_id {
+                Some(module_id) => diesel::select(diesel::dsl::exists(
+                    ModuleRelease::all()
+                        .filter(ModuleRelease::with_parent(module_id))
+                        .filter(module_releases::version.eq(&json.version)),
+                ))
+                .get_result::<bool>(&mut *db.connection)?,
+                None => false,
+            };
+
+            db.create_release(&json, repo.id, module_id)?;
+            // The repository no longer matches the commit it was updated
+            // from, so its next update replaces this release again.
+            db.forget_repo_commit(repo.id)?;
+            db.finish_update(repo.id)?;
+            Ok(replaced)
+        })?;
+
+        info!(
+            identifier = %json.identifier,
+            version = %json.version,
+            repo = %repo.name,
+            "Refreshed a release"
+        );
+        Ok(RefreshedRelease {
+            identifier: json.identifier.to_string(),
+            version: json.version.clone(),
+            repo: repo.name.clone(),
+            url,
+            replaced,
+        })
+    }
+
+    /// Where the document for a module's newest release is in the repository
+    /// the module is from.
+    fn newest_document_url(&self, identifier: &Identifier) -> Result<Url> {
+        let newest = self.db()?.read_with_retry(|conn| {
+            modules::table
+                .inner_join(module_releases::table)
+                .inner_join(repositories::table)
+                .filter(Module::with_slug(identifier))
+                .order_by((repositories::priority, repositories::name))
+                .then_order_by(ModuleRelease::by_version())
+                .select((Repository::as_select(), module_releases::version))
+                .first::<(Repository, String)>(conn)
+                .optional()
+        })?;
+
+        let Some((repo, version)) = newest else {
+            return Err(RefreshError::UnknownModule {
+                identifier: identifier.to_string(),
+            }
+            .into());
+        };
+
+        document_url(&repo.url, identifier, &version).ok_or_else(|| {
+            RefreshError::NoDocumentUrl {
+                identifier: identifier.to_string(),
+                repo_url: repo.url.clone(),
+            }
+            .into()
+        })
+    }
+
+    /// The repository a refreshed release belongs in, when one isn't chosen.
+    fn repo_for(&self, json: &JsonModule) -> Result<Repository> {
+        let mut db = self.db()?;
+        let holder = db.read_with_retry(|conn| {
+            repositories::table
+                .inner_join(modules::table)
+                .filter(Module::with_slug(&json.identifier))
+                .order_by((repositories::priority, repositories::name))
+                .select(Repository::as_select())
+                .first::<Repository>(conn)
+                .optional()
+        })?;
+
+        if let Some(repo) = holder {
+            return Ok(repo);
+        }
+
+        let repos = db.all_repos(false)?;
+        repos
+            .into_iter()
+            .min_by(|a, b| (a.priority, &a.name).cmp(&(b.priority, &b.name)))
+            .ok_or_else(|| {
+                RefreshError::NoRepository {
+                    identifier: json.identifier.to_string(),
+                }
+                .into()
+            })
+    }
+}
+
+/// The URL of a release's document in a repository downloaded from a GitHub
+/// or GitLab archive. CKAN-meta keeps each release at
+/// `Identifier/Identifier-version.ckan`, with any `:` in the version replaced
+/// by `-`.
+fn document_url(repo_url: &Url, identifier: &Identifier, version: &str) -> Option<Url> {
+    let (_, channel) = crate::database::detect_channel(repo_url)?;
+    let segments = repo_url.path_segments()?.collect::<Vec<_>>();
+    let archive = segments.iter().position(|s| *s == "archive")?;
+    let file = format!("{identifier}/{identifier}-{}.ckan", version.replace(':', "-"));
+
+    let raw = match (repo_url.host_str()?, &segments[..archive]) {
+        ("github.com", [owner, project]) => {
+            format!("https://raw.githubusercontent.com/{owner}/{project}/{channel}/{file}")
+        }
+        (host, [project @ .., "-"]) if !project.is_empty() => {
+            let project = project.join("/");
+            format!("{}://{host}/{project}/-/raw/{channel}/{file}", repo_url.scheme())
+        }
+        _ => return None,
+    };
+    Url::parse(&raw).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use futures_util::{FutureExt, StreamExt, future::BoxFuture, stream};
+    use parking_lot::Mutex;
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::repo::{HttpResponse, HttpTransport, test_support::release};
+
+    fn id(identifier: &str) -> Identifier {
+        identifier.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_document_urls() {
+        let url = |s| Url::parse(s).unwrap();
+
+        assert_eq!(
+            document_url(
+                &url("https://github.com/KSP-CKAN/CKAN-meta/archive/master.tar.gz"),
+                &id("Parallax"),
+                "2.0.8"
+            ),
+            Some(url(
+                "https://raw.githubusercontent.com/KSP-CKAN/CKAN-meta/master/Parallax/Parallax-2.0.8.ckan"
+            ))
+        );
+        assert_eq!(
+            document_url(
+                &url("https://gitlab.com/ksp/meta/-/archive/testing/meta-testing.tar.gz"),
+                &id("Kopernicus"),
+                "2:release-1.12.1-65"
+            ),
+            Some(url(
+                "https://gitlab.com/ksp/meta/-/raw/testing/Kopernicus/Kopernicus-2-release-1.12.1-65.ckan"
+            ))
+        );
+        assert_eq!(
+            document_url(
+                &url("https://example.com/repo.tar.gz"),
+                &id("Parallax"),
+                "2.0.8"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_sources() {
+        assert_eq!(
+            "Parallax".parse::<RefreshSource>(),
+            Ok(RefreshSource::Module(id("Parallax")))
+        );
+        assert!(matches!(
+            "https://example.com/Parallax.ckan".parse(),
+            Ok(RefreshSource::Url(_))
+        ));
+        assert!("not a mod".parse::<RefreshSource>().is_err());
+    }
+
+    /// Serves one `.ckan` document at every URL, remembering which were asked
+    /// for.
+    struct OneDocument {
+        document: Vec<u8>,
+        requested: Arc<Mutex<Vec<Url>>>,
+    }
+
+    impl HttpTransport for OneDocument {
+        fn get_streaming(&self, url: &Url, _: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>> {
+            self.requested.lock().push(url.clone());
+            let response = HttpResponse {
+                url: url.clone(),
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: stream::iter([Ok(Bytes::from(self.document.clone()))]).boxed(),
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refreshes_a_release() {
+        let document = release("Parallax", "2.0").set("abstract", "Fixed abstract");
+        let requested = Arc::default();
+        let mgr = RepoManager::builder(":memory:")
+            .transport(OneDocument {
+                document: document.to_json().to_string().into_bytes(),
+                requested: Arc::clone(&requested),
+            })
+            .build()
+            .unwrap();
+
+        let mut db = mgr.db().unwrap();
+        let repo = db.all_repos(true).unwrap().remove(0);
+        let old = document.set("abstract", "Typo'd abstract").build();
+        db.create_release(&old, repo.id, None).unwrap();
+        drop(db);
+
+        let refreshed = mgr
+            .refresh_module(&RefreshSource::Module(id("Parallax")), None)
+            .await
+            .unwrap();
+        assert_eq!(refreshed.repo, repo.name);
+        assert_eq!(refreshed.version, "2.0");
+        assert!(refreshed.replaced);
+        assert_eq!(
+            requested.lock().as_slice(),
+            [Url::parse(
+                "https://raw.githubusercontent.com/KSP-CKAN/CKAN-meta/master/Parallax/Parallax-2.0.ckan"
+            )
+            .unwrap()]
+        );
+
+        let summaries: Vec<String> = module_releases::table
+            .select(module_releases::summary)
+            .load(mgr.db().unwrap().connection_mut())
+            .unwrap();
+        assert_eq!(summaries, ["Fixed abstract"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refreshing_without_repositories_fails() {
+        let document = release("Parallax", "2.0").to_json().to_string();
+        let mgr = RepoManager::builder(":memory:")
+            .transport(OneDocument {
+                document: document.into_bytes(),
+                requested: Arc::default(),
+            })
+            .build()
+            .unwrap();
+        diesel::delete(repositories::table)
+            .execute(mgr.db().unwrap().connection_mut())
+            .unwrap();
+
+        let url = Url::parse("https://example.com/Parallax.ckan").unwrap();
+        let err = mgr
+            .refresh_module(&RefreshSource::Url(url), None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                crate::Error::Refresh(RefreshError::NoRepository { ref identifier })
+                    if identifier == "Parallax"
+            ),
+            "{err:?}"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Fetching release notes from GitHub for modules hosted there.
+
+use std::time::Duration;
+
+use reqwest::{
+    StatusCode,
+    header::{ACCEPT, HeaderMap, HeaderValue},
+};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{
+    Result,
+    database::{ReleaseNotes, github_repo, models::ModuleRelease},
+    repo::RepoManager,
+};
+
+const GITHUB_API: &str = "https://api.github.com";
+
+/// How long it's remembered that GitHub has no notes for a release, in case
+/// they're published later.
+const MISSING_NOTES_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The parts of GitHub's release object that are kept.
+#[derive(Debug, Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    body: Option<String>,
+    html_url: Url,
+}
+
+impl RepoManager {
+    /// Finds the notes GitHub has for a release, if the module's repository is
+    /// hosted there.
+    ///
+    /// Notes are cached, so each release's notes are only fetched once. New
+    /// notes are only fetched while the `release_notes` setting is on; when
+    /// it's off, only notes which are already cached are returned.
+    #[instrument(skip_all, fields(version = release.version))]
+    pub async fn fetch_release_notes(
+        &self,
+        release: &ModuleRelease,
+    ) -> Result<Option<ReleaseNotes>> {
+        let Some(repo) = release
+            .metadata
+            .resources
+            .repository
+            .as_ref()
+            .and_then(github_repo)
+        else {
+            return Ok(None);
+        };
+
+        {
+            let mut db = self.db()?;
+            if let Some(notes) =
+                db.cached_release_notes(&repo, &release.version, MISSING_NOTES_TTL)?
+            {
+                return Ok(notes);
+            }
+            if !db.fetches_release_notes()? {
+                return Ok(None);
+            }
+        }
+
+        let mut notes = None;
+        for tag in tag_candidates(&release.version) {
+            let mut url = Url::parse(GITHUB_API).expect("GitHub API URL is valid");
+            url.path_segments_mut()
+                .expect("GitHub API URL has a path")
+                .extend(repo.split('/'))
+                .extend(["releases", "tags", &tag]);
+
+            debug!(%url, "Fetching release notes");
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                ACCEPT,
+                HeaderValue::from_static("application/vnd.github+json"),
+            );
+            let response = self.transport.get(&url, headers).await?;
+            if response.status == StatusCode::NOT_FOUND {
+                continue;
+            }
+
+            let data = response.error_for_status()?.bytes().await?;
+            let found = serde_json::from_slice::<GitHubRelease>(&data)?;
+            notes = Some(ReleaseNotes {
+                tag: found.tag_name,
+                body: found.body.unwrap_or_default(),
+                url: found.html_url,
+            });
+            break;
+        }
+
+        self.db()?
+            .cache_release_notes(&repo, &release.version, notes.as_ref())?;
+        Ok(notes)
+    }
+}
+
+/// The tags a release might be published under on GitHub. Numeric versions are
+/// often tagged with a `v` in front, and CKAN's epochs never appear in tags.
+fn tag_candidates(version: &str) -> Vec<String> {
+    let version = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.bytes().all(|b| b.is_ascii_digit()) => rest,
+        _ => version,
+    };
+
+    if let Some(bare) = version.strip_prefix('v') {
+        vec![version.to_owned(), bare.to_owned()]
+    } else if version.starts_with(|c: char| c.is_ascii_digit()) {
+        vec![version.to_owned(), format!("v{version}")]
+    } else {
+        vec![version.to_owned()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::RepoDB;
+
+    #[test]
+    fn guesses_tags() {
+        assert_eq!(tag_candidates("1.2.0"), ["1.2.0", "v1.2.0"]);
+        assert_eq!(tag_candidates("v2.4.8.6"), ["v2.4.8.6", "2.4.8.6"]);
+        assert_eq!(tag_candidates("1:release-1.12.1-65"), ["release-1.12.1-65"]);
+    }
+
+    #[test]
+    fn caches_notes() {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+        let notes = ReleaseNotes {
+            tag: "v1.0".into(),
+            body: "* Fixed the terrain shader".into(),
+            url: Url::parse("https://github.com/Linx/Parallax/releases/tag/v1.0").unwrap(),
+        };
+
+        let cached = |db: &mut RepoDB<_>, version, ttl| {
+            db.cached_release_notes("Linx/Parallax", version, ttl)
+                .unwrap()
+        };
+        assert_eq!(cached(&mut db, "1.0", MISSING_NOTES_TTL), None);
+
+        db.cache_release_notes("Linx/Parallax", "1.0", Some(&notes))
+            .unwrap();
+        db.cache_release_notes("Linx/Parallax", "1.1", None)
+            .unwrap();
+
+        assert_eq!(
+            cached(&mut db, "1.0", Duration::ZERO),
+            Some(Some(notes.clone()))
+        );
+        assert_eq!(cached(&mut db, "1.1", MISSING_NOTES_TTL), Some(None));
+        // Once a missing release has been remembered for long enough, it's
+        // looked for again.
+        assert_eq!(cached(&mut db, "1.1", Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! Writing repository documents to disk while they wait to be parsed, for
+//! devices without much memory.
+//!
+//! Documents arrive from the archive faster than they can be parsed, so
+//! normally every document which hasn't been parsed yet is held in memory. With
+//! [`SpoolOptions`], documents which would put the unparsed ones over a memory
+//! budget are written to a temporary directory instead, and read back one
+//! parse task at a time.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+use crate::{
+    Result,
+    repo::{RepoAssetBuf, RepoAssetVariant},
+};
+
+/// Used to give each spool directory a different name.
+static SPOOL_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// When repository documents are written to disk while they wait to be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpoolOptions {
+    /// The most bytes of unparsed documents to keep in memory at once. Once
+    /// this is reached, documents are written to disk until some of the ones
+    /// in memory have been parsed.
+    pub memory_budget: usize,
+    /// Where the temporary directory for the documents is created.
+    pub directory: PathBuf,
+}
+
+impl SpoolOptions {
+    /// Keeps up to `memory_budget` bytes of unparsed documents in memory, and
+    /// writes the rest to the system's temporary directory.
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            memory_budget,
+            directory: std::env::temp_dir(),
+        }
+    }
+
+    /// Writes documents to a temporary directory inside `directory` instead.
+    pub fn in_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = directory.into();
+        self
+    }
+}
+
+/// Decides whether each document waiting to be parsed is kept in memory or
+/// written to disk.
+pub(crate) struct Spool {
+    options: Option<SpoolOptions>,
+    /// The bytes of unparsed documents which are currently in memory.
+    in_memory: Arc<AtomicUsize>,
+    /// Only as many documents as there are threads are read back from the
+    /// disk at once, otherwise waiting parse tasks would read them all back
+    /// into memory.
+    readers: Arc<Semaphore>,
+    /// Created when the first document is written to disk.
+    dir: Option<Arc<SpoolDir>>,
+    written: u64,
+}
+
+impl Spool {
+    /// Holds documents according to `options`, or always in memory if there
+    /// aren't any.
+    pub fn new(options: Option<SpoolOptions>) -> Self {
+        let readers = thread::available_parallelism().map_or(1, |n| n.get());
+
+        Self {
+            options,
+            in_memory: Arc::default(),
+            readers: Arc::new(Semaphore::new(readers)),
+            dir: None,
+            written: 0,
+        }
+    }
+
+    /// Keeps `asset` in memory if it fits in the budget, otherwise writes it
+    /// to disk. If it can't be written, it's kept in memory anyway.
+    pub fn hold(&mut self, asset: RepoAssetBuf) -> PendingAsset {
+        let size = asset.data.len();
+
+        let budget = self.options.as_ref().map_or(usize::MAX, |o| o.memory_budget);
+        if self.in_memory.load(Ordering::Acquire).saturating_add(size) > budget {
+            match self.write(&asset) {
+                Ok(file) => {
+                    return PendingAsset::OnDisk {
+                        path: asset.path,
+                        variant: asset.variant,
+                        file,
+                        readers: self.readers.clone(),
+                    };
+                }
+                Err(error) => warn!(
+                    path = %asset.path.display(),
+                    %error,
+                    "Couldn't write a repository document to disk, keeping it in memory"
+                ),
+            }
+        }
+
+        self.in_memory.fetch_add(size, Ordering::AcqRel);
+        PendingAsset::InMemory {
+            asset,
+            reservation: Reservation {
+                size,
+                in_memory: self.in_memory.clone(),
+            },
+        }
+    }
+
+    fn write(&mut self, asset: &RepoAssetBuf) -> io::Result<SpooledFile> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let options = self.options.as_ref().expect("spooling is enabled");
+                let dir = Arc::new(SpoolDir::create(&options.directory)?);
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+
+        let path = dir.path.join(format!("{}.json", self.written));
+        fs::write(&path, &asset.data)?;
+        self.written += 1;
+
+        Ok(SpooledFile { path, _dir: dir })
+    }
+}
+
+/// A document which is waiting to be parsed.
+pub(crate) enum PendingAsset {
+    InMemory {
+        asset: RepoAssetBuf,
+        reservation: Reservation,
+    },
+    OnDisk {
+        path: PathBuf,
+        variant: RepoAssetVariant,
+        file: SpooledFile,
+        readers: Arc<Semaphore>,
+    },
+}
+
+impl PendingAsset {
+    /// Returns the document's contents, reading them back from the disk if
+    /// necessary. The contents count towards the memory budget until the
+    /// returned [`Loaded`] is dropped.
+    pub async fn load(self) -> Result<Loaded> {
+        match self {
+            Self::InMemory { asset, reservation } => Ok(Loaded {
+                asset,
+                _reservation: Some(reservation),
+                _permit: None,
+            }),
+            Self::OnDisk {
+                path,
+                variant,
+                file,
+                readers,
+            } => {
+                let permit = readers
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed");
+                let data = tokio::fs::read(&file.path).await?;
+                drop(file);
+
+                Ok(Loaded {
+                    asset: RepoAssetBuf {
+                        path,
+                        variant,
+                        data: data.into_boxed_slice(),
+                    },
+                    _reservation: None,
+                    _permit: Some(permit),
+                })
+            }
+        }
+    }
+}
+
+/// The contents of a document which is about to be parsed.
+pub(crate) struct Loaded {
+    pub asset: RepoAssetBuf,
+    _reservation: Option<Reservation>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Part of the memory budget used by a document, which is given back when
+/// it's dropped.
+pub(crate) struct Reservation {
+    size: usize,
+    in_memory: Arc<AtomicUsize>,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.in_memory.fetch_sub(self.size, Ordering::AcqRel);
+    }
+}
+
+/// A document written to disk, which is deleted when dropped.
+pub(crate) struct SpooledFile {
+    path: PathBuf,
+    /// Keeps the directory until every document in it has been read.
+    _dir: Arc<SpoolDir>,
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                debug!(path = %self.path.display(), %error, "Couldn't delete a spooled document");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A temporary directory of spooled documents, deleted with everything in it
+/// when dropped.
+struct SpoolDir {
+    path: PathBuf,
+}
+
+impl SpoolDir {
+    fn create(parent: &Path) -> io::Result<Self> {
+        let n = SPOOL_COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("camrete-spool-{}-{n}", process::id()));
+        fs::create_dir_all(&path)?;
+        debug!(path = %path.display(), "Spooling repository documents to disk");
+
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SpoolDir {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_dir_all(&self.path) {
+            warn!(
+                path = %self.path.display(),
+                %error,
+                "Couldn't delete the directory of spooled repository documents"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    fn asset(name: &str, size: usize) -> RepoAssetBuf {
+        RepoAssetBuf {
+            path: format!("{name}/{name}-1.0.ckan").into(),
+            variant: RepoAssetVariant::Release,
+            data: vec![b'x'; size].into_boxed_slice(),
+        }
+    }
+
+    #[tokio::test]
+    async fn spools_assets_over_budget() {
+        let parent = env::temp_dir().join(format!("camrete-spool-test-{}", process::id()));
+        let mut spool = Spool::new(Some(SpoolOptions::new(100).in_directory(&parent)));
+
+        let first = spool.hold(asset("First", 60));
+        let second = spool.hold(asset("Second", 60));
+        assert!(matches!(first, PendingAsset::InMemory { .. }));
+        assert!(matches!(second, PendingAsset::OnDisk { .. }));
+        assert_eq!(fs::read_dir(&parent).unwrap().count(), 1);
+
+        // Parsing the first document makes room for the next one.
+        drop(first.load().await.unwrap());
+        let third = spool.hold(asset("Third", 60));
+        assert!(matches!(third, PendingAsset::InMemory { .. }));
+
+        let loaded = second.load().await.unwrap();
+        assert_eq!(loaded.asset.path, Path::new("Second/Second-1.0.ckan"));
+        assert_eq!(loaded.asset.data.len(), 60);
+
+        drop((loaded, third, spool));
+        assert_eq!(fs::read_dir(&parent).unwrap().count(), 0);
+        fs::remove_dir(&parent).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+//! Helpers for testing how repositories are downloaded and unpacked, and for
+//! building the releases they contain.
+
+#[cfg(feature = "network")]
+use std::{io, time::Duration};
+
+#[cfg(feature = "network")]
+use futures_core::stream::BoxStream;
+#[cfg(feature = "network")]
+use futures_util::{StreamExt, stream};
+use serde_json::{Map, Value, from_value, json};
+#[cfg(feature = "network")]
+use url::Url;
+#[cfg(feature = "network")]
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{self, header, method},
+};
+
+use crate::{
+    DbConnection,
+    database::{RepoDB, models::Repository},
+    json::JsonModule,
+    repo::RepoManager,
+};
+#[cfg(feature = "network")]
+use crate::{
+    Result,
+    repo::{RepoAssetBuf, RepoAssetLoader, RepoAssetVariant},
+};
+
+/// The metadata of a release for a test, which only has the fields every
+/// release needs until more are [set](Self::set).
+#[derive(Debug, Clone)]
+pub struct TestRelease(Map<String, Value>);
+
+/// Starts building a release of `identifier` at `version`, named after its
+/// identifier and written by Linx.
+pub fn release(identifier: &str, version: &str) -> TestRelease {
+    let json = json!({
+        "spec_version": 1,
+        "name": identifier,
+        "identifier": identifier,
+        "version": version,
+        "abstract": "A mod",
+        "author": "Linx",
+    });
+    let Value::Object(fields) = json else {
+        unreachable!()
+    };
+
+    TestRelease(fields)
+}
+
+impl TestRelease {
+    /// Sets a field of the release's metadata, replacing it if it's already
+    /// set.
+    pub fn set(mut self, field: &str, value: impl Into<Value>) -> Self {
+        self.0.insert(field.into(), value.into());
+        self
+    }
+
+    /// Sets each field of a JSON object.
+    pub fn merge(mut self, fields: Value) -> Self {
+        let Value::Object(fields) = fields else {
+            panic!("release fields must be given as an object");
+        };
+        self.0.extend(fields);
+        self
+    }
+
+    /// The metadata as it would be written in a `.ckan` file.
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.0.clone())
+    }
+
+    pub fn build(self) -> JsonModule {
+        from_value(Value::Object(self.0)).expect("test releases must be valid")
+    }
+}
+
+/// Opens an in-memory database holding only the default repository, which
+/// tests save their releases to.
+///
+/// Every connection to an in-memory database sees a database of its own, so
+/// tests which need more than one connection open a file instead.
+pub fn db_with_default_repo() -> (RepoDB<DbConnection>, Repository) {
+    let mgr = RepoManager::new(":memory:").unwrap();
+    let mut db = mgr.db().unwrap();
+    let repo = db.all_repos(true).unwrap().remove(0);
+    (db, repo)
+}
+
+/// The repository used by tests and benchmarks, a `.tar.gz` archive of a few
+/// modules' history in CKAN-meta.
+#[cfg(feature = "network")]
+pub const MINI_REPO: &[u8] = include_bytes!("../../benches/mini_repo.tgz");
+
+/// A problem which [`FaultyAssetLoader`] can insert into a repository.
+#[cfg(feature = "network")]
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// The archive can't be read any further. No assets are sent after this
+    /// fault.
+    Io(io::ErrorKind),
+    /// A release whose JSON is cut off partway through.
+    MalformedJson,
+    /// Another copy of the most recent release.
+    DuplicateRelease,
+    /// A valid release with a description of the given number of bytes.
+    GiantAsset { size: usize },
+    /// An otherwise valid release whose identifier contains spaces.
+    InvalidIdentifier,
+}
+
+#[cfg(feature = "network")]
+impl Fault {
+    /// The path of the asset which holds a malformed release.
+    pub const MALFORMED_PATH: &str = "Broken/Broken-1.0.ckan";
+    /// The identifier of the module released by a giant asset.
+    pub const GIANT_IDENTIFIER: &str = "Giant";
+}
+
+#[cfg(feature = "network")]
+/// An asset loader which sends a list of assets with faults inserted between
+/// them, so that the unpacking process can be tested with broken repositories.
+#[derive(Debug, Clone)]
+pub struct FaultyAssetLoader {
+    assets: Vec<RepoAssetBuf>,
+    faults: Vec<(usize, Fault)>,
+}
+
+#[cfg(feature = "network")]
+impl FaultyAssetLoader {
+    /// Create a loader which sends the given assets, in order.
+    pub fn new(assets: Vec<RepoAssetBuf>) -> Self {
+        Self {
+            assets,
+            faults: vec![],
+        }
+    }
+
+    /// Sends the fault before the asset at `position`. Positions past the end
+    /// of the assets send the fault last.
+    pub fn inject(mut self, position: usize, fault: Fault) -> Self {
+        self.faults.push((position, fault));
+        self
+    }
+}
+
+#[cfg(feature = "network")]
+impl<'a> RepoAssetLoader<'a> for FaultyAssetLoader {
+    fn asset_stream(self) -> Result<BoxStream<'a, Result<RepoAssetBuf>>> {
+        let mut faults = self.faults;
+        faults.sort_by_key(|&(position, _)| position);
+        let mut faults = faults.into_iter().peekable();
+
+        let mut items = vec![];
+        let mut last_release = None;
+        // A final `None` marks where faults past the end are sent.
+        let assets = self.assets.into_iter().map(Some).chain([None]);
+
+        for (position, asset) in assets.enumerate() {
+            let is_due = |&(at, _): &(usize, Fault)| at <= position || asset.is_none();
+
+            while let Some((_, fault)) = faults.next_if(is_due) {
+                let item = match fault {
+                    Fault::Io(kind) => {
+                        items.push(Err(io::Error::new(kind, "injected fault").into()));
+                        return Ok(stream::iter(items).boxed());
+                    }
+                    Fault::MalformedJson => release_asset(
+                        Fault::MALFORMED_PATH,
+                        br#"{"spec_version": 1, "identifier": "Bro"#.as_slice().into(),
+                    ),
+                    Fault::DuplicateRelease => last_release
+                        .clone()
+                        .expect("a release must be sent before it can be duplicated"),
+                    Fault::GiantAsset { size } => giant_release(size),
+                    Fault::InvalidIdentifier => release_asset(
+                        "Bad/Bad-1.0.ckan",
+                        release("Bad Mod", "1.0")
+                            .to_json()
+                            .to_string()
+                            .into_bytes()
+                            .into_boxed_slice(),
+                    ),
+                };
+                items.push(Ok(item));
+            }
+
+            let Some(asset) = asset else {
+                break;
+            };
+            if asset.variant == RepoAssetVariant::Release {
+                last_release = Some(asset.clone());
+            }
+            items.push(Ok(asset));
+        }
+
+        Ok(stream::iter(items).boxed())
+    }
+}
+
+#[cfg(feature = "network")]
+fn release_asset(path: &str, data: Box<[u8]>) -> RepoAssetBuf {
+    RepoAssetBuf {
+        path: path.into(),
+        variant: RepoAssetVariant::Release,
+        data,
+    }
+}
+
+#[cfg(feature = "network")]
+fn giant_release(size: usize) -> RepoAssetBuf {
+    let json = release(Fault::GIANT_IDENTIFIER, "1.0")
+        .set("description", "x".repeat(size))
+        .to_json();
+
+    let path = format!("{0}/{0}-1.0.ckan", Fault::GIANT_IDENTIFIER);
+    release_asset(&path, json.to_string().into_bytes().into_boxed_slice())
+}
+
+#[cfg(feature = "network")]
+/// An HTTP server on the loopback interface which repositories are downloaded
+/// from. Unlike a fake [`HttpTransport`](crate::repo::HttpTransport), requests
+/// to it go through reqwest, like they do outside of tests.
+pub struct MockRepo {
+    server: MockServer,
+}
+
+#[cfg(feature = "network")]
+impl MockRepo {
+    pub async fn start() -> Self {
+        Self {
+            server: MockServer::start().await,
+        }
+    }
+
+    /// The URL of `path` on the server.
+    pub fn url(&self, path: &str) -> Url {
+        Url::parse(&self.server.uri()).unwrap().join(path).unwrap()
+    }
+
+    /// Opens an in-memory database whose default repository is downloaded
+    /// from `path` on the server.
+    pub fn manager(&self, path: &str) -> (RepoManager, Repository) {
+        let mgr = RepoManager::new(":memory:").unwrap();
+        let mut db = mgr.db().unwrap();
+        let repo = db.all_repos(true).unwrap().remove(0);
+        let repo = db.update_repo_url(repo.id, &self.url(path)).unwrap();
+        drop(db);
+
+        (mgr, repo)
+    }
+
+    /// Answers requests for `path` with `response`.
+    pub async fn serve(&self, path: &str, response: RepoResponse) {
+        Mock::given(method("GET"))
+            .and(matchers::path(path))
+            .respond_with(response.template())
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Answers requests for `path` whose `If-None-Match` header is `etag` with
+    /// `response`, rather than what [`serve`](Self::serve) was given.
+    pub async fn serve_if_none_match(&self, path: &str, etag: &str, response: RepoResponse) {
+        Mock::given(method("GET"))
+            .and(matchers::path(path))
+            .and(header("if-none-match", etag))
+            .respond_with(response.template())
+            .with_priority(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// The number of requests the server has received for `path`.
+    pub async fn requests(&self, path: &str) -> usize {
+        let requests = self.server.received_requests().await.unwrap_or_default();
+        requests
+            .iter()
+            .filter(|request| request.url.path() == path)
+            .count()
+    }
+}
+
+#[cfg(feature = "network")]
+/// A response for [`MockRepo`] to send.
+#[derive(Debug, Clone)]
+pub struct RepoResponse {
+    status: u16,
+    body: Vec<u8>,
+    headers: Vec<(&'static str, String)>,
+    delay: Option<Duration>,
+}
+
+#[cfg(feature = "network")]
+impl RepoResponse {
+    /// An empty response with the given status.
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            body: vec![],
+            headers: vec![],
+            delay: None,
+        }
+    }
+
+    /// The [test repository](MINI_REPO), without a content type.
+    pub fn archive() -> Self {
+        Self::status(200).body(MINI_REPO)
+    }
+
+    /// A redirect to `location`, which can be relative to the server.
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Self::status(status).header("location", location)
+    }
+
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    pub fn content_type(self, content_type: &str) -> Self {
+        self.header("content-type", content_type)
+    }
+
+    pub fn etag(self, etag: &str) -> Self {
+        self.header("etag", etag)
+    }
+
+    /// Cuts the body off after `len` bytes, as if the connection was lost
+    /// partway through the download.
+    pub fn truncated(mut self, len: usize) -> Self {
+        self.body.truncate(len);
+        self
+    }
+
+    /// Waits for `delay` before responding, like a slow server.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn template(self) -> ResponseTemplate {
+        let mut template = ResponseTemplate::new(self.status).set_body_bytes(self.body);
+        for (name, value) in self.headers {
+            template = template.insert_header(name, value.as_str());
+        }
+        if let Some(delay) = self.delay {
+            template = template.set_delay(delay);
+        }
+        template
+    }
+}
@@ -0,0 +1,84 @@
+//! Measurements of how long each repository asset takes to parse.
+//!
+//! These are only collected when the `parse-timing` feature is enabled, and are
+//! intended to help find pathological metadata files.
+
+use std::{cmp::Reverse, collections::BinaryHeap, path::PathBuf, time::Duration};
+
+/// The number of slow assets included in an [`UpdateReport`](super::client::UpdateReport).
+pub const SLOWEST_ASSETS: usize = 10;
+
+/// How long it took to parse a single asset.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AssetTiming {
+    /// The time spent parsing the asset.
+    pub duration: Duration,
+    /// The size of the asset's serialized data, in bytes.
+    pub size: usize,
+    /// The path which the asset was sourced from in the repo.
+    pub path: PathBuf,
+}
+
+/// Keeps track of the slowest assets parsed so far, without holding on to
+/// the timings for every asset in the repository.
+#[derive(Debug)]
+pub(crate) struct SlowestAssets {
+    limit: usize,
+    heap: BinaryHeap<Reverse<AssetTiming>>,
+}
+
+impl SlowestAssets {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    pub fn record(&mut self, timing: AssetTiming) {
+        self.heap.push(Reverse(timing));
+
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    /// Returns the recorded timings, slowest first.
+    pub fn into_vec(self) -> Vec<AssetTiming> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(timing)| timing)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn timing(millis: u64) -> AssetTiming {
+        AssetTiming {
+            duration: Duration::from_millis(millis),
+            size: 0,
+            path: format!("{millis}.ckan").into(),
+        }
+    }
+
+    #[test]
+    fn keeps_slowest() {
+        let mut slowest = SlowestAssets::new(3);
+
+        for millis in [5, 1, 9, 3, 7, 2] {
+            slowest.record(timing(millis));
+        }
+
+        let durations = slowest
+            .into_vec()
+            .into_iter()
+            .map(|t| t.duration.as_millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(durations, [9, 7, 5]);
+    }
+}
@@ -0,0 +1,65 @@
+//! Changes made to release metadata as a repository is unpacked.
+//!
+//! A mirror can use these to adjust a repository's releases without changing
+//! how repositories are read, such as to add its own tags or to send downloads
+//! through a caching proxy.
+
+use std::{any::type_name, fmt, sync::Arc};
+
+use crate::{database::models::Repository, json::JsonModule};
+
+/// Changes the metadata of each release in a repository before it's saved.
+///
+/// Any function taking the release and its repository can be used as a
+/// transform.
+pub trait ReleaseTransform: Send + Sync {
+    /// Changes a release from the given repository.
+    fn transform(&self, release: &mut JsonModule, repo: &Repository);
+
+    /// The name of the transform, for logging.
+    fn name(&self) -> &str {
+        type_name::<Self>()
+    }
+}
+
+impl<F> ReleaseTransform for F
+where
+    F: Fn(&mut JsonModule, &Repository) + Send + Sync,
+{
+    fn transform(&self, release: &mut JsonModule, repo: &Repository) {
+        self(release, repo)
+    }
+}
+
+/// The transforms applied to releases when a repository is unpacked, in the
+/// order they were registered.
+#[derive(Clone, Default)]
+pub struct TransformRegistry {
+    transforms: Vec<Arc<dyn ReleaseTransform>>,
+}
+
+impl TransformRegistry {
+    /// Adds a transform, which runs after the ones already registered.
+    pub fn register(&mut self, transform: impl ReleaseTransform + 'static) {
+        self.transforms.push(Arc::new(transform));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Runs every transform on a release, in order.
+    pub fn apply(&self, release: &mut JsonModule, repo: &Repository) {
+        for transform in &self.transforms {
+            transform.transform(release, repo);
+        }
+    }
+}
+
+impl fmt::Debug for TransformRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.transforms.iter().map(|t| t.name()))
+            .finish()
+    }
+}
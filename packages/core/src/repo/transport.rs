@@ -0,0 +1,412 @@
+//! The HTTP client which repositories and downloads are fetched with.
+//!
+//! Requests are sent with reqwest unless the host program supplies its own
+//! [`HttpTransport`], such as to use its own TLS settings or the networking of
+//! a sandbox. Tests use one to serve repositories without a network.
+//!
+//! Redirects are followed here rather than by the transport, so that whether
+//! they're permanent is known. A repository which permanently redirects has
+//! moved, and its URL can be updated to match.
+
+use std::{any::type_name, fmt, io, sync::Arc};
+
+use bytes::{Bytes, BytesMut};
+use futures_core::stream::BoxStream;
+use futures_util::{FutureExt, StreamExt, TryStreamExt, future::BoxFuture};
+use reqwest::{
+    StatusCode,
+    header::{
+        AUTHORIZATION, CONTENT_LENGTH, COOKIE, HeaderMap, HeaderValue, LOCATION,
+        PROXY_AUTHORIZATION,
+    },
+};
+use url::Url;
+
+use crate::{
+    Error, Result,
+    policy::{Policy, PolicyError},
+};
+
+/// The most redirects followed for one request, the same as reqwest's default.
+const MAX_REDIRECTS: usize = 10;
+
+/// Sends `GET` requests for a [`RepoManager`](crate::repo::RepoManager).
+pub trait HttpTransport: Send + Sync {
+    /// Sends a `GET` request with the given headers, returning once the
+    /// response's headers have been received. The body is streamed as it
+    /// arrives.
+    ///
+    /// Responses with error statuses should be returned like any other, since
+    /// some of them are expected. Redirects shouldn't be followed, so that
+    /// Camrete can tell whether they're permanent. A transport which follows
+    /// them anyway should set the response's URL to where it came from.
+    fn get_streaming(&self, url: &Url, headers: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>>;
+
+    /// The name of the transport, for logging.
+    fn name(&self) -> &str {
+        type_name::<Self>()
+    }
+}
+
+/// A response whose body hasn't been read yet.
+pub struct HttpResponse {
+    /// The URL the response came from, after any redirects.
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The body, as it was sent. Transports mustn't decompress it, so that
+    /// download progress is counted in the bytes sent over the network.
+    pub body: BoxStream<'static, io::Result<Bytes>>,
+}
+
+impl HttpResponse {
+    /// The length of the body, if the server said what it is.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    }
+
+    /// Turns a response with an error status into an error.
+    pub fn error_for_status(self) -> Result<Self> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            return Err(Error::HttpStatus {
+                url: self.url,
+                status: self.status.as_u16(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// The next part of the body, or `None` once it's all been read.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        Ok(self.body.try_next().await?)
+    }
+
+    /// Reads the whole body.
+    pub async fn bytes(self) -> Result<Bytes> {
+        let body = self.body.try_collect::<BytesMut>().await?;
+        Ok(body.freeze())
+    }
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("url", &self.url.as_str())
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A redirect followed while sending a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub status: StatusCode,
+    pub from: Url,
+    pub to: Url,
+}
+
+impl Redirect {
+    /// Whether the server said the resource has moved for good, so the new URL
+    /// should be used from now on.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self.status,
+            StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+        )
+    }
+
+    /// Where a chain of redirects says the first URL has moved, which is the
+    /// end of the permanent redirects it starts with. A temporary redirect
+    /// partway along doesn't change where the first URL has moved to.
+    pub fn moved_to(redirects: &[Self]) -> Option<&Url> {
+        redirects
+            .iter()
+            .take_while(|redirect| redirect.is_permanent())
+            .last()
+            .map(|redirect| &redirect.to)
+    }
+}
+
+/// Sends requests with a reqwest client. This is the transport used unless
+/// another is chosen. Clients which follow redirects themselves hide whether
+/// they're permanent, so the default client doesn't.
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn get_streaming(&self, url: &Url, headers: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>> {
+        let request = self.client.get(url.clone()).headers(headers);
+
+        async move {
+            let response = request.send().await?;
+            Ok(HttpResponse {
+                url: response.url().clone(),
+                status: response.status(),
+                headers: response.headers().clone(),
+                body: response.bytes_stream().map_err(io::Error::other).boxed(),
+            })
+        }
+        .boxed()
+    }
+}
+
+/// The transport a [`RepoManager`](crate::repo::RepoManager) sends requests
+/// with.
+#[derive(Clone)]
+pub(crate) struct Transport(pub Arc<dyn HttpTransport>);
+
+impl Transport {
+    /// Sends a `GET` request with the given headers, following any redirects.
+    pub async fn get(&self, url: &Url, headers: HeaderMap) -> Result<HttpResponse> {
+        Ok(self.get_redirected(url, headers).await?.0)
+    }
+
+    /// Sends a `GET` request for a file on a host the policy allows, following
+    /// any redirects to hosts it allows too. A redirect to a blocked host
+    /// fails before it's requested.
+    pub async fn get_allowed(
+        &self,
+        url: &Url,
+        headers: HeaderMap,
+        policy: &Policy,
+    ) -> Result<HttpResponse> {
+        if !policy.allows_url(url) {
+            return Err(PolicyError::BlockedHost { url: url.clone() }.into());
+        }
+
+        Ok(self.follow_redirects(url, headers, Some(policy)).await?.0)
+    }
+
+    /// Sends a `GET` request with the given headers, following any redirects
+    /// and returning them along with the response.
+    ///
+    /// Redirects from HTTP to HTTPS are followed, but not the other way around,
+    /// since that would send the request unencrypted.
+    pub async fn get_redirected(
+        &self,
+        url: &Url,
+        headers: HeaderMap,
+    ) -> Result<(HttpResponse, Vec<Redirect>)> {
+        self.follow_redirects(url, headers, None).await
+    }
+
+    async fn follow_redirects(
+        &self,
+        url: &Url,
+        mut headers: HeaderMap,
+        policy: Option<&Policy>,
+    ) -> Result<(HttpResponse, Vec<Redirect>)> {
+        let mut redirects = Vec::<Redirect>::new();
+        let mut next = url.clone();
+
+        loop {
+            let response = self.0.get_streaming(&next, headers.clone()).await?;
+            let Some(location) = redirect_location(&response) else {
+                return Ok((response, redirects));
+            };
+
+            let to = location
+                .to_str()
+                .ok()
+                .and_then(|location| response.url.join(location).ok())
+                .ok_or_else(|| Error::InvalidRedirect {
+                    url: response.url.clone(),
+                    location: String::from_utf8_lossy(location.as_bytes()).into_owned(),
+                })?;
+            if redirects.len() == MAX_REDIRECTS {
+                return Err(Error::TooManyRedirects { url: url.clone() });
+            }
+            if response.url.scheme() == "https" && to.scheme() != "https" {
+                return Err(Error::InsecureRedirect {
+                    from: Box::new(response.url),
+                    to: Box::new(to),
+                });
+            }
+            if policy.is_some_and(|policy| !policy.allows_url(&to)) {
+                return Err(PolicyError::BlockedRedirect {
+                    url: Box::new(url.clone()),
+                    to: Box::new(to),
+                }
+                .into());
+            }
+
+            // Credentials are only meant for the host they were given to.
+            if to.host_str() != response.url.host_str() {
+                for header in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION] {
+                    headers.remove(header);
+                }
+            }
+
+            next = to.clone();
+            redirects.push(Redirect {
+                status: response.status,
+                from: response.url,
+                to,
+            });
+        }
+    }
+}
+
+/// Where a response redirects to, if it's a redirect which should be followed.
+fn redirect_location(response: &HttpResponse) -> Option<&HeaderValue> {
+    match response.status {
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => response.headers.get(LOCATION),
+        _ => None,
+    }
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::prelude::*;
+    use futures_util::stream;
+    use reqwest::header::ETAG;
+
+    use super::*;
+    use crate::{database::schema::*, repo::RepoManager};
+
+    /// Serves the test repository from memory, in chunks.
+    struct MiniRepo;
+
+    impl HttpTransport for MiniRepo {
+        fn get_streaming(&self, url: &Url, _: HeaderMap) -> BoxFuture<'_, Result<HttpResponse>> {
+            let archive = include_bytes!("../../benches/mini_repo.tgz");
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_LENGTH, archive.len().into());
+            headers.insert(ETAG, "\"mini\"".parse().unwrap());
+
+            let chunks = archive.chunks(4096).map(|c| Ok(Bytes::from_static(c)));
+            let response = HttpResponse {
+                url: url.clone(),
+                status: StatusCode::OK,
+                headers,
+                body: stream::iter(chunks.collect::<Vec<_>>()).boxed(),
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    /// Moves everything on `example.com` from HTTP to HTTPS for good, then
+    /// sends it to a mirror for now, where [`MiniRepo`] serves it.
+    struct Redirecting;
+
+    impl HttpTransport for Redirecting {
+        fn get_streaming(
+            &self,
+            url: &Url,
+            headers: HeaderMap,
+        ) -> BoxFuture<'_, Result<HttpResponse>> {
+            let (status, location) = match (url.scheme(), url.host_str()) {
+                ("http", _) => (
+                    StatusCode::MOVED_PERMANENTLY,
+                    url.as_str().replacen("http:", "https:", 1),
+                ),
+                (_, Some("example.com")) => (StatusCode::FOUND, "//mirror.example.com/".into()),
+                (_, Some("insecure.example.com")) => {
+                    (StatusCode::FOUND, "http://example.com/".into())
+                }
+                _ => {
+                    let mini: &'static MiniRepo = &MiniRepo;
+                    return mini.get_streaming(url, headers);
+                }
+            };
+
+            let mut headers = HeaderMap::new();
+            headers.insert(LOCATION, location.parse().unwrap());
+            let response = HttpResponse {
+                url: url.clone(),
+                status,
+                headers,
+                body: stream::empty().boxed(),
+            };
+            async move { Ok(response) }.boxed()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn updates_through_custom_transport() {
+        let mut mgr = RepoManager::builder(":memory:")
+            .transport(MiniRepo)
+            .build()
+            .unwrap();
+        let repo = mgr.db().unwrap().all_repos(true).unwrap().remove(0);
+
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        assert!(report.quarantined.is_empty());
+
+        let modules: i64 = modules::table
+            .count()
+            .get_result(mgr.db().unwrap().connection_mut())
+            .unwrap();
+        assert!(modules > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn records_permanent_redirects() {
+        let mut mgr = RepoManager::builder(":memory:")
+            .transport(Redirecting)
+            .build()
+            .unwrap();
+        let mut db = mgr.db().unwrap();
+        let repo = db.all_repos(true).unwrap().remove(0);
+        let url = Url::parse("http://example.com/repo.tar.gz").unwrap();
+        let repo = db.update_repo_url(repo.id, &url).unwrap();
+        drop(db);
+
+        let report = mgr.download(&repo, Box::new(|_| {})).await.unwrap();
+        let moved_to = Url::parse("https://example.com/repo.tar.gz").unwrap();
+        assert_eq!(report.moved_to.as_ref(), Some(&moved_to));
+
+        let mut db = mgr.db().unwrap();
+        let repo = db.all_repos(true).unwrap().remove(0);
+        assert_eq!(
+            repo.final_url.unwrap().as_str(),
+            "https://mirror.example.com/"
+        );
+        let moved = db.follow_repo_redirect(repo.id).unwrap().unwrap();
+        assert_eq!(moved.url, moved_to);
+    }
+
+    #[tokio::test]
+    async fn refuses_insecure_redirects() {
+        let transport = Transport(Arc::new(Redirecting));
+        let url = Url::parse("https://insecure.example.com/repo.tar.gz").unwrap();
+
+        let error = transport.get(&url, HeaderMap::new()).await.unwrap_err();
+        assert!(matches!(error, Error::InsecureRedirect { .. }));
+    }
+
+    #[test]
+    fn error_statuses_fail() {
+        let response = HttpResponse {
+            url: "https://example.com/repo.tar.gz".parse().unwrap(),
+            status: StatusCode::NOT_FOUND,
+            headers: HeaderMap::new(),
+            body: stream::empty().boxed(),
+        };
+
+        let error = response.error_for_status().unwrap_err();
+        assert!(matches!(error, Error::HttpStatus { status: 404, .. }));
+    }
+}
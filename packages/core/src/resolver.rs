@@ -0,0 +1,626 @@
+//! Working out which releases can be installed together.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    ops::DerefMut,
+};
+
+use diesel::prelude::*;
+use petgraph::{
+    Direction,
+    dot::Dot,
+    stable_graph::{NodeIndex, StableDiGraph},
+    visit::EdgeRef,
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    database::{
+        DepGroupId, Operation, QueryContext, QueryError, ReleaseId, RepoDB, RepoId,
+        models::module::{ModuleVersion, RelationshipType},
+        schema::*,
+    },
+    install::DetectedDlc,
+    json::ModuleKind,
+};
+
+mod cycles;
+mod explain;
+
+pub use cycles::{CycleStep, DependencyCycle};
+pub use explain::{Choice, Reason, Resolution, Selection, Unsatisfied};
+
+/// The relationships between every release in a set of repositories, held in
+/// memory so they can be followed without querying the database.
+///
+/// Releases are linked to the identifiers they depend on (or recommend,
+/// conflict with, etc.), and identifiers are linked to the releases which
+/// provide them. Every release provides its own module's identifier.
+///
+/// The graph isn't updated when the database changes. After a repository is
+/// updated, call [`Self::reload_repo`] to replace its releases.
+///
+/// DLC can't be downloaded, so its releases are never candidates. Instead, the
+/// DLC found in the game directory is added with [`Self::add_detected_dlc`].
+/// Yanked releases are never candidates either, but they're still part of the
+/// graph, so that the relationships of installed ones can be followed.
+#[derive(Debug, Default)]
+pub struct Graph {
+    graph: StableDiGraph<Node, Edge>,
+    identifiers: HashMap<String, NodeIndex>,
+    releases: HashMap<ReleaseId, NodeIndex>,
+    detected_dlc: HashMap<String, DetectedDlc>,
+}
+
+#[derive(Debug)]
+pub enum Node {
+    /// A name which can be the target of a relationship. This is either a
+    /// module's identifier or a virtual identifier provided by other modules.
+    Identifier(String),
+    Release(ReleaseNode),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReleaseNode {
+    pub id: ReleaseId,
+    pub repo_id: RepoId,
+    pub identifier: String,
+    pub version: ModuleVersion<'static>,
+    pub kind: ModuleKind,
+    pub yanked: bool,
+    /// Whether the database's [`Policy`](crate::policy::Policy) blocks the
+    /// release.
+    pub blocked: bool,
+}
+
+impl ReleaseNode {
+    /// Whether the release can be installed. DLC can't be downloaded, and
+    /// yanked releases and releases blocked by policy are never installed.
+    pub fn is_installable(&self) -> bool {
+        self.kind != ModuleKind::Dlc && !self.yanked && !self.blocked
+    }
+}
+
+#[derive(Debug)]
+pub enum Edge {
+    /// From an identifier to a release which provides it.
+    ProvidedBy,
+    /// From a release to an identifier it has a relationship with.
+    Relationship(Relationship),
+}
+
+/// One member of a relationship group. Members of the same `any_of` group
+/// share a group ID.
+#[derive(Debug, Clone)]
+pub struct Relationship {
+    pub rel_type: RelationshipType,
+    pub group_id: DepGroupId,
+    /// The member's position in its group.
+    pub ordinal: i32,
+    pub version_max: Option<ModuleVersion<'static>>,
+    pub version_min: Option<ModuleVersion<'static>>,
+}
+
+impl Relationship {
+    /// Returns whether the given release is within this relationship's version
+    /// bounds. Bounds only apply to releases of the named module, so releases
+    /// which provide it virtually always match.
+    pub fn allows(&self, target: &str, release: &ReleaseNode) -> bool {
+        release.identifier != target || self.allows_version(&release.version)
+    }
+
+    /// Returns whether the given version is within this relationship's
+    /// version bounds.
+    pub fn allows_version(&self, version: &ModuleVersion) -> bool {
+        self.version_min.as_ref().is_none_or(|min| version >= min)
+            && self.version_max.as_ref().is_none_or(|max| version <= max)
+    }
+}
+
+impl Graph {
+    /// Loads the releases in the given repositories, or in every repository
+    /// if `repo_filter` is `None`.
+    #[instrument(skip(db))]
+    pub fn load<T: DerefMut<Target = SqliteConnection>>(
+        db: &mut RepoDB<T>,
+        repo_filter: Option<&[RepoId]>,
+    ) -> Result<Self, QueryError> {
+        let blocked = db.blocked_releases(repo_filter)?;
+        let mut graph = Self::default();
+        graph
+            .add_releases(db, repo_filter, &blocked)
+            .during(|| Operation::LoadGraph)?;
+        Ok(graph)
+    }
+
+    /// Replaces the releases from the given repository with the ones currently
+    /// in the database.
+    #[instrument(skip(self, db))]
+    pub fn reload_repo<T: DerefMut<Target = SqliteConnection>>(
+        &mut self,
+        db: &mut RepoDB<T>,
+        repo: RepoId,
+    ) -> Result<(), QueryError> {
+        let blocked = db.blocked_releases(Some(&[repo]))?;
+        self.remove_repo(repo);
+        self.add_releases(db, Some(&[repo]), &blocked)
+            .during(|| Operation::LoadGraph)
+    }
+
+    /// Removes the releases from the given repository, along with any
+    /// identifiers which nothing else refers to.
+    pub fn remove_repo(&mut self, repo: RepoId) {
+        self.releases.retain(|_, &mut index| {
+            let Some(Node::Release(release)) = self.graph.node_weight(index) else {
+                unreachable!("release index refers to a release");
+            };
+            if release.repo_id != repo {
+                return true;
+            }
+
+            self.graph.remove_node(index);
+            false
+        });
+
+        self.identifiers.retain(|_, &mut index| {
+            let orphaned = self.graph.neighbors_undirected(index).next().is_none();
+            if orphaned {
+                self.graph.remove_node(index);
+            }
+            !orphaned
+        });
+    }
+
+    fn add_releases<T: DerefMut<Target = SqliteConnection>>(
+        &mut self,
+        db: &mut RepoDB<T>,
+        repo_filter: Option<&[RepoId]>,
+        blocked: &HashSet<ReleaseId>,
+    ) -> QueryResult<()> {
+        let mut releases = module_releases::table
+            .inner_join(modules::table)
+            .left_join(
+                yanked_releases::table.on(yanked_releases::module_slug
+                    .eq(modules::module_slug)
+                    .and(yanked_releases::version.eq(module_releases::version))),
+            )
+            .select((
+                module_releases::release_id,
+                modules::repo_id,
+                modules::module_slug,
+                module_releases::version,
+                module_releases::kind,
+                yanked_releases::yanked_at.nullable().is_not_null(),
+            ))
+            .into_boxed();
+        let mut relationships = module_relationships::table
+            .inner_join(
+                module_relationship_groups::table
+                    .inner_join(module_releases::table.inner_join(modules::table)),
+            )
+            .select((
+                module_relationship_groups::release_id,
+                module_relationship_groups::rel_type,
+                module_relationships::group_id,
+                module_relationships::ordinal,
+                module_relationships::target_name,
+                module_relationships::target_version,
+                module_relationships::target_version_min,
+            ))
+            .into_boxed();
+
+        if let Some(repos) = repo_filter {
+            releases = releases.filter(modules::repo_id.eq_any(repos));
+            relationships = relationships.filter(modules::repo_id.eq_any(repos));
+        }
+
+        let releases = releases
+            .load::<(ReleaseId, RepoId, String, ModuleVersion<'static>, i32, bool)>(
+                &mut *db.connection,
+            )?;
+        let relationships = relationships.load::<(
+            ReleaseId,
+            RelationshipType,
+            DepGroupId,
+            i32,
+            String,
+            Option<String>,
+            Option<String>,
+        )>(&mut *db.connection)?;
+
+        debug!(
+            releases = releases.len(),
+            relationships = relationships.len(),
+            "Adding releases to the relationship graph"
+        );
+
+        for (id, repo_id, identifier, version, kind, yanked) in releases {
+            let kind = ModuleKind::try_from(kind)
+                .map_err(|e| diesel::result::Error::DeserializationError(e.into()))?;
+            let target = self.identifier(&identifier);
+            let release = self.graph.add_node(Node::Release(ReleaseNode {
+                id,
+                repo_id,
+                identifier,
+                version,
+                kind,
+                yanked,
+                blocked: blocked.contains(&id),
+            }));
+            self.graph.add_edge(target, release, Edge::ProvidedBy);
+            self.releases.insert(id, release);
+        }
+
+        for (release_id, rel_type, group_id, ordinal, target, version_max, version_min) in
+            relationships
+        {
+            let release = self.releases[&release_id];
+            let target = self.identifier(&target);
+
+            if rel_type == RelationshipType::Provides {
+                self.graph.add_edge(target, release, Edge::ProvidedBy);
+                continue;
+            }
+
+            let relationship = Relationship {
+                rel_type,
+                group_id,
+                ordinal,
+                version_max: version_max.map(ModuleVersion::from),
+                version_min: version_min.map(ModuleVersion::from),
+            };
+            self.graph
+                .add_edge(release, target, Edge::Relationship(relationship));
+        }
+
+        Ok(())
+    }
+
+    fn identifier(&mut self, name: &str) -> NodeIndex {
+        if let Some(&index) = self.identifiers.get(name) {
+            return index;
+        }
+
+        let index = self.graph.add_node(Node::Identifier(name.to_owned()));
+        self.identifiers.insert(name.to_owned(), index);
+        index
+    }
+
+    fn release_at(&self, index: NodeIndex) -> &ReleaseNode {
+        match &self.graph[index] {
+            Node::Release(release) => release,
+            Node::Identifier(_) => unreachable!("edge endpoint is a release"),
+        }
+    }
+
+    /// Returns the release with the given ID, if it's part of this graph.
+    pub fn release(&self, id: ReleaseId) -> Option<&ReleaseNode> {
+        Some(self.release_at(*self.releases.get(&id)?))
+    }
+
+    /// Returns every release which provides the given identifier, including
+    /// the releases of the module with that identifier.
+    pub fn providers(&self, identifier: &str) -> impl Iterator<Item = &ReleaseNode> {
+        self.identifiers
+            .get(identifier)
+            .into_iter()
+            .flat_map(|&index| self.graph.neighbors_directed(index, Direction::Outgoing))
+            .map(|index| self.release_at(index))
+    }
+
+    /// Returns the relationships of the given release, alongside the
+    /// identifier each one targets.
+    pub fn relationships(&self, release: ReleaseId) -> impl Iterator<Item = (&str, &Relationship)> {
+        self.releases
+            .get(&release)
+            .into_iter()
+            .flat_map(|&index| self.graph.edges_directed(index, Direction::Outgoing))
+            .map(|edge| {
+                let Edge::Relationship(relationship) = edge.weight() else {
+                    unreachable!("releases only have relationships as outgoing edges");
+                };
+                let Node::Identifier(target) = &self.graph[edge.target()] else {
+                    unreachable!("relationships target an identifier");
+                };
+                (target.as_str(), relationship)
+            })
+    }
+
+    /// Returns the releases which could be installed to satisfy a relationship
+    /// with the given target identifier. Yanked releases are left out, and so
+    /// is DLC, since it can't be installed; see [`Self::detected_dlc`].
+    pub fn candidates<'a>(
+        &'a self,
+        target: &'a str,
+        relationship: &'a Relationship,
+    ) -> impl Iterator<Item = &'a ReleaseNode> {
+        self.providers(target)
+            .filter(|release| release.is_installable())
+            .filter(move |release| relationship.allows(target, release))
+    }
+
+    /// Records the DLC found in the game directory, which satisfies
+    /// relationships with it without anything being installed.
+    pub fn add_detected_dlc(&mut self, dlc: impl IntoIterator<Item = DetectedDlc>) {
+        self.detected_dlc
+            .extend(dlc.into_iter().map(|dlc| (dlc.identifier.clone(), dlc)));
+    }
+
+    /// Returns the detected DLC which satisfies a relationship with the given
+    /// target identifier, if there is one. DLC whose version couldn't be found
+    /// only satisfies relationships without version bounds.
+    pub fn detected_dlc(&self, target: &str, relationship: &Relationship) -> Option<&DetectedDlc> {
+        let dlc = self.detected_dlc.get(target)?;
+        let allowed = match &dlc.version {
+            Some(version) => relationship.allows_version(&ModuleVersion::from(version.as_str())),
+            None => relationship.version_min.is_none() && relationship.version_max.is_none(),
+        };
+        allowed.then_some(dlc)
+    }
+
+    /// Returns the releases which have a relationship of the given type with
+    /// an identifier, such as the releases which depend on it.
+    pub fn dependents(
+        &self,
+        identifier: &str,
+        rel_type: RelationshipType,
+    ) -> impl Iterator<Item = &ReleaseNode> {
+        self.identifiers
+            .get(identifier)
+            .into_iter()
+            .flat_map(|&index| self.graph.edges_directed(index, Direction::Incoming))
+            .filter(move |edge| {
+                matches!(edge.weight(), Edge::Relationship(r) if r.rel_type == rel_type)
+            })
+            .map(|edge| self.release_at(edge.source()))
+    }
+
+    /// Renders the graph in Graphviz's DOT language.
+    pub fn to_dot(&self) -> String {
+        Dot::new(&self.graph).to_string()
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(identifier) => write!(f, "{identifier}"),
+            Self::Release(release) => write!(f, "{} {}", release.identifier, release.version),
+        }
+    }
+}
+
+impl Display for Edge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProvidedBy => write!(f, "provided by"),
+            Self::Relationship(r) => f.write_str(&format!("{:?}", r.rel_type).to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        policy::{Policy, PolicyRules},
+        repo::{
+            RepoManager,
+            test_support::{db_with_default_repo, release},
+        },
+    };
+
+    #[test]
+    fn follows_relationships() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (_, scatterer) = db
+            .create_release(
+                &release("Scatterer", "1.0")
+                    .merge(json!({ "depends": [{ "name": "EVE", "min_version": "2.0" }] }))
+                    .build(),
+                repo.id,
+                None,
+            )
+            .unwrap();
+        db.create_release(&release("EVE", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(&release("EVE", "2.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(
+            &release("EVE-Redux", "1.0")
+                .merge(json!({ "provides": ["EVE"] }))
+                .build(),
+            repo.id,
+            None,
+        )
+        .unwrap();
+
+        let graph = Graph::load(&mut db, None).unwrap();
+
+        let mut providers = graph
+            .providers("EVE")
+            .map(|r| format!("{} {}", r.identifier, r.version))
+            .collect::<Vec<_>>();
+        providers.sort();
+        assert_eq!(providers, ["EVE 1.0", "EVE 2.0", "EVE-Redux 1.0"]);
+
+        let (target, depends) = graph.relationships(scatterer).next().unwrap();
+        assert_eq!(target, "EVE");
+        let mut candidates = graph
+            .candidates(target, depends)
+            .map(|r| format!("{} {}", r.identifier, r.version))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        assert_eq!(candidates, ["EVE 2.0", "EVE-Redux 1.0"]);
+
+        let dependents = graph
+            .dependents("EVE", RelationshipType::Depends)
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(dependents, [scatterer]);
+        assert!(graph.dependency_cycles().is_empty());
+        assert!(graph.to_dot().contains("\"Scatterer 1.0\""));
+    }
+
+    #[test]
+    fn finds_cycles() {
+        let (mut db, repo) = db_with_default_repo();
+
+        for (identifier, dependency) in [("ModA", "ModB"), ("ModB", "ModA"), ("ModC", "ModA")] {
+            let json = release(identifier, "1.0")
+                .merge(json!({ "depends": [{ "name": dependency }] }))
+                .build();
+            db.create_release(&json, repo.id, None).unwrap();
+        }
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let cycles = graph.dependency_cycles();
+        assert_eq!(cycles.len(), 1);
+
+        let members = cycles[0]
+            .steps
+            .iter()
+            .map(|step| step.identifier.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(members, ["ModA", "ModB"]);
+    }
+
+    #[test]
+    fn dlc_comes_from_the_game() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let json = release("MakingHistory-DLC", "1.12.1")
+            .merge(json!({ "kind": "dlc" }))
+            .build();
+        db.create_release(&json, repo.id, None).unwrap();
+        let json = release("Tantares", "1.0")
+            .merge(json!({ "depends": [{ "name": "MakingHistory-DLC", "min_version": "1.9" }] }))
+            .build();
+        let (_, tantares) = db.create_release(&json, repo.id, None).unwrap();
+
+        let mut graph = Graph::load(&mut db, None).unwrap();
+        let (target, depends) = graph.relationships(tantares).next().unwrap();
+
+        // The DLC can't be downloaded, so until it's detected nothing satisfies
+        // the dependency.
+        assert_eq!(graph.providers(target).count(), 1);
+        assert_eq!(graph.candidates(target, depends).count(), 0);
+        assert_eq!(graph.detected_dlc(target, depends), None);
+
+        let detected = |version: Option<&str>| DetectedDlc {
+            identifier: "MakingHistory-DLC".into(),
+            version: version.map(Into::into),
+        };
+
+        graph.add_detected_dlc([detected(Some("1.8.0"))]);
+        let (target, depends) = graph.relationships(tantares).next().unwrap();
+        assert_eq!(graph.detected_dlc(target, depends), None);
+
+        graph.add_detected_dlc([detected(Some("1.12.1"))]);
+        let (target, depends) = graph.relationships(tantares).next().unwrap();
+        assert_eq!(
+            graph.detected_dlc(target, depends),
+            Some(&detected(Some("1.12.1")))
+        );
+
+        // Without a version, the DLC can't be shown to be new enough.
+        graph.add_detected_dlc([detected(None)]);
+        let (target, depends) = graph.relationships(tantares).next().unwrap();
+        assert_eq!(graph.detected_dlc(target, depends), None);
+    }
+
+    #[test]
+    fn yanked_releases_are_not_candidates() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let (parallax, _) = db
+            .create_release(&release("Parallax", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(&release("Parallax", "1.1").build(), repo.id, Some(parallax))
+            .unwrap();
+        let json = release("Tantares", "1.0")
+            .merge(json!({ "depends": [{ "name": "Parallax" }] }))
+            .build();
+        let (_, tantares) = db.create_release(&json, repo.id, None).unwrap();
+        db.yank_release("Parallax", "1.1", None).unwrap();
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let (target, depends) = graph.relationships(tantares).next().unwrap();
+        assert_eq!(graph.providers(target).count(), 2);
+
+        let candidates = graph.candidates(target, depends).collect::<Vec<_>>();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].version, ModuleVersion::from("1.0"));
+    }
+
+    #[test]
+    fn blocked_releases_are_not_candidates() {
+        let policy = Policy {
+            modules: PolicyRules {
+                deny: vec!["*-Redux".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mgr = RepoManager::builder(":memory:")
+            .policy(policy)
+            .build()
+            .unwrap();
+        let mut db = mgr.db().unwrap();
+        let repo = db.all_repos(true).unwrap().remove(0);
+
+        db.create_release(&release("EVE", "1.0").build(), repo.id, None)
+            .unwrap();
+        db.create_release(
+            &release("EVE-Redux", "1.0")
+                .merge(json!({ "provides": ["EVE"] }))
+                .build(),
+            repo.id,
+            None,
+        )
+        .unwrap();
+        let json = release("Scatterer", "1.0")
+            .merge(json!({ "depends": [{ "name": "EVE" }] }))
+            .build();
+        let (_, scatterer) = db.create_release(&json, repo.id, None).unwrap();
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let (target, depends) = graph.relationships(scatterer).next().unwrap();
+        assert_eq!(graph.providers(target).count(), 2);
+
+        let candidates = graph.candidates(target, depends).collect::<Vec<_>>();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].identifier, "EVE");
+        let resolution = graph.resolve(&["EVE-Redux".into()], false);
+        assert!(resolution.selected.is_empty());
+    }
+
+    #[test]
+    fn reloads_one_repo() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let json = release("Parallax", "1.0")
+            .merge(json!({ "provides": ["Terrain"] }))
+            .build();
+        db.create_release(&json, repo.id, None).unwrap();
+
+        let mut graph = Graph::load(&mut db, Some(&[repo.id])).unwrap();
+        assert_eq!(graph.providers("Terrain").count(), 1);
+
+        diesel::delete(modules::table)
+            .execute(&mut *db.connection)
+            .unwrap();
+        let json = release("Parallax", "2.0").build();
+        let (_, release_id) = db.create_release(&json, repo.id, None).unwrap();
+
+        graph.reload_repo(&mut db, repo.id).unwrap();
+        assert_eq!(graph.providers("Terrain").count(), 0);
+        assert!(!graph.identifiers.contains_key("Terrain"));
+        assert_eq!(graph.release(release_id).unwrap().version.as_str(), "2.0");
+        assert_eq!(graph.providers("Parallax").count(), 1);
+    }
+}
@@ -0,0 +1,266 @@
+//! Finding releases which depend on each other in a loop.
+//!
+//! The spec doesn't forbid loops, and [`Graph::resolve`] installs every
+//! release in one together, but they're usually a mistake in the metadata.
+//! Loops are found with Kosaraju's algorithm, which unlike Tarjan's doesn't
+//! recurse, so a long chain of dependencies can't overflow the stack.
+
+use std::collections::{HashMap, HashSet, VecDeque, hash_map::Entry};
+
+use miette::Diagnostic;
+use petgraph::{
+    Direction,
+    algo::kosaraju_scc,
+    stable_graph::NodeIndex,
+    visit::{EdgeFiltered, EdgeRef},
+};
+use thiserror::Error;
+
+use crate::{
+    database::{ReleaseId, models::module::RelationshipType},
+    resolver::{Edge, Graph, Node},
+};
+
+/// A loop of releases, each of which depends on something provided by the
+/// next. The last one depends on something the first provides.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Diagnostic, uniffi::Record)]
+#[error("releases depend on each other in a loop: {}", describe(.steps))]
+#[diagnostic(
+    code(camrete::resolver::dependency_cycle),
+    severity(Warning),
+    help("the releases can still be installed together, but their metadata is probably wrong")
+)]
+pub struct DependencyCycle {
+    /// The shortest loop through the release with the lowest ID.
+    pub steps: Vec<CycleStep>,
+    /// Every release which is part of this loop or of another one sharing
+    /// releases with it, such as other versions of the same modules.
+    pub releases: Vec<ReleaseId>,
+}
+
+/// A release in a [`DependencyCycle`].
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct CycleStep {
+    pub release: ReleaseId,
+    pub identifier: String,
+    pub version: String,
+    /// The identifier which the release depends on, and which the next
+    /// release provides. It's a virtual identifier if the next release only
+    /// provides it.
+    pub depends_on: String,
+}
+
+fn describe(steps: &[CycleStep]) -> String {
+    steps
+        .iter()
+        .map(|step| {
+            format!(
+                "{} {} depends on {}",
+                step.identifier, step.version, step.depends_on
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether an edge is followed when looking for loops. Relationships other than
+/// dependencies, and version bounds, are ignored.
+fn is_dependency(edge: &Edge) -> bool {
+    match edge {
+        Edge::ProvidedBy => true,
+        Edge::Relationship(r) => r.rel_type == RelationshipType::Depends,
+    }
+}
+
+impl Graph {
+    /// Finds the releases which depend on each other in a loop, ordered by the
+    /// ID of their first release. A release which depends on its own
+    /// identifier forms a loop by itself.
+    pub fn dependency_cycles(&self) -> Vec<DependencyCycle> {
+        let depends = EdgeFiltered::from_fn(&self.graph, |edge| is_dependency(edge.weight()));
+
+        let mut cycles = kosaraju_scc(&depends)
+            .into_iter()
+            // Identifiers and releases alternate around a loop, so even the smallest
+            // one has two members.
+            .filter(|component| component.len() > 1)
+            .filter_map(|component| self.shortest_cycle(component))
+            .collect::<Vec<_>>();
+        cycles.sort_by_key(|cycle| cycle.steps[0].release);
+        cycles
+    }
+
+    /// Finds the shortest loop through the release with the lowest ID in a
+    /// group of nodes which can all reach each other.
+    fn shortest_cycle(&self, component: Vec<NodeIndex>) -> Option<DependencyCycle> {
+        let mut releases = component
+            .iter()
+            .filter_map(|&index| match &self.graph[index] {
+                Node::Release(release) => Some((release.id, index)),
+                Node::Identifier(_) => None,
+            })
+            .collect::<Vec<_>>();
+        releases.sort();
+        let start = releases.first()?.1;
+        let members = component.into_iter().collect::<HashSet<_>>();
+
+        // A breadth-first search from the start back to itself, remembering
+        // where each node was reached from.
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        let mut last = None;
+        'search: while let Some(node) = queue.pop_front() {
+            for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+                let next = edge.target();
+                if !is_dependency(edge.weight()) || !members.contains(&next) {
+                    continue;
+                }
+                if next == start {
+                    last = Some(node);
+                    break 'search;
+                }
+                if let Entry::Vacant(entry) = previous.entry(next) {
+                    entry.insert(node);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        // Nodes reached directly from the start were reached from it, so
+        // walking back ends there.
+        let mut path = vec![last?];
+        while let Some(&node) = path.last().and_then(|node| previous.get(node)) {
+            path.push(node);
+        }
+        path.reverse();
+
+        // The path alternates between releases and the identifiers they
+        // depend on, ending with the identifier the start provides.
+        let steps = path
+            .chunks_exact(2)
+            .map(|pair| {
+                let release = self.release_at(pair[0]);
+                let Node::Identifier(depends_on) = &self.graph[pair[1]] else {
+                    unreachable!("releases depend on identifiers");
+                };
+                CycleStep {
+                    release: release.id,
+                    identifier: release.identifier.clone(),
+                    version: release.version.to_string(),
+                    depends_on: depends_on.clone(),
+                }
+            })
+            .collect();
+
+        Some(DependencyCycle {
+            steps,
+            releases: releases.into_iter().map(|(id, _)| id).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        database::models::module::ModuleVersion,
+        json::{JsonModule, ModuleKind},
+        repo::test_support::db_with_default_repo,
+        resolver::{Relationship, ReleaseNode},
+    };
+
+    /// Releases whose dependencies form loops of one, two and three releases,
+    /// along with one which depends on a loop without being part of it.
+    const FIXTURES: [&str; 7] = [
+        include_str!("fixtures/cycles/ModA-1.0.ckan"),
+        include_str!("fixtures/cycles/ModB-1.0.ckan"),
+        include_str!("fixtures/cycles/Planets-1.0.ckan"),
+        include_str!("fixtures/cycles/Parallax-1.0.ckan"),
+        include_str!("fixtures/cycles/Scatterer-1.0.ckan"),
+        include_str!("fixtures/cycles/Selfish-1.0.ckan"),
+        include_str!("fixtures/cycles/Standalone-1.0.ckan"),
+    ];
+
+    fn loop_of(cycle: &DependencyCycle) -> Vec<(&str, &str)> {
+        cycle
+            .steps
+            .iter()
+            .map(|step| (step.identifier.as_str(), step.depends_on.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let (mut db, repo) = db_with_default_repo();
+
+        for fixture in FIXTURES {
+            let json = serde_json::from_str::<JsonModule>(fixture).unwrap();
+            db.create_release(&json, repo.id, None).unwrap();
+        }
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let cycles = graph.dependency_cycles();
+        assert_eq!(cycles.len(), 3);
+
+        assert_eq!(loop_of(&cycles[0]), [("ModA", "ModB"), ("ModB", "ModA")]);
+        assert_eq!(
+            loop_of(&cycles[1]),
+            [
+                ("Planets", "Terrain"),
+                ("Parallax", "Scatterer"),
+                ("Scatterer", "Planets")
+            ]
+        );
+        assert_eq!(cycles[1].releases.len(), 3);
+        assert_eq!(loop_of(&cycles[2]), [("Selfish", "Selfish")]);
+        assert_eq!(
+            cycles[1].to_string(),
+            "releases depend on each other in a loop: Planets 1.0 depends on Terrain, \
+             Parallax 1.0 depends on Scatterer, Scatterer 1.0 depends on Planets"
+        );
+
+        // Resolving follows each loop once.
+        let resolution = graph.resolve(&["Standalone".into()], false);
+        assert_eq!(resolution.selected.len(), 3);
+        assert!(resolution.unsatisfied.is_empty());
+    }
+
+    #[test]
+    fn long_chains_do_not_overflow() {
+        let mut graph = Graph::default();
+        let length = 200_000;
+
+        for i in 0..length {
+            let identifier = format!("Mod{i}");
+            let release = graph.graph.add_node(Node::Release(ReleaseNode {
+                id: ReleaseId::from(i),
+                repo_id: 1.into(),
+                identifier: identifier.clone(),
+                version: ModuleVersion::from("1.0"),
+                kind: ModuleKind::Package,
+                yanked: false,
+                blocked: false,
+            }));
+            let provided = graph.identifier(&identifier);
+            graph.graph.add_edge(provided, release, Edge::ProvidedBy);
+
+            // Each module depends on the next, and the last on the first.
+            let dependency = graph.identifier(&format!("Mod{}", (i + 1) % length));
+            let relationship = Relationship {
+                rel_type: RelationshipType::Depends,
+                group_id: i.into(),
+                ordinal: 0,
+                version_max: None,
+                version_min: None,
+            };
+            graph
+                .graph
+                .add_edge(release, dependency, Edge::Relationship(relationship));
+        }
+
+        let cycles = graph.dependency_cycles();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].steps.len(), length as usize);
+        assert_eq!(cycles[0].steps[0].identifier, "Mod0");
+    }
+}
@@ -0,0 +1,352 @@
+//! Choosing the releases needed to install a set of modules, and keeping track
+//! of why each one was chosen.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    database::{ReleaseId, models::module::RelationshipType},
+    resolver::{Graph, Relationship, ReleaseNode},
+};
+
+/// The releases needed to install a set of requested modules, with the reason
+/// each one was chosen.
+///
+/// Only one release of each module is chosen, but conflicts between the
+/// chosen releases aren't checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct Resolution {
+    /// The chosen releases, in the order they were chosen, so a release comes
+    /// after the one whose relationship it satisfies. The requested modules
+    /// come first.
+    pub selected: Vec<Selection>,
+    /// Requested modules and dependencies which no installable release
+    /// satisfies. Unsatisfied recommendations aren't included.
+    pub unsatisfied: Vec<Unsatisfied>,
+}
+
+/// A chosen release.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct Selection {
+    pub release: ReleaseId,
+    pub identifier: String,
+    pub version: String,
+    pub reason: Reason,
+}
+
+/// Why a release was chosen.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum Reason {
+    /// Its module was requested, and it's the module's newest installable
+    /// release.
+    Requested,
+    /// An earlier selection has a relationship which this release satisfies.
+    Relationship {
+        /// The release with the relationship.
+        dependent: ReleaseId,
+        rel_type: RelationshipType,
+        /// The identifier this release was chosen for. It's a virtual
+        /// identifier if the release only provides it.
+        target: String,
+        /// Every identifier in the relationship's `any_of` group, in order, or
+        /// nothing if it names a single identifier. The target is the first
+        /// one which something installable provides.
+        any_of: Vec<String>,
+        choice: Choice,
+    },
+}
+
+/// Why a release was chosen out of those providing an identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum Choice {
+    /// It's the newest suitable release of the module with that identifier.
+    Newest,
+    /// Its module is the only one which provides the identifier, and this is
+    /// the module's newest suitable release.
+    OnlyProvider,
+    /// Several modules provide the identifier, and this module's identifier
+    /// sorts first. This is the newest suitable release of it.
+    FirstProvider,
+}
+
+/// A requested module or dependency which couldn't be satisfied.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct Unsatisfied {
+    /// The release with the dependency, or `None` for a requested module.
+    pub dependent: Option<ReleaseId>,
+    /// The identifiers which would satisfy it. A dependency with an `any_of`
+    /// group has more than one.
+    pub targets: Vec<String>,
+}
+
+impl Resolution {
+    /// Returns the selection of a module, followed by the selection whose
+    /// relationship it satisfies and so on, ending with a requested module.
+    /// Returns nothing if the module wasn't chosen.
+    pub fn explain(&self, identifier: &str) -> Vec<&Selection> {
+        let mut chain = vec![];
+        let mut next = self.selected.iter().find(|s| s.identifier == identifier);
+
+        while let Some(selection) = next {
+            chain.push(selection);
+            next = match &selection.reason {
+                Reason::Requested => None,
+                Reason::Relationship { dependent, .. } => {
+                    self.selected.iter().find(|s| s.release == *dependent)
+                }
+            };
+        }
+
+        chain
+    }
+
+    /// Returns the chosen release with the given ID.
+    pub fn selection(&self, release: ReleaseId) -> Option<&Selection> {
+        self.selected.iter().find(|s| s.release == release)
+    }
+}
+
+impl Graph {
+    /// Chooses the releases needed to install the requested modules, following
+    /// their dependencies, and their recommendations too if `recommends` is
+    /// set.
+    ///
+    /// The newest installable release of each requested module is chosen. A
+    /// relationship which an already chosen release or detected DLC satisfies
+    /// is left alone. Otherwise, the first member of its `any_of` group which
+    /// something installable provides is used, and for it, the newest release
+    /// of the named module is preferred over other modules providing it.
+    pub fn resolve(&self, requested: &[String], recommends: bool) -> Resolution {
+        let mut resolver = Resolver {
+            graph: self,
+            resolution: Resolution::default(),
+            modules: HashMap::new(),
+            queue: VecDeque::new(),
+        };
+
+        for identifier in requested {
+            let newest = self
+                .providers(identifier)
+                .filter(|release| release.identifier == *identifier)
+                .filter(|release| release.is_installable())
+                .max_by(|a, b| a.version.cmp(&b.version));
+
+            match newest {
+                Some(release) => resolver.select(release, Reason::Requested),
+                None => resolver.resolution.unsatisfied.push(Unsatisfied {
+                    dependent: None,
+                    targets: vec![identifier.clone()],
+                }),
+            }
+        }
+
+        while let Some(release) = resolver.queue.pop_front() {
+            resolver.follow(release, recommends);
+        }
+
+        resolver.resolution
+    }
+}
+
+/// The state of [`Graph::resolve`].
+struct Resolver<'a> {
+    graph: &'a Graph,
+    resolution: Resolution,
+    /// The chosen release of each module.
+    modules: HashMap<&'a str, ReleaseId>,
+    /// Chosen releases whose relationships haven't been followed yet.
+    queue: VecDeque<ReleaseId>,
+}
+
+impl<'a> Resolver<'a> {
+    fn select(&mut self, release: &'a ReleaseNode, reason: Reason) {
+        if self.modules.contains_key(release.identifier.as_str()) {
+            return;
+        }
+
+        self.modules.insert(&release.identifier, release.id);
+        self.queue.push_back(release.id);
+        self.resolution.selected.push(Selection {
+            release: release.id,
+            identifier: release.identifier.clone(),
+            version: release.version.to_string(),
+            reason,
+        });
+    }
+
+    /// Satisfies the relationships of a chosen release.
+    fn follow(&mut self, release: ReleaseId, recommends: bool) {
+        let mut relationships = self
+            .graph
+            .relationships(release)
+            .filter(|(_, r)| {
+                r.rel_type == RelationshipType::Depends
+                    || (recommends && r.rel_type == RelationshipType::Recommends)
+            })
+            .collect::<Vec<_>>();
+        relationships.sort_by_key(|(_, r)| (r.group_id, r.ordinal));
+
+        for group in relationships.chunk_by(|(_, a), (_, b)| a.group_id == b.group_id) {
+            let satisfied = group.iter().any(|&(target, relationship)| {
+                self.graph.detected_dlc(target, relationship).is_some()
+                    || self
+                        .graph
+                        .candidates(target, relationship)
+                        .any(|c| self.modules.get(c.identifier.as_str()) == Some(&c.id))
+            });
+            if satisfied {
+                continue;
+            }
+
+            let chosen = group.iter().find_map(|&(target, relationship)| {
+                let (candidate, choice) = self.choose(target, relationship)?;
+                Some((target, relationship, candidate, choice))
+            });
+
+            let Some((target, relationship, candidate, choice)) = chosen else {
+                if group[0].1.rel_type == RelationshipType::Depends {
+                    self.resolution.unsatisfied.push(Unsatisfied {
+                        dependent: Some(release),
+                        targets: group.iter().map(|(target, _)| target.to_string()).collect(),
+                    });
+                }
+                continue;
+            };
+
+            let any_of = if group.len() > 1 {
+                group.iter().map(|(target, _)| target.to_string()).collect()
+            } else {
+                vec![]
+            };
+            let reason = Reason::Relationship {
+                dependent: release,
+                rel_type: relationship.rel_type,
+                target: target.to_owned(),
+                any_of,
+                choice,
+            };
+            self.select(candidate, reason);
+        }
+    }
+
+    /// Picks the release to satisfy one member of a relationship, leaving out
+    /// modules which already have a different release chosen.
+    fn choose(
+        &self,
+        target: &'a str,
+        relationship: &'a Relationship,
+    ) -> Option<(&'a ReleaseNode, Choice)> {
+        let candidates = self
+            .graph
+            .candidates(target, relationship)
+            .filter(|c| !self.modules.contains_key(c.identifier.as_str()))
+            .collect::<Vec<_>>();
+        let newest_of = |identifier: &str| {
+            candidates
+                .iter()
+                .copied()
+                .filter(|c| c.identifier == identifier)
+                .max_by(|a, b| a.version.cmp(&b.version))
+        };
+
+        if let Some(release) = newest_of(target) {
+            return Some((release, Choice::Newest));
+        }
+
+        let first = candidates.iter().map(|c| c.identifier.as_str()).min()?;
+        let choice = if candidates.iter().all(|c| c.identifier == first) {
+            Choice::OnlyProvider
+        } else {
+            Choice::FirstProvider
+        };
+        Some((newest_of(first)?, choice))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{
+        repo::test_support::{db_with_default_repo, release},
+        resolver::Graph,
+    };
+
+    use super::*;
+
+    #[test]
+    fn explains_each_choice() {
+        let (mut db, repo) = db_with_default_repo();
+
+        let modules = [
+            release("Parallax", "2.0")
+                .merge(json!({
+                    "depends": [
+                        { "any_of": [{ "name": "Kopernicus-Fork" }, { "name": "Kopernicus" }] },
+                        { "name": "Terrain" },
+                        { "name": "Missing" },
+                    ],
+                }))
+                .build(),
+            release("Kopernicus", "1.0").build(),
+            release("Kopernicus", "1.1")
+                .merge(json!({ "depends": [{ "name": "ModuleManager" }] }))
+                .build(),
+            release("ModuleManager", "4.2").build(),
+            release("TerrainB", "1.0")
+                .merge(json!({ "provides": ["Terrain"] }))
+                .build(),
+            release("TerrainA", "1.0")
+                .merge(json!({ "provides": ["Terrain"] }))
+                .build(),
+        ];
+        for module in &modules {
+            db.create_release(module, repo.id, None).unwrap();
+        }
+
+        let graph = Graph::load(&mut db, None).unwrap();
+        let resolution = graph.resolve(&["Parallax".into(), "Nothing".into()], false);
+
+        let chosen = resolution
+            .selected
+            .iter()
+            .map(|s| format!("{} {}", s.identifier, s.version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chosen,
+            [
+                "Parallax 2.0",
+                "Kopernicus 1.1",
+                "TerrainA 1.0",
+                "ModuleManager 4.2"
+            ]
+        );
+
+        let chain = resolution.explain("ModuleManager");
+        let chain = chain
+            .iter()
+            .map(|s| s.identifier.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(chain, ["ModuleManager", "Kopernicus", "Parallax"]);
+        assert_eq!(resolution.explain("Parallax")[0].reason, Reason::Requested);
+        assert!(resolution.explain("Nothing").is_empty());
+
+        let Reason::Relationship { any_of, choice, .. } = &resolution.selected[1].reason else {
+            panic!("Kopernicus wasn't requested");
+        };
+        assert_eq!(any_of, &["Kopernicus-Fork", "Kopernicus"]);
+        assert_eq!(*choice, Choice::Newest);
+
+        let Reason::Relationship { target, choice, .. } = &resolution.selected[2].reason else {
+            panic!("TerrainA wasn't requested");
+        };
+        assert_eq!(target, "Terrain");
+        assert_eq!(*choice, Choice::FirstProvider);
+
+        let unsatisfied = resolution
+            .unsatisfied
+            .iter()
+            .map(|u| u.targets.join("|"))
+            .collect::<Vec<_>>();
+        assert_eq!(unsatisfied, ["Nothing", "Missing"]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Replays a subset of CKAN-meta into a fresh database and checks that what
+//! comes out is consistent with what went in.
+//!
+//! The snapshot is the benchmark repository, which is real CKAN-meta history
+//! for a handful of modules, plus the documents in `tests/fixtures/snapshot`,
+//! which cover the parts of the spec the benchmark modules don't use: `any_of`
+//! groups, metapackages, DLC and unusual version strings.
+//!
+//! Run with `cargo test -p camrete-core --features snapshot-tests`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use camrete_core::{
+    database::{
+        models::{RepositoryRef, module::ModuleVersion},
+        schema::*,
+    },
+    diesel::prelude::*,
+    json::{JsonModule, ModuleKind, ReleaseStatus},
+    repo::{
+        RepoAssetBuf, RepoAssetLoader, RepoAssetVariant, RepoManager, TarGzAssetLoader,
+        asset_stream::InMemoryAssetLoader, client::DownloadProgressReporter,
+    },
+};
+use futures_util::TryStreamExt;
+use url::Url;
+
+const BENCH_REPO: &[u8] = include_bytes!("../benches/mini_repo.tgz");
+
+/// Reads every asset under a directory laid out like a CKAN-meta checkout.
+fn read_fixtures(dir: &Path, assets: &mut Vec<RepoAssetBuf>) {
+    let mut entries = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            read_fixtures(&path, assets);
+        } else if let Some(variant) = RepoAssetVariant::from_path(&path) {
+            assets.push(RepoAssetBuf {
+                data: fs::read(&path).unwrap().into(),
+                path,
+                variant,
+            });
+        }
+    }
+}
+
+async fn load_snapshot() -> Vec<RepoAssetBuf> {
+    let loader = TarGzAssetLoader::from_buf(BENCH_REPO);
+    let mut assets = loader
+        .asset_stream()
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/snapshot");
+    read_fixtures(&fixtures, &mut assets);
+    assets
+}
+
+/// Counts a release's relationship groups and their members, the way they're
+/// stored in the database.
+fn relationship_counts(release: &JsonModule) -> (usize, usize) {
+    let groups = release.relationships().count() + release.provides.len();
+    let members = release
+        .relationships()
+        .map(|(_, relationship)| relationship.descriptor.flatten().len())
+        .sum::<usize>()
+        + release.provides.len();
+
+    (groups, members)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_snapshot() {
+    let assets = load_snapshot().await;
+    let releases = assets
+        .iter()
+        .filter(|asset| asset.variant == RepoAssetVariant::Release)
+        .map(|asset| serde_json::from_slice::<JsonModule>(&asset.data).unwrap())
+        .collect::<Vec<_>>();
+    assert!(releases.len() > 200, "the snapshot is missing documents");
+
+    let mut repo_mgr = RepoManager::new(":memory:").unwrap();
+    let url = Url::parse("about:blank").unwrap();
+    let repo = repo_mgr
+        .db()
+        .unwrap()
+        .create_empty_repo(RepositoryRef::shared("snapshot", &url))
+        .unwrap();
+
+    let progress = Arc::new(DownloadProgressReporter::new(None, Box::new(|_| {})));
+    let report = repo_mgr
+        .unpack_repo(&repo, InMemoryAssetLoader::from(assets), None, progress)
+        .await
+        .unwrap();
+
+    assert!(report.quarantined.is_empty());
+    assert_eq!(report.items_unpacked, releases.len() as u64);
+
+    let mut db = repo_mgr.db().unwrap();
+    let hash = db.content_hash(repo.id).unwrap();
+    assert!(hash.is_some_and(|hash| hash.len() == 64));
+
+    let conn = &mut **db.as_mut();
+
+    // Every document becomes one release, and every identifier one module.
+    let identifiers = releases
+        .iter()
+        .map(|release| release.identifier.as_str())
+        .collect::<HashSet<_>>();
+    let module_count: i64 = modules::table.count().get_result(conn).unwrap();
+    let release_count: i64 = module_releases::table.count().get_result(conn).unwrap();
+    assert_eq!(module_count as usize, identifiers.len());
+    assert_eq!(release_count as usize, releases.len());
+
+    let module_ids = modules::table
+        .select((modules::module_slug, modules::module_id))
+        .load::<(String, i32)>(conn)
+        .unwrap()
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    // The latest stable release of each module is the greatest version, by
+    // the same ordering CKAN uses.
+    let mut expected_latest = HashMap::<&str, &str>::new();
+    for release in releases
+        .iter()
+        .filter(|release| release.release_status == ReleaseStatus::Stable)
+    {
+        let latest = expected_latest
+            .entry(release.identifier.as_str())
+            .or_insert(&release.version);
+        if ModuleVersion::from(release.version.as_str()) > ModuleVersion::from(*latest) {
+            *latest = &release.version;
+        }
+    }
+
+    let mut latest = HashMap::new();
+    for (slug, expected) in &expected_latest {
+        let module_id = module_ids[*slug];
+        let release = db.latest_release(module_id.into()).unwrap().unwrap();
+        assert_eq!(release.version, *expected, "latest release of {slug}");
+        latest.insert(*slug, release.version);
+    }
+
+    // Spot checks, so a mistake in the ordering can't hide in both halves of
+    // the comparison above.
+    assert_eq!(latest["Parallax"], "2.0.8");
+    assert_eq!(latest["TweakScale"], "v2.4.8.6");
+    // An epoch beats any version without one, and the newer testing release
+    // isn't offered.
+    assert_eq!(latest["Kopernicus"], "1:release-1.12.1-65");
+
+    let conn = &mut **db.as_mut();
+
+    // Every relationship group belongs to a release and has at least one
+    // member, and every member belongs to a group.
+    let orphan_groups: i64 = module_relationship_groups::table
+        .left_join(module_releases::table)
+        .filter(module_releases::release_id.is_null())
+        .count()
+        .get_result(conn)
+        .unwrap();
+    let empty_groups: i64 = module_relationship_groups::table
+        .left_join(module_relationships::table)
+        .filter(module_relationships::relationship_id.is_null())
+        .count()
+        .get_result(conn)
+        .unwrap();
+    let orphan_members: i64 = module_relationships::table
+        .left_join(module_relationship_groups::table)
+        .filter(module_relationship_groups::group_id.is_null())
+        .count()
+        .get_result(conn)
+        .unwrap();
+    assert_eq!((orphan_groups, empty_groups, orphan_members), (0, 0, 0));
+
+    // Each release keeps exactly the relationships its document lists.
+    let stored = module_relationships::table
+        .inner_join(module_relationship_groups::table.inner_join(module_releases::table))
+        .group_by(module_releases::release_id)
+        .select((
+            module_releases::release_id,
+            diesel::dsl::count(module_relationship_groups::group_id).aggregate_distinct(),
+            diesel::dsl::count(module_relationships::relationship_id),
+        ))
+        .load::<(i32, i64, i64)>(conn)
+        .unwrap()
+        .into_iter()
+        .map(|(release, groups, members)| (release, (groups as usize, members as usize)))
+        .collect::<HashMap<_, _>>();
+
+    for release in &releases {
+        let release_id: i32 = module_releases::table
+            .filter(module_releases::module_id.eq(module_ids[release.identifier.as_str()]))
+            .filter(module_releases::version.eq(&release.version))
+            .select(module_releases::release_id)
+            .get_result(conn)
+            .unwrap();
+
+        let counts = stored.get(&release_id).copied().unwrap_or_default();
+        assert_eq!(
+            counts,
+            relationship_counts(release),
+            "relationships of {} {}",
+            release.identifier.as_str(),
+            release.version
+        );
+    }
+
+    // any_of groups keep their members together, along with the help text.
+    let opm_choice = module_relationship_groups::table
+        .inner_join(module_releases::table)
+        .filter(module_releases::module_id.eq(module_ids["OuterPlanetsMod"]))
+        .filter(module_relationship_groups::choice_help_text.is_not_null())
+        .select(module_relationship_groups::group_id)
+        .get_result::<i32>(conn)
+        .unwrap();
+    let choices = module_relationships::table
+        .filter(module_relationships::group_id.eq(opm_choice))
+        .order_by(module_relationships::ordinal)
+        .select(module_relationships::target_name)
+        .load::<String>(conn)
+        .unwrap();
+    assert_eq!(
+        choices,
+        [
+            "EnvironmentalVisualEnhancements",
+            "EnvironmentalVisualEnhancements-Redux",
+            "OuterPlanetsMod-NoClouds",
+        ]
+    );
+
+    // Metapackages and DLC keep their kind, and don't need a download.
+    let kind_of = |conn: &mut SqliteConnection, slug: &str| {
+        module_releases::table
+            .filter(module_releases::module_id.eq(module_ids[slug]))
+            .select(module_releases::kind)
+            .first::<i32>(conn)
+            .unwrap()
+    };
+    assert_eq!(
+        kind_of(conn, "OuterPlanetsMod-Complete"),
+        i32::from(ModuleKind::Metapackage)
+    );
+    assert_eq!(
+        kind_of(conn, "MakingHistory-DLC"),
+        i32::from(ModuleKind::Dlc)
+    );
+    assert_eq!(kind_of(conn, "Parallax"), i32::from(ModuleKind::Package));
+}
@@ -0,0 +1,63 @@
+//! Kotlin bindings, for Android apps.
+//!
+//! The `kotlin` directory is an Android library, which Gradle packs into an
+//! AAR. Native libraries go in `jniLibs`, by ABI, where JNA finds them at run
+//! time. Android doesn't come with a SQLite that apps can link to, so it's
+//! built into the library.
+
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use fs_err as fs;
+
+use crate::{build_core, generate_bindings, target_dir};
+
+/// The sources of the Android library.
+const SOURCE_DIR: &str = "kotlin/src/main";
+const LIBRARY: &str = "libcamrete_core.so";
+
+/// The targets built unless others are chosen, with the ABI Android calls each.
+const TARGETS: [(&str, &str); 4] = [
+    ("aarch64-linux-android", "arm64-v8a"),
+    ("armv7-linux-androideabi", "armeabi-v7a"),
+    ("x86_64-linux-android", "x86_64"),
+    ("i686-linux-android", "x86"),
+];
+
+fn abi_of(triple: &str) -> Result<&'static str> {
+    match TARGETS.iter().find(|(target, _)| *target == triple) {
+        Some((_, abi)) => Ok(abi),
+        None => bail!("{triple:?} isn't an Android target which Kotlin bindings can be built for"),
+    }
+}
+
+pub fn generate(targets: Vec<String>, release: bool) -> Result<()> {
+    eprintln!("--- Building shared libs & Kotlin bindings ---");
+    let targets = if targets.is_empty() {
+        TARGETS
+            .iter()
+            .map(|(triple, _)| triple.to_string())
+            .collect()
+    } else {
+        targets
+    };
+
+    for triple in &targets {
+        let abi = abi_of(triple)?;
+        eprintln!("Building {triple}");
+        // cross's Android images come with the NDK, which linking needs.
+        build_core(triple, release, true, &["bundled-sqlite"])?;
+
+        let lib_dir = Path::new(SOURCE_DIR).join("jniLibs").join(abi);
+        fs::create_dir_all(&lib_dir)?;
+        fs::copy(
+            target_dir(triple, release).join(LIBRARY),
+            lib_dir.join(LIBRARY),
+        )?;
+    }
+
+    // Every library has the metadata the bindings are generated from.
+    eprintln!("Generating Kotlin source code");
+    let library = target_dir(&targets[0], release).join(LIBRARY);
+    generate_bindings("kotlin", &library, &Path::new(SOURCE_DIR).join("kotlin"))
+}
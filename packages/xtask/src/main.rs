@@ -2,7 +2,7 @@ use std::{
     collections::HashSet,
     env::{self, set_current_dir},
     ffi::{OsStr, OsString},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{self, exit},
 };
 
@@ -10,6 +10,10 @@ use anyhow::Result;
 use clap::{Parser, Subcommand};
 use fs_err as fs;
 
+mod kotlin;
+mod nuget;
+mod swift;
+
 #[derive(Debug, Parser)]
 struct Args {
     #[clap(subcommand)]
@@ -18,7 +22,11 @@ struct Args {
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    #[clap(disable_help_flag = true, disable_help_subcommand = true)]
+    #[clap(
+        alias = "gen-dotnet",
+        disable_help_flag = true,
+        disable_help_subcommand = true
+    )]
     CreateBindings {
         #[clap(long, short)]
         target: Vec<String>,
@@ -31,6 +39,39 @@ enum Command {
         )]
         args: Vec<OsString>,
     },
+    /// Build an XCFramework for iOS and macOS, and generate Swift bindings for
+    /// it, in the `swift` package.
+    GenSwift {
+        /// The Apple targets to build, instead of every one in the framework.
+        #[clap(long, short)]
+        target: Vec<String>,
+        #[clap(long, short)]
+        release: bool,
+    },
+    /// Build native libraries for Android, and generate Kotlin bindings for
+    /// them, in the `kotlin` library, which Gradle packs into an AAR.
+    GenKotlin {
+        /// The Android targets to build, instead of every ABI Android supports.
+        #[clap(long, short)]
+        target: Vec<String>,
+        #[clap(long, short)]
+        release: bool,
+    },
+    /// Pack the .NET bindings made by `create-bindings` into a NuGet package,
+    /// with the native library for each runtime.
+    PackageNuget {
+        /// The .NET runtime IDs the package must support, like `linux-x64`.
+        /// Packing fails if any of their libraries haven't been built. By
+        /// default, every runtime which has been built is included.
+        #[clap(long)]
+        rid: Vec<String>,
+        /// The package's version, instead of the one in the project file.
+        #[clap(long)]
+        version: Option<String>,
+        /// Where to save the package.
+        #[clap(long, short, default_value = "target/nuget")]
+        output: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
@@ -59,20 +100,7 @@ fn main() -> Result<()> {
                     eprintln!("(Using `cross` for cross-compilation)");
                 }
 
-                let mut cmd = if needs_cross {
-                    process::Command::new("cross")
-                } else {
-                    cargo()
-                };
-                cmd.args(["build", "-p", "camrete-core", "--target", &platform.triple]);
-                if release {
-                    cmd.arg("--release");
-                }
-
-                let success = cmd.status()?.success();
-                if !success {
-                    exit(1);
-                }
+                build_core(&platform.triple, release, needs_cross, &[])?;
 
                 // Copy DLL to respective platform directory.
 
@@ -111,16 +139,57 @@ fn main() -> Result<()> {
                 }
             }
         }
+        Command::GenSwift { target, release } => swift::generate(target, release)?,
+        Command::GenKotlin { target, release } => kotlin::generate(target, release)?,
+        Command::PackageNuget {
+            rid,
+            version,
+            output,
+        } => nuget::package(rid, version, output)?,
     }
 
     Ok(())
 }
 
-fn launch_bin(name: &str, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Result<()> {
-    let mut cmd = cargo();
-    cmd.args(["run", "-p", "xtask", "--bin", name, "--"]);
-    cmd.args(args);
+/// Builds `camrete-core` for a target, with `cross` if it can't be built on
+/// this machine, turning on the given features.
+fn build_core(triple: &str, release: bool, use_cross: bool, features: &[&str]) -> Result<()> {
+    let mut cmd = if use_cross {
+        process::Command::new("cross")
+    } else {
+        cargo()
+    };
+    cmd.args(["build", "-p", "camrete-core", "--target", triple]);
+    if release {
+        cmd.arg("--release");
+    }
+    if !features.is_empty() {
+        cmd.args(["--features", &features.join(",")]);
+    }
 
+    run(cmd)
+}
+
+/// Generates bindings in another language from the metadata in a library
+/// built from `camrete-core`.
+fn generate_bindings(language: &str, library: &Path, out_dir: &Path) -> Result<()> {
+    launch_bin(
+        "bindgen-uniffi",
+        [
+            OsStr::new("generate"),
+            OsStr::new("--language"),
+            OsStr::new(language),
+            OsStr::new("--config=uniffi.toml"),
+            OsStr::new("--out-dir"),
+            out_dir.as_os_str(),
+            OsStr::new("--library"),
+            library.as_os_str(),
+        ],
+    )
+}
+
+/// Runs a command, exiting if it fails.
+fn run(mut cmd: process::Command) -> Result<()> {
     let success = cmd.status()?.success();
     if !success {
         exit(1);
@@ -129,6 +198,14 @@ fn launch_bin(name: &str, args: impl IntoIterator<Item = impl AsRef<OsStr>>) ->
     Ok(())
 }
 
+fn launch_bin(name: &str, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Result<()> {
+    let mut cmd = cargo();
+    cmd.args(["run", "-p", "xtask", "--bin", name, "--"]);
+    cmd.args(args);
+
+    run(cmd)
+}
+
 fn cargo() -> process::Command {
     let cargo = env::var("CARGO").unwrap();
     process::Command::new(cargo)
@@ -167,6 +244,13 @@ fn lookup_triple(triple: String) -> TripleDetails {
     }
 }
 
+/// Where cargo puts what it builds for a target.
+fn target_dir(triple: &str, release: bool) -> PathBuf {
+    PathBuf::from("target")
+        .join(triple)
+        .join(if release { "release" } else { "debug" })
+}
+
 fn default_triple() -> TripleDetails {
     let mut rustc = process::Command::new("rustc");
     rustc.args(["--print", "host-tuple"]);
@@ -189,9 +273,7 @@ impl TripleDetails {
     }
 
     fn target_dir(&self, release: bool) -> PathBuf {
-        PathBuf::from("target")
-            .join(&self.triple)
-            .join(if release { "release" } else { "debug" })
+        target_dir(&self.triple, release)
     }
 
     fn needs_cross_for(&self, target: &Self) -> bool {
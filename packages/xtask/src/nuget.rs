@@ -0,0 +1,201 @@
+//! Packing the .NET bindings into a NuGet package with a native library for
+//! each runtime.
+//!
+//! `create-bindings` copies each target's library into `runtimes/<RID>`, but
+//! nothing checks that every runtime a package should support is there. This
+//! makes sure they are before packing them with the compiled bindings.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+
+use crate::run;
+
+const PROJECT_DIR: &str = "dotnet/Core";
+const PROJECT: &str = "dotnet/Core/Camrete.Core.csproj";
+
+/// The file name of the native library for a runtime.
+fn library_name(rid: &str) -> Option<&'static str> {
+    match rid.split('-').next()? {
+        "osx" => Some("libcamrete_core.dylib"),
+        "linux" => Some("libcamrete_core.so"),
+        "win" => Some("camrete_core.dll"),
+        _ => None,
+    }
+}
+
+/// The target to build for a runtime which is missing, for suggesting a
+/// command which builds it.
+fn triple_for(rid: &str) -> Option<&'static str> {
+    Some(match rid {
+        "osx-arm64" => "aarch64-apple-darwin",
+        "osx-x64" => "x86_64-apple-darwin",
+        "win-arm64" => "aarch64-pc-windows-msvc",
+        "win-x64" => "x86_64-pc-windows-gnu",
+        "linux-x64" => "x86_64-unknown-linux-gnu",
+        "linux-musl-x64" => "x86_64-unknown-linux-musl",
+        "linux-arm64" => "aarch64-unknown-linux-gnu",
+        "linux-musl-arm64" => "aarch64-unknown-linux-musl",
+        "linux-arm" => "armv7-unknown-linux-gnueabihf",
+        _ => return None,
+    })
+}
+
+/// Reads a property which is set in the project file, like its version.
+fn project_property(project: &str, name: &str) -> Result<String> {
+    let start = format!("<{name}>");
+    let end = format!("</{name}>");
+    project
+        .split_once(&start)
+        .and_then(|(_, rest)| rest.split_once(&end))
+        .map(|(value, _)| value.trim().to_owned())
+        .with_context(|| format!("{PROJECT} doesn't set {name}"))
+}
+
+/// The runtimes which `create-bindings` has copied a library for.
+fn built_runtimes() -> Result<Vec<String>> {
+    let mut rids = vec![];
+    for entry in fs::read_dir(Path::new(PROJECT_DIR).join("runtimes"))? {
+        let rid = entry?.file_name().to_string_lossy().into_owned();
+        // `native` holds the library for this machine, for local use.
+        if rid != "native" {
+            rids.push(rid);
+        }
+    }
+    rids.sort();
+    Ok(rids)
+}
+
+pub fn package(rids: Vec<String>, version: Option<String>, out_dir: PathBuf) -> Result<()> {
+    eprintln!("--- Packing the .NET bindings ---");
+    let rids = if rids.is_empty() {
+        built_runtimes()?
+    } else {
+        rids
+    };
+    if rids.is_empty() {
+        bail!("no native libraries have been built; run `cargo xtask create-bindings` first");
+    }
+
+    let mut missing = vec![];
+    for rid in &rids {
+        let Some(library) = library_name(rid) else {
+            bail!("{rid:?} isn't a runtime which Camrete can be built for");
+        };
+        let path = Path::new(PROJECT_DIR)
+            .join("runtimes")
+            .join(rid)
+            .join("native")
+            .join(library);
+        if !path.is_file() {
+            missing.push(rid.as_str());
+        }
+    }
+    if !missing.is_empty() {
+        let targets = missing
+            .iter()
+            .filter_map(|rid| triple_for(rid))
+            .map(|triple| format!(" -t {triple}"))
+            .collect::<String>();
+        bail!(
+            "the native libraries for {} haven't been built; run `cargo xtask create-bindings --release{targets}`",
+            missing.join(", ")
+        );
+    }
+
+    let has_bindings = fs::read_dir(PROJECT_DIR)?.any(|entry| {
+        entry.is_ok_and(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with("camrete") && name.ends_with(".cs")
+        })
+    });
+    if !has_bindings {
+        bail!("the C# bindings haven't been generated; run `cargo xtask create-bindings` first");
+    }
+
+    let project = fs::read_to_string(PROJECT)?;
+    let id = project_property(&project, "PackageId")?;
+    let version = match version {
+        Some(version) => version,
+        None => project_property(&project, "Version")?,
+    };
+    let authors = project_property(&project, "Authors")?;
+    let framework = project_property(&project, "TargetFramework")?;
+
+    eprintln!("Building the bindings");
+    let mut build = process::Command::new("dotnet");
+    build.args(["build", PROJECT, "--configuration", "Release"]);
+    run(build)?;
+
+    // Everything in the package is staged in one place, laid out as it is in
+    // the package, so the nuspec only needs to list two folders.
+    eprintln!("Staging {}", rids.join(", "));
+    let stage = env::current_dir()?.join("target/nuget/stage");
+    if stage.exists() {
+        fs::remove_dir_all(&stage)?;
+    }
+
+    let lib_dir = stage.join("lib").join(&framework);
+    fs::create_dir_all(&lib_dir)?;
+    let assembly = format!("{id}.dll");
+    fs::copy(
+        Path::new(PROJECT_DIR)
+            .join("bin/Release")
+            .join(&framework)
+            .join(&assembly),
+        lib_dir.join(&assembly),
+    )?;
+
+    for rid in &rids {
+        let library = library_name(rid).expect("runtimes were checked");
+        let native_dir = Path::new("runtimes").join(rid).join("native");
+        fs::create_dir_all(stage.join(&native_dir))?;
+        fs::copy(
+            Path::new(PROJECT_DIR).join(&native_dir).join(library),
+            stage.join(&native_dir).join(library),
+        )?;
+    }
+
+    let nuspec = stage.join(format!("{id}.nuspec"));
+    fs::write(
+        &nuspec,
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://schemas.microsoft.com/packaging/2013/05/nuspec.xsd">
+  <metadata>
+    <id>{id}</id>
+    <version>{version}</version>
+    <authors>{authors}</authors>
+    <description>Bindings to Camrete, with native libraries for {runtimes}.</description>
+    <dependencies>
+      <group targetFramework="{framework}" />
+    </dependencies>
+  </metadata>
+  <files>
+    <file src="lib/**" target="lib" />
+    <file src="runtimes/**" target="runtimes" />
+  </files>
+</package>
+"#,
+            runtimes = rids.join(", "),
+        ),
+    )?;
+
+    eprintln!("Packing {id} {version}");
+    let out_dir = env::current_dir()?.join(out_dir);
+    let mut pack = process::Command::new("dotnet");
+    pack.args(["pack", PROJECT, "--no-build", "--configuration", "Release"])
+        .arg("--output")
+        .arg(&out_dir)
+        .arg(format!("-p:NuspecFile={}", nuspec.display()))
+        .arg(format!("-p:NuspecBasePath={}", stage.display()));
+    run(pack)?;
+
+    eprintln!("Saved the package in {}", out_dir.display());
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Swift bindings, for iOS and macOS apps.
+//!
+//! The `swift` directory is a Swift package, whose bindings call into an
+//! XCFramework holding a static library for each Apple platform. Targets for
+//! the same platform, like the simulator on Apple silicon and on Intel, are
+//! combined with `lipo` first, since a framework only has one library for each.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Result, bail};
+use fs_err as fs;
+
+use crate::{build_core, generate_bindings, run, target_dir};
+
+/// The Swift package, which the framework and bindings are put in.
+const PACKAGE_DIR: &str = "swift";
+/// The module the framework provides, which is `ffi_module_name` in
+/// `uniffi.toml`.
+const FFI_MODULE: &str = "CamreteCoreFFI";
+const LIBRARY: &str = "libcamrete_core.a";
+
+/// The targets built unless others are chosen, with the platform each runs on.
+const TARGETS: [(&str, &str); 5] = [
+    ("aarch64-apple-ios", "ios"),
+    ("aarch64-apple-ios-sim", "ios-simulator"),
+    ("x86_64-apple-ios", "ios-simulator"),
+    ("aarch64-apple-darwin", "macos"),
+    ("x86_64-apple-darwin", "macos"),
+];
+
+fn platform_of(triple: &str) -> Result<&'static str> {
+    match TARGETS.iter().find(|(target, _)| *target == triple) {
+        Some((_, platform)) => Ok(platform),
+        None => bail!("{triple:?} isn't an Apple target which Swift bindings can be built for"),
+    }
+}
+
+pub fn generate(targets: Vec<String>, release: bool) -> Result<()> {
+    eprintln!("--- Building static libs & Swift bindings ---");
+    let targets = if targets.is_empty() {
+        TARGETS
+            .iter()
+            .map(|(triple, _)| triple.to_string())
+            .collect()
+    } else {
+        targets
+    };
+
+    let mut platforms = BTreeMap::<&str, Vec<PathBuf>>::new();
+    for triple in &targets {
+        let platform = platform_of(triple)?;
+        eprintln!("Building {triple}");
+        build_core(triple, release, false, &[])?;
+
+        platforms
+            .entry(platform)
+            .or_default()
+            .push(target_dir(triple, release).join(LIBRARY));
+    }
+
+    let build_dir = PathBuf::from("target/swift");
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir)?;
+    }
+    let headers = build_dir.join("headers");
+    fs::create_dir_all(&headers)?;
+
+    // Every library has the metadata the bindings are generated from.
+    eprintln!("Generating Swift source code");
+    let library = &platforms.values().next().expect("a target was built")[0];
+    generate_bindings("swift", library, &build_dir)?;
+
+    // The framework's headers are the C side of the bindings, and a module map
+    // which Xcode only finds by its usual name.
+    let header = format!("{FFI_MODULE}.h");
+    fs::rename(build_dir.join(&header), headers.join(&header))?;
+    fs::rename(
+        build_dir.join(format!("{FFI_MODULE}.modulemap")),
+        headers.join("module.modulemap"),
+    )?;
+
+    let sources = Path::new(PACKAGE_DIR).join("Sources/CamreteCore");
+    fs::create_dir_all(&sources)?;
+    for entry in fs::read_dir(&build_dir)? {
+        let path = entry?.path();
+        if let Some(name) = path.file_name()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "swift")
+        {
+            fs::rename(&path, sources.join(name))?;
+        }
+    }
+
+    eprintln!("Creating the XCFramework");
+    let framework = Path::new(PACKAGE_DIR).join(format!("{FFI_MODULE}.xcframework"));
+    if framework.exists() {
+        fs::remove_dir_all(&framework)?;
+    }
+
+    let mut xcodebuild = process::Command::new("xcodebuild");
+    xcodebuild.arg("-create-xcframework");
+    for (platform, libraries) in platforms {
+        let library = match libraries.as_slice() {
+            [library] => library.clone(),
+            _ => {
+                let combined = build_dir.join(platform).join(LIBRARY);
+                fs::create_dir_all(build_dir.join(platform))?;
+
+                let mut lipo = process::Command::new("lipo");
+                lipo.arg("-create")
+                    .args(&libraries)
+                    .arg("-output")
+                    .arg(&combined);
+                run(lipo)?;
+                combined
+            }
+        };
+        xcodebuild
+            .arg("-library")
+            .arg(library)
+            .arg("-headers")
+            .arg(&headers);
+    }
+    xcodebuild.arg("-output").arg(framework);
+
+    run(xcodebuild)
+}
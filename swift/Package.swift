@@ -0,0 +1,20 @@
+// swift-tools-version:5.9
+// Run `cargo xtask gen-swift` to build the framework and generate the
+// bindings before using this package.
+import PackageDescription
+
+let package = Package(
+    name: "CamreteCore",
+    platforms: [.iOS(.v15), .macOS(.v12)],
+    products: [
+        .library(name: "CamreteCore", targets: ["CamreteCore"]),
+    ],
+    targets: [
+        .binaryTarget(name: "CamreteCoreFFI", path: "CamreteCoreFFI.xcframework"),
+        .target(
+            name: "CamreteCore",
+            dependencies: ["CamreteCoreFFI"],
+            linkerSettings: [.linkedLibrary("sqlite3")]
+        ),
+    ]
+)